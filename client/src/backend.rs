@@ -0,0 +1,111 @@
+use crate::keys::{Hash, Privkey, Pubkey};
+use crate::manifest::ManifestSigned;
+use crate::types::{SnapshotUploaded, VolumeEdit, VolumeInfo};
+use crate::{Error, StorageClient};
+use async_trait::async_trait;
+
+/// Operations on volumes and their snapshots. Implemented by [`StorageClient`] and, with the
+/// `testing` feature, by the in-memory [`MockStorage`](crate::testing::MockStorage), so
+/// that code built on this crate can be tested without running the service.
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+    /// Create a new volume.
+    async fn volume_create(&self, volume: &Privkey) -> Result<(), Error>;
+
+    /// Get volume's info.
+    async fn volume_get(&self, volume: &Pubkey) -> Result<VolumeInfo, Error>;
+
+    /// List the volumes of the account with their info, ordered by public key.
+    async fn volume_list(&self) -> Result<Vec<(Pubkey, VolumeInfo)>, Error>;
+
+    /// Edit a volume's properties.
+    async fn volume_edit(&self, volume: &Privkey, edit: &VolumeEdit) -> Result<(), Error>;
+
+    /// Remove a volume.
+    async fn volume_remove(&self, volume: &Privkey) -> Result<(), Error>;
+
+    /// Upload the signed manifest of a new snapshot.
+    async fn snapshot_upload(
+        &self,
+        volume: &Pubkey,
+        manifest: &ManifestSigned,
+    ) -> Result<SnapshotUploaded, Error>;
+
+    /// Fetch the signed manifest of a snapshot, validated against the volume.
+    async fn snapshot_fetch(
+        &self,
+        volume: &Pubkey,
+        snapshot: &Hash,
+    ) -> Result<ManifestSigned, Error>;
+
+    /// List the snapshots of a volume by ascending generation, optionally only the children
+    /// of `parent` or only root snapshots.
+    async fn snapshot_list(
+        &self,
+        volume: &Pubkey,
+        parent: Option<&Hash>,
+        root: bool,
+    ) -> Result<Vec<Hash>, Error>;
+
+    /// Which of the snapshots exist in the volume.
+    async fn snapshot_exists(&self, volume: &Pubkey, hashes: &[Hash]) -> Result<Vec<Hash>, Error>;
+
+    /// Delete a snapshot that has no children.
+    async fn snapshot_delete(&self, volume: &Pubkey, snapshot: &Hash) -> Result<(), Error>;
+}
+
+#[async_trait]
+impl StorageBackend for StorageClient {
+    async fn volume_create(&self, volume: &Privkey) -> Result<(), Error> {
+        StorageClient::volume_create(self, volume).await
+    }
+
+    async fn volume_get(&self, volume: &Pubkey) -> Result<VolumeInfo, Error> {
+        StorageClient::volume_get(self, volume).await
+    }
+
+    async fn volume_list(&self) -> Result<Vec<(Pubkey, VolumeInfo)>, Error> {
+        StorageClient::volume_list(self).await
+    }
+
+    async fn volume_edit(&self, volume: &Privkey, edit: &VolumeEdit) -> Result<(), Error> {
+        StorageClient::volume_edit(self, volume, edit).await
+    }
+
+    async fn volume_remove(&self, volume: &Privkey) -> Result<(), Error> {
+        StorageClient::volume_remove(self, volume).await
+    }
+
+    async fn snapshot_upload(
+        &self,
+        volume: &Pubkey,
+        manifest: &ManifestSigned,
+    ) -> Result<SnapshotUploaded, Error> {
+        StorageClient::snapshot_upload(self, volume, manifest).await
+    }
+
+    async fn snapshot_fetch(
+        &self,
+        volume: &Pubkey,
+        snapshot: &Hash,
+    ) -> Result<ManifestSigned, Error> {
+        StorageClient::snapshot_fetch(self, volume, snapshot).await
+    }
+
+    async fn snapshot_list(
+        &self,
+        volume: &Pubkey,
+        parent: Option<&Hash>,
+        root: bool,
+    ) -> Result<Vec<Hash>, Error> {
+        StorageClient::snapshot_list(self, volume, parent, root).await
+    }
+
+    async fn snapshot_exists(&self, volume: &Pubkey, hashes: &[Hash]) -> Result<Vec<Hash>, Error> {
+        StorageClient::snapshot_exists(self, volume, hashes).await
+    }
+
+    async fn snapshot_delete(&self, volume: &Pubkey, snapshot: &Hash) -> Result<(), Error> {
+        StorageClient::snapshot_delete(self, volume, snapshot).await
+    }
+}
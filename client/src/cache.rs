@@ -0,0 +1,154 @@
+use crate::keys::{Hash, Pubkey};
+use crate::manifest::ManifestSigned;
+use log::warn;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tokio::fs;
+
+/// Default size limit of the manifest cache, in bytes.
+pub const MANIFEST_CACHE_SIZE_DEFAULT: u64 = 64 * 1024 * 1024;
+
+/// On-disk cache of signed manifests. Manifests are immutable, so they are cached by the hash
+/// of the snapshot (and its volume) without ever expiring. Once the cache exceeds its size
+/// limit, the least recently stored manifests are removed.
+#[derive(Clone, Debug)]
+pub struct ManifestCache {
+    dir: PathBuf,
+    size_max: u64,
+    bypass: bool,
+}
+
+impl ManifestCache {
+    /// Cache manifests in `dir`, which is created when the first manifest is stored.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        ManifestCache {
+            dir: dir.into(),
+            size_max: MANIFEST_CACHE_SIZE_DEFAULT,
+            bypass: false,
+        }
+    }
+
+    /// Limit the size of the cache to this many bytes.
+    pub fn size_max(mut self, size_max: u64) -> Self {
+        self.size_max = size_max;
+        self
+    }
+
+    /// Do not look up manifests in the cache, but still store the ones that were fetched.
+    pub fn bypass(mut self, bypass: bool) -> Self {
+        self.bypass = bypass;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path(&self, volume: &Pubkey, snapshot: &Hash) -> PathBuf {
+        self.dir
+            .join(format!("{}-{}", volume.to_hex(), snapshot.to_hex()))
+    }
+
+    /// Look up the manifest of a snapshot. Entries that do not match their hash, such as
+    /// truncated ones, are removed.
+    pub async fn get(&self, volume: &Pubkey, snapshot: &Hash) -> Option<ManifestSigned> {
+        if self.bypass {
+            return None;
+        }
+        let path = self.path(volume, snapshot);
+        let data = fs::read(&path).await.ok()?;
+        match ManifestSigned::parse(&data) {
+            Ok(manifest) if manifest.hash() == *snapshot => Some(manifest),
+            _ => {
+                warn!("Removing invalid cached manifest {}", path.display());
+                let _ = fs::remove_file(&path).await;
+                None
+            }
+        }
+    }
+
+    /// Store the manifest of a snapshot, then shrink the cache to its size limit.
+    pub async fn put(&self, volume: &Pubkey, manifest: &ManifestSigned) -> io::Result<()> {
+        fs::create_dir_all(&self.dir).await?;
+        let path = self.path(volume, &manifest.hash());
+        // written to a temporary file first, so that readers never see partial entries.
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, manifest.data()).await?;
+        fs::rename(&temp, &path).await?;
+        self.shrink().await
+    }
+
+    /// Remove the least recently stored entries until the cache fits its size limit.
+    async fn shrink(&self) -> io::Result<()> {
+        let mut entries = vec![];
+        let mut size = 0;
+        let mut dir = fs::read_dir(&self.dir).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            size += metadata.len();
+            entries.push((modified, metadata.len(), entry.path()));
+        }
+        entries.sort();
+        for (_, length, path) in entries {
+            if size <= self.size_max {
+                break;
+            }
+            fs::remove_file(&path).await?;
+            size -= length;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_manifest_cache() {
+    use crate::{Manifest, Privkey};
+    use std::str::FromStr;
+    use uuid::Uuid;
+
+    let dir = std::env::temp_dir().join(format!("manifest-cache-{}", Uuid::new_v4()));
+    let cache = ManifestCache::new(&dir);
+    let privkey = Privkey::generate();
+    let volume = privkey.pubkey();
+    let manifest = |generation| {
+        Manifest {
+            creation: 0,
+            machine: Uuid::nil(),
+            path: PathBuf::from("/tmp/path"),
+            size: 100,
+            size_total: 100,
+            generation,
+            parent: None,
+            data: url::Url::from_str("ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth")
+                .unwrap(),
+        }
+        .sign(&privkey)
+    };
+
+    let first = manifest(0);
+    assert_eq!(cache.get(&volume, &first.hash()).await, None);
+    cache.put(&volume, &first).await.unwrap();
+    assert_eq!(cache.get(&volume, &first.hash()).await, Some(first.clone()));
+    assert_eq!(
+        cache.clone().bypass(true).get(&volume, &first.hash()).await,
+        None
+    );
+
+    // entries of other volumes are not shared
+    let other = Privkey::generate().pubkey();
+    assert_eq!(cache.get(&other, &first.hash()).await, None);
+
+    // storing more than fits evicts the oldest entries
+    let size = first.data().len() as u64;
+    let cache = cache.size_max(size);
+    let second = manifest(1);
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    cache.put(&volume, &second).await.unwrap();
+    assert_eq!(cache.get(&volume, &second.hash()).await, Some(second));
+    assert_eq!(cache.get(&volume, &first.hash()).await, None);
+
+    fs::remove_dir_all(&dir).await.unwrap();
+}
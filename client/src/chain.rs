@@ -0,0 +1,219 @@
+use crate::ipfs::fetch_decrypt;
+use crate::keys::{Hash, Privkey, Secret};
+use crate::manifest::ManifestSigned;
+use crate::stream::ChaCha20DecryptionStream;
+use crate::{Error, SnapshotDataStream, StorageClient};
+use anyhow::{anyhow, Result};
+use cid::Cid;
+use futures::{Stream, StreamExt};
+use ipfs_api::IpfsClient;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::str::FromStr;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+
+/// How many payloads of a chain are downloaded at the same time, by default.
+pub const CHAIN_PARALLELISM_DEFAULT: usize = 4;
+
+/// Decrypted payload of one snapshot of a chain, downloaded into a file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChainDownload {
+    pub cid: Cid,
+    /// File holding the decrypted payload, which the caller should remove once it was applied.
+    pub path: PathBuf,
+    /// Size of the decrypted payload, in bytes.
+    pub size: u64,
+}
+
+/// Stream of the downloaded payloads of a chain, in the order of the chain.
+pub type ChainDownloadStream = Pin<Box<dyn Stream<Item = Result<ChainDownload>> + Send>>;
+
+/// Download and decrypt the payloads of a chain of snapshots into files in `dir`. Up to
+/// `parallelism` payloads are downloaded at the same time, even while the caller is still
+/// applying earlier ones, but they are yielded in the order of `cids`, root first.
+pub fn chain_download(
+    ipfs: &IpfsClient,
+    secret: &Secret,
+    cids: Vec<Cid>,
+    dir: PathBuf,
+    parallelism: usize,
+) -> ChainDownloadStream {
+    let ipfs = ipfs.clone();
+    let secret = *secret;
+    let downloads = futures::stream::iter(cids).map(move |cid| {
+        let path = dir.join(format!("chain-{cid}"));
+        let task = tokio::spawn(download(ipfs.clone(), secret, cid, path));
+        async move {
+            task.await
+                .map_err(|e| anyhow!("Download of {cid} failed: {e}"))?
+        }
+    });
+    Box::pin(downloads.buffered(parallelism.max(1)))
+}
+
+/// Download and decrypt a single payload into the file at `path`.
+async fn download(
+    ipfs: IpfsClient,
+    secret: Secret,
+    cid: Cid,
+    path: PathBuf,
+) -> Result<ChainDownload> {
+    let mut data = fetch_decrypt(&ipfs, &secret, &cid).await?;
+    let mut file = File::create(&path).await?;
+    let mut size = 0;
+    while let Some(chunk) = data.next().await {
+        let chunk = chunk.map_err(|e| anyhow!("Error fetching {cid} from IPFS: {e}"))?;
+        size += chunk.len() as u64;
+        file.write_all(&chunk).await?;
+    }
+    file.sync_all().await?;
+    Ok(ChainDownload { cid, path, size })
+}
+
+/// Snapshot of a chain being restored, with its decrypted payload.
+pub struct ChainSnapshot {
+    pub manifest: ManifestSigned,
+    pub data: SnapshotDataStream,
+}
+
+/// Stream of the snapshots of a chain being restored, root first.
+pub type ChainRestoreStream = Pin<Box<dyn Stream<Item = Result<ChainSnapshot, Error>> + Send>>;
+
+/// Check that the generation and total size of a snapshot continue the ones of its parent.
+fn chain_verify(manifest: &ManifestSigned, parent: Option<&ManifestSigned>) -> Result<(), Error> {
+    let invalid = |reason: &str| Err(Error::ChainInvalid(manifest.hash(), reason.into()));
+    let (generation, size_total) = parent
+        .map(|parent| (parent.manifest.generation + 1, parent.manifest.size_total))
+        .unwrap_or((0, 0));
+    if manifest.manifest.generation != generation {
+        return invalid("generation does not follow its parent");
+    }
+    if size_total.checked_add(manifest.manifest.size) != Some(manifest.manifest.size_total) {
+        return invalid("total size does not match its parent");
+    }
+    Ok(())
+}
+
+/// Restore the chain of a snapshot. The manifests of the snapshot and all of its parents are
+/// fetched and verified first, then the decrypted payloads are yielded root first, for the
+/// caller to apply in order. Payloads are fetched from IPFS or from the storage service, as
+/// the manifest says, only once the caller asks for them.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(skip_all, fields(volume = %privkey.pubkey(), snapshot = %target))
+)]
+pub async fn restore_chain(
+    client: &StorageClient,
+    ipfs: &IpfsClient,
+    privkey: &Privkey,
+    target: &Hash,
+) -> Result<ChainRestoreStream, Error> {
+    let volume = privkey.pubkey();
+    let mut chain = vec![];
+    let mut hash = *target;
+    loop {
+        // parents are only followed once the manifest is known to be genuine.
+        let manifest = client.snapshot_fetch(&volume, &hash).await?;
+        let (generation, parent) = (
+            manifest.manifest.generation,
+            manifest.manifest.parent.clone(),
+        );
+        chain.push((hash, manifest));
+        match parent {
+            None => break,
+            Some(parent) if parent.volume.is_some() => {
+                return Err(Error::ChainInvalid(
+                    hash,
+                    "parent is in another volume".into(),
+                ));
+            }
+            // generations strictly decrease towards the root, so this ends.
+            Some(_) if generation == 0 => {
+                return Err(Error::ChainInvalid(
+                    hash,
+                    "root snapshot has a parent".into(),
+                ));
+            }
+            Some(parent) => hash = parent.hash,
+        }
+    }
+    chain.reverse();
+    let mut parent = None;
+    for (_, manifest) in &chain {
+        chain_verify(manifest, parent)?;
+        parent = Some(manifest);
+    }
+
+    let client = client.clone();
+    let ipfs = ipfs.clone();
+    let secret = privkey.derive_secret();
+    let snapshots = futures::stream::iter(chain).then(move |(hash, manifest)| {
+        let client = client.clone();
+        let ipfs = ipfs.clone();
+        async move {
+            let url = &manifest.manifest.data;
+            let data: SnapshotDataStream = match (url.scheme(), url.host_str()) {
+                ("ipfs", Some(cid)) => {
+                    let cid = Cid::from_str(cid).map_err(|e| anyhow!("Invalid CID {cid}: {e}"))?;
+                    let data = fetch_decrypt(&ipfs, &secret, &cid).await?;
+                    Box::pin(data.map(|chunk| {
+                        chunk.map_err(|e| {
+                            Error::Other(anyhow!("Error fetching data from IPFS: {e}"))
+                        })
+                    }))
+                }
+                _ => {
+                    let data = client.snapshot_data_fetch(&volume, &hash).await?;
+                    Box::pin(ChaCha20DecryptionStream::new(
+                        data,
+                        &secret.to_chacha20_key(),
+                    ))
+                }
+            };
+            Ok::<_, Error>(ChainSnapshot { manifest, data })
+        }
+    });
+    Ok(Box::pin(snapshots))
+}
+
+#[cfg(test)]
+#[test]
+fn test_chain_verify() {
+    use crate::ManifestBuilder;
+
+    let privkey = Privkey::generate();
+    let url = url::Url::parse("ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth").unwrap();
+    let builder = ManifestBuilder::new()
+        .machine(uuid::Uuid::nil())
+        .path("/tmp/path")
+        .data(url)
+        .size(1000)
+        .creation(0);
+    let root = builder.sign(&privkey).unwrap();
+    let child = builder.clone().parent(&root).sign(&privkey).unwrap();
+    assert!(chain_verify(&root, None).is_ok());
+    assert!(chain_verify(&child, Some(&root)).is_ok());
+
+    // skipped generation
+    let mut manifest = child.manifest.clone();
+    manifest.generation = 2;
+    let skipped = manifest.sign(&privkey);
+    assert!(matches!(
+        chain_verify(&skipped, Some(&root)),
+        Err(Error::ChainInvalid(hash, _)) if hash == skipped.hash()
+    ));
+
+    // total size not including the parent
+    let mut manifest = child.manifest.clone();
+    manifest.size_total = manifest.size;
+    let size = manifest.sign(&privkey);
+    assert!(matches!(
+        chain_verify(&size, Some(&root)),
+        Err(Error::ChainInvalid(..))
+    ));
+    assert!(matches!(
+        chain_verify(&child, None),
+        Err(Error::ChainInvalid(..))
+    ));
+}
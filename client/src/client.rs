@@ -0,0 +1,1398 @@
+use crate::*;
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use reqwest::{Client, ClientBuilder, Method, RequestBuilder, Response};
+use std::collections::BTreeMap;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+use uuid::Uuid;
+
+/// Client for the storage service, which owns the HTTP client, the URL of the API and the
+/// token requests are authorized with. Created with [`StorageClient::builder`].
+#[derive(Clone, Debug)]
+pub struct StorageClient {
+    api: Url,
+    client: Client,
+    token: Option<String>,
+    timeout: Option<Duration>,
+    cache: Option<ManifestCache>,
+    queue: Option<UploadQueue>,
+    hooks: ClientHooks,
+}
+
+/// Builder for a [`StorageClient`]. Only the URL of the API is required, requests are sent
+/// without a token unless one is set.
+#[derive(Clone, Debug, Default)]
+pub struct StorageClientBuilder {
+    api: Option<Url>,
+    client: Option<Client>,
+    token: Option<String>,
+    timeouts: Timeouts,
+    tcp_keepalive: Option<Duration>,
+    pool_idle_timeout: Option<Option<Duration>>,
+    pool_max_idle_per_host: Option<usize>,
+    cache: Option<ManifestCache>,
+    queue: Option<UploadQueue>,
+    hooks: ClientHooks,
+}
+
+impl StorageClientBuilder {
+    /// URL of the API of the storage service.
+    pub fn api(mut self, api: Url) -> Self {
+        self.api = Some(api);
+        self
+    }
+
+    /// Token to authorize requests with.
+    pub fn token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// HTTP client to send requests with, such as one that pins the certificate of the
+    /// service. Defaults to a client with default settings.
+    pub fn client(mut self, client: Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Time allowed for each request, from connecting until the response was read
+    /// completely. This includes streaming snapshot payloads, but not account events.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.request = Some(timeout);
+        self
+    }
+
+    /// Time allowed for connecting to the service. A client set with
+    /// [`client`](Self::client) keeps its own connect timeout, see [`with_timeouts`].
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.connect = Some(timeout);
+        self
+    }
+
+    /// Send TCP keep-alive probes on idle connections at this interval, so that connections
+    /// to the service are not dropped by middleboxes during long backups. Like the connect
+    /// timeout, this and the other connection settings only apply to the default client.
+    pub fn tcp_keepalive(mut self, interval: Duration) -> Self {
+        self.tcp_keepalive = Some(interval);
+        self
+    }
+
+    /// How long idle connections are kept open for reuse. Defaults to 90 seconds, `None`
+    /// keeps them open until the service closes them.
+    pub fn pool_idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.pool_idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Maximum number of idle connections kept open for reuse, per host. By default, there is
+    /// no limit.
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.pool_max_idle_per_host = Some(max);
+        self
+    }
+
+    /// Cache manifests on disk, so that [`StorageClient::snapshot_fetch`] only fetches each
+    /// of them once.
+    pub fn manifest_cache(mut self, cache: ManifestCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Queue manifests that fail to upload because the service cannot be reached, to upload
+    /// them later with [`StorageClient::flush_pending`].
+    pub fn upload_queue(mut self, queue: UploadQueue) -> Self {
+        self.queue = Some(queue);
+        self
+    }
+
+    /// Called with the method and URL of every request, before it is sent.
+    pub fn on_request(mut self, hook: impl Fn(&Method, &Url) + Send + Sync + 'static) -> Self {
+        self.hooks.request = Some(Arc::new(hook));
+        self
+    }
+
+    /// Called with the volume and hash of every queued snapshot, before its upload is retried
+    /// by [`StorageClient::flush_pending`].
+    pub fn on_retry(mut self, hook: impl Fn(&Pubkey, &Hash) + Send + Sync + 'static) -> Self {
+        self.hooks.retry = Some(Arc::new(hook));
+        self
+    }
+
+    /// Called with the volume and the result of every snapshot that was uploaded.
+    pub fn on_upload_complete(
+        mut self,
+        hook: impl Fn(&Pubkey, &SnapshotUploaded) + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks.upload_complete = Some(Arc::new(hook));
+        self
+    }
+
+    /// Called with every error of a request, whether the service could not be reached,
+    /// answered with an error, or its response could not be read, decoded or verified. Errors
+    /// of streamed responses are reported as they are yielded.
+    pub fn on_error(mut self, hook: impl Fn(&Error) + Send + Sync + 'static) -> Self {
+        self.hooks.error = Some(Arc::new(hook));
+        self
+    }
+
+    pub fn build(self) -> Result<StorageClient, Error> {
+        let api = self.api.ok_or(Error::MissingApi)?;
+        let client = match self.client {
+            Some(client) => client,
+            None => {
+                let mut client = ClientBuilder::new().tcp_keepalive(self.tcp_keepalive);
+                if let Some(timeout) = self.timeouts.connect {
+                    client = client.connect_timeout(timeout);
+                }
+                if let Some(timeout) = self.pool_idle_timeout {
+                    client = client.pool_idle_timeout(timeout);
+                }
+                if let Some(max) = self.pool_max_idle_per_host {
+                    client = client.pool_max_idle_per_host(max);
+                }
+                client.build()?
+            }
+        };
+        Ok(StorageClient {
+            api,
+            client,
+            token: self.token,
+            timeout: self.timeouts.request,
+            cache: self.cache,
+            queue: self.queue,
+            hooks: self.hooks,
+        })
+    }
+}
+
+impl StorageClient {
+    pub fn builder() -> StorageClientBuilder {
+        StorageClientBuilder::default()
+    }
+
+    /// Client for the free functions, which take the parts of a client as arguments.
+    pub(crate) fn from_parts(api: &Url, client: &Client, token: Option<&str>) -> Self {
+        StorageClient {
+            api: api.clone(),
+            client: client.clone(),
+            token: token.map(String::from),
+            timeout: None,
+            cache: None,
+            queue: None,
+            hooks: ClientHooks::default(),
+        }
+    }
+
+    /// URL of the API of the storage service.
+    pub fn api(&self) -> &Url {
+        &self.api
+    }
+
+    /// HTTP client requests are sent with.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Authorize a request with the token, if there is one.
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.token {
+            Some(token) => request.header("Authorization", format!("Bearer {token}")),
+            None => request,
+        }
+    }
+
+    /// Start a request that is not authorized, limited by the request timeout.
+    fn request_anonymous(&self, method: Method, url: Url) -> RequestBuilder {
+        let request = self.client.request(method, url);
+        match self.timeout {
+            Some(timeout) => request.timeout(timeout),
+            None => request,
+        }
+    }
+
+    /// Start a request, authorized with the token if there is one and limited by the
+    /// request timeout.
+    fn request(&self, method: Method, url: Url) -> RequestBuilder {
+        self.authorize(self.request_anonymous(method, url))
+    }
+
+    /// Send a request, calling the hooks for it.
+    async fn send(&self, request: RequestBuilder) -> Result<Response, Error> {
+        let request = request.build().map_err(|e| self.error(e.into()))?;
+        self.hooks.request(request.method(), request.url());
+        self.client
+            .execute(request)
+            .await
+            .map_err(|e| self.error(e.into()))
+    }
+
+    /// Call the error hook for an error of a request.
+    fn error(&self, error: Error) -> Error {
+        self.hooks.error(&error);
+        error
+    }
+
+    /// Decode the JSON body of a response, calling the error hook if that fails.
+    async fn json<T: serde::de::DeserializeOwned>(&self, response: Response) -> Result<T, Error> {
+        response.json().await.map_err(|e| self.error(e.into()))
+    }
+
+    /// Read the body of a response, calling the error hook if that fails.
+    async fn bytes(&self, response: Response) -> Result<bytes::Bytes, Error> {
+        response.bytes().await.map_err(|e| self.error(e.into()))
+    }
+
+    async fn cache_get(&self, volume: &Pubkey, snapshot: &Hash) -> Option<ManifestSigned> {
+        self.cache.as_ref()?.get(volume, snapshot).await
+    }
+
+    /// Store a manifest in the cache, if there is one. Failing to is not fatal.
+    async fn cache_put(&self, volume: &Pubkey, manifest: &ManifestSigned) {
+        if let Some(cache) = &self.cache {
+            if let Err(e) = cache.put(volume, manifest).await {
+                log::warn!("Error caching manifest in {}: {e}", cache.dir().display());
+            }
+        }
+    }
+
+    /// Health check.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn health_check(&self) -> Result<(), Error> {
+        let url = self.api.join(&format!("/health"))?;
+        let response = self.send(self.request_anonymous(Method::GET, url)).await?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(self.error(Error::from_response(response).await))
+        }
+    }
+
+    /// Readiness of the service: whether its startup self-test passed and its database is
+    /// usable. A service that is not ready yet answers with its report, too.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn health_ready(&self) -> Result<HealthReport, Error> {
+        let url = self.api.join("/health/ready")?;
+        let response = self.send(self.request_anonymous(Method::GET, url)).await?;
+        match response.status() {
+            reqwest::StatusCode::OK | reqwest::StatusCode::SERVICE_UNAVAILABLE => {
+                self.json(response).await
+            }
+            _ => Err(self.error(Error::from_response(response).await)),
+        }
+    }
+
+    /// Fetch the principal (account and kind of token) that the token maps to.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn whoami(&self) -> Result<Whoami, Error> {
+        let url = self.api.join("/api/v1/whoami")?;
+        let response = self.send(self.request(Method::GET, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        self.json(response).await
+    }
+
+    /// Issue a new API key for the account of the token.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn api_key_create(&self, request: &ApiKeyCreate) -> Result<ApiKeyCreated, Error> {
+        let url = self.api.join("/api/v1/account/keys")?;
+        let response = self
+            .send(self.request(Method::POST, url).json(request))
+            .await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        self.json(response).await
+    }
+
+    /// List the API keys of the account of the token.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn api_key_list(&self) -> Result<Vec<ApiKeyInfo>, Error> {
+        let url = self.api.join("/api/v1/account/keys")?;
+        let response = self.send(self.request(Method::GET, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        self.json(response).await
+    }
+
+    /// Revoke an API key of the account of the token.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn api_key_revoke(&self, id: i64) -> Result<(), Error> {
+        let url = self.api.join(&format!("/api/v1/account/keys/{id}"))?;
+        let response = self.send(self.request(Method::DELETE, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        Ok(())
+    }
+
+    /// Fetch the default labels of the account, which are attached to every snapshot uploaded
+    /// to its volumes.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn account_labels(&self) -> Result<BTreeMap<String, String>, Error> {
+        let url = self.api.join("/api/v1/account/labels")?;
+        let response = self.send(self.request(Method::GET, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        self.json(response).await
+    }
+
+    /// Replace the default labels of the account.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn account_labels_set(&self, labels: &BTreeMap<String, String>) -> Result<(), Error> {
+        let url = self.api.join("/api/v1/account/labels")?;
+        let response = self
+            .send(self.request(Method::PUT, url).json(labels))
+            .await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        Ok(())
+    }
+
+    /// Settings of the account.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn account_settings(&self) -> Result<AccountSettings, Error> {
+        let url = self.api.join("/api/v1/account/settings")?;
+        let response = self.send(self.request(Method::GET, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        self.json(response).await
+    }
+
+    /// Replace the settings of the account.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn account_settings_set(&self, settings: &AccountSettings) -> Result<(), Error> {
+        let url = self.api.join("/api/v1/account/settings")?;
+        let response = self
+            .send(self.request(Method::PUT, url).json(settings))
+            .await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        Ok(())
+    }
+
+    /// Alerts currently raised for volumes of the account.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn account_alerts(&self) -> Result<Vec<Alert>, Error> {
+        let url = self.api.join("/api/v1/account/alerts")?;
+        let response = self.send(self.request(Method::GET, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        self.json(response).await
+    }
+
+    /// When each volume of the account was last verified to be restorable by a restore drill.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn account_drills(&self) -> Result<Vec<DrillStatus>, Error> {
+        let url = self.api.join("/api/v1/account/drills")?;
+        let response = self.send(self.request(Method::GET, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        self.json(response).await
+    }
+
+    /// Find snapshots of the account that have all of the given labels.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn snapshot_search(
+        &self,
+        labels: &BTreeMap<String, String>,
+    ) -> Result<Vec<LabelMatch>, Error> {
+        let mut url = self.api.join("/api/v1/snapshots/search")?;
+        for (key, value) in labels {
+            url.query_pairs_mut()
+                .append_pair("label", &format!("{key}={value}"));
+        }
+        let response = self.send(self.request(Method::GET, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        self.json(response).await
+    }
+
+    /// Find snapshots of the account created on the given machine, newest first.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn snapshot_search_machine(
+        &self,
+        machine: &Uuid,
+        limit: Option<u64>,
+    ) -> Result<Vec<MachineSnapshot>, Error> {
+        let mut url = self.api.join("/api/v1/snapshots")?;
+        url.query_pairs_mut()
+            .append_pair("machine", &machine.to_string());
+        if let Some(limit) = limit {
+            url.query_pairs_mut()
+                .append_pair("limit", &limit.to_string());
+        }
+        let response = self.send(self.request(Method::GET, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        self.json(response).await
+    }
+
+    /// Find payloads referenced by more than one snapshot of the account. If a payload is
+    /// given, returns the snapshots referencing it instead, or nothing if it is not stored yet.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn snapshot_duplicates(
+        &self,
+        data: Option<&Url>,
+    ) -> Result<Vec<DuplicateData>, Error> {
+        let mut url = self.api.join("/api/v1/snapshots/duplicates")?;
+        if let Some(data) = data {
+            url.query_pairs_mut().append_pair("data", data.as_str());
+        }
+        let response = self.send(self.request(Method::GET, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        self.json(response).await
+    }
+
+    /// Fetch the capabilities of the storage service, used to negotiate the manifest version.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn capabilities(&self) -> Result<Capabilities, Error> {
+        let url = self.api.join("/api/v1/capabilities")?;
+        let response = self.send(self.request_anonymous(Method::GET, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        self.json(response).await
+    }
+
+    /// Fetch latest (as in, most current generation) based on the parent
+    /// generation that is passed.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(volume = %volume)))]
+    pub async fn snapshot_list(
+        &self,
+        volume: &Pubkey,
+        parent: Option<&Hash>,
+        root: bool,
+    ) -> Result<Vec<Hash>, Error> {
+        let ordering = SnapshotOrdering::default();
+        self.snapshot_list_ordered(volume, parent, root, &ordering)
+            .await
+    }
+
+    /// List snapshots in the given order.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(volume = %volume)))]
+    pub async fn snapshot_list_ordered(
+        &self,
+        volume: &Pubkey,
+        parent: Option<&Hash>,
+        root: bool,
+        ordering: &SnapshotOrdering,
+    ) -> Result<Vec<Hash>, Error> {
+        let url = self
+            .api
+            .join(&format!("/api/v1/volume/{}/snapshots", &volume.to_hex()))
+            .unwrap();
+        let mut query = vec![];
+        if let Some(parent) = parent {
+            query.push(("parent", parent.to_string()));
+        }
+        if root {
+            query.push(("root", "true".to_string()));
+        }
+        query.push(("order", ordering.order.as_str().to_string()));
+        query.push(("dir", ordering.direction.as_str().to_string()));
+        let response = self
+            .send(self.request(Method::GET, url).query(&query))
+            .await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        self.json::<Vec<Hash>>(response).await
+    }
+
+    /// Check which of the given snapshots exist in the volume, returns the hashes of the ones
+    /// that do. Lets sync agents reconcile many snapshots with a single request.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(volume = %volume)))]
+    pub async fn snapshot_exists(
+        &self,
+        volume: &Pubkey,
+        hashes: &[Hash],
+    ) -> Result<Vec<Hash>, Error> {
+        let url = self.api.join(&format!(
+            "/api/v1/volume/{}/snapshots/exists",
+            &volume.to_hex()
+        ))?;
+        let response = self
+            .send(self.request(Method::POST, url).json(&hashes))
+            .await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        self.json(response).await
+    }
+
+    /// List a page of snapshots with full records. Pass the cursor of the returned page in the
+    /// options to fetch the next page.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(volume = %volume)))]
+    pub async fn snapshot_list_v2(
+        &self,
+        volume: &Pubkey,
+        options: &SnapshotListOptions,
+    ) -> Result<SnapshotPage, Error> {
+        let url = self
+            .api
+            .join(&format!("/api/v2/volume/{}/snapshots", &volume.to_hex()))?;
+        let mut query = vec![];
+        if let Some(parent) = &options.parent {
+            query.push(("parent", parent.to_string()));
+        }
+        if options.root {
+            query.push(("root", "true".to_string()));
+        }
+        if let Some(cursor) = &options.cursor {
+            query.push(("cursor", cursor.clone()));
+        }
+        if let Some(limit) = options.limit {
+            query.push(("limit", limit.to_string()));
+        }
+        let response = self
+            .send(self.request(Method::GET, url).query(&query))
+            .await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        self.json(response).await
+    }
+
+    /// List the snapshots of the volume with the v2 listing API, following the cursors of the
+    /// pages so that callers do not have to. The limit of the options is the size of the pages
+    /// that are fetched. Pages are only fetched as the stream is consumed.
+    pub fn snapshot_list_paged(
+        &self,
+        volume: &Pubkey,
+        options: &SnapshotListOptions,
+    ) -> SnapshotHashStream {
+        let client = self.clone();
+        let volume = *volume;
+        let pages = stream::try_unfold(Some(options.clone()), move |options| {
+            let client = client.clone();
+            async move {
+                let mut options = match options {
+                    Some(options) => options,
+                    None => return Ok(None),
+                };
+                let page = client.snapshot_list_v2(&volume, &options).await?;
+                let hashes: Vec<Result<Hash, Error>> = page
+                    .snapshots
+                    .iter()
+                    .map(|snapshot| Ok(snapshot.hash))
+                    .collect();
+                let next = page.cursor.map(|cursor| {
+                    options.cursor = Some(cursor);
+                    options
+                });
+                Ok(Some((stream::iter(hashes), next)))
+            }
+        });
+        Box::pin(pages.try_flatten())
+    }
+
+    /// Snapshots of the volume that were uploaded before their parent, ordered by generation.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(volume = %volume)))]
+    pub async fn snapshot_pending_list(
+        &self,
+        volume: &Pubkey,
+    ) -> Result<Vec<SnapshotPending>, Error> {
+        let url = self.api.join(&format!(
+            "/api/v1/volume/{}/snapshots/pending",
+            &volume.to_hex()
+        ))?;
+        let response = self.send(self.request(Method::GET, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        self.json(response).await
+    }
+
+    /// Create new snapshot repository, given a private key.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(volume = %volume.pubkey()))
+    )]
+    pub async fn volume_create(&self, volume: &Privkey) -> Result<(), Error> {
+        let url = self
+            .api
+            .join(&format!("/api/v1/volume/{}", &volume.pubkey().to_hex()))?;
+        let response = self.send(self.request(Method::POST, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        Ok(())
+    }
+
+    /// Get volume's info.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(volume = %volume)))]
+    pub async fn volume_get(&self, volume: &Pubkey) -> Result<VolumeInfo, Error> {
+        let url = self
+            .api
+            .join(&format!("/api/v1/volume/{}", &volume.to_hex()))?;
+        let response = self.send(self.request(Method::GET, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        self.json(response).await
+    }
+
+    /// List the volumes of the account of the token with their info, ordered by public key.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn volume_list(&self) -> Result<Vec<(Pubkey, VolumeInfo)>, Error> {
+        let url = self.api.join("/api/v1/account/volumes")?;
+        let response = self.send(self.request(Method::GET, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        self.json(response).await
+    }
+
+    /// Edit a volume's properties. The request is signed with the volume's key.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(volume = %volume.pubkey()))
+    )]
+    pub async fn volume_edit(&self, volume: &Privkey, edit: &VolumeEdit) -> Result<(), Error> {
+        let url = self
+            .api
+            .join(&format!("/api/v1/volume/{}", &volume.pubkey().to_hex()))?;
+        let body = serde_json::to_vec(edit)?;
+        let response = self
+            .send(
+                self.request(Method::PATCH, url.clone())
+                    .header(
+                        SIGNATURE_HEADER,
+                        request_signature(volume, "PATCH", &url, &body),
+                    )
+                    .header(DIGEST_HEADER, RequestSignature::digest(&body))
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .body(body),
+            )
+            .await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        Ok(())
+    }
+
+    /// Remove volume. The request is signed with the volume's key, and the server requires
+    /// signing a challenge it issues first to prove possession of the key.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(volume = %volume.pubkey()))
+    )]
+    pub async fn volume_remove(&self, volume: &Privkey) -> Result<(), Error> {
+        let url = self
+            .api
+            .join(&format!("/api/v1/volume/{}", &volume.pubkey().to_hex()))?;
+        let challenge = self.volume_challenge(&volume.pubkey()).await?;
+        let response = self
+            .send(
+                self.request(Method::DELETE, url.clone())
+                    .header(
+                        SIGNATURE_HEADER,
+                        request_signature(volume, "DELETE", &url, &[]),
+                    )
+                    .header(PROOF_HEADER, challenge.proof(volume)),
+            )
+            .await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        Ok(())
+    }
+
+    /// Export the metadata of a volume as a portable archive.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(volume = %volume)))]
+    pub async fn volume_export(&self, volume: &Pubkey) -> Result<VolumeArchive, Error> {
+        let url = self
+            .api
+            .join(&format!("/api/v1/volume/{}/export", &volume.to_hex()))?;
+        let response = self.send(self.request(Method::GET, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        self.json(response).await
+    }
+
+    /// Import a volume archive, creating the volume with all of its snapshots. The volume must
+    /// not exist yet.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn volume_import(&self, archive: &VolumeArchive) -> Result<(), Error> {
+        let url = self.api.join(&format!(
+            "/api/v1/volume/{}/import",
+            &archive.volume.to_hex()
+        ))?;
+        let response = self
+            .send(self.request(Method::POST, url).json(archive))
+            .await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        Ok(())
+    }
+
+    /// Get the status of replicating a volume to the peers of the storage service.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(volume = %volume)))]
+    pub async fn volume_replication(
+        &self,
+        volume: &Pubkey,
+    ) -> Result<Vec<ReplicationStatus>, Error> {
+        let url = self
+            .api
+            .join(&format!("/api/v1/volume/{}/replication", &volume.to_hex()))?;
+        let response = self.send(self.request(Method::GET, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        self.json(response).await
+    }
+
+    /// Record the result of a restore drill of a snapshot of the volume.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(volume = %volume)))]
+    pub async fn drill_record(
+        &self,
+        volume: &Pubkey,
+        result: &DrillResult,
+    ) -> Result<Drill, Error> {
+        let url = self
+            .api
+            .join(&format!("/api/v1/volume/{}/drills", &volume.to_hex()))?;
+        let response = self
+            .send(self.request(Method::POST, url).json(result))
+            .await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        self.json(response).await
+    }
+
+    /// Restore drills recorded for the volume, most recent first.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(volume = %volume)))]
+    pub async fn drill_list(&self, volume: &Pubkey) -> Result<Vec<Drill>, Error> {
+        let url = self
+            .api
+            .join(&format!("/api/v1/volume/{}/drills", &volume.to_hex()))?;
+        let response = self.send(self.request(Method::GET, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        self.json(response).await
+    }
+
+    /// Accounts other than the owner that were granted access to the volume.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(volume = %volume)))]
+    pub async fn volume_acl_list(&self, volume: &Pubkey) -> Result<Vec<VolumeGrant>, Error> {
+        let url = self
+            .api
+            .join(&format!("/api/v1/volume/{}/acl", &volume.to_hex()))?;
+        let response = self.send(self.request(Method::GET, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        self.json(response).await
+    }
+
+    /// Grant another account access to the volume, replacing any access it was granted before.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(volume = %volume)))]
+    pub async fn volume_acl_grant(
+        &self,
+        volume: &Pubkey,
+        grant: &VolumeGrant,
+    ) -> Result<(), Error> {
+        let url = self
+            .api
+            .join(&format!("/api/v1/volume/{}/acl", &volume.to_hex()))?;
+        let response = self
+            .send(self.request(Method::PUT, url).json(grant))
+            .await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        Ok(())
+    }
+
+    /// Revoke the access of an account to the volume.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(volume = %volume)))]
+    pub async fn volume_acl_revoke(&self, volume: &Pubkey, account: &Uuid) -> Result<(), Error> {
+        let url = self.api.join(&format!(
+            "/api/v1/volume/{}/acl/{account}",
+            &volume.to_hex()
+        ))?;
+        let response = self.send(self.request(Method::DELETE, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        Ok(())
+    }
+
+    /// Request a challenge for removing a volume, which has to be signed with its key.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(volume = %volume)))]
+    pub async fn volume_challenge(&self, volume: &Pubkey) -> Result<VolumeChallenge, Error> {
+        let url = self
+            .api
+            .join(&format!("/api/v1/volume/{}/challenge", &volume.to_hex()))?;
+        let response = self.send(self.request(Method::POST, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        self.json(response).await
+    }
+
+    /// Restore a removed volume. This is only possible until the volume is purged.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(volume = %volume)))]
+    pub async fn volume_restore(&self, volume: &Pubkey) -> Result<(), Error> {
+        let url = self
+            .api
+            .join(&format!("/api/v1/volume/{}/restore", &volume.to_hex()))?;
+        let response = self.send(self.request(Method::POST, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        Ok(())
+    }
+
+    /// Mint a token that only allows uploading snapshots to the volume, valid for `ttl` (such as
+    /// `30m` or `1h`, defaults to one hour). Only the owner of the volume can do this.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(volume = %volume)))]
+    pub async fn upload_token_create(
+        &self,
+        volume: &Pubkey,
+        ttl: Option<&str>,
+    ) -> Result<UploadToken, Error> {
+        let mut url = self
+            .api
+            .join(&format!("/api/v1/volume/{}/upload-token", volume.to_hex()))?;
+        if let Some(ttl) = ttl {
+            url.query_pairs_mut().append_pair("ttl", ttl);
+        }
+        let response = self.send(self.request(Method::POST, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        self.json(response).await
+    }
+
+    /// Delete an account with all of its volumes, snapshots, API keys and labels. Requires a
+    /// system token.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn account_delete(&self, account: &Uuid) -> Result<AccountDeleted, Error> {
+        let url = self.api.join(&format!("/api/v1/account/{account}"))?;
+        let response = self.send(self.request(Method::DELETE, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        self.json(response).await
+    }
+
+    /// Fetch statistics about all stored data, including snapshots created per day over the
+    /// given number of days. Only allowed for system tokens.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn admin_stats(&self, days: u64) -> Result<StorageStats, Error> {
+        let mut url = self.api.join("/api/v1/admin/stats")?;
+        url.query_pairs_mut().append_pair("days", &days.to_string());
+        let response = self.send(self.request(Method::GET, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        self.json(response).await
+    }
+
+    /// Upload a new snapshot, returning its hash, whether it was stored already, where its
+    /// payload is and warnings about it. With an [`UploadQueue`], manifests that fail to
+    /// upload because the service cannot be reached are queued, see
+    /// [`flush_pending`](Self::flush_pending).
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(volume = %volume, snapshot = %manifest.hash()))
+    )]
+    pub async fn snapshot_upload(
+        &self,
+        volume: &Pubkey,
+        manifest: &ManifestSigned,
+    ) -> Result<SnapshotUploaded, Error> {
+        let key = manifest.idempotency_key();
+        let result = self
+            .snapshot_upload_with_key(volume, manifest, Some(&key))
+            .await;
+        if let (Err(error), Some(queue)) = (&result, &self.queue) {
+            if error.is_transient() {
+                if let Err(e) = queue.push(volume, manifest).await {
+                    log::warn!("Error queueing manifest in {}: {e}", queue.dir().display());
+                }
+            }
+        }
+        result
+    }
+
+    /// Upload the manifests in the [`UploadQueue`] by ascending generation, returning the
+    /// results of the ones that were uploaded. Stops at the first one that fails because the
+    /// service cannot be reached, leaving it and the following ones queued. Manifests the
+    /// service rejects are removed from the queue.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn flush_pending(&self) -> Result<Vec<SnapshotUploaded>, Error> {
+        let queue = match &self.queue {
+            Some(queue) => queue,
+            None => return Ok(vec![]),
+        };
+        let pending = queue.pending().await.map_err(anyhow::Error::from)?;
+        let mut uploaded = vec![];
+        for upload in pending {
+            self.hooks.retry(&upload.volume, &upload.manifest.hash());
+            match self
+                .snapshot_upload_with_key(
+                    &upload.volume,
+                    &upload.manifest,
+                    Some(&upload.manifest.idempotency_key()),
+                )
+                .await
+            {
+                Ok(result) => uploaded.push(result),
+                Err(error) if error.is_transient() => return Err(error),
+                Err(error) => log::warn!(
+                    "Dropping queued manifest {} rejected by the service: {error}",
+                    upload.manifest.hash()
+                ),
+            }
+            queue
+                .remove(&upload.volume, &upload.manifest)
+                .await
+                .map_err(anyhow::Error::from)?;
+        }
+        Ok(uploaded)
+    }
+
+    /// Upload a new snapshot with the given idempotency key instead of the one derived from
+    /// the manifest, or without one. Unlike [`snapshot_upload`](Self::snapshot_upload), failed
+    /// uploads are never queued.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(volume = %volume, snapshot = %manifest.hash()))
+    )]
+    pub async fn snapshot_upload_with_key(
+        &self,
+        volume: &Pubkey,
+        manifest: &ManifestSigned,
+        key: Option<&str>,
+    ) -> Result<SnapshotUploaded, Error> {
+        let url = self
+            .api
+            .join(&format!("/api/v1/volume/{}/snapshot", &volume.to_hex()))
+            .unwrap();
+        let mut request = self
+            .request(Method::POST, url)
+            .header("Accept", "application/json");
+        if let Some(key) = key {
+            request = request.header(IDEMPOTENCY_HEADER, key);
+        }
+        let response = self.send(request.body(manifest.data())).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        // older versions of the service redirect to the snapshot instead
+        let json = response
+            .headers()
+            .get("Content-Type")
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.starts_with("application/json"))
+            .unwrap_or(false);
+        let uploaded = if json {
+            self.json(response).await?
+        } else {
+            SnapshotUploaded {
+                hash: manifest.hash(),
+                deduplicated: false,
+                payload: None,
+                warnings: vec![],
+                pending: false,
+            }
+        };
+        self.hooks.upload_complete(volume, &uploaded);
+        Ok(uploaded)
+    }
+
+    /// Encrypt and upload the payload of a snapshot to IPFS, then build, sign and upload its
+    /// manifest. The payload is streamed in a single pass and its size is measured on the way,
+    /// so it does not need to be known up front. The manifest records the encrypted size, which
+    /// is what the snapshot occupies in storage.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(volume = %privkey.pubkey()))
+    )]
+    pub async fn snapshot_publish(
+        &self,
+        ipfs: &ipfs_api::IpfsClient,
+        privkey: &Privkey,
+        snapshot: &SnapshotPublish,
+        data: Pin<Box<dyn Stream<Item = Result<bytes::Bytes, std::io::Error>> + Send + Sync>>,
+    ) -> Result<SnapshotPublished, Error> {
+        let (cid, size) = upload_encrypt_sized(ipfs, &privkey.derive_secret(), data).await?;
+        let mut builder = ManifestBuilder::new()
+            .machine(snapshot.machine)
+            .path(snapshot.path.clone())
+            .data(Url::parse(&format!("ipfs://{cid}"))?)
+            .size(size.ciphertext);
+        if let Some(parent) = &snapshot.parent {
+            builder = builder.parent(parent);
+        }
+        let manifest = builder.sign(privkey)?;
+        let warnings = self
+            .snapshot_upload(&privkey.pubkey(), &manifest)
+            .await?
+            .warnings;
+        Ok(SnapshotPublished {
+            manifest,
+            cid,
+            size,
+            warnings,
+        })
+    }
+
+    /// Upload a batch of snapshots in a single request. The server stores either all or none of
+    /// them, the results indicate what happened to each manifest.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(volume = %volume)))]
+    pub async fn snapshot_upload_batch(
+        &self,
+        volume: &Pubkey,
+        manifests: &[ManifestSigned],
+    ) -> Result<Vec<SnapshotUploadResult>, Error> {
+        let url = self
+            .api
+            .join(&format!("/api/v1/volume/{}/snapshots", &volume.to_hex()))?;
+        let response = self
+            .send(self.request(Method::POST, url).json(&manifests))
+            .await?;
+        match response.status() {
+            status if status.is_success() => {}
+            reqwest::StatusCode::UNPROCESSABLE_ENTITY => {}
+            _ => return Err(self.error(Error::from_response(response).await)),
+        }
+        self.json(response).await
+    }
+
+    /// Subscribe to activity on all volumes of the account. Events are pushed by the server as
+    /// they happen, the stream ends when the connection is closed.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn account_events(&self) -> Result<AccountEventStream, Error> {
+        let url = self.api.join("/api/v1/events")?;
+        // the stream stays open for as long as the caller wants events
+        let response = self
+            .send(
+                self.authorize(self.client.get(url))
+                    .header("Accept", "text/event-stream"),
+            )
+            .await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+
+        // buffer incoming data and split it into events, which are separated by empty lines.
+        let chunks = response.bytes_stream().boxed();
+        let events = stream::unfold(
+            (chunks, String::new()),
+            |(mut chunks, mut buffer)| async move {
+                loop {
+                    if let Some(end) = buffer.find("\n\n") {
+                        let event: String = buffer.drain(..end + 2).collect();
+                        return Some((Ok(event), (chunks, buffer)));
+                    }
+                    match chunks.next().await? {
+                        Ok(chunk) => buffer.push_str(&String::from_utf8_lossy(&chunk)),
+                        Err(error) => return Some((Err(error.into()), (chunks, buffer))),
+                    }
+                }
+            },
+        );
+
+        let hooks = self.hooks.clone();
+        let events = events.filter_map(move |event| {
+            let result: Option<Result<AccountEvent, Error>> = match event {
+                Ok(event) => event_data(&event).map(|data| Ok(serde_json::from_str(&data)?)),
+                Err(error) => Some(Err(error)),
+            };
+            if let Some(Err(error)) = &result {
+                hooks.error(error);
+            }
+            async move { result }
+        });
+        Ok(Box::pin(events))
+    }
+
+    /// Fetch the signed manifest of a snapshot. The manifest is checked to have the requested
+    /// hash and to be signed by the volume, so that the service cannot forge manifests. Hybrid
+    /// signatures are checked against the Dilithium3 key pinned for the volume, which is looked
+    /// up from the service.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(volume = %volume, snapshot = %snapshot))
+    )]
+    pub async fn snapshot_fetch(
+        &self,
+        volume: &Pubkey,
+        snapshot: &Hash,
+    ) -> Result<ManifestSigned, Error> {
+        let manifest = self.snapshot_fetch_unverified(volume, snapshot).await?;
+        let pq_pubkey = match SignatureAlgorithm::of(&manifest.signature) {
+            Some(SignatureAlgorithm::Ed25519Dilithium3) => {
+                self.volume_get(volume).await?.pq_pubkey_bytes()
+            }
+            _ => None,
+        };
+        if manifest.hash() != *snapshot
+            || manifest
+                .validate_pinned(volume, pq_pubkey.as_deref())
+                .is_err()
+        {
+            return Err(self.error(Error::ManifestValidation(*snapshot)));
+        }
+        Ok(manifest)
+    }
+
+    /// Fetch the signed manifest of a snapshot without validating it, for callers that check
+    /// manifests themselves.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(volume = %volume, snapshot = %snapshot))
+    )]
+    pub async fn snapshot_fetch_unverified(
+        &self,
+        volume: &Pubkey,
+        snapshot: &Hash,
+    ) -> Result<ManifestSigned, Error> {
+        let url = self
+            .api
+            .join(&format!(
+                "/api/v1/volume/{}/{}",
+                &volume.to_hex(),
+                &snapshot.to_hex(),
+            ))
+            .unwrap();
+        if let Some(manifest) = self.cache_get(volume, snapshot).await {
+            return Ok(manifest);
+        }
+        let response = self.send(self.request(Method::GET, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        let manifest = self.bytes(response).await?;
+        let manifest = ManifestSigned::parse(&manifest).map_err(|e| self.error(e.into()))?;
+        if manifest.hash() == *snapshot {
+            self.cache_put(volume, &manifest).await;
+        }
+        Ok(manifest)
+    }
+
+    /// Delete a snapshot of the volume, releasing its payload. Fails with
+    /// [`Error::HasChildren`] while other snapshots have it as their parent, delete these first.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(volume = %volume, snapshot = %snapshot))
+    )]
+    pub async fn snapshot_delete(&self, volume: &Pubkey, snapshot: &Hash) -> Result<(), Error> {
+        let url = self.api.join(&format!(
+            "/api/v1/volume/{}/{}",
+            &volume.to_hex(),
+            &snapshot.to_hex(),
+        ))?;
+        let response = self.send(self.request(Method::DELETE, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        Ok(())
+    }
+
+    /// Fetch the signed manifests of several snapshots of the volume in a single request, such
+    /// as the chain of a snapshot being restored. Snapshots that do not exist are left out.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(volume = %volume)))]
+    pub async fn snapshot_fetch_batch(
+        &self,
+        volume: &Pubkey,
+        snapshots: &[Hash],
+    ) -> Result<Vec<ManifestSigned>, Error> {
+        let url = self
+            .api
+            .join(&format!("/api/v1/volume/{}/manifests", &volume.to_hex()))?;
+        let response = self
+            .send(self.request(Method::POST, url).json(snapshots))
+            .await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        let manifests = self.bytes(response).await?;
+        ManifestSigned::parse_batch(&manifests).map_err(|e| self.error(e.into()))
+    }
+
+    /// Mint a URL that allows fetching the manifest of a snapshot without a bearer token, valid
+    /// for `ttl` (such as `30m` or `1h`, defaults to one hour). Only the owner of the volume can
+    /// do this.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(volume = %volume, snapshot = %snapshot))
+    )]
+    pub async fn snapshot_presign(
+        &self,
+        volume: &Pubkey,
+        snapshot: &Hash,
+        ttl: Option<&str>,
+    ) -> Result<Url, Error> {
+        let mut url = self.api.join(&format!(
+            "/api/v1/volume/{}/{}/presign",
+            volume.to_hex(),
+            snapshot.to_hex()
+        ))?;
+        if let Some(ttl) = ttl {
+            url.query_pairs_mut().append_pair("ttl", ttl);
+        }
+        let response = self.send(self.request(Method::POST, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        let presigned: PresignedUrl = self.json(response).await?;
+        Ok(self.api.join(&presigned.path)?)
+    }
+
+    /// Validate the chain of parents of a snapshot back to the root.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(volume = %volume, snapshot = %snapshot))
+    )]
+    pub async fn snapshot_chain_validate(
+        &self,
+        volume: &Pubkey,
+        snapshot: &Hash,
+    ) -> Result<ChainReport, Error> {
+        let url = self.api.join(&format!(
+            "/api/v1/volume/{}/{}/chain/validate",
+            &volume.to_hex(),
+            &snapshot.to_hex(),
+        ))?;
+        let response = self.send(self.request(Method::GET, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        self.json(response).await
+    }
+
+    /// Fetch the signed manifests of a snapshot and its ancestors back to the root.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(volume = %volume, snapshot = %snapshot))
+    )]
+    pub async fn snapshot_ancestry(
+        &self,
+        volume: &Pubkey,
+        snapshot: &Hash,
+    ) -> Result<SnapshotAncestry, Error> {
+        let url = self.api.join(&format!(
+            "/api/v1/volume/{}/{}/ancestry",
+            &volume.to_hex(),
+            &snapshot.to_hex(),
+        ))?;
+        let response = self.send(self.request(Method::GET, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        self.json(response).await
+    }
+
+    /// Upload the (encrypted) payload of a snapshot directly to the storage service, for
+    /// deployments without IPFS. The snapshot's manifest must have been uploaded already.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(volume = %volume, snapshot = %snapshot))
+    )]
+    pub async fn snapshot_data_upload(
+        &self,
+        volume: &Pubkey,
+        snapshot: &Hash,
+        data: reqwest::Body,
+    ) -> Result<(), Error> {
+        let url = self.api.join(&format!(
+            "/api/v1/volume/{}/{}/data",
+            &volume.to_hex(),
+            &snapshot.to_hex(),
+        ))?;
+        let response = self.send(self.request(Method::PUT, url).body(data)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        Ok(())
+    }
+
+    /// Fetch the (encrypted) payload of a snapshot that was stored directly on the storage
+    /// service. The payload is streamed.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(volume = %volume, snapshot = %snapshot))
+    )]
+    pub async fn snapshot_data_fetch(
+        &self,
+        volume: &Pubkey,
+        snapshot: &Hash,
+    ) -> Result<SnapshotDataStream, Error> {
+        let url = self.api.join(&format!(
+            "/api/v1/volume/{}/{}/data",
+            &volume.to_hex(),
+            &snapshot.to_hex(),
+        ))?;
+        let response = self.send(self.request(Method::GET, url)).await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        let hooks = self.hooks.clone();
+        Ok(Box::pin(response.bytes_stream().map(move |chunk| {
+            chunk.map_err(|e| {
+                let error = Error::from(e);
+                hooks.error(&error);
+                error
+            })
+        })))
+    }
+
+    /// Fetch the manifest of a snapshot using a pre-signed URL.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn snapshot_fetch_presigned(&self, url: &Url) -> Result<ManifestSigned, Error> {
+        let response = self
+            .send(self.request_anonymous(Method::GET, url.clone()))
+            .await?;
+        if !response.status().is_success() {
+            return Err(self.error(Error::from_response(response).await));
+        }
+        let manifest = self.bytes(response).await?;
+        ManifestSigned::parse(&manifest).map_err(|e| self.error(e.into()))
+    }
+}
+
+/// Value of the [`SIGNATURE_HEADER`] for a request to the URL with the given body, signed
+/// with the volume's key.
+fn request_signature(volume: &Privkey, method: &str, url: &Url, body: &[u8]) -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    RequestSignature::sign(volume, method, url.path(), body, timestamp).to_string()
+}
+
+/// Extract the data of a server-sent event, returns `None` for comments and keep-alives.
+fn event_data(event: &str) -> Option<String> {
+    let data: Vec<&str> = event
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(|data| data.strip_prefix(' ').unwrap_or(data))
+        .collect();
+    if data.is_empty() {
+        None
+    } else {
+        Some(data.join("\n"))
+    }
+}
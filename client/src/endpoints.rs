@@ -0,0 +1,281 @@
+use crate::keys::Secret;
+use crate::stream::*;
+use crate::Error;
+use bytes::Bytes;
+use cid::Cid;
+use futures::{Stream, StreamExt, TryStreamExt};
+use ipfs_api::{IpfsApi, IpfsClient, TryFromUri};
+use log::warn;
+use reqwest::Client;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// How long an endpoint that failed is skipped for, by default.
+pub const IPFS_ENDPOINT_RETRY: Duration = Duration::from_secs(30);
+
+/// Stream of (decrypted) data fetched from IPFS.
+pub type IpfsDataStream = Pin<Box<dyn Stream<Item = Result<Bytes, io::Error>> + Send>>;
+
+/// Where data can be fetched from IPFS.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IpfsEndpoint {
+    /// HTTP API of an IPFS node, such as `http://localhost:5001`.
+    Api(Url),
+    /// HTTP gateway, such as `https://ipfs.io`. Data is fetched from `/ipfs/<cid>`.
+    Gateway(Url),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum IpfsEndpointError {
+    #[error("No IPFS endpoints configured")]
+    NoEndpoints,
+    #[error("Invalid IPFS API URL {0:}: {1:}")]
+    InvalidUrl(Url, String),
+    #[error("Error fetching data from all IPFS endpoints, last error: {0:}")]
+    Unavailable(String),
+}
+
+#[derive(Clone)]
+enum Backend {
+    Api(IpfsClient),
+    Gateway(Url),
+}
+
+/// Ordered list of IPFS endpoints that fetches fail over between. Endpoints that fail are
+/// skipped for a while, so that dead nodes are not tried for every fetch. Fetches only fail
+/// over until data starts arriving, failures in the middle of a stream are returned.
+#[derive(Clone)]
+pub struct IpfsEndpoints {
+    endpoints: Vec<(IpfsEndpoint, Backend)>,
+    client: Client,
+    retry: Duration,
+    down: Arc<Mutex<Vec<Option<Instant>>>>,
+}
+
+impl IpfsEndpoints {
+    /// Endpoints to try, in order of preference.
+    pub fn new(endpoints: Vec<IpfsEndpoint>) -> Result<Self, IpfsEndpointError> {
+        if endpoints.is_empty() {
+            return Err(IpfsEndpointError::NoEndpoints);
+        }
+        let endpoints = endpoints
+            .into_iter()
+            .map(|endpoint| {
+                let backend = match &endpoint {
+                    IpfsEndpoint::Api(url) => {
+                        Backend::Api(IpfsClient::from_str(url.as_str()).map_err(|e| {
+                            IpfsEndpointError::InvalidUrl(url.clone(), e.to_string())
+                        })?)
+                    }
+                    IpfsEndpoint::Gateway(url) => Backend::Gateway(url.clone()),
+                };
+                Ok((endpoint, backend))
+            })
+            .collect::<Result<Vec<_>, IpfsEndpointError>>()?;
+        Ok(IpfsEndpoints {
+            down: Arc::new(Mutex::new(vec![None; endpoints.len()])),
+            endpoints,
+            client: Client::new(),
+            retry: IPFS_ENDPOINT_RETRY,
+        })
+    }
+
+    /// HTTP client to fetch from gateways with.
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// How long to skip endpoints for after they failed.
+    pub fn with_retry(mut self, retry: Duration) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn endpoints(&self) -> impl Iterator<Item = &IpfsEndpoint> {
+        self.endpoints.iter().map(|(endpoint, _)| endpoint)
+    }
+
+    /// Whether the endpoint failed recently and is skipped.
+    pub fn is_down(&self, endpoint: &IpfsEndpoint) -> bool {
+        let now = Instant::now();
+        let down = self.down.lock().unwrap();
+        self.endpoints
+            .iter()
+            .zip(down.iter())
+            .any(|((other, _), until)| other == endpoint && until.map_or(false, |t| t > now))
+    }
+
+    /// Indices of the endpoints to try, in order. If all of them are down, all of them are
+    /// tried anyway.
+    fn candidates(&self) -> Vec<usize> {
+        let now = Instant::now();
+        let down = self.down.lock().unwrap();
+        let up: Vec<usize> = (0..self.endpoints.len())
+            .filter(|index| down[*index].map_or(true, |until| until <= now))
+            .collect();
+        match up.is_empty() {
+            true => (0..self.endpoints.len()).collect(),
+            false => up,
+        }
+    }
+
+    fn mark(&self, index: usize, ok: bool) {
+        let until = (!ok).then(|| Instant::now() + self.retry);
+        self.down.lock().unwrap()[index] = until;
+    }
+
+    /// Fetch the (encrypted) data from an endpoint, making sure that it answers.
+    async fn fetch(&self, backend: &Backend, cid: &Cid) -> Result<IpfsDataStream, String> {
+        match backend {
+            Backend::Api(ipfs) => {
+                let mut data = ipfs
+                    .cat(&cid.to_string())
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e));
+                // the node is only contacted once the stream is polled.
+                let first = match data.next().await {
+                    Some(Err(e)) => return Err(e.to_string()),
+                    first => first,
+                };
+                Ok(Box::pin(futures::stream::iter(first).chain(data)))
+            }
+            Backend::Gateway(gateway) => gateway_fetch(&self.client, gateway, cid)
+                .await
+                .map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Fetch a snapshot from the first endpoint that answers, decrypt it on-the-fly with the
+    /// volume's decryption key.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(cid = %cid)))]
+    pub async fn fetch_decrypt(
+        &self,
+        secret: &Secret,
+        cid: &Cid,
+    ) -> Result<IpfsDataStream, IpfsEndpointError> {
+        let mut error = String::new();
+        for index in self.candidates() {
+            let (endpoint, backend) = &self.endpoints[index];
+            match self.fetch(backend, cid).await {
+                Ok(data) => {
+                    self.mark(index, true);
+                    let key = secret.to_chacha20_key();
+                    return Ok(Box::pin(ChaCha20DecryptionStream::new(data, &key)));
+                }
+                Err(e) => {
+                    warn!("Error fetching {cid} from {endpoint:?}, trying next endpoint: {e}");
+                    self.mark(index, false);
+                    error = e;
+                }
+            }
+        }
+        Err(IpfsEndpointError::Unavailable(error))
+    }
+}
+
+/// Fetch the (encrypted) data of a CID from an HTTP gateway.
+async fn gateway_fetch(client: &Client, gateway: &Url, cid: &Cid) -> Result<IpfsDataStream, Error> {
+    let url = gateway.join(&format!("/ipfs/{cid}"))?;
+    let response = client.get(url).send().await?;
+    if !response.status().is_success() {
+        return Err(Error::Unsuccessful(response.status()));
+    }
+    let data = response
+        .bytes_stream()
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e));
+    Ok(Box::pin(data))
+}
+
+/// Fetch a snapshot from an HTTP gateway such as `https://ipfs.io`, decrypt it on-the-fly
+/// with the volume's decryption key. This does not need an IPFS node.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(cid = %cid)))]
+pub async fn fetch_decrypt_gateway(
+    client: &Client,
+    gateway: &Url,
+    secret: &Secret,
+    cid: &Cid,
+) -> Result<IpfsDataStream, Error> {
+    let data = gateway_fetch(client, gateway, cid).await?;
+    let key = secret.to_chacha20_key();
+    Ok(Box::pin(ChaCha20DecryptionStream::new(data, &key)))
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_endpoints_fail_over() {
+    use crate::Privkey;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    let secret = Privkey::generate().derive_secret();
+    let data = Bytes::from_static(b"hello, world!");
+    let stream = futures::stream::iter(vec![Ok::<_, io::Error>(data.clone())]);
+    let encrypted: Vec<Bytes> = ChaCha20EncryptionStream::new(stream, &secret.to_chacha20_key())
+        .try_collect()
+        .await
+        .unwrap();
+    let encrypted = encrypted.concat();
+
+    // gateway answering every request with the encrypted data
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let gateway = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = socket.read(&mut request).await;
+            let header = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                encrypted.len()
+            );
+            socket.write_all(header.as_bytes()).await.unwrap();
+            socket.write_all(&encrypted).await.unwrap();
+        }
+    });
+
+    let dead = IpfsEndpoint::Api(Url::parse("http://127.0.0.1:1").unwrap());
+    let endpoints = IpfsEndpoints::new(vec![dead.clone(), IpfsEndpoint::Gateway(gateway)])
+        .unwrap()
+        .with_retry(Duration::from_secs(3600));
+    let cid = Cid::try_from("QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth").unwrap();
+    let stream = endpoints.fetch_decrypt(&secret, &cid).await.unwrap();
+    let fetched: Vec<Bytes> = stream.try_collect().await.unwrap();
+    assert_eq!(fetched.concat(), data);
+    assert!(endpoints.is_down(&dead));
+    assert_eq!(endpoints.candidates(), vec![1]);
+
+    assert!(matches!(
+        IpfsEndpoints::new(vec![]),
+        Err(IpfsEndpointError::NoEndpoints)
+    ));
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_fetch_decrypt_gateway() {
+    use crate::Privkey;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    // gateway that does not have the CID
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let gateway = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = [0; 1024];
+        let _ = socket.read(&mut request).await;
+        let response = "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+        socket.write_all(response.as_bytes()).await.unwrap();
+    });
+
+    let secret = Privkey::generate().derive_secret();
+    let cid = Cid::try_from("QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth").unwrap();
+    let result = fetch_decrypt_gateway(&Client::new(), &gateway, &secret, &cid).await;
+    assert!(matches!(
+        result,
+        Err(Error::Unsuccessful(reqwest::StatusCode::NOT_FOUND))
+    ));
+}
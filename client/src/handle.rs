@@ -0,0 +1,87 @@
+use crate::keys::{Hash, Privkey, Pubkey, Secret};
+use crate::manifest::{Manifest, ManifestSigned};
+use crate::types::{
+    SnapshotOrder, SnapshotOrdering, SnapshotUploaded, SortDirection, VolumeEdit, VolumeInfo,
+};
+use crate::{Error, StorageClient};
+
+/// Operations on a single volume, created with [`StorageClient::volume`]. It holds the key
+/// of the volume, so that requests are always made for, and signed with, the right one.
+#[derive(Clone, Debug)]
+pub struct VolumeHandle<'a> {
+    client: &'a StorageClient,
+    privkey: Privkey,
+}
+
+impl StorageClient {
+    /// Handle for the volume with this private key.
+    pub fn volume(&self, privkey: Privkey) -> VolumeHandle<'_> {
+        VolumeHandle {
+            client: self,
+            privkey,
+        }
+    }
+}
+
+impl<'a> VolumeHandle<'a> {
+    pub fn pubkey(&self) -> Pubkey {
+        self.privkey.pubkey()
+    }
+
+    /// Key that payloads of the volume are encrypted with.
+    pub fn secret(&self) -> Secret {
+        self.privkey.derive_secret()
+    }
+
+    /// Create the volume.
+    pub async fn create(&self) -> Result<(), Error> {
+        self.client.volume_create(&self.privkey).await
+    }
+
+    /// Get the volume's info.
+    pub async fn info(&self) -> Result<VolumeInfo, Error> {
+        self.client.volume_get(&self.pubkey()).await
+    }
+
+    /// Edit the volume's properties.
+    pub async fn edit(&self, edit: &VolumeEdit) -> Result<(), Error> {
+        self.client.volume_edit(&self.privkey, edit).await
+    }
+
+    /// Remove the volume.
+    pub async fn remove(&self) -> Result<(), Error> {
+        self.client.volume_remove(&self.privkey).await
+    }
+
+    /// Hashes of all snapshots of the volume, by ascending generation.
+    pub async fn snapshots(&self) -> Result<Vec<Hash>, Error> {
+        self.client.snapshot_list(&self.pubkey(), None, false).await
+    }
+
+    /// Fetch the manifest of a snapshot of the volume, validated against its key.
+    pub async fn snapshot(&self, snapshot: &Hash) -> Result<ManifestSigned, Error> {
+        self.client.snapshot_fetch(&self.pubkey(), snapshot).await
+    }
+
+    /// Sign a manifest with the volume's key and upload it.
+    pub async fn upload(&self, manifest: &Manifest) -> Result<SnapshotUploaded, Error> {
+        let manifest = manifest.sign(&self.privkey);
+        self.client.snapshot_upload(&self.pubkey(), &manifest).await
+    }
+
+    /// Manifest of the snapshot with the highest generation, if there are any.
+    pub async fn latest(&self) -> Result<Option<ManifestSigned>, Error> {
+        let ordering = SnapshotOrdering {
+            order: SnapshotOrder::Generation,
+            direction: SortDirection::Desc,
+        };
+        let snapshots = self
+            .client
+            .snapshot_list_ordered(&self.pubkey(), None, false, &ordering)
+            .await?;
+        match snapshots.first() {
+            Some(latest) => Ok(Some(self.snapshot(latest).await?)),
+            None => Ok(None),
+        }
+    }
+}
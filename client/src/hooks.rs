@@ -0,0 +1,67 @@
+use crate::keys::{Hash, Pubkey};
+use crate::types::SnapshotUploaded;
+use crate::Error;
+use reqwest::Method;
+use std::fmt;
+use std::sync::Arc;
+use url::Url;
+
+/// Called with the method and URL of every request, before it is sent.
+pub type RequestHook = Arc<dyn Fn(&Method, &Url) + Send + Sync>;
+
+/// Called with the volume and hash of a snapshot whose upload is retried.
+pub type RetryHook = Arc<dyn Fn(&Pubkey, &Hash) + Send + Sync>;
+
+/// Called with the volume and the result of every snapshot that was uploaded.
+pub type UploadHook = Arc<dyn Fn(&Pubkey, &SnapshotUploaded) + Send + Sync>;
+
+/// Called with every error of a request to the service.
+pub type ErrorHook = Arc<dyn Fn(&Error) + Send + Sync>;
+
+/// Callbacks of a [`StorageClient`](crate::StorageClient), for applications to surface its
+/// activity in their own interface or telemetry. Set with the `on_*` methods of the builder.
+/// They are called synchronously, so they should return quickly.
+#[derive(Clone, Default)]
+pub struct ClientHooks {
+    pub(crate) request: Option<RequestHook>,
+    pub(crate) retry: Option<RetryHook>,
+    pub(crate) upload_complete: Option<UploadHook>,
+    pub(crate) error: Option<ErrorHook>,
+}
+
+impl ClientHooks {
+    pub(crate) fn request(&self, method: &Method, url: &Url) {
+        if let Some(hook) = &self.request {
+            hook(method, url);
+        }
+    }
+
+    pub(crate) fn retry(&self, volume: &Pubkey, snapshot: &Hash) {
+        if let Some(hook) = &self.retry {
+            hook(volume, snapshot);
+        }
+    }
+
+    pub(crate) fn upload_complete(&self, volume: &Pubkey, uploaded: &SnapshotUploaded) {
+        if let Some(hook) = &self.upload_complete {
+            hook(volume, uploaded);
+        }
+    }
+
+    pub(crate) fn error(&self, error: &Error) {
+        if let Some(hook) = &self.error {
+            hook(error);
+        }
+    }
+}
+
+impl fmt::Debug for ClientHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientHooks")
+            .field("request", &self.request.is_some())
+            .field("retry", &self.retry.is_some())
+            .field("upload_complete", &self.upload_complete.is_some())
+            .field("error", &self.error.is_some())
+            .finish()
+    }
+}
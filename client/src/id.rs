@@ -0,0 +1,76 @@
+use rand_core::{OsRng, RngCore};
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// Version number of time-ordered UUIDs.
+const UUID_V7: usize = 7;
+
+/// Source of new identifiers, such as machine IDs.
+pub trait IdGenerator: Send + Sync {
+    fn generate(&self) -> Uuid;
+}
+
+/// Generates random (version 4) UUIDs.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RandomIds;
+
+impl IdGenerator for RandomIds {
+    fn generate(&self) -> Uuid {
+        Uuid::new_v4()
+    }
+}
+
+/// Generates time-ordered (version 7) UUIDs. These start with the time they were generated
+/// at, so identifiers generated later sort after earlier ones, which keeps inserts into
+/// indexes on them local.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TimeOrderedIds;
+
+impl TimeOrderedIds {
+    /// Generate a time-ordered UUID for the given time, in milliseconds since the epoch.
+    pub fn at(time: u64) -> Uuid {
+        let mut bytes = [0; 16];
+        OsRng.fill_bytes(&mut bytes[6..]);
+        bytes[..6].copy_from_slice(&time.to_be_bytes()[2..]);
+        bytes[6] = 0x70 | (bytes[6] & 0x0f);
+        bytes[8] = 0x80 | (bytes[8] & 0x3f);
+        Uuid::from_bytes(bytes)
+    }
+}
+
+impl IdGenerator for TimeOrderedIds {
+    fn generate(&self) -> Uuid {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        TimeOrderedIds::at(time as u64)
+    }
+}
+
+/// Time a time-ordered UUID was generated at, in milliseconds since the epoch. Returns
+/// `None` for other versions of UUIDs.
+pub fn id_timestamp(id: &Uuid) -> Option<u64> {
+    if id.get_version_num() != UUID_V7 {
+        return None;
+    }
+    let mut time = [0; 8];
+    time[2..].copy_from_slice(&id.as_bytes()[..6]);
+    Some(u64::from_be_bytes(time))
+}
+
+#[test]
+fn test_time_ordered_ids() {
+    let id = TimeOrderedIds::at(1666000000000);
+    assert_eq!(id.get_version_num(), UUID_V7);
+    assert_eq!(id.get_variant(), uuid::Variant::RFC4122);
+    assert_eq!(id_timestamp(&id), Some(1666000000000));
+    assert_eq!(id_timestamp(&RandomIds.generate()), None);
+
+    // later identifiers sort after earlier ones, also as strings
+    let later = TimeOrderedIds::at(1666000000001);
+    assert!(id < later);
+    assert!(id.to_string() < later.to_string());
+    let now = TimeOrderedIds.generate();
+    assert!(later < now);
+}
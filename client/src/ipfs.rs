@@ -1,12 +1,99 @@
 use crate::keys::Secret;
 use crate::stream::*;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use bytes::Bytes;
 use cid::Cid;
 use futures::{Stream, TryStreamExt};
 use ipfs_api::{IpfsApi, IpfsClient};
 use reqwest::Error;
-use std::{pin::Pin, str::FromStr};
+use std::{fmt, pin::Pin, str::FromStr};
+
+/// IPFS features used by this crate, along with the oldest release that supports them.
+pub const IPFS_FEATURES: &[(&str, IpfsVersion)] = &[("the HTTP API", IpfsVersion(0, 5, 0))];
+
+/// Version of an IPFS node (major, minor, patch).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct IpfsVersion(pub u64, pub u64, pub u64);
+
+impl FromStr for IpfsVersion {
+    type Err = IpfsPreflightError;
+
+    /// Parses versions like `0.12.2` or `0.13.0-rc1`, anything after the patch number is
+    /// ignored.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let invalid = || IpfsPreflightError::InvalidVersion(input.to_string());
+        let release = input.trim().split(|c| c == '-' || c == '+').next();
+        let mut parts = release.ok_or_else(invalid)?.split('.');
+        let mut next = || -> Result<u64, IpfsPreflightError> {
+            parts
+                .next()
+                .and_then(|part| part.parse().ok())
+                .ok_or_else(invalid)
+        };
+        Ok(IpfsVersion(next()?, next()?, next()?))
+    }
+}
+
+impl fmt::Display for IpfsVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.0, self.1, self.2)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum IpfsPreflightError {
+    #[error("IPFS API unreachable at {0:}: {1:}")]
+    Unreachable(String, String),
+    #[error("IPFS node reported invalid version {0:?}")]
+    InvalidVersion(String),
+    #[error("IPFS node too old for {feature:}: version is {version:}, need at least {required:}")]
+    TooOld {
+        feature: &'static str,
+        version: IpfsVersion,
+        required: IpfsVersion,
+    },
+    #[error("IPFS node does not support {0:}: {1:}")]
+    Unsupported(&'static str, String),
+}
+
+/// Check that the IPFS node is reachable and supports the features needed for uploading
+/// and fetching snapshots, so that workflows fail early with a clear error. The `url` is
+/// only used for error messages. Returns the version of the node.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+pub async fn ipfs_preflight(
+    ipfs: &IpfsClient,
+    url: &str,
+) -> Result<IpfsVersion, IpfsPreflightError> {
+    let version = ipfs
+        .version()
+        .await
+        .map_err(|e| IpfsPreflightError::Unreachable(url.to_string(), e.to_string()))?;
+    let version: IpfsVersion = version.version.parse()?;
+    for (feature, required) in IPFS_FEATURES {
+        if version < *required {
+            return Err(IpfsPreflightError::TooOld {
+                feature: *feature,
+                version,
+                required: *required,
+            });
+        }
+    }
+
+    // probe the files API, which some gateways and pinning services do not expose.
+    ipfs.files_stat("/")
+        .await
+        .map_err(|e| IpfsPreflightError::Unsupported("the files API", e.to_string()))?;
+    Ok(version)
+}
+
+/// Sizes of a payload, measured while uploading it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UploadSize {
+    /// Size of the data before encryption, in bytes.
+    pub plaintext: u64,
+    /// Size of the data as stored, after encryption, in bytes.
+    pub ciphertext: u64,
+}
 
 /// Upload a stream of data to IPFS, encrypted with the volume's encryption key.
 pub async fn upload_encrypt(
@@ -14,11 +101,50 @@ pub async fn upload_encrypt(
     secret: &Secret,
     data: Pin<Box<dyn Stream<Item = Result<Bytes, std::io::Error>> + Send + Sync>>,
 ) -> Result<Cid> {
-    let stream = ChaCha20EncryptionStream::new(data, &secret.to_chacha20_key());
-    let reader = stream.into_async_read();
+    let (cid, _) = upload_encrypt_sized(ipfs, secret, data).await?;
+    Ok(cid)
+}
+
+/// Upload a stream of data to IPFS, encrypted with the volume's encryption key, measuring
+/// its size before and after encryption on the way. The length of the data does not need
+/// to be known up front, so this works for pipes such as `btrfs send`.
+pub async fn upload_encrypt_sized(
+    ipfs: &IpfsClient,
+    secret: &Secret,
+    data: Pin<Box<dyn Stream<Item = Result<Bytes, std::io::Error>> + Send + Sync>>,
+) -> Result<(Cid, UploadSize)> {
+    upload_encrypt_progress(ipfs, secret, data, None).await
+}
+
+/// Like [`upload_encrypt_sized`], but adds the bytes sent to IPFS to `progress` as the upload
+/// goes on.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+pub async fn upload_encrypt_progress(
+    ipfs: &IpfsClient,
+    secret: &Secret,
+    data: Pin<Box<dyn Stream<Item = Result<Bytes, std::io::Error>> + Send + Sync>>,
+    progress: Option<&BytesCount>,
+) -> Result<(Cid, UploadSize)> {
+    let plaintext = CountBytesStream::new(data);
+    let plaintext_count = plaintext.bytes_count();
+    let stream = ChaCha20EncryptionStream::new(plaintext, &secret.to_chacha20_key());
+    let ciphertext = CountBytesStream::new(Box::pin(stream));
+    let ciphertext_count = ciphertext.bytes_count();
+    let progress = progress.cloned();
+    let reader = ciphertext
+        .inspect_ok(move |bytes| {
+            if let Some(progress) = &progress {
+                progress.add(bytes.len());
+            }
+        })
+        .into_async_read();
     let cid = ipfs.add_async(reader).await?;
     let cid = Cid::from_str(&cid.hash)?;
-    Ok(cid)
+    let size = UploadSize {
+        plaintext: plaintext_count.get() as u64,
+        ciphertext: ciphertext_count.get() as u64,
+    };
+    Ok((cid, size))
 }
 
 /// Fetch a snapshot from IPFS, decrypt it on-the-fly with the volume's decryption key.
@@ -27,10 +153,74 @@ pub async fn fetch_decrypt(
     secret: &Secret,
     cid: &Cid,
 ) -> Result<Pin<Box<dyn Stream<Item = Result<Bytes, ipfs_api::Error>> + Send>>, Error> {
-    let data = ipfs.cat(&cid.to_string());
+    fetch_decrypt_progress(ipfs, secret, cid, None).await
+}
+
+/// Like [`fetch_decrypt`], but adds the bytes received from IPFS to `progress` as the
+/// returned stream is consumed.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(cid = %cid)))]
+pub async fn fetch_decrypt_progress(
+    ipfs: &IpfsClient,
+    secret: &Secret,
+    cid: &Cid,
+    progress: Option<&BytesCount>,
+) -> Result<Pin<Box<dyn Stream<Item = Result<Bytes, ipfs_api::Error>> + Send>>, Error> {
+    let progress = progress.cloned();
+    let data = ipfs.cat(&cid.to_string()).inspect_ok(move |bytes| {
+        if let Some(progress) = &progress {
+            progress.add(bytes.len());
+        }
+    });
     let data = Box::pin(ChaCha20DecryptionStream::new(
         data,
         &secret.to_chacha20_key(),
     ));
     Ok(data)
 }
+
+/// Resume fetching a snapshot from IPFS at `offset` bytes into its decrypted data, such as
+/// after a restore was interrupted. Only the nonce and the data from the offset on are
+/// fetched, the keystream is seeked to the matching position.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(cid = %cid, offset = offset)))]
+pub async fn fetch_decrypt_from(
+    ipfs: &IpfsClient,
+    secret: &Secret,
+    cid: &Cid,
+    offset: u64,
+) -> Result<Pin<Box<dyn Stream<Item = Result<Bytes, ipfs_api::Error>> + Send>>> {
+    let path = cid.to_string();
+    let nonce: Vec<u8> = ipfs
+        .cat_range(&path, 0, CHACHA20_NONCE_LENGTH)
+        .map_ok(|bytes| bytes.to_vec())
+        .try_concat()
+        .await?;
+    let nonce: [u8; CHACHA20_NONCE_LENGTH] = nonce
+        .try_into()
+        .map_err(|_| anyhow!("Data of {cid} is too short to be encrypted"))?;
+    // the node reads until the end of the data, but does not accept unlimited lengths.
+    let start = CHACHA20_NONCE_LENGTH + usize::try_from(offset)?;
+    let data = ipfs.cat_range(&path, start, i64::MAX as usize);
+    let data = Box::pin(ChaCha20DecryptionStream::resume(
+        data,
+        &secret.to_chacha20_key(),
+        &nonce,
+        offset,
+    ));
+    Ok(data)
+}
+
+#[test]
+fn test_ipfs_version() {
+    assert_eq!(
+        "0.12.2".parse::<IpfsVersion>().unwrap(),
+        IpfsVersion(0, 12, 2)
+    );
+    assert_eq!(
+        "0.13.0-rc1".parse::<IpfsVersion>().unwrap(),
+        IpfsVersion(0, 13, 0)
+    );
+    assert!("0.12".parse::<IpfsVersion>().is_err());
+    assert!("latest".parse::<IpfsVersion>().is_err());
+    assert!(IpfsVersion(0, 4, 23) < IpfsVersion(0, 5, 0));
+    assert!(IpfsVersion(0, 10, 0) > IpfsVersion(0, 5, 0));
+}
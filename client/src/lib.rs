@@ -1,46 +1,312 @@
 //! Library used to interact with storage backend and IPFS (to store
 //! encrypted snapshots and manage metadata).
 
+pub use crate::backend::*;
+pub use crate::cache::*;
+pub use crate::chain::*;
+pub use crate::client::*;
+pub use crate::endpoints::*;
+pub use crate::handle::*;
+pub use crate::hooks::*;
+pub use crate::id::*;
 pub use crate::ipfs::*;
 pub use crate::keys::{Hash, Privkey, Pubkey, Secret};
 pub use crate::manifest::*;
+pub use crate::net::*;
+pub use crate::prefetch::*;
+pub use crate::proxy::*;
+pub use crate::queue::*;
+pub use crate::signature::*;
+pub use crate::signing::*;
 pub use crate::stream::*;
+pub use crate::tls::*;
 pub use crate::types::*;
 use anyhow::Result;
+use futures::stream::Stream;
 use reqwest::Client;
+use std::collections::BTreeMap;
+use std::pin::Pin;
 use url::Url;
+use uuid::Uuid;
 
+mod backend;
+mod cache;
+mod chain;
+mod client;
+mod endpoints;
+mod handle;
+mod hooks;
+mod id;
 mod ipfs;
 pub mod keys;
 mod manifest;
+mod net;
+mod prefetch;
+mod proxy;
+mod queue;
+mod signature;
+mod signing;
 pub mod stream;
+#[cfg(feature = "testing")]
+pub mod testing;
 #[cfg(test)]
 mod tests;
+mod tls;
 mod types;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("Error making HTTP request: {0:}")]
-    Reqwest(#[from] reqwest::Error),
+    Reqwest(reqwest::Error),
+    #[error("Request timed out: {0:}")]
+    Timeout(reqwest::Error),
     #[error("Error parsing URL: {0:}")]
     UrlParse(#[from] url::ParseError),
     #[error("Error making HTTP request: {0:}")]
     Unsuccessful(reqwest::StatusCode),
+    #[error("{0:}")]
+    VolumeNotFound(String),
+    #[error("{0:}")]
+    SnapshotNotFound(String),
+    #[error("{0:}")]
+    ManifestExists(String),
+    #[error("{0:}")]
+    QuotaExceeded(String),
+    #[error("{0:}")]
+    Locked(String),
+    #[error("{0:}")]
+    HasChildren(String),
     #[error("Other error occured: {0:?}")]
     Other(#[from] anyhow::Error),
     #[error("Error parsing manifest: {0:}")]
     ManifestSignedParse(#[from] ManifestSignedParseError),
+    #[error("Manifest {0:} failed validation")]
+    ManifestValidation(Hash),
+    #[error("Snapshot {0:} breaks the chain: {1:}")]
+    ChainInvalid(Hash, String),
+    #[error("Error building manifest: {0:}")]
+    ManifestBuild(#[from] ManifestBuildError),
+    #[error("Error parsing event: {0:}")]
+    EventParse(#[from] serde_json::Error),
+    #[error("Missing URL of the storage API")]
+    MissingApi,
 }
 
+impl Error {
+    /// Error for an unsuccessful response of the service. Errors with a code callers are
+    /// likely to handle map to their own variants, any others (and responses of older
+    /// versions of the service) to [`Error::Unsuccessful`].
+    pub fn from_body(status: reqwest::StatusCode, body: &[u8]) -> Self {
+        let response: ErrorResponse = match serde_json::from_slice(body) {
+            Ok(response) => response,
+            Err(_) => return Error::Unsuccessful(status),
+        };
+        match response.code {
+            ErrorCode::VolumeNotFound => Error::VolumeNotFound(response.message),
+            ErrorCode::SnapshotNotFound => Error::SnapshotNotFound(response.message),
+            ErrorCode::ManifestExists => Error::ManifestExists(response.message),
+            ErrorCode::QuotaExceeded => Error::QuotaExceeded(response.message),
+            ErrorCode::Locked => Error::Locked(response.message),
+            ErrorCode::HasChildren => Error::HasChildren(response.message),
+            _ => Error::Unsuccessful(status),
+        }
+    }
+
+    /// Whether the request failed because the service could not be reached or had an
+    /// internal error, so that it may succeed when retried later.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Reqwest(_) | Error::Timeout(_) => true,
+            Error::Unsuccessful(status) => status.is_server_error(),
+            _ => false,
+        }
+    }
+
+    pub(crate) async fn from_response(response: reqwest::Response) -> Self {
+        let status = response.status();
+        match response.bytes().await {
+            Ok(body) => Error::from_body(status, &body),
+            Err(_) => Error::Unsuccessful(status),
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Self {
+        if error.is_timeout() {
+            Error::Timeout(error)
+        } else {
+            Error::Reqwest(error)
+        }
+    }
+}
+
+/// Stream of account activity events.
+pub type AccountEventStream = Pin<Box<dyn Stream<Item = Result<AccountEvent, Error>> + Send>>;
+
+/// Stream of the hashes of snapshots, fetched page by page.
+pub type SnapshotHashStream = Pin<Box<dyn Stream<Item = Result<Hash, Error>> + Send>>;
+
 /// Health check.
 pub async fn health_check(api: &Url, client: &Client) -> Result<(), Error> {
-    let url = api.join(&format!("/health"))?;
-    let response = client.get(url).send().await?;
-    if response.status().is_success() {
-        Ok(())
-    } else {
-        Err(Error::Unsuccessful(response.status()))
-    }
+    StorageClient::from_parts(api, client, None)
+        .health_check()
+        .await
+}
+
+/// Readiness of the service: whether its startup self-test passed and its database is
+/// usable. A service that is not ready yet answers with its report, too.
+pub async fn health_ready(api: &Url, client: &Client) -> Result<HealthReport, Error> {
+    StorageClient::from_parts(api, client, None)
+        .health_ready()
+        .await
+}
+
+/// Fetch the principal (account and kind of token) that the token maps to.
+pub async fn whoami(api: &Url, client: &Client, token: &str) -> Result<Whoami, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .whoami()
+        .await
+}
+
+/// Issue a new API key for the account of the token.
+pub async fn api_key_create(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    request: &ApiKeyCreate,
+) -> Result<ApiKeyCreated, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .api_key_create(request)
+        .await
+}
+
+/// List the API keys of the account of the token.
+pub async fn api_key_list(
+    api: &Url,
+    client: &Client,
+    token: &str,
+) -> Result<Vec<ApiKeyInfo>, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .api_key_list()
+        .await
+}
+
+/// Revoke an API key of the account of the token.
+pub async fn api_key_revoke(api: &Url, client: &Client, token: &str, id: i64) -> Result<(), Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .api_key_revoke(id)
+        .await
+}
+
+/// Fetch the default labels of the account, which are attached to every snapshot uploaded
+/// to its volumes.
+pub async fn account_labels(
+    api: &Url,
+    client: &Client,
+    token: &str,
+) -> Result<BTreeMap<String, String>, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .account_labels()
+        .await
+}
+
+/// Replace the default labels of the account.
+pub async fn account_labels_set(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    labels: &BTreeMap<String, String>,
+) -> Result<(), Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .account_labels_set(labels)
+        .await
+}
+
+/// Settings of the account.
+pub async fn account_settings(
+    api: &Url,
+    client: &Client,
+    token: &str,
+) -> Result<AccountSettings, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .account_settings()
+        .await
+}
+
+/// Replace the settings of the account.
+pub async fn account_settings_set(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    settings: &AccountSettings,
+) -> Result<(), Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .account_settings_set(settings)
+        .await
+}
+
+/// Alerts currently raised for volumes of the account.
+pub async fn account_alerts(api: &Url, client: &Client, token: &str) -> Result<Vec<Alert>, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .account_alerts()
+        .await
+}
+
+/// When each volume of the account was last verified to be restorable by a restore drill.
+pub async fn account_drills(
+    api: &Url,
+    client: &Client,
+    token: &str,
+) -> Result<Vec<DrillStatus>, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .account_drills()
+        .await
+}
+
+/// Find snapshots of the account that have all of the given labels.
+pub async fn snapshot_search(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    labels: &BTreeMap<String, String>,
+) -> Result<Vec<LabelMatch>, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .snapshot_search(labels)
+        .await
+}
+
+/// Find snapshots of the account created on the given machine, newest first.
+pub async fn snapshot_search_machine(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    machine: &Uuid,
+    limit: Option<u64>,
+) -> Result<Vec<MachineSnapshot>, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .snapshot_search_machine(machine, limit)
+        .await
+}
+
+/// Find payloads referenced by more than one snapshot of the account. If a payload is
+/// given, returns the snapshots referencing it instead, or nothing if it is not stored yet.
+pub async fn snapshot_duplicates(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    data: Option<&Url>,
+) -> Result<Vec<DuplicateData>, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .snapshot_duplicates(data)
+        .await
+}
+
+/// Fetch the capabilities of the storage service, used to negotiate the manifest version.
+pub async fn capabilities(api: &Url, client: &Client) -> Result<Capabilities, Error> {
+    StorageClient::from_parts(api, client, None)
+        .capabilities()
+        .await
 }
 
 /// Fetch latest (as in, most current generation) based on the parent
@@ -53,26 +319,76 @@ pub async fn snapshot_list(
     parent: Option<&Hash>,
     root: bool,
 ) -> Result<Vec<Hash>, Error> {
-    let url = api
-        .join(&format!("/api/v1/volume/{}/snapshots", &volume.to_hex()))
-        .unwrap();
-    let mut query = vec![];
-    if let Some(parent) = parent {
-        query.push(("parent", parent.to_string()));
-    }
-    if root {
-        query.push(("root", "true".to_string()));
-    }
-    let response = client
-        .get(url)
-        .header("Authorization", format!("Bearer {token}"))
-        .query(&query)
-        .send()
-        .await?;
-    if !response.status().is_success() {
-        return Err(Error::Unsuccessful(response.status()));
-    }
-    Ok(response.json::<Vec<Hash>>().await?)
+    StorageClient::from_parts(api, client, Some(token))
+        .snapshot_list(volume, parent, root)
+        .await
+}
+
+/// List snapshots in the given order.
+pub async fn snapshot_list_ordered(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    volume: &Pubkey,
+    parent: Option<&Hash>,
+    root: bool,
+    ordering: &SnapshotOrdering,
+) -> Result<Vec<Hash>, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .snapshot_list_ordered(volume, parent, root, ordering)
+        .await
+}
+
+/// Check which of the given snapshots exist in the volume, returns the hashes of the ones
+/// that do. Lets sync agents reconcile many snapshots with a single request.
+pub async fn snapshot_exists(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    volume: &Pubkey,
+    hashes: &[Hash],
+) -> Result<Vec<Hash>, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .snapshot_exists(volume, hashes)
+        .await
+}
+
+/// List a page of snapshots with full records. Pass the cursor of the returned page in the
+/// options to fetch the next page.
+pub async fn snapshot_list_v2(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    volume: &Pubkey,
+    options: &SnapshotListOptions,
+) -> Result<SnapshotPage, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .snapshot_list_v2(volume, options)
+        .await
+}
+
+/// List the snapshots of the volume with the v2 listing API, following the cursors of the
+/// pages. The limit of the options is the size of the pages that are fetched.
+pub fn snapshot_list_paged(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    volume: &Pubkey,
+    options: &SnapshotListOptions,
+) -> SnapshotHashStream {
+    StorageClient::from_parts(api, client, Some(token)).snapshot_list_paged(volume, options)
+}
+
+/// Snapshots of the volume that were uploaded before their parent, ordered by generation.
+pub async fn snapshot_pending_list(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    volume: &Pubkey,
+) -> Result<Vec<SnapshotPending>, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .snapshot_pending_list(volume)
+        .await
 }
 
 /// Create new snapshot repository, given a private key.
@@ -82,16 +398,9 @@ pub async fn volume_create(
     token: &str,
     volume: &Privkey,
 ) -> Result<(), Error> {
-    let url = api.join(&format!("/api/v1/volume/{}", &volume.pubkey().to_hex()))?;
-    let response = client
-        .post(url)
-        .header("Authorization", format!("Bearer {token}"))
-        .send()
-        .await?;
-    if !response.status().is_success() {
-        return Err(Error::Unsuccessful(response.status()));
-    }
-    Ok(())
+    StorageClient::from_parts(api, client, Some(token))
+        .volume_create(volume)
+        .await
 }
 
 /// Get volume's info.
@@ -101,19 +410,23 @@ pub async fn volume_get(
     token: &str,
     volume: &Pubkey,
 ) -> Result<VolumeInfo, Error> {
-    let url = api.join(&format!("/api/v1/volume/{}", &volume.to_hex()))?;
-    let response = client
-        .get(url)
-        .header("Authorization", format!("Bearer {token}"))
-        .send()
-        .await?;
-    if !response.status().is_success() {
-        return Err(Error::Unsuccessful(response.status()));
-    }
-    Ok(response.json().await?)
+    StorageClient::from_parts(api, client, Some(token))
+        .volume_get(volume)
+        .await
+}
+
+/// List the volumes of the account of the token with their info, ordered by public key.
+pub async fn volume_list(
+    api: &Url,
+    client: &Client,
+    token: &str,
+) -> Result<Vec<(Pubkey, VolumeInfo)>, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .volume_list()
+        .await
 }
 
-/// Edit a volume's properties.
+/// Edit a volume's properties. The request is signed with the volume's key.
 pub async fn volume_edit(
     api: &Url,
     client: &Client,
@@ -121,62 +434,263 @@ pub async fn volume_edit(
     volume: &Privkey,
     edit: &VolumeEdit,
 ) -> Result<(), Error> {
-    let url = api.join(&format!("/api/v1/volume/{}", &volume.pubkey().to_hex()))?;
-    let response = client
-        .patch(url)
-        .header("Authorization", format!("Bearer {token}"))
-        .json(&edit)
-        .send()
-        .await?;
-    if !response.status().is_success() {
-        return Err(Error::Unsuccessful(response.status()));
-    }
-    Ok(())
+    StorageClient::from_parts(api, client, Some(token))
+        .volume_edit(volume, edit)
+        .await
 }
 
-/// Remove volume.
+/// Remove volume. The request is signed with the volume's key, and the server requires
+/// signing a challenge it issues first to prove possession of the key.
 pub async fn volume_remove(
     api: &Url,
     client: &Client,
     token: &str,
     volume: &Privkey,
 ) -> Result<(), Error> {
-    let url = api.join(&format!("/api/v1/volume/{}", &volume.pubkey().to_hex()))?;
-    let response = client
-        .delete(url)
-        .header("Authorization", format!("Bearer {token}"))
-        .send()
-        .await?;
-    if !response.status().is_success() {
-        return Err(Error::Unsuccessful(response.status()));
-    }
-    Ok(())
+    StorageClient::from_parts(api, client, Some(token))
+        .volume_remove(volume)
+        .await
+}
+
+/// Export the metadata of a volume as a portable archive.
+pub async fn volume_export(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    volume: &Pubkey,
+) -> Result<VolumeArchive, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .volume_export(volume)
+        .await
+}
+
+/// Import a volume archive, creating the volume with all of its snapshots. The volume must
+/// not exist yet.
+pub async fn volume_import(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    archive: &VolumeArchive,
+) -> Result<(), Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .volume_import(archive)
+        .await
+}
+
+/// Get the status of replicating a volume to the peers of the storage service.
+pub async fn volume_replication(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    volume: &Pubkey,
+) -> Result<Vec<ReplicationStatus>, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .volume_replication(volume)
+        .await
+}
+
+/// Record the result of a restore drill of a snapshot of the volume.
+pub async fn drill_record(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    volume: &Pubkey,
+    result: &DrillResult,
+) -> Result<Drill, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .drill_record(volume, result)
+        .await
+}
+
+/// Restore drills recorded for the volume, most recent first.
+pub async fn drill_list(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    volume: &Pubkey,
+) -> Result<Vec<Drill>, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .drill_list(volume)
+        .await
 }
 
-/// Upload a new snapshot
+/// Accounts other than the owner that were granted access to the volume.
+pub async fn volume_acl_list(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    volume: &Pubkey,
+) -> Result<Vec<VolumeGrant>, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .volume_acl_list(volume)
+        .await
+}
+
+/// Grant another account access to the volume, replacing any access it was granted before.
+pub async fn volume_acl_grant(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    volume: &Pubkey,
+    grant: &VolumeGrant,
+) -> Result<(), Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .volume_acl_grant(volume, grant)
+        .await
+}
+
+/// Revoke the access of an account to the volume.
+pub async fn volume_acl_revoke(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    volume: &Pubkey,
+    account: &Uuid,
+) -> Result<(), Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .volume_acl_revoke(volume, account)
+        .await
+}
+
+/// Request a challenge for removing a volume, which has to be signed with its key.
+pub async fn volume_challenge(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    volume: &Pubkey,
+) -> Result<VolumeChallenge, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .volume_challenge(volume)
+        .await
+}
+
+/// Restore a removed volume. This is only possible until the volume is purged.
+pub async fn volume_restore(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    volume: &Pubkey,
+) -> Result<(), Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .volume_restore(volume)
+        .await
+}
+
+/// Mint a token that only allows uploading snapshots to the volume, valid for `ttl` (such as
+/// `30m` or `1h`, defaults to one hour). Only the owner of the volume can do this.
+pub async fn upload_token_create(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    volume: &Pubkey,
+    ttl: Option<&str>,
+) -> Result<UploadToken, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .upload_token_create(volume, ttl)
+        .await
+}
+
+/// Delete an account with all of its volumes, snapshots, API keys and labels. Requires a
+/// system token.
+pub async fn account_delete(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    account: &Uuid,
+) -> Result<AccountDeleted, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .account_delete(account)
+        .await
+}
+
+/// Fetch statistics about all stored data, including snapshots created per day over the
+/// given number of days. Only allowed for system tokens.
+pub async fn admin_stats(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    days: u64,
+) -> Result<StorageStats, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .admin_stats(days)
+        .await
+}
+
+/// Upload a new snapshot, returning its hash, whether it was stored already, where its
+/// payload is and warnings about it.
 pub async fn snapshot_upload(
     api: &Url,
     client: &Client,
     token: &str,
     volume: &Pubkey,
     manifest: &ManifestSigned,
-) -> Result<(), Error> {
-    let url = api
-        .join(&format!("/api/v1/volume/{}/snapshot", &volume.to_hex()))
-        .unwrap();
-    let response = client
-        .post(url)
-        .header("Authorization", format!("Bearer {token}"))
-        .body(manifest.data())
-        .send()
-        .await?;
-    if !response.status().is_success() {
-        return Err(Error::Unsuccessful(response.status()));
-    }
-    Ok(())
+) -> Result<SnapshotUploaded, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .snapshot_upload(volume, manifest)
+        .await
+}
+
+/// Upload a new snapshot with the given idempotency key instead of the one derived from the
+/// manifest, or without one.
+pub async fn snapshot_upload_with_key(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    volume: &Pubkey,
+    manifest: &ManifestSigned,
+    key: Option<&str>,
+) -> Result<SnapshotUploaded, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .snapshot_upload_with_key(volume, manifest, key)
+        .await
+}
+
+/// Encrypt and upload the payload of a snapshot to IPFS, then build, sign and upload its
+/// manifest. The payload is streamed in a single pass and its size is measured on the way,
+/// so it does not need to be known up front. The manifest records the encrypted size, which
+/// is what the snapshot occupies in storage.
+pub async fn snapshot_publish(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    ipfs: &ipfs_api::IpfsClient,
+    privkey: &Privkey,
+    snapshot: &SnapshotPublish,
+    data: Pin<Box<dyn Stream<Item = Result<bytes::Bytes, std::io::Error>> + Send + Sync>>,
+) -> Result<SnapshotPublished, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .snapshot_publish(ipfs, privkey, snapshot, data)
+        .await
+}
+
+/// Upload a batch of snapshots in a single request. The server stores either all or none of
+/// them, the results indicate what happened to each manifest.
+pub async fn snapshot_upload_batch(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    volume: &Pubkey,
+    manifests: &[ManifestSigned],
+) -> Result<Vec<SnapshotUploadResult>, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .snapshot_upload_batch(volume, manifests)
+        .await
 }
 
-/// Upload a new snapshot
+/// Subscribe to activity on all volumes of the account. Events are pushed by the server as
+/// they happen, the stream ends when the connection is closed.
+pub async fn account_events(
+    api: &Url,
+    client: &Client,
+    token: &str,
+) -> Result<AccountEventStream, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .account_events()
+        .await
+}
+
+/// Fetch the signed manifest of a snapshot. The manifest is checked to have the requested
+/// hash and to be signed by the volume, so that the service cannot forge manifests.
 pub async fn snapshot_fetch(
     api: &Url,
     client: &Client,
@@ -184,22 +698,133 @@ pub async fn snapshot_fetch(
     volume: &Pubkey,
     snapshot: &Hash,
 ) -> Result<ManifestSigned, Error> {
-    let url = api
-        .join(&format!(
-            "/api/v1/volume/{}/{}",
-            &volume.to_hex(),
-            &snapshot.to_hex(),
-        ))
-        .unwrap();
-    let response = client
-        .get(url)
-        .header("Authorization", format!("Bearer {token}"))
-        .send()
-        .await?;
+    StorageClient::from_parts(api, client, Some(token))
+        .snapshot_fetch(volume, snapshot)
+        .await
+}
+
+/// Fetch the signed manifest of a snapshot without validating it, for callers that check
+/// manifests themselves.
+pub async fn snapshot_fetch_unverified(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    volume: &Pubkey,
+    snapshot: &Hash,
+) -> Result<ManifestSigned, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .snapshot_fetch_unverified(volume, snapshot)
+        .await
+}
+
+/// Delete a snapshot of the volume, releasing its payload. Fails with
+/// [`Error::HasChildren`] while other snapshots have it as their parent, delete these first.
+pub async fn snapshot_delete(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    volume: &Pubkey,
+    snapshot: &Hash,
+) -> Result<(), Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .snapshot_delete(volume, snapshot)
+        .await
+}
+
+/// Fetch the signed manifests of several snapshots of the volume in a single request, such
+/// as the chain of a snapshot being restored. Snapshots that do not exist are left out.
+pub async fn snapshot_fetch_batch(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    volume: &Pubkey,
+    snapshots: &[Hash],
+) -> Result<Vec<ManifestSigned>, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .snapshot_fetch_batch(volume, snapshots)
+        .await
+}
+
+/// Mint a URL that allows fetching the manifest of a snapshot without a bearer token, valid
+/// for `ttl` (such as `30m` or `1h`, defaults to one hour). Only the owner of the volume can
+/// do this.
+pub async fn snapshot_presign(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    volume: &Pubkey,
+    snapshot: &Hash,
+    ttl: Option<&str>,
+) -> Result<Url, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .snapshot_presign(volume, snapshot, ttl)
+        .await
+}
+
+/// Fetch the manifest of a snapshot using a pre-signed URL.
+pub async fn snapshot_fetch_presigned(client: &Client, url: &Url) -> Result<ManifestSigned, Error> {
+    let response = client.get(url.clone()).send().await?;
     if !response.status().is_success() {
-        return Err(Error::Unsuccessful(response.status()));
+        return Err(Error::from_response(response).await);
     }
     let manifest = response.bytes().await?;
-    let manifest = ManifestSigned::parse(&manifest)?;
-    Ok(manifest)
+    Ok(ManifestSigned::parse(&manifest)?)
+}
+
+/// Validate the chain of parents of a snapshot back to the root.
+pub async fn snapshot_chain_validate(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    volume: &Pubkey,
+    snapshot: &Hash,
+) -> Result<ChainReport, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .snapshot_chain_validate(volume, snapshot)
+        .await
+}
+
+/// Fetch the signed manifests of a snapshot and its ancestors back to the root.
+pub async fn snapshot_ancestry(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    volume: &Pubkey,
+    snapshot: &Hash,
+) -> Result<SnapshotAncestry, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .snapshot_ancestry(volume, snapshot)
+        .await
+}
+
+/// Stream of snapshot payload data fetched from the storage service.
+pub type SnapshotDataStream = Pin<Box<dyn Stream<Item = Result<bytes::Bytes, Error>> + Send>>;
+
+/// Upload the (encrypted) payload of a snapshot directly to the storage service, for
+/// deployments without IPFS. The snapshot's manifest must have been uploaded already.
+pub async fn snapshot_data_upload(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    volume: &Pubkey,
+    snapshot: &Hash,
+    data: reqwest::Body,
+) -> Result<(), Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .snapshot_data_upload(volume, snapshot, data)
+        .await
+}
+
+/// Fetch the (encrypted) payload of a snapshot that was stored directly on the storage
+/// service. The payload is streamed.
+pub async fn snapshot_data_fetch(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    volume: &Pubkey,
+    snapshot: &Hash,
+) -> Result<SnapshotDataStream, Error> {
+    StorageClient::from_parts(api, client, Some(token))
+        .snapshot_data_fetch(volume, snapshot)
+        .await
 }
@@ -1,17 +1,50 @@
 use crate::keys::{Privkey, Pubkey, Secret};
+use crate::signing::{signature_length, ManifestSigner};
 use crate::Hash;
 use anyhow::Result;
-use ed25519_dalek_fiat::{ExpandedSecretKey, PublicKey, SecretKey, Signature, Verifier};
+use bincode::Options;
+use ed25519_dalek_fiat::{ExpandedSecretKey, PublicKey, SecretKey};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha512};
 use std::path::PathBuf;
 #[cfg(test)]
 use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 use url::Url;
 use uuid::Uuid;
 
 pub const MANIFEST_SIGNATURE_LENGTH: usize = 64;
 
+/// Magic bytes that versioned manifests start with.
+pub const MANIFEST_MAGIC: [u8; 4] = *b"FSMF";
+
+/// Version of manifests without envelope (plain bincode).
+pub const MANIFEST_VERSION_LEGACY: u8 = 0;
+
+/// Newest manifest version. Services that predate it cannot decode it, so it is only
+/// produced when requested (see [`Manifest::sign_version`]).
+pub const MANIFEST_VERSION: u8 = 1;
+
+/// Version of manifests produced by default, which every service can decode.
+pub const MANIFEST_VERSION_DEFAULT: u8 = MANIFEST_VERSION_LEGACY;
+
+/// Manifest versions this library can decode.
+pub const MANIFEST_VERSIONS: &[u8] = &[MANIFEST_VERSION_LEGACY, MANIFEST_VERSION];
+
+/// Maximum size of encoded manifests, in bytes. Decoding never reads (or allocates) more
+/// than this, whatever length prefixes the data claims.
+pub const MANIFEST_SIZE_MAX: usize = 16 * 1024;
+
+/// Maximum length of the snapshot path of manifests, in bytes.
+pub const MANIFEST_PATH_MAX: usize = 4096;
+
+/// Maximum length of the data URL of manifests, in bytes.
+pub const MANIFEST_DATA_MAX: usize = 2048;
+
+/// Minimum size of snapshots accepted by the service, in bytes. Smaller snapshots are most
+/// likely broken.
+pub const MANIFEST_SIZE_MIN: u64 = 64;
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct Parent {
     /// Hash of parent snapshot.
@@ -72,6 +105,8 @@ pub enum ManifestSignedParseError {
     Bincode(#[from] Box<bincode::ErrorKind>),
     #[error("Missing snapshot signature, got length {0:}, expected {MANIFEST_SIGNATURE_LENGTH}")]
     MissingSignature(usize),
+    #[error("Truncated manifest batch at offset {0:}")]
+    Truncated(usize),
 }
 
 impl ManifestSigned {
@@ -95,6 +130,36 @@ impl ManifestSigned {
         })
     }
 
+    /// Encode signed manifests for transferring them in bulk: the data of each of them,
+    /// prefixed with its length as a big-endian `u32`.
+    pub fn encode_batch(manifests: &[ManifestSigned]) -> Vec<u8> {
+        let mut data = vec![];
+        for manifest in manifests {
+            let manifest = manifest.data();
+            data.extend_from_slice(&(manifest.len() as u32).to_be_bytes());
+            data.extend_from_slice(&manifest);
+        }
+        data
+    }
+
+    /// Parse signed manifests encoded with [`ManifestSigned::encode_batch`].
+    pub fn parse_batch(data: &[u8]) -> Result<Vec<Self>, ManifestSignedParseError> {
+        let mut manifests = vec![];
+        let mut offset = 0;
+        while offset < data.len() {
+            let length = data
+                .get(offset..offset + 4)
+                .ok_or(ManifestSignedParseError::Truncated(offset))?;
+            let length = u32::from_be_bytes(length.try_into().unwrap()) as usize;
+            let manifest = data
+                .get(offset + 4..offset + 4 + length)
+                .ok_or(ManifestSignedParseError::Truncated(offset))?;
+            manifests.push(Self::parse(manifest)?);
+            offset += 4 + length;
+        }
+        Ok(manifests)
+    }
+
     /// Return the raw data for this signature
     pub fn data(&self) -> Vec<u8> {
         self.raw
@@ -109,16 +174,33 @@ impl ManifestSigned {
         Manifest::validate(&self.raw, &self.signature, pubkey)
     }
 
+    /// Validate the signature for a volume with a pinned Dilithium3 key, if it has one.
+    pub fn validate_pinned(&self, pubkey: &Pubkey, pq_pubkey: Option<&[u8]>) -> Result<()> {
+        Manifest::validate_pinned(&self.raw, &self.signature, pubkey, pq_pubkey)
+    }
+
     /// Generate hash of manifest.
     pub fn hash(&self) -> Hash {
         Manifest::hash(&self.raw)
     }
+
+    /// Idempotency key that uploads of this manifest are sent with by default. It is derived
+    /// from the hash, so retries of an upload always send the same key.
+    pub fn idempotency_key(&self) -> String {
+        format!("snapshot-{}", self.hash().to_hex())
+    }
 }
 
 impl Manifest {
     /// Given a manifest and a private key, produce a signed manifest.
     pub fn sign(&self, privkey: &Privkey) -> ManifestSigned {
-        let encoded = self.encode();
+        self.sign_version(privkey, MANIFEST_VERSION_DEFAULT)
+    }
+
+    /// Given a manifest and a private key, produce a signed manifest using the given
+    /// manifest version (see [`Capabilities::manifest_version`](crate::Capabilities)).
+    pub fn sign_version(&self, privkey: &Privkey, version: u8) -> ManifestSigned {
+        let encoded = self.encode_version(version);
         let signature = Self::signature(&encoded, privkey);
         ManifestSigned {
             raw: encoded,
@@ -127,12 +209,111 @@ impl Manifest {
         }
     }
 
+    /// Given a manifest and a signer, produce a signed manifest using the signer's
+    /// algorithm.
+    pub fn sign_with(&self, signer: &dyn ManifestSigner) -> ManifestSigned {
+        let encoded = self.encode();
+        let signature = signer.sign(&encoded);
+        ManifestSigned {
+            raw: encoded,
+            manifest: self.clone(),
+            signature,
+        }
+    }
+
     pub fn encode(&self) -> Vec<u8> {
-        bincode::serialize(self).unwrap()
+        self.encode_version(MANIFEST_VERSION_DEFAULT)
     }
 
+    /// Build the manifest of a snapshot whose payload was uploaded to `data`, measuring `size`
+    /// bytes. The generation and total size follow from the parent, if any.
+    pub fn build(
+        parent: Option<&ManifestSigned>,
+        machine: Uuid,
+        path: PathBuf,
+        data: Url,
+        size: u64,
+        creation: u64,
+    ) -> Manifest {
+        Manifest {
+            creation,
+            machine,
+            path,
+            size,
+            size_total: parent.map(|p| p.manifest.size_total).unwrap_or(0) + size,
+            generation: parent.map(|p| p.manifest.generation + 1).unwrap_or(0),
+            parent: parent.map(|p| Parent::new(p.hash())),
+            data,
+        }
+    }
+
+    /// Encode manifest with the given version. Versioned manifests are wrapped in an
+    /// envelope consisting of magic bytes and the version, legacy ones are plain bincode.
+    pub fn encode_version(&self, version: u8) -> Vec<u8> {
+        let encoded = bincode::serialize(self).unwrap();
+        match version {
+            MANIFEST_VERSION_LEGACY => encoded,
+            version => MANIFEST_MAGIC
+                .iter()
+                .chain(&[version])
+                .chain(encoded.iter())
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Decode manifest, accepts both versioned and legacy manifests. The version is
+    /// determined from the envelope, never by trial. Manifests exceeding the size, path or
+    /// data URL limits are rejected.
     pub fn decode(data: &[u8]) -> Result<Manifest, Box<bincode::ErrorKind>> {
-        bincode::deserialize(data)
+        if data.len() > MANIFEST_SIZE_MAX {
+            return Err(Box::new(bincode::ErrorKind::SizeLimit));
+        }
+        let manifest = match Self::version(data) {
+            Some(MANIFEST_VERSION) => Self::deserialize(&data[MANIFEST_MAGIC.len() + 1..])?,
+            Some(version) => {
+                return Err(Box::new(bincode::ErrorKind::Custom(format!(
+                    "Unsupported manifest version {version}"
+                ))))
+            }
+            None => Self::deserialize(data)?,
+        };
+        manifest.check_limits()?;
+        Ok(manifest)
+    }
+
+    /// Deserialize the bincode encoding of a manifest, with the same options as
+    /// `bincode::deserialize` but bounded to [`MANIFEST_SIZE_MAX`].
+    fn deserialize(data: &[u8]) -> Result<Manifest, Box<bincode::ErrorKind>> {
+        bincode::options()
+            .with_fixint_encoding()
+            .allow_trailing_bytes()
+            .with_limit(MANIFEST_SIZE_MAX as u64)
+            .deserialize(data)
+    }
+
+    fn check_limits(&self) -> Result<(), Box<bincode::ErrorKind>> {
+        if self.path.as_os_str().len() > MANIFEST_PATH_MAX {
+            return Err(Box::new(bincode::ErrorKind::Custom(format!(
+                "Manifest path exceeds {MANIFEST_PATH_MAX} bytes"
+            ))));
+        }
+        if self.data.as_str().len() > MANIFEST_DATA_MAX {
+            return Err(Box::new(bincode::ErrorKind::Custom(format!(
+                "Manifest data URL exceeds {MANIFEST_DATA_MAX} bytes"
+            ))));
+        }
+        Ok(())
+    }
+
+    /// Determine the version of an encoded manifest, `None` if it has no envelope. Envelopes
+    /// never carry the legacy version, so legacy manifests that happen to start with the
+    /// magic bytes are still recognized as such.
+    pub fn version(data: &[u8]) -> Option<u8> {
+        match data.strip_prefix(&MANIFEST_MAGIC) {
+            Some([version, ..]) if *version != MANIFEST_VERSION_LEGACY => Some(*version),
+            _ => None,
+        }
     }
 
     pub fn signature(manifest: &[u8], privkey: &Privkey) -> Vec<u8> {
@@ -159,25 +340,156 @@ impl Manifest {
         encoded
     }
 
+    /// Verify a signature made with any of the supported [`SignatureAlgorithm`](crate::SignatureAlgorithm)s.
     pub fn validate(manifest: &[u8], signature: &[u8], pubkey: &Pubkey) -> Result<()> {
-        let pubkey = PublicKey::from_bytes(pubkey.as_slice())?;
-        let signature = Signature::from_bytes(signature)?;
-        pubkey.verify(manifest, &signature)?;
-        Ok(())
+        crate::signing::verify(manifest, signature, pubkey)
+    }
+
+    pub fn validate_pinned(
+        manifest: &[u8],
+        signature: &[u8],
+        pubkey: &Pubkey,
+        pq_pubkey: Option<&[u8]>,
+    ) -> Result<()> {
+        crate::signing::verify_pinned(manifest, signature, pubkey, pq_pubkey)
     }
 
     pub fn split(data: &[u8]) -> Option<(&[u8], &[u8])> {
-        if data.len() < MANIFEST_SIGNATURE_LENGTH {
+        let length = signature_length(data);
+        if data.len() < length {
             return None;
         }
 
         Some((
-            &data[0..data.len() - MANIFEST_SIGNATURE_LENGTH],
-            &data[data.len() - MANIFEST_SIGNATURE_LENGTH..data.len()],
+            &data[0..data.len() - length],
+            &data[data.len() - length..data.len()],
         ))
     }
 }
 
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum ManifestBuildError {
+    #[error("Missing {0:} of manifest")]
+    Missing(&'static str),
+    #[error("Invalid size in manifest: {0:} (must be at least {MANIFEST_SIZE_MIN} bytes)")]
+    InvalidSize(u64),
+    #[error("Total size of snapshot and its parents overflows")]
+    SizeOverflow,
+    #[error("Manifest path exceeds {MANIFEST_PATH_MAX} bytes")]
+    PathTooLong,
+    #[error("Manifest data URL exceeds {MANIFEST_DATA_MAX} bytes")]
+    DataTooLong,
+    #[error("Parent manifest was not signed with the key of this volume")]
+    InvalidParent,
+}
+
+/// Builder for the manifest of a new snapshot. The generation and total size follow from the
+/// parent and the creation time defaults to now, so that only the properties of the snapshot
+/// itself need to be set. The manifest is validated before it is built.
+#[derive(Clone, Debug, Default)]
+pub struct ManifestBuilder {
+    parent: Option<ManifestSigned>,
+    machine: Uuid,
+    path: Option<PathBuf>,
+    data: Option<Url>,
+    size: Option<u64>,
+    creation: Option<u64>,
+}
+
+impl ManifestBuilder {
+    pub fn new() -> Self {
+        ManifestBuilder::default()
+    }
+
+    /// Snapshot this one is based on, in the same volume. Without one, this is the first
+    /// snapshot of its chain.
+    pub fn parent(mut self, parent: &ManifestSigned) -> Self {
+        self.parent = Some(parent.clone());
+        self
+    }
+
+    /// Machine the snapshot was created on, the nil UUID if not set.
+    pub fn machine(mut self, machine: Uuid) -> Self {
+        self.machine = machine;
+        self
+    }
+
+    pub fn path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// URL of the (encrypted) payload of the snapshot.
+    pub fn data(mut self, data: Url) -> Self {
+        self.data = Some(data);
+        self
+    }
+
+    /// Size of this snapshot, in bytes.
+    pub fn size(mut self, size: u64) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    /// Time the snapshot was created, in seconds since the epoch. Defaults to now.
+    pub fn creation(mut self, creation: u64) -> Self {
+        self.creation = Some(creation);
+        self
+    }
+
+    /// Validate and build the manifest.
+    pub fn build(&self) -> Result<Manifest, ManifestBuildError> {
+        let path = self
+            .path
+            .clone()
+            .ok_or(ManifestBuildError::Missing("path"))?;
+        let data = self
+            .data
+            .clone()
+            .ok_or(ManifestBuildError::Missing("data"))?;
+        let size = self.size.ok_or(ManifestBuildError::Missing("size"))?;
+        if size < MANIFEST_SIZE_MIN {
+            return Err(ManifestBuildError::InvalidSize(size));
+        }
+        if path.as_os_str().len() > MANIFEST_PATH_MAX {
+            return Err(ManifestBuildError::PathTooLong);
+        }
+        if data.as_str().len() > MANIFEST_DATA_MAX {
+            return Err(ManifestBuildError::DataTooLong);
+        }
+        let parent = self.parent.as_ref();
+        parent
+            .map(|parent| parent.manifest.size_total)
+            .unwrap_or(0)
+            .checked_add(size)
+            .ok_or(ManifestBuildError::SizeOverflow)?;
+        let creation = self.creation.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+        });
+        Ok(Manifest::build(
+            parent,
+            self.machine,
+            path,
+            data,
+            size,
+            creation,
+        ))
+    }
+
+    /// Validate, build and sign the manifest. The parent has to be signed with the same key.
+    pub fn sign(&self, privkey: &Privkey) -> Result<ManifestSigned, ManifestBuildError> {
+        if let Some(parent) = &self.parent {
+            parent
+                .validate(&privkey.pubkey())
+                .map_err(|_| ManifestBuildError::InvalidParent)?;
+        }
+        Ok(self.build()?.sign(privkey))
+    }
+}
+
 #[test]
 fn manifest_hash() {
     let manifest = Manifest {
@@ -196,6 +508,24 @@ fn manifest_hash() {
     assert_eq!(Manifest::hash(&manifest).to_hex(), "ab93233657a07df4bde570f9b2ad3d069e14fc80e5b07c3773a937d624b8f7bbf2dade0a3d48a121274e1fc8e787d72fd88171f10a66e84e4207a03d45acf637");
 }
 
+#[test]
+fn manifest_hash_versioned() {
+    let manifest = Manifest {
+        creation: 124123,
+        machine: Uuid::default(),
+        path: PathBuf::from_str("/tmp/path").unwrap(),
+        generation: 0,
+        size: 123412,
+        size_total: 12341241,
+        parent: None,
+        data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+            .try_into()
+            .unwrap(),
+    };
+    let manifest = manifest.encode_version(MANIFEST_VERSION);
+    assert_eq!(Manifest::hash(&manifest).to_hex(), "640d68339aa494e310106b02b42915e402deec33d4aa11ccbff6da90f93aca074c7c95b2fa0d65781a29f8d5f695f04817d1bb9c52590f8663728b098c7bc30c");
+}
+
 #[test]
 fn manifest_encode_decode() {
     let manifest = Manifest {
@@ -215,6 +545,35 @@ fn manifest_encode_decode() {
     assert_eq!(manifest, decoded);
 }
 
+#[test]
+fn manifest_batch_encode_parse() {
+    let privkey = Privkey::generate();
+    let manifests: Vec<ManifestSigned> = (0..3)
+        .map(|generation| {
+            Manifest {
+                creation: 124123,
+                generation,
+                machine: Uuid::new_v4(),
+                path: PathBuf::from_str("/tmp/path").unwrap(),
+                size: 123412,
+                size_total: 12341241,
+                parent: None,
+                data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                    .try_into()
+                    .unwrap(),
+            }
+            .sign(&privkey)
+        })
+        .collect();
+    let encoded = ManifestSigned::encode_batch(&manifests);
+    assert_eq!(ManifestSigned::parse_batch(&encoded).unwrap(), manifests);
+    assert!(ManifestSigned::parse_batch(&[]).unwrap().is_empty());
+    assert!(matches!(
+        ManifestSigned::parse_batch(&encoded[..encoded.len() - 1]),
+        Err(ManifestSignedParseError::Truncated(_))
+    ));
+}
+
 #[test]
 fn manifest_sign_and_verify() {
     let privkey = Privkey::generate();
@@ -258,3 +617,172 @@ fn manifest_sign_split() {
     assert_eq!(encoded, manifest.encode());
     assert_eq!(signature, Manifest::signature(encoded, &privkey));
 }
+
+#[test]
+fn manifest_encode_decode_versions() {
+    let manifest = Manifest {
+        creation: 124123,
+        generation: 0,
+        machine: Uuid::new_v4(),
+        path: PathBuf::from_str("/tmp/path").unwrap(),
+        size: 123412,
+        size_total: 12341241,
+        parent: None,
+        data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+            .try_into()
+            .unwrap(),
+    };
+
+    let legacy = manifest.encode();
+    assert_eq!(legacy, manifest.encode_version(MANIFEST_VERSION_LEGACY));
+    assert_eq!(Manifest::version(&legacy), None);
+    assert_eq!(Manifest::decode(&legacy).unwrap(), manifest);
+
+    let versioned = manifest.encode_version(MANIFEST_VERSION);
+    assert_eq!(Manifest::version(&versioned), Some(MANIFEST_VERSION));
+    assert_eq!(&versioned[MANIFEST_MAGIC.len() + 1..], &legacy[..]);
+    assert_eq!(Manifest::decode(&versioned).unwrap(), manifest);
+
+    let unknown = manifest.encode_version(MANIFEST_VERSION + 1);
+    assert!(Manifest::decode(&unknown).is_err());
+
+    // versioned manifests with a broken body do not fall back to the legacy encoding
+    let truncated = &versioned[..versioned.len() - 1];
+    assert!(Manifest::decode(truncated).is_err());
+
+    // legacy manifests that happen to start with the magic bytes are not mistaken for
+    // versioned ones
+    let creation = u32::from_le_bytes(MANIFEST_MAGIC) as u64;
+    let lookalike = Manifest {
+        creation,
+        ..manifest.clone()
+    };
+    let encoded = lookalike.encode();
+    assert!(encoded.starts_with(&MANIFEST_MAGIC));
+    assert_eq!(Manifest::version(&encoded), None);
+    assert_eq!(Manifest::decode(&encoded).unwrap(), lookalike);
+}
+
+#[test]
+fn manifest_decode_limits() {
+    let manifest = Manifest {
+        creation: 124123,
+        generation: 0,
+        machine: Uuid::new_v4(),
+        path: PathBuf::from_str("/tmp/path").unwrap(),
+        size: 123412,
+        size_total: 12341241,
+        parent: None,
+        data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+            .try_into()
+            .unwrap(),
+    };
+
+    // hostile length prefix for the path must not be allocated
+    let mut legacy = manifest.encode_version(MANIFEST_VERSION_LEGACY);
+    let offset = legacy
+        .windows(9)
+        .position(|window| window == b"/tmp/path")
+        .unwrap();
+    legacy[offset - 8..offset].copy_from_slice(&(u64::MAX / 2).to_le_bytes());
+    assert!(matches!(
+        *Manifest::decode(&legacy).unwrap_err(),
+        bincode::ErrorKind::SizeLimit
+    ));
+    let versioned: Vec<u8> = MANIFEST_MAGIC
+        .iter()
+        .chain(&[MANIFEST_VERSION])
+        .chain(legacy.iter())
+        .cloned()
+        .collect();
+    assert!(Manifest::decode(&versioned).is_err());
+
+    // oversized manifests and fields are rejected
+    assert!(Manifest::decode(&vec![0; MANIFEST_SIZE_MAX + 1]).is_err());
+    let long_path = Manifest {
+        path: PathBuf::from("/".repeat(MANIFEST_PATH_MAX + 1)),
+        ..manifest.clone()
+    };
+    assert!(Manifest::decode(&long_path.encode()).is_err());
+    let long_data = Manifest {
+        data: format!("ipfs://{}", "a".repeat(MANIFEST_DATA_MAX))
+            .parse()
+            .unwrap(),
+        ..manifest.clone()
+    };
+    assert!(Manifest::decode(&long_data.encode()).is_err());
+
+    // truncated and corrupted manifests fail to decode without panicking
+    let encoded = manifest.encode();
+    for length in 0..encoded.len() {
+        let _ = Manifest::decode(&encoded[..length]);
+    }
+    for index in 0..encoded.len() {
+        for byte in [0x00, 0x7f, 0xff] {
+            let mut corrupted = encoded.clone();
+            corrupted[index] = byte;
+            let _ = Manifest::decode(&corrupted);
+        }
+    }
+}
+
+#[test]
+fn manifest_build() {
+    let privkey = Privkey::generate();
+    let data: Url = "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+        .try_into()
+        .unwrap();
+    let path = PathBuf::from("/tmp/path");
+    let root = Manifest::build(None, Uuid::default(), path.clone(), data.clone(), 100, 1);
+    assert_eq!(root.generation, 0);
+    assert_eq!(root.size_total, 100);
+    assert_eq!(root.parent, None);
+
+    let root = root.sign(&privkey);
+    let child = Manifest::build(Some(&root), Uuid::default(), path, data, 50, 2);
+    assert_eq!(child.generation, 1);
+    assert_eq!(child.size, 50);
+    assert_eq!(child.size_total, 150);
+    assert_eq!(child.parent, Some(Parent::new(root.hash())));
+}
+
+#[test]
+fn manifest_builder() {
+    let privkey = Privkey::generate();
+    let data: Url = "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+        .try_into()
+        .unwrap();
+    let builder = ManifestBuilder::new().path("/tmp/path").data(data.clone());
+    assert_eq!(
+        builder.build().unwrap_err(),
+        ManifestBuildError::Missing("size")
+    );
+    assert_eq!(
+        builder.clone().size(1).build().unwrap_err(),
+        ManifestBuildError::InvalidSize(1)
+    );
+
+    let root = builder.clone().size(100).sign(&privkey).unwrap();
+    assert_eq!(root.manifest.generation, 0);
+    assert_eq!(root.manifest.size_total, 100);
+    assert!(root.manifest.creation > 0);
+    root.validate(&privkey.pubkey()).unwrap();
+
+    let child = builder
+        .clone()
+        .parent(&root)
+        .size(MANIFEST_SIZE_MIN)
+        .creation(2);
+    let manifest = child.build().unwrap();
+    assert_eq!(manifest.generation, 1);
+    assert_eq!(manifest.size_total, 100 + MANIFEST_SIZE_MIN);
+    assert_eq!(manifest.creation, 2);
+    assert_eq!(manifest.parent, Some(Parent::new(root.hash())));
+
+    // the parent has to be signed with the same key
+    let other = Privkey::generate();
+    assert_eq!(
+        child.sign(&other).unwrap_err(),
+        ManifestBuildError::InvalidParent
+    );
+}
@@ -0,0 +1,101 @@
+use reqwest::ClientBuilder;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Address families the client connects over. With [`AddressFamily::Any`], connections to
+/// hosts with both IPv4 and IPv6 addresses are raced (happy eyeballs): the preferred family
+/// is tried first and the other one shortly after, so broken IPv6 connectivity costs a few
+/// hundred milliseconds rather than a full connect timeout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressFamily {
+    Any,
+    Ipv4,
+    Ipv6,
+}
+
+impl AddressFamily {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AddressFamily::Any => "any",
+            AddressFamily::Ipv4 => "ipv4",
+            AddressFamily::Ipv6 => "ipv6",
+        }
+    }
+
+    /// Local address to bind connections to, which restricts them to the family.
+    fn local_address(&self) -> Option<IpAddr> {
+        match self {
+            AddressFamily::Any => None,
+            AddressFamily::Ipv4 => Some(Ipv4Addr::UNSPECIFIED.into()),
+            AddressFamily::Ipv6 => Some(Ipv6Addr::UNSPECIFIED.into()),
+        }
+    }
+}
+
+impl Default for AddressFamily {
+    fn default() -> Self {
+        AddressFamily::Any
+    }
+}
+
+impl FromStr for AddressFamily {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "any" => Ok(AddressFamily::Any),
+            "ipv4" | "4" => Ok(AddressFamily::Ipv4),
+            "ipv6" | "6" => Ok(AddressFamily::Ipv6),
+            other => Err(format!("Unknown address family {other:?}")),
+        }
+    }
+}
+
+impl fmt::Display for AddressFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Make the client connect only over the given address family, for example to avoid a
+/// broken IPv6 setup altogether.
+pub fn with_address_family(builder: ClientBuilder, family: AddressFamily) -> ClientBuilder {
+    builder.local_address(family.local_address())
+}
+
+/// Timeouts for requests to the storage service. Without them, requests to a stalled server
+/// hang forever.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Time allowed for a request, from connecting until the response was read completely.
+    pub request: Option<Duration>,
+    /// Time allowed for connecting to the server.
+    pub connect: Option<Duration>,
+}
+
+/// Make the client give up on requests that exceed the timeouts, which the free functions
+/// of this crate then report as [`Error::Timeout`](crate::Error::Timeout). The request
+/// timeout also ends the stream of [`account_events`](crate::account_events).
+pub fn with_timeouts(mut builder: ClientBuilder, timeouts: &Timeouts) -> ClientBuilder {
+    if let Some(timeout) = timeouts.request {
+        builder = builder.timeout(timeout);
+    }
+    if let Some(timeout) = timeouts.connect {
+        builder = builder.connect_timeout(timeout);
+    }
+    builder
+}
+
+#[test]
+fn test_address_family() {
+    assert_eq!("ipv4".parse(), Ok(AddressFamily::Ipv4));
+    assert_eq!("6".parse(), Ok(AddressFamily::Ipv6));
+    assert!("ipx".parse::<AddressFamily>().is_err());
+    for family in [AddressFamily::Any, AddressFamily::Ipv4, AddressFamily::Ipv6] {
+        assert_eq!(family.to_string().parse(), Ok(family));
+    }
+    assert_eq!(AddressFamily::Any.local_address(), None);
+    assert!(AddressFamily::Ipv6.local_address().unwrap().is_ipv6());
+}
@@ -0,0 +1,57 @@
+use crate::{snapshot_fetch, Error, Hash, ManifestSigned, Pubkey};
+use futures::stream::{self, Stream, StreamExt};
+use reqwest::Client;
+use std::pin::Pin;
+use url::Url;
+
+/// Default number of manifests fetched concurrently.
+pub const PREFETCH_CONCURRENCY: usize = 8;
+
+/// Stream of prefetched manifests.
+pub type ManifestStream = Pin<Box<dyn Stream<Item = Result<ManifestSigned, Error>> + Send>>;
+
+/// Fetches the manifests of many snapshots of a volume with bounded concurrency, for
+/// example to display the results of `snapshot_list`. Manifests are verified like
+/// [`snapshot_fetch`] does, and yielded in the order they were requested.
+#[derive(Clone, Debug)]
+pub struct ManifestPrefetcher {
+    api: Url,
+    client: Client,
+    token: String,
+    volume: Pubkey,
+    concurrency: usize,
+}
+
+impl ManifestPrefetcher {
+    pub fn new(api: &Url, client: &Client, token: &str, volume: &Pubkey) -> Self {
+        ManifestPrefetcher {
+            api: api.clone(),
+            client: client.clone(),
+            token: token.to_string(),
+            volume: *volume,
+            concurrency: PREFETCH_CONCURRENCY,
+        }
+    }
+
+    /// Set how many manifests are fetched concurrently (at least one).
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Fetch the manifests of the given snapshots.
+    pub fn fetch(&self, hashes: Vec<Hash>) -> ManifestStream {
+        let prefetcher = self.clone();
+        let stream = stream::iter(hashes)
+            .map(move |hash| {
+                let prefetcher = prefetcher.clone();
+                async move { prefetcher.fetch_one(&hash).await }
+            })
+            .buffered(self.concurrency);
+        Box::pin(stream)
+    }
+
+    async fn fetch_one(&self, hash: &Hash) -> Result<ManifestSigned, Error> {
+        snapshot_fetch(&self.api, &self.client, &self.token, &self.volume, hash).await
+    }
+}
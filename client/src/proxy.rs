@@ -0,0 +1,155 @@
+use reqwest::ClientBuilder;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use thiserror::Error;
+use url::{Host, Url};
+
+/// SOCKS port of a local Tor daemon, with host names resolved by Tor.
+pub const TOR_PROXY: &str = "socks5h://127.0.0.1:9050";
+
+/// Environment variables a proxy is read from, in order of precedence. `HTTP_PROXY` and
+/// `HTTPS_PROXY` are honored by reqwest itself.
+const PROXY_VARIABLES: &[&str] = &["STORAGE_PROXY", "ALL_PROXY", "all_proxy"];
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ProxyError {
+    #[error("Invalid proxy URL {0:?}")]
+    InvalidUrl(String),
+    #[error("Unsupported proxy scheme {0:?}, expected http, https, socks5 or socks5h")]
+    UnsupportedScheme(String),
+    #[error("Onion service {0:} can only be reached through a proxy that resolves host names, such as Tor ({TOR_PROXY})")]
+    OnionUnreachable(String),
+    #[error("{0:} cannot be reached through a proxy, use a local IPFS node")]
+    Unproxied(String),
+}
+
+/// Proxy to send requests through: an HTTP(S) proxy, or a SOCKS5 proxy such as Tor. With
+/// `socks5h`, host names are resolved by the proxy rather than locally, which is needed to
+/// reach `.onion` services and avoids leaking lookups to the local resolver.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProxyConfig(Url);
+
+impl ProxyConfig {
+    /// Proxy of a local Tor daemon.
+    pub fn tor() -> Self {
+        TOR_PROXY.parse().unwrap()
+    }
+
+    /// Proxy configured in the environment (`STORAGE_PROXY` or `ALL_PROXY`), if any.
+    pub fn from_env() -> Result<Option<Self>, ProxyError> {
+        PROXY_VARIABLES
+            .iter()
+            .filter_map(|variable| std::env::var(variable).ok())
+            .find(|value| !value.is_empty())
+            .map(|value| value.parse())
+            .transpose()
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.0
+    }
+
+    /// Determines if host names are resolved by the proxy rather than locally.
+    pub fn remote_dns(&self) -> bool {
+        self.0.scheme() != "socks5"
+    }
+
+    /// Check that the endpoint can be reached through this proxy, or without one.
+    pub fn check(proxy: Option<&ProxyConfig>, endpoint: &Url) -> Result<(), ProxyError> {
+        let onion = endpoint
+            .host_str()
+            .map(|host| host.ends_with(".onion"))
+            .unwrap_or(false);
+        match proxy {
+            _ if !onion => Ok(()),
+            Some(proxy) if proxy.remote_dns() => Ok(()),
+            _ => Err(ProxyError::OnionUnreachable(endpoint.to_string())),
+        }
+    }
+
+    /// Check that an endpoint accessed without going through the proxy, such as the IPFS
+    /// API, is local, so that no traffic bypasses the proxy.
+    pub fn check_local(proxy: Option<&ProxyConfig>, endpoint: &Url) -> Result<(), ProxyError> {
+        let local = match endpoint.host() {
+            Some(Host::Domain(domain)) => domain == "localhost",
+            Some(Host::Ipv4(address)) => IpAddr::from(address).is_loopback(),
+            Some(Host::Ipv6(address)) => IpAddr::from(address).is_loopback(),
+            None => false,
+        };
+        match proxy {
+            Some(_) if !local => Err(ProxyError::Unproxied(endpoint.to_string())),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl FromStr for ProxyConfig {
+    type Err = ProxyError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let url = Url::parse(input).map_err(|_| ProxyError::InvalidUrl(input.to_string()))?;
+        match url.scheme() {
+            "http" | "https" | "socks5" | "socks5h" => {}
+            other => return Err(ProxyError::UnsupportedScheme(other.to_string())),
+        }
+        if url.host().is_none() {
+            return Err(ProxyError::InvalidUrl(input.to_string()));
+        }
+        Ok(ProxyConfig(url))
+    }
+}
+
+impl fmt::Display for ProxyConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Make the client send all requests, including plain HTTP ones, through the proxy.
+pub fn with_proxy(
+    builder: ClientBuilder,
+    proxy: &ProxyConfig,
+) -> Result<ClientBuilder, ProxyError> {
+    let proxy = reqwest::Proxy::all(proxy.0.as_str())
+        .map_err(|_| ProxyError::InvalidUrl(proxy.to_string()))?;
+    Ok(builder.proxy(proxy))
+}
+
+#[test]
+fn test_proxy_config() {
+    let tor = ProxyConfig::tor();
+    assert_eq!(tor.url().scheme(), "socks5h");
+    assert!(tor.remote_dns());
+    let socks: ProxyConfig = "socks5://proxy.example.com:1080".parse().unwrap();
+    assert!(!socks.remote_dns());
+    let http: ProxyConfig = "http://proxy.example.com:3128".parse().unwrap();
+    assert!(http.remote_dns());
+    assert_eq!(
+        "ftp://proxy.example.com".parse::<ProxyConfig>(),
+        Err(ProxyError::UnsupportedScheme("ftp".into()))
+    );
+    assert!("proxy".parse::<ProxyConfig>().is_err());
+
+    // onion services need the proxy to resolve them
+    let onion = Url::parse("http://storagexyz.onion/").unwrap();
+    let clearnet = Url::parse("https://storage.fractalnetworks.co/").unwrap();
+    assert!(ProxyConfig::check(Some(&tor), &onion).is_ok());
+    assert!(ProxyConfig::check(Some(&socks), &onion).is_err());
+    assert!(ProxyConfig::check(None, &onion).is_err());
+    assert!(ProxyConfig::check(None, &clearnet).is_ok());
+
+    // endpoints bypassing the proxy must be local
+    let local = Url::parse("http://localhost:5001").unwrap();
+    assert!(ProxyConfig::check_local(Some(&tor), &local).is_ok());
+    assert!(
+        ProxyConfig::check_local(Some(&tor), &Url::parse("http://[::1]:5001").unwrap()).is_ok()
+    );
+    assert!(ProxyConfig::check_local(Some(&tor), &clearnet).is_err());
+    assert!(ProxyConfig::check_local(None, &clearnet).is_ok());
+
+    assert!(with_proxy(ClientBuilder::new(), &tor)
+        .unwrap()
+        .build()
+        .is_ok());
+}
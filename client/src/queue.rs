@@ -0,0 +1,143 @@
+use crate::keys::Pubkey;
+use crate::manifest::ManifestSigned;
+use log::warn;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// Signed manifest waiting in an [`UploadQueue`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QueuedUpload {
+    pub volume: Pubkey,
+    pub manifest: ManifestSigned,
+}
+
+/// Durable queue of signed manifests whose upload failed because the service could not be
+/// reached, kept as files in a directory so that they survive restarts of the application.
+/// They are uploaded again with [`StorageClient::flush_pending`](crate::StorageClient).
+#[derive(Clone, Debug)]
+pub struct UploadQueue {
+    dir: PathBuf,
+}
+
+impl UploadQueue {
+    /// Queue manifests in `dir`, which is created when the first manifest is queued.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        UploadQueue { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path(&self, volume: &Pubkey, manifest: &ManifestSigned) -> PathBuf {
+        self.dir.join(format!(
+            "{}-{:020}-{}",
+            volume.to_hex(),
+            manifest.manifest.generation,
+            manifest.hash().to_hex()
+        ))
+    }
+
+    /// Add the manifest of a snapshot of the volume to the queue.
+    pub async fn push(&self, volume: &Pubkey, manifest: &ManifestSigned) -> io::Result<()> {
+        fs::create_dir_all(&self.dir).await?;
+        let path = self.path(volume, manifest);
+        // written to a temporary file first, so that partial entries are never replayed.
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, manifest.data()).await?;
+        fs::rename(&temp, &path).await
+    }
+
+    /// Remove the manifest of a snapshot of the volume from the queue.
+    pub async fn remove(&self, volume: &Pubkey, manifest: &ManifestSigned) -> io::Result<()> {
+        match fs::remove_file(self.path(volume, manifest)).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Manifests in the queue, by ascending generation so that parents come before their
+    /// children. Entries that cannot be parsed are skipped.
+    pub async fn pending(&self) -> io::Result<Vec<QueuedUpload>> {
+        let mut dir = match fs::read_dir(&self.dir).await {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+        let mut pending = vec![];
+        while let Some(entry) = dir.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_some() {
+                continue;
+            }
+            let name = entry.file_name();
+            let volume = name
+                .to_str()
+                .and_then(|name| name.split('-').next())
+                .and_then(|volume| Pubkey::from_hex(volume).ok());
+            let data = fs::read(&path).await?;
+            match (volume, ManifestSigned::parse(&data)) {
+                (Some(volume), Ok(manifest)) => pending.push(QueuedUpload { volume, manifest }),
+                _ => warn!("Skipping invalid queued manifest {}", path.display()),
+            }
+        }
+        pending.sort_by_key(|upload| (upload.manifest.manifest.generation, upload.volume));
+        Ok(pending)
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_upload_queue() {
+    use crate::{Manifest, Privkey};
+    use std::str::FromStr;
+    use uuid::Uuid;
+
+    let dir = std::env::temp_dir().join(format!("upload-queue-{}", Uuid::new_v4()));
+    let queue = UploadQueue::new(&dir);
+    assert_eq!(queue.pending().await.unwrap(), vec![]);
+
+    let privkey = Privkey::generate();
+    let volume = privkey.pubkey();
+    let manifest = |generation| {
+        Manifest {
+            creation: 0,
+            machine: Uuid::nil(),
+            path: PathBuf::from("/tmp/path"),
+            size: 100,
+            size_total: 100 * (generation + 1),
+            generation,
+            parent: None,
+            data: url::Url::from_str("ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth")
+                .unwrap(),
+        }
+        .sign(&privkey)
+    };
+
+    // queued out of order, replayed by generation
+    let (first, second) = (manifest(9), manifest(10));
+    queue.push(&volume, &second).await.unwrap();
+    queue.push(&volume, &first).await.unwrap();
+    let pending: Vec<ManifestSigned> = queue
+        .pending()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|upload| upload.manifest)
+        .collect();
+    assert_eq!(pending, vec![first.clone(), second.clone()]);
+
+    queue.remove(&volume, &first).await.unwrap();
+    queue.remove(&volume, &first).await.unwrap();
+    let pending = queue.pending().await.unwrap();
+    assert_eq!(
+        pending,
+        vec![QueuedUpload {
+            volume,
+            manifest: second
+        }]
+    );
+
+    fs::remove_dir_all(&dir).await.unwrap();
+}
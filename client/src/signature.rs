@@ -0,0 +1,215 @@
+use crate::keys::{Privkey, Pubkey};
+use crate::manifest::Manifest;
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+
+/// Header carrying the signature of a request, made with the private key of the volume it
+/// refers to.
+pub const SIGNATURE_HEADER: &str = "X-Storage-Signature";
+
+/// Header carrying the hex-encoded SHA-256 digest of the body of a signed request. The
+/// signature covers the digest, a missing header stands for an empty body.
+pub const DIGEST_HEADER: &str = "X-Storage-Content-SHA256";
+
+/// Header carrying the proof of possession of the volume key that deleting a volume requires.
+pub const PROOF_HEADER: &str = "X-Storage-Proof";
+
+/// Length (in bytes) of request nonces.
+const NONCE_LENGTH: usize = 16;
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum RequestSignatureError {
+    #[error("Malformed request signature")]
+    Malformed,
+    #[error("Invalid request signature")]
+    Invalid,
+}
+
+/// Signature binding a request to possession of the volume's private key. It covers the
+/// method, path, the digest of the body, a timestamp and a random nonce, so that servers can
+/// reject altered, stale and replayed requests. Sent in the [`SIGNATURE_HEADER`] as
+/// `<timestamp>.<nonce>.<signature>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestSignature {
+    /// Time the request was signed, in seconds since the epoch.
+    pub timestamp: u64,
+    /// Random nonce, hex-encoded.
+    pub nonce: String,
+    pub signature: Vec<u8>,
+}
+
+impl RequestSignature {
+    fn message(method: &str, path: &str, digest: &str, timestamp: u64, nonce: &str) -> Vec<u8> {
+        format!("{method} {path} {digest} {timestamp} {nonce}").into_bytes()
+    }
+
+    /// Digest of a request body, as sent in the [`DIGEST_HEADER`].
+    pub fn digest(body: &[u8]) -> String {
+        hex_encode(&Sha256::digest(body))
+    }
+
+    /// Sign a request to the given path with the given body.
+    pub fn sign(privkey: &Privkey, method: &str, path: &str, body: &[u8], timestamp: u64) -> Self {
+        let mut nonce = [0; NONCE_LENGTH];
+        OsRng.fill_bytes(&mut nonce);
+        let nonce = hex_encode(&nonce);
+        let message = Self::message(method, path, &Self::digest(body), timestamp, &nonce);
+        RequestSignature {
+            timestamp,
+            signature: Manifest::signature(&message, privkey),
+            nonce,
+        }
+    }
+
+    /// Verify that the request was signed by the given key, for a body with the given digest.
+    pub fn verify(
+        &self,
+        pubkey: &Pubkey,
+        method: &str,
+        path: &str,
+        digest: &str,
+    ) -> Result<(), RequestSignatureError> {
+        let message = Self::message(method, path, digest, self.timestamp, &self.nonce);
+        Manifest::validate(&message, &self.signature, pubkey)
+            .map_err(|_| RequestSignatureError::Invalid)
+    }
+}
+
+impl fmt::Display for RequestSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let signature = hex_encode(&self.signature);
+        write!(f, "{}.{}.{}", self.timestamp, self.nonce, signature)
+    }
+}
+
+impl FromStr for RequestSignature {
+    type Err = RequestSignatureError;
+
+    fn from_str(header: &str) -> Result<Self, Self::Err> {
+        let mut fields = header.trim().split('.');
+        let (timestamp, nonce, signature) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(timestamp), Some(nonce), Some(signature)) => (timestamp, nonce, signature),
+            _ => return Err(RequestSignatureError::Malformed),
+        };
+        if fields.next().is_some() || hex_decode(nonce)?.len() != NONCE_LENGTH {
+            return Err(RequestSignatureError::Malformed);
+        }
+        Ok(RequestSignature {
+            timestamp: timestamp
+                .parse()
+                .map_err(|_| RequestSignatureError::Malformed)?,
+            nonce: nonce.to_string(),
+            signature: hex_decode(signature)?,
+        })
+    }
+}
+
+/// Random challenge issued by the server, which has to be signed with the volume key to
+/// delete the volume.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct VolumeChallenge {
+    pub challenge: String,
+    /// Time the challenge expires, in seconds since the epoch.
+    pub expires: u64,
+}
+
+impl VolumeChallenge {
+    fn message(volume: &Pubkey, challenge: &str) -> Vec<u8> {
+        format!("delete-volume {} {challenge}", volume.to_hex()).into_bytes()
+    }
+
+    /// Sign the challenge, returning the value of the [`PROOF_HEADER`].
+    pub fn proof(&self, privkey: &Privkey) -> String {
+        let message = Self::message(&privkey.pubkey(), &self.challenge);
+        let signature = Manifest::signature(&message, privkey);
+        format!("{}.{}", self.challenge, hex_encode(&signature))
+    }
+
+    /// Verify a proof made for the volume, returning the challenge that was signed.
+    pub fn proof_verify(volume: &Pubkey, proof: &str) -> Result<String, RequestSignatureError> {
+        let (challenge, signature) = proof
+            .trim()
+            .split_once('.')
+            .ok_or(RequestSignatureError::Malformed)?;
+        let signature = hex_decode(signature)?;
+        Manifest::validate(&Self::message(volume, challenge), &signature, volume)
+            .map_err(|_| RequestSignatureError::Invalid)?;
+        Ok(challenge.to_string())
+    }
+}
+
+fn hex_encode(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn hex_decode(data: &str) -> Result<Vec<u8>, RequestSignatureError> {
+    if data.len() % 2 != 0 || !data.is_ascii() {
+        return Err(RequestSignatureError::Malformed);
+    }
+    (0..data.len())
+        .step_by(2)
+        .map(|index| {
+            u8::from_str_radix(&data[index..index + 2], 16)
+                .map_err(|_| RequestSignatureError::Malformed)
+        })
+        .collect()
+}
+
+#[test]
+fn test_request_signature() {
+    let privkey = Privkey::generate();
+    let path = format!("/api/v1/volume/{}", privkey.pubkey());
+    let signature = RequestSignature::sign(&privkey, "DELETE", &path, b"{}", 1000);
+    let parsed = RequestSignature::from_str(&signature.to_string()).unwrap();
+    assert_eq!(parsed, signature);
+    let digest = RequestSignature::digest(b"{}");
+    assert!(parsed
+        .verify(&privkey.pubkey(), "DELETE", &path, &digest)
+        .is_ok());
+
+    // signature only covers the given key, method, path and body
+    let other = Privkey::generate().pubkey();
+    assert!(parsed.verify(&other, "DELETE", &path, &digest).is_err());
+    assert!(parsed
+        .verify(&privkey.pubkey(), "PATCH", &path, &digest)
+        .is_err());
+    assert!(parsed
+        .verify(&privkey.pubkey(), "DELETE", "/", &digest)
+        .is_err());
+    let altered = RequestSignature::digest(b"{\"locked\":false}");
+    assert!(parsed
+        .verify(&privkey.pubkey(), "DELETE", &path, &altered)
+        .is_err());
+    let altered = RequestSignature {
+        timestamp: 1001,
+        ..parsed
+    };
+    assert!(altered
+        .verify(&privkey.pubkey(), "DELETE", &path, &digest)
+        .is_err());
+
+    assert!(RequestSignature::from_str("1000.abcd").is_err());
+    assert!(RequestSignature::from_str("1000.zz.abcd").is_err());
+}
+
+#[test]
+fn test_volume_challenge() {
+    let privkey = Privkey::generate();
+    let challenge = VolumeChallenge {
+        challenge: "abcd".into(),
+        expires: 1000,
+    };
+    let proof = challenge.proof(&privkey);
+    assert_eq!(
+        VolumeChallenge::proof_verify(&privkey.pubkey(), &proof).unwrap(),
+        "abcd"
+    );
+    let other = Privkey::generate().pubkey();
+    assert!(VolumeChallenge::proof_verify(&other, &proof).is_err());
+    let altered = proof.replacen("abcd", "abce", 1);
+    assert!(VolumeChallenge::proof_verify(&privkey.pubkey(), &altered).is_err());
+    assert!(VolumeChallenge::proof_verify(&privkey.pubkey(), "abcd").is_err());
+}
@@ -0,0 +1,351 @@
+use crate::keys::{Privkey, Pubkey};
+use crate::manifest::{Manifest, MANIFEST_SIGNATURE_LENGTH};
+use anyhow::{anyhow, Result};
+use ed25519_dalek_fiat::{PublicKey, Signature, Verifier};
+#[cfg(feature = "pq")]
+use pqcrypto_dilithium::dilithium3;
+#[cfg(feature = "pq")]
+use pqcrypto_traits::sign::{DetachedSignature as _, PublicKey as _, SecretKey as _};
+use std::fmt;
+use std::str::FromStr;
+
+/// Magic bytes that signatures with an algorithm identifier end with.
+pub const SIGNATURE_MAGIC: [u8; 4] = *b"FSSG";
+
+/// Length of the trailer of signatures with an algorithm identifier: the length of the
+/// signature as a big-endian `u32`, the algorithm and the magic bytes.
+const SIGNATURE_TRAILER_LENGTH: usize = 4 + 1 + SIGNATURE_MAGIC.len();
+
+/// Algorithm a manifest is signed with. Plain Ed25519 signatures are 64 bytes without any
+/// envelope, as they always were. Signatures made with other algorithms are followed by a
+/// trailer identifying the algorithm, so that verifiers can dispatch on it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignatureAlgorithm {
+    /// Ed25519 signature made with the volume key.
+    Ed25519,
+    /// Ed25519 signature made with the volume key, followed by a Dilithium3 public key and a
+    /// signature made with it. Both signatures must be valid, and the Dilithium3 key must be
+    /// the one pinned for the volume, so manifests stay verifiable if either algorithm is
+    /// broken. Requires the `pq` feature.
+    Ed25519Dilithium3,
+}
+
+impl SignatureAlgorithm {
+    /// Identifier of the algorithm in signature trailers.
+    pub fn id(&self) -> u8 {
+        match self {
+            SignatureAlgorithm::Ed25519 => 0,
+            SignatureAlgorithm::Ed25519Dilithium3 => 1,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(SignatureAlgorithm::Ed25519),
+            1 => Some(SignatureAlgorithm::Ed25519Dilithium3),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SignatureAlgorithm::Ed25519 => "ed25519",
+            SignatureAlgorithm::Ed25519Dilithium3 => "ed25519-dilithium3",
+        }
+    }
+
+    /// Algorithms that this build of the library can verify.
+    pub fn supported() -> Vec<SignatureAlgorithm> {
+        let mut algorithms = vec![SignatureAlgorithm::Ed25519];
+        if cfg!(feature = "pq") {
+            algorithms.push(SignatureAlgorithm::Ed25519Dilithium3);
+        }
+        algorithms
+    }
+
+    /// Determine the algorithm of a signature.
+    pub fn of(signature: &[u8]) -> Option<Self> {
+        match trailer(signature) {
+            Some((algorithm, _)) => Some(algorithm),
+            None if signature.len() == MANIFEST_SIGNATURE_LENGTH => {
+                Some(SignatureAlgorithm::Ed25519)
+            }
+            None => None,
+        }
+    }
+}
+
+impl FromStr for SignatureAlgorithm {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "ed25519" => Ok(SignatureAlgorithm::Ed25519),
+            "ed25519-dilithium3" => Ok(SignatureAlgorithm::Ed25519Dilithium3),
+            other => Err(format!("Unknown signature algorithm {other:?}")),
+        }
+    }
+}
+
+impl fmt::Display for SignatureAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Algorithm and length (without trailer) of a signature at the end of the data, if it has
+/// a trailer. Plain Ed25519 signatures could end in something resembling a trailer by
+/// chance, but then the length would also have to fit, which is vanishingly unlikely.
+fn trailer(data: &[u8]) -> Option<(SignatureAlgorithm, usize)> {
+    let rest = data.strip_suffix(&SIGNATURE_MAGIC)?;
+    let (rest, algorithm) = rest.split_last()?;
+    let algorithm = SignatureAlgorithm::from_id(*algorithm)?;
+    let length = rest.get(rest.len().checked_sub(4)?..)?;
+    let length = u32::from_be_bytes(length.try_into().unwrap()) as usize;
+    if length + SIGNATURE_TRAILER_LENGTH > data.len() {
+        return None;
+    }
+    Some((algorithm, length))
+}
+
+/// Length of the signature at the end of signed data, including its trailer.
+pub(crate) fn signature_length(data: &[u8]) -> usize {
+    match trailer(data) {
+        Some((_, length)) => length + SIGNATURE_TRAILER_LENGTH,
+        None => MANIFEST_SIGNATURE_LENGTH,
+    }
+}
+
+/// Append the trailer identifying the algorithm to a signature.
+#[cfg(any(test, feature = "pq"))]
+fn envelope(algorithm: SignatureAlgorithm, mut signature: Vec<u8>) -> Vec<u8> {
+    let length = signature.len() as u32;
+    signature.extend_from_slice(&length.to_be_bytes());
+    signature.push(algorithm.id());
+    signature.extend_from_slice(&SIGNATURE_MAGIC);
+    signature
+}
+
+/// Verify a signature made with any of the supported algorithms, for a volume without a
+/// pinned Dilithium3 key. Hybrid signatures are rejected, see [`verify_pinned`].
+pub fn verify(data: &[u8], signature: &[u8], pubkey: &Pubkey) -> Result<()> {
+    verify_pinned(data, signature, pubkey, None)
+}
+
+/// Verify a signature made with any of the supported algorithms. `pq_pubkey` is the
+/// Dilithium3 public key pinned for the volume, if any. Hybrid signatures are only accepted
+/// if they carry that key, and once a key is pinned, plain Ed25519 signatures are rejected so
+/// that they cannot be used to get around it.
+pub fn verify_pinned(
+    data: &[u8],
+    signature: &[u8],
+    pubkey: &Pubkey,
+    pq_pubkey: Option<&[u8]>,
+) -> Result<()> {
+    let (algorithm, signature) = match trailer(signature) {
+        Some((algorithm, length)) if length + SIGNATURE_TRAILER_LENGTH == signature.len() => {
+            (algorithm, &signature[..length])
+        }
+        Some(_) => return Err(anyhow!("Malformed signature trailer")),
+        None => (SignatureAlgorithm::Ed25519, signature),
+    };
+    match (algorithm, pq_pubkey) {
+        (SignatureAlgorithm::Ed25519, None) => verify_ed25519(data, signature, pubkey),
+        (SignatureAlgorithm::Ed25519, Some(_)) => Err(anyhow!(
+            "Volume has a pinned Dilithium3 key and requires {} signatures",
+            SignatureAlgorithm::Ed25519Dilithium3
+        )),
+        (SignatureAlgorithm::Ed25519Dilithium3, None) => Err(anyhow!(
+            "Volume has no pinned Dilithium3 key to verify {} signatures with",
+            SignatureAlgorithm::Ed25519Dilithium3
+        )),
+        (SignatureAlgorithm::Ed25519Dilithium3, Some(pinned)) => {
+            verify_hybrid(data, signature, pubkey, pinned)
+        }
+    }
+}
+
+fn verify_ed25519(data: &[u8], signature: &[u8], pubkey: &Pubkey) -> Result<()> {
+    let pubkey = PublicKey::from_bytes(pubkey.as_slice())?;
+    let signature = Signature::from_bytes(signature)?;
+    pubkey.verify(data, &signature)?;
+    Ok(())
+}
+
+#[cfg(feature = "pq")]
+fn verify_hybrid(data: &[u8], signature: &[u8], pubkey: &Pubkey, pinned: &[u8]) -> Result<()> {
+    let malformed = || anyhow!("Malformed hybrid signature");
+    let (classic, rest) =
+        split_at_checked(signature, MANIFEST_SIGNATURE_LENGTH).ok_or_else(malformed)?;
+    verify_ed25519(data, classic, pubkey)?;
+    let (length, rest) = split_at_checked(rest, 4).ok_or_else(malformed)?;
+    let length = u32::from_be_bytes(length.try_into().unwrap()) as usize;
+    let (public, signature) = split_at_checked(rest, length).ok_or_else(malformed)?;
+    if public != pinned {
+        return Err(anyhow!(
+            "Dilithium3 key does not match the one pinned for the volume"
+        ));
+    }
+    let public = dilithium3::PublicKey::from_bytes(public).map_err(|_| malformed())?;
+    let signature =
+        dilithium3::DetachedSignature::from_bytes(signature).map_err(|_| malformed())?;
+    dilithium3::verify_detached_signature(&signature, data, &public)
+        .map_err(|_| anyhow!("Invalid Dilithium3 signature"))
+}
+
+#[cfg(not(feature = "pq"))]
+fn verify_hybrid(_data: &[u8], _signature: &[u8], _pubkey: &Pubkey, _pinned: &[u8]) -> Result<()> {
+    Err(anyhow!(
+        "Cannot verify {} signatures, the pq feature is not enabled",
+        SignatureAlgorithm::Ed25519Dilithium3
+    ))
+}
+
+#[cfg(feature = "pq")]
+fn split_at_checked(data: &[u8], index: usize) -> Option<(&[u8], &[u8])> {
+    (index <= data.len()).then(|| data.split_at(index))
+}
+
+/// Signs manifests for a volume. Implemented for the volume's private key, which signs with
+/// Ed25519, and for [`HybridSigner`].
+pub trait ManifestSigner {
+    fn algorithm(&self) -> SignatureAlgorithm;
+
+    /// Sign encoded data, returning the signature including its trailer (if any).
+    fn sign(&self, data: &[u8]) -> Vec<u8>;
+}
+
+impl ManifestSigner for Privkey {
+    fn algorithm(&self) -> SignatureAlgorithm {
+        SignatureAlgorithm::Ed25519
+    }
+
+    fn sign(&self, data: &[u8]) -> Vec<u8> {
+        Manifest::signature(data, self)
+    }
+}
+
+/// Signs with the volume key and a Dilithium3 key, see
+/// [`SignatureAlgorithm::Ed25519Dilithium3`]. Its public key has to be pinned for the volume
+/// first, with [`VolumeEdit::pq_pubkey`](crate::VolumeEdit), as manifests are verified
+/// against the pinned key rather than the one carried in the signature.
+#[cfg(feature = "pq")]
+#[derive(Clone)]
+pub struct HybridSigner {
+    privkey: Privkey,
+    public: dilithium3::PublicKey,
+    secret: dilithium3::SecretKey,
+}
+
+#[cfg(feature = "pq")]
+impl HybridSigner {
+    /// Generate a new Dilithium3 key to sign alongside the volume key.
+    pub fn generate(privkey: &Privkey) -> Self {
+        let (public, secret) = dilithium3::keypair();
+        HybridSigner {
+            privkey: *privkey,
+            public,
+            secret,
+        }
+    }
+
+    /// Use an existing Dilithium3 key pair, as exported by [`HybridSigner::public_key`] and
+    /// [`HybridSigner::secret_key`].
+    pub fn from_bytes(privkey: &Privkey, public: &[u8], secret: &[u8]) -> Result<Self> {
+        Ok(HybridSigner {
+            privkey: *privkey,
+            public: dilithium3::PublicKey::from_bytes(public)
+                .map_err(|_| anyhow!("Invalid Dilithium3 public key"))?,
+            secret: dilithium3::SecretKey::from_bytes(secret)
+                .map_err(|_| anyhow!("Invalid Dilithium3 secret key"))?,
+        })
+    }
+
+    pub fn public_key(&self) -> &[u8] {
+        self.public.as_bytes()
+    }
+
+    pub fn secret_key(&self) -> &[u8] {
+        self.secret.as_bytes()
+    }
+}
+
+#[cfg(feature = "pq")]
+impl ManifestSigner for HybridSigner {
+    fn algorithm(&self) -> SignatureAlgorithm {
+        SignatureAlgorithm::Ed25519Dilithium3
+    }
+
+    fn sign(&self, data: &[u8]) -> Vec<u8> {
+        let mut signature = Manifest::signature(data, &self.privkey);
+        let public = self.public.as_bytes();
+        signature.extend_from_slice(&(public.len() as u32).to_be_bytes());
+        signature.extend_from_slice(public);
+        signature.extend_from_slice(dilithium3::detached_sign(data, &self.secret).as_bytes());
+        envelope(self.algorithm(), signature)
+    }
+}
+
+#[test]
+fn test_signature_algorithm() {
+    let privkey = Privkey::generate();
+    let data = b"manifest";
+    let signature = privkey.sign(data);
+    assert_eq!(signature.len(), MANIFEST_SIGNATURE_LENGTH);
+    assert_eq!(
+        SignatureAlgorithm::of(&signature),
+        Some(SignatureAlgorithm::Ed25519)
+    );
+    assert!(verify(data, &signature, &privkey.pubkey()).is_ok());
+    assert!(verify(b"other", &signature, &privkey.pubkey()).is_err());
+
+    // Ed25519 signatures can also carry a trailer
+    let enveloped = envelope(SignatureAlgorithm::Ed25519, signature.clone());
+    assert_eq!(signature_length(&enveloped), enveloped.len());
+    assert!(verify(data, &enveloped, &privkey.pubkey()).is_ok());
+    let mut signed = data.to_vec();
+    signed.extend_from_slice(&enveloped);
+    assert_eq!(signature_length(&signed), enveloped.len());
+
+    for algorithm in [
+        SignatureAlgorithm::Ed25519,
+        SignatureAlgorithm::Ed25519Dilithium3,
+    ] {
+        assert_eq!(SignatureAlgorithm::from_id(algorithm.id()), Some(algorithm));
+        assert_eq!(algorithm.to_string().parse(), Ok(algorithm));
+    }
+    assert!(SignatureAlgorithm::supported().contains(&SignatureAlgorithm::Ed25519));
+}
+
+#[cfg(feature = "pq")]
+#[test]
+fn test_hybrid_signer() {
+    let privkey = Privkey::generate();
+    let signer = HybridSigner::generate(&privkey);
+    let data = b"manifest";
+    let signature = signer.sign(data);
+    assert_eq!(
+        SignatureAlgorithm::of(&signature),
+        Some(SignatureAlgorithm::Ed25519Dilithium3)
+    );
+    let pubkey = privkey.pubkey();
+    let pinned = Some(signer.public_key());
+    assert!(verify_pinned(data, &signature, &pubkey, pinned).is_ok());
+    assert!(verify_pinned(b"other", &signature, &pubkey, pinned).is_err());
+    assert!(verify_pinned(data, &signature, &Privkey::generate().pubkey(), pinned).is_err());
+
+    // both signatures have to be valid
+    let mut forged = signature.clone();
+    forged[MANIFEST_SIGNATURE_LENGTH + 100] ^= 1;
+    assert!(verify_pinned(data, &forged, &pubkey, pinned).is_err());
+
+    // the Dilithium3 key has to be the pinned one, and cannot be left out once pinned
+    let other = HybridSigner::generate(&privkey);
+    assert!(verify_pinned(data, &other.sign(data), &pubkey, pinned).is_err());
+    assert!(verify(data, &signature, &pubkey).is_err());
+    assert!(verify_pinned(data, &privkey.sign(data), &pubkey, pinned).is_err());
+
+    let restored =
+        HybridSigner::from_bytes(&privkey, signer.public_key(), signer.secret_key()).unwrap();
+    assert!(verify_pinned(data, &restored.sign(data), &pubkey, pinned).is_ok());
+}
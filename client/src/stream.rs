@@ -4,6 +4,7 @@ mod ed25519;
 
 pub use crate::stream::chacha20::{
     DecryptionStream as ChaCha20DecryptionStream, EncryptionStream as ChaCha20EncryptionStream,
+    NONCE_LENGTH as CHACHA20_NONCE_LENGTH,
 };
-pub use crate::stream::count::{BytesCount, CountBytesStream};
+pub use crate::stream::count::{BytesCount, BytesObserver, CountBytesStream};
 pub use ed25519::{SignStream as Ed25519SignStream, VerifyStream as Ed25519VerifyStream};
@@ -1,5 +1,5 @@
 use bytes::{Buf, BufMut, Bytes, BytesMut};
-use chacha20::cipher::{NewCipher, StreamCipher};
+use chacha20::cipher::{NewCipher, StreamCipher, StreamCipherSeek};
 use chacha20::{Key, XChaCha20, XNonce};
 use futures::task::Context;
 use futures::task::Poll;
@@ -9,6 +9,9 @@ use rand_core::{OsRng, RngCore};
 use std::error::Error as StdError;
 use std::pin::Pin;
 
+/// Length of the nonce that encrypted data starts with, in bytes.
+pub const NONCE_LENGTH: usize = 24;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum EncryptionStreamState {
     Start,
@@ -30,7 +33,7 @@ impl<E: StdError> EncryptionStream<E> {
         key: &Key,
     ) -> Self {
         // generate nonce
-        let mut nonce = [0u8; 24];
+        let mut nonce = [0u8; NONCE_LENGTH];
         OsRng.fill_bytes(&mut nonce);
         let nonce = XNonce::from_slice(&nonce);
 
@@ -110,7 +113,24 @@ impl<E: StdError> DecryptionStream<E> {
     pub fn new<S: Stream<Item = Result<Bytes, E>> + Send + 'static>(stream: S, key: &Key) -> Self {
         DecryptionStream {
             stream: Box::pin(stream),
-            state: DecryptionStreamState::Start(key.clone(), BytesMut::with_capacity(24)),
+            state: DecryptionStreamState::Start(key.clone(), BytesMut::with_capacity(NONCE_LENGTH)),
+        }
+    }
+
+    /// Decrypt data from `offset` bytes into the plaintext on, given the nonce the encrypted
+    /// data starts with. The stream has to start at the same offset, right after the nonce,
+    /// the keystream is seeked to the matching position.
+    pub fn resume<S: Stream<Item = Result<Bytes, E>> + Send + 'static>(
+        stream: S,
+        key: &Key,
+        nonce: &[u8; NONCE_LENGTH],
+        offset: u64,
+    ) -> Self {
+        let mut crypter = XChaCha20::new(key, XNonce::from_slice(nonce));
+        crypter.seek(offset);
+        DecryptionStream {
+            stream: Box::pin(stream),
+            state: DecryptionStreamState::Stream(crypter),
         }
     }
 }
@@ -125,10 +145,10 @@ impl<E: StdError> Stream for DecryptionStream<E> {
             Start(key, nonce) => match result {
                 Poll::Ready(Some(Ok(mut bytes))) => {
                     debug!("Read {} bytes raw data", bytes.len());
-                    let nonce_data = bytes.split_to((24 - nonce.len()).min(bytes.len()));
+                    let nonce_data = bytes.split_to((NONCE_LENGTH - nonce.len()).min(bytes.len()));
                     debug!("Putting nonce data");
                     nonce.put(nonce_data);
-                    if nonce.len() == 24 {
+                    if nonce.len() == NONCE_LENGTH {
                         debug!("Got nonce");
                         let nonce = XNonce::from_slice(&nonce);
                         let mut crypter = XChaCha20::new(&key, &nonce);
@@ -360,3 +380,29 @@ async fn endtoend_multi_stream() {
 
     assert!(stream.next().await.is_none());
 }
+
+#[cfg(test)]
+#[tokio::test]
+async fn endtoend_resume_stream() {
+    use futures::{StreamExt, TryStreamExt};
+    let key = Key::from_slice(b"abcdefghijklmnopqrstuvwxyz012345");
+    let data: Vec<u8> = (0..200u8).collect();
+    let stream = futures::stream::iter(vec![Ok(Bytes::copy_from_slice(&data))]);
+    let encrypted: Vec<Bytes> = EncryptionStream::<std::io::Error>::new(stream, key)
+        .try_collect()
+        .await
+        .unwrap();
+    let encrypted = encrypted.concat();
+    let nonce: [u8; NONCE_LENGTH] = encrypted[..NONCE_LENGTH].try_into().unwrap();
+
+    // resume past the first block of the keystream
+    for offset in [0, 1, 64, 150] {
+        let rest = Bytes::copy_from_slice(&encrypted[NONCE_LENGTH + offset..]);
+        let stream = futures::stream::iter(vec![Ok(rest)]);
+        let mut stream =
+            DecryptionStream::<std::io::Error>::resume(stream, key, &nonce, offset as u64);
+        let result = stream.next().await.unwrap().unwrap();
+        assert_eq!(result, &data[offset..]);
+        assert!(stream.next().await.is_none());
+    }
+}
@@ -3,15 +3,20 @@ use futures::task::Context;
 use futures::task::Poll;
 use futures::Stream;
 use std::error::Error as StdError;
+use std::fmt;
 use std::pin::Pin;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
+/// Called with the total whenever bytes are added to a [`BytesCount`].
+pub type BytesObserver = Arc<dyn Fn(usize) + Send + Sync>;
+
 /// Atomic counter that is safe to be shared between threads, as it uses atomic
 /// add and load operations.
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct BytesCount {
     bytes: Arc<AtomicUsize>,
+    observer: Option<BytesObserver>,
 }
 
 impl BytesCount {
@@ -19,12 +24,25 @@ impl BytesCount {
     pub fn new(value: usize) -> Self {
         BytesCount {
             bytes: Arc::new(AtomicUsize::new(value)),
+            observer: None,
+        }
+    }
+
+    /// Creates new starting at zero that reports the total to the observer whenever bytes
+    /// are added, such as to display the progress of a transfer whose size is not known.
+    pub fn observed(observer: impl Fn(usize) + Send + Sync + 'static) -> Self {
+        BytesCount {
+            bytes: Arc::new(AtomicUsize::new(0)),
+            observer: Some(Arc::new(observer)),
         }
     }
 
     /// Adds a value to the counter
     pub fn add(&self, value: usize) {
-        self.bytes.fetch_add(value, Ordering::SeqCst);
+        let total = self.bytes.fetch_add(value, Ordering::SeqCst) + value;
+        if let Some(observer) = &self.observer {
+            observer(total);
+        }
     }
 
     /// Fetches the current value
@@ -33,6 +51,15 @@ impl BytesCount {
     }
 }
 
+impl fmt::Debug for BytesCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BytesCount")
+            .field("bytes", &self.get())
+            .field("observed", &self.observer.is_some())
+            .finish()
+    }
+}
+
 /// Stream adaptor that has the ability to measure the amount of bytes that
 /// pass through it.
 pub struct CountBytesStream<E: StdError> {
@@ -114,3 +141,16 @@ async fn can_measure_bytes_multiple() {
     assert!(result.is_none());
     assert_eq!(count.get(), 11);
 }
+
+#[cfg(test)]
+#[test]
+fn can_observe_bytes() {
+    use std::sync::Mutex;
+    let seen = Arc::new(Mutex::new(vec![]));
+    let observer = seen.clone();
+    let count = BytesCount::observed(move |total| observer.lock().unwrap().push(total));
+    count.add(5);
+    count.clone().add(6);
+    assert_eq!(count.get(), 11);
+    assert_eq!(*seen.lock().unwrap(), vec![5, 11]);
+}
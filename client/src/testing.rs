@@ -0,0 +1,790 @@
+//! In-process mock of the storage service, for testing code built on this crate without
+//! running the service and a database. Either as an HTTP server, or as an in-memory
+//! [`StorageBackend`].
+
+use crate::{
+    Error, Hash, ManifestSigned, PayloadStatus, Privkey, Pubkey, SnapshotUploaded, StorageBackend,
+    StorageClass, VolumeEdit, VolumeInfo,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use hyper::body::to_bytes;
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use optional_field::Field;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::{Ipv4Addr, TcpListener};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+use url::Url;
+use uuid::Uuid;
+
+/// Volume stored in the mock server.
+#[derive(Debug, Default)]
+struct MockVolume {
+    account: Uuid,
+    writer: Option<Uuid>,
+    /// Dilithium3 public key pinned for the volume, hex-encoded.
+    pq_pubkey: Option<String>,
+    /// Snapshots, in the order they were uploaded.
+    snapshots: Vec<ManifestSigned>,
+}
+
+type MockState = Arc<Mutex<BTreeMap<Pubkey, MockVolume>>>;
+
+/// Running mock server, shut down when this is dropped.
+pub struct MockServer {
+    url: Url,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl MockServer {
+    /// URL of the API, to pass to the functions of this crate.
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+/// In-memory implementation of [`StorageBackend`], acting as the given account, for unit
+/// tests of code that takes a backend instead of a [`StorageClient`](crate::StorageClient).
+/// It makes the same checks as the mock server. Clones share their state.
+#[derive(Clone, Debug)]
+pub struct MockStorage {
+    account: Uuid,
+    state: MockState,
+}
+
+impl MockStorage {
+    pub fn new(account: Uuid) -> Self {
+        MockStorage {
+            account,
+            state: MockState::default(),
+        }
+    }
+
+    /// Another backend acting as a different account, sharing the state with this one.
+    pub fn with_account(&self, account: Uuid) -> Self {
+        MockStorage {
+            account,
+            state: self.state.clone(),
+        }
+    }
+
+    /// Run `f` on a volume of the account.
+    fn volume<T>(
+        &self,
+        volume: &Pubkey,
+        f: impl FnOnce(&mut MockVolume) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let mut volumes = self.state.lock().unwrap();
+        match volumes.get_mut(volume) {
+            Some(data) if data.account == self.account => f(data),
+            _ => Err(Error::VolumeNotFound(format!("Volume {volume} not found"))),
+        }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for MockStorage {
+    async fn volume_create(&self, volume: &Privkey) -> Result<(), Error> {
+        let mut volumes = self.state.lock().unwrap();
+        if volumes.contains_key(&volume.pubkey()) {
+            return Err(Error::Unsuccessful(StatusCode::INTERNAL_SERVER_ERROR));
+        }
+        volumes.insert(
+            volume.pubkey(),
+            MockVolume {
+                account: self.account,
+                ..Default::default()
+            },
+        );
+        Ok(())
+    }
+
+    async fn volume_get(&self, volume: &Pubkey) -> Result<VolumeInfo, Error> {
+        self.volume(volume, |volume| Ok(mock_info(volume)))
+    }
+
+    async fn volume_list(&self) -> Result<Vec<(Pubkey, VolumeInfo)>, Error> {
+        let volumes = self.state.lock().unwrap();
+        Ok(volumes
+            .iter()
+            .filter(|(_, volume)| volume.account == self.account)
+            .map(|(pubkey, volume)| (*pubkey, mock_info(volume)))
+            .collect())
+    }
+
+    async fn volume_edit(&self, volume: &Privkey, edit: &VolumeEdit) -> Result<(), Error> {
+        self.volume(&volume.pubkey(), |volume| {
+            mock_edit(volume, edit).map_err(Error::Unsuccessful)
+        })
+    }
+
+    async fn volume_remove(&self, volume: &Privkey) -> Result<(), Error> {
+        self.volume(&volume.pubkey(), |_| Ok(()))?;
+        self.state.lock().unwrap().remove(&volume.pubkey());
+        Ok(())
+    }
+
+    async fn snapshot_upload(
+        &self,
+        volume: &Pubkey,
+        manifest: &ManifestSigned,
+    ) -> Result<SnapshotUploaded, Error> {
+        let pubkey = *volume;
+        self.volume(volume, |volume| {
+            mock_verify(&pubkey, volume, manifest).map_err(Error::Unsuccessful)?;
+            let hash = manifest.hash();
+            let deduplicated = volume
+                .snapshots
+                .iter()
+                .any(|snapshot| snapshot.hash() == hash);
+            if !deduplicated {
+                mock_validate(volume, manifest).map_err(Error::Unsuccessful)?;
+                volume.writer = Some(manifest.manifest.machine);
+                volume.snapshots.push(manifest.clone());
+            }
+            Ok(SnapshotUploaded {
+                hash,
+                deduplicated,
+                payload: Some(PayloadStatus::Ipfs),
+                warnings: vec![],
+                pending: false,
+            })
+        })
+    }
+
+    async fn snapshot_fetch(
+        &self,
+        volume: &Pubkey,
+        snapshot: &Hash,
+    ) -> Result<ManifestSigned, Error> {
+        self.volume(volume, |volume| {
+            volume
+                .snapshots
+                .iter()
+                .find(|manifest| manifest.hash() == *snapshot)
+                .cloned()
+                .ok_or_else(|| Error::SnapshotNotFound(format!("Snapshot {snapshot} not found")))
+        })
+    }
+
+    async fn snapshot_list(
+        &self,
+        volume: &Pubkey,
+        parent: Option<&Hash>,
+        root: bool,
+    ) -> Result<Vec<Hash>, Error> {
+        self.volume(volume, |volume| {
+            Ok(mock_list(volume, parent.copied(), root))
+        })
+    }
+
+    async fn snapshot_exists(&self, volume: &Pubkey, hashes: &[Hash]) -> Result<Vec<Hash>, Error> {
+        self.volume(volume, |volume| {
+            Ok(hashes
+                .iter()
+                .filter(|hash| {
+                    volume
+                        .snapshots
+                        .iter()
+                        .any(|snapshot| snapshot.hash() == **hash)
+                })
+                .copied()
+                .collect())
+        })
+    }
+
+    async fn snapshot_delete(&self, volume: &Pubkey, snapshot: &Hash) -> Result<(), Error> {
+        self.volume(volume, |volume| {
+            let index = volume
+                .snapshots
+                .iter()
+                .position(|manifest| manifest.hash() == *snapshot)
+                .ok_or_else(|| Error::SnapshotNotFound(format!("Snapshot {snapshot} not found")))?;
+            let children = volume.snapshots.iter().any(|manifest| {
+                matches!(&manifest.manifest.parent, Some(parent) if parent.hash == *snapshot)
+            });
+            if children {
+                return Err(Error::HasChildren(format!(
+                    "Snapshot {snapshot} has children"
+                )));
+            }
+            volume.snapshots.remove(index);
+            Ok(())
+        })
+    }
+}
+
+/// Spawn a mock server on a random local port. It implements the health check, creating,
+/// editing and fetching volumes, and uploading, listing and fetching snapshots, including
+/// checks for signatures, generations, parents and writers, with the status codes of the
+/// service. Any bearer token that is a UUID is accepted, as the account it belongs to. State
+/// is kept in memory. Must be called from within a Tokio runtime.
+pub async fn spawn_mock_server() -> Result<MockServer> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let url = Url::parse(&format!("http://{}", listener.local_addr()?))?;
+    let state = MockState::default();
+    let service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let state = state.clone();
+                async move {
+                    let response = match route(&state, request).await {
+                        Ok(response) => response,
+                        Err(status) => respond(status),
+                    };
+                    Ok::<_, Infallible>(response)
+                }
+            }))
+        }
+    });
+    let (shutdown, signal) = oneshot::channel();
+    let server = Server::from_tcp(listener)?
+        .serve(service)
+        .with_graceful_shutdown(async {
+            signal.await.ok();
+        });
+    tokio::spawn(server);
+    Ok(MockServer {
+        url,
+        shutdown: Some(shutdown),
+    })
+}
+
+fn respond(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+fn json<T: Serialize>(value: &T) -> Result<Response<Body>, StatusCode> {
+    let data = serde_json::to_vec(value).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut response = Response::new(Body::from(data));
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, "application/json".parse().unwrap());
+    Ok(response)
+}
+
+/// Account of the request, from its bearer token.
+fn account(request: &Request<Body>) -> Result<Uuid, StatusCode> {
+    request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "))
+        .and_then(|token| Uuid::from_str(token).ok())
+        .ok_or(StatusCode::UNAUTHORIZED)
+}
+
+async fn route(state: &MockState, request: Request<Body>) -> Result<Response<Body>, StatusCode> {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let path: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let query: BTreeMap<String, String> = request
+        .uri()
+        .query()
+        .map(|query| {
+            url::form_urlencoded::parse(query.as_bytes())
+                .into_owned()
+                .collect()
+        })
+        .unwrap_or_default();
+    if method == Method::GET && path == ["health"] {
+        return Ok(respond(StatusCode::OK));
+    }
+    let path = match path.as_slice() {
+        ["api", "v1", "volume", volume, rest @ ..] => (
+            Pubkey::from_hex(volume).map_err(|_| StatusCode::NOT_FOUND)?,
+            rest,
+        ),
+        _ => return Err(StatusCode::NOT_FOUND),
+    };
+    let account = account(&request)?;
+    let body = to_bytes(request.into_body())
+        .await
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let mut volumes = state.lock().unwrap();
+    match (method, path) {
+        (Method::POST, (pubkey, [])) => {
+            if volumes.contains_key(&pubkey) {
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+            volumes.insert(
+                pubkey,
+                MockVolume {
+                    account,
+                    ..Default::default()
+                },
+            );
+            Ok(respond(StatusCode::OK))
+        }
+        (Method::GET, (pubkey, [])) => {
+            let volume = volumes.get(&pubkey).ok_or(StatusCode::NOT_FOUND)?;
+            json(&mock_info(volume))
+        }
+        (Method::PATCH, (pubkey, [])) => {
+            let volume = volumes.get_mut(&pubkey).ok_or(StatusCode::NOT_FOUND)?;
+            let edit: VolumeEdit =
+                serde_json::from_slice(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+            mock_edit(volume, &edit)?;
+            Ok(respond(StatusCode::OK))
+        }
+        (Method::POST, (pubkey, ["snapshot"])) => {
+            let volume = volumes.get_mut(&pubkey).ok_or(StatusCode::NOT_FOUND)?;
+            let manifest = ManifestSigned::parse(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+            mock_verify(&pubkey, volume, &manifest)?;
+            let hash = manifest.hash();
+            let deduplicated = volume
+                .snapshots
+                .iter()
+                .any(|snapshot| snapshot.hash() == hash);
+            if !deduplicated {
+                mock_validate(volume, &manifest)?;
+                volume.writer = Some(manifest.manifest.machine);
+                volume.snapshots.push(manifest);
+            }
+            // payloads are not stored by the mock
+            json(&SnapshotUploaded {
+                hash,
+                deduplicated,
+                payload: Some(PayloadStatus::Ipfs),
+                warnings: vec![],
+                pending: false,
+            })
+        }
+        (Method::GET, (pubkey, ["snapshots"])) => {
+            let volume = volumes.get(&pubkey).ok_or(StatusCode::NOT_FOUND)?;
+            let parent = query
+                .get("parent")
+                .map(|parent| Hash::from_str(parent))
+                .transpose()
+                .map_err(|_| StatusCode::BAD_REQUEST)?;
+            let root = query
+                .get("root")
+                .map(|root| root == "true")
+                .unwrap_or(false);
+            json(&mock_list(volume, parent, root))
+        }
+        (Method::POST, (pubkey, ["snapshots", "exists"])) => {
+            let volume = volumes.get(&pubkey).ok_or(StatusCode::NOT_FOUND)?;
+            let hashes: Vec<Hash> =
+                serde_json::from_slice(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+            let existing: Vec<Hash> = hashes
+                .into_iter()
+                .filter(|hash| {
+                    volume
+                        .snapshots
+                        .iter()
+                        .any(|snapshot| snapshot.hash() == *hash)
+                })
+                .collect();
+            json(&existing)
+        }
+        (Method::GET, (pubkey, [snapshot])) => {
+            let volume = volumes.get(&pubkey).ok_or(StatusCode::NOT_FOUND)?;
+            let hash = Hash::from_str(snapshot).map_err(|_| StatusCode::NOT_FOUND)?;
+            let manifest = volume
+                .snapshots
+                .iter()
+                .find(|snapshot| snapshot.hash() == hash)
+                .ok_or(StatusCode::NOT_FOUND)?;
+            Ok(Response::new(Body::from(manifest.data())))
+        }
+        _ => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+/// Info of a volume, as the service reports it.
+fn mock_info(volume: &MockVolume) -> VolumeInfo {
+    VolumeInfo {
+        writer: volume.writer,
+        account: volume.account,
+        retain_count: None,
+        retain_age: None,
+        snapshot_count: volume.snapshots.len() as u64,
+        latest_generation: volume
+            .snapshots
+            .iter()
+            .map(|snapshot| snapshot.manifest.generation)
+            .max(),
+        bytes_stored: volume
+            .snapshots
+            .iter()
+            .map(|snapshot| snapshot.manifest.size)
+            .sum(),
+        worm_period: None,
+        immutable_until: None,
+        storage_class: StorageClass::default(),
+        snapshots_pending: 0,
+        pq_pubkey: volume.pq_pubkey.clone(),
+    }
+}
+
+/// Apply the properties of an edit that the mock tracks. Like the service, a Dilithium3 key
+/// can only be pinned once.
+fn mock_edit(volume: &mut MockVolume, edit: &VolumeEdit) -> Result<(), StatusCode> {
+    if let Some(key) = &edit.pq_pubkey {
+        if matches!(&volume.pq_pubkey, Some(pinned) if pinned != key) {
+            return Err(StatusCode::CONFLICT);
+        }
+        volume.pq_pubkey = Some(key.clone());
+    }
+    if let Field::Present(writer) = edit.writer {
+        volume.writer = writer;
+    }
+    if let Some(account) = edit.account {
+        volume.account = account;
+    }
+    Ok(())
+}
+
+/// Verify the signature of a manifest, against the pinned Dilithium3 key if there is one.
+fn mock_verify(
+    pubkey: &Pubkey,
+    volume: &MockVolume,
+    manifest: &ManifestSigned,
+) -> Result<(), StatusCode> {
+    manifest
+        .validate_pinned(pubkey, mock_info(volume).pq_pubkey_bytes().as_deref())
+        .map_err(|_| StatusCode::BAD_REQUEST)
+}
+
+/// Snapshots of a volume by ascending generation, optionally only the children of `parent`
+/// or only root snapshots.
+fn mock_list(volume: &MockVolume, parent: Option<Hash>, root: bool) -> Vec<Hash> {
+    let mut snapshots: Vec<&ManifestSigned> = volume
+        .snapshots
+        .iter()
+        .filter(|snapshot| {
+            let hash = snapshot.manifest.parent.as_ref().map(|parent| parent.hash);
+            (!root || hash.is_none()) && (parent.is_none() || hash == parent)
+        })
+        .collect();
+    snapshots.sort_by_key(|snapshot| snapshot.manifest.generation);
+    snapshots.iter().map(|snapshot| snapshot.hash()).collect()
+}
+
+/// Checks the service makes before storing a new snapshot.
+fn mock_validate(volume: &MockVolume, manifest: &ManifestSigned) -> Result<(), StatusCode> {
+    let manifest = &manifest.manifest;
+    let exists = volume
+        .snapshots
+        .iter()
+        .any(|snapshot| snapshot.manifest.generation == manifest.generation);
+    if exists {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if matches!(volume.writer, Some(writer) if writer != manifest.machine) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if let Some(parent) = &manifest.parent {
+        // parents in other volumes are not tracked by the mock
+        let found = volume
+            .snapshots
+            .iter()
+            .any(|snapshot| snapshot.hash() == parent.hash);
+        if parent.volume.is_none() && !found {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mock_server() {
+    use crate::{Error, Manifest, Parent, Privkey};
+    use reqwest::Client;
+
+    let server = spawn_mock_server().await.unwrap();
+    let url = server.url();
+    let client = Client::new();
+    let token = Uuid::new_v4().to_string();
+    crate::health_check(url, &client).await.unwrap();
+
+    let volume = Privkey::generate();
+    crate::volume_create(url, &client, &token, &volume)
+        .await
+        .unwrap();
+    let info = crate::volume_get(url, &client, &token, &volume.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(info.account.to_string(), token);
+    let result = crate::volume_get(url, &client, "invalid", &volume.pubkey()).await;
+    assert!(matches!(
+        result,
+        Err(Error::Unsuccessful(StatusCode::UNAUTHORIZED))
+    ));
+
+    let mut manifest = Manifest {
+        generation: 0,
+        creation: 0,
+        path: "/tmp/path".into(),
+        machine: Uuid::new_v4(),
+        size: 1024,
+        size_total: 1024,
+        parent: None,
+        data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+            .parse()
+            .unwrap(),
+    };
+    let root = manifest.sign(&volume);
+    for deduplicated in [false, true] {
+        let uploaded = crate::snapshot_upload(url, &client, &token, &volume.pubkey(), &root)
+            .await
+            .unwrap();
+        assert_eq!(uploaded.hash, root.hash());
+        assert_eq!(uploaded.deduplicated, deduplicated);
+    }
+    manifest.generation = 1;
+    manifest.parent = Some(Parent::new(root.hash()));
+    let child = manifest.sign(&volume);
+    crate::snapshot_upload(url, &client, &token, &volume.pubkey(), &child)
+        .await
+        .unwrap();
+
+    // conflicting generations and foreign signatures are rejected
+    manifest.size = 2048;
+    for manifest in [
+        manifest.sign(&volume),
+        root.manifest.sign(&Privkey::generate()),
+    ] {
+        let result =
+            crate::snapshot_upload(url, &client, &token, &volume.pubkey(), &manifest).await;
+        assert!(matches!(
+            result,
+            Err(Error::Unsuccessful(StatusCode::BAD_REQUEST))
+        ));
+    }
+
+    let snapshots = crate::snapshot_list(url, &client, &token, &volume.pubkey(), None, false)
+        .await
+        .unwrap();
+    assert_eq!(snapshots, vec![root.hash(), child.hash()]);
+    let roots = crate::snapshot_list(url, &client, &token, &volume.pubkey(), None, true)
+        .await
+        .unwrap();
+    assert_eq!(roots, vec![root.hash()]);
+    let fetched = crate::snapshot_fetch(url, &client, &token, &volume.pubkey(), &child.hash())
+        .await
+        .unwrap();
+    assert_eq!(fetched, child);
+}
+
+#[tokio::test]
+async fn test_mock_storage() {
+    use crate::{Manifest, Parent};
+    use std::path::PathBuf;
+
+    async fn backup(backend: &dyn StorageBackend, volume: &Privkey, manifest: &Manifest) -> Hash {
+        backend
+            .snapshot_upload(&volume.pubkey(), &manifest.sign(volume))
+            .await
+            .unwrap()
+            .hash
+    }
+
+    let account = Uuid::new_v4();
+    let storage = MockStorage::new(account);
+    let volume = Privkey::generate();
+    storage.volume_create(&volume).await.unwrap();
+    let mut manifest = Manifest {
+        creation: 0,
+        machine: Uuid::new_v4(),
+        path: PathBuf::from("/tmp/path"),
+        size: 1024,
+        size_total: 1024,
+        generation: 0,
+        parent: None,
+        data: Url::parse("ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth").unwrap(),
+    };
+    let root = backup(&storage, &volume, &manifest).await;
+    manifest.generation = 1;
+    manifest.parent = Some(Parent::new(root));
+    let child = backup(&storage, &volume, &manifest).await;
+
+    let pubkey = volume.pubkey();
+    let info = storage.volume_get(&pubkey).await.unwrap();
+    assert_eq!(info.snapshot_count, 2);
+    assert_eq!(info.latest_generation, Some(1));
+    assert_eq!(
+        storage.snapshot_list(&pubkey, None, false).await.unwrap(),
+        vec![root, child]
+    );
+    assert_eq!(
+        storage.snapshot_fetch(&pubkey, &child).await.unwrap(),
+        manifest.sign(&volume)
+    );
+    assert!(matches!(
+        storage.snapshot_delete(&pubkey, &root).await,
+        Err(Error::HasChildren(_))
+    ));
+    storage.snapshot_delete(&pubkey, &child).await.unwrap();
+    assert_eq!(
+        storage
+            .snapshot_exists(&pubkey, &[root, child])
+            .await
+            .unwrap(),
+        vec![root]
+    );
+
+    // volumes of other accounts are not visible
+    let other = storage.with_account(Uuid::new_v4());
+    assert!(matches!(
+        other.volume_get(&pubkey).await,
+        Err(Error::VolumeNotFound(_))
+    ));
+    assert!(other.volume_list().await.unwrap().is_empty());
+    assert_eq!(storage.volume_list().await.unwrap().len(), 1);
+    storage.volume_remove(&volume).await.unwrap();
+    assert!(storage.volume_list().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_upload_queue_flush() {
+    use crate::{Manifest, StorageClient, UploadQueue};
+    use std::path::PathBuf;
+
+    let dir = std::env::temp_dir().join(format!("upload-queue-{}", Uuid::new_v4()));
+    let queue = UploadQueue::new(&dir);
+    let volume = Privkey::generate();
+    let mut manifest = Manifest {
+        creation: 0,
+        machine: Uuid::new_v4(),
+        path: PathBuf::from("/tmp/path"),
+        size: 1024,
+        size_total: 1024,
+        generation: 0,
+        parent: None,
+        data: Url::parse("ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth").unwrap(),
+    };
+    let root = manifest.sign(&volume);
+    manifest.generation = 1;
+    manifest.parent = Some(crate::Parent::new(root.hash()));
+    let child = manifest.sign(&volume);
+
+    // uploads to an unreachable service are queued
+    let token = Uuid::new_v4().to_string();
+    let offline = StorageClient::builder()
+        .api(Url::parse("http://127.0.0.1:1").unwrap())
+        .token(token.clone())
+        .upload_queue(queue.clone())
+        .build()
+        .unwrap();
+    for manifest in [&child, &root] {
+        let result = offline.snapshot_upload(&volume.pubkey(), manifest).await;
+        assert!(matches!(result, Err(error) if error.is_transient()));
+    }
+    assert_eq!(queue.pending().await.unwrap().len(), 2);
+
+    // and replayed parents first once it is reachable
+    let server = spawn_mock_server().await.unwrap();
+    let online = StorageClient::builder()
+        .api(server.url().clone())
+        .token(token)
+        .upload_queue(queue.clone())
+        .build()
+        .unwrap();
+    online.volume_create(&volume).await.unwrap();
+    let uploaded = online.flush_pending().await.unwrap();
+    let hashes: Vec<Hash> = uploaded.iter().map(|upload| upload.hash).collect();
+    assert_eq!(hashes, vec![root.hash(), child.hash()]);
+    assert!(queue.pending().await.unwrap().is_empty());
+    assert!(online.flush_pending().await.unwrap().is_empty());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_client_hooks() {
+    use crate::{Error, Manifest, StorageClient};
+    use std::path::PathBuf;
+
+    let server = spawn_mock_server().await.unwrap();
+    let requests = Arc::new(Mutex::new(vec![]));
+    let uploads = Arc::new(Mutex::new(vec![]));
+    let errors = Arc::new(Mutex::new(vec![]));
+    let client = {
+        let (requests, uploads, errors) = (requests.clone(), uploads.clone(), errors.clone());
+        StorageClient::builder()
+            .api(server.url().clone())
+            .token(Uuid::new_v4().to_string())
+            .on_request(move |method, url| {
+                requests
+                    .lock()
+                    .unwrap()
+                    .push((method.clone(), url.path().to_string()))
+            })
+            .on_upload_complete(move |_, uploaded| uploads.lock().unwrap().push(uploaded.hash))
+            .on_error(move |error: &Error| errors.lock().unwrap().push(error.to_string()))
+            .build()
+            .unwrap()
+    };
+
+    let volume = Privkey::generate();
+    assert!(client.volume_get(&volume.pubkey()).await.is_err());
+    assert_eq!(errors.lock().unwrap().len(), 1);
+
+    client.volume_create(&volume).await.unwrap();
+    let manifest = Manifest {
+        creation: 0,
+        machine: Uuid::new_v4(),
+        path: PathBuf::from("/tmp/path"),
+        size: 1024,
+        size_total: 1024,
+        generation: 0,
+        parent: None,
+        data: Url::parse("ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth").unwrap(),
+    }
+    .sign(&volume);
+    client
+        .snapshot_upload(&volume.pubkey(), &manifest)
+        .await
+        .unwrap();
+
+    {
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[2].0, reqwest::Method::POST);
+        assert_eq!(*uploads.lock().unwrap(), vec![manifest.hash()]);
+        assert_eq!(errors.lock().unwrap().len(), 1);
+    }
+
+    // responses that cannot be decoded are reported as well
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let url = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+    let service = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(|_| async {
+            Ok::<_, Infallible>(Response::new(Body::from("not json")))
+        }))
+    });
+    tokio::spawn(Server::from_tcp(listener).unwrap().serve(service));
+    let client = {
+        let errors = errors.clone();
+        StorageClient::builder()
+            .api(url)
+            .on_error(move |error: &Error| errors.lock().unwrap().push(error.to_string()))
+            .build()
+            .unwrap()
+    };
+    assert!(client.volume_get(&volume.pubkey()).await.is_err());
+    assert!(client
+        .snapshot_fetch(&volume.pubkey(), &manifest.hash())
+        .await
+        .is_err());
+    assert_eq!(errors.lock().unwrap().len(), 3);
+}
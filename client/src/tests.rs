@@ -47,3 +47,118 @@ async fn test_ipfs_upload() {
     OsRng.fill_bytes(&mut data[..]);
     test_ipfs_upload_data(&ipfs_client, &secret, &data).await;
 }
+
+#[test]
+fn test_error_from_body() {
+    use reqwest::StatusCode;
+
+    let body = br#"{"code":"volume-not-found","message":"Volume not found for user"}"#;
+    assert!(matches!(
+        Error::from_body(StatusCode::NOT_FOUND, body),
+        Error::VolumeNotFound(message) if message == "Volume not found for user"
+    ));
+    let body = br#"{"code":"locked","message":"Snapshots are immutable until 100"}"#;
+    assert!(matches!(
+        Error::from_body(StatusCode::FORBIDDEN, body),
+        Error::Locked(_)
+    ));
+
+    // codes without a variant, unknown codes and unstructured bodies keep the status
+    let body = br#"{"code":"forbidden","message":"Access denied"}"#;
+    assert!(matches!(
+        Error::from_body(StatusCode::FORBIDDEN, body),
+        Error::Unsuccessful(StatusCode::FORBIDDEN)
+    ));
+    let body = br#"{"code":"from-the-future","message":"Something new"}"#;
+    assert!(matches!(
+        Error::from_body(StatusCode::BAD_REQUEST, body),
+        Error::Unsuccessful(StatusCode::BAD_REQUEST)
+    ));
+    assert!(matches!(
+        Error::from_body(StatusCode::NOT_FOUND, b"Not Found"),
+        Error::Unsuccessful(StatusCode::NOT_FOUND)
+    ));
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_chain_download() {
+    let secret = Privkey::generate().derive_secret();
+    let ipfs_client = ipfs_client();
+    let payloads: Vec<Vec<u8>> = (0..6u8).map(|i| vec![i; 1024 * (i as usize + 1)]).collect();
+    let mut cids = vec![];
+    for payload in &payloads {
+        let stream = stream::iter(vec![Ok(Bytes::copy_from_slice(payload))]);
+        let cid = ipfs::upload_encrypt(&ipfs_client, &secret, Box::pin(stream))
+            .await
+            .unwrap();
+        cids.push(cid);
+    }
+
+    let dir = std::env::temp_dir();
+    let downloads: Vec<ChainDownload> = chain_download(&ipfs_client, &secret, cids.clone(), dir, 3)
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(downloads.len(), payloads.len());
+    for ((download, cid), payload) in downloads.iter().zip(&cids).zip(&payloads) {
+        assert_eq!(&download.cid, cid);
+        assert_eq!(download.size, payload.len() as u64);
+        assert_eq!(&std::fs::read(&download.path).unwrap(), payload);
+        std::fs::remove_file(&download.path).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_snapshot_fetch_forged() {
+    use std::path::PathBuf;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    // service answering every request with a manifest signed by another key
+    let volume = Privkey::generate();
+    let forged = Manifest {
+        creation: 0,
+        machine: uuid::Uuid::nil(),
+        path: PathBuf::from("/tmp/path"),
+        size: 100,
+        size_total: 100,
+        generation: 0,
+        parent: None,
+        data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+            .try_into()
+            .unwrap(),
+    }
+    .sign(&Privkey::generate());
+    let body = forged.data();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let api = url::Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = socket.read(&mut request).await;
+            let header = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            socket.write_all(header.as_bytes()).await.unwrap();
+            socket.write_all(&body).await.unwrap();
+        }
+    });
+
+    let client = StorageClient::builder()
+        .api(api)
+        .token("token")
+        .build()
+        .unwrap();
+    let result = client
+        .snapshot_fetch(&volume.pubkey(), &forged.hash())
+        .await;
+    assert!(matches!(result, Err(Error::ManifestValidation(hash)) if hash == forged.hash()));
+    let fetched = client
+        .snapshot_fetch_unverified(&volume.pubkey(), &forged.hash())
+        .await
+        .unwrap();
+    assert_eq!(fetched, forged);
+}
@@ -0,0 +1,130 @@
+use reqwest::ClientBuilder;
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::{Certificate, ClientConfig, ServerName};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::SystemTime;
+use thiserror::Error;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum PinError {
+    #[error("Invalid certificate pin, expected 64 hex digits")]
+    InvalidPin,
+    #[error("Cannot parse certificate")]
+    InvalidCertificate,
+}
+
+/// SHA-256 hash of the public key (SubjectPublicKeyInfo) of a certificate. Pinning the key
+/// rather than the certificate keeps the pin valid when a certificate is renewed with the
+/// same key. Written as hex, optionally prefixed with `sha256:` and with colons between
+/// bytes, as shown by most tools.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CertificatePin([u8; 32]);
+
+impl CertificatePin {
+    /// Pin of a DER-encoded certificate.
+    pub fn from_certificate(der: &[u8]) -> Result<Self, PinError> {
+        let (_, certificate) =
+            X509Certificate::from_der(der).map_err(|_| PinError::InvalidCertificate)?;
+        Ok(CertificatePin(
+            Sha256::digest(certificate.public_key().raw).into(),
+        ))
+    }
+}
+
+impl FromStr for CertificatePin {
+    type Err = PinError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let input = input.strip_prefix("sha256:").unwrap_or(input);
+        let digits: Vec<u8> = input.bytes().filter(|c| *c != b':').collect();
+        if digits.len() != 64 {
+            return Err(PinError::InvalidPin);
+        }
+        let mut pin = [0; 32];
+        for (byte, pair) in pin.iter_mut().zip(digits.chunks(2)) {
+            let pair = std::str::from_utf8(pair).map_err(|_| PinError::InvalidPin)?;
+            *byte = u8::from_str_radix(pair, 16).map_err(|_| PinError::InvalidPin)?;
+        }
+        Ok(CertificatePin(pin))
+    }
+}
+
+impl fmt::Display for CertificatePin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Accepts only servers presenting a certificate with the pinned key, regardless of who
+/// issued it. The handshake signature is still checked against that key by rustls.
+struct PinnedVerifier(CertificatePin);
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        match CertificatePin::from_certificate(&end_entity.0) {
+            Ok(pin) if pin == self.0 => Ok(ServerCertVerified::assertion()),
+            Ok(pin) => Err(rustls::Error::General(format!(
+                "certificate key {pin} does not match pinned key {}",
+                self.0
+            ))),
+            Err(_) => Err(rustls::Error::InvalidCertificateEncoding),
+        }
+    }
+}
+
+/// Make the client accept only servers presenting a certificate with the pinned key, such
+/// as a self-signed certificate, and reject everything else, including certificates issued
+/// by public CAs.
+pub fn with_pinned_cert(builder: ClientBuilder, pin: CertificatePin) -> ClientBuilder {
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(PinnedVerifier(pin)))
+        .with_no_client_auth();
+    builder.use_preconfigured_tls(config)
+}
+
+#[test]
+fn test_certificate_pin() {
+    let certificate = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+    let der = certificate.serialize_der().unwrap();
+    let pin = CertificatePin::from_certificate(&der).unwrap();
+    let expected = Sha256::digest(certificate.get_key_pair().public_key_der());
+    assert_eq!(pin.0.as_slice(), expected.as_slice());
+
+    // parsing accepts the common notations
+    assert_eq!(pin.to_string().parse(), Ok(pin));
+    assert_eq!(format!("sha256:{pin}").parse(), Ok(pin));
+    let colons: Vec<String> = pin.0.iter().map(|byte| format!("{byte:02X}")).collect();
+    assert_eq!(colons.join(":").parse(), Ok(pin));
+    assert_eq!("abcd".parse::<CertificatePin>(), Err(PinError::InvalidPin));
+
+    let verify = |pin: CertificatePin| {
+        PinnedVerifier(pin).verify_server_cert(
+            &Certificate(der.clone()),
+            &[],
+            &ServerName::try_from("localhost").unwrap(),
+            &mut std::iter::empty(),
+            &[],
+            SystemTime::now(),
+        )
+    };
+    assert!(verify(pin).is_ok());
+    let other = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+    let other = CertificatePin::from_certificate(&other.serialize_der().unwrap()).unwrap();
+    assert!(verify(other).is_err());
+}
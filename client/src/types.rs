@@ -1,14 +1,22 @@
+use crate::ipfs::UploadSize;
+use crate::keys::{Hash, Pubkey};
+use crate::manifest::ManifestSigned;
 use anyhow::Result;
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use bytes::{Bytes, BytesMut};
+use cid::Cid;
 use futures::stream::Stream;
 use futures::task::Context;
 use futures::task::Poll;
 use optional_field::Field;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::error::Error as StdError;
 use std::io::Cursor;
+use std::path::PathBuf;
 use std::pin::Pin;
+use std::str::FromStr;
+use url::Url;
 use uuid::Uuid;
 
 pub const SNAPSHOT_HEADER_SIZE: usize = 3 * 8;
@@ -30,10 +38,909 @@ pub struct SnapshotInfo {
     pub size: u64,
 }
 
+/// Snapshot as returned by the v2 listing API.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotRecord {
+    /// Hash of the snapshot's manifest.
+    pub hash: Hash,
+    pub generation: u64,
+    pub creation: u64,
+    pub size: u64,
+    pub size_total: u64,
+    /// Hash of the parent snapshot, if any.
+    pub parent: Option<Hash>,
+    /// Location of the snapshot's data (IPFS CID).
+    pub data: Url,
+    /// Storage class of the volume when the snapshot was uploaded.
+    #[serde(default)]
+    pub storage_class: StorageClass,
+}
+
+/// Page of snapshots returned by the v2 listing API.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotPage {
+    pub snapshots: Vec<SnapshotRecord>,
+    /// Opaque cursor to fetch the next page with, `None` if this is the last page.
+    pub cursor: Option<String>,
+}
+
+/// Field to order snapshot listings by.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SnapshotOrder {
+    /// Generation of the snapshot.
+    Generation,
+    /// Time the snapshot was created, according to its manifest.
+    Creation,
+    /// Size of the snapshot.
+    Size,
+}
+
+impl SnapshotOrder {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SnapshotOrder::Generation => "generation",
+            SnapshotOrder::Creation => "creation",
+            SnapshotOrder::Size => "size",
+        }
+    }
+}
+
+impl FromStr for SnapshotOrder {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "generation" => Ok(SnapshotOrder::Generation),
+            "creation" => Ok(SnapshotOrder::Creation),
+            "size" => Ok(SnapshotOrder::Size),
+            other => Err(format!("Unknown snapshot order {other:?}")),
+        }
+    }
+}
+
+/// Direction to sort listings in.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+impl SortDirection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SortDirection::Asc => "asc",
+            SortDirection::Desc => "desc",
+        }
+    }
+}
+
+impl FromStr for SortDirection {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "asc" => Ok(SortDirection::Asc),
+            "desc" => Ok(SortDirection::Desc),
+            other => Err(format!("Unknown sort direction {other:?}")),
+        }
+    }
+}
+
+/// Ordering of snapshot listings. Snapshots that compare equal are ordered by their hash,
+/// so that listings are deterministic. Defaults to ascending generation.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotOrdering {
+    pub order: SnapshotOrder,
+    pub direction: SortDirection,
+}
+
+impl Default for SnapshotOrdering {
+    fn default() -> Self {
+        SnapshotOrdering {
+            order: SnapshotOrder::Generation,
+            direction: SortDirection::Asc,
+        }
+    }
+}
+
+/// Options for listing snapshots with the v2 listing API.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct SnapshotListOptions {
+    /// Only list children of this snapshot.
+    pub parent: Option<Hash>,
+    /// Only list root snapshots.
+    pub root: bool,
+    /// Cursor returned with the previous page.
+    pub cursor: Option<String>,
+    /// Maximum number of snapshots to return.
+    pub limit: Option<u64>,
+}
+
+/// Result of validating a snapshot's chain of parents back to the root.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChainReport {
+    /// True if no problems were found in any of the links.
+    pub valid: bool,
+    /// Links of the chain, from the requested snapshot to the root.
+    pub links: Vec<ChainLink>,
+}
+
+/// Signed manifests of a snapshot and its ancestors.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotAncestry {
+    /// Snapshots from the requested one to the root.
+    pub snapshots: Vec<AncestryLink>,
+    /// False if the chain stops at a parent the caller may not access.
+    pub complete: bool,
+}
+
+/// Single snapshot in a [`SnapshotAncestry`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AncestryLink {
+    /// Volume the snapshot is stored in, parents can be in other volumes.
+    pub volume: Pubkey,
+    pub manifest: ManifestSigned,
+}
+
+/// Single snapshot in a [`ChainReport`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChainLink {
+    pub hash: Hash,
+    /// Volume the snapshot is stored in, parents can be in other volumes.
+    pub volume: Pubkey,
+    pub generation: u64,
+    pub size: u64,
+    pub size_total: u64,
+    /// Problems found with this snapshot, empty if it is valid.
+    pub errors: Vec<String>,
+}
+
+/// What requests an API key may make.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ApiKeyScope {
+    /// Only reading requests (`GET`).
+    Read,
+    /// Reading and modifying requests, but not managing API keys.
+    Write,
+}
+
+impl ApiKeyScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiKeyScope::Read => "read",
+            ApiKeyScope::Write => "write",
+        }
+    }
+}
+
+impl FromStr for ApiKeyScope {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "read" => Ok(ApiKeyScope::Read),
+            "write" => Ok(ApiKeyScope::Write),
+            other => Err(format!("Unknown API key scope {other:?}")),
+        }
+    }
+}
+
+/// Request to issue a new API key.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApiKeyCreate {
+    /// Human-readable name of the key.
+    pub name: Option<String>,
+    /// Restrict the key to this scope, if not set the key has full access to the account.
+    pub scope: Option<ApiKeyScope>,
+    /// Restrict the key to a single volume.
+    pub volume: Option<Pubkey>,
+    /// How long the key is valid for, in seconds, at most ten years. If not set, it does
+    /// not expire.
+    pub ttl: Option<u64>,
+}
+
+/// API key, as listed by the API key endpoints. The key itself is only returned once,
+/// when it is created.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ApiKeyInfo {
+    pub id: i64,
+    pub name: Option<String>,
+    pub scope: Option<ApiKeyScope>,
+    pub volume: Option<Pubkey>,
+    /// Time the key was created, in seconds since the epoch.
+    pub created: u64,
+    /// Time the key expires, in seconds since the epoch.
+    pub expires: Option<u64>,
+}
+
+/// Newly issued API key.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ApiKeyCreated {
+    pub info: ApiKeyInfo,
+    /// Bearer token to authenticate with, it cannot be retrieved again.
+    pub key: String,
+}
+
+/// Token that only allows uploading snapshots to a single volume, for devices that should
+/// not hold broader credentials.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct UploadToken {
+    /// Bearer token to authenticate uploads with.
+    pub token: String,
+    pub volume: Pubkey,
+    /// Time the token expires, in seconds since the epoch.
+    pub expires: u64,
+}
+
+/// URL for fetching the manifest of a snapshot without a bearer token, until it expires.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PresignedUrl {
+    /// Path and query of the URL, relative to the storage service.
+    pub path: String,
+    /// Time the URL expires, in seconds since the epoch.
+    pub expires: u64,
+}
+
+/// Kind of token a request was authenticated with.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TokenType {
+    User,
+    System,
+    ApiKey,
+    /// Upload token minted for a single volume.
+    Upload,
+}
+
+/// Principal that a token maps to, as returned by the whoami endpoint.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Whoami {
+    pub account: Uuid,
+    pub token_type: TokenType,
+    /// Scopes granted to the token, empty if it is not scoped.
+    pub scopes: Vec<String>,
+    /// Time at which the token expires (in seconds since the epoch), only known for JWTs.
+    pub expiry: Option<u64>,
+}
+
+/// Outcome of a single check of the readiness probe.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HealthCheck {
+    pub name: String,
+    pub ok: bool,
+    /// What went wrong, if the check failed.
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+/// Response of the readiness probe of the service.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HealthReport {
+    /// Whether the service can handle requests, only if all checks passed.
+    pub ready: bool,
+    pub checks: Vec<HealthCheck>,
+}
+
+/// Kind of error the service responded with, lets clients tell errors apart without
+/// matching on messages.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorCode {
+    VolumeNotFound,
+    VolumeExists,
+    VolumeDeleted,
+    SnapshotNotFound,
+    ManifestInvalid,
+    /// A different manifest was already uploaded for the generation.
+    ManifestExists,
+    /// The account has too many uploads in flight.
+    QuotaExceeded,
+    /// The volume is locked, or its snapshots are within their write-once period.
+    Locked,
+    /// The snapshot is the parent of other snapshots.
+    HasChildren,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    Conflict,
+    TooLarge,
+    InvalidRequest,
+    Unavailable,
+    Internal,
+    /// Error code introduced by a newer version of the service.
+    #[serde(other)]
+    Unknown,
+}
+
+/// Body of the error responses of the service.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ErrorResponse {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+/// Status of a single manifest in a batch upload.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "state", rename_all = "kebab-case")]
+pub enum SnapshotUploadStatus {
+    /// Snapshot was newly created.
+    Created,
+    /// Identical snapshot already existed.
+    Existing,
+    /// Snapshot was rejected by the server.
+    Failed { message: String },
+    /// Snapshot was not stored because another manifest in the batch failed.
+    Skipped,
+    /// Parent of the snapshot was not uploaded yet, it is stored once the parent is.
+    Pending,
+}
+
+/// Result of uploading a single manifest in a batch upload.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotUploadResult {
+    /// Hash of the manifest.
+    pub hash: Hash,
+    /// What happened to it.
+    #[serde(flatten)]
+    pub status: SnapshotUploadStatus,
+    /// Conditions that did not prevent the upload, but should be looked into.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<Warning>,
+}
+
+/// Where the payload of a snapshot is stored, as far as the storage service knows.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PayloadStatus {
+    /// Payload is stored in the service's blob backend.
+    Stored,
+    /// Payload is on IPFS. The service keeps no copy of it, it stays available for as long
+    /// as it is pinned.
+    Ipfs,
+    /// Payload is neither stored on the service nor on IPFS, it still needs to be uploaded
+    /// with [`snapshot_data_upload`](crate::snapshot_data_upload).
+    Missing,
+}
+
+/// Header carrying the idempotency key of a snapshot upload. Uploads retried with the same
+/// key get the response of the original upload.
+pub const IDEMPOTENCY_HEADER: &str = "Idempotency-Key";
+
+/// Response to uploading a single manifest.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotUploaded {
+    /// Hash of the manifest.
+    pub hash: Hash,
+    /// Whether the identical manifest was stored already, in which case nothing changed.
+    #[serde(default)]
+    pub deduplicated: bool,
+    /// Where the payload of the snapshot is stored, unknown for older versions of the
+    /// service.
+    #[serde(default)]
+    pub payload: Option<PayloadStatus>,
+    #[serde(default)]
+    pub warnings: Vec<Warning>,
+    /// Whether the parent of the snapshot was not uploaded yet, in which case it is only
+    /// stored once the parent is.
+    #[serde(default)]
+    pub pending: bool,
+}
+
+/// Snapshot that was uploaded before its parent. It is validated and stored once the parent
+/// is uploaded.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotPending {
+    /// Hash of the snapshot's manifest.
+    pub hash: Hash,
+    pub generation: u64,
+    /// Hash of the parent the snapshot is waiting for.
+    pub parent: Hash,
+    /// Time the snapshot was uploaded, in seconds since the epoch.
+    pub uploaded: u64,
+}
+
+/// Snapshot to publish with [`snapshot_publish`](crate::snapshot_publish). The sizes and
+/// data URL of its manifest are filled in from the upload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotPublish {
+    /// Machine the snapshot was created on.
+    pub machine: Uuid,
+    /// Path of the snapshot.
+    pub path: PathBuf,
+    /// Parent snapshot, if this is an incremental snapshot.
+    pub parent: Option<ManifestSigned>,
+}
+
+/// Snapshot published with [`snapshot_publish`](crate::snapshot_publish).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotPublished {
+    /// Signed manifest, as uploaded.
+    pub manifest: ManifestSigned,
+    /// IPFS CID of the payload.
+    pub cid: Cid,
+    /// Sizes of the payload, measured during the upload.
+    pub size: UploadSize,
+    pub warnings: Vec<Warning>,
+}
+
+/// Result of deleting an account.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AccountDeleted {
+    /// Volumes that were deleted, including previously deleted ones.
+    pub volumes: Vec<Pubkey>,
+    /// Number of snapshots that were deleted.
+    pub snapshots: u64,
+}
+
+/// Settings of an account.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccountSettings {
+    #[serde(default)]
+    pub alerts: AlertSettings,
+}
+
+/// Thresholds to raise alerts for, checked for every volume of the account.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct AlertSettings {
+    /// Raise an alert when the payloads of a volume add up to more than this many bytes.
+    #[serde(default)]
+    pub volume_size: Option<u64>,
+    /// Raise an alert when a volume has more than this many snapshots.
+    #[serde(default)]
+    pub snapshot_count: Option<u64>,
+    /// Raise an alert (and warn the uploader) when a snapshot is more than this many times
+    /// larger than the recent snapshots of its volume, which can mean that its data was
+    /// encrypted by ransomware. Zero disables the check, when missing the default of the
+    /// service applies.
+    #[serde(default)]
+    pub size_anomaly: Option<u64>,
+    /// URL that alerts are posted to (as JSON) when they are raised.
+    #[serde(default)]
+    pub webhook: Option<Url>,
+}
+
+/// What an alert was raised for.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum AlertKind {
+    /// Total size of the payloads of a volume.
+    VolumeSize,
+    /// Number of snapshots of a volume.
+    SnapshotCount,
+    /// Size of the latest snapshot of a volume, compared to the recent ones.
+    SizeAnomaly,
+}
+
+impl AlertKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertKind::VolumeSize => "volume-size",
+            AlertKind::SnapshotCount => "snapshot-count",
+            AlertKind::SizeAnomaly => "size-anomaly",
+        }
+    }
+}
+
+impl FromStr for AlertKind {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "volume-size" => Ok(AlertKind::VolumeSize),
+            "snapshot-count" => Ok(AlertKind::SnapshotCount),
+            "size-anomaly" => Ok(AlertKind::SizeAnomaly),
+            other => Err(format!("Unknown alert kind {other:?}")),
+        }
+    }
+}
+
+/// Volume exceeding one of the alert thresholds of its account.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Alert {
+    pub volume: Pubkey,
+    pub kind: AlertKind,
+    /// Value when the alert was last evaluated.
+    pub value: u64,
+    pub threshold: u64,
+    /// Time the threshold was first exceeded, in seconds since the epoch.
+    pub since: u64,
+}
+
+/// Access to a volume granted to an account other than its owner.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum VolumeAccess {
+    /// List and fetch snapshots and their payloads.
+    Read,
+    /// Also upload snapshots and their payloads.
+    Write,
+}
+
+impl VolumeAccess {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VolumeAccess::Read => "read",
+            VolumeAccess::Write => "write",
+        }
+    }
+}
+
+impl FromStr for VolumeAccess {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "read" => Ok(VolumeAccess::Read),
+            "write" => Ok(VolumeAccess::Write),
+            other => Err(format!("Unknown volume access {other:?}")),
+        }
+    }
+}
+
+/// Storage class of a volume, which tells the service how quickly its snapshots need to be
+/// restorable, and lets clients estimate restore latency.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum StorageClass {
+    /// Restored frequently, kept readily available.
+    #[default]
+    Hot,
+    /// Restored occasionally, may be slower to restore.
+    Cold,
+    /// Rarely restored, if ever. Manifests are archived without waiting for them to age, and
+    /// snapshots beyond the retention settings are pruned first.
+    Archive,
+}
+
+impl StorageClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StorageClass::Hot => "hot",
+            StorageClass::Cold => "cold",
+            StorageClass::Archive => "archive",
+        }
+    }
+}
+
+impl FromStr for StorageClass {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "hot" => Ok(StorageClass::Hot),
+            "cold" => Ok(StorageClass::Cold),
+            "archive" => Ok(StorageClass::Archive),
+            other => Err(format!("Unknown storage class {other:?}")),
+        }
+    }
+}
+
+/// Grant of access to a volume for another account.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct VolumeGrant {
+    pub account: Uuid,
+    pub access: VolumeAccess,
+}
+
+/// Outcome of a restore drill.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DrillOutcome {
+    /// The snapshot was restored and its data could be read back in full.
+    Success,
+    /// The snapshot could not be restored.
+    Failure,
+}
+
+impl DrillOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DrillOutcome::Success => "success",
+            DrillOutcome::Failure => "failure",
+        }
+    }
+}
+
+impl FromStr for DrillOutcome {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "success" => Ok(DrillOutcome::Success),
+            "failure" => Ok(DrillOutcome::Failure),
+            other => Err(format!("Unknown drill outcome {other:?}")),
+        }
+    }
+}
+
+/// Result of a restore drill, reported by the client that performed it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DrillResult {
+    /// Snapshot that was restored.
+    pub snapshot: Hash,
+    pub outcome: DrillOutcome,
+    /// How long the restore took, in milliseconds.
+    pub duration: u64,
+    /// Details about the restore, such as why it failed.
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+/// Restore drill recorded for a volume.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Drill {
+    pub snapshot: Hash,
+    pub outcome: DrillOutcome,
+    /// How long the restore took, in milliseconds.
+    pub duration: u64,
+    pub message: Option<String>,
+    /// Time the drill was recorded, in seconds since the epoch.
+    pub time: u64,
+}
+
+/// When a volume was last verified to be restorable.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DrillStatus {
+    pub volume: Pubkey,
+    /// Time of the last successful restore drill, in seconds since the epoch. Volumes that
+    /// were never verified have none.
+    pub verified: Option<u64>,
+    /// Snapshot restored by the last successful drill.
+    pub snapshot: Option<Hash>,
+    /// Outcome of the most recent drill, a failure means the volume may no longer be
+    /// restorable even though it was verified before.
+    pub latest: Option<DrillOutcome>,
+}
+
+/// Statistics about all data stored in the service, for operators.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StorageStats {
+    /// Number of volumes, not counting deleted ones.
+    pub volumes: u64,
+    /// Number of accounts owning volumes.
+    pub accounts: u64,
+    /// Number of stored snapshots.
+    pub snapshots: u64,
+    /// Total size of the payloads of stored snapshots, in bytes.
+    pub bytes: u64,
+    /// Snapshots created per day, oldest first.
+    pub daily: Vec<DailyStats>,
+}
+
+/// Snapshots created on a single day (in UTC).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DailyStats {
+    /// Start of the day, in seconds since the epoch.
+    pub day: u64,
+    /// Number of snapshots created on this day.
+    pub snapshots: u64,
+    /// Total size of their payloads, in bytes.
+    pub bytes: u64,
+}
+
+/// Snapshot found by searching for labels.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LabelMatch {
+    pub volume: Pubkey,
+    pub hash: Hash,
+    pub generation: u64,
+    /// All labels of the snapshot.
+    pub labels: BTreeMap<String, String>,
+}
+
+/// Snapshot found by searching for the machine it was created on.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MachineSnapshot {
+    pub volume: Pubkey,
+    pub hash: Hash,
+    pub generation: u64,
+    /// Time the snapshot was created, in seconds since the epoch.
+    pub creation: u64,
+}
+
+/// Snapshot referencing a payload.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotReference {
+    pub volume: Pubkey,
+    pub hash: Hash,
+    pub generation: u64,
+}
+
+/// Payload along with the snapshots of the account that reference it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateData {
+    pub data: Url,
+    pub snapshots: Vec<SnapshotReference>,
+}
+
+/// Header carrying warnings (as a JSON array) on responses that are not JSON. Older versions
+/// of the service used it when redirecting after manifest uploads.
+pub const WARNINGS_HEADER: &str = "X-Storage-Warnings";
+
+/// Condition that did not cause a request to fail, but that the user should know about.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "code", rename_all = "kebab-case")]
+pub enum Warning {
+    /// Generations between the snapshot and its parent are missing.
+    GenerationGap { parent: u64, generation: u64 },
+    /// Snapshot was created in the future, the clock of the machine is likely off.
+    ClockSkew { creation: u64, server: u64 },
+    /// Snapshot is much larger than the recent snapshots of the volume (their median size
+    /// is the baseline), its data may have been encrypted by ransomware.
+    SizeAnomaly {
+        size: u64,
+        baseline: u64,
+        factor: u64,
+    },
+    /// Warning introduced by a newer version of the service.
+    #[serde(other)]
+    Unknown,
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::GenerationGap { parent, generation } => write!(
+                f,
+                "snapshot has generation {generation} but its parent has generation {parent}"
+            ),
+            Warning::ClockSkew { creation, server } => write!(
+                f,
+                "snapshot was created at {creation}, {}s ahead of the server",
+                creation.saturating_sub(*server)
+            ),
+            Warning::SizeAnomaly {
+                size,
+                baseline,
+                factor,
+            } => write!(
+                f,
+                "snapshot has {size} bytes, more than {factor} times the {baseline} bytes of recent snapshots"
+            ),
+            Warning::Unknown => write!(f, "unknown warning"),
+        }
+    }
+}
+
+/// Features supported by the storage service.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Manifest versions the service accepts.
+    pub manifest_versions: Vec<u8>,
+    /// Identifiers of the manifest signature algorithms the service verifies, see
+    /// [`SignatureAlgorithm::id`](crate::SignatureAlgorithm::id). Services that predate
+    /// this only verify Ed25519.
+    #[serde(default)]
+    pub signature_algorithms: Vec<u8>,
+}
+
+impl Capabilities {
+    /// Newest manifest version supported by both the service and this library, if any.
+    pub fn manifest_version(&self) -> Option<u8> {
+        self.manifest_versions
+            .iter()
+            .filter(|version| crate::MANIFEST_VERSIONS.contains(version))
+            .max()
+            .copied()
+    }
+}
+
+/// Activity on one of the volumes of an account, pushed by the events endpoint.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum AccountEvent {
+    /// Volume was created.
+    VolumeCreated { volume: Pubkey },
+    /// Volume's properties were edited.
+    VolumeEdited { volume: Pubkey },
+    /// Volume was deleted.
+    VolumeDeleted { volume: Pubkey },
+    /// Deleted volume was restored.
+    VolumeRestored { volume: Pubkey },
+    /// Snapshot was uploaded.
+    SnapshotCreated {
+        volume: Pubkey,
+        snapshot: Hash,
+        generation: u64,
+    },
+    /// Stored payload of a snapshot no longer matches the digest recorded on upload.
+    PayloadCorrupted { volume: Pubkey, snapshot: Hash },
+    /// Volume exceeded one of the alert thresholds of the account.
+    AlertRaised { alert: Alert },
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct VolumeInfo {
     pub writer: Option<Uuid>,
     pub account: Uuid,
+    /// Maximum number of snapshots to keep, older ones are pruned.
+    #[serde(default)]
+    pub retain_count: Option<u64>,
+    /// Maximum age of snapshots to keep in seconds, older ones are pruned.
+    #[serde(default)]
+    pub retain_age: Option<u64>,
+    /// Number of snapshots stored for the volume.
+    #[serde(default)]
+    pub snapshot_count: u64,
+    /// Generation of the newest snapshot, if there are any.
+    #[serde(default)]
+    pub latest_generation: Option<u64>,
+    /// Total size of the payloads of the volume's snapshots, in bytes.
+    #[serde(default)]
+    pub bytes_stored: u64,
+    /// Period after their upload during which snapshots cannot be deleted, in seconds.
+    #[serde(default)]
+    pub worm_period: Option<u64>,
+    /// Time until which snapshots of the volume cannot be deleted (and neither can the
+    /// volume), in seconds since the epoch.
+    #[serde(default)]
+    pub immutable_until: Option<u64>,
+    /// Storage class of the volume.
+    #[serde(default)]
+    pub storage_class: StorageClass,
+    /// Number of snapshots waiting for their parent to be uploaded.
+    #[serde(default)]
+    pub snapshots_pending: u64,
+    /// Dilithium3 public key pinned for the volume, hex-encoded. Manifests signed with
+    /// [`SignatureAlgorithm::Ed25519Dilithium3`](crate::SignatureAlgorithm) are verified
+    /// against it.
+    #[serde(default)]
+    pub pq_pubkey: Option<String>,
+}
+
+impl VolumeInfo {
+    /// Decoded Dilithium3 public key pinned for the volume, to verify hybrid signatures
+    /// with. Keys that cannot be decoded are treated as missing, so that hybrid signatures
+    /// fail to verify.
+    pub fn pq_pubkey_bytes(&self) -> Option<Vec<u8>> {
+        let key = self.pq_pubkey.as_deref()?;
+        #[cfg(feature = "hex")]
+        {
+            hex::decode(key).ok()
+        }
+        #[cfg(not(feature = "hex"))]
+        {
+            let _ = key;
+            None
+        }
+    }
+}
+
+/// Version of volume archives produced by this library.
+pub const VOLUME_ARCHIVE_VERSION: u8 = 1;
+
+/// Portable archive of the metadata of a volume: its attributes and the signed manifests
+/// of all of its snapshots. Used to migrate volumes between deployments.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct VolumeArchive {
+    pub version: u8,
+    pub volume: Pubkey,
+    pub writer: Option<Uuid>,
+    pub locked: bool,
+    pub retain_count: Option<u64>,
+    pub retain_age: Option<u64>,
+    /// Dilithium3 public key pinned for the volume, hex-encoded.
+    #[serde(default)]
+    pub pq_pubkey: Option<String>,
+    /// Signed manifests, in the order they were stored so that parents come first.
+    pub snapshots: Vec<ManifestSigned>,
+}
+
+/// Replication of a volume's snapshots to a peer storage service.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ReplicationStatus {
+    pub peer: Url,
+    /// Number of snapshots the peer accepted.
+    pub replicated: u64,
+    /// Number of snapshots waiting to be pushed to the peer.
+    pub pending: u64,
+    /// Time the peer last accepted a snapshot, in seconds since the epoch.
+    pub last_replicated: Option<u64>,
+    /// Error that is holding back replication, if any.
+    pub error: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
@@ -186,6 +1093,27 @@ pub struct VolumeEdit {
     /// Set this volume locked, this prevents pushing of new snapshots.
     #[serde(default)]
     pub lock: Option<bool>,
+    /// Maximum number of snapshots to keep. When missing, it doesn't change anything, when
+    /// `None`, the limit is removed.
+    #[serde(default)]
+    pub retain_count: Field<u64>,
+    /// Maximum age of snapshots to keep, in seconds. When missing, it doesn't change
+    /// anything, when `None`, the limit is removed.
+    #[serde(default)]
+    pub retain_age: Field<u64>,
+    /// Period after their upload during which snapshots cannot be deleted, in seconds, by
+    /// anyone. Once set, it can only be extended. When missing, it doesn't change anything.
+    #[serde(default)]
+    pub worm_period: Field<u64>,
+    /// Storage class of the volume, applies to snapshots uploaded from then on. When missing,
+    /// it doesn't change anything.
+    #[serde(default)]
+    pub storage_class: Option<StorageClass>,
+    /// Pin a Dilithium3 public key, hex-encoded, for the volume. From then on, manifests must
+    /// be signed with it alongside the volume key, see `HybridSigner`.
+    /// It can only be pinned once. When missing, it doesn't change anything.
+    #[serde(default)]
+    pub pq_pubkey: Option<String>,
 }
 
 #[cfg(test)]
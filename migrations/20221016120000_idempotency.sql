@@ -0,0 +1,12 @@
+-- Idempotency keys supplied with snapshot uploads, along with the hash of the
+-- manifest that was uploaded with them. Used to replay the response when a
+-- client retries an upload with the same key.
+CREATE TABLE storage_idempotency(
+    volume_id INTEGER NOT NULL REFERENCES storage_volume(volume_id) ON DELETE CASCADE,
+    idempotency_key TEXT NOT NULL,
+    -- hash of the manifest uploaded with this key
+    snapshot_hash BLOB NOT NULL,
+    -- unix timestamp of when the key was first used
+    idempotency_created INTEGER NOT NULL,
+    PRIMARY KEY (volume_id, idempotency_key)
+);
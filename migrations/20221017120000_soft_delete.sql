@@ -0,0 +1,4 @@
+-- Time (in seconds since the epoch) at which the volume was deleted. When not
+-- null, the volume is hidden and can be restored until it is purged.
+ALTER TABLE storage_volume
+    ADD COLUMN volume_deleted_at INTEGER;
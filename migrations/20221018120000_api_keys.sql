@@ -0,0 +1,20 @@
+-- API keys issued by accounts. Only the SHA-256 hash of the key is stored.
+CREATE TABLE storage_api_key(
+    api_key_id INTEGER PRIMARY KEY NOT NULL,
+    -- account that issued this key, requests made with it act as this account
+    account_id UUID NOT NULL,
+    -- hash of the key
+    api_key_hash BLOB NOT NULL UNIQUE,
+    -- human-readable name
+    api_key_name TEXT,
+    -- scope of the key (read or write), null for full access
+    api_key_scope TEXT,
+    -- volume this key is restricted to, if any
+    volume_pubkey BLOB,
+    -- time the key was created, in seconds since the epoch
+    api_key_created INTEGER NOT NULL,
+    -- time the key expires, in seconds since the epoch
+    api_key_expires INTEGER
+);
+
+CREATE INDEX storage_api_key_account ON storage_api_key(account_id);
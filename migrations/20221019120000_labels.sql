@@ -0,0 +1,20 @@
+-- Labels that are attached to every snapshot uploaded to volumes of an account.
+CREATE TABLE storage_account_label(
+    -- account these labels are configured for
+    account_id UUID NOT NULL,
+    label_key TEXT NOT NULL,
+    label_value TEXT NOT NULL,
+    PRIMARY KEY (account_id, label_key)
+);
+
+-- Labels of snapshots. These are kept outside of the signed manifest, so that the
+-- service can attach them.
+CREATE TABLE storage_snapshot_label(
+    -- snapshot this label is attached to
+    snapshot_id INTEGER NOT NULL REFERENCES storage_snapshot(snapshot_id) ON DELETE CASCADE,
+    label_key TEXT NOT NULL,
+    label_value TEXT NOT NULL,
+    PRIMARY KEY (snapshot_id, label_key)
+);
+
+CREATE INDEX storage_snapshot_label_key_value ON storage_snapshot_label(label_key, label_value);
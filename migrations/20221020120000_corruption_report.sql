@@ -0,0 +1,22 @@
+-- Digests of payloads stored in the blob backend, recorded when they are uploaded.
+CREATE TABLE storage_payload(
+    snapshot_id INTEGER PRIMARY KEY NOT NULL REFERENCES storage_snapshot(snapshot_id) ON DELETE CASCADE,
+    payload_size INTEGER NOT NULL,
+    payload_sha256 BLOB NOT NULL,
+    -- when the payload was last verified against its digest
+    payload_verified INTEGER
+);
+
+-- Payloads whose contents no longer match the digest recorded on upload.
+CREATE TABLE storage_corruption_report(
+    report_id INTEGER PRIMARY KEY NOT NULL,
+    snapshot_id INTEGER NOT NULL REFERENCES storage_snapshot(snapshot_id) ON DELETE CASCADE,
+    report_time INTEGER NOT NULL,
+    expected_size INTEGER NOT NULL,
+    expected_sha256 BLOB NOT NULL,
+    -- size and digest of the stored payload, null if it is missing
+    actual_size INTEGER,
+    actual_sha256 BLOB
+);
+
+CREATE INDEX storage_corruption_report_snapshot ON storage_corruption_report(snapshot_id);
@@ -0,0 +1,5 @@
+-- Payload reference (data URL) of the manifest, so that snapshots sharing a payload can
+-- be found. Filled in on startup for snapshots stored before this was added.
+ALTER TABLE storage_snapshot ADD COLUMN snapshot_data TEXT;
+
+CREATE INDEX storage_snapshot_data ON storage_snapshot(snapshot_data);
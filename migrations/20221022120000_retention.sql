@@ -0,0 +1,5 @@
+-- Retention settings of volumes, snapshots beyond these are pruned by the service.
+-- maximum number of snapshots to keep
+ALTER TABLE storage_volume ADD COLUMN volume_retain_count INTEGER;
+-- maximum age of snapshots to keep, in seconds
+ALTER TABLE storage_volume ADD COLUMN volume_retain_age INTEGER;
@@ -0,0 +1,10 @@
+-- Nonces of signed requests, remembered while their timestamp is within the accepted
+-- window so that requests cannot be replayed.
+CREATE TABLE storage_request_nonce(
+    -- volume whose key signed the request
+    volume_pubkey BLOB NOT NULL,
+    request_nonce TEXT NOT NULL,
+    -- timestamp of the request, in seconds since the epoch
+    request_time INTEGER NOT NULL,
+    PRIMARY KEY (volume_pubkey, request_nonce)
+);
@@ -0,0 +1,8 @@
+-- Challenges issued for deleting volumes, which have to be signed with the volume key.
+CREATE TABLE storage_volume_challenge(
+    volume_id INTEGER NOT NULL REFERENCES storage_volume(volume_id) ON DELETE CASCADE,
+    challenge_value TEXT NOT NULL,
+    -- time the challenge expires, in seconds since the epoch
+    challenge_expires INTEGER NOT NULL,
+    PRIMARY KEY (volume_id, challenge_value)
+);
@@ -0,0 +1,15 @@
+-- Snapshots queued for replication to peer storage services, and the outcome of pushing
+-- them.
+CREATE TABLE storage_replication(
+    snapshot_id INTEGER NOT NULL REFERENCES storage_snapshot(snapshot_id) ON DELETE CASCADE,
+    -- API of the peer
+    replication_peer TEXT NOT NULL,
+    -- time the peer accepted the manifest, in seconds since the epoch
+    replication_time INTEGER,
+    replication_attempts INTEGER NOT NULL DEFAULT 0,
+    -- error of the last failed attempt
+    replication_error TEXT,
+    PRIMARY KEY (snapshot_id, replication_peer)
+);
+
+CREATE INDEX storage_replication_pending ON storage_replication(replication_time);
@@ -0,0 +1,27 @@
+-- Payloads of deleted snapshots that still have to be released (unpinned from IPFS and
+-- deleted from the blob backend). Processed by the purge task.
+CREATE TABLE storage_release(
+    release_id INTEGER PRIMARY KEY NOT NULL,
+    -- data URL of the manifest, unpinned once no snapshot references it anymore
+    release_data TEXT NOT NULL,
+    -- key of the payload in the blob backend
+    release_key TEXT NOT NULL,
+    -- time the release was queued, in seconds since the epoch
+    release_time INTEGER NOT NULL
+);
+
+-- Record of administrative actions.
+CREATE TABLE storage_audit(
+    audit_id INTEGER PRIMARY KEY NOT NULL,
+    audit_time INTEGER NOT NULL,
+    -- account of the principal that performed the action
+    audit_actor UUID NOT NULL,
+    -- account the action was performed on
+    account_id UUID NOT NULL,
+    -- action that was performed, such as `account-delete`
+    audit_action TEXT NOT NULL,
+    -- details of the action, such as what was removed
+    audit_detail TEXT
+);
+
+CREATE INDEX storage_audit_account ON storage_audit(account_id);
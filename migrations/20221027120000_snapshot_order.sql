@@ -0,0 +1,11 @@
+-- Creation time and size from the manifest, so that listings can be ordered by them.
+-- Filled in on startup for snapshots stored before this was added.
+ALTER TABLE storage_snapshot ADD COLUMN snapshot_creation INTEGER;
+ALTER TABLE storage_snapshot ADD COLUMN snapshot_size INTEGER;
+
+CREATE INDEX storage_snapshot_order_generation
+    ON storage_snapshot(volume_id, snapshot_generation, snapshot_hash);
+CREATE INDEX storage_snapshot_order_creation
+    ON storage_snapshot(volume_id, snapshot_creation, snapshot_hash);
+CREATE INDEX storage_snapshot_order_size
+    ON storage_snapshot(volume_id, snapshot_size, snapshot_hash);
@@ -0,0 +1,6 @@
+-- Manifests of old snapshots, compressed and moved out of the snapshot table. The
+-- manifest column of archived snapshots is left empty.
+CREATE TABLE storage_snapshot_archive(
+    snapshot_id INTEGER PRIMARY KEY NOT NULL REFERENCES storage_snapshot(snapshot_id) ON DELETE CASCADE,
+    archive_data BLOB NOT NULL
+);
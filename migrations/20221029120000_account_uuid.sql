@@ -0,0 +1,9 @@
+-- Accounts as binary UUIDs, for tables that are written at high volume. These are half
+-- the size of the textual form, and indexes on them stay local for time-ordered (version
+-- 7) UUIDs. Filled in on startup for rows stored before this was added, the textual
+-- columns are kept for compatibility.
+ALTER TABLE storage_audit ADD COLUMN account_uuid BLOB;
+ALTER TABLE storage_account_label ADD COLUMN account_uuid BLOB;
+
+CREATE INDEX storage_audit_account_uuid ON storage_audit(account_uuid, audit_time);
+CREATE INDEX storage_account_label_account_uuid ON storage_account_label(account_uuid);
@@ -0,0 +1,6 @@
+-- Machine the snapshot was created on, from the manifest, so that snapshots can be found
+-- by machine across volumes. Filled in on startup for snapshots stored before this was
+-- added.
+ALTER TABLE storage_snapshot ADD COLUMN snapshot_machine TEXT;
+
+CREATE INDEX storage_snapshot_machine ON storage_snapshot(snapshot_machine, snapshot_creation);
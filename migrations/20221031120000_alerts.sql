@@ -0,0 +1,23 @@
+-- Alert thresholds configured by accounts, checked for every volume of the account.
+CREATE TABLE storage_alert_setting(
+    account_id UUID PRIMARY KEY NOT NULL,
+    -- total size of the payloads of a volume, in bytes
+    alert_volume_size INTEGER,
+    -- number of snapshots of a volume
+    alert_snapshot_count INTEGER,
+    -- URL raised alerts are posted to
+    alert_webhook TEXT
+);
+
+-- Thresholds currently exceeded by volumes. Rows are removed once the volume is back
+-- under the threshold.
+CREATE TABLE storage_alert(
+    volume_id INTEGER NOT NULL REFERENCES storage_volume(volume_id) ON DELETE CASCADE,
+    alert_kind TEXT NOT NULL,
+    -- value and threshold when the alert was last evaluated
+    alert_value INTEGER NOT NULL,
+    alert_threshold INTEGER NOT NULL,
+    -- time the threshold was first exceeded, in seconds since the epoch
+    alert_since INTEGER NOT NULL,
+    PRIMARY KEY (volume_id, alert_kind)
+);
@@ -0,0 +1,5 @@
+-- Write-once period of volumes, in seconds: snapshots cannot be deleted until this long
+-- after they were uploaded. Once set, it can only be extended.
+ALTER TABLE storage_volume ADD COLUMN volume_worm_period INTEGER;
+-- Time (in seconds since the epoch) at which the snapshot was uploaded to this service.
+ALTER TABLE storage_snapshot ADD COLUMN snapshot_uploaded INTEGER;
@@ -0,0 +1,3 @@
+-- Snapshots more than this many times larger than the recent ones of their volume raise
+-- an alert, zero disables the check. The default of the service applies when not set.
+ALTER TABLE storage_alert_setting ADD COLUMN alert_size_anomaly INTEGER;
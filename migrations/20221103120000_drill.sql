@@ -0,0 +1,17 @@
+-- Results of restore drills, reported by clients that restored a snapshot to check that
+-- the volume can actually be recovered. Snapshots are referenced by hash, so that the
+-- record survives the snapshot being pruned.
+CREATE TABLE storage_drill(
+    drill_id INTEGER PRIMARY KEY NOT NULL,
+    volume_id INTEGER NOT NULL REFERENCES storage_volume(volume_id) ON DELETE CASCADE,
+    drill_snapshot BLOB NOT NULL,
+    -- either 'success' or 'failure'
+    drill_outcome TEXT NOT NULL,
+    -- how long the restore took, in milliseconds
+    drill_duration INTEGER NOT NULL,
+    drill_message TEXT,
+    -- time the result was recorded, in seconds since the epoch
+    drill_time INTEGER NOT NULL
+);
+
+CREATE INDEX storage_drill_volume ON storage_drill(volume_id, drill_time);
@@ -0,0 +1,10 @@
+-- Accounts other than the owner that were granted access to a volume.
+CREATE TABLE storage_volume_acl(
+    volume_id INTEGER NOT NULL REFERENCES storage_volume(volume_id) ON DELETE CASCADE,
+    account_id UUID NOT NULL,
+    -- either 'read' or 'write'
+    acl_access TEXT NOT NULL,
+    PRIMARY KEY (volume_id, account_id)
+);
+
+CREATE INDEX storage_volume_acl_account ON storage_volume_acl(account_id);
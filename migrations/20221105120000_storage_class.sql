@@ -0,0 +1,4 @@
+-- Storage class of volumes ('hot', 'cold' or 'archive'), hot when not set.
+ALTER TABLE storage_volume ADD COLUMN volume_storage_class TEXT;
+-- Storage class of the volume at the time the snapshot was uploaded.
+ALTER TABLE storage_snapshot ADD COLUMN snapshot_storage_class TEXT;
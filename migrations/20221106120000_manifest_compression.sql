@@ -0,0 +1,2 @@
+-- Compression of snapshot_manifest, either 'zstd' or NULL when it is stored uncompressed.
+ALTER TABLE storage_snapshot ADD COLUMN snapshot_compression TEXT;
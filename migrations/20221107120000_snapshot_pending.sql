@@ -0,0 +1,15 @@
+-- Snapshots uploaded before their parent, which are stored once the parent is uploaded.
+CREATE TABLE storage_snapshot_pending(
+    pending_id INTEGER PRIMARY KEY NOT NULL,
+    volume_id INTEGER NOT NULL REFERENCES storage_volume(volume_id) ON DELETE CASCADE,
+    pending_hash BLOB NOT NULL,
+    -- hash of the parent the snapshot is waiting for, which may be in another volume
+    pending_parent BLOB NOT NULL,
+    pending_generation INTEGER NOT NULL,
+    -- signed manifest, as uploaded
+    pending_manifest BLOB NOT NULL,
+    pending_uploaded INTEGER NOT NULL,
+    UNIQUE (volume_id, pending_hash)
+);
+
+CREATE INDEX storage_snapshot_pending_parent ON storage_snapshot_pending(pending_parent);
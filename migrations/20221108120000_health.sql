@@ -0,0 +1,5 @@
+-- Written to (and rolled back) by the startup self-test, to check that the database is
+-- writable. It never holds any rows.
+CREATE TABLE storage_health(
+    health_time INTEGER NOT NULL
+);
@@ -0,0 +1,2 @@
+-- Dilithium3 public key pinned for the volume, hybrid manifest signatures must carry it.
+ALTER TABLE storage_volume ADD COLUMN volume_pq_pubkey BLOB;
@@ -0,0 +1,259 @@
+use crate::snapshot::{Snapshot, SnapshotError};
+use crate::volume::{Volume, VolumeError};
+use fractal_storage_client::{AccountDeleted, Pubkey};
+use log::warn;
+use sqlx::{query, AnyConnection, Row};
+use thiserror::Error;
+use uuid::Uuid;
+
+#[derive(Error, Debug)]
+pub enum AccountError {
+    #[error("Error talking to database: {0:}")]
+    Database(#[from] sqlx::Error),
+    #[error("Error in volume: {0:}")]
+    Volume(#[from] VolumeError),
+    #[error("Error in snapshots: {0:}")]
+    Snapshot(#[from] SnapshotError),
+    #[error("Volume {0:} has snapshots within its write-once period")]
+    Immutable(Pubkey),
+}
+
+/// Tables that record accounts as binary UUIDs as well as in their textual form.
+const ACCOUNT_UUID_TABLES: &[&str] = &["storage_audit", "storage_account_label"];
+
+/// Record the binary form of accounts in rows stored before it was recorded, returning how
+/// many rows were updated.
+pub async fn uuid_backfill(conn: &mut AnyConnection) -> Result<usize, AccountError> {
+    let mut count = 0;
+    for table in ACCOUNT_UUID_TABLES {
+        let statement =
+            format!("SELECT DISTINCT account_id FROM {table} WHERE account_uuid IS NULL");
+        let accounts = query(&statement).fetch_all(&mut *conn).await?;
+        let statement = format!(
+            "UPDATE {table} SET account_uuid = ? WHERE account_id = ? AND account_uuid IS NULL"
+        );
+        for row in &accounts {
+            let account: String = row.try_get("account_id")?;
+            let uuid = match Uuid::parse_str(&account) {
+                Ok(uuid) => uuid,
+                Err(_) => {
+                    warn!("Invalid account {:?} in {}", account, table);
+                    continue;
+                }
+            };
+            let result = query(&statement)
+                .bind(uuid.as_bytes().as_slice())
+                .bind(account.as_str())
+                .execute(&mut *conn)
+                .await?;
+            count += result.rows_affected() as usize;
+        }
+    }
+    Ok(count)
+}
+
+/// Record an administrative action in the audit log.
+pub async fn audit(
+    conn: &mut AnyConnection,
+    actor: &Uuid,
+    account: &Uuid,
+    action: &str,
+    detail: &str,
+    time: u64,
+) -> Result<(), sqlx::Error> {
+    query(
+        "INSERT INTO storage_audit(
+            audit_time, audit_actor, account_id, account_uuid, audit_action, audit_detail)
+        VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(time as i64)
+    .bind(actor.to_string())
+    .bind(account.to_string())
+    .bind(account.as_bytes().as_slice())
+    .bind(action)
+    .bind(detail)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Delete an account: all of its volumes (including deleted ones) with their snapshots, its
+/// API keys and its labels. The payloads of the snapshots are queued to be released by the
+/// purge task, and the deletion is recorded in the audit log. Fails if any volume has
+/// snapshots within its write-once period. This should be run in a transaction, so that
+/// either everything or nothing is deleted.
+pub async fn delete(
+    conn: &mut AnyConnection,
+    account: &Uuid,
+    actor: &Uuid,
+    time: u64,
+) -> Result<AccountDeleted, AccountError> {
+    let volumes = Volume::list_account(conn, account).await?;
+    for volume in &volumes {
+        if volume.immutable(conn, time).await? {
+            return Err(AccountError::Immutable(*volume.pubkey()));
+        }
+    }
+    let mut snapshots = 0;
+    for volume in &volumes {
+        for snapshot in Snapshot::list(conn, &volume.volume(), None, false).await? {
+            query(
+                "INSERT INTO storage_release(release_data, release_key, release_time)
+                VALUES (?, ?, ?)",
+            )
+            .bind(snapshot.manifest().data.as_str())
+            .bind(snapshot.hash().to_hex())
+            .bind(time as i64)
+            .execute(&mut *conn)
+            .await?;
+            snapshots += 1;
+        }
+    }
+
+    // snapshots and everything attached to them are removed by cascading deletes
+    query("DELETE FROM storage_volume WHERE account_id = ?")
+        .bind(account.to_string())
+        .execute(&mut *conn)
+        .await?;
+    query("DELETE FROM storage_api_key WHERE account_id = ?")
+        .bind(account.to_string())
+        .execute(&mut *conn)
+        .await?;
+    query("DELETE FROM storage_account_label WHERE account_uuid = ?")
+        .bind(account.as_bytes().as_slice())
+        .execute(&mut *conn)
+        .await?;
+    query("DELETE FROM storage_alert_setting WHERE account_id = ?")
+        .bind(account.to_string())
+        .execute(&mut *conn)
+        .await?;
+    query("DELETE FROM storage_volume_acl WHERE account_id = ?")
+        .bind(account.to_string())
+        .execute(&mut *conn)
+        .await?;
+
+    let detail = format!("{} volumes, {} snapshots", volumes.len(), snapshots);
+    audit(conn, actor, account, "account-delete", &detail, time).await?;
+    Ok(AccountDeleted {
+        volumes: volumes.iter().map(|volume| *volume.pubkey()).collect(),
+        snapshots,
+    })
+}
+
+#[tokio::test]
+async fn test_account_delete() {
+    use crate::snapshot::MINIMUM_SNAPSHOT_SIZE;
+    use fractal_storage_client::{Manifest, Privkey};
+    use sqlx::AnyPool;
+
+    let pool = AnyPool::connect("sqlite://:memory:").await.unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+    let mut conn = pool.acquire().await.unwrap();
+    let account = Uuid::new_v4();
+    let other = Uuid::new_v4();
+
+    let mut volumes = vec![];
+    for owner in [&account, &account, &other] {
+        let privkey = Privkey::generate();
+        let volume = Volume::create(&mut conn, &privkey.pubkey(), owner)
+            .await
+            .unwrap();
+        let manifest = Manifest {
+            creation: 0,
+            data: "ipfs://asd99a0s8098da0sd98".parse().unwrap(),
+            generation: 0,
+            parent: None,
+            size: MINIMUM_SNAPSHOT_SIZE,
+            size_total: MINIMUM_SNAPSHOT_SIZE,
+            machine: Default::default(),
+            path: std::path::PathBuf::from("abc"),
+        }
+        .sign(&privkey);
+        Snapshot::create(
+            &mut conn,
+            &volume,
+            &manifest.raw,
+            &manifest.signature,
+            &manifest.hash(),
+            None,
+            0,
+            &manifest.manifest.data,
+        )
+        .await
+        .unwrap();
+        volumes.push(privkey.pubkey());
+    }
+
+    // deleted volumes are removed as well
+    let deleted = Volume::lookup(&mut conn, &volumes[1])
+        .await
+        .unwrap()
+        .unwrap();
+    deleted.delete(&mut conn, 500).await.unwrap();
+
+    let actor = Uuid::new_v4();
+    let deleted = delete(&mut conn, &account, &actor, 1000).await.unwrap();
+    assert_eq!(deleted.volumes, volumes[0..2].to_vec());
+    assert_eq!(deleted.snapshots, 2);
+
+    assert!(Volume::lookup(&mut conn, &volumes[0])
+        .await
+        .unwrap()
+        .is_none());
+    assert!(Volume::lookup_deleted(&mut conn, &volumes[1])
+        .await
+        .unwrap()
+        .is_none());
+
+    // volumes of other accounts are untouched
+    assert!(Volume::lookup(&mut conn, &volumes[2])
+        .await
+        .unwrap()
+        .is_some());
+    let row = query(
+        "SELECT
+            (SELECT COUNT(*) FROM storage_snapshot) AS snapshots,
+            (SELECT COUNT(*) FROM storage_release) AS releases",
+    )
+    .fetch_one(&mut conn)
+    .await
+    .unwrap();
+    assert_eq!(row.get::<i64, _>("snapshots"), 1);
+    assert_eq!(row.get::<i64, _>("releases"), 2);
+    let audit = query("SELECT * FROM storage_audit")
+        .fetch_one(&mut conn)
+        .await
+        .unwrap();
+    assert_eq!(audit.get::<String, _>("audit_action"), "account-delete");
+    assert_eq!(audit.get::<String, _>("audit_actor"), actor.to_string());
+}
+
+#[tokio::test]
+async fn test_uuid_backfill() {
+    use crate::label;
+    use sqlx::AnyPool;
+
+    let pool = AnyPool::connect("sqlite://:memory:").await.unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+    let mut conn = pool.acquire().await.unwrap();
+    let account = Uuid::new_v4();
+
+    // labels stored before binary accounts were recorded
+    query(
+        "INSERT INTO storage_account_label(account_id, label_key, label_value)
+        VALUES (?, 'env', 'prod')",
+    )
+    .bind(account.to_string())
+    .execute(&mut conn)
+    .await
+    .unwrap();
+    assert!(label::account_labels(&mut conn, &account)
+        .await
+        .unwrap()
+        .is_empty());
+
+    assert_eq!(uuid_backfill(&mut conn).await.unwrap(), 1);
+    assert_eq!(uuid_backfill(&mut conn).await.unwrap(), 0);
+    let labels = label::account_labels(&mut conn, &account).await.unwrap();
+    assert_eq!(labels.get("env").map(String::as_str), Some("prod"));
+}
@@ -0,0 +1,151 @@
+use crate::volume::VolumeData;
+use fractal_storage_client::{VolumeAccess, VolumeGrant};
+use sqlx::{query, AnyConnection, Row};
+use std::str::FromStr;
+use uuid::Uuid;
+
+#[derive(thiserror::Error, Debug)]
+pub enum AclError {
+    #[error("Error talking to database: {0:}")]
+    Database(#[from] sqlx::Error),
+    #[error("Invalid account in grant: {0:}")]
+    InvalidAccount(#[from] uuid::Error),
+}
+
+/// Access a route requires to a volume. Other accounts can be granted read or write
+/// access, anything else is reserved to the owner of the volume.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Access {
+    Read,
+    Write,
+    Owner,
+}
+
+impl From<VolumeAccess> for Access {
+    fn from(access: VolumeAccess) -> Self {
+        match access {
+            VolumeAccess::Read => Access::Read,
+            VolumeAccess::Write => Access::Write,
+        }
+    }
+}
+
+/// Access to the volume granted to the account, if any.
+pub async fn granted(
+    conn: &mut AnyConnection,
+    volume: &VolumeData,
+    account: &Uuid,
+) -> Result<Option<VolumeAccess>, AclError> {
+    let row =
+        query("SELECT acl_access FROM storage_volume_acl WHERE volume_id = ? AND account_id = ?")
+            .bind(volume.id())
+            .bind(account.to_string())
+            .fetch_optional(conn)
+            .await?;
+    let access = match row {
+        Some(row) => row.try_get::<String, _>("acl_access")?,
+        None => return Ok(None),
+    };
+    // access levels this version does not know about grant nothing
+    Ok(VolumeAccess::from_str(&access).ok())
+}
+
+/// Grants of the volume, ordered by account.
+pub async fn list(
+    conn: &mut AnyConnection,
+    volume: &VolumeData,
+) -> Result<Vec<VolumeGrant>, AclError> {
+    let rows = query(
+        "SELECT account_id, acl_access FROM storage_volume_acl WHERE volume_id = ?
+        ORDER BY account_id",
+    )
+    .bind(volume.id())
+    .fetch_all(conn)
+    .await?;
+    let mut grants = vec![];
+    for row in &rows {
+        let account: String = row.try_get("account_id")?;
+        let access: String = row.try_get("acl_access")?;
+        if let Ok(access) = VolumeAccess::from_str(&access) {
+            grants.push(VolumeGrant {
+                account: Uuid::parse_str(&account)?,
+                access,
+            });
+        }
+    }
+    Ok(grants)
+}
+
+/// Grant an account access to the volume, replacing any previous grant.
+pub async fn grant(
+    conn: &mut AnyConnection,
+    volume: &VolumeData,
+    grant: &VolumeGrant,
+) -> Result<(), AclError> {
+    query("DELETE FROM storage_volume_acl WHERE volume_id = ? AND account_id = ?")
+        .bind(volume.id())
+        .bind(grant.account.to_string())
+        .execute(&mut *conn)
+        .await?;
+    query("INSERT INTO storage_volume_acl(volume_id, account_id, acl_access) VALUES (?, ?, ?)")
+        .bind(volume.id())
+        .bind(grant.account.to_string())
+        .bind(grant.access.as_str())
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+/// Revoke the access of an account to the volume. Returns false if it had none.
+pub async fn revoke(
+    conn: &mut AnyConnection,
+    volume: &VolumeData,
+    account: &Uuid,
+) -> Result<bool, AclError> {
+    let result = query("DELETE FROM storage_volume_acl WHERE volume_id = ? AND account_id = ?")
+        .bind(volume.id())
+        .bind(account.to_string())
+        .execute(conn)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+#[tokio::test]
+async fn test_acl() {
+    use crate::volume::Volume;
+    use fractal_storage_client::Privkey;
+    use sqlx::AnyPool;
+
+    let pool = AnyPool::connect("sqlite://:memory:").await.unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+    let mut conn = pool.acquire().await.unwrap();
+
+    let pubkey = Privkey::generate().pubkey();
+    Volume::create(&mut conn, &pubkey, &Uuid::new_v4())
+        .await
+        .unwrap();
+    let volume = Volume::lookup(&mut conn, &pubkey).await.unwrap().unwrap();
+    let account = Uuid::new_v4();
+    assert_eq!(granted(&mut conn, &volume, &account).await.unwrap(), None);
+
+    let mut shared = VolumeGrant {
+        account,
+        access: VolumeAccess::Read,
+    };
+    grant(&mut conn, &volume, &shared).await.unwrap();
+    assert_eq!(
+        granted(&mut conn, &volume, &account).await.unwrap(),
+        Some(VolumeAccess::Read)
+    );
+
+    // granting again replaces the previous grant
+    shared.access = VolumeAccess::Write;
+    grant(&mut conn, &volume, &shared).await.unwrap();
+    assert_eq!(list(&mut conn, &volume).await.unwrap(), vec![shared]);
+
+    assert!(revoke(&mut conn, &volume, &account).await.unwrap());
+    assert!(!revoke(&mut conn, &volume, &account).await.unwrap());
+    assert!(list(&mut conn, &volume).await.unwrap().is_empty());
+    assert!(Access::from(VolumeAccess::Write) > Access::Read);
+    assert!(Access::from(VolumeAccess::Write) < Access::Owner);
+}
@@ -0,0 +1,248 @@
+use crate::blobs::Blobs;
+use crate::ipfs::Ipfs;
+use crate::purge::{Purge, PurgeError};
+use crate::reconcile::{Reconcile, ReconcileError};
+use crate::snapshot::{SnapshotData, SnapshotError, ARCHIVE_DATA};
+use fractal_storage_client::Pubkey;
+use log::{error, info};
+use rocket::serde::json::serde_json;
+use serde::Serialize;
+use sqlx::{query, AnyConnection, Row};
+use std::time::Duration;
+use structopt::StructOpt;
+use thiserror::Error;
+
+/// How many snapshots to verify per query.
+const VERIFY_BATCH: i64 = 1000;
+
+#[derive(Error, Debug)]
+pub enum AdminError {
+    #[error("Error talking to database: {0:}")]
+    Database(#[from] sqlx::Error),
+    #[error("Error in snapshots: {0:}")]
+    Snapshot(#[from] SnapshotError),
+    #[error("Error parsing key: {0:}")]
+    ParseKey(#[from] fractal_storage_client::keys::ParseError),
+    #[error("Error collecting garbage: {0:}")]
+    Purge(#[from] PurgeError),
+    #[error("Error verifying payloads: {0:}")]
+    Reconcile(#[from] ReconcileError),
+    #[error("Verification failed: {0:} invalid manifests, {1:} corrupted payloads")]
+    VerifyFailed(usize, usize),
+}
+
+/// Maintenance commands, these operate on the database directly instead of launching the
+/// service, so that they can be run from cron or CI.
+#[derive(StructOpt, Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Apply pending database migrations.
+    Migrate,
+    /// Purge deleted volumes whose grace period has elapsed, prune snapshots beyond the
+    /// retention settings of their volumes, release payloads of deleted accounts and archive
+    /// manifests of old snapshots.
+    Gc,
+    /// Verify the signatures of all stored manifests and, if a blob backend is configured,
+    /// the digests of a sample of stored payloads. Fails if any are invalid.
+    Verify {
+        /// How many payloads to verify.
+        #[structopt(long, default_value = "100")]
+        sample: usize,
+    },
+    /// Print statistics about the stored data, as JSON.
+    Stats,
+}
+
+/// Statistics about the stored data.
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub volumes: u64,
+    pub volumes_deleted: u64,
+    pub snapshots: u64,
+    pub payloads: u64,
+    pub payload_bytes: u64,
+    pub corruption_reports: u64,
+}
+
+/// Collect statistics about the stored data.
+pub async fn stats(conn: &mut AnyConnection) -> Result<Stats, AdminError> {
+    let row = query(
+        "SELECT
+            (SELECT COUNT(*) FROM storage_volume WHERE volume_deleted_at IS NULL) AS volumes,
+            (SELECT COUNT(*) FROM storage_volume WHERE volume_deleted_at IS NOT NULL)
+                AS volumes_deleted,
+            (SELECT COUNT(*) FROM storage_snapshot) AS snapshots,
+            (SELECT COUNT(*) FROM storage_payload) AS payloads,
+            (SELECT COALESCE(SUM(payload_size), 0) FROM storage_payload) AS payload_bytes,
+            (SELECT COUNT(*) FROM storage_corruption_report) AS corruption_reports",
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    let get = |column| row.try_get::<i64, _>(column).map(|value| value as u64);
+    Ok(Stats {
+        volumes: get("volumes")?,
+        volumes_deleted: get("volumes_deleted")?,
+        snapshots: get("snapshots")?,
+        payloads: get("payloads")?,
+        payload_bytes: get("payload_bytes")?,
+        corruption_reports: get("corruption_reports")?,
+    })
+}
+
+/// Verify the signatures of all stored manifests against the keys of their volumes,
+/// returning how many are invalid.
+pub async fn verify_manifests(conn: &mut AnyConnection) -> Result<usize, AdminError> {
+    let mut invalid = 0;
+    let mut last = 0;
+    loop {
+        let statement = format!(
+            "SELECT storage_snapshot.*, {ARCHIVE_DATA}, volume_pubkey, volume_pq_pubkey
+            FROM storage_snapshot
+            JOIN storage_volume ON storage_volume.volume_id = storage_snapshot.volume_id
+            WHERE snapshot_id > ?
+            ORDER BY snapshot_id
+            LIMIT ?"
+        );
+        let rows = query(&statement)
+            .bind(last)
+            .bind(VERIFY_BATCH)
+            .fetch_all(&mut *conn)
+            .await?;
+        for row in &rows {
+            last = row.try_get("snapshot_id")?;
+            let volume: Vec<u8> = row.try_get("volume_pubkey")?;
+            let volume = Pubkey::try_from(volume.as_slice())?;
+            let pq_pubkey: Option<Vec<u8>> = row.try_get("volume_pq_pubkey")?;
+            let valid = match SnapshotData::from_row(row) {
+                Ok(snapshot) => snapshot
+                    .manifest_signed()
+                    .validate_pinned(&volume, pq_pubkey.as_deref())
+                    .is_ok(),
+                Err(_) => false,
+            };
+            if !valid {
+                error!(
+                    "Manifest of snapshot {} in volume {} is invalid",
+                    last, volume
+                );
+                invalid += 1;
+            }
+        }
+        if (rows.len() as i64) < VERIFY_BATCH {
+            break;
+        }
+    }
+    Ok(invalid)
+}
+
+impl Command {
+    /// Run the command against a database that was already migrated.
+    pub async fn run(
+        &self,
+        conn: &mut AnyConnection,
+        purge: &Purge,
+        ipfs: Option<&Ipfs>,
+        blobs: Option<&Blobs>,
+    ) -> Result<(), AdminError> {
+        match self {
+            Command::Migrate => {
+                info!("Database is up to date");
+            }
+            Command::Gc => {
+                let purged = purge.run(conn, ipfs, blobs).await?;
+                let pruned = purge.prune(conn, ipfs, blobs).await?;
+                let released = purge.release_queued(conn, ipfs, blobs).await?;
+                let archived = purge.archive(conn).await?;
+                info!(
+                    "Purged {} volumes, pruned {} snapshots, released {} payloads, archived {} manifests",
+                    purged, pruned, released, archived
+                );
+            }
+            Command::Verify { sample } => {
+                let invalid = verify_manifests(conn).await?;
+                let corrupted = match blobs {
+                    Some(blobs) if *sample > 0 => {
+                        Reconcile::new(Duration::ZERO, *sample)
+                            .run(conn, blobs, None)
+                            .await?
+                    }
+                    _ => 0,
+                };
+                if invalid > 0 || corrupted > 0 {
+                    return Err(AdminError::VerifyFailed(invalid, corrupted));
+                }
+                info!("All manifests and sampled payloads are valid");
+            }
+            Command::Stats => {
+                let stats = stats(conn).await?;
+                println!("{}", serde_json::to_string_pretty(&stats).unwrap());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_stats_verify() {
+    use crate::snapshot::{Snapshot, MINIMUM_SNAPSHOT_SIZE};
+    use crate::volume::Volume;
+    use fractal_storage_client::{Manifest, Privkey};
+    use sqlx::AnyPool;
+    use uuid::Uuid;
+
+    let pool = AnyPool::connect("sqlite://:memory:").await.unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+    let mut conn = pool.acquire().await.unwrap();
+    assert_eq!(stats(&mut conn).await.unwrap(), Stats::default());
+
+    let privkey = Privkey::generate();
+    let volume = Volume::create(&mut conn, &privkey.pubkey(), &Uuid::new_v4())
+        .await
+        .unwrap();
+    let manifest = Manifest {
+        creation: 0,
+        data: "ipfs://asd99a0s8098da0sd98".parse().unwrap(),
+        generation: 0,
+        parent: None,
+        size: MINIMUM_SNAPSHOT_SIZE,
+        size_total: MINIMUM_SNAPSHOT_SIZE,
+        machine: Default::default(),
+        path: std::path::PathBuf::from("abc"),
+    }
+    .sign(&privkey);
+    Snapshot::create(
+        &mut conn,
+        &volume,
+        &manifest.raw,
+        &manifest.signature,
+        &manifest.hash(),
+        None,
+        0,
+        &manifest.manifest.data,
+    )
+    .await
+    .unwrap();
+    let stats = stats(&mut conn).await.unwrap();
+    assert_eq!(stats.volumes, 1);
+    assert_eq!(stats.snapshots, 1);
+    assert_eq!(verify_manifests(&mut conn).await.unwrap(), 0);
+
+    // manifests signed with another key are reported
+    let forged = Manifest {
+        generation: 1,
+        ..manifest.manifest.clone()
+    }
+    .sign(&Privkey::generate());
+    Snapshot::create(
+        &mut conn,
+        &volume,
+        &forged.raw,
+        &forged.signature,
+        &forged.hash(),
+        None,
+        1,
+        &forged.manifest.data,
+    )
+    .await
+    .unwrap();
+    assert_eq!(verify_manifests(&mut conn).await.unwrap(), 1);
+}
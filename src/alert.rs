@@ -0,0 +1,735 @@
+use crate::events::Events;
+use crate::purge::now;
+use crate::snapshot::SnapshotData;
+use fractal_storage_client::{
+    AccountEvent, AccountSettings, Alert, AlertKind, AlertSettings, Pubkey, Warning,
+};
+use log::{error, info, warn};
+use reqwest::{redirect, Client};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::tokio::{select, time};
+use rocket::{Build, Orbit, Rocket};
+use sqlx::{query, AnyConnection, AnyPool, Row};
+use std::collections::BTreeSet;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use url::{Host, Url};
+use uuid::Uuid;
+
+/// How long webhooks may take to accept an alert.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many of the preceding snapshots of a volume new ones are compared against.
+const SIZE_HISTORY: i64 = 10;
+
+/// How many preceding snapshots a volume needs before sizes are compared at all.
+const SIZE_HISTORY_MIN: usize = 3;
+
+#[derive(Error, Debug)]
+pub enum AlertError {
+    #[error("Error talking to database: {0:}")]
+    Database(#[from] sqlx::Error),
+    #[error("Error parsing key: {0:}")]
+    ParseKey(#[from] fractal_storage_client::keys::ParseError),
+    #[error("Invalid webhook {0:}, must be an http or https URL of a public host")]
+    InvalidWebhook(Url),
+    #[error("Error resolving webhook host: {0:}")]
+    Resolve(#[from] std::io::Error),
+    #[error("Error posting to webhook: {0:}")]
+    Webhook(#[from] reqwest::Error),
+}
+
+/// Determines if the address is publicly routable. Webhooks are set by users, so they must
+/// not be able to make the service talk to itself or to hosts on its internal network.
+fn address_public(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => {
+            let [a, b, ..] = address.octets();
+            !(address.is_unspecified()
+                || address.is_loopback()
+                || address.is_private()
+                || address.is_link_local()
+                || address.is_broadcast()
+                || address.is_multicast()
+                || address.is_documentation()
+                // shared address space (carrier-grade NAT)
+                || (a == 100 && (64..128).contains(&b))
+                || a == 0)
+        }
+        IpAddr::V6(address) => {
+            if let Some(mapped) = address.to_ipv4_mapped() {
+                return address_public(IpAddr::V4(mapped));
+            }
+            let first = address.segments()[0];
+            !(address.is_unspecified()
+                || address.is_loopback()
+                || address.is_multicast()
+                // unique local
+                || (first & 0xfe00) == 0xfc00
+                // link-local
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// Check that a webhook is an http(s) URL that does not obviously point at a private host.
+/// Hostnames are checked again when they are resolved, before each delivery.
+fn webhook_check(webhook: &Url) -> Result<(), AlertError> {
+    let public = match webhook.host() {
+        _ if !matches!(webhook.scheme(), "http" | "https") => false,
+        Some(Host::Domain(domain)) => {
+            let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+            domain != "localhost" && !domain.ends_with(".localhost")
+        }
+        Some(Host::Ipv4(address)) => address_public(address.into()),
+        Some(Host::Ipv6(address)) => address_public(address.into()),
+        None => false,
+    };
+    if !public {
+        return Err(AlertError::InvalidWebhook(webhook.clone()));
+    }
+    Ok(())
+}
+
+/// Settings of an account, defaults if it has none.
+pub async fn settings(
+    conn: &mut AnyConnection,
+    account: &Uuid,
+) -> Result<AccountSettings, AlertError> {
+    let row = query("SELECT * FROM storage_alert_setting WHERE account_id = ?")
+        .bind(account.to_string())
+        .fetch_optional(conn)
+        .await?;
+    let row = match row {
+        Some(row) => row,
+        None => return Ok(AccountSettings::default()),
+    };
+    let volume_size: Option<i64> = row.try_get("alert_volume_size")?;
+    let snapshot_count: Option<i64> = row.try_get("alert_snapshot_count")?;
+    let size_anomaly: Option<i64> = row.try_get("alert_size_anomaly")?;
+    let webhook: Option<String> = row.try_get("alert_webhook")?;
+    Ok(AccountSettings {
+        alerts: AlertSettings {
+            volume_size: volume_size.map(|size| size as u64),
+            snapshot_count: snapshot_count.map(|count| count as u64),
+            size_anomaly: size_anomaly.map(|factor| factor as u64),
+            webhook: webhook.and_then(|webhook| Url::parse(&webhook).ok()),
+        },
+    })
+}
+
+/// Replace the settings of an account. Alerts raised under the previous thresholds are
+/// updated the next time alerts are evaluated.
+pub async fn settings_set(
+    conn: &mut AnyConnection,
+    account: &Uuid,
+    settings: &AccountSettings,
+) -> Result<(), AlertError> {
+    let alerts = &settings.alerts;
+    if let Some(webhook) = &alerts.webhook {
+        webhook_check(webhook)?;
+    }
+    query("DELETE FROM storage_alert_setting WHERE account_id = ?")
+        .bind(account.to_string())
+        .execute(&mut *conn)
+        .await?;
+    if *settings == AccountSettings::default() {
+        return Ok(());
+    }
+    query(
+        "INSERT INTO storage_alert_setting(account_id, alert_volume_size, alert_snapshot_count,
+            alert_size_anomaly, alert_webhook)
+        VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(account.to_string())
+    .bind(alerts.volume_size.map(|size| size as i64))
+    .bind(alerts.snapshot_count.map(|count| count as i64))
+    .bind(alerts.size_anomaly.map(|factor| factor as i64))
+    .bind(alerts.webhook.as_ref().map(Url::as_str))
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Alerts currently raised for volumes of an account.
+pub async fn list(conn: &mut AnyConnection, account: &Uuid) -> Result<Vec<Alert>, AlertError> {
+    let rows = query(
+        "SELECT volume_pubkey, alert_kind, alert_value, alert_threshold, alert_since
+        FROM storage_alert
+        JOIN storage_volume ON storage_volume.volume_id = storage_alert.volume_id
+        WHERE account_id = ? AND volume_deleted_at IS NULL
+        ORDER BY storage_alert.volume_id, alert_kind",
+    )
+    .bind(account.to_string())
+    .fetch_all(conn)
+    .await?;
+    let mut alerts = vec![];
+    for row in &rows {
+        let volume: Vec<u8> = row.try_get("volume_pubkey")?;
+        let kind: String = row.try_get("alert_kind")?;
+        // kinds this version does not know about are skipped
+        let kind = match AlertKind::from_str(&kind) {
+            Ok(kind) => kind,
+            Err(_) => continue,
+        };
+        alerts.push(Alert {
+            volume: Pubkey::try_from(volume.as_slice())?,
+            kind,
+            value: row.try_get::<i64, _>("alert_value")? as u64,
+            threshold: row.try_get::<i64, _>("alert_threshold")? as u64,
+            since: row.try_get::<i64, _>("alert_since")? as u64,
+        });
+    }
+    Ok(alerts)
+}
+
+/// Median size of the snapshots of a volume preceding the given generation, if it has enough
+/// of them to tell what is normal.
+async fn size_baseline(
+    conn: &mut AnyConnection,
+    volume_id: i64,
+    generation: u64,
+) -> Result<Option<u64>, AlertError> {
+    let rows = query(
+        "SELECT snapshot_size FROM storage_snapshot
+        WHERE volume_id = ? AND snapshot_generation < ? AND snapshot_size IS NOT NULL
+        ORDER BY snapshot_generation DESC
+        LIMIT ?",
+    )
+    .bind(volume_id)
+    .bind(generation as i64)
+    .bind(SIZE_HISTORY)
+    .fetch_all(conn)
+    .await?;
+    if rows.len() < SIZE_HISTORY_MIN {
+        return Ok(None);
+    }
+    let mut sizes = rows
+        .iter()
+        .map(|row| Ok(row.try_get::<i64, _>("snapshot_size")? as u64))
+        .collect::<Result<Vec<u64>, sqlx::Error>>()?;
+    sizes.sort_unstable();
+    Ok(Some(sizes[sizes.len() / 2]))
+}
+
+/// Check whether a snapshot is more than `factor` times larger than the recent snapshots of
+/// its volume, a sudden jump like this is typical for data encrypted by ransomware.
+pub async fn size_anomaly(
+    conn: &mut AnyConnection,
+    snapshot: &SnapshotData,
+    factor: u64,
+) -> Result<Option<Warning>, AlertError> {
+    if factor == 0 {
+        return Ok(None);
+    }
+    let manifest = snapshot.manifest();
+    let baseline = size_baseline(conn, snapshot.volume().id(), manifest.generation).await?;
+    Ok(baseline
+        .filter(|baseline| manifest.size > baseline.saturating_mul(factor))
+        .map(|baseline| Warning::SizeAnomaly {
+            size: manifest.size,
+            baseline,
+            factor,
+        }))
+}
+
+/// Counters collected by the alert evaluator.
+#[derive(Debug, Default)]
+pub struct AlertMetrics {
+    /// Alerts currently raised.
+    active: AtomicU64,
+    /// Alerts raised since the service was started.
+    raised: AtomicU64,
+    /// Alerts that could not be delivered to a webhook.
+    webhook_failures: AtomicU64,
+}
+
+impl AlertMetrics {
+    /// Render metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        format!(
+            "storage_alerts_active {}\nstorage_alerts_raised_total {}\nstorage_alerts_webhook_failures_total {}\n",
+            self.active.load(Ordering::Relaxed),
+            self.raised.load(Ordering::Relaxed),
+            self.webhook_failures.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// Background evaluation of the alert thresholds of accounts, so that users notice runaway
+/// volumes before they cost them. Volumes exceeding a threshold are recorded as alerts,
+/// which are published to the account, posted to its webhook and counted in the metrics
+/// when they are first raised, and removed once the volume is back under the threshold.
+/// Size anomalies are checked for every volume, using the default factor for accounts
+/// that have not configured one.
+#[derive(Clone, Debug)]
+pub struct Alerts {
+    /// How often to evaluate thresholds.
+    pub interval: Duration,
+    /// Default factor for size anomalies, zero disables the check.
+    anomaly_factor: u64,
+    metrics: Arc<AlertMetrics>,
+}
+
+impl Alerts {
+    pub fn new(interval: Duration, anomaly_factor: u64) -> Self {
+        Alerts {
+            interval,
+            anomaly_factor,
+            metrics: Default::default(),
+        }
+    }
+
+    /// Factor for size anomalies under the given settings.
+    pub fn anomaly_factor(&self, settings: &AlertSettings) -> u64 {
+        settings.size_anomaly.unwrap_or(self.anomaly_factor)
+    }
+
+    pub fn metrics(&self) -> &AlertMetrics {
+        &self.metrics
+    }
+
+    /// Evaluate the thresholds of all accounts, returning the alerts that were newly raised.
+    pub async fn run(
+        &self,
+        conn: &mut AnyConnection,
+        events: Option<&Events>,
+    ) -> Result<Vec<Alert>, AlertError> {
+        let rows = query(
+            "SELECT storage_volume.volume_id, volume_pubkey, storage_volume.account_id,
+                alert_volume_size, alert_snapshot_count, alert_size_anomaly, alert_webhook,
+                (SELECT COUNT(*) FROM storage_snapshot
+                    WHERE storage_snapshot.volume_id = storage_volume.volume_id)
+                    AS snapshot_count,
+                (SELECT COALESCE(SUM(snapshot_size), 0) FROM storage_snapshot
+                    WHERE storage_snapshot.volume_id = storage_volume.volume_id)
+                    AS volume_size,
+                (SELECT snapshot_generation FROM storage_snapshot
+                    WHERE storage_snapshot.volume_id = storage_volume.volume_id
+                    ORDER BY snapshot_generation DESC LIMIT 1)
+                    AS latest_generation,
+                (SELECT snapshot_size FROM storage_snapshot
+                    WHERE storage_snapshot.volume_id = storage_volume.volume_id
+                    ORDER BY snapshot_generation DESC LIMIT 1)
+                    AS latest_size
+            FROM storage_volume
+            LEFT JOIN storage_alert_setting
+                ON storage_alert_setting.account_id = storage_volume.account_id
+            WHERE volume_deleted_at IS NULL",
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+        let existing = query("SELECT volume_id, alert_kind FROM storage_alert")
+            .fetch_all(&mut *conn)
+            .await?
+            .iter()
+            .map(|row| Ok((row.try_get("volume_id")?, row.try_get("alert_kind")?)))
+            .collect::<Result<BTreeSet<(i64, String)>, sqlx::Error>>()?;
+
+        let time = now();
+        let mut active = BTreeSet::new();
+        let mut raised = vec![];
+        for row in &rows {
+            let volume_id: i64 = row.try_get("volume_id")?;
+            let mut checks = vec![
+                (
+                    AlertKind::VolumeSize,
+                    row.try_get::<i64, _>("volume_size")?,
+                    row.try_get::<Option<i64>, _>("alert_volume_size")?,
+                ),
+                (
+                    AlertKind::SnapshotCount,
+                    row.try_get::<i64, _>("snapshot_count")?,
+                    row.try_get::<Option<i64>, _>("alert_snapshot_count")?,
+                ),
+            ];
+
+            // the latest snapshot is compared against the ones preceding it
+            let factor = row
+                .try_get::<Option<i64>, _>("alert_size_anomaly")?
+                .map(|factor| factor as u64)
+                .unwrap_or(self.anomaly_factor);
+            let latest_generation: Option<i64> = row.try_get("latest_generation")?;
+            let latest_size: Option<i64> = row.try_get("latest_size")?;
+            if let (true, Some(generation), Some(size)) =
+                (factor > 0, latest_generation, latest_size)
+            {
+                let baseline = size_baseline(&mut *conn, volume_id, generation as u64).await?;
+                if let Some(baseline) = baseline {
+                    let threshold = baseline.saturating_mul(factor).min(i64::MAX as u64);
+                    checks.push((AlertKind::SizeAnomaly, size, Some(threshold as i64)));
+                }
+            }
+
+            for (kind, value, threshold) in checks {
+                let threshold = match threshold {
+                    Some(threshold) if value > threshold => threshold,
+                    _ => continue,
+                };
+                let key = (volume_id, kind.as_str().to_string());
+                if existing.contains(&key) {
+                    query(
+                        "UPDATE storage_alert SET alert_value = ?, alert_threshold = ?
+                        WHERE volume_id = ? AND alert_kind = ?",
+                    )
+                    .bind(value)
+                    .bind(threshold)
+                    .bind(volume_id)
+                    .bind(kind.as_str())
+                    .execute(&mut *conn)
+                    .await?;
+                    active.insert(key);
+                    continue;
+                }
+
+                query(
+                    "INSERT INTO storage_alert(volume_id, alert_kind, alert_value,
+                        alert_threshold, alert_since)
+                    VALUES (?, ?, ?, ?, ?)",
+                )
+                .bind(volume_id)
+                .bind(kind.as_str())
+                .bind(value)
+                .bind(threshold)
+                .bind(time as i64)
+                .execute(&mut *conn)
+                .await?;
+                active.insert(key);
+                let volume: Vec<u8> = row.try_get("volume_pubkey")?;
+                let alert = Alert {
+                    volume: Pubkey::try_from(volume.as_slice())?,
+                    kind,
+                    value: value as u64,
+                    threshold: threshold as u64,
+                    since: time,
+                };
+                info!(
+                    "Volume {} exceeds {} threshold: {} > {}",
+                    alert.volume,
+                    kind.as_str(),
+                    value,
+                    threshold
+                );
+                let account: String = row.try_get("account_id")?;
+                if let (Some(events), Ok(account)) = (events, Uuid::parse_str(&account)) {
+                    events.publish(
+                        &account,
+                        AccountEvent::AlertRaised {
+                            alert: alert.clone(),
+                        },
+                    );
+                }
+                let webhook: Option<String> = row.try_get("alert_webhook")?;
+                if let Some(webhook) = webhook.and_then(|webhook| Url::parse(&webhook).ok()) {
+                    self.notify(&webhook, &alert).await;
+                }
+                raised.push(alert);
+            }
+        }
+
+        // volumes back under their thresholds, or whose thresholds were removed
+        for (volume_id, kind) in existing.difference(&active) {
+            query("DELETE FROM storage_alert WHERE volume_id = ? AND alert_kind = ?")
+                .bind(*volume_id)
+                .bind(kind.as_str())
+                .execute(&mut *conn)
+                .await?;
+        }
+        self.metrics
+            .active
+            .store(active.len() as u64, Ordering::Relaxed);
+        self.metrics
+            .raised
+            .fetch_add(raised.len() as u64, Ordering::Relaxed);
+        Ok(raised)
+    }
+
+    /// Post a newly raised alert to the webhook of the account. Alerts are not retried, they
+    /// remain visible in the alerts endpoint.
+    async fn notify(&self, webhook: &Url, alert: &Alert) {
+        if let Err(e) = self.post(webhook, alert).await {
+            warn!("Error posting alert to webhook: {}", e);
+            self.metrics
+                .webhook_failures
+                .fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Post an alert to a webhook. The host is resolved here and every address it resolves
+    /// to must be public, the request is then pinned to the checked address so that it
+    /// cannot be rebound in between. Redirects are not followed for the same reason.
+    async fn post(&self, webhook: &Url, alert: &Alert) -> Result<(), AlertError> {
+        webhook_check(webhook)?;
+        let mut client = Client::builder()
+            .redirect(redirect::Policy::none())
+            .timeout(WEBHOOK_TIMEOUT);
+        if let Some(Host::Domain(domain)) = webhook.host() {
+            let port = webhook.port_or_known_default().unwrap_or(443);
+            let addresses: Vec<SocketAddr> = rocket::tokio::net::lookup_host((domain, port))
+                .await?
+                .collect();
+            let address = match addresses.first() {
+                Some(address) if addresses.iter().all(|a| address_public(a.ip())) => *address,
+                _ => return Err(AlertError::InvalidWebhook(webhook.clone())),
+            };
+            client = client.resolve(domain, address);
+        }
+        client
+            .build()?
+            .post(webhook.clone())
+            .json(alert)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Alerts {
+    fn info(&self) -> Info {
+        Info {
+            name: "Evaluate alert thresholds",
+            kind: Kind::Ignite | Kind::Liftoff,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
+        Ok(rocket.manage(self.clone()))
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let pool = match rocket.state::<AnyPool>() {
+            Some(pool) => pool.clone(),
+            None => return,
+        };
+        let events = rocket.state::<Events>().cloned();
+        let mut shutdown = rocket.shutdown();
+        let alerts = self.clone();
+        rocket::tokio::spawn(async move {
+            let mut interval = time::interval(alerts.interval);
+            loop {
+                select! {
+                    _ = interval.tick() => {},
+                    _ = &mut shutdown => break,
+                }
+                let result = match pool.acquire().await {
+                    Ok(mut conn) => alerts.run(&mut conn, events.as_ref()).await,
+                    Err(e) => Err(e.into()),
+                };
+                if let Err(e) = result {
+                    error!("Error evaluating alerts: {}", e);
+                }
+            }
+        });
+    }
+}
+
+#[tokio::test]
+async fn test_alerts() {
+    use crate::snapshot::{Snapshot, MINIMUM_SNAPSHOT_SIZE};
+    use crate::volume::Volume;
+    use fractal_storage_client::{Manifest, Parent, Privkey};
+
+    let pool = AnyPool::connect("sqlite://:memory:").await.unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+    let mut conn = pool.acquire().await.unwrap();
+    let account = Uuid::new_v4();
+    let privkey = Privkey::generate();
+    Volume::create(&mut conn, &privkey.pubkey(), &account)
+        .await
+        .unwrap();
+    let volume = Volume::lookup(&mut conn, &privkey.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let mut parent = None;
+    for generation in 0..3 {
+        let manifest = Manifest {
+            creation: 0,
+            data: "ipfs://asd99a0s8098da0sd98".parse().unwrap(),
+            generation,
+            parent: parent.map(Parent::new),
+            size: MINIMUM_SNAPSHOT_SIZE,
+            size_total: (generation + 1) * MINIMUM_SNAPSHOT_SIZE,
+            machine: Default::default(),
+            path: std::path::PathBuf::from("abc"),
+        }
+        .sign(&privkey);
+        Snapshot::create_from_manifest(&mut conn, &volume, &manifest.data())
+            .await
+            .unwrap();
+        parent = Some(manifest.hash());
+    }
+
+    // nothing is raised without thresholds
+    let alerts = Alerts::new(Duration::from_secs(60), 0);
+    assert!(alerts.run(&mut conn, None).await.unwrap().is_empty());
+
+    let mut settings = AccountSettings {
+        alerts: AlertSettings {
+            volume_size: Some(4 * MINIMUM_SNAPSHOT_SIZE),
+            snapshot_count: Some(2),
+            size_anomaly: None,
+            webhook: None,
+        },
+    };
+    settings_set(&mut conn, &account, &settings).await.unwrap();
+    assert_eq!(settings(&mut conn, &account).await.unwrap(), settings);
+
+    // alerts are raised once, and stay listed while the threshold is exceeded
+    let raised = alerts.run(&mut conn, None).await.unwrap();
+    assert_eq!(raised.len(), 1);
+    assert_eq!(raised[0].kind, AlertKind::SnapshotCount);
+    assert_eq!(raised[0].value, 3);
+    assert!(alerts.run(&mut conn, None).await.unwrap().is_empty());
+    assert_eq!(list(&mut conn, &account).await.unwrap(), raised);
+    assert!(list(&mut conn, &Uuid::new_v4()).await.unwrap().is_empty());
+    assert!(alerts
+        .metrics()
+        .render()
+        .contains("storage_alerts_active 1"));
+
+    // and are removed once the threshold is no longer exceeded
+    settings.alerts.snapshot_count = Some(3);
+    settings_set(&mut conn, &account, &settings).await.unwrap();
+    assert!(alerts.run(&mut conn, None).await.unwrap().is_empty());
+    assert!(list(&mut conn, &account).await.unwrap().is_empty());
+
+    settings.alerts.webhook = Some("ftp://example.com".parse().unwrap());
+    assert!(matches!(
+        settings_set(&mut conn, &account, &settings).await,
+        Err(AlertError::InvalidWebhook(_))
+    ));
+    settings.alerts.webhook = Some("http://169.254.169.254/latest".parse().unwrap());
+    assert!(matches!(
+        settings_set(&mut conn, &account, &settings).await,
+        Err(AlertError::InvalidWebhook(_))
+    ));
+}
+
+#[test]
+fn test_webhook_check() {
+    for webhook in [
+        "https://example.com/alerts",
+        "http://example.com:8080/alerts",
+        "https://93.184.216.34/alerts",
+        "https://[2606:2800:220:1:248:1893:25c8:1946]/alerts",
+    ] {
+        assert!(
+            webhook_check(&webhook.parse().unwrap()).is_ok(),
+            "{webhook}"
+        );
+    }
+    for webhook in [
+        "ftp://example.com/alerts",
+        "http://localhost:8000/alerts",
+        "http://LOCALHOST./alerts",
+        "http://service.localhost/alerts",
+        "http://127.0.0.1/alerts",
+        "http://0.0.0.0/alerts",
+        "http://10.1.2.3/alerts",
+        "http://172.16.0.1/alerts",
+        "http://192.168.1.1/alerts",
+        "http://169.254.169.254/latest/meta-data",
+        "http://100.64.0.1/alerts",
+        "http://[::1]/alerts",
+        "http://[::ffff:127.0.0.1]/alerts",
+        "http://[fd00::1]/alerts",
+        "http://[fe80::1]/alerts",
+    ] {
+        assert!(
+            matches!(
+                webhook_check(&webhook.parse().unwrap()),
+                Err(AlertError::InvalidWebhook(_))
+            ),
+            "{webhook}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_size_anomaly() {
+    use crate::snapshot::{Snapshot, MINIMUM_SNAPSHOT_SIZE};
+    use crate::volume::Volume;
+    use fractal_storage_client::{Manifest, Parent, Privkey};
+
+    let pool = AnyPool::connect("sqlite://:memory:").await.unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+    let mut conn = pool.acquire().await.unwrap();
+    let account = Uuid::new_v4();
+    let privkey = Privkey::generate();
+    Volume::create(&mut conn, &privkey.pubkey(), &account)
+        .await
+        .unwrap();
+    let volume = Volume::lookup(&mut conn, &privkey.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let alerts = Alerts::new(Duration::from_secs(60), 10);
+    let mut parent = None;
+    let mut size_total = 0;
+    for generation in 0..4 {
+        // the last snapshot jumps to 20 times the size of the others
+        let size = match generation {
+            3 => 20 * MINIMUM_SNAPSHOT_SIZE,
+            _ => MINIMUM_SNAPSHOT_SIZE,
+        };
+        size_total += size;
+        let manifest = Manifest {
+            creation: 0,
+            data: "ipfs://asd99a0s8098da0sd98".parse().unwrap(),
+            generation,
+            parent: parent.map(Parent::new),
+            size,
+            size_total,
+            machine: Default::default(),
+            path: std::path::PathBuf::from("abc"),
+        }
+        .sign(&privkey);
+        let snapshot = Snapshot::create_from_manifest(&mut conn, &volume, &manifest.data())
+            .await
+            .unwrap()
+            .fetch(&mut conn)
+            .await
+            .unwrap();
+        parent = Some(manifest.hash());
+
+        let warning = size_anomaly(&mut conn, &snapshot, 10).await.unwrap();
+        let raised = alerts.run(&mut conn, None).await.unwrap();
+        if generation < 3 {
+            assert_eq!(warning, None);
+            assert!(raised.is_empty());
+            continue;
+        }
+        assert_eq!(
+            warning,
+            Some(Warning::SizeAnomaly {
+                size,
+                baseline: MINIMUM_SNAPSHOT_SIZE,
+                factor: 10,
+            })
+        );
+        assert_eq!(size_anomaly(&mut conn, &snapshot, 0).await.unwrap(), None);
+        assert_eq!(raised.len(), 1);
+        assert_eq!(raised[0].kind, AlertKind::SizeAnomaly);
+        assert_eq!(raised[0].value, size);
+        assert_eq!(raised[0].threshold, 10 * MINIMUM_SNAPSHOT_SIZE);
+    }
+
+    // accounts can disable the check
+    let settings = AccountSettings {
+        alerts: AlertSettings {
+            size_anomaly: Some(0),
+            ..Default::default()
+        },
+    };
+    settings_set(&mut conn, &account, &settings).await.unwrap();
+    assert_eq!(alerts.anomaly_factor(&settings.alerts), 0);
+    assert!(alerts.run(&mut conn, None).await.unwrap().is_empty());
+    assert!(list(&mut conn, &account).await.unwrap().is_empty());
+}
@@ -1,19 +1,51 @@
-use crate::snapshot::{Snapshot, SnapshotError};
-use crate::volume::{Volume, VolumeError};
-use fractal_auth_client::UserContext;
-use fractal_storage_client::{Hash, ManifestSigned, Pubkey, VolumeEdit, VolumeInfo};
-use rocket::response::status::BadRequest;
-use rocket::response::Redirect;
+use crate::account::{self, AccountError};
+use crate::acl::{self, Access, AclError};
+use crate::alert::{self, AlertError, Alerts};
+use crate::apikey::{ApiKeyData, ApiKeyError};
+use crate::auth::{Principal, SystemPrincipal};
+use crate::blobs::{BlobError, Blobs};
+use crate::chaos::Chaos;
+use crate::drill::{self, DrillError};
+use crate::events::Events;
+use crate::idempotency::{IdempotencyError, IdempotencyKey};
+use crate::ipfs::{data_cid, identity_acceptable, ByteRange, Ipfs, IpfsError, PayloadStream};
+use crate::label::{self, LabelError, Labels};
+use crate::limit::{UploadLimit, UPLOAD_RETRY_AFTER};
+use crate::pending::{self, PendingError, PendingUploads};
+use crate::purge::{self, now, Purge};
+use crate::reconcile::{self, DigestReader};
+use crate::redact::{RedactedHash, RedactedManifest, Redaction};
+use crate::replicate::{self, Replication, ReplicationError};
+use crate::signature::{self, SignatureError, SignedRequest, VolumeProof};
+use crate::snapshot::{chain_validate, Snapshot, SnapshotData, SnapshotError};
+use crate::upload_token::{
+    parse_ttl, UploadGrant, UploadTokenError, UploadTokens, UPLOAD_TOKEN_TTL_DEFAULT,
+};
+use crate::volume::{Volume, VolumeCache, VolumeData, VolumeError};
+use fractal_storage_client::{
+    AccountDeleted, AccountEvent, AccountSettings, Alert, AncestryLink, ApiKeyCreate,
+    ApiKeyCreated, ApiKeyInfo, Capabilities, ChainReport, Drill, DrillResult, DrillStatus,
+    DuplicateData, ErrorCode, ErrorResponse, Hash, LabelMatch, MachineSnapshot, Manifest,
+    ManifestSigned, PayloadStatus, PresignedUrl, Pubkey, ReplicationStatus, SignatureAlgorithm,
+    SnapshotAncestry, SnapshotOrdering, SnapshotPage, SnapshotPending, SnapshotRecord,
+    SnapshotUploadResult, SnapshotUploadStatus, SnapshotUploaded, StorageStats, UploadToken,
+    VolumeArchive, VolumeChallenge, VolumeEdit, VolumeGrant, VolumeInfo, Warning,
+    MANIFEST_VERSIONS, VOLUME_ARCHIVE_VERSION,
+};
+use rocket::data::{ByteUnit, Limits};
+use rocket::response::status::{self, BadRequest};
 use rocket::{
-    http::Status,
-    request::Request,
+    http::{ContentType, Status},
+    request::{FromRequest, Outcome, Request},
     response::{self, Responder, Response},
-    serde::json::Json,
+    serde::json::{serde_json, Json},
     *,
 };
-use sqlx::AnyPool;
+use sqlx::{AnyConnection, AnyPool, Connection};
 use std::io::Cursor;
 use thiserror::Error;
+use tokio_util::io::StreamReader;
+use url::Url;
 use uuid::Uuid;
 
 #[derive(Error, Debug)]
@@ -34,143 +66,1089 @@ pub enum StorageError {
     Database(#[from] sqlx::Error),
     #[error("Manifest for generation already exists but is different")]
     ManifestExists,
+    #[error("Error talking to IPFS: {0:}")]
+    Ipfs(#[from] IpfsError),
+    #[error("No IPFS node configured")]
+    IpfsUnavailable,
+    #[error("Requested range not satisfiable, size is {0:}")]
+    RangeNotSatisfiable(u64),
+    #[error("Payload can only be sent with identity encoding")]
+    NotAcceptable,
+    #[error("Error storing payload: {0:}")]
+    Blob(#[from] BlobError),
+    #[error("No blob backend configured")]
+    BlobsUnavailable,
+    #[error("Payload for snapshot already exists")]
+    BlobExists,
+    #[error("Payload not found")]
+    BlobNotFound,
+    #[error("Error handling idempotency key: {0:}")]
+    Idempotency(#[from] IdempotencyError),
+    #[error("Idempotency key was already used for a different manifest")]
+    IdempotencyKeyReused,
+    #[error("Invalid cursor")]
+    InvalidCursor,
+    #[error("{0:}")]
+    InvalidOrder(String),
+    #[error("Volume was deleted, restore it or wait for it to be purged")]
+    VolumeDeleted,
+    #[error("Error managing API keys: {0:}")]
+    ApiKey(#[from] ApiKeyError),
+    #[error("API key not found")]
+    ApiKeyNotFound,
+    #[error("Restricted API keys cannot issue credentials")]
+    Forbidden,
+    #[error("Missing or invalid token or pre-signed URL")]
+    Unauthorized,
+    #[error("Error issuing upload token: {0:}")]
+    UploadToken(#[from] UploadTokenError),
+    #[error("Error handling labels: {0:}")]
+    Label(#[from] LabelError),
+    #[error("{0:}")]
+    Signature(#[from] SignatureError),
+    #[error("Error getting replication status: {0:}")]
+    Replication(#[from] ReplicationError),
+    #[error("Volume already exists")]
+    VolumeExists,
+    #[error("Archive is for a different volume or has an unsupported version")]
+    ArchiveInvalid,
+    #[error("Invalid account")]
+    AccountInvalid,
+    #[error("Invalid machine")]
+    MachineInvalid,
+    #[error("Error deleting account: {0:}")]
+    Account(#[from] AccountError),
+    #[error("Too many uploads in flight, at most {0:} are allowed per account")]
+    TooManyUploads(usize),
+    #[error("Manifest too large, limit is {0:} bytes")]
+    ManifestTooLarge(u64),
+    #[error("Payload too large, limit is {0:} bytes")]
+    PayloadTooLarge(u64),
+    #[error("Error reading request body: {0:}")]
+    Body(#[from] std::io::Error),
+    #[error("Invalid request body: {0:}")]
+    BodyInvalid(serde_json::Error),
+    #[error("Error handling alerts: {0:}")]
+    Alert(#[from] AlertError),
+    #[error("Too many items in batch, at most {0:} are allowed")]
+    BatchTooLarge(usize),
+    #[error("Snapshots are immutable until {0:}")]
+    Immutable(u64),
+    #[error("Error recording restore drill: {0:}")]
+    Drill(#[from] DrillError),
+    #[error("Error checking access to volume: {0:}")]
+    Acl(#[from] AclError),
+    #[error("Access to volume was not granted for this operation")]
+    AccessDenied,
+    #[error("Account was not granted access to volume")]
+    GrantNotFound,
+    #[error("Error handling pending snapshots: {0:}")]
+    Pending(#[from] PendingError),
+    #[error("Snapshot is the parent of other snapshots, delete them first")]
+    HasChildren,
 }
 
 impl<'r> Responder<'r, 'static> for StorageError {
     fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
-        ::log::error!("Responding with error: {self:?}");
+        match Redaction::current() {
+            Redaction::Off => ::log::error!("Responding with error: {self:?}"),
+            _ => ::log::error!("Responding with error: {self}"),
+        }
+        use ErrorCode as Code;
         use StorageError::*;
-        let status = match &self {
-            VolumeNotFound => Status::NotFound,
-            Internal => Status::InternalServerError,
-            ManifestInvalid => Status::BadRequest,
-            SnapshotNotFound => Status::NotFound,
-            Snapshot(_) => Status::InternalServerError,
-            Volume(_) => Status::InternalServerError,
-            Database(_) => Status::InternalServerError,
-            ManifestExists => Status::BadRequest,
+        let (status, code) = match &self {
+            VolumeNotFound => (Status::NotFound, Code::VolumeNotFound),
+            Internal => (Status::InternalServerError, Code::Internal),
+            ManifestInvalid => (Status::BadRequest, Code::ManifestInvalid),
+            SnapshotNotFound => (Status::NotFound, Code::SnapshotNotFound),
+            Snapshot(SnapshotError::InvalidData(_)) => (Status::BadRequest, Code::ManifestInvalid),
+            Snapshot(SnapshotError::VolumeLocked) => (Status::Locked, Code::Locked),
+            Snapshot(SnapshotError::ManifestInvalid) => (Status::BadRequest, Code::ManifestInvalid),
+            Snapshot(SnapshotError::ParentNotFound(_)) => {
+                (Status::BadRequest, Code::ManifestInvalid)
+            }
+            Snapshot(_) => (Status::InternalServerError, Code::Internal),
+            Volume(VolumeError::WormShortened) => (Status::Forbidden, Code::Forbidden),
+            Volume(VolumeError::PqKeyInvalid) => (Status::BadRequest, Code::InvalidRequest),
+            Volume(VolumeError::PqKeyPinned) => (Status::Conflict, Code::Conflict),
+            Volume(_) => (Status::InternalServerError, Code::Internal),
+            Database(_) => (Status::InternalServerError, Code::Internal),
+            ManifestExists => (Status::BadRequest, Code::ManifestExists),
+            Ipfs(_) => (Status::BadGateway, Code::Unavailable),
+            IpfsUnavailable => (Status::NotImplemented, Code::Unavailable),
+            RangeNotSatisfiable(_) => (Status::RangeNotSatisfiable, Code::InvalidRequest),
+            NotAcceptable => (Status::NotAcceptable, Code::InvalidRequest),
+            Blob(_) => (Status::InternalServerError, Code::Internal),
+            BlobsUnavailable => (Status::NotImplemented, Code::Unavailable),
+            BlobExists => (Status::Conflict, Code::Conflict),
+            BlobNotFound => (Status::NotFound, Code::NotFound),
+            Idempotency(IdempotencyError::InvalidKey) => (Status::BadRequest, Code::InvalidRequest),
+            Idempotency(_) => (Status::InternalServerError, Code::Internal),
+            IdempotencyKeyReused => (Status::UnprocessableEntity, Code::Conflict),
+            InvalidCursor => (Status::BadRequest, Code::InvalidRequest),
+            InvalidOrder(_) => (Status::BadRequest, Code::InvalidRequest),
+            VolumeDeleted => (Status::Conflict, Code::VolumeDeleted),
+            ApiKey(ApiKeyError::TtlInvalid(_)) => (Status::BadRequest, Code::InvalidRequest),
+            ApiKey(_) => (Status::InternalServerError, Code::Internal),
+            ApiKeyNotFound => (Status::NotFound, Code::NotFound),
+            Forbidden => (Status::Forbidden, Code::Forbidden),
+            Unauthorized => (Status::Unauthorized, Code::Unauthorized),
+            UploadToken(_) => (Status::BadRequest, Code::InvalidRequest),
+            Label(LabelError::Database(_)) => (Status::InternalServerError, Code::Internal),
+            Label(_) => (Status::BadRequest, Code::InvalidRequest),
+            Alert(AlertError::InvalidWebhook(_)) => (Status::BadRequest, Code::InvalidRequest),
+            Alert(_) => (Status::InternalServerError, Code::Internal),
+            BatchTooLarge(_) => (Status::BadRequest, Code::TooLarge),
+            Immutable(_) => (Status::Forbidden, Code::Locked),
+            Drill(DrillError::MessageTooLong) => (Status::BadRequest, Code::TooLarge),
+            Drill(_) => (Status::InternalServerError, Code::Internal),
+            Acl(_) => (Status::InternalServerError, Code::Internal),
+            AccessDenied => (Status::Forbidden, Code::Forbidden),
+            GrantNotFound => (Status::NotFound, Code::NotFound),
+            Pending(PendingError::LimitExceeded(_)) => {
+                (Status::TooManyRequests, Code::QuotaExceeded)
+            }
+            Pending(_) => (Status::InternalServerError, Code::Internal),
+            HasChildren => (Status::Conflict, Code::HasChildren),
+            Signature(error) => (error.status(), error.code()),
+            Replication(_) => (Status::InternalServerError, Code::Internal),
+            VolumeExists => (Status::Conflict, Code::VolumeExists),
+            ArchiveInvalid => (Status::BadRequest, Code::InvalidRequest),
+            AccountInvalid => (Status::BadRequest, Code::InvalidRequest),
+            MachineInvalid => (Status::BadRequest, Code::InvalidRequest),
+            Account(AccountError::Immutable(_)) => (Status::Forbidden, Code::Locked),
+            Account(_) => (Status::InternalServerError, Code::Internal),
+            TooManyUploads(_) => (Status::TooManyRequests, Code::QuotaExceeded),
+            ManifestTooLarge(_) => (Status::PayloadTooLarge, Code::TooLarge),
+            PayloadTooLarge(_) => (Status::PayloadTooLarge, Code::TooLarge),
+            Body(_) => (Status::BadRequest, Code::InvalidRequest),
+            BodyInvalid(_) => (Status::BadRequest, Code::InvalidRequest),
+        };
+        let body = ErrorResponse {
+            code,
+            message: self.to_string(),
         };
-        let message = self.to_string();
-        let response = Response::build()
-            .sized_body(message.len(), Cursor::new(message))
-            .status(status)
-            .ok();
+        let body = serde_json::to_vec(&body).map_err(|_| Status::InternalServerError)?;
+        let mut response = Response::build();
         response
+            .header(ContentType::JSON)
+            .sized_body(body.len(), Cursor::new(body))
+            .status(status);
+        if let TooManyUploads(_) = self {
+            response.raw_header("Retry-After", UPLOAD_RETRY_AFTER.to_string());
+        }
+        response.ok()
+    }
+}
+
+/// Request guard for conditional requests, holds the value of the `If-None-Match` header.
+pub struct IfNoneMatch(Option<String>);
+
+impl IfNoneMatch {
+    /// Determines if the supplied entity tag matches any of the ones in the header.
+    pub fn matches(&self, etag: &str) -> bool {
+        match &self.0 {
+            Some(header) => header
+                .split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == "*" || tag == etag),
+            None => false,
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfNoneMatch {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let header = request.headers().get_one("If-None-Match");
+        Outcome::Success(IfNoneMatch(header.map(String::from)))
+    }
+}
+
+/// Response for a manifest fetch. Manifests are content-addressed, so the hash is used as
+/// entity tag and the response can be cached indefinitely, though only privately since
+/// fetching it requires authentication.
+pub struct ManifestResponse {
+    etag: String,
+    data: Option<Vec<u8>>,
+}
+
+impl<'r> Responder<'r, 'static> for ManifestResponse {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = match self.data {
+            Some(data) => data.respond_to(request)?,
+            None => Response::build().status(Status::NotModified).finalize(),
+        };
+        response.set_raw_header("ETag", self.etag);
+        response.set_raw_header("Cache-Control", "private, max-age=31536000, immutable");
+        Ok(response)
+    }
+}
+
+/// Request guard for payload downloads, holds the requested byte range and whether the
+/// client accepts the identity encoding.
+pub struct PayloadRequest {
+    range: Option<ByteRange>,
+    identity: bool,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for PayloadRequest {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let headers = request.headers();
+        Outcome::Success(PayloadRequest {
+            range: headers.get_one("Range").and_then(ByteRange::parse),
+            identity: headers
+                .get_one("Accept-Encoding")
+                .map(identity_acceptable)
+                .unwrap_or(true),
+        })
+    }
+}
+
+/// Response for a payload download, streams the data and marks partial responses. The length
+/// is announced up front and the connection is aborted if the stream fails midway through, so
+/// that clients can always detect a truncated payload.
+pub struct PayloadResponse {
+    stream: PayloadStream,
+    size: u64,
+    range: Option<(u64, u64)>,
+}
+
+impl<'r> Responder<'r, 'r> for PayloadResponse {
+    fn respond_to(self, _request: &'r Request<'_>) -> response::Result<'r> {
+        let length = match self.range {
+            Some((start, end)) => end + 1 - start,
+            None => self.size,
+        };
+        let mut response = Response::build()
+            .header(ContentType::Binary)
+            .streamed_body(StreamReader::new(self.stream))
+            .finalize();
+        response.set_raw_header("Content-Length", length.to_string());
+        response.set_raw_header("Accept-Ranges", "bytes");
+        response.set_raw_header("Vary", "Accept-Encoding");
+        if let Some((start, end)) = self.range {
+            response.set_status(Status::PartialContent);
+            response.set_raw_header(
+                "Content-Range",
+                format!("bytes {start}-{end}/{}", self.size),
+            );
+        }
+        Ok(response)
+    }
+}
+
+/// Look up a volume on behalf of the principal, which needs the given access to it. Volumes of
+/// other accounts are reported as not found rather than forbidden, so that their existence is
+/// not revealed, unless the account was granted access to them. System tokens can access
+/// volumes of any account.
+async fn volume_lookup(
+    conn: &mut AnyConnection,
+    volumes: &VolumeCache,
+    context: &Principal,
+    volume: &Pubkey,
+    access: Access,
+) -> Result<VolumeData, StorageError> {
+    let volume = volumes
+        .lookup(&mut *conn, volume)
+        .await?
+        .ok_or(StorageError::VolumeNotFound)?;
+    if context.authorized(volume.account()) {
+        return Ok(volume);
+    }
+    match acl::granted(conn, &volume, context.account()).await? {
+        Some(granted) if Access::from(granted) >= access => Ok(volume),
+        Some(_) => Err(StorageError::AccessDenied),
+        None => Err(StorageError::VolumeNotFound),
     }
 }
 
 #[post("/volume/<volume>")]
 async fn volume_create(
-    context: UserContext,
+    context: Principal,
     pool: &State<AnyPool>,
+    events: &State<Events>,
     volume: Pubkey,
 ) -> Result<(), StorageError> {
     let mut conn = pool.acquire().await?;
-    let account = Uuid::parse_str(&context.account().to_string()).unwrap();
+    let account = *context.account();
+    if Volume::lookup_deleted(&mut conn, &volume).await?.is_some() {
+        return Err(StorageError::VolumeDeleted);
+    }
     Volume::create(&mut conn, &volume, &account).await?;
+    events.publish(&account, AccountEvent::VolumeCreated { volume });
     Ok(())
 }
 
 #[get("/volume/<volume>")]
 async fn volume_get(
-    _context: UserContext,
+    context: Principal,
     pool: &State<AnyPool>,
+    volumes: &State<VolumeCache>,
     volume: Pubkey,
 ) -> Result<Json<VolumeInfo>, StorageError> {
     let mut conn = pool.acquire().await?;
-    let volume = Volume::lookup(&mut conn, &volume)
-        .await?
-        .ok_or(StorageError::VolumeNotFound)?;
-    Ok(Json(VolumeInfo {
+    let volume = volume_lookup(&mut conn, volumes, &context, &volume, Access::Read).await?;
+    Ok(Json(volume_info(&mut conn, &volume).await?))
+}
+
+/// Properties and statistics of a volume.
+async fn volume_info(
+    conn: &mut AnyConnection,
+    volume: &VolumeData,
+) -> Result<VolumeInfo, StorageError> {
+    let (snapshot_count, latest_generation, bytes_stored) = volume.volume().stats(conn).await?;
+    Ok(VolumeInfo {
         account: volume.account().clone(),
         writer: volume.writer().cloned(),
-    }))
+        retain_count: volume.retain_count(),
+        retain_age: volume.retain_age(),
+        snapshot_count,
+        latest_generation,
+        bytes_stored,
+        worm_period: volume.worm_period(),
+        immutable_until: volume.immutable_until(conn).await?,
+        storage_class: volume.storage_class(),
+        snapshots_pending: pending::count(conn, volume).await?,
+        pq_pubkey: volume.pq_pubkey().map(hex::encode),
+    })
 }
 
 #[delete("/volume/<volume>")]
 async fn volume_delete(
-    context: UserContext,
+    context: Principal,
+    signed: SignedRequest,
+    proof: VolumeProof,
     pool: &State<AnyPool>,
+    volumes: &State<VolumeCache>,
+    events: &State<Events>,
     volume: Pubkey,
 ) -> Result<(), StorageError> {
+    signed.verify_body(&[])?;
     let mut conn = pool.acquire().await?;
-    let volume = Volume::lookup(&mut conn, &volume)
-        .await?
-        .ok_or(StorageError::VolumeNotFound)?;
-    let account = Uuid::parse_str(&context.account().to_string()).unwrap();
-    if volume.account() == &account {
-        volume.delete(&mut conn).await?;
+    let volume = volume_lookup(&mut conn, volumes, &context, &volume, Access::Owner).await?;
+    if let Some(until) = volume.immutable_until(&mut conn).await? {
+        if until > now() {
+            return Err(StorageError::Immutable(until));
+        }
     }
+    signature::challenge_consume(&mut conn, &volume, proof.proof()?, now()).await?;
+    volume.delete(&mut conn, now()).await?;
+    volumes.invalidate(volume.pubkey());
+    events.publish(
+        volume.account(),
+        AccountEvent::VolumeDeleted {
+            volume: *volume.pubkey(),
+        },
+    );
     Ok(())
 }
 
-#[patch("/volume/<volume>", data = "<edit>")]
-async fn volume_edit(
-    _context: UserContext,
+/// Export the metadata of the volume as a portable archive.
+#[get("/volume/<volume>/export")]
+async fn volume_export(
+    context: Principal,
     pool: &State<AnyPool>,
+    volumes: &State<VolumeCache>,
+    volume: Pubkey,
+) -> Result<Json<VolumeArchive>, StorageError> {
+    let mut conn = pool.acquire().await?;
+    let volume = volume_lookup(&mut conn, volumes, &context, &volume, Access::Read).await?;
+    let mut snapshots = Snapshot::list(&mut conn, &volume.volume(), None, false).await?;
+    snapshots.sort_by_key(|snapshot| snapshot.snapshot());
+    Ok(Json(VolumeArchive {
+        version: VOLUME_ARCHIVE_VERSION,
+        volume: *volume.pubkey(),
+        writer: volume.writer().cloned(),
+        locked: volume.locked(),
+        retain_count: volume.retain_count(),
+        retain_age: volume.retain_age(),
+        pq_pubkey: volume.pq_pubkey().map(hex::encode),
+        snapshots: snapshots
+            .iter()
+            .map(|snapshot| snapshot.manifest_signed().clone())
+            .collect(),
+    }))
+}
+
+/// Import a volume archive, creating the volume for the account along with all of its
+/// snapshots. The import is atomic, if any manifest is rejected nothing is stored. Archives
+/// are subject to the payload limit.
+#[post("/volume/<volume>/import", data = "<archive>")]
+async fn volume_import(
+    context: Principal,
+    archive: Json<VolumeArchive>,
+    pool: &State<AnyPool>,
+    events: &State<Events>,
+    chaos: &State<Chaos>,
+    replication: &State<Replication>,
+    alerts: &State<Alerts>,
     volume: Pubkey,
-    edit: Json<VolumeEdit>,
 ) -> Result<(), StorageError> {
+    if archive.volume != volume || archive.version != VOLUME_ARCHIVE_VERSION {
+        return Err(StorageError::ArchiveInvalid);
+    }
+    let pq_pubkey = archive
+        .pq_pubkey
+        .as_deref()
+        .map(hex::decode)
+        .transpose()
+        .map_err(|_| StorageError::ArchiveInvalid)?;
+    for manifest in &archive.snapshots {
+        manifest
+            .validate_pinned(&volume, pq_pubkey.as_deref())
+            .map_err(|_| StorageError::ManifestInvalid)?;
+    }
+    let mut conn = pool.acquire().await?;
+    if Volume::lookup_deleted(&mut conn, &volume).await?.is_some() {
+        return Err(StorageError::VolumeDeleted);
+    }
+    if Volume::lookup(&mut conn, &volume).await?.is_some() {
+        return Err(StorageError::VolumeExists);
+    }
+
+    let account = *context.account();
+    let mut transaction = conn.begin().await?;
+    let created = Volume::create(&mut transaction, &volume, &account).await?;
+    if let Some(key) = &pq_pubkey {
+        created.pq_pubkey_set(&mut transaction, key).await?;
+    }
+    let data = created.fetch(&mut transaction).await?;
+    for manifest in &archive.snapshots {
+        snapshot_upload_manifest(
+            &mut transaction,
+            &data,
+            &manifest.data(),
+            chaos,
+            replication,
+            alerts,
+        )
+        .await?;
+    }
+
+    // attributes are applied last, as a locked volume does not accept snapshots.
+    created
+        .writer_set(&mut transaction, archive.writer.as_ref())
+        .await?;
+    created
+        .retention_set(&mut transaction, archive.retain_count, archive.retain_age)
+        .await?;
+    created.locked_set(&mut transaction, archive.locked).await?;
+    transaction.commit().await?;
+    info!(
+        "Imported volume {} with {} snapshots",
+        volume,
+        archive.snapshots.len()
+    );
+    events.publish(&account, AccountEvent::VolumeCreated { volume });
+    Ok(())
+}
+
+/// Status of replicating the volume to peers of this service.
+#[get("/volume/<volume>/replication")]
+async fn volume_replication(
+    context: Principal,
+    pool: &State<AnyPool>,
+    volumes: &State<VolumeCache>,
+    volume: Pubkey,
+) -> Result<Json<Vec<ReplicationStatus>>, StorageError> {
+    let mut conn = pool.acquire().await?;
+    let volume = volume_lookup(&mut conn, volumes, &context, &volume, Access::Read).await?;
+    Ok(Json(replicate::status(&mut conn, &volume).await?))
+}
+
+/// Issue a challenge for deleting the volume. Deleting a volume requires signing it with the
+/// volume key, so that a bearer token alone is not enough to destroy backups.
+#[post("/volume/<volume>/challenge")]
+async fn volume_challenge(
+    context: Principal,
+    pool: &State<AnyPool>,
+    volumes: &State<VolumeCache>,
+    volume: Pubkey,
+) -> Result<Json<VolumeChallenge>, StorageError> {
     let mut conn = pool.acquire().await?;
-    let volume = Volume::lookup(&mut conn, &volume)
+    let volume = volume_lookup(&mut conn, volumes, &context, &volume, Access::Owner).await?;
+    let challenge = signature::challenge_create(&mut conn, &volume, now()).await?;
+    Ok(Json(challenge))
+}
+
+#[post("/volume/<volume>/restore")]
+async fn volume_restore(
+    context: Principal,
+    pool: &State<AnyPool>,
+    events: &State<Events>,
+    purge: &State<Purge>,
+    volume: Pubkey,
+) -> Result<(), StorageError> {
+    let mut conn = pool.acquire().await?;
+    let volume = Volume::lookup_deleted(&mut conn, &volume)
         .await?
         .ok_or(StorageError::VolumeNotFound)?;
-    volume.edit(&mut conn, &edit).await?;
+    if !context.authorized(volume.account()) || purge.expired(&volume) {
+        return Err(StorageError::VolumeNotFound);
+    }
+    volume.restore(&mut conn).await?;
+    events.publish(
+        volume.account(),
+        AccountEvent::VolumeRestored {
+            volume: *volume.pubkey(),
+        },
+    );
     Ok(())
 }
 
-#[post("/volume/<volume>/snapshot", data = "<data>")]
-async fn volume_snapshot_upload(
-    _context: UserContext,
-    data: Vec<u8>,
+/// Mint a token that only allows uploading snapshots to this volume, valid for `ttl` (such
+/// as `30m` or `1h`).
+#[post("/volume/<volume>/upload-token?<ttl>")]
+async fn volume_upload_token(
+    context: Principal,
     pool: &State<AnyPool>,
+    volumes: &State<VolumeCache>,
+    tokens: &State<UploadTokens>,
     volume: Pubkey,
-) -> Result<Redirect, StorageError> {
+    ttl: Option<&str>,
+) -> Result<Json<UploadToken>, StorageError> {
+    if context
+        .api_key()
+        .map(ApiKeyData::restricted)
+        .unwrap_or(false)
+    {
+        return Err(StorageError::Forbidden);
+    }
+    let ttl = ttl
+        .map(parse_ttl)
+        .transpose()?
+        .unwrap_or(UPLOAD_TOKEN_TTL_DEFAULT);
     let mut conn = pool.acquire().await?;
-    let volume = Volume::lookup(&mut conn, &volume)
+    let volume = volume_lookup(&mut conn, volumes, &context, &volume, Access::Write).await?;
+
+    // tokens act as the account that minted them, so that they stop working once its grant
+    // is revoked. System tokens mint them on behalf of the owner.
+    let account = match context.authorized(volume.account()) {
+        true => *volume.account(),
+        false => *context.account(),
+    };
+    let grant = UploadGrant {
+        account,
+        volume: *volume.pubkey(),
+        expires: now() + ttl,
+    };
+    Ok(Json(UploadToken {
+        token: tokens.mint(&grant),
+        volume: grant.volume,
+        expires: grant.expires,
+    }))
+}
+
+/// Mint a URL for fetching the manifest of a snapshot without a bearer token, valid for
+/// `ttl` (such as `30m` or `1h`).
+#[post("/volume/<volume>/<snapshot>/presign?<ttl>")]
+async fn volume_snapshot_presign(
+    context: Principal,
+    pool: &State<AnyPool>,
+    volumes: &State<VolumeCache>,
+    tokens: &State<UploadTokens>,
+    volume: Pubkey,
+    snapshot: Hash,
+    ttl: Option<&str>,
+) -> Result<Json<PresignedUrl>, StorageError> {
+    if context
+        .api_key()
+        .map(ApiKeyData::restricted)
+        .unwrap_or(false)
+    {
+        return Err(StorageError::Forbidden);
+    }
+    let ttl = ttl
+        .map(parse_ttl)
+        .transpose()?
+        .unwrap_or(UPLOAD_TOKEN_TTL_DEFAULT);
+    let mut conn = pool.acquire().await?;
+    let volume = volume_lookup(&mut conn, volumes, &context, &volume, Access::Read).await?;
+    let snapshot = Snapshot::fetch_by_hash(&mut conn, &volume.volume(), &snapshot)
         .await?
-        .ok_or(StorageError::VolumeNotFound)?;
-    let manifest_signed =
-        ManifestSigned::parse(&data).map_err(|_| StorageError::ManifestInvalid)?;
-    match Snapshot::fetch_by_generation(
-        &mut conn,
+        .ok_or(StorageError::SnapshotNotFound)?;
+    let expires = now() + ttl;
+    let signature = tokens.presign(volume.pubkey(), &snapshot.hash(), expires);
+    let path = format!(
+        "/api/v1/volume/{}/{}?expires={expires}&signature={signature}",
+        volume.pubkey().to_hex(),
+        snapshot.hash().to_hex()
+    );
+    Ok(Json(PresignedUrl { path, expires }))
+}
+
+#[patch("/volume/<volume>", data = "<edit>")]
+async fn volume_edit(
+    context: Principal,
+    signed: SignedRequest,
+    pool: &State<AnyPool>,
+    volumes: &State<VolumeCache>,
+    events: &State<Events>,
+    volume: Pubkey,
+    edit: Vec<u8>,
+) -> Result<(), StorageError> {
+    // read as raw bytes, the signature covers the body exactly as it was sent.
+    signed.verify_body(&edit)?;
+    let edit: VolumeEdit = serde_json::from_slice(&edit).map_err(StorageError::BodyInvalid)?;
+    let mut conn = pool.acquire().await?;
+    let volume = volume_lookup(&mut conn, volumes, &context, &volume, Access::Owner).await?;
+    volume.edit(&mut conn, &edit).await?;
+    volumes.invalidate(volume.pubkey());
+    events.publish(
+        volume.account(),
+        AccountEvent::VolumeEdited {
+            volume: *volume.pubkey(),
+        },
+    );
+    Ok(())
+}
+
+/// Event published when a new snapshot was created.
+fn snapshot_created(volume: &VolumeData, snapshot: &SnapshotData) -> AccountEvent {
+    AccountEvent::SnapshotCreated {
+        volume: *volume.pubkey(),
+        snapshot: snapshot.hash(),
+        generation: snapshot.manifest().generation,
+    }
+}
+
+/// Uploads a single signed manifest into the given volume. Returns the manifest hash, the
+/// snapshot if it was newly created (`None` if an identical manifest was uploaded
+/// previously) and warnings about the new snapshot, including size anomalies.
+async fn snapshot_upload_manifest(
+    conn: &mut AnyConnection,
+    volume: &VolumeData,
+    data: &[u8],
+    chaos: &Chaos,
+    replication: &Replication,
+    alerts: &Alerts,
+) -> Result<(Hash, Option<SnapshotData>, Vec<Warning>), StorageError> {
+    let (manifest, signature) = Manifest::split(data).ok_or(StorageError::ManifestInvalid)?;
+    let hash = Manifest::hash(manifest);
+
+    // fast path: identical manifest was already uploaded, no need to decode anything.
+    if let Some(snapshot) =
+        Snapshot::lookup_by_hash(&mut *conn, &volume.volume(), &hash, signature).await?
+    {
+        info!(
+            "Existing manifest {} for volume {} as snapshot {}",
+            RedactedHash::new(hash),
+            volume.pubkey(),
+            snapshot.id()
+        );
+        return Ok((hash, None, vec![]));
+    }
+
+    // any other manifest with the same generation is a conflict. a concurrent upload can
+    // still get in between, that is caught by the unique constraint on the generation.
+    let manifest_signed = ManifestSigned::parse(data).map_err(|_| StorageError::ManifestInvalid)?;
+    if Snapshot::fetch_by_generation(
+        &mut *conn,
         &volume.volume(),
         manifest_signed.manifest.generation,
     )
     .await?
+    .is_some()
     {
-        // snapshot does not exist yet, all good.
-        None => {}
-        Some(snapshot) => {
-            if *snapshot.manifest_signed() != manifest_signed {
-                return Err(StorageError::ManifestExists);
-            } else {
-                info!(
-                    "Existing manifest for volume {} generation {}",
+        return Err(StorageError::ManifestExists);
+    }
+
+    let snapshot = Snapshot::create_from_manifest(&mut *conn, volume, data)
+        .await
+        .map_err(|error| match error {
+            SnapshotError::GenerationExists(_) => StorageError::ManifestExists,
+            error => error.into(),
+        })?;
+    chaos.database()?;
+    label::stamp(&mut *conn, &snapshot, volume.account()).await?;
+    replication.enqueue(&mut *conn, &snapshot).await?;
+    let snapshot = snapshot.fetch(&mut *conn).await?;
+    info!(
+        "Accepted manifest {} for volume {}: {}",
+        RedactedHash::new(hash),
+        volume.pubkey(),
+        RedactedManifest::new(snapshot.manifest())
+    );
+    let mut warnings = snapshot.warnings(&mut *conn, now()).await?;
+    let settings = alert::settings(&mut *conn, volume.account()).await?;
+    let factor = alerts.anomaly_factor(&settings.alerts);
+    warnings.extend(alert::size_anomaly(&mut *conn, &snapshot, factor).await?);
+    Ok((hash, Some(snapshot), warnings))
+}
+
+/// Determines if an upload failed because the parent of the snapshot was not uploaded yet.
+fn missing_parent(error: &StorageError) -> bool {
+    matches!(
+        error,
+        StorageError::Snapshot(SnapshotError::MissingParent(_) | SnapshotError::ParentNotFound(_))
+    )
+}
+
+/// Keep a snapshot whose parent was not uploaded yet aside, until the parent is uploaded.
+/// The signature is checked now, everything else once it is linked to its parent.
+async fn snapshot_upload_pending(
+    conn: &mut AnyConnection,
+    volume: &VolumeData,
+    data: &[u8],
+    limit: u64,
+) -> Result<Hash, StorageError> {
+    let manifest = ManifestSigned::parse(data).map_err(|_| StorageError::ManifestInvalid)?;
+    manifest
+        .validate_pinned(volume.pubkey(), volume.pq_pubkey())
+        .map_err(|_| StorageError::ManifestInvalid)?;
+    let parent = manifest
+        .manifest
+        .parent
+        .as_ref()
+        .ok_or(StorageError::ManifestInvalid)?;
+    pending::store(conn, volume, &manifest, &parent.hash, now(), limit).await?;
+    info!(
+        "Keeping manifest {} for volume {} until its parent {} is uploaded",
+        RedactedHash::new(manifest.hash()),
+        volume.pubkey(),
+        RedactedHash::new(parent.hash)
+    );
+    Ok(manifest.hash())
+}
+
+/// Store the snapshots that were waiting for the given snapshot to be uploaded, and in turn
+/// the ones waiting for those. Snapshots that turn out to be invalid are dropped. Returns the
+/// volumes the snapshots were stored in, along with the events to publish for them.
+async fn snapshot_link_pending(
+    conn: &mut AnyConnection,
+    snapshot: &SnapshotData,
+    chaos: &Chaos,
+    replication: &Replication,
+    alerts: &Alerts,
+) -> Result<Vec<(VolumeData, AccountEvent)>, StorageError> {
+    let mut linked = vec![];
+    let mut parents = vec![snapshot.hash()];
+    while let Some(parent) = parents.pop() {
+        for (volume, data) in pending::take(&mut *conn, &parent).await? {
+            let volume = volume.fetch(&mut *conn).await?;
+            if volume.deleted_at().is_some() {
+                continue;
+            }
+            match snapshot_upload_manifest(&mut *conn, &volume, &data, chaos, replication, alerts)
+                .await
+            {
+                Ok((hash, Some(child), _)) => {
+                    info!(
+                        "Linked pending manifest {} to its parent {}",
+                        RedactedHash::new(hash),
+                        RedactedHash::new(parent)
+                    );
+                    linked.push((volume.clone(), snapshot_created(&volume, &child)));
+                    parents.push(hash);
+                }
+                Ok((_, None, _)) => {}
+                // the parent may be in a different volume than the one the child expects. it
+                // was just taken from the pending snapshots, so it does not count against the
+                // limit again.
+                Err(error) if missing_parent(&error) => {
+                    snapshot_upload_pending(&mut *conn, &volume, &data, u64::MAX).await?;
+                }
+                Err(error) => warn!(
+                    "Dropping pending manifest for parent {} in volume {}: {}",
+                    RedactedHash::new(parent),
                     volume.pubkey(),
-                    manifest_signed.manifest.generation
-                );
-                return Ok(Redirect::to(snapshot.hash().to_hex()));
+                    error
+                ),
+            }
+        }
+    }
+    Ok(linked)
+}
+
+/// Name of the limit for manifest uploads.
+pub const MANIFEST_LIMIT: &str = "manifest";
+
+/// Limit for manifest uploads, if not configured.
+pub const MANIFEST_LIMIT_DEFAULT: ByteUnit = ByteUnit::Kibibyte(64);
+
+/// Name of the limit for payloads uploaded to the blob backend, unlimited if not configured.
+pub const PAYLOAD_LIMIT: &str = "payload";
+
+#[post("/volume/<volume>/snapshot", data = "<data>")]
+async fn volume_snapshot_upload(
+    context: Principal,
+    data: Data<'_>,
+    limits: &Limits,
+    pool: &State<AnyPool>,
+    volumes: &State<VolumeCache>,
+    events: &State<Events>,
+    chaos: &State<Chaos>,
+    replication: &State<Replication>,
+    alerts: &State<Alerts>,
+    limit: &State<UploadLimit>,
+    pending: &State<PendingUploads>,
+    volume: Pubkey,
+    idempotency: IdempotencyKey,
+) -> Result<Json<SnapshotUploaded>, StorageError> {
+    let _permit = limit
+        .acquire(context.account())
+        .ok_or(StorageError::TooManyUploads(limit.limit()))?;
+    let data_limit = limits.get(MANIFEST_LIMIT).unwrap_or(MANIFEST_LIMIT_DEFAULT);
+    let data = data.open(data_limit).into_bytes().await?;
+    if !data.is_complete() {
+        return Err(StorageError::ManifestTooLarge(data_limit.as_u64()));
+    }
+    let data = data.into_inner();
+    let mut conn = pool.acquire().await?;
+    let volume = volume_lookup(&mut conn, volumes, &context, &volume, Access::Write).await?;
+    let (manifest, signature) = Manifest::split(&data).ok_or(StorageError::ManifestInvalid)?;
+    let request = Manifest::hash(manifest);
+
+    // replay response if this request was already processed
+    if let Some(hash) = idempotency.lookup(&mut conn, &volume.volume()).await? {
+        idempotency_check(hash, request)?;
+        return upload_response(&mut conn, &volume, hash, true, vec![]).await;
+    }
+
+    // snapshot and idempotency key are stored atomically, when a concurrent request with
+    // the same key wins, its response is replayed.
+    let mut transaction = conn.begin().await?;
+    volume.volume().lock_for_update(&mut transaction).await?;
+    let result = match snapshot_upload_manifest(
+        &mut transaction,
+        &volume,
+        &data,
+        chaos,
+        replication,
+        alerts,
+    )
+    .await
+    {
+        Ok((hash, snapshot, warnings)) => idempotency
+            .store(&mut transaction, &volume.volume(), &hash)
+            .await
+            .map(|_| (hash, snapshot, warnings))
+            .map_err(StorageError::from),
+        Err(error) => Err(error),
+    };
+    let result = match result {
+        Ok((hash, Some(snapshot), warnings)) => {
+            snapshot_link_pending(&mut transaction, &snapshot, chaos, replication, alerts)
+                .await
+                .map(|linked| (hash, Some(snapshot), warnings, linked))
+        }
+        Ok((hash, None, warnings)) => Ok((hash, None, warnings, vec![])),
+        Err(error) => Err(error),
+    };
+    let (hash, snapshot, warnings, linked) = match result {
+        Ok(result) => {
+            transaction.commit().await?;
+            result
+        }
+        Err(error) => {
+            transaction.rollback().await?;
+            if let Some(hash) = idempotency.lookup(&mut conn, &volume.volume()).await? {
+                idempotency_check(hash, request)?;
+                return upload_response(&mut conn, &volume, hash, true, vec![]).await;
+            }
+            // a concurrent upload of the identical manifest won.
+            if let StorageError::ManifestExists = error {
+                let existing =
+                    Snapshot::lookup_by_hash(&mut conn, &volume.volume(), &request, signature)
+                        .await?;
+                if existing.is_some() {
+                    return upload_response(&mut conn, &volume, request, true, vec![]).await;
+                }
+            }
+            if pending.enabled && missing_parent(&error) {
+                let hash =
+                    snapshot_upload_pending(&mut conn, &volume, &data, pending.limit).await?;
+                return Ok(Json(SnapshotUploaded {
+                    hash,
+                    deduplicated: false,
+                    payload: None,
+                    warnings: vec![],
+                    pending: true,
+                }));
             }
+            return Err(error);
+        }
+    };
+    for (volume, event) in linked {
+        volumes.invalidate(volume.pubkey());
+        events.publish(volume.account(), event);
+    }
+    let deduplicated = snapshot.is_none();
+    if let Some(snapshot) = snapshot {
+        // the first snapshot sets the writer of the volume.
+        if volume.writer().is_none() {
+            volumes.invalidate(volume.pubkey());
         }
+        events.publish(volume.account(), snapshot_created(&volume, &snapshot));
+    }
+    upload_response(&mut conn, &volume, hash, deduplicated, warnings).await
+}
+
+/// Check that an upload which used the same idempotency key was for the same manifest.
+fn idempotency_check(hash: Hash, request: Hash) -> Result<(), StorageError> {
+    if hash != request {
+        return Err(StorageError::IdempotencyKeyReused);
+    }
+    Ok(())
+}
+
+/// Response for a single manifest upload, along with where the snapshot's payload is
+/// stored. Manifests that were stored already are reported as deduplicated.
+async fn upload_response(
+    conn: &mut AnyConnection,
+    volume: &VolumeData,
+    hash: Hash,
+    deduplicated: bool,
+    warnings: Vec<Warning>,
+) -> Result<Json<SnapshotUploaded>, StorageError> {
+    let snapshot = Snapshot::fetch_by_hash(&mut *conn, &volume.volume(), &hash)
+        .await?
+        .ok_or(StorageError::SnapshotNotFound)?;
+    let payload = if reconcile::recorded(&mut *conn, &snapshot.snapshot()).await? {
+        PayloadStatus::Stored
+    } else if data_cid(&snapshot.manifest().data).is_ok() {
+        PayloadStatus::Ipfs
+    } else {
+        PayloadStatus::Missing
     };
-    let snapshot = Snapshot::create_from_manifest(&mut conn, &volume, &data).await?;
-    let snapshot = snapshot.fetch(&mut conn).await?;
-    Ok(Redirect::to(snapshot.hash().to_hex()))
+    Ok(Json(SnapshotUploaded {
+        hash,
+        deduplicated,
+        payload: Some(payload),
+        warnings,
+        pending: false,
+    }))
 }
 
-#[get("/volume/<volume>/snapshots?<parent>&<root>")]
+/// Upload a batch of signed manifests. These are validated in generation order (so that
+/// parents are stored before their children) inside a single transaction. If any of them
+/// fails, nothing is stored and the results indicate which manifest failed.
+#[post("/volume/<volume>/snapshots", data = "<manifests>")]
+async fn volume_snapshot_upload_batch(
+    context: Principal,
+    manifests: Json<Vec<ManifestSigned>>,
+    pool: &State<AnyPool>,
+    volumes: &State<VolumeCache>,
+    events: &State<Events>,
+    chaos: &State<Chaos>,
+    replication: &State<Replication>,
+    alerts: &State<Alerts>,
+    limit: &State<UploadLimit>,
+    pending: &State<PendingUploads>,
+    volume: Pubkey,
+) -> Result<status::Custom<Json<Vec<SnapshotUploadResult>>>, StorageError> {
+    let _permit = limit
+        .acquire(context.account())
+        .ok_or(StorageError::TooManyUploads(limit.limit()))?;
+    let manifests = manifests.into_inner();
+    let mut conn = pool.acquire().await?;
+    let volume = volume_lookup(&mut conn, volumes, &context, &volume, Access::Write).await?;
+
+    // process in dependency order, but report results in request order
+    let mut order: Vec<usize> = (0..manifests.len()).collect();
+    order.sort_by_key(|index| manifests[*index].manifest.generation);
+
+    let mut results: Vec<SnapshotUploadResult> = manifests
+        .iter()
+        .map(|manifest| SnapshotUploadResult {
+            hash: manifest.hash(),
+            status: SnapshotUploadStatus::Skipped,
+            warnings: vec![],
+        })
+        .collect();
+    let mut transaction = conn.begin().await?;
+    volume.volume().lock_for_update(&mut transaction).await?;
+    let mut failed = false;
+    let mut created = vec![];
+    for index in order {
+        let data = manifests[index].data();
+        let upload = match snapshot_upload_manifest(
+            &mut transaction,
+            &volume,
+            &data,
+            chaos,
+            replication,
+            alerts,
+        )
+        .await
+        {
+            Ok((_, None, _)) => SnapshotUploadStatus::Existing,
+            Ok((_, Some(snapshot), warnings)) => {
+                created.push((volume.clone(), snapshot_created(&volume, &snapshot)));
+                created.extend(
+                    snapshot_link_pending(&mut transaction, &snapshot, chaos, replication, alerts)
+                        .await?,
+                );
+                results[index].warnings = warnings;
+                SnapshotUploadStatus::Created
+            }
+            Err(error) if pending.enabled && missing_parent(&error) => {
+                match snapshot_upload_pending(&mut transaction, &volume, &data, pending.limit).await
+                {
+                    Ok(_) => SnapshotUploadStatus::Pending,
+                    Err(error) => SnapshotUploadStatus::Failed {
+                        message: error.to_string(),
+                    },
+                }
+            }
+            Err(error) => SnapshotUploadStatus::Failed {
+                message: error.to_string(),
+            },
+        };
+        failed = matches!(upload, SnapshotUploadStatus::Failed { .. });
+        results[index].status = upload;
+        if failed {
+            break;
+        }
+    }
+
+    if failed {
+        transaction.rollback().await?;
+        for result in results.iter_mut() {
+            if !matches!(result.status, SnapshotUploadStatus::Failed { .. }) {
+                result.status = SnapshotUploadStatus::Skipped;
+                result.warnings.clear();
+            }
+        }
+        Ok(status::Custom(Status::UnprocessableEntity, Json(results)))
+    } else {
+        transaction.commit().await?;
+        for (volume, event) in created {
+            volumes.invalidate(volume.pubkey());
+            events.publish(volume.account(), event);
+        }
+        Ok(status::Custom(Status::Ok, Json(results)))
+    }
+}
+
+/// List snapshots, by default ordered by generation. Use `order` (`generation`, `creation` or
+/// `size`) and `dir` (`asc` or `desc`) to choose the order, ties are broken by hash.
+#[get("/volume/<volume>/snapshots?<parent>&<root>&<order>&<dir>")]
 async fn volume_snapshot_list(
-    _context: UserContext,
+    context: Principal,
     pool: &State<AnyPool>,
+    volumes: &State<VolumeCache>,
     volume: Pubkey,
     parent: Option<Hash>,
     root: bool,
+    order: Option<&str>,
+    dir: Option<&str>,
 ) -> Result<Json<Vec<Hash>>, StorageError> {
+    let mut ordering = SnapshotOrdering::default();
+    if let Some(order) = order {
+        ordering.order = order.parse().map_err(StorageError::InvalidOrder)?;
+    }
+    if let Some(dir) = dir {
+        ordering.direction = dir.parse().map_err(StorageError::InvalidOrder)?;
+    }
     let mut conn = pool.acquire().await?;
-    let volume = Volume::lookup(&mut conn, &volume)
-        .await?
-        .ok_or(StorageError::VolumeNotFound)?;
+    let volume = volume_lookup(&mut conn, volumes, &context, &volume, Access::Read).await?;
     let parent = match parent {
         Some(hash) => Some(
             Snapshot::fetch_by_hash(&mut conn, &volume.volume(), &hash)
@@ -180,27 +1158,836 @@ async fn volume_snapshot_list(
         ),
         None => None,
     };
-    let snapshots = Snapshot::list(&mut conn, &volume.volume(), parent.as_ref(), root).await?;
+    let snapshots = Snapshot::list_ordered(
+        &mut conn,
+        &volume.volume(),
+        parent.as_ref(),
+        root,
+        &ordering,
+    )
+    .await?;
     Ok(Json(
         snapshots.iter().map(|snapshot| snapshot.hash()).collect(),
     ))
 }
 
-#[get("/volume/<volume>/<snapshot>")]
+/// List snapshots that were uploaded before their parent, and are waiting for it.
+#[get("/volume/<volume>/snapshots/pending")]
+async fn volume_snapshot_pending(
+    context: Principal,
+    pool: &State<AnyPool>,
+    volumes: &State<VolumeCache>,
+    volume: Pubkey,
+) -> Result<Json<Vec<SnapshotPending>>, StorageError> {
+    let mut conn = pool.acquire().await?;
+    let volume = volume_lookup(&mut conn, volumes, &context, &volume, Access::Read).await?;
+    Ok(Json(pending::list(&mut conn, &volume).await?))
+}
+
+/// Check which of the given snapshot hashes exist in the volume, returns the ones that do.
+#[post("/volume/<volume>/snapshots/exists", data = "<hashes>")]
+async fn volume_snapshot_exists(
+    context: Principal,
+    pool: &State<AnyPool>,
+    volumes: &State<VolumeCache>,
+    volume: Pubkey,
+    hashes: Json<Vec<Hash>>,
+) -> Result<Json<Vec<Hash>>, StorageError> {
+    let mut conn = pool.acquire().await?;
+    let volume = volume_lookup(&mut conn, volumes, &context, &volume, Access::Read).await?;
+    let existing = Snapshot::existing(&mut conn, &volume.volume(), &hashes).await?;
+    Ok(Json(existing))
+}
+
+/// Maximum number of manifests that can be fetched in a single request.
+const MANIFEST_BATCH_MAX: usize = 1000;
+
+/// Fetch the signed manifests of several snapshots of the volume at once, in the order they
+/// were requested. Snapshots that do not exist are left out. Manifests are encoded as with
+/// [`ManifestSigned::encode_batch`], prefixed with their length.
+#[post("/volume/<volume>/manifests", data = "<hashes>")]
+async fn volume_manifest_batch(
+    context: Principal,
+    pool: &State<AnyPool>,
+    volumes: &State<VolumeCache>,
+    volume: Pubkey,
+    hashes: Json<Vec<Hash>>,
+) -> Result<Vec<u8>, StorageError> {
+    if hashes.len() > MANIFEST_BATCH_MAX {
+        return Err(StorageError::BatchTooLarge(MANIFEST_BATCH_MAX));
+    }
+    let mut conn = pool.acquire().await?;
+    let volume = volume_lookup(&mut conn, volumes, &context, &volume, Access::Read).await?;
+    let mut manifests = vec![];
+    for hash in hashes.iter() {
+        if let Some(snapshot) = Snapshot::fetch_by_hash(&mut conn, &volume.volume(), hash).await? {
+            manifests.push(snapshot.manifest_signed().clone());
+        }
+    }
+    Ok(ManifestSigned::encode_batch(&manifests))
+}
+
+/// Default number of snapshots per page in the v2 listing API.
+const SNAPSHOT_PAGE_LIMIT: u64 = 100;
+
+/// Maximum number of snapshots per page in the v2 listing API.
+const SNAPSHOT_PAGE_LIMIT_MAX: u64 = 1000;
+
+/// List snapshots with pagination, returning full records. The cursor identifies the last
+/// snapshot of the previous page, so pages are stable even as new snapshots are uploaded.
+#[get("/volume/<volume>/snapshots?<parent>&<root>&<cursor>&<limit>")]
+async fn volume_snapshot_list_v2(
+    context: Principal,
+    pool: &State<AnyPool>,
+    volumes: &State<VolumeCache>,
+    volume: Pubkey,
+    parent: Option<Hash>,
+    root: bool,
+    cursor: Option<&str>,
+    limit: Option<u64>,
+) -> Result<Json<SnapshotPage>, StorageError> {
+    let mut conn = pool.acquire().await?;
+    let volume = volume_lookup(&mut conn, volumes, &context, &volume, Access::Read).await?;
+    let parent = match parent {
+        Some(hash) => Some(
+            Snapshot::fetch_by_hash(&mut conn, &volume.volume(), &hash)
+                .await?
+                .ok_or_else(|| StorageError::SnapshotNotFound)?
+                .snapshot(),
+        ),
+        None => None,
+    };
+    let after = match cursor {
+        Some(cursor) => Some(Snapshot::from(
+            cursor
+                .parse::<i64>()
+                .map_err(|_| StorageError::InvalidCursor)?,
+        )),
+        None => None,
+    };
+    let limit = limit
+        .unwrap_or(SNAPSHOT_PAGE_LIMIT)
+        .clamp(1, SNAPSHOT_PAGE_LIMIT_MAX);
+
+    // fetch one more than requested, to know if there is another page
+    let mut snapshots = Snapshot::list_page(
+        &mut conn,
+        &volume.volume(),
+        parent.as_ref(),
+        root,
+        after.as_ref(),
+        limit + 1,
+    )
+    .await?;
+    let cursor = if snapshots.len() as u64 > limit {
+        snapshots.truncate(limit as usize);
+        snapshots
+            .last()
+            .map(|snapshot| snapshot.snapshot().id().to_string())
+    } else {
+        None
+    };
+    Ok(Json(SnapshotPage {
+        snapshots: snapshots.iter().map(snapshot_record).collect(),
+        cursor,
+    }))
+}
+
+fn snapshot_record(snapshot: &SnapshotData) -> SnapshotRecord {
+    let manifest = snapshot.manifest();
+    SnapshotRecord {
+        hash: snapshot.hash(),
+        generation: manifest.generation,
+        creation: manifest.creation,
+        size: manifest.size,
+        size_total: manifest.size_total,
+        parent: manifest.parent.as_ref().map(|parent| parent.hash),
+        data: manifest.data.clone(),
+        storage_class: snapshot.storage_class(),
+    }
+}
+
+/// Fetch the signed manifest of a snapshot. Requests are authenticated either with a bearer
+/// token or by being pre-signed. Ranked below the other routes of the volume, whose paths it
+/// would otherwise collide with.
+#[get("/volume/<volume>/<snapshot>?<expires>&<signature>", rank = 2)]
 async fn volume_snapshot_get(
+    context: Option<Principal>,
     pool: &State<AnyPool>,
+    volumes: &State<VolumeCache>,
+    tokens: &State<UploadTokens>,
     volume: Pubkey,
     snapshot: Hash,
-) -> Result<Vec<u8>, StorageError> {
+    expires: Option<u64>,
+    signature: Option<&str>,
+    if_none_match: IfNoneMatch,
+) -> Result<ManifestResponse, StorageError> {
+    if context.is_none() {
+        let (expires, signature) = expires.zip(signature).ok_or(StorageError::Unauthorized)?;
+        tokens
+            .presigned_verify(&volume, &snapshot, expires, signature, now())
+            .map_err(|_| StorageError::Unauthorized)?;
+    }
     let mut conn = pool.acquire().await?;
-    let volume = Volume::lookup(&mut conn, &volume)
-        .await?
-        .ok_or(StorageError::VolumeNotFound)?;
+    let volume = match &context {
+        Some(context) => volume_lookup(&mut conn, volumes, context, &volume, Access::Read).await?,
+        None => volumes
+            .lookup(&mut conn, &volume)
+            .await?
+            .ok_or(StorageError::VolumeNotFound)?,
+    };
     let snapshot = Snapshot::fetch_by_hash(&mut conn, &volume.volume(), &snapshot)
         .await?
         .ok_or(StorageError::SnapshotNotFound)?;
+    let etag = format!("\"{}\"", snapshot.hash().to_hex());
+    if if_none_match.matches(&etag) {
+        return Ok(ManifestResponse { etag, data: None });
+    }
     let manifest = snapshot.manifest_signed().data();
-    Ok(manifest)
+    Ok(ManifestResponse {
+        etag,
+        data: Some(manifest),
+    })
+}
+
+/// Delete a snapshot and release its payload. Snapshots that other snapshots have as their
+/// parent cannot be deleted, since these could no longer be restored.
+#[delete("/volume/<volume>/<snapshot>")]
+async fn volume_snapshot_delete(
+    context: Principal,
+    pool: &State<AnyPool>,
+    volumes: &State<VolumeCache>,
+    ipfs: &State<Option<Ipfs>>,
+    blobs: &State<Option<Blobs>>,
+    volume: Pubkey,
+    snapshot: Hash,
+) -> Result<(), StorageError> {
+    let mut conn = pool.acquire().await?;
+    let volume = volume_lookup(&mut conn, volumes, &context, &volume, Access::Owner).await?;
+    let snapshot = Snapshot::fetch_by_hash(&mut conn, &volume.volume(), &snapshot)
+        .await?
+        .ok_or(StorageError::SnapshotNotFound)?;
+    if snapshot.immutable(volume.worm_period(), now()) {
+        let until = snapshot.uploaded().unwrap_or_default();
+        return Err(StorageError::Immutable(
+            until.saturating_add(volume.worm_period().unwrap_or_default()),
+        ));
+    }
+    if snapshot.snapshot().has_children(&mut conn).await? {
+        return Err(StorageError::HasChildren);
+    }
+    purge::remove(
+        &mut conn,
+        &snapshot,
+        ipfs.inner().as_ref(),
+        blobs.inner().as_ref(),
+    )
+    .await?;
+    info!(
+        "Deleted snapshot {} of volume {}",
+        RedactedHash::new(snapshot.hash()),
+        volume.pubkey()
+    );
+    Ok(())
+}
+
+/// Walk the parents of a snapshot back to the root and validate every link, so that a
+/// multi-generation restore can be checked before attempting it.
+#[get("/volume/<volume>/<snapshot>/chain/validate")]
+async fn volume_snapshot_chain_validate(
+    context: Principal,
+    pool: &State<AnyPool>,
+    volumes: &State<VolumeCache>,
+    volume: Pubkey,
+    snapshot: Hash,
+) -> Result<Json<ChainReport>, StorageError> {
+    let mut conn = pool.acquire().await?;
+    let volume = volume_lookup(&mut conn, volumes, &context, &volume, Access::Read).await?;
+    let snapshot = Snapshot::fetch_by_hash(&mut conn, &volume.volume(), &snapshot)
+        .await?
+        .ok_or(StorageError::SnapshotNotFound)?;
+    let chain = snapshot.ancestors(&mut conn).await?;
+    Ok(Json(chain_validate(&chain)))
+}
+
+/// Fetch the signed manifests of a snapshot and all of its ancestors, ordered from the
+/// snapshot to the root, so that restores get the whole chain in a single request. Parents in
+/// volumes of other accounts are not resolved, the response is then marked as incomplete.
+#[get("/volume/<volume>/<snapshot>/ancestry")]
+async fn volume_snapshot_ancestry(
+    context: Principal,
+    pool: &State<AnyPool>,
+    volumes: &State<VolumeCache>,
+    volume: Pubkey,
+    snapshot: Hash,
+) -> Result<Json<SnapshotAncestry>, StorageError> {
+    let mut conn = pool.acquire().await?;
+    let volume = volume_lookup(&mut conn, volumes, &context, &volume, Access::Read).await?;
+    let snapshot = Snapshot::fetch_by_hash(&mut conn, &volume.volume(), &snapshot)
+        .await?
+        .ok_or(StorageError::SnapshotNotFound)?;
+    let (chain, complete) = snapshot.ancestry(&mut conn, volume.account()).await?;
+    Ok(Json(SnapshotAncestry {
+        snapshots: chain
+            .into_iter()
+            .map(|(snapshot, volume)| AncestryLink {
+                volume,
+                manifest: snapshot.manifest_signed().clone(),
+            })
+            .collect(),
+        complete,
+    }))
+}
+
+/// Proxy the (encrypted) payload of a snapshot from IPFS. Supports fetching a single byte
+/// range, the payload is streamed and never held in memory in full.
+#[get("/volume/<volume>/<snapshot>/payload")]
+async fn volume_snapshot_payload(
+    context: Principal,
+    pool: &State<AnyPool>,
+    volumes: &State<VolumeCache>,
+    ipfs: &State<Option<Ipfs>>,
+    volume: Pubkey,
+    snapshot: Hash,
+    request: PayloadRequest,
+) -> Result<PayloadResponse, StorageError> {
+    let ipfs = ipfs.inner().as_ref().ok_or(StorageError::IpfsUnavailable)?;
+    if !request.identity {
+        return Err(StorageError::NotAcceptable);
+    }
+    let mut conn = pool.acquire().await?;
+    let volume = volume_lookup(&mut conn, volumes, &context, &volume, Access::Read).await?;
+    let snapshot = Snapshot::fetch_by_hash(&mut conn, &volume.volume(), &snapshot)
+        .await?
+        .ok_or(StorageError::SnapshotNotFound)?;
+    let cid = data_cid(&snapshot.manifest().data)?;
+    let size = ipfs.size(cid).await?;
+    let range = match request.range {
+        Some(range) => Some(
+            range
+                .resolve(size)
+                .ok_or(StorageError::RangeNotSatisfiable(size))?,
+        ),
+        None => None,
+    };
+    let stream = match range {
+        Some((start, end)) => ipfs.cat(cid, start, end).await?,
+        None if size == 0 => Box::pin(rocket::futures::stream::empty()),
+        None => ipfs.cat(cid, 0, size - 1).await?,
+    };
+    Ok(PayloadResponse {
+        stream,
+        size,
+        range,
+    })
+}
+
+/// Store the (encrypted) payload of a snapshot on this service, for deployments without
+/// IPFS. The manifest must be uploaded first, the payload is streamed to the blob backend.
+#[put("/volume/<volume>/<snapshot>/data", data = "<data>")]
+async fn volume_snapshot_data_upload(
+    context: Principal,
+    pool: &State<AnyPool>,
+    volumes: &State<VolumeCache>,
+    blobs: &State<Option<Blobs>>,
+    chaos: &State<Chaos>,
+    limit: &State<UploadLimit>,
+    limits: &Limits,
+    volume: Pubkey,
+    snapshot: Hash,
+    data: Data<'_>,
+) -> Result<(), StorageError> {
+    let _permit = limit
+        .acquire(context.account())
+        .ok_or(StorageError::TooManyUploads(limit.limit()))?;
+    let blobs = blobs
+        .inner()
+        .as_ref()
+        .ok_or(StorageError::BlobsUnavailable)?;
+    let mut conn = pool.acquire().await?;
+    let volume = volume_lookup(&mut conn, volumes, &context, &volume, Access::Write).await?;
+    let snapshot = Snapshot::fetch_by_hash(&mut conn, &volume.volume(), &snapshot)
+        .await?
+        .ok_or(StorageError::SnapshotNotFound)?;
+    drop(conn);
+
+    // payloads are immutable, just like the manifests referencing them.
+    let key = snapshot.hash().to_hex();
+    if blobs.backend().size(&key).await?.is_some() {
+        return Err(StorageError::BlobExists);
+    }
+    // the backend reads one byte past the limit to tell oversized payloads apart from ones
+    // that fit, and does not store them.
+    let data_limit = limits.get(PAYLOAD_LIMIT).unwrap_or(ByteUnit::max_value());
+    let reader = DigestReader::new(Box::pin(data.open(data_limit + 1u64)));
+    let digest = reader.digest();
+    let size = match blobs
+        .backend()
+        .put(&key, Box::pin(reader), data_limit.as_u64())
+        .await
+    {
+        Err(BlobError::TooLarge(limit)) => return Err(StorageError::PayloadTooLarge(limit)),
+        Err(BlobError::Exists(_)) => return Err(StorageError::BlobExists),
+        result => result?,
+    };
+
+    // the digest lets the payload be verified against bit rot later on. if it cannot be
+    // recorded, the payload is removed again so that the upload can be retried.
+    let digest = digest.finalize();
+    if let Err(error) = payload_record(pool, chaos, &snapshot, size, &digest).await {
+        if let Err(e) = blobs.backend().delete(&key).await {
+            warn!("Error removing payload {}: {}", RedactedHash::new(&key), e);
+        }
+        return Err(error);
+    }
+    info!(
+        "Stored payload of snapshot {} ({} bytes)",
+        RedactedHash::new(&key),
+        size
+    );
+    Ok(())
+}
+
+async fn payload_record(
+    pool: &AnyPool,
+    chaos: &Chaos,
+    snapshot: &SnapshotData,
+    size: u64,
+    digest: &[u8],
+) -> Result<(), StorageError> {
+    let mut conn = pool.acquire().await?;
+    chaos.database()?;
+    reconcile::record(&mut conn, &snapshot.snapshot(), size, digest).await?;
+    Ok(())
+}
+
+/// Fetch the (encrypted) payload of a snapshot that was stored on this service. Supports
+/// fetching a single byte range, the payload is streamed.
+#[get("/volume/<volume>/<snapshot>/data")]
+async fn volume_snapshot_data(
+    context: Principal,
+    pool: &State<AnyPool>,
+    volumes: &State<VolumeCache>,
+    blobs: &State<Option<Blobs>>,
+    volume: Pubkey,
+    snapshot: Hash,
+    request: PayloadRequest,
+) -> Result<PayloadResponse, StorageError> {
+    let blobs = blobs
+        .inner()
+        .as_ref()
+        .ok_or(StorageError::BlobsUnavailable)?;
+    if !request.identity {
+        return Err(StorageError::NotAcceptable);
+    }
+    let mut conn = pool.acquire().await?;
+    let volume = volume_lookup(&mut conn, volumes, &context, &volume, Access::Read).await?;
+    let snapshot = Snapshot::fetch_by_hash(&mut conn, &volume.volume(), &snapshot)
+        .await?
+        .ok_or(StorageError::SnapshotNotFound)?;
+    let key = snapshot.hash().to_hex();
+    let size = blobs
+        .backend()
+        .size(&key)
+        .await?
+        .ok_or(StorageError::BlobNotFound)?;
+    let range = match request.range {
+        Some(range) => Some(
+            range
+                .resolve(size)
+                .ok_or(StorageError::RangeNotSatisfiable(size))?,
+        ),
+        None => None,
+    };
+    let stream = match range {
+        Some((start, end)) => blobs.backend().get(&key, start, end).await?,
+        None if size == 0 => Box::pin(rocket::futures::stream::empty()),
+        None => blobs.backend().get(&key, 0, size - 1).await?,
+    };
+    Ok(PayloadResponse {
+        stream,
+        size,
+        range,
+    })
+}
+
+/// Record the result of a restore drill, in which the client restored a snapshot of the
+/// volume to check that it can be recovered.
+#[post("/volume/<volume>/drills", data = "<result>")]
+async fn volume_drill_record(
+    context: Principal,
+    pool: &State<AnyPool>,
+    volumes: &State<VolumeCache>,
+    volume: Pubkey,
+    result: Json<DrillResult>,
+) -> Result<Json<Drill>, StorageError> {
+    let mut conn = pool.acquire().await?;
+    let volume = volume_lookup(&mut conn, volumes, &context, &volume, Access::Write).await?;
+    let snapshot = Snapshot::fetch_by_hash(&mut conn, &volume.volume(), &result.snapshot)
+        .await?
+        .ok_or(StorageError::SnapshotNotFound)?;
+    let drill = drill::record(&mut conn, &volume, &snapshot, &result, now()).await?;
+    info!(
+        "Recorded {} restore drill of snapshot {} of volume {}",
+        drill.outcome.as_str(),
+        RedactedHash::new(drill.snapshot),
+        volume.pubkey()
+    );
+    Ok(Json(drill))
+}
+
+/// Restore drills recorded for the volume, most recent first.
+#[get("/volume/<volume>/drills")]
+async fn volume_drill_list(
+    context: Principal,
+    pool: &State<AnyPool>,
+    volumes: &State<VolumeCache>,
+    volume: Pubkey,
+) -> Result<Json<Vec<Drill>>, StorageError> {
+    let mut conn = pool.acquire().await?;
+    let volume = volume_lookup(&mut conn, volumes, &context, &volume, Access::Read).await?;
+    Ok(Json(drill::list(&mut conn, &volume).await?))
+}
+
+/// Accounts other than the owner that were granted access to the volume.
+#[get("/volume/<volume>/acl")]
+async fn volume_acl_list(
+    context: Principal,
+    pool: &State<AnyPool>,
+    volumes: &State<VolumeCache>,
+    volume: Pubkey,
+) -> Result<Json<Vec<VolumeGrant>>, StorageError> {
+    let mut conn = pool.acquire().await?;
+    let volume = volume_lookup(&mut conn, volumes, &context, &volume, Access::Owner).await?;
+    Ok(Json(acl::list(&mut conn, &volume).await?))
+}
+
+/// Grant another account read or read/write access to the volume, replacing any access it
+/// was granted before.
+#[put("/volume/<volume>/acl", data = "<grant>")]
+async fn volume_acl_grant(
+    context: Principal,
+    pool: &State<AnyPool>,
+    volumes: &State<VolumeCache>,
+    volume: Pubkey,
+    grant: Json<VolumeGrant>,
+) -> Result<(), StorageError> {
+    if context
+        .api_key()
+        .map(ApiKeyData::restricted)
+        .unwrap_or(false)
+    {
+        return Err(StorageError::Forbidden);
+    }
+    let mut conn = pool.acquire().await?;
+    let volume = volume_lookup(&mut conn, volumes, &context, &volume, Access::Owner).await?;
+    if &grant.account == volume.account() {
+        return Err(StorageError::AccountInvalid);
+    }
+    acl::grant(&mut conn, &volume, &grant).await?;
+    info!(
+        "Granted {} access to volume {} for {}",
+        grant.access.as_str(),
+        volume.pubkey(),
+        grant.account
+    );
+    Ok(())
+}
+
+/// Revoke the access of an account to the volume.
+#[delete("/volume/<volume>/acl/<account>")]
+async fn volume_acl_revoke(
+    context: Principal,
+    pool: &State<AnyPool>,
+    volumes: &State<VolumeCache>,
+    volume: Pubkey,
+    account: &str,
+) -> Result<(), StorageError> {
+    if context
+        .api_key()
+        .map(ApiKeyData::restricted)
+        .unwrap_or(false)
+    {
+        return Err(StorageError::Forbidden);
+    }
+    let account = Uuid::parse_str(account).map_err(|_| StorageError::AccountInvalid)?;
+    let mut conn = pool.acquire().await?;
+    let volume = volume_lookup(&mut conn, volumes, &context, &volume, Access::Owner).await?;
+    if !acl::revoke(&mut conn, &volume, &account).await? {
+        return Err(StorageError::GrantNotFound);
+    }
+    Ok(())
+}
+
+/// Issue a new API key for the account.
+#[post("/account/keys", data = "<request>")]
+async fn account_key_create(
+    context: Principal,
+    pool: &State<AnyPool>,
+    request: Json<ApiKeyCreate>,
+) -> Result<Json<ApiKeyCreated>, StorageError> {
+    if context
+        .api_key()
+        .map(ApiKeyData::restricted)
+        .unwrap_or(false)
+    {
+        return Err(StorageError::Forbidden);
+    }
+    let mut conn = pool.acquire().await?;
+    let (key, token) = ApiKeyData::create(&mut conn, context.account(), &request).await?;
+    Ok(Json(ApiKeyCreated {
+        info: key.info(),
+        key: token,
+    }))
+}
+
+#[get("/account/keys")]
+async fn account_key_list(
+    context: Principal,
+    pool: &State<AnyPool>,
+) -> Result<Json<Vec<ApiKeyInfo>>, StorageError> {
+    if context
+        .api_key()
+        .map(ApiKeyData::restricted)
+        .unwrap_or(false)
+    {
+        return Err(StorageError::Forbidden);
+    }
+    let mut conn = pool.acquire().await?;
+    let keys = ApiKeyData::list(&mut conn, context.account()).await?;
+    Ok(Json(keys.iter().map(ApiKeyData::info).collect()))
+}
+
+#[delete("/account/keys/<id>")]
+async fn account_key_revoke(
+    context: Principal,
+    pool: &State<AnyPool>,
+    id: i64,
+) -> Result<(), StorageError> {
+    if context
+        .api_key()
+        .map(ApiKeyData::restricted)
+        .unwrap_or(false)
+    {
+        return Err(StorageError::Forbidden);
+    }
+    let mut conn = pool.acquire().await?;
+    match ApiKeyData::revoke(&mut conn, context.account(), id).await? {
+        true => Ok(()),
+        false => Err(StorageError::ApiKeyNotFound),
+    }
+}
+
+/// Default labels of the account, attached to every snapshot uploaded to its volumes.
+#[get("/account/labels")]
+async fn account_labels(
+    context: Principal,
+    pool: &State<AnyPool>,
+) -> Result<Json<Labels>, StorageError> {
+    let mut conn = pool.acquire().await?;
+    Ok(Json(
+        label::account_labels(&mut conn, context.account()).await?,
+    ))
+}
+
+#[put("/account/labels", data = "<labels>")]
+async fn account_labels_set(
+    context: Principal,
+    pool: &State<AnyPool>,
+    labels: Json<Labels>,
+) -> Result<(), StorageError> {
+    if context
+        .api_key()
+        .map(ApiKeyData::restricted)
+        .unwrap_or(false)
+    {
+        return Err(StorageError::Forbidden);
+    }
+    let mut conn = pool.acquire().await?;
+    label::account_labels_set(&mut conn, context.account(), &labels).await?;
+    Ok(())
+}
+
+/// Delete an account with all of its volumes, snapshots, API keys and labels, for when
+/// users are deprovisioned. Only allowed for system tokens.
+#[delete("/account/<account>")]
+async fn account_delete(
+    context: Principal,
+    _system: SystemPrincipal,
+    pool: &State<AnyPool>,
+    volumes: &State<VolumeCache>,
+    events: &State<Events>,
+    account: &str,
+) -> Result<Json<AccountDeleted>, StorageError> {
+    let account = Uuid::parse_str(account).map_err(|_| StorageError::AccountInvalid)?;
+    let mut conn = pool.acquire().await?;
+    let mut transaction = conn.begin().await?;
+    let deleted = account::delete(&mut transaction, &account, context.account(), now()).await?;
+    transaction.commit().await?;
+    for volume in &deleted.volumes {
+        volumes.invalidate(volume);
+        events.publish(&account, AccountEvent::VolumeDeleted { volume: *volume });
+    }
+    Ok(Json(deleted))
+}
+
+/// Default number of days to report snapshots per day for in the statistics.
+const STATS_DAYS: u64 = 30;
+
+/// Maximum number of days to report snapshots per day for in the statistics.
+const STATS_DAYS_MAX: u64 = 366;
+
+/// Statistics about all stored data, for capacity planning. Only allowed for system tokens.
+#[get("/admin/stats?<days>")]
+async fn admin_stats(
+    _context: Principal,
+    _system: SystemPrincipal,
+    pool: &State<AnyPool>,
+    days: Option<u64>,
+) -> Result<Json<StorageStats>, StorageError> {
+    let days = days.unwrap_or(STATS_DAYS).min(STATS_DAYS_MAX);
+    let mut conn = pool.acquire().await?;
+    let (volumes, accounts) = Volume::totals(&mut conn).await?;
+    let (snapshots, bytes) = Snapshot::totals(&mut conn).await?;
+    let daily = Snapshot::daily(&mut conn, now(), days).await?;
+    Ok(Json(StorageStats {
+        volumes,
+        accounts,
+        snapshots,
+        bytes,
+        daily,
+    }))
+}
+
+/// Settings of the account, such as its alert thresholds.
+#[get("/account/settings")]
+async fn account_settings(
+    context: Principal,
+    pool: &State<AnyPool>,
+) -> Result<Json<AccountSettings>, StorageError> {
+    let mut conn = pool.acquire().await?;
+    Ok(Json(alert::settings(&mut conn, context.account()).await?))
+}
+
+#[put("/account/settings", data = "<settings>")]
+async fn account_settings_set(
+    context: Principal,
+    pool: &State<AnyPool>,
+    settings: Json<AccountSettings>,
+) -> Result<(), StorageError> {
+    if context
+        .api_key()
+        .map(ApiKeyData::restricted)
+        .unwrap_or(false)
+    {
+        return Err(StorageError::Forbidden);
+    }
+    let mut conn = pool.acquire().await?;
+    alert::settings_set(&mut conn, context.account(), &settings).await?;
+    Ok(())
+}
+
+/// Volumes of the account currently exceeding its alert thresholds.
+#[get("/account/alerts")]
+async fn account_alerts(
+    context: Principal,
+    pool: &State<AnyPool>,
+) -> Result<Json<Vec<Alert>>, StorageError> {
+    let mut conn = pool.acquire().await?;
+    Ok(Json(alert::list(&mut conn, context.account()).await?))
+}
+
+/// When each volume of the account was last verified to be restorable by a restore drill.
+#[get("/account/drills")]
+async fn account_drills(
+    context: Principal,
+    pool: &State<AnyPool>,
+) -> Result<Json<Vec<DrillStatus>>, StorageError> {
+    let mut conn = pool.acquire().await?;
+    Ok(Json(drill::report(&mut conn, context.account()).await?))
+}
+
+/// Volumes of the account with their properties, ordered by public key. Deleted volumes are
+/// left out.
+#[get("/account/volumes")]
+async fn account_volumes(
+    context: Principal,
+    pool: &State<AnyPool>,
+) -> Result<Json<Vec<(Pubkey, VolumeInfo)>>, StorageError> {
+    let mut conn = pool.acquire().await?;
+    let mut volumes = vec![];
+    for volume in Volume::list_account(&mut conn, context.account()).await? {
+        if volume.deleted_at().is_none() {
+            volumes.push((*volume.pubkey(), volume_info(&mut conn, &volume).await?));
+        }
+    }
+    volumes.sort_by_key(|(pubkey, _)| pubkey.to_hex());
+    Ok(Json(volumes))
+}
+
+/// Find snapshots of the account with all of the given labels (as `key=value`).
+#[get("/snapshots/search?<label>")]
+async fn snapshot_search(
+    context: Principal,
+    pool: &State<AnyPool>,
+    label: Vec<&str>,
+) -> Result<Json<Vec<LabelMatch>>, StorageError> {
+    let labels = label
+        .into_iter()
+        .map(label::parse)
+        .collect::<Result<Labels, _>>()?;
+    let mut conn = pool.acquire().await?;
+    Ok(Json(
+        label::search(&mut conn, context.account(), &labels).await?,
+    ))
+}
+
+/// Find snapshots of the account created on a machine, across its volumes, newest first.
+#[get("/snapshots?<machine>&<limit>")]
+async fn snapshot_search_machine(
+    context: Principal,
+    pool: &State<AnyPool>,
+    machine: &str,
+    limit: Option<u64>,
+) -> Result<Json<Vec<MachineSnapshot>>, StorageError> {
+    let machine = Uuid::parse_str(machine).map_err(|_| StorageError::MachineInvalid)?;
+    let limit = limit
+        .unwrap_or(SNAPSHOT_PAGE_LIMIT)
+        .clamp(1, SNAPSHOT_PAGE_LIMIT_MAX);
+    let mut conn = pool.acquire().await?;
+    Ok(Json(
+        Snapshot::by_machine(&mut conn, context.account(), &machine, limit).await?,
+    ))
+}
+
+/// Find payloads referenced by more than one snapshot of the account, or the snapshots
+/// referencing the given payload.
+#[get("/snapshots/duplicates?<data>")]
+async fn snapshot_duplicates(
+    context: Principal,
+    pool: &State<AnyPool>,
+    data: Option<&str>,
+) -> Result<Json<Vec<DuplicateData>>, StorageError> {
+    let data = data
+        .map(Url::parse)
+        .transpose()
+        .map_err(SnapshotError::from)?;
+    let mut conn = pool.acquire().await?;
+    Ok(Json(
+        Snapshot::duplicates(&mut conn, context.account(), data.as_ref()).await?,
+    ))
+}
+
+/// Features supported by this service, lets clients negotiate the manifest version.
+#[get("/capabilities")]
+async fn capabilities() -> Json<Capabilities> {
+    Json(Capabilities {
+        manifest_versions: MANIFEST_VERSIONS.to_vec(),
+        signature_algorithms: SignatureAlgorithm::supported()
+            .iter()
+            .map(SignatureAlgorithm::id)
+            .collect(),
+    })
 }
 
 #[get("/health")]
@@ -214,12 +2001,59 @@ pub fn routes() -> Vec<Route> {
         volume_get,
         volume_edit,
         volume_delete,
+        volume_challenge,
+        volume_replication,
+        volume_export,
+        volume_import,
+        volume_restore,
+        volume_upload_token,
+        volume_snapshot_presign,
         volume_snapshot_upload,
+        volume_snapshot_upload_batch,
         volume_snapshot_get,
+        volume_snapshot_delete,
         volume_snapshot_list,
+        volume_snapshot_pending,
+        volume_snapshot_exists,
+        volume_manifest_batch,
+        volume_snapshot_payload,
+        volume_snapshot_chain_validate,
+        volume_snapshot_ancestry,
+        volume_drill_record,
+        volume_drill_list,
+        volume_acl_list,
+        volume_acl_grant,
+        volume_acl_revoke,
+        account_key_create,
+        account_key_list,
+        account_key_revoke,
+        account_delete,
+        admin_stats,
+        account_labels,
+        account_labels_set,
+        account_settings,
+        account_settings_set,
+        account_alerts,
+        account_drills,
+        account_volumes,
+        snapshot_search,
+        snapshot_search_machine,
+        snapshot_duplicates,
+        capabilities,
     ]
 }
 
+/// Routes for payloads stored on this service. These are not subject to the payload
+/// budget, as payloads are much larger than manifests.
+pub fn blobs() -> Vec<Route> {
+    routes![volume_snapshot_data_upload, volume_snapshot_data]
+}
+
+/// Routes of the v2 API.
+pub fn routes_v2() -> Vec<Route> {
+    routes![volume_snapshot_list_v2]
+}
+
 pub fn health() -> Vec<Route> {
     routes![health_check]
 }
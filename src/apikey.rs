@@ -0,0 +1,264 @@
+use crate::purge::now;
+use fractal_storage_client::{ApiKeyCreate, ApiKeyInfo, ApiKeyScope, Pubkey};
+use rand::{thread_rng, RngCore};
+use sha2::{Digest, Sha256};
+use sqlx::any::AnyRow;
+use sqlx::{query, AnyConnection, Row};
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Prefix of API keys, tells them apart from JWTs and static tokens.
+pub const API_KEY_PREFIX: &str = "fsk_";
+
+/// Number of random bytes in an API key.
+const API_KEY_LENGTH: usize = 32;
+
+/// Maximum lifetime of API keys that expire, in seconds (ten years).
+pub const API_KEY_TTL_MAX: u64 = 10 * 365 * 24 * 60 * 60;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ApiKeyError {
+    #[error("Error talking to database: {0:}")]
+    Database(#[from] sqlx::Error),
+    #[error("Error inserting data: missing rowid")]
+    MissingRowid,
+    #[error("Error parsing UUID: {0:}")]
+    ParseUuid(#[from] uuid::Error),
+    #[error("Error parsing key: {0:}")]
+    ParseKey(#[from] fractal_storage_client::keys::ParseError),
+    #[error("Error parsing scope: {0:}")]
+    ParseScope(String),
+    #[error("API key TTL of {0:} seconds exceeds the maximum of {API_KEY_TTL_MAX} seconds")]
+    TtlInvalid(u64),
+}
+
+/// Represents a row in the storage_api_key table.
+#[derive(Clone, Debug)]
+pub struct ApiKeyData {
+    id: i64,
+    account: Uuid,
+    name: Option<String>,
+    scope: Option<ApiKeyScope>,
+    volume: Option<Pubkey>,
+    created: u64,
+    expires: Option<u64>,
+}
+
+/// Hash of an API key, only the hash is stored.
+fn api_key_hash(key: &str) -> Vec<u8> {
+    Sha256::digest(key.as_bytes()).to_vec()
+}
+
+impl ApiKeyData {
+    pub fn from_row(row: &AnyRow) -> Result<Self, ApiKeyError> {
+        let account: &str = row.try_get("account_id")?;
+        let scope: Option<&str> = row.try_get("api_key_scope")?;
+        let volume: Option<&[u8]> = row.try_get("volume_pubkey")?;
+        let created: i64 = row.try_get("api_key_created")?;
+        let expires: Option<i64> = row.try_get("api_key_expires")?;
+        Ok(ApiKeyData {
+            id: row.try_get("api_key_id")?,
+            account: Uuid::from_str(account)?,
+            name: row.try_get("api_key_name")?,
+            scope: scope
+                .map(ApiKeyScope::from_str)
+                .transpose()
+                .map_err(ApiKeyError::ParseScope)?,
+            volume: volume.map(Pubkey::try_from).transpose()?,
+            created: created as u64,
+            expires: expires.map(|time| time as u64),
+        })
+    }
+
+    /// Issue a new API key for the account, returning it along with the bearer token.
+    pub async fn create(
+        conn: &mut AnyConnection,
+        account: &Uuid,
+        request: &ApiKeyCreate,
+    ) -> Result<(Self, String), ApiKeyError> {
+        let created = now();
+        let expires = match request.ttl {
+            Some(ttl) if ttl > API_KEY_TTL_MAX => return Err(ApiKeyError::TtlInvalid(ttl)),
+            Some(ttl) => Some(
+                created
+                    .checked_add(ttl)
+                    .ok_or(ApiKeyError::TtlInvalid(ttl))?,
+            ),
+            None => None,
+        };
+        let mut random = [0; API_KEY_LENGTH];
+        thread_rng().fill_bytes(&mut random);
+        let key = format!("{API_KEY_PREFIX}{}", hex::encode(random));
+        let result = query(
+            "INSERT INTO storage_api_key(
+                account_id,
+                api_key_hash,
+                api_key_name,
+                api_key_scope,
+                volume_pubkey,
+                api_key_created,
+                api_key_expires)
+            VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(account.to_string())
+        .bind(api_key_hash(&key))
+        .bind(request.name.clone())
+        .bind(request.scope.map(|scope| scope.as_str()))
+        .bind(request.volume.map(|volume| volume.to_vec()))
+        .bind(created as i64)
+        .bind(expires.map(|time| time as i64))
+        .execute(conn)
+        .await?;
+        let data = ApiKeyData {
+            id: result.last_insert_id().ok_or(ApiKeyError::MissingRowid)?,
+            account: *account,
+            name: request.name.clone(),
+            scope: request.scope,
+            volume: request.volume,
+            created,
+            expires,
+        };
+        Ok((data, key))
+    }
+
+    /// Look up an API key that has not expired.
+    pub async fn lookup(conn: &mut AnyConnection, key: &str) -> Result<Option<Self>, ApiKeyError> {
+        let row = query(
+            "SELECT * FROM storage_api_key
+                WHERE api_key_hash = ?
+                AND (api_key_expires IS NULL OR api_key_expires > ?)",
+        )
+        .bind(api_key_hash(key))
+        .bind(now() as i64)
+        .fetch_optional(conn)
+        .await?;
+        row.as_ref().map(ApiKeyData::from_row).transpose()
+    }
+
+    /// List all API keys of an account.
+    pub async fn list(conn: &mut AnyConnection, account: &Uuid) -> Result<Vec<Self>, ApiKeyError> {
+        let rows = query("SELECT * FROM storage_api_key WHERE account_id = ? ORDER BY api_key_id")
+            .bind(account.to_string())
+            .fetch_all(conn)
+            .await?;
+        rows.iter().map(ApiKeyData::from_row).collect()
+    }
+
+    /// Revoke an API key of the account, returns false if it does not exist.
+    pub async fn revoke(
+        conn: &mut AnyConnection,
+        account: &Uuid,
+        id: i64,
+    ) -> Result<bool, ApiKeyError> {
+        let result = query("DELETE FROM storage_api_key WHERE api_key_id = ? AND account_id = ?")
+            .bind(id)
+            .bind(account.to_string())
+            .execute(conn)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub fn account(&self) -> &Uuid {
+        &self.account
+    }
+
+    pub fn scope(&self) -> Option<ApiKeyScope> {
+        self.scope
+    }
+
+    pub fn volume(&self) -> Option<&Pubkey> {
+        self.volume.as_ref()
+    }
+
+    pub fn expires(&self) -> Option<u64> {
+        self.expires
+    }
+
+    /// Determines if this key is restricted in any way, restricted keys cannot manage
+    /// API keys.
+    pub fn restricted(&self) -> bool {
+        self.scope.is_some() || self.volume.is_some()
+    }
+
+    pub fn info(&self) -> ApiKeyInfo {
+        ApiKeyInfo {
+            id: self.id,
+            name: self.name.clone(),
+            scope: self.scope,
+            volume: self.volume,
+            created: self.created,
+            expires: self.expires,
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_api_key() {
+    use sqlx::AnyPool;
+
+    let pool = AnyPool::connect("sqlite://:memory:").await.unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+    let mut conn = pool.acquire().await.unwrap();
+    let account = Uuid::new_v4();
+
+    let (created, key) = ApiKeyData::create(&mut conn, &account, &ApiKeyCreate::default())
+        .await
+        .unwrap();
+    assert!(key.starts_with(API_KEY_PREFIX));
+    let found = ApiKeyData::lookup(&mut conn, &key).await.unwrap().unwrap();
+    assert_eq!(found.info(), created.info());
+    assert!(!found.restricted());
+    assert!(ApiKeyData::lookup(&mut conn, "fsk_other")
+        .await
+        .unwrap()
+        .is_none());
+
+    // expired keys are not found
+    let request = ApiKeyCreate {
+        ttl: Some(0),
+        scope: Some(ApiKeyScope::Read),
+        ..Default::default()
+    };
+    let (_, expired) = ApiKeyData::create(&mut conn, &account, &request)
+        .await
+        .unwrap();
+    assert!(ApiKeyData::lookup(&mut conn, &expired)
+        .await
+        .unwrap()
+        .is_none());
+
+    // lifetimes beyond the maximum are rejected rather than overflowing
+    for ttl in [API_KEY_TTL_MAX + 1, u64::MAX] {
+        let request = ApiKeyCreate {
+            ttl: Some(ttl),
+            ..Default::default()
+        };
+        assert!(matches!(
+            ApiKeyData::create(&mut conn, &account, &request).await,
+            Err(ApiKeyError::TtlInvalid(_))
+        ));
+    }
+    let request = ApiKeyCreate {
+        ttl: Some(API_KEY_TTL_MAX),
+        ..Default::default()
+    };
+    let (created_max, _) = ApiKeyData::create(&mut conn, &account, &request)
+        .await
+        .unwrap();
+    assert_eq!(
+        created_max.expires(),
+        Some(created_max.created + API_KEY_TTL_MAX)
+    );
+
+    assert_eq!(
+        ApiKeyData::list(&mut conn, &account).await.unwrap().len(),
+        3
+    );
+    assert!(!ApiKeyData::revoke(&mut conn, &Uuid::new_v4(), created.id)
+        .await
+        .unwrap());
+    assert!(ApiKeyData::revoke(&mut conn, &account, created.id)
+        .await
+        .unwrap());
+    assert!(ApiKeyData::lookup(&mut conn, &key).await.unwrap().is_none());
+}
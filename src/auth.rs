@@ -0,0 +1,259 @@
+use crate::apikey::{ApiKeyData, API_KEY_PREFIX};
+use crate::policy::{Policy, PolicyInput};
+use crate::purge::now;
+use crate::upload_token::{UploadGrant, UploadTokens, UPLOAD_TOKEN_PREFIX};
+use crate::whoami::SystemTokens;
+use fractal_auth_client::UserContext;
+use fractal_storage_client::{ApiKeyScope, Pubkey, TokenType};
+use rocket::http::{Method, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use sqlx::AnyPool;
+use std::str::FromStr;
+use uuid::Uuid;
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthError {
+    #[error("Missing or invalid token")]
+    Unauthorized,
+    #[error("Token does not grant access to this resource")]
+    Forbidden,
+    #[error("Error verifying API key: {0:}")]
+    Internal(String),
+    #[error("Request denied by policy")]
+    Denied,
+    #[error("Error evaluating policy: {0:}")]
+    Policy(String),
+}
+
+/// Authenticated principal of a request. Requests are authenticated either with an API key
+/// issued by the account, an upload token minted for a volume, or with any token accepted
+/// by the auth client (JWTs and static tokens).
+pub struct Principal {
+    account: Uuid,
+    api_key: Option<ApiKeyData>,
+    upload: Option<UploadGrant>,
+    system: bool,
+}
+
+impl Principal {
+    pub fn account(&self) -> &Uuid {
+        &self.account
+    }
+
+    /// API key the request was authenticated with, if any.
+    pub fn api_key(&self) -> Option<&ApiKeyData> {
+        self.api_key.as_ref()
+    }
+
+    /// Grant of the upload token the request was authenticated with, if any.
+    pub fn upload(&self) -> Option<&UploadGrant> {
+        self.upload.as_ref()
+    }
+
+    /// Kind of token the request was authenticated with.
+    pub fn token_type(&self) -> TokenType {
+        match self {
+            Principal { system: true, .. } => TokenType::System,
+            Principal {
+                api_key: Some(_), ..
+            } => TokenType::ApiKey,
+            Principal {
+                upload: Some(_), ..
+            } => TokenType::Upload,
+            _ => TokenType::User,
+        }
+    }
+
+    /// Determines if this principal may access resources of the account. Principals only
+    /// have access to their own account, except for system tokens which can access any.
+    pub fn authorized(&self, account: &Uuid) -> bool {
+        self.system || &self.account == account
+    }
+
+    /// Context of a request made by this principal, for evaluating policies.
+    fn policy_input(&self, request: &Request<'_>) -> PolicyInput {
+        let path = request.uri().path().to_string();
+        PolicyInput {
+            account: self.account,
+            api_key: self.api_key.is_some(),
+            scope: self.api_key.as_ref().and_then(ApiKeyData::scope),
+            volume: request_volume(&path),
+            operation: (&request.method()).into(),
+            method: request.method().to_string(),
+            path,
+        }
+    }
+}
+
+/// Volume that a request path refers to, for paths like `/api/v1/volume/<volume>/...`.
+pub fn request_volume(path: &str) -> Option<Pubkey> {
+    let mut segments = path.split('/');
+    segments.find(|segment| *segment == "volume")?;
+    Pubkey::from_str(segments.next()?).ok()
+}
+
+/// Determines if a restricted API key grants access to a request.
+fn api_key_allows(key: &ApiKeyData, method: Method, path: &str) -> bool {
+    if let Some(volume) = key.volume() {
+        if request_volume(path).as_ref() != Some(volume) {
+            return false;
+        }
+    }
+    match key.scope() {
+        Some(ApiKeyScope::Read) => matches!(method, Method::Get | Method::Head),
+        Some(ApiKeyScope::Write) | None => true,
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Principal {
+    type Error = AuthError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let principal = match authenticate(request).await {
+            Outcome::Success(principal) => principal,
+            outcome => return outcome,
+        };
+
+        // custom access rules are applied on top of the built-in ones, if configured.
+        if let Some(policy) = request.rocket().state::<Policy>() {
+            match policy.allows(&principal.policy_input(request)).await {
+                Ok(true) => {}
+                Ok(false) => return Outcome::Failure((Status::Forbidden, AuthError::Denied)),
+                Err(e) => {
+                    let error = AuthError::Policy(e.to_string());
+                    return Outcome::Failure((Status::InternalServerError, error));
+                }
+            }
+        }
+        Outcome::Success(principal)
+    }
+}
+
+/// Request guard for requests made with a static system token, which administrative routes
+/// require in addition to a [`Principal`].
+pub struct SystemPrincipal;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SystemPrincipal {
+    type Error = AuthError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match bearer_token(request) {
+            Some(token) if system_token(request, token) => Outcome::Success(SystemPrincipal),
+            Some(_) => Outcome::Failure((Status::Forbidden, AuthError::Forbidden)),
+            None => Outcome::Failure((Status::Unauthorized, AuthError::Unauthorized)),
+        }
+    }
+}
+
+/// Token in the `Authorization` header of a request.
+fn bearer_token<'r>(request: &'r Request<'_>) -> Option<&'r str> {
+    request
+        .headers()
+        .get_one("Authorization")
+        .and_then(|header| header.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+/// Request guard for the token in the `Authorization` header of a request. This does not
+/// authenticate the request, use it alongside a [`Principal`].
+pub struct BearerToken<'r>(pub Option<&'r str>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BearerToken<'r> {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(BearerToken(bearer_token(request)))
+    }
+}
+
+/// Determines if the token is one of the static system tokens.
+fn system_token(request: &Request<'_>, token: &str) -> bool {
+    request
+        .rocket()
+        .state::<SystemTokens>()
+        .map(|system| system.0.iter().any(|system| system == token))
+        .unwrap_or(false)
+}
+
+/// Authenticate a request using the token in its `Authorization` header.
+async fn authenticate(request: &Request<'_>) -> Outcome<Principal, AuthError> {
+    let token = bearer_token(request);
+
+    // API keys are verified here, anything else is left to the auth client.
+    if let Some(token) = token.filter(|token| token.starts_with(API_KEY_PREFIX)) {
+        let pool = match request.rocket().state::<AnyPool>() {
+            Some(pool) => pool,
+            None => {
+                let error = AuthError::Internal("no database".into());
+                return Outcome::Failure((Status::InternalServerError, error));
+            }
+        };
+        let key = match pool.acquire().await {
+            Ok(mut conn) => ApiKeyData::lookup(&mut conn, token).await,
+            Err(e) => Err(e.into()),
+        };
+        return match key {
+            Ok(Some(key))
+                if api_key_allows(&key, request.method(), request.uri().path().as_str()) =>
+            {
+                Outcome::Success(Principal {
+                    account: *key.account(),
+                    api_key: Some(key),
+                    upload: None,
+                    system: false,
+                })
+            }
+            Ok(Some(_)) => Outcome::Failure((Status::Forbidden, AuthError::Forbidden)),
+            Ok(None) => Outcome::Failure((Status::Unauthorized, AuthError::Unauthorized)),
+            Err(e) => Outcome::Failure((
+                Status::InternalServerError,
+                AuthError::Internal(e.to_string()),
+            )),
+        };
+    }
+
+    // upload tokens are verified statelessly, they only allow uploads to one volume.
+    if let Some(token) = token.filter(|token| token.starts_with(UPLOAD_TOKEN_PREFIX)) {
+        let tokens = match request.rocket().state::<UploadTokens>() {
+            Some(tokens) => tokens,
+            None => return Outcome::Failure((Status::Unauthorized, AuthError::Unauthorized)),
+        };
+        return match tokens.verify(token, now()) {
+            Ok(grant) if grant.allows(request.method(), request.uri().path().as_str()) => {
+                Outcome::Success(Principal {
+                    account: grant.account,
+                    api_key: None,
+                    upload: Some(grant),
+                    system: false,
+                })
+            }
+            Ok(_) => Outcome::Failure((Status::Forbidden, AuthError::Forbidden)),
+            Err(_) => Outcome::Failure((Status::Unauthorized, AuthError::Unauthorized)),
+        };
+    }
+
+    match request.guard::<UserContext>().await {
+        Outcome::Success(context) => Outcome::Success(Principal {
+            account: Uuid::parse_str(&context.account().to_string()).unwrap(),
+            api_key: None,
+            upload: None,
+            system: token
+                .map(|token| system_token(request, token))
+                .unwrap_or(false),
+        }),
+        Outcome::Failure((status, _)) => Outcome::Failure((status, AuthError::Unauthorized)),
+        Outcome::Forward(()) => Outcome::Forward(()),
+    }
+}
+
+#[test]
+fn test_request_volume() {
+    let pubkey = fractal_storage_client::Privkey::generate().pubkey();
+    let path = format!("/api/v1/volume/{}/snapshots", pubkey.to_hex());
+    assert_eq!(request_volume(&path), Some(pubkey));
+    assert_eq!(request_volume("/api/v1/events"), None);
+    assert_eq!(request_volume("/api/v1/volume/invalid"), None);
+}
@@ -0,0 +1,80 @@
+use crate::snapshot::{Snapshot, SnapshotError};
+use log::{error, info};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::tokio::{select, time};
+use rocket::{Orbit, Rocket};
+use sqlx::AnyPool;
+use std::time::Duration;
+
+/// Pause between chunks of manifests being converted.
+const BACKFILL_PAUSE: Duration = Duration::from_millis(100);
+
+/// Converts the compression of manifests stored by older versions in the background, so that
+/// startup is not held up on large databases. Snapshots are converted in chunks, each on a
+/// connection of its own, pausing in between so that requests are not starved.
+#[derive(Clone, Debug)]
+pub struct Backfill {
+    /// How long to pause between chunks.
+    pub pause: Duration,
+}
+
+impl Default for Backfill {
+    fn default() -> Self {
+        Backfill {
+            pause: BACKFILL_PAUSE,
+        }
+    }
+}
+
+impl Backfill {
+    /// Convert the next chunk of manifests, returning the ID of the last snapshot visited
+    /// along with how many were, or `None` once all are done.
+    async fn chunk(pool: &AnyPool, after: i64) -> Result<Option<(i64, usize)>, SnapshotError> {
+        let mut conn = pool.acquire().await?;
+        Snapshot::compression_backfill(&mut conn, after).await
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Backfill {
+    fn info(&self) -> Info {
+        Info {
+            name: "Convert manifest compression",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let pool = match rocket.state::<AnyPool>() {
+            Some(pool) => pool.clone(),
+            None => return,
+        };
+        let mut shutdown = rocket.shutdown();
+        let pause = self.pause;
+        rocket::tokio::spawn(async move {
+            let mut last = 0;
+            let mut count = 0;
+            loop {
+                match Backfill::chunk(&pool, last).await {
+                    Ok(Some((snapshot, visited))) => {
+                        last = snapshot;
+                        count += visited;
+                    }
+                    Ok(None) => break,
+                    // picked up again on the next start.
+                    Err(e) => {
+                        error!("Error converting manifest compression: {}", e);
+                        break;
+                    }
+                }
+                select! {
+                    _ = time::sleep(pause) => {},
+                    _ = &mut shutdown => break,
+                }
+            }
+            if count > 0 {
+                info!("Converted compression of {} manifests", count);
+            }
+        });
+    }
+}
@@ -0,0 +1,417 @@
+use crate::ipfs::{exact_length, PayloadStream};
+use async_trait::async_trait;
+use bytes::BytesMut;
+use log::warn;
+use rocket::futures::stream;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
+use url::Url;
+
+/// Size of chunks that blobs are streamed in.
+const BLOB_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Reader for blob data that is being uploaded.
+pub type BlobReader<'a> = Pin<Box<dyn AsyncRead + Send + 'a>>;
+
+#[derive(Error, Debug)]
+pub enum BlobError {
+    #[error("I/O error: {0:}")]
+    Io(#[from] std::io::Error),
+    #[error("Unsupported blob backend: {0:}")]
+    UnsupportedBackend(Url),
+    #[error("Blob exceeds limit of {0:} bytes")]
+    TooLarge(u64),
+    #[error("Blob {0:} already exists")]
+    Exists(String),
+    #[cfg(feature = "backend-s3")]
+    #[error("Error talking to S3: {0:}")]
+    S3(String),
+}
+
+/// Storage for encrypted snapshot payloads that are uploaded directly to this service,
+/// for deployments that cannot run IPFS. Blobs are addressed by key and immutable.
+#[async_trait]
+pub trait BlobBackend: Send + Sync {
+    /// Size of the blob with the given key, or `None` if it does not exist.
+    async fn size(&self, key: &str) -> Result<Option<u64>, BlobError>;
+
+    /// Store a blob under the given key, returning the number of bytes written. Blobs larger
+    /// than the limit are rejected and not stored. Blobs are immutable, storing one under a
+    /// key that exists fails.
+    async fn put(&self, key: &str, data: BlobReader<'_>, limit: u64) -> Result<u64, BlobError>;
+
+    /// Stream the given (inclusive) byte range of the blob with the given key.
+    async fn get(&self, key: &str, start: u64, end: u64) -> Result<PayloadStream, BlobError>;
+
+    /// Delete the blob with the given key, if it exists.
+    async fn delete(&self, key: &str) -> Result<(), BlobError>;
+}
+
+/// Configured blob backend.
+#[derive(Clone)]
+pub struct Blobs(Arc<dyn BlobBackend>);
+
+impl Blobs {
+    /// Create blob backend from URL, `file:///path` stores blobs in a local directory,
+    /// `s3://bucket/prefix` stores them in an S3-compatible object store.
+    pub fn from_url(url: &Url) -> Result<Self, BlobError> {
+        match url.scheme() {
+            #[cfg(feature = "backend-local")]
+            "file" => Ok(Blobs(Arc::new(LocalBlobs::new(PathBuf::from(url.path()))))),
+            #[cfg(feature = "backend-s3")]
+            "s3" => Ok(Blobs(Arc::new(S3Blobs::from_url(url)?))),
+            _ => Err(BlobError::UnsupportedBackend(url.clone())),
+        }
+    }
+
+    pub fn backend(&self) -> &dyn BlobBackend {
+        self.0.as_ref()
+    }
+}
+
+/// Stores blobs as files in a local directory.
+#[cfg(feature = "backend-local")]
+#[derive(Clone, Debug)]
+pub struct LocalBlobs {
+    path: PathBuf,
+}
+
+#[cfg(feature = "backend-local")]
+impl LocalBlobs {
+    pub fn new(path: PathBuf) -> Self {
+        LocalBlobs { path }
+    }
+
+    /// Write data to a file, up to the limit.
+    async fn write(path: &Path, data: BlobReader<'_>, limit: u64) -> Result<u64, BlobError> {
+        let mut file = tokio::fs::File::create(path).await?;
+        // reading one byte past the limit tells oversized blobs apart from ones that fit.
+        let size = tokio::io::copy(&mut data.take(limit.saturating_add(1)), &mut file).await?;
+        if size > limit {
+            return Err(BlobError::TooLarge(limit));
+        }
+        file.sync_all().await?;
+        Ok(size)
+    }
+}
+
+#[cfg(feature = "backend-local")]
+#[async_trait]
+impl BlobBackend for LocalBlobs {
+    async fn size(&self, key: &str) -> Result<Option<u64>, BlobError> {
+        match tokio::fs::metadata(self.path.join(key)).await {
+            Ok(metadata) => Ok(Some(metadata.len())),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    async fn put(&self, key: &str, data: BlobReader<'_>, limit: u64) -> Result<u64, BlobError> {
+        // write to a temporary file unique to this upload first, so that partial or oversized
+        // uploads are never visible and concurrent uploads cannot interleave. it is published
+        // by linking it, which unlike renaming fails if a concurrent upload got there first.
+        tokio::fs::create_dir_all(&self.path).await?;
+        let partial = self
+            .path
+            .join(format!("{key}.{}.partial", uuid::Uuid::new_v4()));
+        let result = match Self::write(&partial, data, limit).await {
+            Ok(size) => match tokio::fs::hard_link(&partial, self.path.join(key)).await {
+                Ok(()) => Ok(size),
+                Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => {
+                    Err(BlobError::Exists(key.to_string()))
+                }
+                Err(error) => Err(error.into()),
+            },
+            Err(error) => Err(error),
+        };
+        if let Err(error) = tokio::fs::remove_file(&partial).await {
+            if error.kind() != std::io::ErrorKind::NotFound {
+                warn!("Error removing partial blob {:?}: {}", partial, error);
+            }
+        }
+        result
+    }
+
+    async fn get(&self, key: &str, start: u64, end: u64) -> Result<PayloadStream, BlobError> {
+        let mut file = tokio::fs::File::open(self.path.join(key)).await?;
+        file.seek(SeekFrom::Start(start)).await?;
+        let length = end + 1 - start;
+        let reader = file.take(length);
+        let stream = stream::unfold(reader, |mut reader| async move {
+            let mut chunk = BytesMut::with_capacity(BLOB_CHUNK_SIZE);
+            match reader.read_buf(&mut chunk).await {
+                Ok(0) => None,
+                Ok(_) => Some((Ok(chunk.freeze()), reader)),
+                Err(error) => Some((Err(error), reader)),
+            }
+        });
+        Ok(exact_length(stream, length))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), BlobError> {
+        match tokio::fs::remove_file(self.path.join(key)).await {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => Err(error.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Size of the ranges that blobs are fetched from S3 in.
+#[cfg(feature = "backend-s3")]
+const S3_RANGE_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Stores blobs as objects in an S3-compatible object store (such as MinIO). Credentials
+/// are read from the usual `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` variables.
+#[cfg(feature = "backend-s3")]
+#[derive(Clone, Debug)]
+pub struct S3Blobs {
+    bucket: s3::Bucket,
+    prefix: String,
+}
+
+#[cfg(feature = "backend-s3")]
+impl S3Blobs {
+    /// Create from URL like `s3://bucket/prefix?region=eu-central-1`. To use an object store
+    /// other than AWS, add `endpoint=https://minio.example.com`, which also enables path-style
+    /// bucket addressing.
+    pub fn from_url(url: &Url) -> Result<Self, BlobError> {
+        let name = url
+            .host_str()
+            .ok_or_else(|| BlobError::UnsupportedBackend(url.clone()))?;
+        let mut region = None;
+        let mut endpoint = None;
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "region" => region = Some(value.to_string()),
+                "endpoint" => endpoint = Some(value.to_string()),
+                _ => return Err(BlobError::UnsupportedBackend(url.clone())),
+            }
+        }
+        let region_name = region.unwrap_or_else(|| "us-east-1".to_string());
+        let credentials =
+            s3::creds::Credentials::default().map_err(|e| BlobError::S3(e.to_string()))?;
+        let bucket = match endpoint {
+            Some(endpoint) => {
+                let region = s3::Region::Custom {
+                    region: region_name,
+                    endpoint,
+                };
+                s3::Bucket::new_with_path_style(name, region, credentials)
+            }
+            None => {
+                let region: s3::Region = region_name
+                    .parse()
+                    .map_err(|e| BlobError::S3(format!("Invalid region: {e:?}")))?;
+                s3::Bucket::new(name, region, credentials)
+            }
+        }
+        .map_err(|e| BlobError::S3(e.to_string()))?;
+        Ok(S3Blobs {
+            bucket,
+            prefix: url.path().trim_matches('/').to_string(),
+        })
+    }
+
+    fn path(&self, key: &str) -> String {
+        match self.prefix.as_str() {
+            "" => format!("/{key}"),
+            prefix => format!("/{prefix}/{key}"),
+        }
+    }
+}
+
+#[cfg(feature = "backend-s3")]
+#[async_trait]
+impl BlobBackend for S3Blobs {
+    async fn size(&self, key: &str) -> Result<Option<u64>, BlobError> {
+        let (head, status) = self
+            .bucket
+            .head_object(self.path(key))
+            .await
+            .map_err(|e| BlobError::S3(e.to_string()))?;
+        match status {
+            404 => Ok(None),
+            200 => Ok(Some(head.content_length.unwrap_or_default() as u64)),
+            status => Err(BlobError::S3(format!(
+                "HEAD responded with status {status}"
+            ))),
+        }
+    }
+
+    async fn put(&self, key: &str, data: BlobReader<'_>, limit: u64) -> Result<u64, BlobError> {
+        // S3 cannot create objects exclusively, so this only catches uploads that do not
+        // overlap.
+        if self.size(key).await?.is_some() {
+            return Err(BlobError::Exists(key.to_string()));
+        }
+        // uploaded as multipart upload in chunks, so only a single chunk is held in memory.
+        // reading one byte past the limit tells oversized blobs apart from ones that fit.
+        let mut data = data.take(limit.saturating_add(1));
+        let status = self
+            .bucket
+            .put_object_stream(&mut data, self.path(key))
+            .await
+            .map_err(|e| BlobError::S3(e.to_string()))?;
+        if status != 200 {
+            return Err(BlobError::S3(format!("PUT responded with status {status}")));
+        }
+        let size = self
+            .size(key)
+            .await?
+            .ok_or_else(|| BlobError::S3(format!("Uploaded object {key} is missing")))?;
+        if size > limit {
+            self.delete(key).await?;
+            return Err(BlobError::TooLarge(limit));
+        }
+        Ok(size)
+    }
+
+    async fn get(&self, key: &str, start: u64, end: u64) -> Result<PayloadStream, BlobError> {
+        // fetched in ranges, so only a single range is held in memory.
+        let blobs = self.clone();
+        let path = self.path(key);
+        let stream = stream::unfold(start, move |offset| {
+            let blobs = blobs.clone();
+            let path = path.clone();
+            async move {
+                if offset > end {
+                    return None;
+                }
+                let last = end.min(offset + S3_RANGE_SIZE - 1);
+                match blobs
+                    .bucket
+                    .get_object_range(&path, offset, Some(last))
+                    .await
+                {
+                    Ok((data, 200..=299)) if !data.is_empty() => {
+                        Some((Ok(bytes::Bytes::from(data)), last + 1))
+                    }
+                    Ok((_, status)) => {
+                        let message = match status {
+                            200..=299 => format!("GET of {path} returned no data"),
+                            status => format!("GET responded with status {status}"),
+                        };
+                        let error = std::io::Error::new(std::io::ErrorKind::Other, message);
+                        Some((Err(error), end + 1))
+                    }
+                    Err(error) => {
+                        let error =
+                            std::io::Error::new(std::io::ErrorKind::Other, error.to_string());
+                        Some((Err(error), end + 1))
+                    }
+                }
+            }
+        });
+        Ok(exact_length(stream, end + 1 - start))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), BlobError> {
+        let (_, status) = self
+            .bucket
+            .delete_object(self.path(key))
+            .await
+            .map_err(|e| BlobError::S3(e.to_string()))?;
+        match status {
+            200..=299 | 404 => Ok(()),
+            status => Err(BlobError::S3(format!(
+                "DELETE responded with status {status}"
+            ))),
+        }
+    }
+}
+
+#[cfg(feature = "backend-local")]
+#[tokio::test]
+async fn test_local_blobs() {
+    let path = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+    let blobs = LocalBlobs::new(path.clone());
+    assert_eq!(blobs.size("blob").await.unwrap(), None);
+
+    let data: Vec<u8> = (0..=255).collect();
+    let size = blobs.put("blob", Box::pin(&data[..]), 256).await.unwrap();
+    assert_eq!(size, 256);
+    assert_eq!(blobs.size("blob").await.unwrap(), Some(256));
+
+    // blobs are immutable
+    assert!(matches!(
+        blobs.put("blob", Box::pin(&data[..10]), 256).await,
+        Err(BlobError::Exists(_))
+    ));
+    assert_eq!(blobs.size("blob").await.unwrap(), Some(256));
+
+    // of concurrent uploads, only one is stored
+    let (first, second) = tokio::join!(
+        blobs.put("concurrent", Box::pin(&data[..100]), 256),
+        blobs.put("concurrent", Box::pin(&data[..200]), 256),
+    );
+    assert!(matches!(
+        (&first, &second),
+        (Ok(100), Err(BlobError::Exists(_))) | (Err(BlobError::Exists(_)), Ok(200))
+    ));
+    let size = first.or(second).unwrap();
+    assert_eq!(blobs.size("concurrent").await.unwrap(), Some(size));
+    blobs.delete("concurrent").await.unwrap();
+
+    // oversized blobs are not stored, and leave no temporary files behind
+    assert!(matches!(
+        blobs.put("large", Box::pin(&data[..]), 255).await,
+        Err(BlobError::TooLarge(255))
+    ));
+    assert_eq!(blobs.size("large").await.unwrap(), None);
+    let mut entries = tokio::fs::read_dir(&path).await.unwrap();
+    let mut names = vec![];
+    while let Some(entry) = entries.next_entry().await.unwrap() {
+        names.push(entry.file_name().into_string().unwrap());
+    }
+    assert_eq!(names, vec!["blob"]);
+
+    use rocket::futures::{StreamExt, TryStreamExt};
+    let chunks: Vec<_> = blobs
+        .get("blob", 10, 19)
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(chunks.concat(), data[10..20].to_vec());
+
+    // reading past the end of the blob is reported rather than truncated silently
+    let chunks: Vec<_> = blobs.get("blob", 200, 299).await.unwrap().collect().await;
+    assert!(chunks.last().unwrap().is_err());
+
+    blobs.delete("blob").await.unwrap();
+    assert_eq!(blobs.size("blob").await.unwrap(), None);
+    blobs.delete("blob").await.unwrap();
+
+    tokio::fs::remove_dir_all(&path).await.unwrap();
+}
+
+#[cfg(feature = "backend-local")]
+#[tokio::test]
+async fn test_local_blobs_large() {
+    use rocket::futures::StreamExt;
+
+    // sparse file, so that it takes up no disk space.
+    const SIZE: u64 = 4 * 1024 * 1024 * 1024;
+    let path = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+    tokio::fs::create_dir_all(&path).await.unwrap();
+    let file = tokio::fs::File::create(path.join("blob")).await.unwrap();
+    file.set_len(SIZE).await.unwrap();
+    let blobs = LocalBlobs::new(path.clone());
+
+    // streamed in bounded chunks, never buffered in full
+    let mut stream = blobs.get("blob", 0, SIZE - 1).await.unwrap();
+    let mut received = 0;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.unwrap();
+        assert!(chunk.len() <= BLOB_CHUNK_SIZE);
+        received += chunk.len() as u64;
+    }
+    assert_eq!(received, SIZE);
+
+    tokio::fs::remove_dir_all(&path).await.unwrap();
+}
@@ -0,0 +1,344 @@
+use crate::alert::Alerts;
+use crate::auth::SystemPrincipal;
+use log::{debug, warn};
+use rocket::data::Limits;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Status;
+use rocket::route::{self, Handler, Route};
+use rocket::serde::json::Json;
+use rocket::{catch, catchers, get, routes};
+use rocket::{Build, Data, Request, Response, Rocket, State};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum RouteBudgetError {
+    #[error("Invalid route budget {0:?}, expected <route>=<latency>:<payload>")]
+    Invalid(String),
+}
+
+/// Latency budget and payload ceiling of a route.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limit {
+    /// Maximum time a request may take.
+    pub latency: Duration,
+    /// Maximum size of request bodies, in bytes.
+    pub payload: u64,
+}
+
+/// Limit of a single route, overriding the default one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RouteBudget {
+    /// Name of the route, which is the name of its handler.
+    pub route: String,
+    pub limit: Limit,
+}
+
+/// Latency and payload budget for API routes. Requests that take longer than the latency
+/// budget are aborted with a 503, requests with bodies larger than the payload ceiling are
+/// rejected with a 413. Routes use the default limit unless one is set for their name.
+#[derive(Clone, Debug)]
+pub struct Budget {
+    default: Limit,
+    routes: HashMap<String, Limit>,
+    metrics: Arc<BudgetMetrics>,
+}
+
+/// Counters collected by the budget fairing.
+#[derive(Debug, Default)]
+pub struct BudgetMetrics {
+    /// Requests handled.
+    requests: AtomicU64,
+    /// Requests that exceeded the latency budget.
+    timeouts: AtomicU64,
+    /// Requests that exceeded the payload ceiling.
+    oversized: AtomicU64,
+}
+
+/// Structured error body returned when a budget is exceeded.
+#[derive(Serialize, Debug)]
+pub struct BudgetError {
+    status: u16,
+    error: String,
+}
+
+/// Time at which a request was received, stored in the request-local cache.
+struct RequestStart(Instant);
+
+/// Route handler that enforces the limit of a route around its handler.
+#[derive(Clone)]
+struct BudgetHandler {
+    handler: Box<dyn Handler>,
+    limit: Limit,
+    /// Size at which request body streams are cut off, see [`Budget::limits`].
+    stream: u64,
+    metrics: Arc<BudgetMetrics>,
+}
+
+impl Budget {
+    pub fn new(latency: Duration, payload: u64) -> Self {
+        Budget {
+            default: Limit { latency, payload },
+            routes: HashMap::new(),
+            metrics: Default::default(),
+        }
+    }
+
+    /// Set the limit of a route, by name.
+    pub fn route(mut self, budget: RouteBudget) -> Self {
+        self.routes.insert(budget.route, budget.limit);
+        self
+    }
+
+    /// Limit of the route with the given name, or the default one.
+    pub fn limit(&self, route: Option<&str>) -> Limit {
+        route
+            .and_then(|route| self.routes.get(route))
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    /// Largest payload ceiling of any route.
+    fn payload_max(&self) -> u64 {
+        self.routes
+            .values()
+            .map(|limit| limit.payload)
+            .fold(self.default.payload, u64::max)
+    }
+
+    /// Limits for Rocket's data guards, which stop reading request bodies at the largest
+    /// payload ceiling. Together with the `Content-Length` check of each route, this keeps
+    /// bodies of unknown length from bypassing the ceiling.
+    pub fn limits(&self, limits: Limits) -> Limits {
+        let max = self.payload_max();
+        limits.limit("bytes", max.into()).limit("json", max.into())
+    }
+
+    /// Wrap routes so that this budget is enforced for them.
+    pub fn wrap(&self, routes: Vec<Route>) -> Vec<Route> {
+        let stream = self.payload_max();
+        routes
+            .into_iter()
+            .map(|mut route| {
+                let limit = self.limit(route.name.as_deref());
+                route.handler = Box::new(BudgetHandler {
+                    handler: route.handler,
+                    limit,
+                    stream,
+                    metrics: self.metrics.clone(),
+                });
+                route
+            })
+            .collect()
+    }
+
+    pub fn metrics(&self) -> &BudgetMetrics {
+        &self.metrics
+    }
+}
+
+/// Parse route budgets like `volume_import=60000:16777216`, with the latency in
+/// milliseconds and the payload ceiling in bytes.
+impl FromStr for RouteBudget {
+    type Err = RouteBudgetError;
+
+    fn from_str(budget: &str) -> Result<Self, Self::Err> {
+        let invalid = || RouteBudgetError::Invalid(budget.to_string());
+        let (route, limit) = budget.split_once('=').ok_or_else(invalid)?;
+        let (latency, payload) = limit.split_once(':').ok_or_else(invalid)?;
+        if route.trim().is_empty() {
+            return Err(invalid());
+        }
+        Ok(RouteBudget {
+            route: route.trim().to_string(),
+            limit: Limit {
+                latency: Duration::from_millis(latency.trim().parse().map_err(|_| invalid())?),
+                payload: payload.trim().parse().map_err(|_| invalid())?,
+            },
+        })
+    }
+}
+
+impl BudgetMetrics {
+    /// Render metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        format!(
+            "storage_requests_total {}\nstorage_requests_timeout_total {}\nstorage_requests_oversized_total {}\n",
+            self.requests.load(Ordering::Relaxed),
+            self.timeouts.load(Ordering::Relaxed),
+            self.oversized.load(Ordering::Relaxed),
+        )
+    }
+}
+
+#[rocket::async_trait]
+impl Handler for BudgetHandler {
+    async fn handle<'r>(&self, request: &'r Request<'_>, data: Data<'r>) -> route::Outcome<'r> {
+        let length: Option<u64> = request
+            .headers()
+            .get_one("Content-Length")
+            .and_then(|length| length.parse().ok());
+        match length {
+            Some(length) if length > self.limit.payload => {
+                self.metrics.oversized.fetch_add(1, Ordering::Relaxed);
+                return route::Outcome::Failure(Status::PayloadTooLarge);
+            }
+            // streams are only cut off at the largest ceiling, so bodies of unknown length
+            // cannot be held to a lower one.
+            None if request.headers().contains("Transfer-Encoding")
+                && self.limit.payload < self.stream =>
+            {
+                return route::Outcome::Failure(Status::LengthRequired);
+            }
+            _ => {}
+        }
+        match tokio::time::timeout(self.limit.latency, self.handler.handle(request, data)).await {
+            // bodies of unknown length are cut off by the data guards
+            Ok(route::Outcome::Failure(status)) if status == Status::PayloadTooLarge => {
+                self.metrics.oversized.fetch_add(1, Ordering::Relaxed);
+                route::Outcome::Failure(status)
+            }
+            Ok(outcome) => outcome,
+            Err(_) => {
+                warn!(
+                    "Request {} {} exceeded latency budget of {:?}",
+                    request.method(),
+                    request.uri(),
+                    self.limit.latency
+                );
+                self.metrics.timeouts.fetch_add(1, Ordering::Relaxed);
+                route::Outcome::Failure(Status::ServiceUnavailable)
+            }
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Budget {
+    fn info(&self) -> Info {
+        Info {
+            name: "Latency and payload budget",
+            kind: Kind::Ignite | Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
+        Ok(rocket
+            .register(
+                "/",
+                catchers![length_required, payload_too_large, service_unavailable],
+            )
+            .manage(self.clone()))
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _data: &mut Data<'_>) {
+        request.local_cache(|| RequestStart(Instant::now()));
+        self.metrics.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, _response: &mut Response<'r>) {
+        let start = request.local_cache(|| RequestStart(Instant::now()));
+        let elapsed = start.0.elapsed();
+        debug!(
+            "Request {} {} took {:?}",
+            request.method(),
+            request.uri(),
+            elapsed
+        );
+    }
+}
+
+/// Limit of the route a request was matched to.
+fn route_limit(request: &Request) -> Option<Limit> {
+    let budget = request.rocket().state::<Budget>()?;
+    let route = request.route().and_then(|route| route.name.as_deref());
+    Some(budget.limit(route))
+}
+
+#[catch(411)]
+fn length_required() -> Json<BudgetError> {
+    Json(BudgetError {
+        status: 411,
+        error: "Request bodies of unknown length are not accepted here, send a Content-Length"
+            .into(),
+    })
+}
+
+#[catch(413)]
+fn payload_too_large(request: &Request) -> Json<BudgetError> {
+    let limit = route_limit(request)
+        .map(|limit| limit.payload)
+        .unwrap_or_default();
+    Json(BudgetError {
+        status: 413,
+        error: format!("Payload too large, limit is {limit} bytes"),
+    })
+}
+
+#[catch(503)]
+fn service_unavailable(request: &Request) -> Json<BudgetError> {
+    let latency = route_limit(request)
+        .map(|limit| limit.latency)
+        .unwrap_or_default();
+    Json(BudgetError {
+        status: 503,
+        error: format!("Request exceeded latency budget of {latency:?}"),
+    })
+}
+
+/// Metrics in the Prometheus text format. Only allowed for system tokens, since they
+/// reveal activity across all accounts.
+#[get("/metrics")]
+async fn metrics(
+    _system: SystemPrincipal,
+    budget: &State<Budget>,
+    alerts: &State<Alerts>,
+) -> String {
+    budget.metrics().render() + &alerts.metrics().render()
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![metrics]
+}
+
+#[test]
+fn test_route_budget_parse() {
+    assert_eq!(
+        RouteBudget::from_str("volume_import=60000:16777216").unwrap(),
+        RouteBudget {
+            route: "volume_import".into(),
+            limit: Limit {
+                latency: Duration::from_secs(60),
+                payload: 16777216,
+            },
+        }
+    );
+    assert!(RouteBudget::from_str("volume_import=60000").is_err());
+    assert!(RouteBudget::from_str("=60000:1024").is_err());
+    assert!(RouteBudget::from_str("volume_import=1m:1024").is_err());
+}
+
+#[test]
+fn test_budget_limit() {
+    let default = Limit {
+        latency: Duration::from_secs(30),
+        payload: 1024,
+    };
+    let import = Limit {
+        latency: Duration::from_secs(60),
+        payload: 4096,
+    };
+    let budget = Budget::new(default.latency, default.payload).route(RouteBudget {
+        route: "volume_import".into(),
+        limit: import,
+    });
+    assert_eq!(budget.limit(Some("volume_import")), import);
+    assert_eq!(budget.limit(Some("volume_get")), default);
+    assert_eq!(budget.limit(None), default);
+    assert_eq!(budget.payload_max(), 4096);
+}
@@ -0,0 +1,126 @@
+use rand::{thread_rng, Rng};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::tokio::time;
+use rocket::{Data, Request};
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ChaosError {
+    #[error("Invalid chaos setting {0:?}, expected database=<p>, ipfs=<p> or delay=<ms>")]
+    Invalid(String),
+}
+
+/// Fault injected by the chaos layer.
+#[derive(Error, Debug)]
+#[error("Injected {0:} fault")]
+pub struct Fault(&'static str);
+
+impl From<Fault> for sqlx::Error {
+    fn from(fault: Fault) -> Self {
+        sqlx::Error::Io(std::io::Error::new(std::io::ErrorKind::Other, fault))
+    }
+}
+
+/// Faults injected into the service, to test that it responds with the right errors and
+/// does not leave partial state behind when its dependencies fail. Database faults are
+/// injected between the writes of multi-step operations, IPFS faults act as if the node
+/// timed out, and every response can be delayed. This is only configurable with the `chaos`
+/// feature, otherwise no faults are ever injected.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Chaos {
+    /// Probability of database operations failing.
+    pub database: f64,
+    /// Probability of IPFS requests timing out.
+    pub ipfs: f64,
+    /// Delay added to every request.
+    pub delay: Duration,
+}
+
+fn inject(probability: f64) -> bool {
+    probability > 0.0 && thread_rng().gen_bool(probability.min(1.0))
+}
+
+impl Chaos {
+    /// Possibly fail a database operation.
+    pub fn database(&self) -> Result<(), sqlx::Error> {
+        if inject(self.database) {
+            return Err(Fault("database").into());
+        }
+        Ok(())
+    }
+
+    /// Possibly fail an IPFS request.
+    pub fn ipfs(&self) -> Result<(), Fault> {
+        if inject(self.ipfs) {
+            return Err(Fault("IPFS timeout"));
+        }
+        Ok(())
+    }
+}
+
+/// Parse settings like `database=0.1,ipfs=0.5,delay=200`, probabilities are between 0 and 1
+/// and the delay is in milliseconds.
+impl FromStr for Chaos {
+    type Err = ChaosError;
+
+    fn from_str(settings: &str) -> Result<Self, Self::Err> {
+        let mut chaos = Chaos::default();
+        for setting in settings.split(',').filter(|setting| !setting.is_empty()) {
+            let invalid = || ChaosError::Invalid(setting.to_string());
+            let (key, value) = setting.split_once('=').ok_or_else(invalid)?;
+            let probability = || match value.trim().parse::<f64>() {
+                Ok(value) if (0.0..=1.0).contains(&value) => Ok(value),
+                _ => Err(invalid()),
+            };
+            match key.trim() {
+                "database" => chaos.database = probability()?,
+                "ipfs" => chaos.ipfs = probability()?,
+                "delay" => {
+                    let delay = value.trim().parse().map_err(|_| invalid())?;
+                    chaos.delay = Duration::from_millis(delay);
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(chaos)
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Chaos {
+    fn info(&self) -> Info {
+        Info {
+            name: "Inject faults",
+            kind: Kind::Request,
+        }
+    }
+
+    async fn on_request(&self, _request: &mut Request<'_>, _data: &mut Data<'_>) {
+        if !self.delay.is_zero() {
+            time::sleep(self.delay).await;
+        }
+    }
+}
+
+#[test]
+fn test_chaos_parse() {
+    assert_eq!(
+        Chaos::from_str("database=0.1,ipfs=1,delay=200").unwrap(),
+        Chaos {
+            database: 0.1,
+            ipfs: 1.0,
+            delay: Duration::from_millis(200),
+        }
+    );
+    assert_eq!(Chaos::from_str("").unwrap(), Chaos::default());
+    assert!(Chaos::from_str("database=2").is_err());
+    assert!(Chaos::from_str("disk=0.5").is_err());
+    assert!(Chaos::from_str("delay").is_err());
+
+    // faults are injected according to their probability
+    let chaos = Chaos::from_str("database=1").unwrap();
+    assert!(chaos.database().is_err());
+    assert!(chaos.ipfs().is_ok());
+}
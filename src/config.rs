@@ -0,0 +1,326 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use toml::Value;
+
+/// Environment variable holding the path to the configuration file.
+pub const CONFIG_ENV: &str = "STORAGE_CONFIG";
+
+/// Settings that can be given in the configuration file, and the environment variable
+/// each of them corresponds to.
+const SETTINGS: &[(&str, &str)] = &[
+    ("database", "STORAGE_DATABASE"),
+    ("jwks", "STORAGE_JWKS"),
+    ("ipfs", "STORAGE_IPFS"),
+    ("blob_backend", "STORAGE_BLOB_BACKEND"),
+    ("listen", "STORAGE_LISTEN"),
+    ("latency_budget", "STORAGE_LATENCY_BUDGET"),
+    ("payload_limit", "STORAGE_PAYLOAD_LIMIT"),
+    ("route_budget", "STORAGE_ROUTE_BUDGET"),
+    ("manifest_limit", "STORAGE_MANIFEST_LIMIT"),
+    ("blob_limit", "STORAGE_BLOB_LIMIT"),
+    ("upload_concurrency", "STORAGE_UPLOAD_CONCURRENCY"),
+    ("db_max_connections", "STORAGE_DB_MAX_CONNECTIONS"),
+    ("db_acquire_timeout", "STORAGE_DB_ACQUIRE_TIMEOUT"),
+    ("db_statement_timeout", "STORAGE_DB_STATEMENT_TIMEOUT"),
+    ("volume_cache_size", "STORAGE_VOLUME_CACHE_SIZE"),
+    ("volume_cache_ttl", "STORAGE_VOLUME_CACHE_TTL"),
+    ("slow_query", "STORAGE_SLOW_QUERY"),
+    ("delete_grace", "STORAGE_DELETE_GRACE"),
+    ("purge_interval", "STORAGE_PURGE_INTERVAL"),
+    ("archive_after", "STORAGE_ARCHIVE_AFTER"),
+    ("reconcile_interval", "STORAGE_RECONCILE_INTERVAL"),
+    ("reconcile_sample", "STORAGE_RECONCILE_SAMPLE"),
+    ("alert_interval", "STORAGE_ALERT_INTERVAL"),
+    ("anomaly_factor", "STORAGE_ANOMALY_FACTOR"),
+    ("replicate_peer", "STORAGE_REPLICATE_PEER"),
+    ("replicate_token", "STORAGE_REPLICATE_TOKEN"),
+    ("replicate_interval", "STORAGE_REPLICATE_INTERVAL"),
+    ("upload_token_secret", "STORAGE_UPLOAD_TOKEN_SECRET"),
+    ("otlp_endpoint", "STORAGE_OTLP_ENDPOINT"),
+    ("log_redaction", "STORAGE_LOG_REDACTION"),
+    ("policy_file", "STORAGE_POLICY_FILE"),
+    ("require_signed_requests", "STORAGE_REQUIRE_SIGNED_REQUESTS"),
+    ("accept_pending", "STORAGE_ACCEPT_PENDING"),
+    ("pending_limit", "STORAGE_PENDING_LIMIT"),
+    ("cors_origin", "STORAGE_CORS_ORIGIN"),
+    ("cors_credentials", "STORAGE_CORS_CREDENTIALS"),
+    ("cors_max_age", "STORAGE_CORS_MAX_AGE"),
+];
+
+/// Settings that are switched on or off with `true` or `false`, rather than taking a value.
+const SWITCHES: &[&str] = &[
+    "require_signed_requests",
+    "accept_pending",
+    "cors_credentials",
+];
+
+/// Tables that group related settings, which can also be given outside of them.
+const SECTIONS: &[(&str, &[&str])] = &[
+    (
+        "quota",
+        &[
+            "upload_concurrency",
+            "pending_limit",
+            "blob_limit",
+            "manifest_limit",
+        ],
+    ),
+    (
+        "retention",
+        &["delete_grace", "purge_interval", "archive_after"],
+    ),
+];
+
+/// Settings that have a short flag besides the long one.
+const SHORT_FLAGS: &[(&str, &str)] = &[("database", "-d")];
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Error reading config file {0:?}: {1:}")]
+    Read(PathBuf, std::io::Error),
+    #[error("Error parsing config file: {0:}")]
+    Parse(#[from] toml::de::Error),
+    #[error("Unknown setting {0:?} in config file")]
+    Unknown(String),
+    #[error("Invalid value for setting {0:?} in config file")]
+    Invalid(String),
+}
+
+/// Value of a setting in the configuration file.
+#[derive(Debug, PartialEq, Eq)]
+enum Setting {
+    Value(String),
+    Switch(bool),
+}
+
+/// Settings from a TOML configuration file. These form the lowest layer of the
+/// configuration: environment variables override them, and command-line flags override
+/// both.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ConfigFile {
+    settings: BTreeMap<&'static str, Setting>,
+}
+
+impl ConfigFile {
+    pub fn parse(data: &str) -> Result<Self, ConfigError> {
+        let table: BTreeMap<String, Value> = toml::from_str(data)?;
+        let mut config = ConfigFile::default();
+        for (key, value) in table {
+            let section = SECTIONS.iter().find(|(section, _)| *section == key);
+            match (section, value) {
+                (Some((_, settings)), Value::Table(table)) => {
+                    for (setting, value) in table {
+                        if !settings.contains(&setting.as_str()) {
+                            return Err(ConfigError::Unknown(format!("{key}.{setting}")));
+                        }
+                        config.insert(&setting, value)?;
+                    }
+                }
+                (Some(_), _) => return Err(ConfigError::Invalid(key)),
+                (None, value) => config.insert(&key, value)?,
+            }
+        }
+        Ok(config)
+    }
+
+    fn insert(&mut self, key: &str, value: Value) -> Result<(), ConfigError> {
+        let (setting, _) = SETTINGS
+            .iter()
+            .find(|(setting, _)| *setting == key)
+            .ok_or_else(|| ConfigError::Unknown(key.to_string()))?;
+        let invalid = || ConfigError::Invalid(key.to_string());
+        let value = match value {
+            Value::Boolean(value) if SWITCHES.contains(setting) => Setting::Switch(value),
+            _ if SWITCHES.contains(setting) => return Err(invalid()),
+            Value::String(value) => Setting::Value(value),
+            Value::Integer(value) => Setting::Value(value.to_string()),
+            Value::Array(values) => Setting::Value(
+                values
+                    .into_iter()
+                    .map(|value| match value {
+                        Value::String(value) => Ok(value),
+                        _ => Err(invalid()),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?
+                    .join(","),
+            ),
+            _ => return Err(invalid()),
+        };
+        self.settings.insert(*setting, value);
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let data = std::fs::read_to_string(path).map_err(|e| ConfigError::Read(path.into(), e))?;
+        ConfigFile::parse(&data)
+    }
+
+    /// Merge the settings into command-line arguments. Settings are turned into flags placed
+    /// before the given ones, unless they are already given as flags or `env` reports their
+    /// environment variable as set, so that both of those take precedence.
+    pub fn merge(&self, args: &[OsString], env: impl Fn(&str) -> bool) -> Vec<OsString> {
+        let mut merged: Vec<OsString> = args.iter().take(1).cloned().collect();
+        for (key, variable) in SETTINGS {
+            let setting = match self.settings.get(key) {
+                Some(setting) => setting,
+                None => continue,
+            };
+            if flag_given(args, key) || env(variable) {
+                continue;
+            }
+            let flag = format!("--{}", key.replace('_', "-"));
+            match setting {
+                Setting::Value(value) => {
+                    merged.extend([OsString::from(flag), OsString::from(value)])
+                }
+                Setting::Switch(true) => merged.push(flag.into()),
+                Setting::Switch(false) => {}
+            }
+        }
+        merged.extend(args.iter().skip(1).cloned());
+        merged
+    }
+}
+
+/// Whether the setting is given as a flag in the command-line arguments.
+fn flag_given(args: &[OsString], key: &str) -> bool {
+    let long = format!("--{}", key.replace('_', "-"));
+    let short = SHORT_FLAGS
+        .iter()
+        .find(|(setting, _)| *setting == key)
+        .map(|(_, short)| *short);
+    args.iter().skip(1).any(|arg| {
+        let arg = arg.to_string_lossy();
+        arg == long
+            || arg.starts_with(&format!("{long}="))
+            || short.map_or(false, |short| arg.starts_with(short))
+    })
+}
+
+/// Path of the configuration file, from the `--config` flag or the environment.
+pub fn config_path(args: &[OsString]) -> Option<PathBuf> {
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        let arg = arg.to_string_lossy();
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+    std::env::var_os(CONFIG_ENV).map(PathBuf::from)
+}
+
+#[test]
+fn test_config_parse() {
+    let config = ConfigFile::parse(
+        r#"
+        database = "sqlite://storage.db"
+        listen = "127.0.0.1:8000"
+        accept_pending = true
+        cors_origin = ["https://a.example.com", "https://b.example.com"]
+
+        [quota]
+        upload_concurrency = 4
+
+        [retention]
+        delete_grace = 86400
+        "#,
+    )
+    .unwrap();
+    let expected: BTreeMap<&'static str, Setting> = [
+        ("database", Setting::Value("sqlite://storage.db".into())),
+        ("listen", Setting::Value("127.0.0.1:8000".into())),
+        ("accept_pending", Setting::Switch(true)),
+        (
+            "cors_origin",
+            Setting::Value("https://a.example.com,https://b.example.com".into()),
+        ),
+        ("upload_concurrency", Setting::Value("4".into())),
+        ("delete_grace", Setting::Value("86400".into())),
+    ]
+    .into_iter()
+    .collect();
+    assert_eq!(config.settings, expected);
+
+    assert!(matches!(
+        ConfigFile::parse("storage = 10"),
+        Err(ConfigError::Unknown(_))
+    ));
+    assert!(matches!(
+        ConfigFile::parse("quota = 10"),
+        Err(ConfigError::Invalid(_))
+    ));
+    assert!(matches!(
+        ConfigFile::parse("[quota]\ndelete_grace = 10"),
+        Err(ConfigError::Unknown(_))
+    ));
+    assert!(matches!(
+        ConfigFile::parse("listen = true"),
+        Err(ConfigError::Invalid(_))
+    ));
+    assert!(matches!(
+        ConfigFile::parse("accept_pending = \"yes\""),
+        Err(ConfigError::Invalid(_))
+    ));
+}
+
+#[test]
+fn test_config_merge() {
+    let config = ConfigFile::parse(
+        r#"
+        database = "sqlite://storage.db"
+        listen = "127.0.0.1:8000"
+        require_signed_requests = true
+        accept_pending = false
+
+        [retention]
+        delete_grace = 86400
+        "#,
+    )
+    .unwrap();
+    let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+
+    // settings are placed before the flags, which take precedence
+    assert_eq!(
+        config.merge(&args(&["storage", "--listen=0.0.0.0:8000"]), |_| false),
+        args(&[
+            "storage",
+            "--database",
+            "sqlite://storage.db",
+            "--delete-grace",
+            "86400",
+            "--require-signed-requests",
+            "--listen=0.0.0.0:8000",
+        ])
+    );
+
+    // as do environment variables
+    assert_eq!(
+        config.merge(&args(&["storage", "-d", "sqlite://:memory:"]), |variable| {
+            variable == "STORAGE_LISTEN" || variable == "STORAGE_DELETE_GRACE"
+        }),
+        args(&[
+            "storage",
+            "--require-signed-requests",
+            "-d",
+            "sqlite://:memory:",
+        ])
+    );
+}
+
+#[test]
+fn test_config_path() {
+    let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+    assert_eq!(
+        config_path(&args(&["storage", "--config", "/etc/storage.toml"])),
+        Some(PathBuf::from("/etc/storage.toml"))
+    );
+    assert_eq!(
+        config_path(&args(&["storage", "--config=/etc/storage.toml"])),
+        Some(PathBuf::from("/etc/storage.toml"))
+    );
+}
@@ -0,0 +1,109 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Method, Status};
+use rocket::{options, routes, Request, Response, Route};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CorsError {
+    #[error("CORS credentials cannot be allowed for any origin, list the allowed origins")]
+    WildcardCredentials,
+}
+
+/// CORS configuration, lets browser clients call the API directly. Headers are only
+/// added for requests to the API whose `Origin` is in the list of allowed origins.
+#[derive(Clone, Debug)]
+pub struct Cors {
+    /// Allowed origins, `*` allows any origin.
+    origins: Vec<String>,
+    /// Allow requests to include credentials.
+    credentials: bool,
+    /// How long browsers may cache preflight responses, in seconds.
+    max_age: u64,
+}
+
+impl Cors {
+    /// Allowing credentials along with any origin is rejected, as any site could then make
+    /// authenticated requests on behalf of its visitors.
+    pub fn new(origins: Vec<String>, credentials: bool, max_age: u64) -> Result<Self, CorsError> {
+        if credentials && origins.iter().any(|origin| origin == "*") {
+            return Err(CorsError::WildcardCredentials);
+        }
+        Ok(Cors {
+            origins,
+            credentials,
+            max_age,
+        })
+    }
+
+    /// Determines if the origin is allowed.
+    pub fn allowed(&self, origin: &str) -> bool {
+        self.origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed == origin)
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Cors {
+    fn info(&self) -> Info {
+        Info {
+            name: "CORS",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if !request.uri().path().as_str().starts_with("/api/") {
+            return;
+        }
+        let origin = match request.headers().get_one("Origin") {
+            Some(origin) if self.allowed(origin) => origin.to_string(),
+            _ => return,
+        };
+
+        response.set_raw_header("Access-Control-Allow-Origin", origin);
+        response.set_raw_header("Vary", "Origin");
+        response.set_raw_header("Access-Control-Expose-Headers", "ETag");
+        if self.credentials {
+            response.set_raw_header("Access-Control-Allow-Credentials", "true");
+        }
+        if request.method() == Method::Options {
+            response.set_raw_header(
+                "Access-Control-Allow-Methods",
+                "GET, POST, PUT, PATCH, DELETE, OPTIONS",
+            );
+            response.set_raw_header(
+                "Access-Control-Allow-Headers",
+                "Authorization, Content-Type, If-None-Match, Idempotency-Key",
+            );
+            response.set_raw_header("Access-Control-Max-Age", self.max_age.to_string());
+        }
+    }
+}
+
+/// Answers preflight requests, the headers are added by the fairing.
+#[options("/<_..>")]
+async fn preflight() -> Status {
+    Status::NoContent
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![preflight]
+}
+
+#[test]
+fn test_cors_credentials() {
+    let origins = vec!["https://dashboard.example.com".to_string()];
+    let cors = Cors::new(origins.clone(), true, 3600).unwrap();
+    assert!(cors.allowed("https://dashboard.example.com"));
+    assert!(!cors.allowed("https://evil.example.com"));
+
+    let wildcard = vec!["*".to_string()];
+    assert!(Cors::new(wildcard.clone(), false, 3600)
+        .unwrap()
+        .allowed("https://evil.example.com"));
+    assert!(matches!(
+        Cors::new(wildcard, true, 3600),
+        Err(CorsError::WildcardCredentials)
+    ));
+}
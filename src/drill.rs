@@ -0,0 +1,234 @@
+use crate::snapshot::SnapshotData;
+use crate::volume::VolumeData;
+use fractal_storage_client::{Drill, DrillOutcome, DrillResult, DrillStatus, Hash, Pubkey};
+use sqlx::{any::AnyRow, query, AnyConnection, Row};
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Maximum length of the message of a drill result.
+pub const DRILL_MESSAGE_MAX: usize = 4096;
+
+/// Maximum number of drills returned for a volume.
+const DRILL_LIST_MAX: i64 = 100;
+
+#[derive(thiserror::Error, Debug)]
+pub enum DrillError {
+    #[error("Error talking to database: {0:}")]
+    Database(#[from] sqlx::Error),
+    #[error("Error parsing key: {0:}")]
+    ParseKey(#[from] fractal_storage_client::keys::ParseError),
+    #[error("Drill message too long, at most {DRILL_MESSAGE_MAX} bytes are allowed")]
+    MessageTooLong,
+}
+
+/// Record the result of a restore drill of a snapshot of the volume.
+pub async fn record(
+    conn: &mut AnyConnection,
+    volume: &VolumeData,
+    snapshot: &SnapshotData,
+    result: &DrillResult,
+    time: u64,
+) -> Result<Drill, DrillError> {
+    if let Some(message) = &result.message {
+        if message.len() > DRILL_MESSAGE_MAX {
+            return Err(DrillError::MessageTooLong);
+        }
+    }
+    query(
+        "INSERT INTO storage_drill(volume_id, drill_snapshot, drill_outcome, drill_duration,
+            drill_message, drill_time)
+        VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(volume.id())
+    .bind(snapshot.hash().as_slice())
+    .bind(result.outcome.as_str())
+    .bind(result.duration as i64)
+    .bind(result.message.as_deref())
+    .bind(time as i64)
+    .execute(conn)
+    .await?;
+    Ok(Drill {
+        snapshot: snapshot.hash(),
+        outcome: result.outcome,
+        duration: result.duration,
+        message: result.message.clone(),
+        time,
+    })
+}
+
+fn drill_from_row(row: &AnyRow) -> Result<Option<Drill>, DrillError> {
+    let snapshot: Vec<u8> = row.try_get("drill_snapshot")?;
+    let outcome: String = row.try_get("drill_outcome")?;
+    // outcomes this version does not know about are skipped
+    let outcome = match DrillOutcome::from_str(&outcome) {
+        Ok(outcome) => outcome,
+        Err(_) => return Ok(None),
+    };
+    Ok(Some(Drill {
+        snapshot: Hash::try_from(snapshot.as_slice())?,
+        outcome,
+        duration: row.try_get::<i64, _>("drill_duration")? as u64,
+        message: row.try_get("drill_message")?,
+        time: row.try_get::<i64, _>("drill_time")? as u64,
+    }))
+}
+
+/// Most recent restore drills of the volume, most recent first.
+pub async fn list(conn: &mut AnyConnection, volume: &VolumeData) -> Result<Vec<Drill>, DrillError> {
+    let rows = query(
+        "SELECT * FROM storage_drill WHERE volume_id = ?
+        ORDER BY drill_time DESC, drill_id DESC
+        LIMIT ?",
+    )
+    .bind(volume.id())
+    .bind(DRILL_LIST_MAX)
+    .fetch_all(conn)
+    .await?;
+    let mut drills = vec![];
+    for row in &rows {
+        drills.extend(drill_from_row(row)?);
+    }
+    Ok(drills)
+}
+
+/// When each volume of the account was last verified to be restorable, including volumes
+/// that never were.
+pub async fn report(
+    conn: &mut AnyConnection,
+    account: &Uuid,
+) -> Result<Vec<DrillStatus>, DrillError> {
+    let rows = query(
+        "SELECT volume_pubkey,
+            (SELECT drill_time FROM storage_drill
+                WHERE storage_drill.volume_id = storage_volume.volume_id
+                    AND drill_outcome = 'success'
+                ORDER BY drill_time DESC, drill_id DESC LIMIT 1)
+                AS verified,
+            (SELECT drill_snapshot FROM storage_drill
+                WHERE storage_drill.volume_id = storage_volume.volume_id
+                    AND drill_outcome = 'success'
+                ORDER BY drill_time DESC, drill_id DESC LIMIT 1)
+                AS verified_snapshot,
+            (SELECT drill_outcome FROM storage_drill
+                WHERE storage_drill.volume_id = storage_volume.volume_id
+                ORDER BY drill_time DESC, drill_id DESC LIMIT 1)
+                AS latest_outcome
+        FROM storage_volume
+        WHERE account_id = ? AND volume_deleted_at IS NULL
+        ORDER BY volume_id",
+    )
+    .bind(account.to_string())
+    .fetch_all(conn)
+    .await?;
+    let mut report = vec![];
+    for row in &rows {
+        let volume: Vec<u8> = row.try_get("volume_pubkey")?;
+        let verified: Option<i64> = row.try_get("verified")?;
+        let snapshot: Option<Vec<u8>> = row.try_get("verified_snapshot")?;
+        let latest: Option<String> = row.try_get("latest_outcome")?;
+        report.push(DrillStatus {
+            volume: Pubkey::try_from(volume.as_slice())?,
+            verified: verified.map(|time| time as u64),
+            snapshot: snapshot
+                .map(|hash| Hash::try_from(hash.as_slice()))
+                .transpose()?,
+            latest: latest.and_then(|outcome| DrillOutcome::from_str(&outcome).ok()),
+        });
+    }
+    Ok(report)
+}
+
+#[tokio::test]
+async fn test_drill() {
+    use crate::snapshot::{Snapshot, MINIMUM_SNAPSHOT_SIZE};
+    use crate::volume::Volume;
+    use fractal_storage_client::{Manifest, Privkey};
+    use sqlx::AnyPool;
+
+    let pool = AnyPool::connect("sqlite://:memory:").await.unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+    let mut conn = pool.acquire().await.unwrap();
+
+    let account = Uuid::new_v4();
+    let privkey = Privkey::generate();
+    Volume::create(&mut conn, &privkey.pubkey(), &account)
+        .await
+        .unwrap();
+    Volume::create(&mut conn, &Privkey::generate().pubkey(), &account)
+        .await
+        .unwrap();
+    let volume = Volume::lookup(&mut conn, &privkey.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let manifest = Manifest {
+        generation: 0,
+        creation: 0,
+        path: "/tmp/path".into(),
+        machine: Uuid::new_v4(),
+        size: MINIMUM_SNAPSHOT_SIZE,
+        size_total: MINIMUM_SNAPSHOT_SIZE,
+        parent: None,
+        data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+            .try_into()
+            .unwrap(),
+    };
+    let snapshot = Snapshot::create_from_manifest(&mut conn, &volume, &manifest.signed(&privkey))
+        .await
+        .unwrap()
+        .fetch(&mut conn)
+        .await
+        .unwrap();
+
+    // volumes without drills are reported as never verified
+    let statuses = report(&mut conn, &account).await.unwrap();
+    assert_eq!(statuses.len(), 2);
+    assert!(statuses.iter().all(|status| status.verified.is_none()));
+
+    let result = |outcome| DrillResult {
+        snapshot: snapshot.hash(),
+        outcome,
+        duration: 1500,
+        message: None,
+    };
+    record(
+        &mut conn,
+        &volume,
+        &snapshot,
+        &result(DrillOutcome::Success),
+        100,
+    )
+    .await
+    .unwrap();
+    record(
+        &mut conn,
+        &volume,
+        &snapshot,
+        &result(DrillOutcome::Failure),
+        200,
+    )
+    .await
+    .unwrap();
+    let drills = list(&mut conn, &volume).await.unwrap();
+    assert_eq!(drills.len(), 2);
+    assert_eq!(drills[0].outcome, DrillOutcome::Failure);
+    assert_eq!(drills[1].time, 100);
+
+    // the last success counts as verified, even if a later drill failed
+    let status = report(&mut conn, &account)
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|status| status.volume == privkey.pubkey())
+        .unwrap();
+    assert_eq!(status.verified, Some(100));
+    assert_eq!(status.snapshot, Some(snapshot.hash()));
+    assert_eq!(status.latest, Some(DrillOutcome::Failure));
+
+    let mut long = result(DrillOutcome::Failure);
+    long.message = Some("x".repeat(DRILL_MESSAGE_MAX + 1));
+    assert!(matches!(
+        record(&mut conn, &volume, &snapshot, &long, 300).await,
+        Err(DrillError::MessageTooLong)
+    ));
+}
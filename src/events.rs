@@ -0,0 +1,58 @@
+use crate::auth::Principal;
+use fractal_storage_client::AccountEvent;
+use rocket::response::stream::{Event, EventStream};
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::{self, error::RecvError};
+use rocket::{get, routes, Route, Shutdown, State};
+use uuid::Uuid;
+
+/// How many events are buffered for slow subscribers before they start missing some.
+const EVENTS_CAPACITY: usize = 1024;
+
+/// Broadcasts account activity to subscribers of the events endpoint.
+#[derive(Clone)]
+pub struct Events {
+    sender: broadcast::Sender<(Uuid, AccountEvent)>,
+}
+
+impl Events {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENTS_CAPACITY);
+        Events { sender }
+    }
+
+    /// Publish an event for the given account. Events are dropped if nobody is listening.
+    pub fn publish(&self, account: &Uuid, event: AccountEvent) {
+        let _ = self.sender.send((*account, event));
+    }
+}
+
+/// Pushes events for all volumes of the authenticated account as server-sent events.
+#[get("/events")]
+async fn events(
+    context: Principal,
+    events: &State<Events>,
+    mut shutdown: Shutdown,
+) -> EventStream![] {
+    let account = *context.account();
+    let mut receiver = events.sender.subscribe();
+    EventStream! {
+        loop {
+            let (target, event) = select! {
+                message = receiver.recv() => match message {
+                    Ok(message) => message,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(_)) => continue,
+                },
+                _ = &mut shutdown => break,
+            };
+            if target == account {
+                yield Event::json(&event);
+            }
+        }
+    }
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![events]
+}
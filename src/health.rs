@@ -0,0 +1,201 @@
+use crate::ipfs::Ipfs;
+use crate::purge::now;
+use fractal_storage_client::{HealthCheck, HealthReport};
+use log::{error, info, warn};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::tokio::{select, time};
+use rocket::{get, routes, Build, Orbit, Rocket, Route, State};
+use sqlx::migrate::Migrate;
+use sqlx::{query, AnyConnection, AnyPool, Connection};
+use std::collections::BTreeSet;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// How long to wait before running a failed self-test again.
+const SELF_TEST_RETRY: Duration = Duration::from_secs(5);
+
+/// Outcome of a check with the given name.
+fn check(name: &str, result: Result<(), String>) -> HealthCheck {
+    HealthCheck {
+        name: name.into(),
+        ok: result.is_ok(),
+        message: result.err(),
+    }
+}
+
+/// Check that all migrations this version knows about were applied.
+async fn migrations(conn: &mut AnyConnection) -> Result<(), String> {
+    let applied: BTreeSet<i64> = conn
+        .list_applied_migrations()
+        .await
+        .map_err(|e| e.to_string())?
+        .iter()
+        .map(|migration| migration.version)
+        .collect();
+    let missing: Vec<String> = sqlx::migrate!()
+        .iter()
+        .filter(|migration| !applied.contains(&migration.version))
+        .map(|migration| migration.version.to_string())
+        .collect();
+    match missing.is_empty() {
+        true => Ok(()),
+        false => Err(format!("Migrations not applied: {}", missing.join(", "))),
+    }
+}
+
+/// Check that the database accepts writes, without keeping anything.
+async fn writable(conn: &mut AnyConnection) -> Result<(), sqlx::Error> {
+    let mut transaction = conn.begin().await?;
+    query("INSERT INTO storage_health(health_time) VALUES (?)")
+        .bind(now() as i64)
+        .execute(&mut transaction)
+        .await?;
+    transaction.rollback().await
+}
+
+/// Run the startup self-test: migrations are applied, the database is writable and the
+/// IPFS node, if configured, is reachable.
+pub async fn self_test(pool: &AnyPool, ipfs: Option<&Ipfs>) -> Vec<HealthCheck> {
+    let mut checks = vec![];
+    match pool.acquire().await {
+        Ok(mut conn) => {
+            checks.push(check("migrations", migrations(&mut conn).await));
+            let writable = writable(&mut conn).await.map_err(|e| e.to_string());
+            checks.push(check("database", writable));
+        }
+        Err(e) => checks.push(check("database", Err(e.to_string()))),
+    }
+    if let Some(ipfs) = ipfs {
+        let reachable = ipfs.version().await.map_err(|e| e.to_string());
+        checks.push(check("ipfs", reachable));
+    }
+    checks
+}
+
+/// Runs the self-test once the service has started, until it passes. The readiness probe
+/// fails until then, while the liveness probe only reports that the process is up.
+#[derive(Clone, Debug, Default)]
+pub struct SelfTest {
+    checks: Arc<RwLock<Option<Vec<HealthCheck>>>>,
+}
+
+impl SelfTest {
+    /// Outcome of the most recent self-test, if one has completed.
+    pub fn checks(&self) -> Option<Vec<HealthCheck>> {
+        self.checks.read().unwrap().clone()
+    }
+
+    /// Whether the most recent self-test passed.
+    pub fn passed(&self) -> bool {
+        match &*self.checks.read().unwrap() {
+            Some(checks) => checks.iter().all(|check| check.ok),
+            None => false,
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for SelfTest {
+    fn info(&self) -> Info {
+        Info {
+            name: "Startup self-test",
+            kind: Kind::Ignite | Kind::Liftoff,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
+        Ok(rocket.manage(self.clone()))
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let pool = match rocket.state::<AnyPool>() {
+            Some(pool) => pool.clone(),
+            None => return,
+        };
+        let ipfs = rocket.state::<Option<Ipfs>>().cloned().flatten();
+        let mut shutdown = rocket.shutdown();
+        let test = self.clone();
+        rocket::tokio::spawn(async move {
+            loop {
+                let checks = self_test(&pool, ipfs.as_ref()).await;
+                let failed: Vec<_> = checks.iter().filter(|check| !check.ok).collect();
+                match failed.is_empty() {
+                    true => info!("Self-test passed, ready to handle requests"),
+                    false => {
+                        for check in &failed {
+                            let message = check.message.as_deref().unwrap_or_default();
+                            error!("Self-test check {} failed: {}", check.name, message);
+                        }
+                    }
+                }
+                let passed = failed.is_empty();
+                *test.checks.write().unwrap() = Some(checks);
+                if passed {
+                    break;
+                }
+                warn!("Retrying self-test in {:?}", SELF_TEST_RETRY);
+                select! {
+                    _ = time::sleep(SELF_TEST_RETRY) => {},
+                    _ = &mut shutdown => break,
+                }
+            }
+        });
+    }
+}
+
+/// Liveness probe: the process is up and handling requests. It does not depend on the
+/// database, so that a database outage does not get the service restarted.
+#[get("/health/live")]
+async fn health_live() -> Status {
+    Status::Ok
+}
+
+/// Readiness probe: the self-test passed and the database can still be reached. Answers
+/// with a 503 and the failed checks otherwise.
+#[get("/health/ready")]
+async fn health_ready(
+    test: &State<SelfTest>,
+    pool: &State<AnyPool>,
+) -> status::Custom<Json<HealthReport>> {
+    let mut checks = test.checks().unwrap_or_else(|| {
+        let pending = Err("Self-test has not completed yet".into());
+        vec![check("self-test", pending)]
+    });
+    if test.passed() {
+        let reachable = match pool.acquire().await {
+            Ok(mut conn) => conn.ping().await.map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        if let Some(check) = checks.iter_mut().find(|check| check.name == "database") {
+            check.ok = reachable.is_ok();
+            check.message = reachable.err();
+        }
+    }
+    let ready = checks.iter().all(|check| check.ok);
+    let status = match ready {
+        true => Status::Ok,
+        false => Status::ServiceUnavailable,
+    };
+    status::Custom(status, Json(HealthReport { ready, checks }))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![health_live, health_ready]
+}
+
+#[tokio::test]
+async fn test_self_test() {
+    let pool = AnyPool::connect("sqlite://:memory:").await.unwrap();
+    let checks = self_test(&pool, None).await;
+    assert_eq!(checks.len(), 2);
+    assert!(!checks[0].ok);
+
+    sqlx::migrate!().run(&pool).await.unwrap();
+    let checks = self_test(&pool, None).await;
+    assert!(checks.iter().all(|check| check.ok));
+    let names: Vec<_> = checks.iter().map(|check| check.name.as_str()).collect();
+    assert_eq!(names, vec!["migrations", "database"]);
+}
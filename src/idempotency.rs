@@ -0,0 +1,146 @@
+use crate::volume::Volume;
+use fractal_storage_client::{Hash, IDEMPOTENCY_HEADER};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use sqlx::{query, AnyConnection, Row};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Maximum length of idempotency keys.
+pub const IDEMPOTENCY_KEY_LENGTH: usize = 255;
+
+/// How long idempotency keys are remembered for.
+pub const IDEMPOTENCY_KEY_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(thiserror::Error, Debug)]
+pub enum IdempotencyError {
+    #[error("Error talking to database: {0:}")]
+    Database(#[from] sqlx::Error),
+    #[error("Invalid idempotency key, must be printable ASCII of at most {IDEMPOTENCY_KEY_LENGTH} characters")]
+    InvalidKey,
+}
+
+/// Request guard for the [`IDEMPOTENCY_HEADER`]. Requests retried with the same key get
+/// the response of the original request replayed.
+pub struct IdempotencyKey(Option<String>);
+
+impl IdempotencyKey {
+    pub fn key(&self) -> Option<&str> {
+        self.0.as_deref()
+    }
+
+    /// Look up the manifest hash that was stored with this key, if any.
+    pub async fn lookup(
+        &self,
+        conn: &mut AnyConnection,
+        volume: &Volume,
+    ) -> Result<Option<Hash>, IdempotencyError> {
+        let key = match self.key() {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+        let row = query(
+            "SELECT snapshot_hash FROM storage_idempotency
+                WHERE volume_id = ? AND idempotency_key = ? AND idempotency_created >= ?",
+        )
+        .bind(volume.id())
+        .bind(key)
+        .bind(timestamp() - IDEMPOTENCY_KEY_LIFETIME.as_secs() as i64)
+        .fetch_optional(conn)
+        .await?;
+        match row {
+            None => Ok(None),
+            Some(row) => {
+                let hash: Vec<u8> = row.try_get("snapshot_hash")?;
+                Ok(Some(Hash::try_from(hash.as_slice()).unwrap()))
+            }
+        }
+    }
+
+    /// Remember the manifest hash for this key, expiring keys that are no longer valid.
+    pub async fn store(
+        &self,
+        conn: &mut AnyConnection,
+        volume: &Volume,
+        hash: &Hash,
+    ) -> Result<(), IdempotencyError> {
+        let key = match self.key() {
+            Some(key) => key,
+            None => return Ok(()),
+        };
+        let now = timestamp();
+        query("DELETE FROM storage_idempotency WHERE idempotency_created < ?")
+            .bind(now - IDEMPOTENCY_KEY_LIFETIME.as_secs() as i64)
+            .execute(&mut *conn)
+            .await?;
+        query(
+            "INSERT INTO storage_idempotency(
+                volume_id,
+                idempotency_key,
+                snapshot_hash,
+                idempotency_created)
+            VALUES (?, ?, ?, ?)",
+        )
+        .bind(volume.id())
+        .bind(key)
+        .bind(hash.as_slice())
+        .bind(now)
+        .execute(&mut *conn)
+        .await?;
+        Ok(())
+    }
+}
+
+/// Forget the idempotency keys a snapshot was uploaded with once it is removed, so that
+/// uploading its manifest again stores it again instead of replaying the response. Clients
+/// derive keys from the manifest hash, so the same key is sent for it.
+pub async fn idempotency_forget(
+    conn: &mut AnyConnection,
+    volume: &Volume,
+    hash: &Hash,
+) -> Result<(), sqlx::Error> {
+    query("DELETE FROM storage_idempotency WHERE volume_id = ? AND snapshot_hash = ?")
+        .bind(volume.id())
+        .bind(hash.as_slice())
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Validates an idempotency key.
+pub fn idempotency_key_valid(key: &str) -> bool {
+    !key.is_empty()
+        && key.len() <= IDEMPOTENCY_KEY_LENGTH
+        && key.chars().all(|c| c.is_ascii_graphic())
+}
+
+fn timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IdempotencyKey {
+    type Error = IdempotencyError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.headers().get_one(IDEMPOTENCY_HEADER) {
+            None => Outcome::Success(IdempotencyKey(None)),
+            Some(key) if idempotency_key_valid(key) => {
+                Outcome::Success(IdempotencyKey(Some(key.to_string())))
+            }
+            Some(_) => Outcome::Failure((Status::BadRequest, IdempotencyError::InvalidKey)),
+        }
+    }
+}
+
+#[test]
+fn test_idempotency_key_valid() {
+    assert!(idempotency_key_valid("2f1c7c3e-upload-42"));
+    assert!(!idempotency_key_valid(""));
+    assert!(!idempotency_key_valid("with space"));
+    assert!(!idempotency_key_valid(
+        &"a".repeat(IDEMPOTENCY_KEY_LENGTH + 1)
+    ));
+}
@@ -0,0 +1,300 @@
+use crate::chaos::{Chaos, Fault};
+use bytes::Bytes;
+use reqwest::Client;
+use rocket::futures::{stream, Stream, StreamExt};
+use serde::Deserialize;
+use std::io;
+use std::pin::Pin;
+use thiserror::Error;
+use tracing::instrument;
+use url::Url;
+
+/// Stream of payload data proxied from IPFS. Errors midway through are yielded rather than
+/// ending the stream, so that a truncated payload is never mistaken for a complete one.
+pub type PayloadStream = Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send>>;
+
+/// Ensure that the stream yields exactly `length` bytes, ending it with an error if it is
+/// shorter or longer than that. The stream is also ended after the first error.
+pub fn exact_length<S>(stream: S, length: u64) -> PayloadStream
+where
+    S: Stream<Item = io::Result<Bytes>> + Send + 'static,
+{
+    let stream = stream::unfold(Some((Box::pin(stream), length)), |state| async move {
+        let (mut stream, remaining) = state?;
+        match stream.next().await {
+            Some(Ok(chunk)) if chunk.len() as u64 > remaining => {
+                let error = io::Error::new(io::ErrorKind::InvalidData, "Payload is too long");
+                Some((Err(error), None))
+            }
+            Some(Ok(chunk)) => {
+                let remaining = remaining - chunk.len() as u64;
+                Some((Ok(chunk), Some((stream, remaining))))
+            }
+            Some(Err(error)) => Some((Err(error), None)),
+            None if remaining > 0 => {
+                let error = io::Error::new(io::ErrorKind::UnexpectedEof, "Payload is truncated");
+                Some((Err(error), None))
+            }
+            None => None,
+        }
+    });
+    Box::pin(stream)
+}
+
+#[derive(Error, Debug)]
+pub enum IpfsError {
+    #[error("Error talking to IPFS: {0:}")]
+    Reqwest(#[from] reqwest::Error),
+    #[error("Error parsing URL: {0:}")]
+    UrlParse(#[from] url::ParseError),
+    #[error("IPFS responded with status {0:}")]
+    Unsuccessful(reqwest::StatusCode),
+    #[error("Data URL is not an IPFS URL: {0:}")]
+    InvalidData(Url),
+    #[error("{0:}")]
+    Fault(#[from] Fault),
+}
+
+/// Client for the IPFS node that snapshot payloads are proxied from.
+#[derive(Clone, Debug)]
+pub struct Ipfs {
+    api: Url,
+    client: Client,
+    chaos: Chaos,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct FileStat {
+    size: u64,
+}
+
+impl Ipfs {
+    pub fn new(api: Url) -> Self {
+        Ipfs {
+            api,
+            client: Client::new(),
+            chaos: Chaos::default(),
+        }
+    }
+
+    /// Inject faults into requests to the IPFS node.
+    pub fn with_chaos(mut self, chaos: Chaos) -> Self {
+        self.chaos = chaos;
+        self
+    }
+
+    /// Determine the size of the data with the given CID.
+    #[instrument(skip(self))]
+    pub async fn size(&self, cid: &str) -> Result<u64, IpfsError> {
+        self.chaos.ipfs()?;
+        let url = self.api.join("/api/v0/files/stat")?;
+        let response = self
+            .client
+            .post(url)
+            .query(&[("arg", format!("/ipfs/{cid}"))])
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(IpfsError::Unsuccessful(response.status()));
+        }
+        Ok(response.json::<FileStat>().await?.size)
+    }
+
+    /// Check that the IPFS node is reachable.
+    #[instrument(skip(self))]
+    pub async fn version(&self) -> Result<(), IpfsError> {
+        self.chaos.ipfs()?;
+        let url = self.api.join("/api/v0/version")?;
+        let response = self.client.post(url).send().await?;
+        if !response.status().is_success() {
+            return Err(IpfsError::Unsuccessful(response.status()));
+        }
+        Ok(())
+    }
+
+    /// Unpin the data with the given CID, so that the IPFS node can garbage-collect it.
+    #[instrument(skip(self))]
+    pub async fn unpin(&self, cid: &str) -> Result<(), IpfsError> {
+        self.chaos.ipfs()?;
+        let url = self.api.join("/api/v0/pin/rm")?;
+        let response = self.client.post(url).query(&[("arg", cid)]).send().await?;
+        if !response.status().is_success() {
+            return Err(IpfsError::Unsuccessful(response.status()));
+        }
+        Ok(())
+    }
+
+    /// Fetch the given (inclusive) byte range of the data with the given CID. The data is
+    /// streamed, it is never buffered in memory in full.
+    #[instrument(skip(self))]
+    pub async fn cat(&self, cid: &str, start: u64, end: u64) -> Result<PayloadStream, IpfsError> {
+        self.chaos.ipfs()?;
+        let url = self.api.join("/api/v0/cat")?;
+        let length = end + 1 - start;
+        let response = self
+            .client
+            .post(url)
+            .query(&[
+                ("arg", cid.to_string()),
+                ("offset", start.to_string()),
+                ("length", length.to_string()),
+            ])
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(IpfsError::Unsuccessful(response.status()));
+        }
+
+        let stream = response
+            .bytes_stream()
+            .map(|chunk| chunk.map_err(|error| io::Error::new(io::ErrorKind::Other, error)));
+        Ok(exact_length(stream, length))
+    }
+}
+
+/// Extract the CID from an `ipfs://<cid>` data URL.
+pub fn data_cid(data: &Url) -> Result<&str, IpfsError> {
+    match (data.scheme(), data.host_str()) {
+        ("ipfs", Some(cid)) => Ok(cid),
+        _ => Err(IpfsError::InvalidData(data.clone())),
+    }
+}
+
+/// Single byte range requested with the `Range` header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ByteRange {
+    /// Offset of the first byte.
+    pub start: u64,
+    /// Offset of the last byte (inclusive), if given.
+    pub end: Option<u64>,
+}
+
+impl ByteRange {
+    /// Parse a `Range` header. Only single ranges with a start offset are supported, for
+    /// anything else `None` is returned, meaning the full content is served.
+    pub fn parse(header: &str) -> Option<Self> {
+        let range = header.trim().strip_prefix("bytes=")?;
+        if range.contains(',') {
+            return None;
+        }
+        let (start, end) = range.split_once('-')?;
+        let start = start.trim().parse().ok()?;
+        let end = match end.trim() {
+            "" => None,
+            end => Some(end.parse().ok()?),
+        };
+        match end {
+            Some(end) if end < start => None,
+            end => Some(ByteRange { start, end }),
+        }
+    }
+
+    /// Resolve this range against the size of the data, returning the inclusive start and
+    /// end offsets, or `None` if the range cannot be satisfied.
+    pub fn resolve(&self, size: u64) -> Option<(u64, u64)> {
+        if self.start >= size {
+            return None;
+        }
+        let end = self.end.unwrap_or(size - 1).min(size - 1);
+        Some((self.start, end))
+    }
+}
+
+/// Determines if the `Accept-Encoding` header allows the identity encoding. Payloads are
+/// encrypted, so compressing them is pointless and they are always sent as-is.
+pub fn identity_acceptable(header: &str) -> bool {
+    let mut wildcard = true;
+    for encoding in header.split(',') {
+        let mut parts = encoding.split(';').map(str::trim);
+        let name = parts.next().unwrap_or_default();
+        let rejected = parts.any(|param| {
+            param
+                .strip_prefix("q=")
+                .and_then(|q| q.parse::<f32>().ok())
+                .map(|q| q == 0.0)
+                .unwrap_or(false)
+        });
+        if name.eq_ignore_ascii_case("identity") {
+            return !rejected;
+        }
+        if name == "*" {
+            wildcard = !rejected;
+        }
+    }
+    wildcard
+}
+
+#[test]
+fn test_byte_range_parse() {
+    assert_eq!(
+        ByteRange::parse("bytes=0-99"),
+        Some(ByteRange {
+            start: 0,
+            end: Some(99)
+        })
+    );
+    assert_eq!(
+        ByteRange::parse("bytes=100-"),
+        Some(ByteRange {
+            start: 100,
+            end: None
+        })
+    );
+    assert_eq!(ByteRange::parse("bytes=-100"), None);
+    assert_eq!(ByteRange::parse("bytes=0-1,5-6"), None);
+    assert_eq!(ByteRange::parse("bytes=10-5"), None);
+    assert_eq!(ByteRange::parse("items=0-5"), None);
+}
+
+#[test]
+fn test_byte_range_resolve() {
+    let range = ByteRange::parse("bytes=100-").unwrap();
+    assert_eq!(range.resolve(1000), Some((100, 999)));
+    assert_eq!(range.resolve(100), None);
+    let range = ByteRange::parse("bytes=0-5000").unwrap();
+    assert_eq!(range.resolve(1000), Some((0, 999)));
+}
+
+#[test]
+fn test_identity_acceptable() {
+    assert!(identity_acceptable("gzip, deflate, br"));
+    assert!(identity_acceptable("identity"));
+    assert!(!identity_acceptable("gzip, identity;q=0"));
+    assert!(!identity_acceptable("gzip, *;q=0"));
+    assert!(identity_acceptable("identity;q=0.5, *;q=0"));
+}
+
+#[tokio::test]
+async fn test_exact_length() {
+    let chunks = || {
+        let chunks: Vec<io::Result<Bytes>> =
+            vec![Ok(Bytes::from("hello")), Ok(Bytes::from("world"))];
+        stream::iter(chunks)
+    };
+    let result: Vec<_> = exact_length(chunks(), 10).collect().await;
+    assert!(result.iter().all(Result::is_ok));
+
+    // truncated and overlong streams end in an error
+    let result: Vec<_> = exact_length(chunks(), 11).collect().await;
+    assert_eq!(result.len(), 3);
+    assert_eq!(
+        result[2].as_ref().unwrap_err().kind(),
+        io::ErrorKind::UnexpectedEof
+    );
+    let result: Vec<_> = exact_length(chunks(), 8).collect().await;
+    assert_eq!(result.len(), 2);
+    assert_eq!(
+        result[1].as_ref().unwrap_err().kind(),
+        io::ErrorKind::InvalidData
+    );
+
+    // stream ends after the first error
+    let failing = stream::iter(vec![
+        Err(io::Error::new(io::ErrorKind::Other, "failed")),
+        Ok(Bytes::from("hello")),
+    ]);
+    let result: Vec<_> = exact_length(failing, 5).collect().await;
+    assert_eq!(result.len(), 1);
+    assert!(result[0].is_err());
+}
@@ -0,0 +1,195 @@
+use crate::snapshot::Snapshot;
+use fractal_storage_client::{Hash, LabelMatch, Pubkey};
+use sqlx::{query, AnyConnection, Row};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+/// Maximum length of label keys.
+const LABEL_KEY_MAX: usize = 64;
+
+/// Maximum length of label values.
+const LABEL_VALUE_MAX: usize = 256;
+
+/// Maximum number of labels per account, and in a search.
+pub const LABELS_MAX: usize = 32;
+
+#[derive(thiserror::Error, Debug)]
+pub enum LabelError {
+    #[error("Error talking to database: {0:}")]
+    Database(#[from] sqlx::Error),
+    #[error("Invalid label {0:?}: keys are up to {LABEL_KEY_MAX} lowercase letters, digits, '.', '-' or '_', values up to {LABEL_VALUE_MAX} characters")]
+    Invalid(String),
+    #[error("Too many labels, at most {LABELS_MAX} are allowed")]
+    TooMany,
+    #[error("Error parsing key: {0:}")]
+    ParseKey(#[from] fractal_storage_client::keys::ParseError),
+}
+
+/// Labels, keyed by label key.
+pub type Labels = BTreeMap<String, String>;
+
+/// Check that a label is well-formed.
+pub fn validate(key: &str, value: &str) -> Result<(), LabelError> {
+    let key_valid = !key.is_empty()
+        && key.len() <= LABEL_KEY_MAX
+        && key
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '.' | '-' | '_'));
+    if !key_valid || value.len() > LABEL_VALUE_MAX {
+        return Err(LabelError::Invalid(format!("{key}={value}")));
+    }
+    Ok(())
+}
+
+/// Parse a label given as `key=value`.
+pub fn parse(label: &str) -> Result<(String, String), LabelError> {
+    let (key, value) = label
+        .split_once('=')
+        .ok_or_else(|| LabelError::Invalid(label.to_string()))?;
+    validate(key, value)?;
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Default labels of an account, these are attached to every snapshot uploaded to its
+/// volumes.
+pub async fn account_labels(
+    conn: &mut AnyConnection,
+    account: &Uuid,
+) -> Result<Labels, LabelError> {
+    let rows = query("SELECT * FROM storage_account_label WHERE account_uuid = ?")
+        .bind(account.as_bytes().as_slice())
+        .fetch_all(conn)
+        .await?;
+    rows.iter()
+        .map(|row| Ok((row.try_get("label_key")?, row.try_get("label_value")?)))
+        .collect()
+}
+
+/// Replace the default labels of an account. Snapshots that were already uploaded keep
+/// their labels.
+pub async fn account_labels_set(
+    conn: &mut AnyConnection,
+    account: &Uuid,
+    labels: &Labels,
+) -> Result<(), LabelError> {
+    if labels.len() > LABELS_MAX {
+        return Err(LabelError::TooMany);
+    }
+    for (key, value) in labels {
+        validate(key, value)?;
+    }
+    query("DELETE FROM storage_account_label WHERE account_uuid = ?")
+        .bind(account.as_bytes().as_slice())
+        .execute(&mut *conn)
+        .await?;
+    for (key, value) in labels {
+        query(
+            "INSERT INTO storage_account_label(account_id, account_uuid, label_key, label_value)
+            VALUES (?, ?, ?, ?)",
+        )
+        .bind(account.to_string())
+        .bind(account.as_bytes().as_slice())
+        .bind(key.as_str())
+        .bind(value.as_str())
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Attach the default labels of the account to a newly uploaded snapshot.
+pub async fn stamp(
+    conn: &mut AnyConnection,
+    snapshot: &Snapshot,
+    account: &Uuid,
+) -> Result<(), LabelError> {
+    query(
+        "INSERT INTO storage_snapshot_label(snapshot_id, label_key, label_value)
+        SELECT ?, label_key, label_value FROM storage_account_label WHERE account_uuid = ?",
+    )
+    .bind(snapshot.id())
+    .bind(account.as_bytes().as_slice())
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Labels attached to a snapshot.
+pub async fn snapshot_labels(
+    conn: &mut AnyConnection,
+    snapshot: &Snapshot,
+) -> Result<Labels, LabelError> {
+    let rows = query("SELECT * FROM storage_snapshot_label WHERE snapshot_id = ?")
+        .bind(snapshot.id())
+        .fetch_all(conn)
+        .await?;
+    rows.iter()
+        .map(|row| Ok((row.try_get("label_key")?, row.try_get("label_value")?)))
+        .collect()
+}
+
+/// Find snapshots in (non-deleted) volumes of the account that have all of the labels.
+pub async fn search(
+    conn: &mut AnyConnection,
+    account: &Uuid,
+    labels: &Labels,
+) -> Result<Vec<LabelMatch>, LabelError> {
+    if labels.len() > LABELS_MAX {
+        return Err(LabelError::TooMany);
+    }
+    let joins: String = (0..labels.len())
+        .map(|index| {
+            format!(
+                " JOIN storage_snapshot_label label{index}
+                    ON label{index}.snapshot_id = storage_snapshot.snapshot_id
+                    AND label{index}.label_key = ? AND label{index}.label_value = ?"
+            )
+        })
+        .collect();
+    let statement = format!(
+        "SELECT storage_snapshot.snapshot_id, snapshot_hash, snapshot_generation, volume_pubkey
+            FROM storage_snapshot
+            JOIN storage_volume ON storage_volume.volume_id = storage_snapshot.volume_id
+            {joins}
+            WHERE storage_volume.account_id = ? AND volume_deleted_at IS NULL
+            ORDER BY storage_snapshot.snapshot_id"
+    );
+    let mut search = query(&statement);
+    for (key, value) in labels {
+        search = search.bind(key.as_str()).bind(value.as_str());
+    }
+    let rows = search
+        .bind(account.to_string())
+        .fetch_all(&mut *conn)
+        .await?;
+    let mut matches = vec![];
+    for row in &rows {
+        let snapshot = Snapshot::from(row.try_get::<i64, _>("snapshot_id")?);
+        let hash: Vec<u8> = row.try_get("snapshot_hash")?;
+        let volume: Vec<u8> = row.try_get("volume_pubkey")?;
+        let generation: i64 = row.try_get("snapshot_generation")?;
+        matches.push(LabelMatch {
+            volume: Pubkey::try_from(volume.as_slice())?,
+            hash: Hash::try_from(hash.as_slice())?,
+            generation: generation as u64,
+            labels: snapshot_labels(conn, &snapshot).await?,
+        });
+    }
+    Ok(matches)
+}
+
+#[test]
+fn test_label_parse() {
+    assert_eq!(
+        parse("region=eu").unwrap(),
+        ("region".to_string(), "eu".to_string())
+    );
+    assert_eq!(
+        parse("tier=").unwrap(),
+        ("tier".to_string(), "".to_string())
+    );
+    assert!(parse("region").is_err());
+    assert!(parse("=eu").is_err());
+    assert!(parse("Region=eu").is_err());
+    assert!(parse(&format!("region={}", "x".repeat(LABEL_VALUE_MAX + 1))).is_err());
+}
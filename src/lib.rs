@@ -1,14 +1,72 @@
+mod account;
+mod acl;
+mod admin;
+mod alert;
 mod api;
+mod apikey;
+mod auth;
+mod backfill;
+mod blobs;
+mod budget;
+mod chaos;
+mod config;
+mod cors;
+mod drill;
+mod events;
+mod health;
+mod idempotency;
+mod ipfs;
+mod label;
+mod limit;
+mod listen;
+mod pending;
+mod policy;
+mod purge;
+mod reconcile;
+mod redact;
+mod replicate;
+mod signature;
 mod snapshot;
+mod telemetry;
 #[cfg(test)]
 mod tests;
+mod upload_token;
 mod volume;
+mod whoami;
 
+pub use crate::admin::Command;
+use crate::alert::Alerts;
+use crate::backfill::Backfill;
+use crate::blobs::Blobs;
+use crate::budget::{Budget, RouteBudget};
+use crate::chaos::Chaos;
+use crate::config::{config_path, ConfigFile};
+use crate::cors::Cors;
+use crate::events::Events;
+use crate::health::SelfTest;
+use crate::ipfs::Ipfs;
+use crate::limit::UploadLimit;
+use crate::pending::PendingUploads;
+use crate::policy::Policy;
+use crate::purge::Purge;
+use crate::reconcile::Reconcile;
+use crate::redact::Redaction;
+use crate::replicate::Replication;
+use crate::signature::RequestSigning;
+use crate::snapshot::Snapshot;
+use crate::upload_token::UploadTokens;
+use crate::volume::VolumeCache;
+use crate::whoami::SystemTokens;
 use anyhow::Result;
 use fractal_auth_client::{key_store, AuthConfig, StaticToken};
+use log::LevelFilter;
 use rocket::*;
-use sqlx::AnyPool;
+use sqlx::any::{AnyConnectOptions, AnyPoolOptions};
+use sqlx::{AnyPool, ConnectOptions};
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 use structopt::StructOpt;
 use url::Url;
 
@@ -18,6 +76,13 @@ use url::Url;
 /// for querying snapshots.
 #[derive(StructOpt)]
 pub struct Options {
+    /// TOML file to read settings from, such as `/etc/fractal-storage.toml`. Settings are
+    /// named like the flags, with underscores (`delete_grace = 86400`), switches take `true`
+    /// or `false`. Quota and retention settings can be grouped in `[quota]` and `[retention]`
+    /// tables. Environment variables and flags take precedence over it.
+    #[structopt(long, env = "STORAGE_CONFIG")]
+    config: Option<PathBuf>,
+
     /// Which database to use. Specify a string like `sqlite://:memory:` to use an in-memory
     /// database, or `sqlite://storage.db` for a local file.
     #[structopt(long, short, env = "STORAGE_DATABASE")]
@@ -28,13 +93,185 @@ pub struct Options {
     #[structopt(long, env = "STORAGE_JWKS")]
     jwks: Option<Url>,
 
-    /// IPFS node. Not required.
+    /// IPFS node. Not required, if supplied snapshot payloads can be downloaded through
+    /// this service.
     #[structopt(long, env = "STORAGE_IPFS")]
     ipfs: Option<Url>,
 
-    /// What IP address and port to listen on.
-    #[structopt(long, env = "STORAGE_LISTEN", default_value = "0.0.0.0:8000")]
-    listen: SocketAddr,
+    /// Where to store snapshot payloads that are uploaded directly to this service, for
+    /// example `file:///var/lib/storage/blobs` or `s3://bucket/prefix?region=eu-central-1`
+    /// (add `endpoint=<url>` for S3-compatible stores such as MinIO). If not supplied,
+    /// payloads can only be stored in IPFS.
+    #[structopt(long, env = "STORAGE_BLOB_BACKEND")]
+    blob_backend: Option<Url>,
+
+    /// What IP address and port to listen on. Give both wildcard addresses of a port, such
+    /// as `0.0.0.0:8000,[::]:8000`, to accept IPv4 and IPv6 connections on one dual-stack
+    /// socket.
+    #[structopt(
+        long,
+        env = "STORAGE_LISTEN",
+        default_value = "0.0.0.0:8000",
+        use_delimiter = true
+    )]
+    listen: Vec<SocketAddr>,
+
+    /// Latency budget for API requests, in milliseconds. Requests taking longer than this
+    /// are aborted with a 503 error.
+    #[structopt(long, env = "STORAGE_LATENCY_BUDGET", default_value = "30000")]
+    latency_budget: u64,
+
+    /// Maximum size of API request bodies, in bytes. Larger requests are rejected with a
+    /// 413 error.
+    #[structopt(long, env = "STORAGE_PAYLOAD_LIMIT", default_value = "1048576")]
+    payload_limit: u64,
+
+    /// Latency budget and payload limit of individual API routes, overriding the ones
+    /// above, such as `volume_import=60000:16777216`. Routes are named after their handlers.
+    /// Can be given multiple times.
+    #[structopt(long, env = "STORAGE_ROUTE_BUDGET", use_delimiter = true)]
+    route_budget: Vec<RouteBudget>,
+
+    /// Maximum size of manifests, in bytes. Larger manifests are rejected with a 413 error.
+    #[structopt(long, env = "STORAGE_MANIFEST_LIMIT", default_value = "65536")]
+    manifest_limit: u64,
+
+    /// Maximum size of payloads uploaded to the blob backend, in bytes. Larger payloads are
+    /// rejected with a 413 error. If not supplied, payloads are not limited.
+    #[structopt(long, env = "STORAGE_BLOB_LIMIT")]
+    blob_limit: Option<u64>,
+
+    /// Maximum number of uploads each account can have in flight at the same time, zero
+    /// disables the limit. Further uploads are rejected with a 429 error.
+    #[structopt(long, env = "STORAGE_UPLOAD_CONCURRENCY", default_value = "8")]
+    upload_concurrency: usize,
+
+    /// Maximum number of database connections to keep open.
+    #[structopt(long, env = "STORAGE_DB_MAX_CONNECTIONS", default_value = "10")]
+    db_max_connections: u32,
+
+    /// How long requests wait for a database connection before failing, in milliseconds.
+    #[structopt(long, env = "STORAGE_DB_ACQUIRE_TIMEOUT", default_value = "30000")]
+    db_acquire_timeout: u64,
+
+    /// How long database statements may run, in milliseconds. For SQLite, this is how long
+    /// statements wait for the database to be unlocked.
+    #[structopt(long, env = "STORAGE_DB_STATEMENT_TIMEOUT", default_value = "5000")]
+    db_statement_timeout: u64,
+
+    /// How many volumes to keep in the in-memory lookup cache, zero disables caching.
+    #[structopt(long, env = "STORAGE_VOLUME_CACHE_SIZE", default_value = "1024")]
+    volume_cache_size: usize,
+
+    /// How long cached volumes are used for, in seconds. Changes made by other instances
+    /// of the service become visible after this.
+    #[structopt(long, env = "STORAGE_VOLUME_CACHE_TTL", default_value = "30")]
+    volume_cache_ttl: u64,
+
+    /// Database statements taking longer than this many milliseconds are logged as warnings.
+    #[structopt(long, env = "STORAGE_SLOW_QUERY", default_value = "1000")]
+    slow_query: u64,
+
+    /// How long deleted volumes can be restored for, in seconds. After this, they are
+    /// purged and their payloads are unpinned.
+    #[structopt(long, env = "STORAGE_DELETE_GRACE", default_value = "604800")]
+    delete_grace: u64,
+
+    /// How often to look for deleted volumes to purge, in seconds.
+    #[structopt(long, env = "STORAGE_PURGE_INTERVAL", default_value = "3600")]
+    purge_interval: u64,
+
+    /// Archive the manifests of snapshots older than this many seconds. Archived manifests
+    /// are stored compressed and outside of the snapshot table, keeping it small for volumes
+    /// with long histories, and are rehydrated transparently when fetched.
+    #[structopt(long, env = "STORAGE_ARCHIVE_AFTER")]
+    archive_after: Option<u64>,
+
+    /// How often to verify payloads stored in the blob backend against the digest recorded
+    /// when they were uploaded, in seconds.
+    #[structopt(long, env = "STORAGE_RECONCILE_INTERVAL", default_value = "3600")]
+    reconcile_interval: u64,
+
+    /// How many payloads to verify each time, zero disables verification.
+    #[structopt(long, env = "STORAGE_RECONCILE_SAMPLE", default_value = "16")]
+    reconcile_sample: usize,
+
+    /// How often to check volumes against the alert thresholds of their accounts, in
+    /// seconds.
+    #[structopt(long, env = "STORAGE_ALERT_INTERVAL", default_value = "300")]
+    alert_interval: u64,
+
+    /// Warn about and raise alerts for snapshots more than this many times larger than the
+    /// recent snapshots of their volume, unless accounts configure their own factor. Zero
+    /// disables the check.
+    #[structopt(long, env = "STORAGE_ANOMALY_FACTOR", default_value = "10")]
+    anomaly_factor: u64,
+
+    /// Peer storage services to replicate newly uploaded manifests to, such as
+    /// `https://storage.eu.example.com`. If not supplied, nothing is replicated.
+    #[structopt(long, env = "STORAGE_REPLICATE_PEER", use_delimiter = true)]
+    replicate_peer: Vec<Url>,
+
+    /// Token to authenticate with at peers. Volumes are created on peers as needed, owned by
+    /// the account of this token.
+    #[structopt(long, env = "STORAGE_REPLICATE_TOKEN")]
+    replicate_token: Option<String>,
+
+    /// How often to push snapshots to peers, in seconds.
+    #[structopt(long, env = "STORAGE_REPLICATE_INTERVAL", default_value = "10")]
+    replicate_interval: u64,
+
+    /// Key used to sign upload tokens. If not supplied, a random key is used and upload
+    /// tokens become invalid when the service is restarted. Changing it revokes all upload
+    /// tokens.
+    #[structopt(long, env = "STORAGE_UPLOAD_TOKEN_SECRET", hide_env_values = true)]
+    upload_token_secret: Option<String>,
+
+    /// OTLP endpoint to export traces to, such as `http://localhost:4317`. Requires the
+    /// `otlp` feature. If not supplied, spans are only logged.
+    #[structopt(long, env = "STORAGE_OTLP_ENDPOINT")]
+    otlp_endpoint: Option<Url>,
+
+    /// How much of manifests to include in logs and error messages: `off` includes them in
+    /// full, `partial` shortens hashes and machines and masks paths and payload references,
+    /// `strict` removes everything but hash prefixes, generations, times and sizes.
+    #[structopt(long, env = "STORAGE_LOG_REDACTION", default_value = "partial")]
+    log_redaction: Redaction,
+
+    /// Authorization policy to apply on top of the built-in checks, as a TOML file with
+    /// rules and optionally an external policy endpoint (such as Open Policy Agent).
+    #[structopt(long, env = "STORAGE_POLICY_FILE")]
+    policy_file: Option<PathBuf>,
+
+    /// Require requests that delete or edit volumes to be signed with the volume key, so
+    /// that a leaked token alone is not enough to destroy data.
+    #[structopt(long, env = "STORAGE_REQUIRE_SIGNED_REQUESTS")]
+    require_signed_requests: bool,
+
+    /// Accept snapshots uploaded before their parent, such as when they are replayed from an
+    /// offline queue out of order. They are stored once the parent is uploaded, rather than
+    /// rejected.
+    #[structopt(long, env = "STORAGE_ACCEPT_PENDING")]
+    accept_pending: bool,
+
+    /// Maximum number of snapshots each volume can have waiting for their parent. Further
+    /// uploads of snapshots without their parent are rejected with a 429 error.
+    #[structopt(long, env = "STORAGE_PENDING_LIMIT", default_value = "100")]
+    pending_limit: u64,
+
+    /// Origins that browser clients may call the API from (CORS). Use `*` to allow any
+    /// origin. If not supplied, CORS headers are not sent.
+    #[structopt(long, env = "STORAGE_CORS_ORIGIN", use_delimiter = true)]
+    cors_origin: Vec<String>,
+
+    /// Allow CORS requests to include credentials. Cannot be combined with allowing any
+    /// origin.
+    #[structopt(long, env = "STORAGE_CORS_CREDENTIALS")]
+    cors_credentials: bool,
+
+    /// How long browsers may cache CORS preflight responses, in seconds.
+    #[structopt(long, env = "STORAGE_CORS_MAX_AGE", default_value = "3600")]
+    cors_max_age: u64,
 
     /// Disable authentication altogether, parses authentication tokens as UUIDs. This flag is
     /// deprecated, it is recommended to use `--static-system` and `--static-user` instead.
@@ -42,6 +279,13 @@ pub struct Options {
     #[structopt(long, global = true)]
     insecure_auth_stub: bool,
 
+    /// Inject faults for testing, such as `database=0.1,ipfs=0.5,delay=200`: probabilities of
+    /// database operations failing and IPFS requests timing out, and a delay in milliseconds
+    /// added to every request. Never enable this in production.
+    #[cfg(feature = "chaos")]
+    #[structopt(long, env = "STORAGE_CHAOS")]
+    chaos: Option<Chaos>,
+
     /// Adds a static user token. Supply it in the format `token:uuid`.
     #[structopt(long, env = "MANAGER_STATIC_USER", use_delimiter = true)]
     pub static_user: Vec<StaticToken>,
@@ -49,14 +293,97 @@ pub struct Options {
     /// Adds a static system token. Supply it in the format `token:uuid`.
     #[structopt(long, env = "MANAGER_STATIC_SYSTEM", use_delimiter = true)]
     pub static_system: Vec<StaticToken>,
+
+    /// Maintenance command to run instead of launching the service.
+    #[structopt(subcommand)]
+    command: Option<Command>,
 }
 
 impl Options {
-    pub async fn run(&self) -> Result<()> {
-        // connect to database
-        let pool = AnyPool::connect(&self.database).await?;
+    /// Parse options from the command line, the environment and the configuration file, in
+    /// that order of precedence.
+    pub fn load() -> Result<Self> {
+        let mut args: Vec<_> = std::env::args_os().collect();
+        if let Some(path) = config_path(&args) {
+            let env = |variable: &str| std::env::var_os(variable).is_some();
+            args = ConfigFile::load(&path)?.merge(&args, env);
+        }
+        Ok(Options::from_iter(args))
+    }
+
+    /// Set up logging and tracing, must be called before [`Options::run`].
+    pub fn telemetry(&self) -> Result<()> {
+        self.log_redaction.set();
+        telemetry::init(self.otlp_endpoint.as_ref())
+    }
+
+    /// Maintenance command to run instead of launching the service, if any.
+    pub fn command(&self) -> Option<&Command> {
+        self.command.as_ref()
+    }
+
+    /// Connect to the database and apply pending migrations.
+    async fn connect(&self) -> Result<AnyPool> {
+        let mut connect_options = AnyConnectOptions::from_str(&self.database)?;
+        connect_options
+            .log_slow_statements(LevelFilter::Warn, Duration::from_millis(self.slow_query));
+        let statement_timeout = Duration::from_millis(self.db_statement_timeout);
+        if let Some(options) = connect_options.as_sqlite_mut() {
+            *options = options.clone().busy_timeout(statement_timeout);
+        }
+        if let Some(options) = connect_options.as_postgres_mut() {
+            *options = options.clone().options([(
+                "statement_timeout",
+                format!("{}ms", self.db_statement_timeout),
+            )]);
+        }
+        let pool = AnyPoolOptions::new()
+            .max_connections(self.db_max_connections)
+            .connect_timeout(Duration::from_millis(self.db_acquire_timeout))
+            .connect_with(connect_options)
+            .await?;
         sqlx::migrate!().run(&pool).await?;
 
+        // index payload references and sort keys of snapshots stored before they were recorded
+        let mut conn = pool.acquire().await?;
+        let backfilled = Snapshot::data_backfill(&mut conn).await?;
+        if backfilled > 0 {
+            info!(
+                "Indexed {} snapshots stored before they were recorded",
+                backfilled
+            );
+        }
+        let backfilled = account::uuid_backfill(&mut conn).await?;
+        drop(conn);
+        if backfilled > 0 {
+            info!("Recorded binary accounts of {} rows", backfilled);
+        }
+        Ok(pool)
+    }
+
+    /// Run a maintenance command against the database, without launching the service.
+    pub async fn admin(&self, command: &Command) -> Result<()> {
+        let pool = self.connect().await?;
+        let ipfs = self.ipfs.clone().map(Ipfs::new);
+        let blobs = match &self.blob_backend {
+            Some(url) => Some(Blobs::from_url(url)?),
+            None => None,
+        };
+        let purge = Purge::new(
+            Duration::from_secs(self.delete_grace),
+            Duration::from_secs(self.purge_interval),
+        )
+        .with_archive(self.archive_after.map(Duration::from_secs));
+        let mut conn = pool.acquire().await?;
+        command
+            .run(&mut conn, &purge, ipfs.as_ref(), blobs.as_ref())
+            .await?;
+        Ok(())
+    }
+
+    pub async fn run(&self) -> Result<()> {
+        let pool = self.connect().await?;
+
         // auth configuration
         let mut auth_config = AuthConfig::new();
 
@@ -85,16 +412,143 @@ impl Options {
             auth_config = auth_config.with_insecure_stub(self.insecure_auth_stub);
         }
 
+        // blob backend for payloads, if set
+        let blobs = match &self.blob_backend {
+            Some(url) => {
+                info!("Storing payloads in {}", url);
+                Some(Blobs::from_url(url)?)
+            }
+            None => None,
+        };
+
+        // faults to inject, if enabled
+        #[cfg(feature = "chaos")]
+        let chaos = self.chaos.clone().unwrap_or_default();
+        #[cfg(not(feature = "chaos"))]
+        let chaos = Chaos::default();
+        if chaos != Chaos::default() {
+            error!(
+                "Injecting faults ({:?}), do not enable this in production",
+                chaos
+            );
+        }
+
+        // key for upload tokens
+        let upload_tokens = match &self.upload_token_secret {
+            Some(secret) => UploadTokens::new(secret.as_bytes()),
+            None => {
+                warn!("No upload token secret set, upload tokens are lost on restart");
+                UploadTokens::generate()
+            }
+        };
+
+        let budget = self.route_budget.iter().cloned().fold(
+            Budget::new(
+                Duration::from_millis(self.latency_budget),
+                self.payload_limit,
+            ),
+            Budget::route,
+        );
+        let mut limits = budget
+            .limits(data::Limits::default())
+            .limit(api::MANIFEST_LIMIT, self.manifest_limit.into());
+        if let Some(blob_limit) = self.blob_limit {
+            limits = limits.limit(api::PAYLOAD_LIMIT, blob_limit.into());
+        }
+
+        let address = listen::address(&self.listen)?;
         let config = Config::figment()
-            .merge(("port", self.listen.port()))
-            .merge(("address", self.listen.ip()));
-        let _rocket = rocket::custom(config)
-            .mount("/api/v1/", api::routes())
+            .merge(("port", address.port()))
+            .merge(("address", address.ip()))
+            .merge(("limits", limits));
+        let mut rocket = rocket::custom(config)
+            .mount("/api/v1/", telemetry::wrap(budget.wrap(api::routes())))
+            .mount("/api/v2/", telemetry::wrap(budget.wrap(api::routes_v2())))
+            .mount("/api/v1/", telemetry::wrap(budget.wrap(whoami::routes())))
+            .mount("/api/v1/", telemetry::wrap(events::routes()))
+            .mount("/api/v1/", telemetry::wrap(api::blobs()))
             .mount("/", api::health())
+            .mount("/", health::routes())
+            .mount("/", budget::routes())
+            .attach(budget)
+            .attach(SelfTest::default())
+            .attach(
+                Purge::new(
+                    Duration::from_secs(self.delete_grace),
+                    Duration::from_secs(self.purge_interval),
+                )
+                .with_archive(self.archive_after.map(Duration::from_secs)),
+            )
+            .attach(Reconcile::new(
+                Duration::from_secs(self.reconcile_interval),
+                self.reconcile_sample,
+            ))
+            .attach(Backfill::default())
+            .attach(Alerts::new(
+                Duration::from_secs(self.alert_interval),
+                self.anomaly_factor,
+            ))
+            .attach(Replication::new(
+                self.replicate_peer.clone(),
+                self.replicate_token.clone(),
+                Duration::from_secs(self.replicate_interval),
+            ))
             .manage(pool)
+            .manage(VolumeCache::new(
+                self.volume_cache_size,
+                Duration::from_secs(self.volume_cache_ttl),
+            ))
             .manage(auth_config)
-            .launch()
-            .await?;
+            .manage(upload_tokens)
+            .manage(UploadLimit::new(self.upload_concurrency))
+            .manage(RequestSigning {
+                required: self.require_signed_requests,
+            })
+            .manage(PendingUploads {
+                enabled: self.accept_pending,
+                limit: self.pending_limit,
+            })
+            .manage(SystemTokens(
+                self.static_system
+                    .iter()
+                    .map(|system| system.token.clone())
+                    .collect(),
+            ))
+            .manage(Events::new())
+            .manage(
+                self.ipfs
+                    .clone()
+                    .map(|ipfs| Ipfs::new(ipfs).with_chaos(chaos.clone())),
+            )
+            .manage(blobs)
+            .manage(chaos.clone());
+
+        // delay responses, if enabled
+        if !chaos.delay.is_zero() {
+            rocket = rocket.attach(chaos);
+        }
+
+        // apply custom authorization policy, if supplied
+        if let Some(path) = &self.policy_file {
+            info!("Loading authorization policy from {:?}", path);
+            rocket = rocket.manage(Policy::load(path)?);
+        }
+
+        // add CORS headers, if any origins are allowed
+        if !self.cors_origin.is_empty() {
+            info!("Allowing CORS requests from {:?}", self.cors_origin);
+            rocket = rocket
+                .mount("/api/v1/", cors::routes())
+                .mount("/api/v2/", cors::routes())
+                .attach(Cors::new(
+                    self.cors_origin.clone(),
+                    self.cors_credentials,
+                    self.cors_max_age,
+                )?);
+        }
+
+        let _rocket = rocket.launch().await?;
+        telemetry::shutdown();
 
         Ok(())
     }
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use uuid::Uuid;
+
+/// How long clients are asked to wait before retrying an upload that was rejected because
+/// their account has too many uploads in flight, in seconds.
+pub const UPLOAD_RETRY_AFTER: u64 = 1;
+
+/// Limits how many uploads each account can have in flight at the same time, so that a
+/// single runaway client cannot tie up the service. Every account gets a semaphore while it
+/// has uploads in flight, it is removed again when the last of them finishes.
+#[derive(Clone, Debug)]
+pub struct UploadLimit {
+    limit: usize,
+    accounts: Arc<Mutex<HashMap<Uuid, Arc<Semaphore>>>>,
+}
+
+/// Slot for an upload in flight, released when dropped.
+#[derive(Debug)]
+pub struct UploadPermit {
+    permit: Option<OwnedSemaphorePermit>,
+    account: Uuid,
+    accounts: Arc<Mutex<HashMap<Uuid, Arc<Semaphore>>>>,
+}
+
+impl UploadLimit {
+    /// Create a limit of uploads in flight per account, zero disables it.
+    pub fn new(limit: usize) -> Self {
+        UploadLimit {
+            limit,
+            accounts: Default::default(),
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Start an upload for the account. Returns `None` if it already has as many uploads in
+    /// flight as it is allowed to.
+    pub fn acquire(&self, account: &Uuid) -> Option<UploadPermit> {
+        let permit = match self.limit {
+            0 => None,
+            limit => {
+                let mut accounts = self.accounts.lock().unwrap();
+                let semaphore = accounts
+                    .entry(*account)
+                    .or_insert_with(|| Arc::new(Semaphore::new(limit)));
+                Some(semaphore.clone().try_acquire_owned().ok()?)
+            }
+        };
+        Some(UploadPermit {
+            permit,
+            account: *account,
+            accounts: self.accounts.clone(),
+        })
+    }
+}
+
+impl Drop for UploadPermit {
+    fn drop(&mut self) {
+        if self.permit.take().is_none() {
+            return;
+        }
+        // only the map holds on to the semaphore once no permits are left.
+        let mut accounts = self.accounts.lock().unwrap();
+        if let Some(semaphore) = accounts.get(&self.account) {
+            if Arc::strong_count(semaphore) == 1 {
+                accounts.remove(&self.account);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_upload_limit() {
+    let limit = UploadLimit::new(2);
+    let account = Uuid::new_v4();
+    let other = Uuid::new_v4();
+
+    let first = limit.acquire(&account).unwrap();
+    let second = limit.acquire(&account).unwrap();
+    assert!(limit.acquire(&account).is_none());
+
+    // accounts are limited independently
+    let third = limit.acquire(&other).unwrap();
+
+    // finished uploads free their slot, idle accounts are forgotten
+    drop(first);
+    let first = limit.acquire(&account).unwrap();
+    drop(first);
+    drop(second);
+    drop(third);
+    assert!(limit.accounts.lock().unwrap().is_empty());
+
+    // zero disables the limit
+    let unlimited = UploadLimit::new(0);
+    let permits: Vec<_> = (0..16).map(|_| unlimited.acquire(&account)).collect();
+    assert!(permits.iter().all(Option::is_some));
+    assert!(unlimited.accounts.lock().unwrap().is_empty());
+}
@@ -0,0 +1,88 @@
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::net::{Ipv6Addr, SocketAddr};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ListenError {
+    #[error("No listen address")]
+    Empty,
+    #[error("Cannot listen on {0:?}, only one address or the IPv4 and IPv6 wildcard addresses of one port are supported")]
+    Multiple(Vec<SocketAddr>),
+    #[error("Cannot listen on IPv4 and IPv6 with a single socket, this host only allows IPv6 connections on IPv6 sockets")]
+    SingleStack,
+    #[error("Error probing socket options: {0:}")]
+    Io(#[from] io::Error),
+}
+
+/// Address the service binds, out of the listen addresses. The service is served from a
+/// single socket, so either a single address can be given, or the IPv4 and IPv6 wildcard
+/// addresses of one port. These are served by a dual-stack socket bound to the IPv6
+/// wildcard address, which accepts IPv4 connections as IPv4-mapped addresses.
+pub fn address(addresses: &[SocketAddr]) -> Result<SocketAddr, ListenError> {
+    let mut addresses = addresses.to_vec();
+    addresses.sort();
+    addresses.dedup();
+    match addresses[..] {
+        [] => Err(ListenError::Empty),
+        [address] => Ok(address),
+        [first, second]
+            if first.port() == second.port()
+                && first.ip().is_unspecified()
+                && second.ip().is_unspecified()
+                && first.is_ipv4() != second.is_ipv4() =>
+        {
+            if !dual_stack()? {
+                return Err(ListenError::SingleStack);
+            }
+            Ok(SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), first.port()))
+        }
+        _ => Err(ListenError::Multiple(addresses)),
+    }
+}
+
+/// Whether IPv6 sockets accept IPv4 connections by default. The service does not create
+/// its socket itself, so `IPV6_V6ONLY` can only be read here, not changed. On Linux it
+/// follows the `net.ipv6.bindv6only` sysctl, which is off unless changed.
+fn dual_stack() -> io::Result<bool> {
+    let socket = Socket::new(Domain::IPV6, Type::STREAM, Some(Protocol::TCP))?;
+    Ok(!socket.only_v6()?)
+}
+
+#[test]
+fn test_listen_address() {
+    let parse = |addresses: &[&str]| {
+        addresses
+            .iter()
+            .map(|address| address.parse().unwrap())
+            .collect::<Vec<SocketAddr>>()
+    };
+    assert_eq!(
+        address(&parse(&["127.0.0.1:8000"])).unwrap(),
+        "127.0.0.1:8000".parse().unwrap()
+    );
+    assert_eq!(
+        address(&parse(&["[::1]:8000", "[::1]:8000"])).unwrap(),
+        "[::1]:8000".parse().unwrap()
+    );
+    assert!(matches!(address(&[]), Err(ListenError::Empty)));
+    assert!(matches!(
+        address(&parse(&["0.0.0.0:8000", "0.0.0.0:8001"])),
+        Err(ListenError::Multiple(_))
+    ));
+    assert!(matches!(
+        address(&parse(&["127.0.0.1:8000", "[::1]:8000"])),
+        Err(ListenError::Multiple(_))
+    ));
+    assert!(matches!(
+        address(&parse(&["0.0.0.0:8000", "[::]:8001"])),
+        Err(ListenError::Multiple(_))
+    ));
+    match address(&parse(&["0.0.0.0:8000", "[::]:8000"])) {
+        Ok(address) => assert_eq!(address, "[::]:8000".parse().unwrap()),
+        Err(error) => assert!(matches!(
+            error,
+            ListenError::SingleStack | ListenError::Io(_)
+        )),
+    }
+}
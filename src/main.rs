@@ -1,15 +1,25 @@
 use fractal_storage::Options;
 use log::*;
-use structopt::StructOpt;
 
 #[rocket::main]
 async fn main() {
-    env_logger::init();
-    let options = Options::from_args();
-    match options.run().await {
-        Ok(()) => {}
+    let options = match Options::load() {
+        Ok(options) => options,
         Err(error) => {
-            error!("Fatal error: {error:?}");
+            eprintln!("Error loading configuration: {error:?}");
+            return;
         }
+    };
+    if let Err(error) = options.telemetry() {
+        eprintln!("Error setting up logging: {error:?}");
+        return;
+    }
+    let result = match options.command() {
+        Some(command) => options.admin(command).await,
+        None => options.run().await,
+    };
+    if let Err(error) = result {
+        error!("Fatal error: {error:?}");
+        std::process::exit(1);
     }
 }
@@ -0,0 +1,190 @@
+use crate::volume::{Volume, VolumeData};
+use fractal_storage_client::{Hash, ManifestSigned, SnapshotPending};
+use sqlx::{query, AnyConnection, Row};
+
+#[derive(thiserror::Error, Debug)]
+pub enum PendingError {
+    #[error("Error talking to database: {0:}")]
+    Database(#[from] sqlx::Error),
+    #[error("Error parsing key: {0:}")]
+    ParseKey(#[from] fractal_storage_client::keys::ParseError),
+    #[error("Volume already has {0:} snapshots waiting for their parent")]
+    LimitExceeded(u64),
+}
+
+/// Whether snapshots uploaded before their parent are accepted. They are kept aside, and
+/// stored once the parent is uploaded.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PendingUploads {
+    pub enabled: bool,
+    /// Maximum number of snapshots each volume can have waiting for their parent.
+    pub limit: u64,
+}
+
+/// Keep a snapshot whose parent was not uploaded yet, replacing a previous upload of it.
+/// Fails if the volume already has `limit` other snapshots waiting.
+pub async fn store(
+    conn: &mut AnyConnection,
+    volume: &VolumeData,
+    manifest: &ManifestSigned,
+    parent: &Hash,
+    time: u64,
+    limit: u64,
+) -> Result<(), PendingError> {
+    let hash = manifest.hash();
+    query("DELETE FROM storage_snapshot_pending WHERE volume_id = ? AND pending_hash = ?")
+        .bind(volume.id())
+        .bind(hash.as_slice())
+        .execute(&mut *conn)
+        .await?;
+    if count(&mut *conn, volume).await? >= limit {
+        return Err(PendingError::LimitExceeded(limit));
+    }
+    query(
+        "INSERT INTO storage_snapshot_pending(volume_id, pending_hash, pending_parent,
+            pending_generation, pending_manifest, pending_uploaded)
+        VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(volume.id())
+    .bind(hash.as_slice())
+    .bind(parent.as_slice())
+    .bind(manifest.manifest.generation as i64)
+    .bind(manifest.data())
+    .bind(time as i64)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Snapshots of the volume waiting for their parent, ordered by generation.
+pub async fn list(
+    conn: &mut AnyConnection,
+    volume: &VolumeData,
+) -> Result<Vec<SnapshotPending>, PendingError> {
+    let rows = query(
+        "SELECT pending_hash, pending_parent, pending_generation, pending_uploaded
+        FROM storage_snapshot_pending WHERE volume_id = ?
+        ORDER BY pending_generation, pending_id",
+    )
+    .bind(volume.id())
+    .fetch_all(conn)
+    .await?;
+    let mut pending = vec![];
+    for row in &rows {
+        let hash: Vec<u8> = row.try_get("pending_hash")?;
+        let parent: Vec<u8> = row.try_get("pending_parent")?;
+        pending.push(SnapshotPending {
+            hash: Hash::try_from(hash.as_slice())?,
+            generation: row.try_get::<i64, _>("pending_generation")? as u64,
+            parent: Hash::try_from(parent.as_slice())?,
+            uploaded: row.try_get::<i64, _>("pending_uploaded")? as u64,
+        });
+    }
+    Ok(pending)
+}
+
+/// Number of snapshots of the volume waiting for their parent.
+pub async fn count(conn: &mut AnyConnection, volume: &VolumeData) -> Result<u64, PendingError> {
+    let row = query("SELECT COUNT(*) AS pending FROM storage_snapshot_pending WHERE volume_id = ?")
+        .bind(volume.id())
+        .fetch_one(conn)
+        .await?;
+    Ok(row.try_get::<i64, _>("pending")? as u64)
+}
+
+/// Remove the snapshots waiting for the given parent, returning the volume each of them was
+/// uploaded to along with its signed manifest.
+pub async fn take(
+    conn: &mut AnyConnection,
+    parent: &Hash,
+) -> Result<Vec<(Volume, Vec<u8>)>, PendingError> {
+    let rows = query(
+        "SELECT volume_id, pending_manifest FROM storage_snapshot_pending
+        WHERE pending_parent = ?
+        ORDER BY pending_generation, pending_id",
+    )
+    .bind(parent.as_slice())
+    .fetch_all(&mut *conn)
+    .await?;
+    query("DELETE FROM storage_snapshot_pending WHERE pending_parent = ?")
+        .bind(parent.as_slice())
+        .execute(&mut *conn)
+        .await?;
+    let mut children = vec![];
+    for row in &rows {
+        let volume: i64 = row.try_get("volume_id")?;
+        children.push((Volume::from(volume), row.try_get("pending_manifest")?));
+    }
+    Ok(children)
+}
+
+#[tokio::test]
+async fn test_pending() {
+    use fractal_storage_client::{Manifest, Parent, Privkey};
+    use sqlx::AnyPool;
+    use uuid::Uuid;
+
+    let pool = AnyPool::connect("sqlite://:memory:").await.unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+    let mut conn = pool.acquire().await.unwrap();
+
+    let privkey = Privkey::generate();
+    Volume::create(&mut conn, &privkey.pubkey(), &Uuid::new_v4())
+        .await
+        .unwrap();
+    let volume = Volume::lookup(&mut conn, &privkey.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let parent = Hash::generate(b"parent");
+    let manifest = Manifest {
+        generation: 1,
+        creation: 0,
+        path: "/tmp/path".into(),
+        machine: Uuid::new_v4(),
+        size: 64,
+        size_total: 128,
+        parent: Some(Parent::new(parent)),
+        data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+            .try_into()
+            .unwrap(),
+    }
+    .sign(&privkey);
+
+    // uploading the same snapshot again replaces it
+    store(&mut conn, &volume, &manifest, &parent, 100, 1)
+        .await
+        .unwrap();
+    store(&mut conn, &volume, &manifest, &parent, 200, 1)
+        .await
+        .unwrap();
+    assert_eq!(count(&mut conn, &volume).await.unwrap(), 1);
+
+    // other snapshots are rejected once the volume reaches the limit
+    let other = Manifest {
+        generation: 2,
+        ..manifest.manifest.clone()
+    }
+    .sign(&privkey);
+    assert!(matches!(
+        store(&mut conn, &volume, &other, &parent, 300, 1).await,
+        Err(PendingError::LimitExceeded(1))
+    ));
+    assert_eq!(count(&mut conn, &volume).await.unwrap(), 1);
+    assert_eq!(
+        list(&mut conn, &volume).await.unwrap(),
+        vec![SnapshotPending {
+            hash: manifest.hash(),
+            generation: 1,
+            parent,
+            uploaded: 200,
+        }]
+    );
+
+    assert!(take(&mut conn, &manifest.hash()).await.unwrap().is_empty());
+    let children = take(&mut conn, &parent).await.unwrap();
+    assert_eq!(children.len(), 1);
+    assert_eq!(children[0].0.id(), volume.id());
+    assert_eq!(children[0].1, manifest.data());
+    assert_eq!(count(&mut conn, &volume).await.unwrap(), 0);
+}
@@ -0,0 +1,240 @@
+use fractal_storage_client::{ApiKeyScope, Pubkey};
+use rocket::http::Method;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use thiserror::Error;
+use url::Url;
+use uuid::Uuid;
+
+/// How long to wait for the external policy endpoint to make a decision.
+const OPA_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Error, Debug)]
+pub enum PolicyError {
+    #[error("Error reading policy file {0:?}: {1:}")]
+    Read(PathBuf, std::io::Error),
+    #[error("Error parsing policy file: {0:}")]
+    Parse(#[from] toml::de::Error),
+    #[error("Error talking to policy endpoint: {0:}")]
+    Opa(#[from] reqwest::Error),
+}
+
+/// Decision of a policy rule.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Effect {
+    Allow,
+    Deny,
+}
+
+impl Default for Effect {
+    fn default() -> Self {
+        Effect::Allow
+    }
+}
+
+/// Kind of operation a request performs.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Operation {
+    Read,
+    Write,
+}
+
+impl From<&Method> for Operation {
+    fn from(method: &Method) -> Self {
+        match method {
+            Method::Get | Method::Head => Operation::Read,
+            _ => Operation::Write,
+        }
+    }
+}
+
+/// Context of an authenticated request that policies are evaluated against. This is also
+/// what is sent to the external policy endpoint, as `input`.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct PolicyInput {
+    pub account: Uuid,
+    /// Whether the request was authenticated with an API key.
+    pub api_key: bool,
+    /// Scope of the API key, if it is restricted to one.
+    pub scope: Option<ApiKeyScope>,
+    /// Volume the request refers to, if any.
+    pub volume: Option<Pubkey>,
+    pub operation: Operation,
+    pub method: String,
+    pub path: String,
+}
+
+/// Rule of the built-in policy engine. A rule matches a request if every condition it
+/// sets matches, conditions that are left out match any request.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub effect: Effect,
+    #[serde(default)]
+    pub accounts: Vec<Uuid>,
+    #[serde(default)]
+    pub volumes: Vec<Pubkey>,
+    #[serde(default)]
+    pub operations: Vec<Operation>,
+    /// Path prefixes, such as `/api/v1/volume`.
+    #[serde(default)]
+    pub paths: Vec<String>,
+    pub api_key: Option<bool>,
+}
+
+impl Rule {
+    pub fn matches(&self, input: &PolicyInput) -> bool {
+        (self.accounts.is_empty() || self.accounts.contains(&input.account))
+            && (self.volumes.is_empty()
+                || input
+                    .volume
+                    .map(|volume| self.volumes.contains(&volume))
+                    .unwrap_or(false))
+            && (self.operations.is_empty() || self.operations.contains(&input.operation))
+            && (self.paths.is_empty()
+                || self
+                    .paths
+                    .iter()
+                    .any(|path| input.path.starts_with(path.as_str())))
+            && self
+                .api_key
+                .map(|api_key| api_key == input.api_key)
+                .unwrap_or(true)
+    }
+}
+
+/// Authorization policy evaluated for every authenticated request, on top of the checks the
+/// service makes itself. Rules are evaluated in order and the first one that matches
+/// decides, if none match the default applies. Requests allowed by the rules can further be
+/// checked by an external policy endpoint (such as Open Policy Agent), which is sent the
+/// request context as `input` and has to respond with `{"result": true}` to allow it.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    #[serde(default)]
+    pub default: Effect,
+    #[serde(default, rename = "rule")]
+    pub rules: Vec<Rule>,
+    pub opa: Option<Url>,
+    #[serde(skip)]
+    client: reqwest::Client,
+}
+
+#[derive(Serialize)]
+struct OpaRequest<'a> {
+    input: &'a PolicyInput,
+}
+
+#[derive(Deserialize)]
+struct OpaResponse {
+    #[serde(default)]
+    result: bool,
+}
+
+impl Policy {
+    pub fn parse(data: &str) -> Result<Self, PolicyError> {
+        Ok(toml::from_str(data)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, PolicyError> {
+        let data = std::fs::read_to_string(path).map_err(|e| PolicyError::Read(path.into(), e))?;
+        Policy::parse(&data)
+    }
+
+    /// Decision of the built-in rules.
+    pub fn decide(&self, input: &PolicyInput) -> Effect {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(input))
+            .map(|rule| rule.effect)
+            .unwrap_or(self.default)
+    }
+
+    /// Determine if a request is allowed.
+    pub async fn allows(&self, input: &PolicyInput) -> Result<bool, PolicyError> {
+        if self.decide(input) == Effect::Deny {
+            return Ok(false);
+        }
+        let opa = match &self.opa {
+            Some(opa) => opa,
+            None => return Ok(true),
+        };
+        let response: OpaResponse = self
+            .client
+            .post(opa.clone())
+            .timeout(OPA_TIMEOUT)
+            .json(&OpaRequest { input })
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(response.result)
+    }
+}
+
+#[test]
+fn test_policy() {
+    let account = Uuid::new_v4();
+    let volume = fractal_storage_client::Privkey::generate().pubkey();
+    let policy = Policy::parse(&format!(
+        r#"
+        default = "allow"
+
+        [[rule]]
+        effect = "allow"
+        accounts = ["{account}"]
+
+        [[rule]]
+        effect = "deny"
+        operations = ["write"]
+        volumes = ["{volume}"]
+
+        [[rule]]
+        effect = "deny"
+        api_key = true
+        paths = ["/api/v1/events"]
+        "#
+    ))
+    .unwrap();
+    let input = PolicyInput {
+        account: Uuid::new_v4(),
+        api_key: false,
+        scope: None,
+        volume: Some(volume),
+        operation: Operation::Read,
+        method: "GET".into(),
+        path: format!("/api/v1/volume/{volume}"),
+    };
+    assert_eq!(policy.decide(&input), Effect::Allow);
+
+    // writes to the volume are denied, except for the allowed account
+    let write = PolicyInput {
+        operation: Operation::Write,
+        method: "POST".into(),
+        ..input.clone()
+    };
+    assert_eq!(policy.decide(&write), Effect::Deny);
+    let write = PolicyInput { account, ..write };
+    assert_eq!(policy.decide(&write), Effect::Allow);
+
+    // rules can match on the credential and path
+    let events = PolicyInput {
+        api_key: true,
+        volume: None,
+        path: "/api/v1/events".into(),
+        ..input.clone()
+    };
+    assert_eq!(policy.decide(&events), Effect::Deny);
+    let events = PolicyInput {
+        api_key: false,
+        ..events
+    };
+    assert_eq!(policy.decide(&events), Effect::Allow);
+
+    assert!(Policy::parse("[[rule]]\neffect = \"maybe\"").is_err());
+    assert!(Policy::parse("unknown = 1").is_err());
+}
@@ -0,0 +1,564 @@
+use crate::blobs::Blobs;
+use crate::idempotency::idempotency_forget;
+use crate::ipfs::{data_cid, Ipfs};
+use crate::redact::RedactedHash;
+use crate::snapshot::{Snapshot, SnapshotData, SnapshotError};
+use crate::volume::{Volume, VolumeData, VolumeError};
+use log::{error, info, warn};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::tokio::{select, time};
+use rocket::{Build, Orbit, Rocket};
+use sqlx::{query, AnyConnection, AnyPool, Connection, Row};
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use url::Url;
+
+/// How many queued payload releases to process per query.
+const RELEASE_BATCH: i64 = 256;
+
+#[derive(Error, Debug)]
+pub enum PurgeError {
+    #[error("Error talking to database: {0:}")]
+    Database(#[from] sqlx::Error),
+    #[error("Error in volume: {0:}")]
+    Volume(#[from] VolumeError),
+    #[error("Error in snapshots: {0:}")]
+    Snapshot(#[from] SnapshotError),
+}
+
+/// Release the payload of a snapshot that is being deleted. This is best-effort, failures
+/// are only logged.
+async fn release(snapshot: &SnapshotData, ipfs: Option<&Ipfs>, blobs: Option<&Blobs>) {
+    if let Some(ipfs) = ipfs {
+        if let Ok(cid) = data_cid(&snapshot.manifest().data) {
+            if let Err(e) = ipfs.unpin(cid).await {
+                warn!("Error unpinning {}: {}", cid, e);
+            }
+        }
+    }
+    if let Some(blobs) = blobs {
+        let key = snapshot.hash().to_hex();
+        if let Err(e) = blobs.backend().delete(&key).await {
+            warn!("Error deleting payload {}: {}", RedactedHash::new(&key), e);
+        }
+    }
+}
+
+/// Delete a snapshot and release its payload. Callers have to make sure that it is not the
+/// parent of other snapshots and not within the write-once period of its volume.
+pub async fn remove(
+    conn: &mut AnyConnection,
+    snapshot: &SnapshotData,
+    ipfs: Option<&Ipfs>,
+    blobs: Option<&Blobs>,
+) -> Result<(), SnapshotError> {
+    snapshot.snapshot().delete(conn).await?;
+    idempotency_forget(conn, &snapshot.volume(), &snapshot.hash()).await?;
+    // payloads may be shared with other snapshots, these keep them pinned.
+    if Snapshot::data_references(conn, &snapshot.manifest().data).await? == 0 {
+        release(snapshot, ipfs, blobs).await;
+    } else if let Some(blobs) = blobs {
+        release(snapshot, None, Some(blobs)).await;
+    }
+    Ok(())
+}
+
+/// Determine which snapshots of a volume to keep under its retention settings. The newest
+/// snapshot is always kept, as are the ancestors of kept snapshots, since they are needed to
+/// restore them.
+pub fn retained(
+    snapshots: &[SnapshotData],
+    count: Option<u64>,
+    age: Option<u64>,
+    now: u64,
+) -> BTreeSet<Snapshot> {
+    let mut sorted: Vec<&SnapshotData> = snapshots.iter().collect();
+    sorted.sort_by_key(|snapshot| {
+        std::cmp::Reverse((snapshot.manifest().generation, snapshot.snapshot()))
+    });
+    let parents: BTreeMap<Snapshot, Option<Snapshot>> = snapshots
+        .iter()
+        .map(|snapshot| (snapshot.snapshot(), snapshot.parent()))
+        .collect();
+    let mut keep = BTreeSet::new();
+    for (index, snapshot) in sorted.iter().enumerate() {
+        let within_count = count.map(|count| (index as u64) < count).unwrap_or(true);
+        let within_age = age
+            .map(|age| snapshot.manifest().creation.saturating_add(age) >= now)
+            .unwrap_or(true);
+        if index == 0 || (within_count && within_age) {
+            let mut current = Some(snapshot.snapshot());
+            while let Some(snapshot) = current {
+                if !keep.insert(snapshot) {
+                    break;
+                }
+                current = parents.get(&snapshot).copied().flatten();
+            }
+        }
+    }
+    keep
+}
+
+/// Deleted volumes can be restored during a grace period, after which a background task
+/// purges them: their metadata is deleted and their payloads are unpinned. The same task
+/// prunes snapshots of volumes beyond their retention settings.
+#[derive(Clone, Debug)]
+pub struct Purge {
+    /// How long deleted volumes can be restored for.
+    pub grace: Duration,
+    /// How often to look for volumes to purge.
+    pub interval: Duration,
+    /// How old snapshots need to be for their manifests to be archived, if at all.
+    pub archive: Option<Duration>,
+}
+
+impl Purge {
+    pub fn new(grace: Duration, interval: Duration) -> Self {
+        Purge {
+            grace,
+            interval,
+            archive: None,
+        }
+    }
+
+    /// Archive the manifests of snapshots once they are older than the given age.
+    pub fn with_archive(mut self, archive: Option<Duration>) -> Self {
+        self.archive = archive;
+        self
+    }
+
+    /// Archive the manifests of old snapshots, if enabled, returning how many were archived.
+    pub async fn archive(&self, conn: &mut AnyConnection) -> Result<usize, PurgeError> {
+        let archive = match self.archive {
+            Some(archive) => archive,
+            None => return Ok(0),
+        };
+        let archived = Snapshot::archive(conn, now().saturating_sub(archive.as_secs())).await?;
+        if archived > 0 {
+            info!("Archived manifests of {} snapshots", archived);
+        }
+        Ok(archived)
+    }
+
+    /// Determines if the grace period of a deleted volume has elapsed.
+    pub fn expired(&self, volume: &VolumeData) -> bool {
+        match volume.deleted_at() {
+            Some(time) => time + self.grace.as_secs() <= now(),
+            None => false,
+        }
+    }
+
+    /// Purge all volumes whose grace period has elapsed, returning how many were purged.
+    pub async fn run(
+        &self,
+        conn: &mut AnyConnection,
+        ipfs: Option<&Ipfs>,
+        blobs: Option<&Blobs>,
+    ) -> Result<usize, PurgeError> {
+        let cutoff = now().saturating_sub(self.grace.as_secs());
+        let volumes = Volume::deleted_before(conn, cutoff + 1).await?;
+        let mut purged = 0;
+        for volume in &volumes {
+            // volumes are kept until their write-once period has passed.
+            if volume.immutable(conn, now()).await? {
+                continue;
+            }
+            // the metadata is deleted first, so that payloads shared with snapshots of other
+            // volumes can be told apart by their remaining references.
+            let snapshots = Snapshot::list(conn, &volume.volume(), None, false).await?;
+            let mut transaction = conn.begin().await?;
+            query("DELETE FROM storage_snapshot WHERE volume_id = ?")
+                .bind(volume.id())
+                .execute(&mut *transaction)
+                .await?;
+            volume.purge(&mut transaction).await?;
+            transaction.commit().await?;
+
+            // payloads are released on a best-effort basis.
+            for snapshot in &snapshots {
+                if Snapshot::data_references(conn, &snapshot.manifest().data).await? == 0 {
+                    release(snapshot, ipfs, blobs).await;
+                } else if let Some(blobs) = blobs {
+                    release(snapshot, None, Some(blobs)).await;
+                }
+            }
+            info!(
+                "Purged volume {} ({} snapshots)",
+                volume.pubkey(),
+                snapshots.len()
+            );
+            purged += 1;
+        }
+        Ok(purged)
+    }
+
+    /// Release payloads queued when their snapshots were deleted in bulk, returning how many
+    /// were processed. IPFS payloads are only unpinned once no snapshot references them.
+    pub async fn release_queued(
+        &self,
+        conn: &mut AnyConnection,
+        ipfs: Option<&Ipfs>,
+        blobs: Option<&Blobs>,
+    ) -> Result<usize, PurgeError> {
+        let mut released = 0;
+        loop {
+            let rows = query("SELECT * FROM storage_release ORDER BY release_id LIMIT ?")
+                .bind(RELEASE_BATCH)
+                .fetch_all(&mut *conn)
+                .await?;
+            for row in &rows {
+                let id: i64 = row.try_get("release_id")?;
+                let data: String = row.try_get("release_data")?;
+                let key: String = row.try_get("release_key")?;
+                if let (Some(ipfs), Ok(data)) = (ipfs, Url::parse(&data)) {
+                    if Snapshot::data_references(conn, &data).await? == 0 {
+                        if let Ok(cid) = data_cid(&data) {
+                            if let Err(e) = ipfs.unpin(cid).await {
+                                warn!("Error unpinning {}: {}", cid, e);
+                            }
+                        }
+                    }
+                }
+                if let Some(blobs) = blobs {
+                    if let Err(e) = blobs.backend().delete(&key).await {
+                        warn!("Error deleting payload {}: {}", RedactedHash::new(&key), e);
+                    }
+                }
+                query("DELETE FROM storage_release WHERE release_id = ?")
+                    .bind(id)
+                    .execute(&mut *conn)
+                    .await?;
+            }
+            released += rows.len();
+            if (rows.len() as i64) < RELEASE_BATCH {
+                break;
+            }
+        }
+        if released > 0 {
+            info!("Released {} payloads of deleted snapshots", released);
+        }
+        Ok(released)
+    }
+
+    /// Prune snapshots of volumes beyond their retention settings, returning how many were
+    /// deleted. Snapshots that are the parent of another snapshot or within the write-once
+    /// period of their volume are never deleted.
+    pub async fn prune(
+        &self,
+        conn: &mut AnyConnection,
+        ipfs: Option<&Ipfs>,
+        blobs: Option<&Blobs>,
+    ) -> Result<usize, PurgeError> {
+        let mut pruned = 0;
+        for volume in &Volume::retained(conn).await? {
+            let mut snapshots = Snapshot::list(conn, &volume.volume(), None, false).await?;
+            let now = now();
+            let keep = retained(&snapshots, volume.retain_count(), volume.retain_age(), now);
+            let mut count = 0;
+
+            // delete children before their parents
+            snapshots.sort_by_key(|snapshot| {
+                std::cmp::Reverse((snapshot.manifest().generation, snapshot.snapshot()))
+            });
+            for snapshot in &snapshots {
+                if keep.contains(&snapshot.snapshot())
+                    || snapshot.immutable(volume.worm_period(), now)
+                    || snapshot.snapshot().has_children(conn).await?
+                {
+                    continue;
+                }
+                remove(conn, snapshot, ipfs, blobs).await?;
+                count += 1;
+            }
+            if count > 0 {
+                info!("Pruned {} snapshots of volume {}", count, volume.pubkey());
+            }
+            pruned += count;
+        }
+        Ok(pruned)
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Purge {
+    fn info(&self) -> Info {
+        Info {
+            name: "Purge deleted volumes",
+            kind: Kind::Ignite | Kind::Liftoff,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
+        Ok(rocket.manage(self.clone()))
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let pool = match rocket.state::<AnyPool>() {
+            Some(pool) => pool.clone(),
+            None => return,
+        };
+        let ipfs = rocket.state::<Option<Ipfs>>().cloned().flatten();
+        let blobs = rocket.state::<Option<Blobs>>().cloned().flatten();
+        let mut shutdown = rocket.shutdown();
+        let purge = self.clone();
+        rocket::tokio::spawn(async move {
+            let mut interval = time::interval(purge.interval);
+            loop {
+                select! {
+                    _ = interval.tick() => {},
+                    _ = &mut shutdown => break,
+                }
+                let mut conn = match pool.acquire().await {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        error!("Error purging deleted volumes: {}", e);
+                        continue;
+                    }
+                };
+                if let Err(e) = purge.run(&mut conn, ipfs.as_ref(), blobs.as_ref()).await {
+                    error!("Error purging deleted volumes: {}", e);
+                }
+                if let Err(e) = purge.prune(&mut conn, ipfs.as_ref(), blobs.as_ref()).await {
+                    error!("Error pruning snapshots: {}", e);
+                }
+                let released = purge
+                    .release_queued(&mut conn, ipfs.as_ref(), blobs.as_ref())
+                    .await;
+                if let Err(e) = released {
+                    error!("Error releasing payloads: {}", e);
+                }
+                if let Err(e) = purge.archive(&mut conn).await {
+                    error!("Error archiving manifests: {}", e);
+                }
+            }
+        });
+    }
+}
+
+/// Current time, in seconds since the epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[tokio::test]
+async fn test_purge() {
+    use fractal_storage_client::Privkey;
+    use uuid::Uuid;
+
+    let pool = AnyPool::connect("sqlite://:memory:").await.unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+    let mut conn = pool.acquire().await.unwrap();
+    let pubkey = Privkey::generate().pubkey();
+    Volume::create(&mut conn, &pubkey, &Uuid::new_v4())
+        .await
+        .unwrap();
+    let volume = Volume::lookup(&mut conn, &pubkey).await.unwrap().unwrap();
+    volume.delete(&mut conn, now()).await.unwrap();
+
+    // still within grace period
+    let purge = Purge::new(Duration::from_secs(3600), Duration::from_secs(60));
+    assert_eq!(purge.run(&mut conn, None, None).await.unwrap(), 0);
+    let volume = Volume::lookup_deleted(&mut conn, &pubkey)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(!purge.expired(&volume));
+
+    // grace period elapsed
+    let purge = Purge::new(Duration::from_secs(0), Duration::from_secs(60));
+    assert!(purge.expired(&volume));
+    assert_eq!(purge.run(&mut conn, None, None).await.unwrap(), 1);
+    assert!(Volume::lookup_deleted(&mut conn, &pubkey)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_purge_shared_data() {
+    use crate::snapshot::MINIMUM_SNAPSHOT_SIZE;
+    use fractal_storage_client::{Manifest, Privkey};
+    use uuid::Uuid;
+
+    let pool = AnyPool::connect("sqlite://:memory:").await.unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+    let mut conn = pool.acquire().await.unwrap();
+    let data: Url = "ipfs://asd99a0s8098da0sd98".parse().unwrap();
+
+    // two volumes of different accounts with snapshots of the same payload
+    let mut volumes = vec![];
+    for _ in 0..2 {
+        let privkey = Privkey::generate();
+        let volume = Volume::create(&mut conn, &privkey.pubkey(), &Uuid::new_v4())
+            .await
+            .unwrap();
+        let manifest = Manifest {
+            creation: now(),
+            data: data.clone(),
+            generation: 0,
+            parent: None,
+            size: MINIMUM_SNAPSHOT_SIZE,
+            size_total: MINIMUM_SNAPSHOT_SIZE,
+            machine: Default::default(),
+            path: std::path::PathBuf::from("abc"),
+        }
+        .sign(&privkey);
+        Snapshot::create(
+            &mut conn,
+            &volume,
+            &manifest.raw,
+            &manifest.signature,
+            &manifest.hash(),
+            None,
+            0,
+            &manifest.manifest.data,
+        )
+        .await
+        .unwrap();
+        volumes.push(volume);
+    }
+    assert_eq!(
+        Snapshot::data_references(&mut conn, &data).await.unwrap(),
+        2
+    );
+
+    // purging one of them keeps the payload referenced by the other
+    let purged = volumes[0].fetch(&mut conn).await.unwrap();
+    purged.delete(&mut conn, 0).await.unwrap();
+    let purge = Purge::new(Duration::from_secs(0), Duration::from_secs(60));
+    assert_eq!(purge.run(&mut conn, None, None).await.unwrap(), 1);
+    assert_eq!(
+        Snapshot::data_references(&mut conn, &data).await.unwrap(),
+        1
+    );
+    let snapshots = Snapshot::list(&mut conn, &volumes[1], None, false)
+        .await
+        .unwrap();
+    assert_eq!(snapshots.len(), 1);
+}
+
+#[tokio::test]
+async fn test_prune() {
+    use crate::snapshot::MINIMUM_SNAPSHOT_SIZE;
+    use fractal_storage_client::{Manifest, Privkey};
+    use uuid::Uuid;
+
+    let pool = AnyPool::connect("sqlite://:memory:").await.unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+    let mut conn = pool.acquire().await.unwrap();
+    let privkey = Privkey::generate();
+    let volume = Volume::create(&mut conn, &privkey.pubkey(), &Uuid::new_v4())
+        .await
+        .unwrap();
+
+    // two chains of two snapshots each
+    let mut parent = None;
+    for generation in 0..4 {
+        let manifest = Manifest {
+            creation: now(),
+            data: "ipfs://asd99a0s8098da0sd98".parse().unwrap(),
+            generation,
+            parent: None,
+            size: MINIMUM_SNAPSHOT_SIZE,
+            size_total: MINIMUM_SNAPSHOT_SIZE,
+            machine: Default::default(),
+            path: std::path::PathBuf::from("abc"),
+        }
+        .sign(&privkey);
+        let snapshot = Snapshot::create(
+            &mut conn,
+            &volume,
+            &manifest.raw,
+            &manifest.signature,
+            &manifest.hash(),
+            parent.filter(|_| generation % 2 == 1).as_ref(),
+            generation,
+            &manifest.manifest.data,
+        )
+        .await
+        .unwrap();
+        parent = Some(snapshot);
+    }
+
+    // nothing is pruned without retention settings
+    let purge = Purge::new(Duration::from_secs(0), Duration::from_secs(60));
+    assert_eq!(purge.prune(&mut conn, None, None).await.unwrap(), 0);
+    volume
+        .retention_set(&mut conn, Some(1), None)
+        .await
+        .unwrap();
+    assert_eq!(purge.prune(&mut conn, None, None).await.unwrap(), 2);
+    let snapshots = Snapshot::list(&mut conn, &volume, None, false)
+        .await
+        .unwrap();
+    let generations: Vec<u64> = snapshots
+        .iter()
+        .map(|snapshot| snapshot.manifest().generation)
+        .collect();
+    assert_eq!(generations, vec![2, 3]);
+    assert_eq!(purge.prune(&mut conn, None, None).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_prune_worm() {
+    use crate::snapshot::MINIMUM_SNAPSHOT_SIZE;
+    use fractal_storage_client::{Manifest, Privkey};
+    use uuid::Uuid;
+
+    let pool = AnyPool::connect("sqlite://:memory:").await.unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+    let mut conn = pool.acquire().await.unwrap();
+    let privkey = Privkey::generate();
+    let volume = Volume::create(&mut conn, &privkey.pubkey(), &Uuid::new_v4())
+        .await
+        .unwrap();
+    for generation in 0..2 {
+        let manifest = Manifest {
+            creation: now(),
+            data: "ipfs://asd99a0s8098da0sd98".parse().unwrap(),
+            generation,
+            parent: None,
+            size: MINIMUM_SNAPSHOT_SIZE,
+            size_total: MINIMUM_SNAPSHOT_SIZE,
+            machine: Default::default(),
+            path: std::path::PathBuf::from("abc"),
+        }
+        .sign(&privkey);
+        Snapshot::create(
+            &mut conn,
+            &volume,
+            &manifest.raw,
+            &manifest.signature,
+            &manifest.hash(),
+            None,
+            generation,
+            &manifest.manifest.data,
+        )
+        .await
+        .unwrap();
+    }
+    volume
+        .retention_set(&mut conn, Some(1), None)
+        .await
+        .unwrap();
+    assert!(volume.worm_set(&mut conn, Some(3600)).await.unwrap());
+
+    // snapshots within the write-once period are kept, and it cannot be shortened
+    let purge = Purge::new(Duration::from_secs(0), Duration::from_secs(60));
+    assert_eq!(purge.prune(&mut conn, None, None).await.unwrap(), 0);
+    assert!(!volume.worm_set(&mut conn, Some(60)).await.unwrap());
+    assert!(!volume.worm_set(&mut conn, None).await.unwrap());
+    assert!(volume.worm_set(&mut conn, Some(7200)).await.unwrap());
+    let data = volume.fetch(&mut conn).await.unwrap();
+    assert_eq!(data.worm_period(), Some(7200));
+    assert!(data.immutable(&mut conn, now()).await.unwrap());
+    assert!(!data.immutable(&mut conn, now() + 7201).await.unwrap());
+
+    // deleted volumes are not purged while immutable
+    data.delete(&mut conn, 0).await.unwrap();
+    assert_eq!(purge.run(&mut conn, None, None).await.unwrap(), 0);
+}
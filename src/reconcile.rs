@@ -0,0 +1,338 @@
+use crate::blobs::{BlobError, BlobReader, Blobs};
+use crate::events::Events;
+use crate::purge::now;
+use crate::redact::RedactedHash;
+use crate::snapshot::Snapshot;
+use fractal_storage_client::{AccountEvent, Hash, Pubkey};
+use log::{error, info, warn};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::futures::StreamExt;
+use rocket::tokio::{select, time};
+use rocket::{Build, Orbit, Rocket};
+use sha2::{Digest, Sha256};
+use sqlx::{query, AnyConnection, AnyPool, Row};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::io::{AsyncRead, ReadBuf};
+use uuid::Uuid;
+
+#[derive(Error, Debug)]
+pub enum ReconcileError {
+    #[error("Error talking to database: {0:}")]
+    Database(#[from] sqlx::Error),
+    #[error("Error parsing key: {0:}")]
+    ParseKey(#[from] fractal_storage_client::keys::ParseError),
+}
+
+/// Reader that computes the SHA-256 digest of the data read through it, used to record the
+/// digest of payloads as they are uploaded.
+pub struct DigestReader<'a> {
+    reader: BlobReader<'a>,
+    digest: Arc<Mutex<Sha256>>,
+}
+
+impl<'a> DigestReader<'a> {
+    pub fn new(reader: BlobReader<'a>) -> Self {
+        DigestReader {
+            reader,
+            digest: Default::default(),
+        }
+    }
+
+    /// Handle to the digest, which can be finalized once the reader has been consumed.
+    pub fn digest(&self) -> PayloadDigest {
+        PayloadDigest(self.digest.clone())
+    }
+}
+
+impl AsyncRead for DigestReader<'_> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let result = self.reader.as_mut().poll_read(cx, buf);
+        self.digest.lock().unwrap().update(&buf.filled()[filled..]);
+        result
+    }
+}
+
+/// Digest of the data read through a [`DigestReader`].
+pub struct PayloadDigest(Arc<Mutex<Sha256>>);
+
+impl PayloadDigest {
+    pub fn finalize(self) -> Vec<u8> {
+        self.0.lock().unwrap().clone().finalize().to_vec()
+    }
+}
+
+/// Record the size and digest of a payload stored in the blob backend.
+pub async fn record(
+    conn: &mut AnyConnection,
+    snapshot: &Snapshot,
+    size: u64,
+    digest: &[u8],
+) -> Result<(), sqlx::Error> {
+    query(
+        "INSERT INTO storage_payload(snapshot_id, payload_size, payload_sha256)
+        VALUES (?, ?, ?)",
+    )
+    .bind(snapshot.id())
+    .bind(size as i64)
+    .bind(digest)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Whether a payload was stored in the blob backend for the snapshot.
+pub async fn recorded(conn: &mut AnyConnection, snapshot: &Snapshot) -> Result<bool, sqlx::Error> {
+    let row = query("SELECT 1 FROM storage_payload WHERE snapshot_id = ?")
+        .bind(snapshot.id())
+        .fetch_optional(conn)
+        .await?;
+    Ok(row.is_some())
+}
+
+/// Read a payload back from the blob backend, returning its size and digest or `None` if it
+/// is missing.
+async fn payload_digest(blobs: &Blobs, key: &str) -> Result<Option<(u64, Vec<u8>)>, BlobError> {
+    let size = match blobs.backend().size(key).await? {
+        Some(size) => size,
+        None => return Ok(None),
+    };
+    let mut digest = Sha256::new();
+    if size > 0 {
+        // read errors and truncated payloads end the stream with an error, which should not
+        // be mistaken for corruption.
+        let mut stream = blobs.backend().get(key, 0, size - 1).await?;
+        while let Some(chunk) = stream.next().await {
+            digest.update(&chunk?);
+        }
+    }
+    Ok(Some((size, digest.finalize().to_vec())))
+}
+
+/// Payloads stored in the blob backend may silently rot. A background task periodically
+/// reads back the payloads that were verified least recently and compares them against the
+/// digest recorded on upload. Mismatches are recorded as corruption reports, logged, and
+/// published to the account owning the volume.
+#[derive(Clone, Debug)]
+pub struct Reconcile {
+    /// How often to verify payloads.
+    pub interval: Duration,
+    /// How many payloads to verify each time.
+    pub sample: usize,
+}
+
+impl Reconcile {
+    pub fn new(interval: Duration, sample: usize) -> Self {
+        Reconcile { interval, sample }
+    }
+
+    /// Verify a sample of payloads, returning how many were found to be corrupted.
+    /// Payloads that were already reported are not verified again.
+    pub async fn run(
+        &self,
+        conn: &mut AnyConnection,
+        blobs: &Blobs,
+        events: Option<&Events>,
+    ) -> Result<usize, ReconcileError> {
+        let rows = query(
+            "SELECT storage_payload.snapshot_id, payload_size, payload_sha256, snapshot_hash,
+                volume_pubkey, account_id
+            FROM storage_payload
+            JOIN storage_snapshot ON storage_snapshot.snapshot_id = storage_payload.snapshot_id
+            JOIN storage_volume ON storage_volume.volume_id = storage_snapshot.volume_id
+            WHERE NOT EXISTS (
+                SELECT 1 FROM storage_corruption_report
+                WHERE storage_corruption_report.snapshot_id = storage_payload.snapshot_id)
+            ORDER BY payload_verified IS NOT NULL, payload_verified, storage_payload.snapshot_id
+            LIMIT ?",
+        )
+        .bind(self.sample as i64)
+        .fetch_all(&mut *conn)
+        .await?;
+
+        let mut corrupted = 0;
+        for row in &rows {
+            let snapshot = Snapshot::from(row.try_get::<i64, _>("snapshot_id")?);
+            let expected_size: i64 = row.try_get("payload_size")?;
+            let expected_digest: Vec<u8> = row.try_get("payload_sha256")?;
+            let hash: Vec<u8> = row.try_get("snapshot_hash")?;
+            let hash = Hash::try_from(hash.as_slice())?;
+            query("UPDATE storage_payload SET payload_verified = ? WHERE snapshot_id = ?")
+                .bind(now() as i64)
+                .bind(snapshot.id())
+                .execute(&mut *conn)
+                .await?;
+            let actual = match payload_digest(blobs, &hash.to_hex()).await {
+                Ok(actual) => actual,
+                Err(e) => {
+                    warn!("Error reading payload {}: {}", RedactedHash::new(hash), e);
+                    continue;
+                }
+            };
+            if actual.as_ref() == Some(&(expected_size as u64, expected_digest.clone())) {
+                continue;
+            }
+
+            corrupted += 1;
+            let volume: Vec<u8> = row.try_get("volume_pubkey")?;
+            let volume = Pubkey::try_from(volume.as_slice())?;
+            error!(
+                "Payload of snapshot {} in volume {} does not match its digest",
+                RedactedHash::new(hash),
+                volume
+            );
+            query(
+                "INSERT INTO storage_corruption_report(snapshot_id, report_time, expected_size,
+                    expected_sha256, actual_size, actual_sha256)
+                VALUES (?, ?, ?, ?, ?, ?)",
+            )
+            .bind(snapshot.id())
+            .bind(now() as i64)
+            .bind(expected_size)
+            .bind(expected_digest)
+            .bind(actual.as_ref().map(|(size, _)| *size as i64))
+            .bind(actual.map(|(_, digest)| digest))
+            .execute(&mut *conn)
+            .await?;
+            if let Some(events) = events {
+                let account: String = row.try_get("account_id")?;
+                if let Ok(account) = Uuid::parse_str(&account) {
+                    events.publish(
+                        &account,
+                        AccountEvent::PayloadCorrupted {
+                            volume,
+                            snapshot: hash,
+                        },
+                    );
+                }
+            }
+        }
+        info!("Verified {} payloads, {} corrupted", rows.len(), corrupted);
+        Ok(corrupted)
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Reconcile {
+    fn info(&self) -> Info {
+        Info {
+            name: "Verify stored payloads",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let pool = match rocket.state::<AnyPool>() {
+            Some(pool) => pool.clone(),
+            None => return,
+        };
+        // only payloads stored in the blob backend have a recorded digest.
+        let blobs = match rocket.state::<Option<Blobs>>().cloned().flatten() {
+            Some(blobs) => blobs,
+            None => return,
+        };
+        if self.sample == 0 {
+            return;
+        }
+        let events = rocket.state::<Events>().cloned();
+        let mut shutdown = rocket.shutdown();
+        let reconcile = self.clone();
+        rocket::tokio::spawn(async move {
+            let mut interval = time::interval(reconcile.interval);
+            loop {
+                select! {
+                    _ = interval.tick() => {},
+                    _ = &mut shutdown => break,
+                }
+                let result = match pool.acquire().await {
+                    Ok(mut conn) => reconcile.run(&mut conn, &blobs, events.as_ref()).await,
+                    Err(e) => Err(e.into()),
+                };
+                if let Err(e) = result {
+                    error!("Error verifying payloads: {}", e);
+                }
+            }
+        });
+    }
+}
+
+#[tokio::test]
+async fn test_reconcile() {
+    use crate::snapshot::MINIMUM_SNAPSHOT_SIZE;
+    use crate::volume::Volume;
+    use fractal_storage_client::{Manifest, Privkey};
+
+    let pool = AnyPool::connect("sqlite://:memory:").await.unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+    let mut conn = pool.acquire().await.unwrap();
+    let privkey = Privkey::generate();
+    Volume::create(&mut conn, &privkey.pubkey(), &Uuid::new_v4())
+        .await
+        .unwrap();
+    let volume = Volume::lookup(&mut conn, &privkey.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let manifest = Manifest {
+        creation: 0,
+        data: "ipfs://asd99a0s8098da0sd98".parse().unwrap(),
+        generation: 0,
+        parent: None,
+        size: MINIMUM_SNAPSHOT_SIZE,
+        size_total: MINIMUM_SNAPSHOT_SIZE,
+        machine: Default::default(),
+        path: std::path::PathBuf::from("abc"),
+    }
+    .sign(&privkey);
+    let snapshot = Snapshot::create(
+        &mut conn,
+        &volume.volume(),
+        &manifest.raw,
+        &manifest.signature,
+        &manifest.hash(),
+        None,
+        0,
+        &manifest.manifest.data,
+    )
+    .await
+    .unwrap();
+
+    // store payload, recording its digest
+    let path = std::env::temp_dir().join(Uuid::new_v4().to_string());
+    let blobs = Blobs::from_url(&url::Url::from_directory_path(&path).unwrap()).unwrap();
+    let data: Vec<u8> = (0..4096).map(|i| i as u8).collect();
+    let reader = DigestReader::new(Box::pin(std::io::Cursor::new(data)));
+    let digest = reader.digest();
+    let key = manifest.hash().to_hex();
+    let size = blobs
+        .backend()
+        .put(&key, Box::pin(reader), u64::MAX)
+        .await
+        .unwrap();
+    record(&mut conn, &snapshot, size, &digest.finalize())
+        .await
+        .unwrap();
+
+    let reconcile = Reconcile::new(Duration::from_secs(60), 16);
+    assert_eq!(reconcile.run(&mut conn, &blobs, None).await.unwrap(), 0);
+
+    // corrupted payloads are reported once
+    tokio::fs::write(path.join(&key), vec![0; 4096])
+        .await
+        .unwrap();
+    assert_eq!(reconcile.run(&mut conn, &blobs, None).await.unwrap(), 1);
+    assert_eq!(reconcile.run(&mut conn, &blobs, None).await.unwrap(), 0);
+    let reports = query("SELECT * FROM storage_corruption_report")
+        .fetch_all(&mut conn)
+        .await
+        .unwrap();
+    assert_eq!(reports.len(), 1);
+}
@@ -0,0 +1,224 @@
+use fractal_storage_client::Manifest;
+use std::fmt::{self, Display, Formatter};
+use std::path::{Component, Path};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// How many characters of hashes and identifiers are kept when redacting.
+const PREFIX_LENGTH: usize = 8;
+
+/// Placeholder for values that are removed entirely.
+const REDACTED: &str = "<redacted>";
+
+/// Redaction level of the process, set once on startup.
+static REDACTION: AtomicU8 = AtomicU8::new(Redaction::Partial as u8);
+
+/// How much of manifests is included in log and error output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Redaction {
+    /// Manifests are logged in full.
+    Off,
+    /// Hashes and machines are shortened to a prefix, paths are masked except for their
+    /// last component and payload references are reduced to their scheme.
+    Partial,
+    /// Hashes are shortened to a prefix, paths, machines and payload references are removed.
+    Strict,
+}
+
+impl Redaction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Redaction::Off => "off",
+            Redaction::Partial => "partial",
+            Redaction::Strict => "strict",
+        }
+    }
+
+    /// Redaction level used for log and error output.
+    pub fn current() -> Self {
+        match REDACTION.load(Ordering::Relaxed) {
+            level if level == Redaction::Off as u8 => Redaction::Off,
+            level if level == Redaction::Strict as u8 => Redaction::Strict,
+            _ => Redaction::Partial,
+        }
+    }
+
+    /// Use this redaction level for all log and error output of the process.
+    pub fn set(self) {
+        REDACTION.store(self as u8, Ordering::Relaxed);
+    }
+
+    fn prefix(&self, value: &str, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Redaction::Off => write!(f, "{value}"),
+            _ => match value.get(..PREFIX_LENGTH) {
+                Some(prefix) if prefix.len() < value.len() => write!(f, "{prefix}…"),
+                _ => write!(f, "{value}"),
+            },
+        }
+    }
+
+    fn path(&self, path: &Path, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Redaction::Off => write!(f, "{}", path.display()),
+            Redaction::Partial => {
+                let mut components: Vec<String> = path
+                    .components()
+                    .map(|component| match component {
+                        Component::Normal(_) => "*".into(),
+                        other => other.as_os_str().to_string_lossy().into_owned(),
+                    })
+                    .collect();
+                if let (Some(last), Some(name)) = (components.last_mut(), path.file_name()) {
+                    *last = name.to_string_lossy().into_owned();
+                }
+                write!(f, "{}", components.join("/").replacen("//", "/", 1))
+            }
+            Redaction::Strict => write!(f, "{REDACTED}"),
+        }
+    }
+}
+
+impl Default for Redaction {
+    fn default() -> Self {
+        Redaction::Partial
+    }
+}
+
+impl FromStr for Redaction {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "off" => Ok(Redaction::Off),
+            "partial" => Ok(Redaction::Partial),
+            "strict" => Ok(Redaction::Strict),
+            other => Err(format!("Unknown redaction level {other:?}")),
+        }
+    }
+}
+
+/// Display wrapper for hashes and payload keys, which shortens them to a prefix unless
+/// redaction is turned off.
+pub struct RedactedHash<T> {
+    hash: T,
+    redaction: Redaction,
+}
+
+impl<T: Display> RedactedHash<T> {
+    pub fn new(hash: T) -> Self {
+        RedactedHash::with_redaction(hash, Redaction::current())
+    }
+
+    pub fn with_redaction(hash: T, redaction: Redaction) -> Self {
+        RedactedHash { hash, redaction }
+    }
+}
+
+impl<T: Display> Display for RedactedHash<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.redaction.prefix(&self.hash.to_string(), f)
+    }
+}
+
+/// Display wrapper for manifests, which redacts fields that could identify hosts or their
+/// data according to the redaction level.
+pub struct RedactedManifest<'a> {
+    manifest: &'a Manifest,
+    redaction: Redaction,
+}
+
+impl<'a> RedactedManifest<'a> {
+    pub fn new(manifest: &'a Manifest) -> Self {
+        RedactedManifest::with_redaction(manifest, Redaction::current())
+    }
+
+    pub fn with_redaction(manifest: &'a Manifest, redaction: Redaction) -> Self {
+        RedactedManifest {
+            manifest,
+            redaction,
+        }
+    }
+}
+
+impl<'a> Display for RedactedManifest<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let manifest = self.manifest;
+        write!(
+            f,
+            "generation {} created {} size {} parent ",
+            manifest.generation, manifest.creation, manifest.size
+        )?;
+        match &manifest.parent {
+            Some(parent) => self.redaction.prefix(&parent.hash.to_string(), f)?,
+            None => write!(f, "none")?,
+        }
+        write!(f, " machine ")?;
+        match self.redaction {
+            Redaction::Strict => write!(f, "{REDACTED}")?,
+            _ => self.redaction.prefix(&manifest.machine.to_string(), f)?,
+        }
+        write!(f, " path ")?;
+        self.redaction.path(&manifest.path, f)?;
+        write!(f, " data ")?;
+        match self.redaction {
+            Redaction::Off => write!(f, "{}", manifest.data),
+            Redaction::Partial => write!(f, "{}://…", manifest.data.scheme()),
+            Redaction::Strict => write!(f, "{REDACTED}"),
+        }
+    }
+}
+
+#[test]
+fn test_redacted_manifest() {
+    use fractal_storage_client::{Hash, Parent};
+    use std::path::PathBuf;
+    use uuid::Uuid;
+
+    let parent = Hash::generate(&[1, 2, 3]);
+    let machine = Uuid::new_v4();
+    let manifest = Manifest {
+        creation: 1000,
+        data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+            .parse()
+            .unwrap(),
+        generation: 2,
+        parent: Some(Parent::new(parent)),
+        size: 64,
+        size_total: 128,
+        machine,
+        path: PathBuf::from("/var/lib/docker/volumes/secret"),
+    };
+
+    let off = RedactedManifest::with_redaction(&manifest, Redaction::Off).to_string();
+    assert!(off.contains(&parent.to_string()));
+    assert!(off.contains(&machine.to_string()));
+    assert!(off.contains("/var/lib/docker/volumes/secret"));
+    assert!(off.contains("QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"));
+
+    let partial = RedactedManifest::with_redaction(&manifest, Redaction::Partial).to_string();
+    assert_eq!(
+        partial,
+        format!(
+            "generation 2 created 1000 size 64 parent {}… machine {}… path /*/*/*/*/secret data ipfs://…",
+            &parent.to_string()[..8],
+            &machine.to_string()[..8]
+        )
+    );
+
+    let strict = RedactedManifest::with_redaction(&manifest, Redaction::Strict).to_string();
+    assert_eq!(
+        strict,
+        format!(
+            "generation 2 created 1000 size 64 parent {}… machine <redacted> path <redacted> data <redacted>",
+            &parent.to_string()[..8]
+        )
+    );
+
+    assert_eq!(
+        RedactedHash::with_redaction(parent, Redaction::Strict).to_string(),
+        format!("{}…", &parent.to_string()[..8])
+    );
+    assert_eq!("strict".parse(), Ok(Redaction::Strict));
+    assert!("none".parse::<Redaction>().is_err());
+}
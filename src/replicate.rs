@@ -0,0 +1,286 @@
+use crate::purge::now;
+use crate::redact::RedactedHash;
+use crate::snapshot::{Snapshot, SnapshotData, SnapshotError, ARCHIVE_DATA};
+use crate::volume::VolumeData;
+use fractal_storage_client::{Pubkey, ReplicationStatus};
+use log::{error, info, warn};
+use reqwest::{Client, Response, StatusCode};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::tokio::{select, time};
+use rocket::{Build, Orbit, Rocket};
+use sqlx::{query, AnyConnection, AnyPool, Row};
+use std::collections::BTreeSet;
+use std::time::Duration;
+use thiserror::Error;
+use url::Url;
+
+/// How many snapshots to push each time.
+const REPLICATION_BATCH: i64 = 64;
+
+#[derive(Error, Debug)]
+pub enum ReplicationError {
+    #[error("Error talking to database: {0:}")]
+    Database(#[from] sqlx::Error),
+    #[error("Error in snapshots: {0:}")]
+    Snapshot(#[from] SnapshotError),
+    #[error("Error parsing key: {0:}")]
+    ParseKey(#[from] fractal_storage_client::keys::ParseError),
+    #[error("Error parsing URL: {0:}")]
+    UrlParse(#[from] url::ParseError),
+    #[error("Error talking to peer: {0:}")]
+    Reqwest(#[from] reqwest::Error),
+    #[error("Peer responded with status {0:}")]
+    Unsuccessful(StatusCode),
+}
+
+/// Replication of newly accepted manifests to peer storage services, for geo-redundant
+/// metadata. Snapshots are queued for every peer when they are uploaded, and a background
+/// task pushes them in upload order, so that parents reach peers before their children.
+/// Volumes are created on peers as needed, owned by the account of the replication token.
+#[derive(Clone, Debug)]
+pub struct Replication {
+    peers: Vec<Url>,
+    token: Option<String>,
+    interval: Duration,
+    client: Client,
+}
+
+impl Replication {
+    pub fn new(peers: Vec<Url>, token: Option<String>, interval: Duration) -> Self {
+        Replication {
+            peers,
+            token,
+            interval,
+            client: Client::new(),
+        }
+    }
+
+    /// Queue a newly created snapshot for replication to every peer.
+    pub async fn enqueue(
+        &self,
+        conn: &mut AnyConnection,
+        snapshot: &Snapshot,
+    ) -> Result<(), sqlx::Error> {
+        for peer in &self.peers {
+            query("INSERT INTO storage_replication(snapshot_id, replication_peer) VALUES (?, ?)")
+                .bind(snapshot.id())
+                .bind(peer.as_str())
+                .execute(&mut *conn)
+                .await?;
+        }
+        Ok(())
+    }
+
+    fn request(&self, builder: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.token {
+            Some(token) => builder.bearer_auth(token),
+            None => builder,
+        }
+    }
+
+    async fn upload(
+        &self,
+        url: Url,
+        snapshot: &SnapshotData,
+    ) -> Result<Response, ReplicationError> {
+        let request = self
+            .client
+            .post(url)
+            .header("Accept", "application/json")
+            .body(snapshot.manifest_signed().data());
+        Ok(self.request(request).send().await?)
+    }
+
+    /// Push a snapshot to a peer, creating the volume there if it does not exist yet.
+    async fn push(
+        &self,
+        peer: &Url,
+        volume: &Pubkey,
+        snapshot: &SnapshotData,
+    ) -> Result<(), ReplicationError> {
+        let url = peer.join(&format!("/api/v1/volume/{}/snapshot", volume.to_hex()))?;
+        let mut response = self.upload(url.clone(), snapshot).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            let create = peer.join(&format!("/api/v1/volume/{}", volume.to_hex()))?;
+            let created = self.request(self.client.post(create)).send().await?;
+            if !created.status().is_success() {
+                return Err(ReplicationError::Unsuccessful(created.status()));
+            }
+            response = self.upload(url, snapshot).await?;
+        }
+        if !response.status().is_success() {
+            return Err(ReplicationError::Unsuccessful(response.status()));
+        }
+        Ok(())
+    }
+
+    /// Push pending snapshots to the peers, returning how many were replicated. When pushing
+    /// a snapshot fails, later snapshots of the same volume are held back for that peer.
+    pub async fn run(&self, conn: &mut AnyConnection) -> Result<usize, ReplicationError> {
+        if self.peers.is_empty() {
+            return Ok(0);
+        }
+        let peers = vec!["?"; self.peers.len()].join(", ");
+        let statement = format!(
+            "SELECT storage_snapshot.*, {ARCHIVE_DATA}, replication_peer, volume_pubkey
+            FROM storage_replication
+            JOIN storage_snapshot ON storage_snapshot.snapshot_id = storage_replication.snapshot_id
+            JOIN storage_volume ON storage_volume.volume_id = storage_snapshot.volume_id
+            WHERE replication_time IS NULL AND replication_peer IN ({peers})
+            ORDER BY storage_replication.snapshot_id
+            LIMIT ?"
+        );
+        let mut pending = query(&statement);
+        for peer in &self.peers {
+            pending = pending.bind(peer.as_str());
+        }
+        let rows = pending
+            .bind(REPLICATION_BATCH)
+            .fetch_all(&mut *conn)
+            .await?;
+
+        let mut replicated = 0;
+        let mut blocked = BTreeSet::new();
+        for row in &rows {
+            let snapshot = SnapshotData::from_row(row)?;
+            let peer: String = row.try_get("replication_peer")?;
+            let volume: Vec<u8> = row.try_get("volume_pubkey")?;
+            let volume = Pubkey::try_from(volume.as_slice())?;
+            if blocked.contains(&(peer.clone(), volume.to_hex())) {
+                continue;
+            }
+            let result = match Url::parse(&peer) {
+                Ok(url) => self.push(&url, &volume, &snapshot).await,
+                Err(e) => Err(e.into()),
+            };
+            let error = match result {
+                Ok(()) => {
+                    replicated += 1;
+                    None
+                }
+                Err(e) => {
+                    warn!(
+                        "Error replicating snapshot {} of volume {} to {}: {}",
+                        RedactedHash::new(snapshot.hash()),
+                        volume,
+                        peer,
+                        e
+                    );
+                    blocked.insert((peer.clone(), volume.to_hex()));
+                    Some(e.to_string())
+                }
+            };
+            query(
+                "UPDATE storage_replication
+                SET replication_time = ?, replication_error = ?,
+                    replication_attempts = replication_attempts + 1
+                WHERE snapshot_id = ? AND replication_peer = ?",
+            )
+            .bind(error.is_none().then(|| now() as i64))
+            .bind(error)
+            .bind(snapshot.snapshot().id())
+            .bind(peer.as_str())
+            .execute(&mut *conn)
+            .await?;
+        }
+        if !rows.is_empty() {
+            info!("Replicated {} of {} snapshots", replicated, rows.len());
+        }
+        Ok(replicated)
+    }
+}
+
+/// Replication status of a volume, for every peer its snapshots were queued for.
+pub async fn status(
+    conn: &mut AnyConnection,
+    volume: &VolumeData,
+) -> Result<Vec<ReplicationStatus>, ReplicationError> {
+    let rows = query(
+        "SELECT replication_peer, COUNT(*) AS total, COUNT(replication_time) AS replicated,
+            MAX(replication_time) AS last_replicated
+        FROM storage_replication
+        JOIN storage_snapshot ON storage_snapshot.snapshot_id = storage_replication.snapshot_id
+        WHERE storage_snapshot.volume_id = ?
+        GROUP BY replication_peer
+        ORDER BY replication_peer",
+    )
+    .bind(volume.id())
+    .fetch_all(&mut *conn)
+    .await?;
+    let mut statuses = vec![];
+    for row in &rows {
+        let peer: String = row.try_get("replication_peer")?;
+        let total: i64 = row.try_get("total")?;
+        let replicated: i64 = row.try_get("replicated")?;
+        let last_replicated: Option<i64> = row.try_get("last_replicated")?;
+
+        // the oldest pending snapshot is what holds back replication
+        let error = query(
+            "SELECT replication_error FROM storage_replication
+            JOIN storage_snapshot
+                ON storage_snapshot.snapshot_id = storage_replication.snapshot_id
+            WHERE storage_snapshot.volume_id = ? AND replication_peer = ?
+                AND replication_time IS NULL
+            ORDER BY storage_replication.snapshot_id
+            LIMIT 1",
+        )
+        .bind(volume.id())
+        .bind(peer.as_str())
+        .fetch_optional(&mut *conn)
+        .await?;
+        let error = match error {
+            Some(row) => row.try_get("replication_error")?,
+            None => None,
+        };
+        statuses.push(ReplicationStatus {
+            peer: Url::parse(&peer)?,
+            replicated: replicated as u64,
+            pending: (total - replicated) as u64,
+            last_replicated: last_replicated.map(|time| time as u64),
+            error,
+        });
+    }
+    Ok(statuses)
+}
+
+#[rocket::async_trait]
+impl Fairing for Replication {
+    fn info(&self) -> Info {
+        Info {
+            name: "Replicate to peers",
+            kind: Kind::Ignite | Kind::Liftoff,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
+        Ok(rocket.manage(self.clone()))
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let pool = match rocket.state::<AnyPool>() {
+            Some(pool) => pool.clone(),
+            None => return,
+        };
+        if self.peers.is_empty() {
+            return;
+        }
+        let mut shutdown = rocket.shutdown();
+        let replication = self.clone();
+        rocket::tokio::spawn(async move {
+            let mut interval = time::interval(replication.interval);
+            loop {
+                select! {
+                    _ = interval.tick() => {},
+                    _ = &mut shutdown => break,
+                }
+                let result = match pool.acquire().await {
+                    Ok(mut conn) => replication.run(&mut conn).await,
+                    Err(e) => Err(e.into()),
+                };
+                if let Err(e) = result {
+                    error!("Error replicating snapshots: {}", e);
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,355 @@
+use crate::auth::request_volume;
+use crate::purge::now;
+use crate::volume::VolumeData;
+use fractal_storage_client::{
+    ErrorCode, RequestSignature, RequestSignatureError, VolumeChallenge, DIGEST_HEADER,
+    PROOF_HEADER, SIGNATURE_HEADER,
+};
+use rand::{thread_rng, RngCore};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use sqlx::{query, AnyConnection, AnyPool};
+use std::str::FromStr;
+
+/// How far the timestamp of signed requests may be from the time of the server, in seconds.
+/// Nonces are remembered for twice this long.
+pub const SIGNATURE_WINDOW: u64 = 300;
+
+/// How long challenges for deleting a volume can be used, in seconds.
+pub const CHALLENGE_LIFETIME: u64 = 300;
+
+/// Length (in bytes) of challenges.
+const CHALLENGE_LENGTH: usize = 32;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SignatureError {
+    #[error("Error talking to database: {0:}")]
+    Database(#[from] sqlx::Error),
+    #[error("Request must be signed with the volume key")]
+    Missing,
+    #[error("Malformed request signature")]
+    Malformed,
+    #[error("Request signature is not valid for this volume")]
+    Invalid,
+    #[error("Request signature has expired, check the clock of the client")]
+    Expired,
+    #[error("Request signature was already used")]
+    Replayed,
+    #[error("Request body does not match the signed digest")]
+    BodyMismatch,
+    #[error("Deleting a volume requires signing a challenge with the volume key")]
+    ProofMissing,
+    #[error("Challenge is unknown, was already used or has expired")]
+    ChallengeInvalid,
+}
+
+impl From<RequestSignatureError> for SignatureError {
+    fn from(error: RequestSignatureError) -> Self {
+        match error {
+            RequestSignatureError::Malformed => SignatureError::Malformed,
+            RequestSignatureError::Invalid => SignatureError::Invalid,
+        }
+    }
+}
+
+impl SignatureError {
+    pub fn status(&self) -> Status {
+        match self {
+            SignatureError::Database(_) => Status::InternalServerError,
+            SignatureError::Malformed => Status::BadRequest,
+            _ => Status::Forbidden,
+        }
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            SignatureError::Database(_) => ErrorCode::Internal,
+            SignatureError::Malformed => ErrorCode::InvalidRequest,
+            _ => ErrorCode::Forbidden,
+        }
+    }
+}
+
+/// Whether mutating requests for critical volume operations (deleting and editing volumes)
+/// have to be signed with the volume key, in addition to being authenticated.
+#[derive(Clone, Copy, Debug)]
+pub struct RequestSigning {
+    pub required: bool,
+}
+
+/// Request guard for critical volume operations. Verifies the request signature, if one is
+/// sent or signing is required, against the key of the volume in the request path. Each
+/// signature is only accepted once. The signature covers the digest of the body, which the
+/// route has to check with [`SignedRequest::verify_body`] once it has read the body.
+pub struct SignedRequest {
+    digest: Option<String>,
+}
+
+impl SignedRequest {
+    /// Whether the request was signed with the volume key.
+    pub fn signed(&self) -> bool {
+        self.digest.is_some()
+    }
+
+    /// Check that the body is the one that the signature covers, if the request was signed.
+    pub fn verify_body(&self, body: &[u8]) -> Result<(), SignatureError> {
+        match &self.digest {
+            Some(digest) if !digest.eq_ignore_ascii_case(&RequestSignature::digest(body)) => {
+                Err(SignatureError::BodyMismatch)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Remember the nonce of a signed request, failing if it was seen before.
+async fn nonce_record(
+    conn: &mut AnyConnection,
+    volume: &[u8],
+    signature: &RequestSignature,
+    now: u64,
+) -> Result<(), SignatureError> {
+    query("DELETE FROM storage_request_nonce WHERE request_time < ?")
+        .bind(now.saturating_sub(2 * SIGNATURE_WINDOW) as i64)
+        .execute(&mut *conn)
+        .await?;
+    let result = query(
+        "INSERT INTO storage_request_nonce(volume_pubkey, request_nonce, request_time)
+        VALUES (?, ?, ?)",
+    )
+    .bind(volume)
+    .bind(signature.nonce.as_str())
+    .bind(signature.timestamp as i64)
+    .execute(&mut *conn)
+    .await;
+    match result {
+        Ok(_) => Ok(()),
+        Err(sqlx::Error::Database(error)) if unique_violation(error.code().as_deref()) => {
+            Err(SignatureError::Replayed)
+        }
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Whether the database error code reports a violated unique constraint (SQLite reports
+/// primary key violations separately).
+fn unique_violation(code: Option<&str>) -> bool {
+    matches!(code, Some("23505" | "2067" | "1555"))
+}
+
+async fn verify(request: &Request<'_>) -> Result<Option<String>, SignatureError> {
+    let required = request
+        .rocket()
+        .state::<RequestSigning>()
+        .map(|signing| signing.required)
+        .unwrap_or(false);
+    let signature = match request.headers().get_one(SIGNATURE_HEADER) {
+        Some(header) => {
+            RequestSignature::from_str(header).map_err(|_| SignatureError::Malformed)?
+        }
+        None if required => return Err(SignatureError::Missing),
+        None => return Ok(None),
+    };
+    let digest = match request.headers().get_one(DIGEST_HEADER) {
+        Some(digest) => digest.trim().to_string(),
+        None => RequestSignature::digest(&[]),
+    };
+    let path = request.uri().path().to_string();
+    let volume = request_volume(&path).ok_or(SignatureError::Invalid)?;
+    signature
+        .verify(&volume, request.method().as_str(), &path, &digest)
+        .map_err(|_| SignatureError::Invalid)?;
+    let now = now();
+    if signature.timestamp.abs_diff(now) > SIGNATURE_WINDOW {
+        return Err(SignatureError::Expired);
+    }
+    let pool = request
+        .rocket()
+        .state::<AnyPool>()
+        .ok_or(sqlx::Error::PoolClosed)?;
+    let mut conn = pool.acquire().await?;
+    nonce_record(&mut conn, volume.as_slice(), &signature, now).await?;
+    Ok(Some(digest))
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SignedRequest {
+    type Error = SignatureError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match verify(request).await {
+            Ok(digest) => Outcome::Success(SignedRequest { digest }),
+            Err(error) => Outcome::Failure((error.status(), error)),
+        }
+    }
+}
+
+/// Issue a challenge that has to be signed with the volume key to delete the volume.
+pub async fn challenge_create(
+    conn: &mut AnyConnection,
+    volume: &VolumeData,
+    now: u64,
+) -> Result<VolumeChallenge, SignatureError> {
+    query("DELETE FROM storage_volume_challenge WHERE challenge_expires < ?")
+        .bind(now as i64)
+        .execute(&mut *conn)
+        .await?;
+    let mut challenge = [0; CHALLENGE_LENGTH];
+    thread_rng().fill_bytes(&mut challenge);
+    let challenge = VolumeChallenge {
+        challenge: hex::encode(challenge),
+        expires: now + CHALLENGE_LIFETIME,
+    };
+    query(
+        "INSERT INTO storage_volume_challenge(volume_id, challenge_value, challenge_expires)
+        VALUES (?, ?, ?)",
+    )
+    .bind(volume.id())
+    .bind(challenge.challenge.as_str())
+    .bind(challenge.expires as i64)
+    .execute(&mut *conn)
+    .await?;
+    Ok(challenge)
+}
+
+/// Verify the proof that a challenge issued for the volume was signed with its key. Each
+/// challenge can only be used once.
+pub async fn challenge_consume(
+    conn: &mut AnyConnection,
+    volume: &VolumeData,
+    proof: &str,
+    now: u64,
+) -> Result<(), SignatureError> {
+    let challenge = VolumeChallenge::proof_verify(volume.pubkey(), proof)?;
+    let result = query(
+        "DELETE FROM storage_volume_challenge
+        WHERE volume_id = ? AND challenge_value = ? AND challenge_expires >= ?",
+    )
+    .bind(volume.id())
+    .bind(challenge.as_str())
+    .bind(now as i64)
+    .execute(&mut *conn)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(SignatureError::ChallengeInvalid);
+    }
+    Ok(())
+}
+
+/// Request guard for the proof that a volume challenge was signed, if one was sent.
+pub struct VolumeProof(Option<String>);
+
+impl VolumeProof {
+    pub fn proof(&self) -> Result<&str, SignatureError> {
+        self.0.as_deref().ok_or(SignatureError::ProofMissing)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for VolumeProof {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let proof = request.headers().get_one(PROOF_HEADER).map(String::from);
+        Outcome::Success(VolumeProof(proof))
+    }
+}
+
+#[tokio::test]
+async fn test_nonce_record() {
+    use fractal_storage_client::Privkey;
+
+    let pool = AnyPool::connect("sqlite://:memory:").await.unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+    let mut conn = pool.acquire().await.unwrap();
+    let privkey = Privkey::generate();
+    let volume = privkey.pubkey();
+    let signature = RequestSignature::sign(&privkey, "DELETE", "/", &[], now());
+    nonce_record(&mut conn, volume.as_slice(), &signature, now())
+        .await
+        .unwrap();
+    assert!(matches!(
+        nonce_record(&mut conn, volume.as_slice(), &signature, now()).await,
+        Err(SignatureError::Replayed)
+    ));
+
+    // nonces are forgotten once their signature can no longer be used
+    let later = now() + 2 * SIGNATURE_WINDOW + 1;
+    let other = RequestSignature::sign(&privkey, "DELETE", "/", &[], later);
+    nonce_record(&mut conn, volume.as_slice(), &other, later)
+        .await
+        .unwrap();
+    nonce_record(&mut conn, volume.as_slice(), &signature, later)
+        .await
+        .unwrap();
+
+    // other database errors are not mistaken for replays
+    query("DROP TABLE storage_request_nonce")
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+    let signature = RequestSignature::sign(&privkey, "DELETE", "/", &[], now());
+    assert!(matches!(
+        nonce_record(&mut conn, volume.as_slice(), &signature, now()).await,
+        Err(SignatureError::Database(_))
+    ));
+}
+
+#[test]
+fn test_signed_request_body() {
+    let body = br#"{"locked":true}"#;
+    let signed = SignedRequest {
+        digest: Some(RequestSignature::digest(body)),
+    };
+    assert!(signed.verify_body(body).is_ok());
+    assert!(matches!(
+        signed.verify_body(br#"{"locked":false}"#),
+        Err(SignatureError::BodyMismatch)
+    ));
+    let unsigned = SignedRequest { digest: None };
+    assert!(unsigned.verify_body(body).is_ok());
+}
+
+#[tokio::test]
+async fn test_challenge() {
+    use crate::volume::Volume;
+    use fractal_storage_client::Privkey;
+    use uuid::Uuid;
+
+    let pool = AnyPool::connect("sqlite://:memory:").await.unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+    let mut conn = pool.acquire().await.unwrap();
+    let privkey = Privkey::generate();
+    Volume::create(&mut conn, &privkey.pubkey(), &Uuid::new_v4())
+        .await
+        .unwrap();
+    let volume = Volume::lookup(&mut conn, &privkey.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+
+    // challenges must be signed with the volume key, and can only be used once
+    let challenge = challenge_create(&mut conn, &volume, now()).await.unwrap();
+    let proof = challenge.proof(&Privkey::generate());
+    assert!(matches!(
+        challenge_consume(&mut conn, &volume, &proof, now()).await,
+        Err(SignatureError::Invalid)
+    ));
+    let proof = challenge.proof(&privkey);
+    challenge_consume(&mut conn, &volume, &proof, now())
+        .await
+        .unwrap();
+    assert!(matches!(
+        challenge_consume(&mut conn, &volume, &proof, now()).await,
+        Err(SignatureError::ChallengeInvalid)
+    ));
+
+    // expired challenges are rejected
+    let challenge = challenge_create(&mut conn, &volume, now()).await.unwrap();
+    let proof = challenge.proof(&privkey);
+    let later = now() + CHALLENGE_LIFETIME + 1;
+    assert!(matches!(
+        challenge_consume(&mut conn, &volume, &proof, later).await,
+        Err(SignatureError::ChallengeInvalid)
+    ));
+}
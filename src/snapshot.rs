@@ -1,15 +1,55 @@
+use crate::purge::now;
+use crate::redact::RedactedHash;
 use crate::volume::{Volume, VolumeData};
 use async_trait::async_trait;
-use fractal_storage_client::{Hash, Manifest, ManifestSigned};
+use fractal_storage_client::{
+    ChainLink, ChainReport, DailyStats, DuplicateData, Hash, MachineSnapshot, Manifest,
+    ManifestSigned, Pubkey, SnapshotOrder, SnapshotOrdering, SnapshotReference, SortDirection,
+    StorageClass, Warning,
+};
 use serde::{Deserialize, Serialize};
 use sqlx::any::AnyRow;
 use sqlx::{query, AnyConnection, Row};
+use std::str::FromStr;
 use thiserror::Error;
+use tracing::instrument;
+use url::Url;
 use uuid::Uuid;
 
 /// Minimum accepted size for BTRFS snapshot. Experientally determined, used as safeguard
 /// to prevent broken snapshots from being accepted.
-pub const MINIMUM_SNAPSHOT_SIZE: u64 = 64;
+pub const MINIMUM_SNAPSHOT_SIZE: u64 = fractal_storage_client::MANIFEST_SIZE_MIN;
+
+/// How many hashes are checked per statement when checking for existing snapshots.
+const EXISTING_CHUNK_SIZE: usize = 256;
+
+/// How many snapshots are updated at a time when backfilling payload references.
+const BACKFILL_CHUNK_SIZE: i64 = 256;
+
+/// How far in the future snapshots may be created before a clock skew warning is issued,
+/// in seconds.
+const CLOCK_SKEW_MAX: u64 = 300;
+
+/// Length of a day, in seconds.
+const DAY: u64 = 86400;
+
+/// How many snapshots are archived per query.
+const ARCHIVE_CHUNK_SIZE: i64 = 256;
+
+/// Compression level of archived manifests, which are rarely read.
+const ARCHIVE_ZSTD_LEVEL: i32 = 19;
+
+/// Compression of manifests stored in the snapshot table with zstd.
+const MANIFEST_ZSTD: &str = "zstd";
+
+/// Compression level of manifests stored in the snapshot table.
+#[cfg(feature = "compress-manifests")]
+const MANIFEST_ZSTD_LEVEL: i32 = 3;
+
+/// Column with the archived manifest of a snapshot, which queries whose rows are decoded with
+/// [`SnapshotData::from_row`] need to select alongside the snapshot.
+pub const ARCHIVE_DATA: &str = "(SELECT archive_data FROM storage_snapshot_archive
+    WHERE storage_snapshot_archive.snapshot_id = storage_snapshot.snapshot_id) AS archive_data";
 
 #[derive(Error, Debug)]
 pub enum SnapshotError {
@@ -23,8 +63,12 @@ pub enum SnapshotError {
     MissingRowid,
     #[error("Wrong size_total, expected {0:} but got {1:}")]
     WrongSizeTotal(u64, u64),
-    #[error("Missing parent with hash {0:}")]
+    #[error("Missing parent with hash {}", RedactedHash::new(.0))]
     MissingParent(Hash),
+    #[error("Parent with hash {} not found", RedactedHash::new(.0))]
+    ParentNotFound(Hash),
+    #[error("Error checking access to parent volume: {0:}")]
+    Acl(#[from] crate::acl::AclError),
     #[error("Cannot decode manifest: {0:}")]
     ManifestDecode(String),
     #[error("Invalid generation: manifest has generation {0:} but parent has {1:}")]
@@ -35,6 +79,58 @@ pub enum SnapshotError {
     InvalidWriter(Uuid),
     #[error("Volume is locked")]
     VolumeLocked,
+    #[error("Error parsing key: {0:}")]
+    ParseKey(#[from] fractal_storage_client::keys::ParseError),
+    #[error("Invalid payload reference: {0:}")]
+    InvalidData(#[from] url::ParseError),
+    #[error("Cannot compress manifest for archiving: {0:}")]
+    ArchiveCompress(std::io::Error),
+    #[error("Cannot decompress archived manifest: {0:}")]
+    ArchiveDecompress(std::io::Error),
+    #[error("Cannot decompress manifest: {0:}")]
+    ManifestDecompress(std::io::Error),
+    #[error("Unknown manifest compression {0:?}")]
+    ManifestCompression(String),
+    #[error("Snapshot with generation {0:} exists")]
+    GenerationExists(u64),
+}
+
+/// Determines if the error is a violation of a unique constraint, which is how concurrent
+/// inserts of conflicting rows are detected.
+fn unique_violation(error: &sqlx::Error) -> bool {
+    match error {
+        // SQLITE_CONSTRAINT_UNIQUE and unique_violation in Postgres
+        sqlx::Error::Database(error) => matches!(error.code().as_deref(), Some("2067" | "23505")),
+        _ => false,
+    }
+}
+
+/// Encode a manifest for the snapshot table, returning the compression used, if any.
+#[cfg(feature = "compress-manifests")]
+fn manifest_encode(manifest: &[u8]) -> (Vec<u8>, Option<&'static str>) {
+    match zstd::encode_all(manifest, MANIFEST_ZSTD_LEVEL) {
+        Ok(compressed) => (compressed, Some(MANIFEST_ZSTD)),
+        Err(_) => (manifest.to_vec(), None),
+    }
+}
+
+/// Encode a manifest for the snapshot table. Without the `compress-manifests` feature they are
+/// stored as they are, but compressed manifests can still be read, so that the feature can be
+/// turned off again.
+#[cfg(not(feature = "compress-manifests"))]
+fn manifest_encode(manifest: &[u8]) -> (Vec<u8>, Option<&'static str>) {
+    (manifest.to_vec(), None)
+}
+
+/// Decode a manifest stored in the snapshot table with the given compression.
+fn manifest_decode(stored: Vec<u8>, compression: Option<&str>) -> Result<Vec<u8>, SnapshotError> {
+    match compression {
+        None => Ok(stored),
+        Some(MANIFEST_ZSTD) => {
+            zstd::decode_all(stored.as_slice()).map_err(SnapshotError::ManifestDecompress)
+        }
+        Some(other) => Err(SnapshotError::ManifestCompression(other.into())),
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -47,6 +143,10 @@ pub struct SnapshotData {
     parent: Option<i64>,
     manifest: ManifestSigned,
     hash: Vec<u8>,
+    /// Time the snapshot was uploaded, unknown for snapshots stored before it was recorded.
+    uploaded: Option<u64>,
+    /// Storage class of the volume when the snapshot was uploaded.
+    storage_class: StorageClass,
 }
 
 #[async_trait]
@@ -74,8 +174,19 @@ impl SnapshotData {
         let volume: i64 = row.try_get("volume_id")?;
         let hash: Vec<u8> = row.try_get("snapshot_hash")?;
         let parent: Option<i64> = row.try_get("snapshot_parent")?;
-        let manifest: Vec<u8> = row.try_get("snapshot_manifest")?;
+        let manifest: Vec<u8> = match row.try_get::<Option<Vec<u8>>, _>("archive_data")? {
+            Some(archived) => {
+                zstd::decode_all(archived.as_slice()).map_err(SnapshotError::ArchiveDecompress)?
+            }
+            None => manifest_decode(
+                row.try_get("snapshot_manifest")?,
+                row.try_get::<Option<String>, _>("snapshot_compression")?
+                    .as_deref(),
+            )?,
+        };
         let signature: Vec<u8> = row.try_get("snapshot_signature")?;
+        let uploaded: Option<i64> = row.try_get("snapshot_uploaded")?;
+        let storage_class: Option<String> = row.try_get("snapshot_storage_class")?;
         Ok(SnapshotData {
             id,
             volume,
@@ -83,6 +194,10 @@ impl SnapshotData {
             manifest: ManifestSigned::from_parts(&manifest, &signature)
                 .map_err(|e| SnapshotError::ManifestDecode(e.to_string()))?,
             hash,
+            uploaded: uploaded.map(|uploaded| uploaded as u64),
+            storage_class: storage_class
+                .and_then(|class| StorageClass::from_str(&class).ok())
+                .unwrap_or_default(),
         })
     }
 
@@ -105,6 +220,165 @@ impl SnapshotData {
     pub fn hash(&self) -> Hash {
         Hash::try_from(self.hash.as_slice()).unwrap()
     }
+
+    pub fn parent(&self) -> Option<Snapshot> {
+        self.parent.map(Snapshot)
+    }
+
+    pub fn volume(&self) -> Volume {
+        Volume::from(self.volume)
+    }
+
+    pub fn uploaded(&self) -> Option<u64> {
+        self.uploaded
+    }
+
+    pub fn storage_class(&self) -> StorageClass {
+        self.storage_class
+    }
+
+    /// Whether this snapshot is still within the write-once period of its volume, and must
+    /// not be deleted.
+    pub fn immutable(&self, worm_period: Option<u64>, now: u64) -> bool {
+        match (self.uploaded, worm_period) {
+            (Some(uploaded), Some(period)) => uploaded.saturating_add(period) > now,
+            _ => false,
+        }
+    }
+
+    /// Conditions of this snapshot that are accepted, but which the uploader should be
+    /// warned about.
+    pub async fn warnings(
+        &self,
+        conn: &mut AnyConnection,
+        now: u64,
+    ) -> Result<Vec<Warning>, SnapshotError> {
+        let mut warnings = vec![];
+        let manifest = self.manifest();
+        if let Some(parent) = self.parent() {
+            let parent = parent.fetch(conn).await?.manifest().generation;
+            if manifest.generation > parent + 1 {
+                warnings.push(Warning::GenerationGap {
+                    parent,
+                    generation: manifest.generation,
+                });
+            }
+        }
+        if manifest.creation > now + CLOCK_SKEW_MAX {
+            warnings.push(Warning::ClockSkew {
+                creation: manifest.creation,
+                server: now,
+            });
+        }
+        Ok(warnings)
+    }
+
+    /// Fetch this snapshot and all of its ancestors, ordered from this snapshot to the root,
+    /// along with the volume each of them is stored in.
+    pub async fn ancestors(
+        &self,
+        conn: &mut AnyConnection,
+    ) -> Result<Vec<(SnapshotData, VolumeData)>, SnapshotError> {
+        let mut chain = vec![];
+        let mut current = Some(self.clone());
+        while let Some(snapshot) = current {
+            let volume = snapshot.volume().fetch(conn).await?;
+            current = match snapshot.parent() {
+                Some(parent) => Some(parent.fetch(conn).await?),
+                None => None,
+            };
+            chain.push((snapshot, volume));
+        }
+        Ok(chain)
+    }
+
+    /// Fetch this snapshot and its ancestors like [`SnapshotData::ancestors`], but stop at
+    /// the first one stored in a volume that does not belong to the account. Returns whether
+    /// the chain reaches the root.
+    pub async fn ancestry(
+        &self,
+        conn: &mut AnyConnection,
+        account: &Uuid,
+    ) -> Result<(Vec<(SnapshotData, Pubkey)>, bool), SnapshotError> {
+        let mut chain = vec![];
+        let mut current = Some(self.clone());
+        while let Some(snapshot) = current {
+            let volume = snapshot.volume().fetch(conn).await?;
+            if volume.account() != account {
+                return Ok((chain, false));
+            }
+            current = match snapshot.parent() {
+                Some(parent) => Some(parent.fetch(conn).await?),
+                None => None,
+            };
+            chain.push((snapshot, *volume.pubkey()));
+        }
+        Ok((chain, true))
+    }
+}
+
+/// Validate a chain of snapshots, as returned by [`SnapshotData::ancestors`]: checks the
+/// signature of every manifest, that generations increase monotonically and that sizes add
+/// up from the root.
+pub fn chain_validate(chain: &[(SnapshotData, VolumeData)]) -> ChainReport {
+    let mut links = vec![];
+    for (index, (snapshot, volume)) in chain.iter().enumerate() {
+        let manifest = snapshot.manifest();
+        let mut errors = vec![];
+        let signed = snapshot.manifest_signed();
+        if let Err(e) = signed.validate_pinned(volume.pubkey(), volume.pq_pubkey()) {
+            errors.push(format!("Invalid signature: {e}"));
+        }
+        match chain.get(index + 1) {
+            Some((parent, _)) => {
+                let parent_manifest = parent.manifest();
+                if manifest.parent.as_ref().map(|parent| parent.hash) != Some(parent.hash()) {
+                    errors.push(format!(
+                        "Manifest does not reference parent {}",
+                        parent.hash()
+                    ));
+                }
+                if manifest.generation <= parent_manifest.generation {
+                    errors.push(
+                        SnapshotError::InvalidGeneration(
+                            manifest.generation,
+                            parent_manifest.generation,
+                        )
+                        .to_string(),
+                    );
+                }
+                let expected = parent_manifest.size_total + manifest.size;
+                if manifest.size_total != expected {
+                    errors.push(
+                        SnapshotError::WrongSizeTotal(expected, manifest.size_total).to_string(),
+                    );
+                }
+            }
+            None => {
+                if let Some(parent) = &manifest.parent {
+                    errors.push(SnapshotError::MissingParent(parent.hash).to_string());
+                }
+                if manifest.size_total != manifest.size {
+                    errors.push(
+                        SnapshotError::WrongSizeTotal(manifest.size, manifest.size_total)
+                            .to_string(),
+                    );
+                }
+            }
+        }
+        links.push(ChainLink {
+            hash: snapshot.hash(),
+            volume: *volume.pubkey(),
+            generation: manifest.generation,
+            size: manifest.size,
+            size_total: manifest.size_total,
+            errors,
+        });
+    }
+    ChainReport {
+        valid: links.iter().all(|link| link.errors.is_empty()),
+        links,
+    }
 }
 
 impl Snapshot {
@@ -120,7 +394,11 @@ impl Snapshot {
         hash: &Hash,
         parent: Option<&Snapshot>,
         generation: u64,
+        data: &Url,
     ) -> Result<Snapshot, SnapshotError> {
+        // sort keys are only recorded for manifests that can be decoded
+        let decoded = Manifest::decode(manifest).ok();
+        let (stored, compression) = manifest_encode(manifest);
         let result = query(
             "INSERT INTO storage_snapshot(
             volume_id,
@@ -128,22 +406,46 @@ impl Snapshot {
             snapshot_signature,
             snapshot_hash,
             snapshot_parent,
-            snapshot_generation)
-            VALUES (?, ?, ?, ?, ?, ?)",
+            snapshot_generation,
+            snapshot_data,
+            snapshot_creation,
+            snapshot_size,
+            snapshot_machine,
+            snapshot_uploaded,
+            snapshot_compression,
+            snapshot_storage_class)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?,
+                (SELECT volume_storage_class FROM storage_volume WHERE volume_id = ?))",
         )
         .bind(volume.id())
-        .bind(manifest)
+        .bind(stored)
         .bind(signature)
         .bind(hash.as_slice())
         .bind(parent.map(|p| p.id()))
         .bind(generation as i64)
+        .bind(data.as_str())
+        .bind(decoded.as_ref().map(|manifest| manifest.creation as i64))
+        .bind(decoded.as_ref().map(|manifest| manifest.size as i64))
+        .bind(
+            decoded
+                .as_ref()
+                .map(|manifest| manifest.machine.to_string()),
+        )
+        .bind(now() as i64)
+        .bind(compression)
+        .bind(volume.id())
         .execute(conn)
-        .await?;
+        .await
+        .map_err(|error| match unique_violation(&error) {
+            true => SnapshotError::GenerationExists(generation),
+            false => error.into(),
+        })?;
         Ok(Snapshot(
             result.last_insert_id().ok_or(SnapshotError::MissingRowid)?,
         ))
     }
 
+    #[instrument(skip_all, fields(volume = %volume.pubkey()))]
     pub async fn create_from_manifest(
         conn: &mut AnyConnection,
         volume: &VolumeData,
@@ -151,7 +453,8 @@ impl Snapshot {
     ) -> Result<Snapshot, SnapshotError> {
         let (manifest, signature) =
             Manifest::split(&manifest).ok_or(SnapshotError::ManifestInvalid)?;
-        Manifest::validate(manifest, signature, volume.pubkey()).unwrap();
+        Manifest::validate_pinned(manifest, signature, volume.pubkey(), volume.pq_pubkey())
+            .map_err(|_| SnapshotError::ManifestInvalid)?;
         let parsed = Manifest::decode(manifest).map_err(|_| SnapshotError::ManifestInvalid)?;
         let hash = Manifest::hash(manifest);
 
@@ -167,12 +470,19 @@ impl Snapshot {
             }
         }
 
-        // validate parent
+        // validate parent, which may live in a different volume
         let parent = match &parsed.parent {
-            Some(parent) if parent.volume.is_none() => {
-                let parent = Snapshot::fetch_by_hash(conn, &volume.volume(), &parent.hash)
-                    .await?
-                    .ok_or_else(|| SnapshotError::MissingParent(parent.hash))?;
+            Some(parent) => {
+                let parent = match &parent.volume {
+                    None => Snapshot::fetch_by_hash(conn, &volume.volume(), &parent.hash)
+                        .await?
+                        .ok_or(SnapshotError::MissingParent(parent.hash))?,
+                    Some((pubkey, _)) => {
+                        Snapshot::fetch_foreign_parent(conn, volume, pubkey, &parent.hash)
+                            .await?
+                            .ok_or(SnapshotError::ParentNotFound(parent.hash))?
+                    }
+                };
                 let expected_size_total = parent.manifest().size_total + parsed.size;
                 if parsed.size_total != expected_size_total {
                     return Err(SnapshotError::WrongSizeTotal(
@@ -191,7 +501,6 @@ impl Snapshot {
                 }
                 Some(parent.snapshot())
             }
-            Some(_parent) => None,
             None => {
                 if parsed.size != parsed.size_total {
                     return Err(SnapshotError::WrongSizeTotal(
@@ -211,6 +520,7 @@ impl Snapshot {
             &hash,
             parent.as_ref(),
             parsed.generation,
+            &parsed.data,
         )
         .await?;
 
@@ -222,20 +532,76 @@ impl Snapshot {
         Ok(snapshot)
     }
 
-    pub async fn fetch(&self, conn: &mut AnyConnection) -> Result<SnapshotData, SnapshotError> {
-        let row = query("SELECT * FROM storage_snapshot WHERE snapshot_id = ?")
+    /// Fetch the parent of a snapshot of the volume from another volume. The other volume has
+    /// to belong to the same account or be shared with it, otherwise it is treated as if it
+    /// did not exist, so that uploads cannot be used to probe or link into other accounts.
+    async fn fetch_foreign_parent(
+        conn: &mut AnyConnection,
+        volume: &VolumeData,
+        pubkey: &Pubkey,
+        hash: &Hash,
+    ) -> Result<Option<SnapshotData>, SnapshotError> {
+        let parent_volume = match Volume::lookup(conn, pubkey).await? {
+            Some(parent_volume) => parent_volume,
+            None => return Ok(None),
+        };
+        if parent_volume.account() != volume.account()
+            && crate::acl::granted(conn, &parent_volume, volume.account())
+                .await?
+                .is_none()
+        {
+            return Ok(None);
+        }
+        Snapshot::fetch_by_hash(conn, &parent_volume.volume(), hash).await
+    }
+
+    /// Delete this snapshot. Fails if other snapshots still reference it as their parent.
+    pub async fn delete(&self, conn: &mut AnyConnection) -> Result<(), SnapshotError> {
+        query("DELETE FROM storage_snapshot WHERE snapshot_id = ?")
+            .bind(self.0)
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Determines if other snapshots, in any volume, have this snapshot as their parent.
+    pub async fn has_children(&self, conn: &mut AnyConnection) -> Result<bool, SnapshotError> {
+        let row = query("SELECT COUNT(*) AS count FROM storage_snapshot WHERE snapshot_parent = ?")
             .bind(self.0)
             .fetch_one(conn)
             .await?;
+        Ok(row.try_get::<i64, _>("count")? > 0)
+    }
+
+    /// Count the snapshots, in any volume, that reference the given payload.
+    pub async fn data_references(
+        conn: &mut AnyConnection,
+        data: &Url,
+    ) -> Result<u64, SnapshotError> {
+        let row = query("SELECT COUNT(*) AS count FROM storage_snapshot WHERE snapshot_data = ?")
+            .bind(data.as_str())
+            .fetch_one(conn)
+            .await?;
+        Ok(row.try_get::<i64, _>("count")? as u64)
+    }
+
+    pub async fn fetch(&self, conn: &mut AnyConnection) -> Result<SnapshotData, SnapshotError> {
+        let statement =
+            format!("SELECT *, {ARCHIVE_DATA} FROM storage_snapshot WHERE snapshot_id = ?");
+        let row = query(&statement).bind(self.0).fetch_one(conn).await?;
         Ok(SnapshotData::from_row(&row)?)
     }
 
+    #[instrument(skip_all, fields(hash = %RedactedHash::new(hash)))]
     pub async fn fetch_by_hash(
         conn: &mut AnyConnection,
         volume: &Volume,
         hash: &Hash,
     ) -> Result<Option<SnapshotData>, SnapshotError> {
-        let row = query("SELECT * FROM storage_snapshot WHERE snapshot_hash = ? AND volume_id = ?")
+        let statement = format!(
+            "SELECT *, {ARCHIVE_DATA} FROM storage_snapshot WHERE snapshot_hash = ? AND volume_id = ?"
+        );
+        let row = query(&statement)
             .bind(hash.as_slice())
             .bind(volume.id())
             .fetch_optional(conn)
@@ -246,47 +612,451 @@ impl Snapshot {
         }
     }
 
+    /// Look up a snapshot by the hash and signature of its manifest. This only uses the
+    /// index on the hash and does not decode the stored manifest.
+    #[instrument(skip_all, fields(hash = %RedactedHash::new(hash)))]
+    pub async fn lookup_by_hash(
+        conn: &mut AnyConnection,
+        volume: &Volume,
+        hash: &Hash,
+        signature: &[u8],
+    ) -> Result<Option<Snapshot>, SnapshotError> {
+        let row = query(
+            "SELECT snapshot_id FROM storage_snapshot
+                WHERE snapshot_hash = ? AND volume_id = ? AND snapshot_signature = ?",
+        )
+        .bind(hash.as_slice())
+        .bind(volume.id())
+        .bind(signature)
+        .fetch_optional(conn)
+        .await?;
+        match row {
+            None => Ok(None),
+            Some(row) => Ok(Some(Snapshot(row.try_get("snapshot_id")?))),
+        }
+    }
+
+    /// Determine which of the given hashes belong to snapshots in the volume. Hashes are
+    /// looked up in chunks, to stay below the limit of bound parameters per statement.
+    #[instrument(skip_all, fields(count = hashes.len()))]
+    pub async fn existing(
+        conn: &mut AnyConnection,
+        volume: &Volume,
+        hashes: &[Hash],
+    ) -> Result<Vec<Hash>, SnapshotError> {
+        let mut existing = vec![];
+        for chunk in hashes.chunks(EXISTING_CHUNK_SIZE) {
+            let placeholders = vec!["?"; chunk.len()].join(", ");
+            let statement = format!(
+                "SELECT snapshot_hash FROM storage_snapshot
+                    WHERE volume_id = ? AND snapshot_hash IN ({placeholders})"
+            );
+            let mut lookup = query(&statement).bind(volume.id());
+            for hash in chunk {
+                lookup = lookup.bind(hash.as_slice());
+            }
+            for row in lookup.fetch_all(&mut *conn).await? {
+                let hash: Vec<u8> = row.try_get("snapshot_hash")?;
+                existing.push(Hash::try_from(hash.as_slice()).unwrap());
+            }
+        }
+        Ok(existing)
+    }
+
     pub async fn fetch_by_generation(
         conn: &mut AnyConnection,
         volume: &Volume,
         generation: u64,
     ) -> Result<Option<SnapshotData>, SnapshotError> {
-        let row =
-            query("SELECT * FROM storage_snapshot WHERE snapshot_generation = ? AND volume_id = ?")
-                .bind(generation as i64)
-                .bind(volume.id())
-                .fetch_optional(conn)
-                .await?;
+        let statement = format!(
+            "SELECT *, {ARCHIVE_DATA} FROM storage_snapshot
+                WHERE snapshot_generation = ? AND volume_id = ?"
+        );
+        let row = query(&statement)
+            .bind(generation as i64)
+            .bind(volume.id())
+            .fetch_optional(conn)
+            .await?;
         match row {
             None => Ok(None),
             Some(row) => Ok(Some(SnapshotData::from_row(&row)?)),
         }
     }
 
+    /// List snapshots, ordered by generation.
     pub async fn list(
         conn: &mut AnyConnection,
         volume: &Volume,
         parent: Option<&Snapshot>,
         root: bool,
     ) -> Result<Vec<SnapshotData>, SnapshotError> {
-        let rows = query(
-            "SELECT * FROM storage_snapshot
+        let ordering = SnapshotOrdering::default();
+        Snapshot::list_ordered(conn, volume, parent, root, &ordering).await
+    }
+
+    /// List snapshots in the given order, snapshots that compare equal are ordered by hash.
+    #[instrument(skip_all)]
+    pub async fn list_ordered(
+        conn: &mut AnyConnection,
+        volume: &Volume,
+        parent: Option<&Snapshot>,
+        root: bool,
+        ordering: &SnapshotOrdering,
+    ) -> Result<Vec<SnapshotData>, SnapshotError> {
+        let column = match ordering.order {
+            SnapshotOrder::Generation => "snapshot_generation",
+            SnapshotOrder::Creation => "snapshot_creation",
+            SnapshotOrder::Size => "snapshot_size",
+        };
+        let direction = match ordering.direction {
+            SortDirection::Asc => "ASC",
+            SortDirection::Desc => "DESC",
+        };
+        let statement = format!(
+            "SELECT *, {ARCHIVE_DATA} FROM storage_snapshot
                 WHERE volume_id = $1
                 AND ($2 IS NULL OR snapshot_parent = $2)
-                AND ($3 = 0 OR snapshot_parent IS NULL)",
+                AND ($3 = 0 OR snapshot_parent IS NULL)
+                ORDER BY {column} {direction}, snapshot_hash {direction}"
+        );
+        let rows = query(&statement)
+            .bind(volume.id() as i64)
+            .bind(parent.map(|parent| parent.id()))
+            .bind(root)
+            .fetch_all(conn)
+            .await?;
+        let mut snapshots = vec![];
+        for row in &rows {
+            snapshots.push(SnapshotData::from_row(row)?);
+        }
+        Ok(snapshots)
+    }
+
+    /// List a page of snapshots, ordered by when they were stored. Only snapshots stored
+    /// after the `after` snapshot are returned, which makes for stable pagination.
+    #[instrument(skip_all, fields(limit = limit))]
+    pub async fn list_page(
+        conn: &mut AnyConnection,
+        volume: &Volume,
+        parent: Option<&Snapshot>,
+        root: bool,
+        after: Option<&Snapshot>,
+        limit: u64,
+    ) -> Result<Vec<SnapshotData>, SnapshotError> {
+        let statement = format!(
+            "SELECT *, {ARCHIVE_DATA} FROM storage_snapshot
+                WHERE volume_id = $1
+                AND ($2 IS NULL OR snapshot_parent = $2)
+                AND ($3 = 0 OR snapshot_parent IS NULL)
+                AND ($4 IS NULL OR snapshot_id > $4)
+                ORDER BY snapshot_id
+                LIMIT $5"
+        );
+        let rows = query(&statement)
+            .bind(volume.id() as i64)
+            .bind(parent.map(|parent| parent.id()))
+            .bind(root)
+            .bind(after.map(|after| after.id()))
+            .bind(limit as i64)
+            .fetch_all(conn)
+            .await?;
+        let mut snapshots = vec![];
+        for row in &rows {
+            snapshots.push(SnapshotData::from_row(row)?);
+        }
+        Ok(snapshots)
+    }
+
+    /// Find payloads referenced by more than one snapshot in (non-deleted) volumes of the
+    /// account. If a payload reference is given, returns the snapshots referencing it
+    /// instead, which lets clients skip uploading payloads that are already stored.
+    #[instrument(skip_all)]
+    pub async fn duplicates(
+        conn: &mut AnyConnection,
+        account: &Uuid,
+        data: Option<&Url>,
+    ) -> Result<Vec<DuplicateData>, SnapshotError> {
+        let rows = query(
+            "SELECT snapshot_data, snapshot_hash, snapshot_generation, volume_pubkey
+                FROM storage_snapshot
+                JOIN storage_volume ON storage_volume.volume_id = storage_snapshot.volume_id
+                WHERE account_id = $1 AND volume_deleted_at IS NULL
+                AND (($2 IS NULL AND snapshot_data IN (
+                    SELECT snapshot_data FROM storage_snapshot
+                    JOIN storage_volume ON storage_volume.volume_id = storage_snapshot.volume_id
+                    WHERE account_id = $1 AND volume_deleted_at IS NULL
+                    GROUP BY snapshot_data HAVING COUNT(*) > 1))
+                OR snapshot_data = $2)
+                ORDER BY snapshot_data, storage_snapshot.snapshot_id",
         )
-        .bind(volume.id() as i64)
-        .bind(parent.map(|parent| parent.id()))
-        .bind(root)
+        .bind(account.to_string())
+        .bind(data.map(Url::as_str))
         .fetch_all(conn)
-        .await
-        .unwrap();
+        .await?;
+        let mut duplicates: Vec<DuplicateData> = vec![];
+        for row in &rows {
+            let data = Url::parse(row.try_get("snapshot_data")?)?;
+            let hash: Vec<u8> = row.try_get("snapshot_hash")?;
+            let volume: Vec<u8> = row.try_get("volume_pubkey")?;
+            let generation: i64 = row.try_get("snapshot_generation")?;
+            let snapshot = SnapshotReference {
+                volume: Pubkey::try_from(volume.as_slice())?,
+                hash: Hash::try_from(hash.as_slice())?,
+                generation: generation as u64,
+            };
+            match duplicates.last_mut() {
+                Some(duplicate) if duplicate.data == data => duplicate.snapshots.push(snapshot),
+                _ => duplicates.push(DuplicateData {
+                    data,
+                    snapshots: vec![snapshot],
+                }),
+            }
+        }
+        Ok(duplicates)
+    }
+
+    /// Count all stored snapshots, along with the total size of their payloads.
+    pub async fn totals(conn: &mut AnyConnection) -> Result<(u64, u64), SnapshotError> {
+        let row = query(
+            "SELECT COUNT(*) AS snapshots, COALESCE(SUM(snapshot_size), 0) AS bytes
+                FROM storage_snapshot",
+        )
+        .fetch_one(conn)
+        .await?;
+        let snapshots: i64 = row.try_get("snapshots")?;
+        let bytes: i64 = row.try_get("bytes")?;
+        Ok((snapshots as u64, bytes as u64))
+    }
+
+    /// Count the snapshots created on each of the last `days` days up to and including the
+    /// one `now` falls on, along with the size of their payloads. Days without snapshots are
+    /// included with zero counts.
+    pub async fn daily(
+        conn: &mut AnyConnection,
+        now: u64,
+        days: u64,
+    ) -> Result<Vec<DailyStats>, SnapshotError> {
+        let today = now / DAY;
+        let first = (today + 1).saturating_sub(days);
+        let statement = format!(
+            "SELECT snapshot_creation / {DAY} AS day, COUNT(*) AS snapshots,
+                COALESCE(SUM(snapshot_size), 0) AS bytes
+                FROM storage_snapshot
+                WHERE snapshot_creation >= ? AND snapshot_creation < ?
+                GROUP BY day"
+        );
+        let rows = query(&statement)
+            .bind((first * DAY) as i64)
+            .bind(((today + 1) * DAY) as i64)
+            .fetch_all(conn)
+            .await?;
+        let mut daily: Vec<DailyStats> = (first..=today)
+            .map(|day| DailyStats {
+                day: day * DAY,
+                snapshots: 0,
+                bytes: 0,
+            })
+            .collect();
+        for row in &rows {
+            let day: i64 = row.try_get("day")?;
+            let snapshots: i64 = row.try_get("snapshots")?;
+            let bytes: i64 = row.try_get("bytes")?;
+            if let Some(stats) = daily.get_mut((day as u64 - first) as usize) {
+                stats.snapshots = snapshots as u64;
+                stats.bytes = bytes as u64;
+            }
+        }
+        Ok(daily)
+    }
+
+    /// Find snapshots created on a machine in (non-deleted) volumes of the account, newest
+    /// first.
+    #[instrument(skip_all, fields(machine = %machine))]
+    pub async fn by_machine(
+        conn: &mut AnyConnection,
+        account: &Uuid,
+        machine: &Uuid,
+        limit: u64,
+    ) -> Result<Vec<MachineSnapshot>, SnapshotError> {
+        let rows = query(
+            "SELECT snapshot_hash, snapshot_generation, snapshot_creation, volume_pubkey
+                FROM storage_snapshot
+                JOIN storage_volume ON storage_volume.volume_id = storage_snapshot.volume_id
+                WHERE snapshot_machine = ? AND account_id = ? AND volume_deleted_at IS NULL
+                ORDER BY snapshot_creation DESC, storage_snapshot.snapshot_id DESC
+                LIMIT ?",
+        )
+        .bind(machine.to_string())
+        .bind(account.to_string())
+        .bind(limit as i64)
+        .fetch_all(conn)
+        .await?;
         let mut snapshots = vec![];
         for row in &rows {
-            snapshots.push(SnapshotData::from_row(row)?);
+            let hash: Vec<u8> = row.try_get("snapshot_hash")?;
+            let volume: Vec<u8> = row.try_get("volume_pubkey")?;
+            let generation: i64 = row.try_get("snapshot_generation")?;
+            let creation: i64 = row.try_get("snapshot_creation")?;
+            snapshots.push(MachineSnapshot {
+                volume: Pubkey::try_from(volume.as_slice())?,
+                hash: Hash::try_from(hash.as_slice())?,
+                generation: generation as u64,
+                creation: creation as u64,
+            });
         }
         Ok(snapshots)
     }
+
+    /// Record the payload reference and sort keys of snapshots stored before these were
+    /// indexed, returning how many were updated.
+    pub async fn data_backfill(conn: &mut AnyConnection) -> Result<usize, SnapshotError> {
+        let mut count = 0;
+        let statement = format!(
+            "SELECT *, {ARCHIVE_DATA} FROM storage_snapshot
+            WHERE snapshot_data IS NULL OR snapshot_creation IS NULL OR snapshot_size IS NULL
+                OR snapshot_machine IS NULL
+            LIMIT ?"
+        );
+        loop {
+            let rows = query(&statement)
+                .bind(BACKFILL_CHUNK_SIZE)
+                .fetch_all(&mut *conn)
+                .await?;
+            if rows.is_empty() {
+                return Ok(count);
+            }
+            for row in &rows {
+                let snapshot = SnapshotData::from_row(row)?;
+                let manifest = snapshot.manifest();
+                query(
+                    "UPDATE storage_snapshot
+                    SET snapshot_data = ?, snapshot_creation = ?, snapshot_size = ?,
+                        snapshot_machine = ?
+                    WHERE snapshot_id = ?",
+                )
+                .bind(manifest.data.as_str())
+                .bind(manifest.creation as i64)
+                .bind(manifest.size as i64)
+                .bind(manifest.machine.to_string())
+                .bind(snapshot.snapshot().id())
+                .execute(&mut *conn)
+                .await?;
+            }
+            count += rows.len();
+        }
+    }
+
+    /// Compress the manifests of snapshots stored before they were compressed or, when built
+    /// without the `compress-manifests` feature, decompress them again so that older versions
+    /// can read them. Converts one chunk of snapshots after the given snapshot ID, returning
+    /// the ID of the last one along with how many were visited, or `None` once all are done.
+    pub async fn compression_backfill(
+        conn: &mut AnyConnection,
+        after: i64,
+    ) -> Result<Option<(i64, usize)>, SnapshotError> {
+        let condition = match cfg!(feature = "compress-manifests") {
+            true => "snapshot_compression IS NULL",
+            false => "snapshot_compression IS NOT NULL",
+        };
+        let rows = query(&format!(
+            "SELECT snapshot_id, snapshot_manifest, snapshot_compression FROM storage_snapshot
+            WHERE {condition} AND snapshot_id > ?
+                AND snapshot_id NOT IN (SELECT snapshot_id FROM storage_snapshot_archive)
+            ORDER BY snapshot_id
+            LIMIT ?"
+        ))
+        .bind(after)
+        .bind(BACKFILL_CHUNK_SIZE)
+        .fetch_all(&mut *conn)
+        .await?;
+        // manifests that fail to compress stay as they are, so rows are visited only once
+        let mut last = after;
+        for row in &rows {
+            let snapshot: i64 = row.try_get("snapshot_id")?;
+            let manifest = manifest_decode(
+                row.try_get("snapshot_manifest")?,
+                row.try_get::<Option<String>, _>("snapshot_compression")?
+                    .as_deref(),
+            )?;
+            let (stored, compression) = manifest_encode(&manifest);
+            // the service is running, so snapshots may have been archived in the meantime.
+            query(&format!(
+                "UPDATE storage_snapshot SET snapshot_manifest = ?, snapshot_compression = ?
+                WHERE snapshot_id = ? AND {condition}
+                    AND snapshot_id NOT IN (SELECT snapshot_id FROM storage_snapshot_archive)"
+            ))
+            .bind(stored)
+            .bind(compression)
+            .bind(snapshot)
+            .execute(&mut *conn)
+            .await?;
+            last = snapshot;
+        }
+        Ok(match rows.len() {
+            0 => None,
+            count => Some((last, count)),
+        })
+    }
+
+    /// Archive the manifests of snapshots created before the cutoff, and of snapshots uploaded
+    /// to volumes of the archive storage class regardless of age, returning how many were
+    /// archived. Manifests are compressed and moved to a separate table, while the hash,
+    /// generation and signature stay in the snapshot table, so that archived snapshots are
+    /// still found by lookups and rehydrated transparently when fetched.
+    #[instrument(skip_all, fields(cutoff = cutoff))]
+    pub async fn archive(conn: &mut AnyConnection, cutoff: u64) -> Result<usize, SnapshotError> {
+        let mut count = 0;
+        loop {
+            let rows = query(
+                "SELECT snapshot_id, snapshot_manifest, snapshot_compression FROM storage_snapshot
+                WHERE (snapshot_creation < ? OR snapshot_storage_class = 'archive')
+                AND snapshot_id NOT IN (SELECT snapshot_id FROM storage_snapshot_archive)
+                LIMIT ?",
+            )
+            .bind(cutoff as i64)
+            .bind(ARCHIVE_CHUNK_SIZE)
+            .fetch_all(&mut *conn)
+            .await?;
+            if rows.is_empty() {
+                return Ok(count);
+            }
+            for row in &rows {
+                let snapshot: i64 = row.try_get("snapshot_id")?;
+                let manifest = manifest_decode(
+                    row.try_get("snapshot_manifest")?,
+                    row.try_get::<Option<String>, _>("snapshot_compression")?
+                        .as_deref(),
+                )?;
+                let archived = zstd::encode_all(manifest.as_slice(), ARCHIVE_ZSTD_LEVEL)
+                    .map_err(SnapshotError::ArchiveCompress)?;
+
+                // the archived copy takes precedence, so the manifest is only cleared once
+                // it is stored.
+                query(
+                    "INSERT INTO storage_snapshot_archive(snapshot_id, archive_data) VALUES (?, ?)",
+                )
+                .bind(snapshot)
+                .bind(archived)
+                .execute(&mut *conn)
+                .await?;
+                query(
+                    "UPDATE storage_snapshot SET snapshot_manifest = ?, snapshot_compression = NULL
+                    WHERE snapshot_id = ?",
+                )
+                .bind(Vec::<u8>::new())
+                .bind(snapshot)
+                .execute(&mut *conn)
+                .await?;
+            }
+            count += rows.len();
+        }
+    }
+}
+
+impl From<i64> for Snapshot {
+    fn from(id: i64) -> Self {
+        Snapshot(id)
+    }
 }
 
 #[tokio::test]
@@ -326,6 +1096,7 @@ async fn test_snapshot_create() {
         &manifest_signed.hash(),
         None,
         0,
+        &manifest.data,
     )
     .await
     .unwrap();
@@ -345,4 +1116,243 @@ async fn test_snapshot_create() {
     assert_eq!(snapshot_data.manifest_signed().raw, manifest_signed.raw);
     assert_eq!(snapshot_data.signature(), manifest_signed.signature);
     assert_eq!(snapshot_data.hash(), manifest_signed.hash());
+
+    let lookup = Snapshot::lookup_by_hash(
+        &mut conn,
+        &volume.volume(),
+        &manifest_signed.hash(),
+        &manifest_signed.signature,
+    )
+    .await
+    .unwrap();
+    assert_eq!(lookup, Some(snapshot));
+    let lookup = Snapshot::lookup_by_hash(
+        &mut conn,
+        &volume.volume(),
+        &manifest_signed.hash(),
+        &[0; 64],
+    )
+    .await
+    .unwrap();
+    assert_eq!(lookup, None);
+
+    let missing = Hash::generate(&[1, 2, 3]);
+    let existing = Snapshot::existing(
+        &mut conn,
+        &volume.volume(),
+        &[missing, manifest_signed.hash()],
+    )
+    .await
+    .unwrap();
+    assert_eq!(existing, vec![manifest_signed.hash()]);
+
+    // conflicting manifest for the same generation violates the unique constraint
+    let conflicting = Manifest {
+        creation: 1,
+        ..manifest.clone()
+    }
+    .sign(&privkey);
+    let result = Snapshot::create(
+        &mut conn,
+        &volume.volume(),
+        &conflicting.raw,
+        &conflicting.signature,
+        &conflicting.hash(),
+        None,
+        0,
+        &manifest.data,
+    )
+    .await;
+    assert!(matches!(result, Err(SnapshotError::GenerationExists(0))));
+
+    let listed = Snapshot::list(&mut conn, &volume.volume(), None, false)
+        .await
+        .unwrap();
+    assert_eq!(listed.len(), 1);
+
+    // database errors while listing are reported rather than panicking
+    sqlx::query("ALTER TABLE storage_snapshot RENAME TO storage_snapshot_renamed")
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+    let result = Snapshot::list(&mut conn, &volume.volume(), None, false).await;
+    assert!(matches!(result, Err(SnapshotError::Database(_))));
+}
+
+#[tokio::test]
+async fn test_snapshot_archive() {
+    use fractal_storage_client::Privkey;
+    use sqlx::AnyPool;
+    use uuid::Uuid;
+
+    let pool = AnyPool::connect("sqlite://:memory:").await.unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+    let mut conn = pool.acquire().await.unwrap();
+
+    let privkey = Privkey::generate();
+    let volume = Volume::create(&mut conn, &privkey.pubkey(), &Uuid::new_v4())
+        .await
+        .unwrap();
+    let mut manifests = vec![];
+    for generation in 0..2 {
+        let manifest = Manifest {
+            creation: 1000 * generation,
+            data: "ipfs://asd99a0s8098da0sd98".parse().unwrap(),
+            generation,
+            parent: None,
+            size: MINIMUM_SNAPSHOT_SIZE,
+            size_total: MINIMUM_SNAPSHOT_SIZE,
+            machine: Default::default(),
+            path: std::path::PathBuf::from("abc"),
+        }
+        .sign(&privkey);
+        Snapshot::create(
+            &mut conn,
+            &volume,
+            &manifest.raw,
+            &manifest.signature,
+            &manifest.hash(),
+            None,
+            generation,
+            &manifest.manifest.data,
+        )
+        .await
+        .unwrap();
+        manifests.push(manifest);
+    }
+
+    // only the older snapshot is archived, and only once
+    assert_eq!(Snapshot::archive(&mut conn, 500).await.unwrap(), 1);
+    assert_eq!(Snapshot::archive(&mut conn, 500).await.unwrap(), 0);
+    let row = query("SELECT snapshot_manifest FROM storage_snapshot ORDER BY snapshot_generation")
+        .fetch_one(&mut conn)
+        .await
+        .unwrap();
+    assert!(row.get::<Vec<u8>, _>("snapshot_manifest").is_empty());
+
+    // archived manifests are rehydrated when fetched
+    let archived = Snapshot::fetch_by_hash(&mut conn, &volume, &manifests[0].hash())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(archived.manifest_signed().raw, manifests[0].raw);
+    assert_eq!(archived.signature(), manifests[0].signature);
+    let listed = Snapshot::list(&mut conn, &volume, None, false)
+        .await
+        .unwrap();
+    let listed: Vec<_> = listed
+        .iter()
+        .map(|snapshot| snapshot.manifest_signed().raw.clone())
+        .collect();
+    assert_eq!(
+        listed,
+        vec![manifests[0].raw.clone(), manifests[1].raw.clone()]
+    );
+    let lookup = Snapshot::lookup_by_hash(
+        &mut conn,
+        &volume,
+        &manifests[0].hash(),
+        &manifests[0].signature,
+    )
+    .await
+    .unwrap();
+    assert_eq!(lookup, Some(archived.snapshot()));
+}
+
+#[tokio::test]
+async fn test_manifest_compression() {
+    use fractal_storage_client::Privkey;
+    use sqlx::AnyPool;
+    use uuid::Uuid;
+
+    let pool = AnyPool::connect("sqlite://:memory:").await.unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+    let mut conn = pool.acquire().await.unwrap();
+
+    let privkey = Privkey::generate();
+    let volume = Volume::create(&mut conn, &privkey.pubkey(), &Uuid::new_v4())
+        .await
+        .unwrap();
+    let manifest = Manifest {
+        creation: 1000,
+        data: "ipfs://asd99a0s8098da0sd98".parse().unwrap(),
+        generation: 0,
+        parent: None,
+        size: MINIMUM_SNAPSHOT_SIZE,
+        size_total: MINIMUM_SNAPSHOT_SIZE,
+        machine: Default::default(),
+        path: std::path::PathBuf::from("abc"),
+    }
+    .sign(&privkey);
+    let snapshot = Snapshot::create(
+        &mut conn,
+        &volume,
+        &manifest.raw,
+        &manifest.signature,
+        &manifest.hash(),
+        None,
+        0,
+        &manifest.manifest.data,
+    )
+    .await
+    .unwrap();
+    async fn compression(conn: &mut AnyConnection) -> Option<String> {
+        query("SELECT snapshot_compression FROM storage_snapshot")
+            .fetch_one(conn)
+            .await
+            .unwrap()
+            .get("snapshot_compression")
+    }
+    let compressed = cfg!(feature = "compress-manifests");
+    assert_eq!(compression(&mut conn).await.is_some(), compressed);
+    assert_eq!(
+        snapshot
+            .fetch(&mut conn)
+            .await
+            .unwrap()
+            .manifest_signed()
+            .raw,
+        manifest.raw
+    );
+
+    // manifests stored by older versions are converted in the background
+    query("UPDATE storage_snapshot SET snapshot_manifest = ?, snapshot_compression = NULL")
+        .bind(&manifest.raw)
+        .execute(&mut conn)
+        .await
+        .unwrap();
+    let converted = Snapshot::compression_backfill(&mut conn, 0).await.unwrap();
+    assert_eq!(converted.is_some(), compressed);
+    if let Some((last, count)) = converted {
+        assert_eq!(count, 1);
+        assert_eq!(
+            Snapshot::compression_backfill(&mut conn, last)
+                .await
+                .unwrap(),
+            None
+        );
+    }
+    assert_eq!(
+        Snapshot::compression_backfill(&mut conn, 0).await.unwrap(),
+        None
+    );
+    assert_eq!(compression(&mut conn).await.is_some(), compressed);
+    assert_eq!(
+        snapshot
+            .fetch(&mut conn)
+            .await
+            .unwrap()
+            .manifest_signed()
+            .raw,
+        manifest.raw
+    );
+
+    query("UPDATE storage_snapshot SET snapshot_compression = 'lz4'")
+        .execute(&mut conn)
+        .await
+        .unwrap();
+    assert!(matches!(
+        snapshot.fetch(&mut conn).await,
+        Err(SnapshotError::ManifestCompression(_))
+    ));
 }
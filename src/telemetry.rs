@@ -0,0 +1,109 @@
+use anyhow::Result;
+use rocket::route::{self, Handler, Route};
+use rocket::{Data, Request};
+use tracing::field::Empty;
+use tracing::{info_span, Instrument};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+use url::Url;
+
+/// Service name reported to the tracing backend.
+#[cfg(feature = "otlp")]
+const SERVICE_NAME: &str = "fractal-storage";
+
+/// Route handler that runs an existing handler in a span, so that the database and IPFS
+/// calls it makes are traced as part of the request.
+#[derive(Clone)]
+struct TraceHandler {
+    handler: Box<dyn Handler>,
+    name: Option<String>,
+}
+
+/// Wrap routes so that requests to them are traced.
+pub fn wrap(routes: Vec<Route>) -> Vec<Route> {
+    routes
+        .into_iter()
+        .map(|mut route| {
+            route.handler = Box::new(TraceHandler {
+                handler: route.handler,
+                name: route.name.as_ref().map(|name| name.to_string()),
+            });
+            route
+        })
+        .collect()
+}
+
+#[rocket::async_trait]
+impl Handler for TraceHandler {
+    async fn handle<'r>(&self, request: &'r Request<'_>, data: Data<'r>) -> route::Outcome<'r> {
+        let span = info_span!(
+            "request",
+            method = %request.method(),
+            uri = %request.uri(),
+            route = self.name.as_deref().unwrap_or_default(),
+            status = Empty,
+        );
+        let outcome = self
+            .handler
+            .handle(request, data)
+            .instrument(span.clone())
+            .await;
+        let status = match &outcome {
+            route::Outcome::Success(response) => response.status().code,
+            route::Outcome::Failure(status) => status.code,
+            route::Outcome::Forward(_) => 404,
+        };
+        span.record("status", &status);
+        outcome
+    }
+}
+
+/// Set up logging and, if an endpoint is supplied, export traces to it using OTLP. Log
+/// records emitted through the `log` crate are captured as well, filtered by `RUST_LOG`.
+pub fn init(otlp: Option<&Url>) -> Result<()> {
+    #[cfg(feature = "otlp")]
+    let otlp = match otlp {
+        Some(endpoint) => Some(tracing_opentelemetry::layer().with_tracer(otlp_tracer(endpoint)?)),
+        None => None,
+    };
+    #[cfg(not(feature = "otlp"))]
+    let otlp = match otlp {
+        Some(_) => anyhow::bail!("Exporting traces requires the otlp feature"),
+        None => None::<tracing_subscriber::layer::Identity>,
+    };
+    tracing_subscriber::registry()
+        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .with(tracing_subscriber::fmt::layer())
+        .with(otlp)
+        .try_init()?;
+    Ok(())
+}
+
+/// Flush pending traces, called on shutdown.
+pub fn shutdown() {
+    #[cfg(feature = "otlp")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+#[cfg(feature = "otlp")]
+fn otlp_tracer(endpoint: &Url) -> Result<opentelemetry::sdk::trace::Tracer> {
+    use opentelemetry::sdk::{trace, Resource};
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::WithExportConfig;
+
+    let exporter = opentelemetry_otlp::new_exporter()
+        .tonic()
+        .with_endpoint(endpoint.as_str());
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(exporter)
+        .with_trace_config(
+            trace::config().with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                SERVICE_NAME,
+            )])),
+        )
+        .install_batch(opentelemetry::runtime::Tokio)?;
+    Ok(tracer)
+}
@@ -1,11 +1,15 @@
+use crate::purge::now;
 use crate::volume::Volume;
 use crate::Options;
 use anyhow::Result;
 use fractal_storage_client::*;
+use optional_field::Field;
 use rand::{thread_rng, Rng};
 use reqwest::Client;
 use reqwest::StatusCode;
+use rocket::futures::{StreamExt, TryStreamExt};
 use sqlx::AnyPool;
+use std::collections::BTreeMap;
 use std::future::Future;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::ops::Range;
@@ -90,6 +94,11 @@ async fn test_volume_edit() {
         .account_set(&mut conn, &new_account)
         .await
         .unwrap();
+    volume
+        .volume()
+        .retention_set(&mut conn, Some(10), Some(86400))
+        .await
+        .unwrap();
 
     let volume = Volume::lookup(&mut conn, &volume_pubkey)
         .await
@@ -98,6 +107,8 @@ async fn test_volume_edit() {
     assert_eq!(volume.account(), &new_account);
     assert_eq!(volume.locked(), true);
     assert_eq!(volume.writer(), Some(&writer));
+    assert_eq!(volume.retain_count(), Some(10));
+    assert_eq!(volume.retain_age(), Some(86400));
 }
 
 #[tokio::test]
@@ -123,7 +134,7 @@ async fn test_snapshot_upload() {
 }
 
 fn options_url(options: &Options) -> Result<Url> {
-    Ok(Url::parse(&format!("http://{}", options.listen))?)
+    Ok(Url::parse(&format!("http://{}", options.listen[0]))?)
 }
 
 async fn wait_up(service: &Url) {
@@ -145,17 +156,64 @@ async fn wait_up_timeout(service: &Url) -> Result<()> {
 
 fn options_default(listen: SocketAddr) -> Options {
     Options {
+        config: None,
         database: "sqlite://:memory:".into(),
         ipfs: None,
+        blob_backend: None,
         jwks: None,
         insecure_auth_stub: true,
-        listen,
+        #[cfg(feature = "chaos")]
+        chaos: None,
+        listen: vec![listen],
+        latency_budget: 30000,
+        payload_limit: 1024 * 1024,
+        route_budget: vec![],
+        manifest_limit: 65536,
+        blob_limit: None,
+        upload_concurrency: 8,
+        db_max_connections: 10,
+        db_acquire_timeout: 30000,
+        db_statement_timeout: 5000,
+        volume_cache_size: 1024,
+        volume_cache_ttl: 30,
+        slow_query: 1000,
+        delete_grace: 604800,
+        purge_interval: 3600,
+        archive_after: None,
+        reconcile_interval: 3600,
+        reconcile_sample: 16,
+        alert_interval: 300,
+        anomaly_factor: 10,
+        replicate_peer: vec![],
+        replicate_token: None,
+        replicate_interval: 10,
+        upload_token_secret: None,
+        otlp_endpoint: None,
+        log_redaction: crate::redact::Redaction::Partial,
+        policy_file: None,
+        require_signed_requests: false,
+        accept_pending: false,
+        pending_limit: 100,
+        cors_origin: vec![],
+        cors_credentials: false,
+        cors_max_age: 3600,
         static_system: vec![],
+        command: None,
         static_user: vec![],
     }
 }
 
 async fn with_service<F>(test: impl FnOnce(Url) -> F) -> Result<()>
+where
+    F: Future<Output = Result<()>>,
+{
+    with_service_options(|_| {}, test).await
+}
+
+async fn with_service_options<F>(
+    configure: impl FnOnce(&mut Options),
+    test: impl FnOnce(Url) -> F,
+) -> Result<()>
 where
     F: Future<Output = Result<()>>,
 {
@@ -166,7 +224,8 @@ where
         .try_init();
     let port = thread_rng().gen_range(PORT_RANGE);
     let listen = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port);
-    let options = options_default(listen);
+    let mut options = options_default(listen);
+    configure(&mut options);
     let url = options_url(&options)?;
     let service = tokio::spawn(async move {
         options.run().await.unwrap();
@@ -188,13 +247,28 @@ async fn can_launch_service() {
 }
 
 #[tokio::test]
-async fn can_volume_create() {
+async fn can_probe_health() {
     with_service(|url| async move {
-        let privkey = Privkey::generate();
         let client = Client::new();
-        let token = Uuid::new_v4();
-        volume_create(&url, &client, &token.to_string(), &privkey).await?;
-        let _info = volume_get(&url, &client, &token.to_string(), &privkey.pubkey()).await?;
+        let response = client.get(url.join("/health/live")?).send().await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // the self-test runs once the service is up
+        let mut report = health_ready(&url, &client).await?;
+        for _ in 0..50 {
+            if report.ready {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            report = health_ready(&url, &client).await?;
+        }
+        assert!(report.ready);
+        let names: Vec<_> = report
+            .checks
+            .iter()
+            .map(|check| check.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["migrations", "database"]);
         Ok(())
     })
     .await
@@ -202,15 +276,37 @@ async fn can_volume_create() {
 }
 
 #[tokio::test]
-async fn can_volume_remove() {
+async fn can_limit_db_connections() {
+    with_service_options(
+        |options| {
+            options.db_max_connections = 1;
+            options.db_acquire_timeout = 1000;
+        },
+        |url| async move {
+            let client = Client::new();
+            let token = Uuid::new_v4().to_string();
+            for _ in 0..3 {
+                let privkey = Privkey::generate();
+                volume_create(&url, &client, &token, &privkey).await?;
+                volume_get(&url, &client, &token, &privkey.pubkey()).await?;
+            }
+            Ok(())
+        },
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_whoami() {
     with_service(|url| async move {
-        let privkey = Privkey::generate();
         let client = Client::new();
-        let token = Uuid::new_v4();
-        volume_create(&url, &client, &token.to_string(), &privkey).await?;
-        volume_remove(&url, &client, &token.to_string(), &privkey).await?;
-        let result = volume_remove(&url, &client, &token.to_string(), &privkey).await;
-        assert!(result.is_err());
+        let account = Uuid::new_v4();
+        let whoami = whoami(&url, &client, &account.to_string()).await?;
+        assert_eq!(whoami.account, account);
+        assert_eq!(whoami.token_type, TokenType::User);
+        assert!(whoami.scopes.is_empty());
+        assert_eq!(whoami.expiry, None);
         Ok(())
     })
     .await
@@ -218,34 +314,97 @@ async fn can_volume_remove() {
 }
 
 #[tokio::test]
-async fn can_snapshot_upload() {
+async fn can_use_storage_client() {
     with_service(|url| async move {
-        let volume = Privkey::generate();
-        let client = Client::new();
-        let token = Uuid::new_v4();
-        let machine = Uuid::new_v4();
-        volume_create(&url, &client, &token.to_string(), &volume).await?;
-        let manifest = Manifest {
-            generation: 0,
-            path: PathBuf::from_str("/tmp/path").unwrap(),
-            creation: 0,
-            machine,
-            size: 10,
-            size_total: 10,
-            parent: None,
-            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
-                .try_into()
-                .unwrap(),
+        assert!(matches!(
+            StorageClient::builder().build(),
+            Err(Error::MissingApi)
+        ));
+
+        let account = Uuid::new_v4();
+        let storage = StorageClient::builder()
+            .api(url.clone())
+            .token(account.to_string())
+            .build()?;
+        storage.health_check().await?;
+        assert_eq!(storage.whoami().await?.account, account);
+
+        let privkey = Privkey::generate();
+        storage.volume_create(&privkey).await?;
+        let info = storage.volume_get(&privkey.pubkey()).await?;
+        assert_eq!(info.account, account);
+        let snapshots = storage
+            .snapshot_list(&privkey.pubkey(), None, false)
+            .await?;
+        assert!(snapshots.is_empty());
+
+        // connections are reused with the pool settings of the builder
+        let pooled = StorageClient::builder()
+            .api(url.clone())
+            .token(account.to_string())
+            .tcp_keepalive(Duration::from_secs(30))
+            .pool_idle_timeout(Some(Duration::from_secs(60)))
+            .pool_max_idle_per_host(2)
+            .build()?;
+        for _ in 0..3 {
+            assert_eq!(pooled.volume_get(&privkey.pubkey()).await?, info);
+        }
+
+        // requests without a token are rejected
+        let anonymous = StorageClient::builder().api(url).build()?;
+        assert!(anonymous.volume_get(&privkey.pubkey()).await.is_err());
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_use_volume_handle() {
+    with_service(|url| async move {
+        let storage = StorageClient::builder()
+            .api(url)
+            .token(Uuid::new_v4().to_string())
+            .build()?;
+        let volume = storage.volume(Privkey::generate());
+        volume.create().await?;
+        assert_eq!(volume.latest().await?, None);
+
+        let mut hashes = vec![];
+        for generation in 0..3 {
+            let manifest = Manifest {
+                generation,
+                creation: generation,
+                path: PathBuf::from_str("/tmp/path").unwrap(),
+                machine: Uuid::nil(),
+                size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                size_total: (generation + 1) * crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                parent: hashes.last().map(|hash: &Hash| Parent::new(*hash)),
+                data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                    .try_into()
+                    .unwrap(),
+            };
+            hashes.push(volume.upload(&manifest).await?.hash);
+        }
+        assert_eq!(volume.snapshots().await?, hashes);
+        let latest = volume.latest().await?.unwrap();
+        assert_eq!(latest.hash(), hashes[2]);
+        assert_eq!(latest.manifest.generation, 2);
+
+        let edit = VolumeEdit {
+            writer: Field::Missing,
+            account: None,
+            lock: None,
+            retain_count: Field::Present(Some(10)),
+            retain_age: Field::Missing,
+            worm_period: Field::Missing,
+            storage_class: None,
+            pq_pubkey: None,
         };
-        let manifest = manifest.sign(&volume);
-        snapshot_upload(
-            &url,
-            &client,
-            &token.to_string(),
-            &volume.pubkey(),
-            &manifest,
-        )
-        .await?;
+        volume.edit(&edit).await?;
+        let info = volume.info().await?;
+        assert_eq!(info.retain_count, Some(10));
+        assert_eq!(info.snapshot_count, 3);
         Ok(())
     })
     .await
@@ -253,18 +412,121 @@ async fn can_snapshot_upload() {
 }
 
 #[tokio::test]
-async fn can_snapshot_upload_twice() {
+async fn can_time_out_requests() {
+    // server that accepts connections, but never responds
+    let listener = rocket::tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap();
+    let url = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+    let stalled = tokio::spawn(async move {
+        let mut connections = vec![];
+        while let Ok((connection, _)) = listener.accept().await {
+            connections.push(connection);
+        }
+    });
+
+    let timeout = Duration::from_millis(200);
+    let storage = StorageClient::builder()
+        .api(url.clone())
+        .timeout(timeout)
+        .connect_timeout(timeout)
+        .build()
+        .unwrap();
+    let result = storage.health_check().await;
+    assert!(matches!(result, Err(Error::Timeout(_))));
+
+    let timeouts = Timeouts {
+        request: Some(timeout),
+        connect: None,
+    };
+    let client = with_timeouts(reqwest::ClientBuilder::new(), &timeouts)
+        .build()
+        .unwrap();
+    let result = capabilities(&url, &client).await;
+    assert!(matches!(result, Err(Error::Timeout(_))));
+    stalled.abort();
+}
+
+#[tokio::test]
+async fn can_use_api_keys() {
     with_service(|url| async move {
+        let client = Client::new();
+        let account = Uuid::new_v4();
+        let token = account.to_string();
+
+        // full access key acts as the account
+        let created = api_key_create(&url, &client, &token, &ApiKeyCreate::default()).await?;
+        let whoami = whoami(&url, &client, &created.key).await?;
+        assert_eq!(whoami.account, account);
+        assert_eq!(whoami.token_type, TokenType::ApiKey);
         let volume = Privkey::generate();
+        volume_create(&url, &client, &created.key, &volume).await?;
+
+        // read-only key bound to the volume
+        let request = ApiKeyCreate {
+            name: Some("restore".into()),
+            scope: Some(ApiKeyScope::Read),
+            volume: Some(volume.pubkey()),
+            ttl: Some(3600),
+        };
+        let read = api_key_create(&url, &client, &created.key, &request).await?;
+        assert_eq!(read.info.volume, Some(volume.pubkey()));
+        let overflowing = ApiKeyCreate {
+            ttl: Some(u64::MAX),
+            ..Default::default()
+        };
+        let result = api_key_create(&url, &client, &token, &overflowing).await;
+        assert!(matches!(
+            result,
+            Err(Error::Unsuccessful(StatusCode::BAD_REQUEST))
+        ));
+        volume_get(&url, &client, &read.key, &volume.pubkey()).await?;
+        let result = volume_get(&url, &client, &read.key, &Privkey::generate().pubkey()).await;
+        assert!(matches!(
+            result,
+            Err(Error::Unsuccessful(StatusCode::FORBIDDEN))
+        ));
+        let result = volume_remove(&url, &client, &read.key, &volume).await;
+        assert!(matches!(
+            result,
+            Err(Error::Unsuccessful(StatusCode::FORBIDDEN))
+        ));
+        let result = api_key_list(&url, &client, &read.key).await;
+        assert!(matches!(
+            result,
+            Err(Error::Unsuccessful(StatusCode::FORBIDDEN))
+        ));
+
+        // keys are listed and can be revoked
+        let keys = api_key_list(&url, &client, &token).await?;
+        assert_eq!(keys, vec![created.info.clone(), read.info.clone()]);
+        api_key_revoke(&url, &client, &token, read.info.id).await?;
+        let result = volume_get(&url, &client, &read.key, &volume.pubkey()).await;
+        assert!(matches!(
+            result,
+            Err(Error::Unsuccessful(StatusCode::UNAUTHORIZED))
+        ));
+        assert!(api_key_revoke(&url, &client, &token, read.info.id)
+            .await
+            .is_err());
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_fetch_presigned() {
+    with_service(|url| async move {
         let client = Client::new();
-        let token = Uuid::new_v4();
-        let machine = Uuid::new_v4();
-        volume_create(&url, &client, &token.to_string(), &volume).await?;
+        let token = Uuid::new_v4().to_string();
+        let volume = Privkey::generate();
+        volume_create(&url, &client, &token, &volume).await?;
         let manifest = Manifest {
             generation: 0,
             path: PathBuf::from_str("/tmp/path").unwrap(),
             creation: 0,
-            machine,
+            machine: Uuid::new_v4(),
             size: 10,
             size_total: 10,
             parent: None,
@@ -273,23 +535,47 @@ async fn can_snapshot_upload_twice() {
                 .unwrap(),
         };
         let manifest = manifest.sign(&volume);
-        snapshot_upload(
+        snapshot_upload(&url, &client, &token, &volume.pubkey(), &manifest).await?;
+
+        // manifests cannot be fetched without a token or pre-signed URL
+        let path = format!(
+            "/api/v1/volume/{}/{}",
+            volume.pubkey().to_hex(),
+            manifest.hash().to_hex()
+        );
+        let response = client.get(url.join(&path)?).send().await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let presigned = snapshot_presign(
             &url,
             &client,
-            &token.to_string(),
+            &token,
             &volume.pubkey(),
-            &manifest,
+            &manifest.hash(),
+            Some("10m"),
         )
         .await?;
-        // make sure we can upload the same snapshot twice without error
-        snapshot_upload(
+        let fetched = snapshot_fetch_presigned(&client, &presigned).await?;
+        assert_eq!(fetched.hash(), manifest.hash());
+
+        // altered URLs are rejected, and only the owner can mint them
+        let altered = presigned.as_str().replace("expires=", "expires=1");
+        let result = snapshot_fetch_presigned(&client, &Url::parse(&altered)?).await;
+        assert!(matches!(
+            result,
+            Err(Error::Unsuccessful(StatusCode::UNAUTHORIZED))
+        ));
+        let other = Uuid::new_v4().to_string();
+        let result = snapshot_presign(
             &url,
             &client,
-            &token.to_string(),
+            &other,
             &volume.pubkey(),
-            &manifest,
+            &manifest.hash(),
+            None,
         )
-        .await?;
+        .await;
+        assert!(matches!(result, Err(Error::VolumeNotFound(_))));
         Ok(())
     })
     .await
@@ -297,57 +583,68 @@ async fn can_snapshot_upload_twice() {
 }
 
 #[tokio::test]
-async fn can_snapshot_upload_reject_writer() {
+async fn can_use_upload_tokens() {
     with_service(|url| async move {
-        let volume = Privkey::generate();
         let client = Client::new();
-        let token = Uuid::new_v4();
-        let machine = Uuid::new_v4();
-        volume_create(&url, &client, &token.to_string(), &volume).await?;
-        let manifest = Manifest {
-            generation: 0,
-            path: PathBuf::from_str("/tmp/path").unwrap(),
-            creation: 0,
-            machine,
-            size: 10,
-            size_total: 10,
-            parent: None,
-            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
-                .try_into()
-                .unwrap(),
-        };
-        let manifest = manifest.sign(&volume);
-        snapshot_upload(
+        let token = Uuid::new_v4().to_string();
+        let volume = Privkey::generate();
+        volume_create(&url, &client, &token, &volume).await?;
+
+        // only the owner can mint upload tokens
+        let result = upload_token_create(
             &url,
             &client,
-            &token.to_string(),
+            &Uuid::new_v4().to_string(),
             &volume.pubkey(),
-            &manifest,
+            None,
         )
-        .await?;
-        let new_machine = Uuid::new_v4();
+        .await;
+        assert!(matches!(result, Err(Error::VolumeNotFound(_))));
+        let result =
+            upload_token_create(&url, &client, &token, &volume.pubkey(), Some("30d")).await;
+        assert!(matches!(
+            result,
+            Err(Error::Unsuccessful(StatusCode::BAD_REQUEST))
+        ));
+        let upload =
+            upload_token_create(&url, &client, &token, &volume.pubkey(), Some("10m")).await?;
+        assert_eq!(upload.volume, volume.pubkey());
+        let whoami = whoami(&url, &client, &upload.token).await?;
+        assert_eq!(whoami.token_type, TokenType::Upload);
+        assert_eq!(whoami.expiry, Some(upload.expires));
+
+        // upload token can upload snapshots, but nothing else
         let manifest = Manifest {
-            generation: 1,
+            generation: 0,
             path: PathBuf::from_str("/tmp/path").unwrap(),
             creation: 0,
-            machine: new_machine,
+            machine: Uuid::new_v4(),
             size: 10,
-            size_total: 20,
+            size_total: 10,
             parent: None,
             data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
                 .try_into()
                 .unwrap(),
         };
         let manifest = manifest.sign(&volume);
-        let result = snapshot_upload(
-            &url,
-            &client,
-            &token.to_string(),
-            &volume.pubkey(),
-            &manifest,
-        )
-        .await;
-        assert!(result.is_err());
+        snapshot_upload(&url, &client, &upload.token, &volume.pubkey(), &manifest).await?;
+        let result =
+            snapshot_list(&url, &client, &upload.token, &volume.pubkey(), None, false).await;
+        assert!(matches!(
+            result,
+            Err(Error::Unsuccessful(StatusCode::FORBIDDEN))
+        ));
+        let result = volume_remove(&url, &client, &upload.token, &volume).await;
+        assert!(matches!(
+            result,
+            Err(Error::Unsuccessful(StatusCode::FORBIDDEN))
+        ));
+        let result =
+            snapshot_upload(&url, &client, "fsu_forged", &volume.pubkey(), &manifest).await;
+        assert!(matches!(
+            result,
+            Err(Error::Unsuccessful(StatusCode::UNAUTHORIZED))
+        ));
         Ok(())
     })
     .await
@@ -355,18 +652,30 @@ async fn can_snapshot_upload_reject_writer() {
 }
 
 #[tokio::test]
-async fn can_snapshot_fetch() {
+async fn can_stamp_account_labels() {
     with_service(|url| async move {
-        let volume = Privkey::generate();
         let client = Client::new();
-        let token = Uuid::new_v4();
-        let machine = Uuid::new_v4();
-        volume_create(&url, &client, &token.to_string(), &volume).await?;
+        let token = Uuid::new_v4().to_string();
+        let volume = Privkey::generate();
+        volume_create(&url, &client, &token, &volume).await?;
+        let labels: BTreeMap<String, String> = [("region", "eu"), ("tier", "paid")]
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        account_labels_set(&url, &client, &token, &labels).await?;
+        assert_eq!(account_labels(&url, &client, &token).await?, labels);
+        let invalid = [("Region".to_string(), "eu".to_string())].into();
+        let result = account_labels_set(&url, &client, &token, &invalid).await;
+        assert!(matches!(
+            result,
+            Err(Error::Unsuccessful(StatusCode::BAD_REQUEST))
+        ));
+
         let manifest = Manifest {
             generation: 0,
-            creation: 0,
             path: PathBuf::from_str("/tmp/path").unwrap(),
-            machine,
+            creation: 0,
+            machine: Uuid::new_v4(),
             size: 10,
             size_total: 10,
             parent: None,
@@ -375,23 +684,23 @@ async fn can_snapshot_fetch() {
                 .unwrap(),
         };
         let manifest = manifest.sign(&volume);
-        snapshot_upload(
-            &url,
-            &client,
-            &token.to_string(),
-            &volume.pubkey(),
-            &manifest,
-        )
-        .await?;
-        let signed_manifest = snapshot_fetch(
-            &url,
-            &client,
-            &token.to_string(),
-            &volume.pubkey(),
-            &manifest.hash(),
-        )
-        .await?;
-        assert_eq!(manifest, signed_manifest);
+        snapshot_upload(&url, &client, &token, &volume.pubkey(), &manifest).await?;
+
+        // uploaded snapshot is found by its labels, but not by other accounts
+        let region = [("region".to_string(), "eu".to_string())].into();
+        let found = snapshot_search(&url, &client, &token, &region).await?;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].hash, manifest.hash());
+        assert_eq!(found[0].volume, volume.pubkey());
+        assert_eq!(found[0].labels, labels);
+        let other = Uuid::new_v4().to_string();
+        assert!(snapshot_search(&url, &client, &other, &region)
+            .await?
+            .is_empty());
+        let region = [("region".to_string(), "us".to_string())].into();
+        assert!(snapshot_search(&url, &client, &token, &region)
+            .await?
+            .is_empty());
         Ok(())
     })
     .await
@@ -399,39 +708,50 @@ async fn can_snapshot_fetch() {
 }
 
 #[tokio::test]
-async fn can_snapshot_fetch_missing() {
+async fn can_find_duplicate_data() {
     with_service(|url| async move {
         let client = Client::new();
-        let token = Uuid::new_v4();
-        let volume = Privkey::generate();
-        let snapshot = Hash::generate(&[]);
-        let result = snapshot_fetch(
-            &url,
-            &client,
-            &token.to_string(),
-            &volume.pubkey(),
-            &snapshot,
-        )
-        .await;
-        assert!(matches!(
-            result,
-            Err(Error::Unsuccessful(StatusCode::NOT_FOUND))
-        ));
+        let token = Uuid::new_v4().to_string();
+        let data: Url = "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+            .try_into()
+            .unwrap();
+        let mut hashes = vec![];
+        for _ in 0..2 {
+            let volume = Privkey::generate();
+            volume_create(&url, &client, &token, &volume).await?;
+            let manifest = Manifest {
+                generation: 0,
+                path: PathBuf::from_str("/tmp/path").unwrap(),
+                creation: 0,
+                machine: Uuid::new_v4(),
+                size: 10,
+                size_total: 10,
+                parent: None,
+                data: data.clone(),
+            };
+            let manifest = manifest.sign(&volume);
+            snapshot_upload(&url, &client, &token, &volume.pubkey(), &manifest).await?;
+            hashes.push(manifest.hash());
+        }
 
-        volume_create(&url, &client, &token.to_string(), &volume).await?;
-        let result = snapshot_fetch(
-            &url,
-            &client,
-            &token.to_string(),
-            &volume.pubkey(),
-            &snapshot,
-        )
-        .await;
-        assert!(matches!(
-            result,
-            Err(Error::Unsuccessful(StatusCode::NOT_FOUND))
-        ));
+        // payload is referenced from both volumes, but not by other accounts
+        let duplicates = snapshot_duplicates(&url, &client, &token, None).await?;
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].data, data);
+        let found: Vec<_> = duplicates[0].snapshots.iter().map(|s| s.hash).collect();
+        assert_eq!(found, hashes);
+        let other = Uuid::new_v4().to_string();
+        assert!(snapshot_duplicates(&url, &client, &other, None)
+            .await?
+            .is_empty());
 
+        // payloads can be looked up before uploading them
+        let found = snapshot_duplicates(&url, &client, &token, Some(&data)).await?;
+        assert_eq!(found, duplicates);
+        let missing: Url = "ipfs://QmNotStored".try_into().unwrap();
+        assert!(snapshot_duplicates(&url, &client, &token, Some(&missing))
+            .await?
+            .is_empty());
         Ok(())
     })
     .await
@@ -439,40 +759,62 @@ async fn can_snapshot_fetch_missing() {
 }
 
 #[tokio::test]
-async fn can_snapshot_list_empty() {
-    with_service(|url| async move {
-        let client = Client::new();
-        let token = Uuid::new_v4();
-        let volume = Privkey::generate();
+async fn can_apply_policy() {
+    let policy = std::env::temp_dir().join(format!("{}.toml", Uuid::new_v4()));
+    std::fs::write(
+        &policy,
+        "[[rule]]\neffect = \"deny\"\noperations = [\"write\"]\n",
+    )
+    .unwrap();
+    with_service_options(
+        |options| options.policy_file = Some(policy),
+        |url| async move {
+            let client = Client::new();
+            let token = Uuid::new_v4().to_string();
+            account_labels(&url, &client, &token).await?;
+            let result = volume_create(&url, &client, &token, &Privkey::generate()).await;
+            assert!(matches!(
+                result,
+                Err(Error::Unsuccessful(StatusCode::FORBIDDEN))
+            ));
+            Ok(())
+        },
+    )
+    .await
+    .unwrap();
+}
 
-        // Listing snapshots on an nonexistant volume should return 404
-        let result = snapshot_list(
-            &url,
-            &client,
-            &token.to_string(),
-            &volume.pubkey(),
-            None,
-            false,
-        )
-        .await;
-        assert!(matches!(
-            result,
-            Err(Error::Unsuccessful(StatusCode::NOT_FOUND))
-        ));
+#[tokio::test]
+async fn can_volume_create() {
+    with_service(|url| async move {
+        let privkey = Privkey::generate();
+        let client = Client::new();
+        let token = Uuid::new_v4();
+        volume_create(&url, &client, &token.to_string(), &privkey).await?;
+        let _info = volume_get(&url, &client, &token.to_string(), &privkey.pubkey()).await?;
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
 
-        // Listing snapshots on an empty volume should return an empty list.
-        volume_create(&url, &client, &token.to_string(), &volume).await?;
-        let result = snapshot_list(
-            &url,
-            &client,
-            &token.to_string(),
-            &volume.pubkey(),
-            None,
-            false,
-        )
-        .await?;
-        assert_eq!(result, vec![]);
+#[tokio::test]
+async fn can_volume_remove() {
+    with_service(|url| async move {
+        let privkey = Privkey::generate();
+        let client = Client::new();
+        let token = Uuid::new_v4();
+        volume_create(&url, &client, &token.to_string(), &privkey).await?;
+
+        // other accounts cannot remove the volume
+        let other = Uuid::new_v4().to_string();
+        let result = volume_remove(&url, &client, &other, &privkey).await;
+        assert!(matches!(result, Err(Error::VolumeNotFound(_))));
+        volume_get(&url, &client, &token.to_string(), &privkey.pubkey()).await?;
 
+        volume_remove(&url, &client, &token.to_string(), &privkey).await?;
+        let result = volume_remove(&url, &client, &token.to_string(), &privkey).await;
+        assert!(result.is_err());
         Ok(())
     })
     .await
@@ -480,32 +822,339 @@ async fn can_snapshot_list_empty() {
 }
 
 #[tokio::test]
-async fn can_snapshot_list_root() {
+async fn can_require_signed_requests() {
+    with_service_options(
+        |options| options.require_signed_requests = true,
+        |url| async move {
+            let privkey = Privkey::generate();
+            let client = Client::new();
+            let token = Uuid::new_v4().to_string();
+            volume_create(&url, &client, &token, &privkey).await?;
+            let volume = url.join(&format!("/api/v1/volume/{}", privkey.pubkey()))?;
+
+            // unsigned and wrongly signed requests are rejected
+            let response = client
+                .delete(volume.clone())
+                .header("Authorization", format!("Bearer {token}"))
+                .send()
+                .await?;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+            let signature =
+                RequestSignature::sign(&Privkey::generate(), "DELETE", volume.path(), &[], now());
+            let response = client
+                .delete(volume.clone())
+                .header("Authorization", format!("Bearer {token}"))
+                .header(SIGNATURE_HEADER, signature.to_string())
+                .send()
+                .await?;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+            // signatures cover the body
+            let signature = RequestSignature::sign(&privkey, "PATCH", volume.path(), b"{}", now());
+            let altered = r#"{"locked":true}"#;
+            for digest in [
+                RequestSignature::digest(b"{}"),
+                RequestSignature::digest(altered.as_bytes()),
+            ] {
+                let response = client
+                    .patch(volume.clone())
+                    .header("Authorization", format!("Bearer {token}"))
+                    .header("Content-Type", "application/json")
+                    .header(SIGNATURE_HEADER, signature.to_string())
+                    .header(DIGEST_HEADER, digest)
+                    .body(altered)
+                    .send()
+                    .await?;
+                assert_eq!(response.status(), StatusCode::FORBIDDEN);
+            }
+
+            // signed requests cannot be replayed
+            let signature = RequestSignature::sign(&privkey, "PATCH", volume.path(), b"{}", now());
+            for status in [StatusCode::OK, StatusCode::FORBIDDEN] {
+                let response = client
+                    .patch(volume.clone())
+                    .header("Authorization", format!("Bearer {token}"))
+                    .header("Content-Type", "application/json")
+                    .header(SIGNATURE_HEADER, signature.to_string())
+                    .header(DIGEST_HEADER, RequestSignature::digest(b"{}"))
+                    .body("{}")
+                    .send()
+                    .await?;
+                assert_eq!(response.status(), status);
+            }
+            volume_remove(&url, &client, &token, &privkey).await?;
+            Ok(())
+        },
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_require_volume_challenge() {
     with_service(|url| async move {
+        let privkey = Privkey::generate();
         let client = Client::new();
-        let token = Uuid::new_v4();
-        let machine = Uuid::new_v4();
-        let volume = Privkey::generate();
+        let token = Uuid::new_v4().to_string();
+        volume_create(&url, &client, &token, &privkey).await?;
+        let volume = url.join(&format!("/api/v1/volume/{}", privkey.pubkey()))?;
 
-        // Listing snapshots on an empty volume should return an empty list.
-        volume_create(&url, &client, &token.to_string(), &volume).await?;
-        let result = snapshot_list(
-            &url,
-            &client,
-            &token.to_string(),
-            &volume.pubkey(),
-            None,
-            false,
+        // bearer token alone is not enough to delete a volume
+        let response = client
+            .delete(volume.clone())
+            .header("Authorization", format!("Bearer {token}"))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // challenges are only issued to the owner
+        let other = Uuid::new_v4().to_string();
+        assert!(volume_challenge(&url, &client, &other, &privkey.pubkey())
+            .await
+            .is_err());
+
+        // challenge must be signed with the volume key, and can only be used once
+        let challenge = volume_challenge(&url, &client, &token, &privkey.pubkey()).await?;
+        let response = client
+            .delete(volume.clone())
+            .header("Authorization", format!("Bearer {token}"))
+            .header(PROOF_HEADER, challenge.proof(&Privkey::generate()))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        for status in [StatusCode::OK, StatusCode::NOT_FOUND] {
+            let response = client
+                .delete(volume.clone())
+                .header("Authorization", format!("Bearer {token}"))
+                .header(PROOF_HEADER, challenge.proof(&privkey))
+                .send()
+                .await?;
+            assert_eq!(response.status(), status);
+        }
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_replicate_to_peer() {
+    with_service(|peer| async move {
+        let replicate_token = Uuid::new_v4().to_string();
+        let peer_token = replicate_token.clone();
+        let peer_url = peer.clone();
+        with_service_options(
+            |options| {
+                options.replicate_peer = vec![peer_url];
+                options.replicate_token = Some(replicate_token);
+                options.replicate_interval = 1;
+            },
+            |url| async move {
+                let privkey = Privkey::generate();
+                let client = Client::new();
+                let token = Uuid::new_v4().to_string();
+                volume_create(&url, &client, &token, &privkey).await?;
+                let manifest = Manifest {
+                    generation: 0,
+                    creation: 0,
+                    path: PathBuf::from_str("/tmp/path").unwrap(),
+                    machine: Uuid::new_v4(),
+                    size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                    size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                    parent: None,
+                    data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                        .try_into()
+                        .unwrap(),
+                }
+                .sign(&privkey);
+                snapshot_upload(&url, &client, &token, &privkey.pubkey(), &manifest).await?;
+
+                // snapshot is pushed to the peer, into a volume owned by the replication token
+                let mut status = vec![];
+                for _ in 0..50 {
+                    status = volume_replication(&url, &client, &token, &privkey.pubkey()).await?;
+                    if status.iter().all(|status| status.pending == 0) {
+                        break;
+                    }
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+                let hash = manifest.hash();
+                let replicated =
+                    snapshot_exists(&peer, &client, &peer_token, &privkey.pubkey(), &[hash])
+                        .await?;
+                assert_eq!(replicated, vec![hash]);
+                let info = volume_get(&peer, &client, &peer_token, &privkey.pubkey()).await?;
+                assert_eq!(info.account.to_string(), peer_token);
+
+                assert_eq!(status.len(), 1);
+                assert_eq!(status[0].peer, peer);
+                assert_eq!(status[0].replicated, 1);
+                assert_eq!(status[0].pending, 0);
+                assert!(status[0].last_replicated.is_some());
+                assert_eq!(status[0].error, None);
+
+                // status is only visible to the owner
+                let other = Uuid::new_v4().to_string();
+                assert!(volume_replication(&url, &client, &other, &privkey.pubkey())
+                    .await
+                    .is_err());
+                Ok(())
+            },
         )
-        .await?;
-        assert_eq!(result, vec![]);
+        .await
+    })
+    .await
+    .unwrap();
+}
 
-        // upload a single snapshot with no parent (root snapshot)
+#[tokio::test]
+async fn can_export_import_volume() {
+    with_service(|target| async move {
+        with_service(|url| async move {
+            let volume = Privkey::generate();
+            let client = Client::new();
+            let token = Uuid::new_v4().to_string();
+            let machine = Uuid::new_v4();
+            volume_create(&url, &client, &token, &volume).await?;
+            let parent = Manifest {
+                generation: 0,
+                creation: 0,
+                path: PathBuf::from_str("/tmp/path").unwrap(),
+                machine,
+                size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                parent: None,
+                data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                    .try_into()
+                    .unwrap(),
+            }
+            .sign(&volume);
+            let child = Manifest {
+                generation: 1,
+                creation: 0,
+                path: PathBuf::from_str("/tmp/path").unwrap(),
+                machine,
+                size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                size_total: 2 * crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                parent: Some(Parent::new(parent.hash())),
+                data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                    .try_into()
+                    .unwrap(),
+            }
+            .sign(&volume);
+            snapshot_upload(&url, &client, &token, &volume.pubkey(), &parent).await?;
+            snapshot_upload(&url, &client, &token, &volume.pubkey(), &child).await?;
+            let edit = VolumeEdit {
+                writer: Field::Missing,
+                account: None,
+                lock: None,
+                retain_count: Field::Present(Some(10)),
+                retain_age: Field::Missing,
+                worm_period: Field::Missing,
+                storage_class: None,
+                pq_pubkey: None,
+            };
+            volume_edit(&url, &client, &token, &volume, &edit).await?;
+
+            let archive = volume_export(&url, &client, &token, &volume.pubkey()).await?;
+            assert_eq!(archive.volume, volume.pubkey());
+            assert_eq!(archive.writer, Some(machine));
+            assert_eq!(archive.retain_count, Some(10));
+            assert_eq!(archive.snapshots, vec![parent.clone(), child.clone()]);
+
+            // only the owner can export the volume
+            let other = Uuid::new_v4().to_string();
+            assert!(volume_export(&url, &client, &other, &volume.pubkey())
+                .await
+                .is_err());
+
+            // archive recreates the volume on another instance
+            volume_import(&target, &client, &token, &archive).await?;
+            let imported = volume_export(&target, &client, &token, &volume.pubkey()).await?;
+            assert_eq!(imported, archive);
+            let result = volume_import(&target, &client, &token, &archive).await;
+            assert!(matches!(
+                result,
+                Err(Error::Unsuccessful(StatusCode::CONFLICT))
+            ));
+
+            // archives with invalid manifests are not imported at all
+            let other = Privkey::generate();
+            let broken = VolumeArchive {
+                volume: other.pubkey(),
+                snapshots: vec![parent.clone()],
+                ..archive.clone()
+            };
+            assert!(volume_import(&target, &client, &token, &broken)
+                .await
+                .is_err());
+            assert!(volume_get(&target, &client, &token, &other.pubkey())
+                .await
+                .is_err());
+            Ok(())
+        })
+        .await
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_volume_restore() {
+    with_service(|url| async move {
+        let privkey = Privkey::generate();
+        let client = Client::new();
+        let token = Uuid::new_v4().to_string();
+        volume_create(&url, &client, &token, &privkey).await?;
+        volume_remove(&url, &client, &token, &privkey).await?;
+
+        // deleted volume is hidden and cannot be recreated
+        assert!(volume_get(&url, &client, &token, &privkey.pubkey())
+            .await
+            .is_err());
+        assert!(volume_create(&url, &client, &token, &privkey)
+            .await
+            .is_err());
+
+        // only the owner can restore it
+        let other = Uuid::new_v4().to_string();
+        assert!(volume_restore(&url, &client, &other, &privkey.pubkey())
+            .await
+            .is_err());
+        volume_restore(&url, &client, &token, &privkey.pubkey()).await?;
+        volume_get(&url, &client, &token, &privkey.pubkey()).await?;
+        assert!(volume_restore(&url, &client, &token, &privkey.pubkey())
+            .await
+            .is_err());
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_enforce_worm() {
+    with_service(|url| async move {
+        let volume = Privkey::generate();
+        let client = Client::new();
+        let token = Uuid::new_v4().to_string();
+        volume_create(&url, &client, &token, &volume).await?;
+        let mut edit = VolumeEdit {
+            writer: Field::Missing,
+            account: None,
+            lock: None,
+            retain_count: Field::Missing,
+            retain_age: Field::Missing,
+            worm_period: Field::Present(Some(3600)),
+            storage_class: None,
+            pq_pubkey: None,
+        };
+        volume_edit(&url, &client, &token, &volume, &edit).await?;
         let manifest = Manifest {
             generation: 0,
-            creation: 0,
             path: PathBuf::from_str("/tmp/path").unwrap(),
-            machine,
+            creation: 0,
+            machine: Uuid::new_v4(),
             size: 10,
             size_total: 10,
             parent: None,
@@ -513,40 +1162,225 @@ async fn can_snapshot_list_root() {
                 .try_into()
                 .unwrap(),
         };
-        let manifest = manifest.sign(&volume);
         snapshot_upload(
             &url,
             &client,
-            &token.to_string(),
+            &token,
             &volume.pubkey(),
-            &manifest,
+            &manifest.sign(&volume),
         )
         .await?;
+        let info = volume_get(&url, &client, &token, &volume.pubkey()).await?;
+        assert_eq!(info.worm_period, Some(3600));
+        assert!(info.immutable_until.unwrap() > crate::purge::now());
 
-        // listing with no options should return this snapshot
-        let result = snapshot_list(
-            &url,
-            &client,
-            &token.to_string(),
-            &volume.pubkey(),
-            None,
-            false,
-        )
-        .await?;
-        assert_eq!(result, vec![manifest.hash()]);
+        // the period cannot be shortened or removed, and the volume cannot be deleted
+        for period in [Some(60), None] {
+            edit.worm_period = Field::Present(period);
+            let result = volume_edit(&url, &client, &token, &volume, &edit).await;
+            assert!(matches!(
+                result,
+                Err(Error::Unsuccessful(StatusCode::FORBIDDEN))
+            ));
+        }
+        let result = volume_remove(&url, &client, &token, &volume).await;
+        assert!(matches!(result, Err(Error::Locked(_))));
+        edit.worm_period = Field::Present(Some(7200));
+        volume_edit(&url, &client, &token, &volume, &edit).await?;
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
 
-        // listing with root set to true should return this snapshot
-        let result = snapshot_list(
+#[tokio::test]
+async fn can_set_storage_class() {
+    with_service(|url| async move {
+        let volume = Privkey::generate();
+        let client = Client::new();
+        let token = Uuid::new_v4().to_string();
+        volume_create(&url, &client, &token, &volume).await?;
+        let info = volume_get(&url, &client, &token, &volume.pubkey()).await?;
+        assert_eq!(info.storage_class, StorageClass::Hot);
+
+        let mut hashes = vec![];
+        for generation in 0..2 {
+            // snapshots record the storage class of the volume at the time of the upload
+            if generation == 1 {
+                let edit = VolumeEdit {
+                    writer: Field::Missing,
+                    account: None,
+                    lock: None,
+                    retain_count: Field::Missing,
+                    retain_age: Field::Missing,
+                    worm_period: Field::Missing,
+                    storage_class: Some(StorageClass::Archive),
+                    pq_pubkey: None,
+                };
+                volume_edit(&url, &client, &token, &volume, &edit).await?;
+            }
+            let manifest = Manifest {
+                generation,
+                creation: generation,
+                path: PathBuf::from_str("/tmp/path").unwrap(),
+                machine: Uuid::nil(),
+                size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                size_total: (generation + 1) * crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                parent: hashes.last().map(|hash: &Hash| Parent::new(*hash)),
+                data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                    .try_into()
+                    .unwrap(),
+            };
+            let manifest = manifest.sign(&volume);
+            snapshot_upload(&url, &client, &token, &volume.pubkey(), &manifest).await?;
+            hashes.push(manifest.hash());
+        }
+
+        let info = volume_get(&url, &client, &token, &volume.pubkey()).await?;
+        assert_eq!(info.storage_class, StorageClass::Archive);
+        let page =
+            snapshot_list_v2(&url, &client, &token, &volume.pubkey(), &Default::default()).await?;
+        let classes: Vec<_> = page
+            .snapshots
+            .iter()
+            .map(|record| (record.hash, record.storage_class))
+            .collect();
+        assert_eq!(
+            classes,
+            vec![
+                (hashes[0], StorageClass::Hot),
+                (hashes[1], StorageClass::Archive)
+            ]
+        );
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_upload_snapshots_out_of_order() {
+    with_service_options(
+        |options| {
+            options.accept_pending = true;
+            options.pending_limit = 2;
+        },
+        |url| async move {
+            let volume = Privkey::generate();
+            let client = Client::new();
+            let token = Uuid::new_v4().to_string();
+            volume_create(&url, &client, &token, &volume).await?;
+            let mut manifests: Vec<ManifestSigned> = vec![];
+            for generation in 0..3 {
+                let manifest = Manifest {
+                    generation,
+                    creation: generation,
+                    path: PathBuf::from_str("/tmp/path").unwrap(),
+                    machine: Uuid::nil(),
+                    size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                    size_total: (generation + 1) * crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                    parent: manifests.last().map(|parent| Parent::new(parent.hash())),
+                    data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                        .try_into()
+                        .unwrap(),
+                };
+                manifests.push(manifest.sign(&volume));
+            }
+
+            // children are kept aside until their parent arrives
+            let uploaded =
+                snapshot_upload(&url, &client, &token, &volume.pubkey(), &manifests[2]).await?;
+            assert!(uploaded.pending);
+            let results =
+                snapshot_upload_batch(&url, &client, &token, &volume.pubkey(), &manifests[1..2])
+                    .await?;
+            assert_eq!(results[0].status, SnapshotUploadStatus::Pending);
+            let pending = snapshot_pending_list(&url, &client, &token, &volume.pubkey()).await?;
+            assert_eq!(pending.len(), 2);
+            assert_eq!(pending[0].hash, manifests[1].hash());
+            assert_eq!(pending[0].parent, manifests[0].hash());
+            let info = volume_get(&url, &client, &token, &volume.pubkey()).await?;
+            assert_eq!(info.snapshots_pending, 2);
+            assert_eq!(info.snapshot_count, 0);
+
+            // volumes can only have a limited number of snapshots waiting
+            let orphan = Manifest {
+                generation: 5,
+                parent: Some(Parent::new(Hash::generate(b"orphan"))),
+                ..manifests[2].manifest.clone()
+            }
+            .sign(&volume);
+            let result = snapshot_upload(&url, &client, &token, &volume.pubkey(), &orphan).await;
+            assert!(matches!(result, Err(Error::QuotaExceeded(_))));
+            let results =
+                snapshot_upload_batch(&url, &client, &token, &volume.pubkey(), &[orphan]).await?;
+            assert!(matches!(
+                results[0].status,
+                SnapshotUploadStatus::Failed { .. }
+            ));
+            // replacing a snapshot that is already waiting is still allowed
+            let uploaded =
+                snapshot_upload(&url, &client, &token, &volume.pubkey(), &manifests[2]).await?;
+            assert!(uploaded.pending);
+
+            // uploading the root links the whole chain
+            let uploaded =
+                snapshot_upload(&url, &client, &token, &volume.pubkey(), &manifests[0]).await?;
+            assert!(!uploaded.pending);
+            let hashes =
+                snapshot_list(&url, &client, &token, &volume.pubkey(), None, false).await?;
+            assert_eq!(
+                hashes,
+                manifests
+                    .iter()
+                    .map(|manifest| manifest.hash())
+                    .collect::<Vec<_>>()
+            );
+            assert!(
+                snapshot_pending_list(&url, &client, &token, &volume.pubkey())
+                    .await?
+                    .is_empty()
+            );
+            Ok(())
+        },
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn cannot_upload_snapshots_out_of_order_by_default() {
+    with_service(|url| async move {
+        let volume = Privkey::generate();
+        let client = Client::new();
+        let token = Uuid::new_v4().to_string();
+        volume_create(&url, &client, &token, &volume).await?;
+        let manifest = Manifest {
+            generation: 1,
+            creation: 1,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            machine: Uuid::nil(),
+            size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            size_total: 2 * crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            parent: Some(Parent::new(Hash::generate(b"parent"))),
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        };
+        let result = snapshot_upload(
             &url,
             &client,
-            &token.to_string(),
+            &token,
             &volume.pubkey(),
-            None,
-            true,
+            &manifest.sign(&volume),
         )
-        .await?;
-        assert_eq!(result, vec![manifest.hash()]);
-
+        .await;
+        assert!(result.is_err());
+        assert!(
+            snapshot_pending_list(&url, &client, &token, &volume.pubkey())
+                .await?
+                .is_empty()
+        );
         Ok(())
     })
     .await
@@ -554,41 +1388,49 @@ async fn can_snapshot_list_root() {
 }
 
 #[tokio::test]
-async fn can_snapshot_list_child() {
+async fn cannot_volume_restore_after_grace() {
+    with_service_options(
+        |options| options.delete_grace = 0,
+        |url| async move {
+            let privkey = Privkey::generate();
+            let client = Client::new();
+            let token = Uuid::new_v4().to_string();
+            volume_create(&url, &client, &token, &privkey).await?;
+            volume_remove(&url, &client, &token, &privkey).await?;
+            assert!(volume_restore(&url, &client, &token, &privkey.pubkey())
+                .await
+                .is_err());
+            Ok(())
+        },
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_snapshot_upload() {
     with_service(|url| async move {
+        let volume = Privkey::generate();
         let client = Client::new();
         let token = Uuid::new_v4();
         let machine = Uuid::new_v4();
-        let volume = Privkey::generate();
-
-        // Listing snapshots on an empty volume should return an empty list.
         volume_create(&url, &client, &token.to_string(), &volume).await?;
-        let result = snapshot_list(
-            &url,
-            &client,
-            &token.to_string(),
-            &volume.pubkey(),
-            None,
-            false,
-        )
-        .await?;
-        assert_eq!(result, vec![]);
-
-        // upload a single snapshot with no parent (root snapshot)
         let manifest = Manifest {
             generation: 0,
-            creation: 0,
             path: PathBuf::from_str("/tmp/path").unwrap(),
+            creation: 0,
             machine,
-            size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
-            size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            size: 10,
+            size_total: 10,
             parent: None,
             data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
                 .try_into()
                 .unwrap(),
         };
         let manifest = manifest.sign(&volume);
-        let parent = manifest.hash();
+        let info = volume_get(&url, &client, &token.to_string(), &volume.pubkey()).await?;
+        assert_eq!(info.snapshot_count, 0);
+        assert_eq!(info.latest_generation, None);
         snapshot_upload(
             &url,
             &client,
@@ -597,25 +1439,37 @@ async fn can_snapshot_list_child() {
             &manifest,
         )
         .await?;
+        let info = volume_get(&url, &client, &token.to_string(), &volume.pubkey()).await?;
+        assert_eq!(info.snapshot_count, 1);
+        assert_eq!(info.latest_generation, Some(0));
+        assert_eq!(info.bytes_stored, 10);
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
 
-        // upload a single snapshot with parent (child snapshot)
+#[tokio::test]
+async fn can_snapshot_upload_twice() {
+    with_service(|url| async move {
+        let volume = Privkey::generate();
+        let client = Client::new();
+        let token = Uuid::new_v4();
+        let machine = Uuid::new_v4();
+        volume_create(&url, &client, &token.to_string(), &volume).await?;
         let manifest = Manifest {
-            generation: 1,
-            creation: 0,
+            generation: 0,
             path: PathBuf::from_str("/tmp/path").unwrap(),
+            creation: 0,
             machine,
-            size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
-            size_total: 2 * crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
-            parent: Some(Parent {
-                hash: parent,
-                volume: None,
-            }),
+            size: 10,
+            size_total: 10,
+            parent: None,
             data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
                 .try_into()
                 .unwrap(),
         };
         let manifest = manifest.sign(&volume);
-        let child = manifest.hash();
         snapshot_upload(
             &url,
             &client,
@@ -624,43 +1478,2921 @@ async fn can_snapshot_list_child() {
             &manifest,
         )
         .await?;
-
-        // listing with no options should return all snapshots
-        let result = snapshot_list(
-            &url,
+        // make sure we can upload the same snapshot twice without error
+        snapshot_upload(
+            &url,
+            &client,
+            &token.to_string(),
+            &volume.pubkey(),
+            &manifest,
+        )
+        .await?;
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_snapshot_upload_reject_writer() {
+    with_service(|url| async move {
+        let volume = Privkey::generate();
+        let client = Client::new();
+        let token = Uuid::new_v4();
+        let machine = Uuid::new_v4();
+        volume_create(&url, &client, &token.to_string(), &volume).await?;
+        let manifest = Manifest {
+            generation: 0,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            creation: 0,
+            machine,
+            size: 10,
+            size_total: 10,
+            parent: None,
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        };
+        let manifest = manifest.sign(&volume);
+        snapshot_upload(
+            &url,
+            &client,
+            &token.to_string(),
+            &volume.pubkey(),
+            &manifest,
+        )
+        .await?;
+        let new_machine = Uuid::new_v4();
+        let manifest = Manifest {
+            generation: 1,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            creation: 0,
+            machine: new_machine,
+            size: 10,
+            size_total: 20,
+            parent: None,
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        };
+        let manifest = manifest.sign(&volume);
+        let result = snapshot_upload(
+            &url,
+            &client,
+            &token.to_string(),
+            &volume.pubkey(),
+            &manifest,
+        )
+        .await;
+        assert!(result.is_err());
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_snapshot_fetch() {
+    with_service(|url| async move {
+        let volume = Privkey::generate();
+        let client = Client::new();
+        let token = Uuid::new_v4();
+        let machine = Uuid::new_v4();
+        volume_create(&url, &client, &token.to_string(), &volume).await?;
+        let manifest = Manifest {
+            generation: 0,
+            creation: 0,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            machine,
+            size: 10,
+            size_total: 10,
+            parent: None,
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        };
+        let manifest = manifest.sign(&volume);
+        snapshot_upload(
+            &url,
+            &client,
+            &token.to_string(),
+            &volume.pubkey(),
+            &manifest,
+        )
+        .await?;
+        let signed_manifest = snapshot_fetch(
+            &url,
+            &client,
+            &token.to_string(),
+            &volume.pubkey(),
+            &manifest.hash(),
+        )
+        .await?;
+        assert_eq!(manifest, signed_manifest);
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_report_typed_errors() {
+    with_service(|url| async move {
+        let client = Client::new();
+        let token = Uuid::new_v4().to_string();
+        let volume = Privkey::generate();
+
+        // errors are described with a code and a message
+        let response = client
+            .get(url.join(&format!("/api/v1/volume/{}", volume.pubkey().to_hex()))?)
+            .header("Authorization", format!("Bearer {token}"))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let error: ErrorResponse = response.json().await?;
+        assert_eq!(error.code, ErrorCode::VolumeNotFound);
+        assert_eq!(error.message, "Volume not found for user");
+
+        volume_create(&url, &client, &token, &volume).await?;
+        let edit = VolumeEdit {
+            writer: Field::Missing,
+            account: None,
+            lock: Some(true),
+            retain_count: Field::Missing,
+            retain_age: Field::Missing,
+            worm_period: Field::Missing,
+            storage_class: None,
+            pq_pubkey: None,
+        };
+        volume_edit(&url, &client, &token, &volume, &edit).await?;
+        let manifest = Manifest {
+            generation: 0,
+            creation: 0,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            machine: Uuid::nil(),
+            size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            parent: None,
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        }
+        .sign(&volume);
+        let result = snapshot_upload(&url, &client, &token, &volume.pubkey(), &manifest).await;
+        assert!(matches!(result, Err(Error::Locked(_))));
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_volume_list() {
+    with_service(|url| async move {
+        let client = Client::new();
+        let token = Uuid::new_v4().to_string();
+        assert!(volume_list(&url, &client, &token).await?.is_empty());
+
+        let first = Privkey::generate();
+        let second = Privkey::generate();
+        volume_create(&url, &client, &token, &first).await?;
+        volume_create(&url, &client, &token, &second).await?;
+        let volumes = volume_list(&url, &client, &token).await?;
+        let mut pubkeys = vec![first.pubkey(), second.pubkey()];
+        pubkeys.sort_by_key(|pubkey| pubkey.to_hex());
+        let listed: Vec<Pubkey> = volumes.iter().map(|(pubkey, _)| *pubkey).collect();
+        assert_eq!(listed, pubkeys);
+        assert_eq!(
+            volumes[0].1,
+            volume_get(&url, &client, &token, &volumes[0].0).await?
+        );
+
+        // deleted volumes and volumes of other accounts are not listed
+        volume_remove(&url, &client, &token, &first).await?;
+        let volumes = volume_list(&url, &client, &token).await?;
+        assert_eq!(volumes.len(), 1);
+        assert_eq!(volumes[0].0, second.pubkey());
+        let other = Uuid::new_v4().to_string();
+        assert!(volume_list(&url, &client, &other).await?.is_empty());
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_snapshot_delete() {
+    with_service(|url| async move {
+        let client = Client::new();
+        let token = Uuid::new_v4().to_string();
+        let volume = Privkey::generate();
+        volume_create(&url, &client, &token, &volume).await?;
+        let parent = Manifest {
+            generation: 0,
+            creation: 0,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            machine: Uuid::nil(),
+            size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            parent: None,
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        }
+        .sign(&volume);
+        let child = Manifest {
+            generation: 1,
+            creation: 1,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            machine: Uuid::nil(),
+            size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            size_total: 2 * crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            parent: Some(Parent::new(parent.hash())),
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        }
+        .sign(&volume);
+        snapshot_upload(&url, &client, &token, &volume.pubkey(), &parent).await?;
+        snapshot_upload(&url, &client, &token, &volume.pubkey(), &child).await?;
+
+        // parents can only be deleted once their children are gone
+        let pubkey = volume.pubkey();
+        let result = snapshot_delete(&url, &client, &token, &pubkey, &parent.hash()).await;
+        assert!(matches!(result, Err(Error::HasChildren(_))));
+        snapshot_delete(&url, &client, &token, &pubkey, &child.hash()).await?;
+        snapshot_delete(&url, &client, &token, &pubkey, &parent.hash()).await?;
+        let result = snapshot_fetch(&url, &client, &token, &pubkey, &parent.hash()).await;
+        assert!(matches!(result, Err(Error::SnapshotNotFound(_))));
+        let result = snapshot_delete(&url, &client, &token, &pubkey, &parent.hash()).await;
+        assert!(matches!(result, Err(Error::SnapshotNotFound(_))));
+
+        // snapshots of other accounts cannot be deleted
+        let other = Uuid::new_v4().to_string();
+        let result = snapshot_delete(&url, &client, &other, &pubkey, &child.hash()).await;
+        assert!(matches!(result, Err(Error::VolumeNotFound(_))));
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_snapshot_fetch_missing() {
+    with_service(|url| async move {
+        let client = Client::new();
+        let token = Uuid::new_v4();
+        let volume = Privkey::generate();
+        let snapshot = Hash::generate(&[]);
+        let result = snapshot_fetch(
+            &url,
+            &client,
+            &token.to_string(),
+            &volume.pubkey(),
+            &snapshot,
+        )
+        .await;
+        assert!(matches!(result, Err(Error::VolumeNotFound(_))));
+
+        volume_create(&url, &client, &token.to_string(), &volume).await?;
+        let result = snapshot_fetch(
+            &url,
+            &client,
+            &token.to_string(),
+            &volume.pubkey(),
+            &snapshot,
+        )
+        .await;
+        assert!(matches!(result, Err(Error::SnapshotNotFound(_))));
+
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_snapshot_list_empty() {
+    with_service(|url| async move {
+        let client = Client::new();
+        let token = Uuid::new_v4();
+        let volume = Privkey::generate();
+
+        // Listing snapshots on an nonexistant volume should return 404
+        let result = snapshot_list(
+            &url,
             &client,
             &token.to_string(),
             &volume.pubkey(),
             None,
             false,
         )
-        .await?;
-        assert_eq!(result, vec![parent, child]);
+        .await;
+        assert!(matches!(result, Err(Error::VolumeNotFound(_))));
 
-        // listing with root set to true should return parent snapshot
+        // Listing snapshots on an empty volume should return an empty list.
+        volume_create(&url, &client, &token.to_string(), &volume).await?;
         let result = snapshot_list(
             &url,
             &client,
             &token.to_string(),
             &volume.pubkey(),
             None,
-            true,
+            false,
         )
         .await?;
-        assert_eq!(result, vec![parent]);
+        assert_eq!(result, vec![]);
 
-        // listing with parent set to parent snapshot should return child snapshot
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_snapshot_list_root() {
+    with_service(|url| async move {
+        let client = Client::new();
+        let token = Uuid::new_v4();
+        let machine = Uuid::new_v4();
+        let volume = Privkey::generate();
+
+        // Listing snapshots on an empty volume should return an empty list.
+        volume_create(&url, &client, &token.to_string(), &volume).await?;
         let result = snapshot_list(
             &url,
             &client,
             &token.to_string(),
             &volume.pubkey(),
-            Some(&parent),
+            None,
             false,
         )
         .await?;
-        assert_eq!(result, vec![child]);
+        assert_eq!(result, vec![]);
+
+        // upload a single snapshot with no parent (root snapshot)
+        let manifest = Manifest {
+            generation: 0,
+            creation: 0,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            machine,
+            size: 10,
+            size_total: 10,
+            parent: None,
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        };
+        let manifest = manifest.sign(&volume);
+        snapshot_upload(
+            &url,
+            &client,
+            &token.to_string(),
+            &volume.pubkey(),
+            &manifest,
+        )
+        .await?;
+
+        // listing with no options should return this snapshot
+        let result = snapshot_list(
+            &url,
+            &client,
+            &token.to_string(),
+            &volume.pubkey(),
+            None,
+            false,
+        )
+        .await?;
+        assert_eq!(result, vec![manifest.hash()]);
+
+        // listing with root set to true should return this snapshot
+        let result = snapshot_list(
+            &url,
+            &client,
+            &token.to_string(),
+            &volume.pubkey(),
+            None,
+            true,
+        )
+        .await?;
+        assert_eq!(result, vec![manifest.hash()]);
+
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_snapshot_list_child() {
+    with_service(|url| async move {
+        let client = Client::new();
+        let token = Uuid::new_v4();
+        let machine = Uuid::new_v4();
+        let volume = Privkey::generate();
+
+        // Listing snapshots on an empty volume should return an empty list.
+        volume_create(&url, &client, &token.to_string(), &volume).await?;
+        let result = snapshot_list(
+            &url,
+            &client,
+            &token.to_string(),
+            &volume.pubkey(),
+            None,
+            false,
+        )
+        .await?;
+        assert_eq!(result, vec![]);
+
+        // upload a single snapshot with no parent (root snapshot)
+        let manifest = Manifest {
+            generation: 0,
+            creation: 0,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            machine,
+            size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            parent: None,
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        };
+        let manifest = manifest.sign(&volume);
+        let parent = manifest.hash();
+        snapshot_upload(
+            &url,
+            &client,
+            &token.to_string(),
+            &volume.pubkey(),
+            &manifest,
+        )
+        .await?;
+
+        // upload a single snapshot with parent (child snapshot)
+        let manifest = Manifest {
+            generation: 1,
+            creation: 0,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            machine,
+            size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            size_total: 2 * crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            parent: Some(Parent {
+                hash: parent,
+                volume: None,
+            }),
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        };
+        let manifest = manifest.sign(&volume);
+        let child = manifest.hash();
+        snapshot_upload(
+            &url,
+            &client,
+            &token.to_string(),
+            &volume.pubkey(),
+            &manifest,
+        )
+        .await?;
+
+        // listing with no options should return all snapshots
+        let result = snapshot_list(
+            &url,
+            &client,
+            &token.to_string(),
+            &volume.pubkey(),
+            None,
+            false,
+        )
+        .await?;
+        assert_eq!(result, vec![parent, child]);
+
+        // listing with root set to true should return parent snapshot
+        let result = snapshot_list(
+            &url,
+            &client,
+            &token.to_string(),
+            &volume.pubkey(),
+            None,
+            true,
+        )
+        .await?;
+        assert_eq!(result, vec![parent]);
+
+        // listing with parent set to parent snapshot should return child snapshot
+        let result = snapshot_list(
+            &url,
+            &client,
+            &token.to_string(),
+            &volume.pubkey(),
+            Some(&parent),
+            false,
+        )
+        .await?;
+        assert_eq!(result, vec![child]);
+
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_snapshot_fetch_not_modified() {
+    with_service(|url| async move {
+        let volume = Privkey::generate();
+        let client = Client::new();
+        let token = Uuid::new_v4();
+        let machine = Uuid::new_v4();
+        volume_create(&url, &client, &token.to_string(), &volume).await?;
+        let manifest = Manifest {
+            generation: 0,
+            creation: 0,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            machine,
+            size: 10,
+            size_total: 10,
+            parent: None,
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        };
+        let manifest = manifest.sign(&volume);
+        snapshot_upload(
+            &url,
+            &client,
+            &token.to_string(),
+            &volume.pubkey(),
+            &manifest,
+        )
+        .await?;
+
+        // first fetch returns the manifest along with an entity tag
+        let snapshot_url = url.join(&format!(
+            "/api/v1/volume/{}/{}",
+            volume.pubkey().to_hex(),
+            manifest.hash().to_hex()
+        ))?;
+        let response = client
+            .get(snapshot_url.clone())
+            .header("Authorization", format!("Bearer {token}"))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()["ETag"].to_str()?.to_string();
+        assert_eq!(etag, format!("\"{}\"", manifest.hash().to_hex()));
+        // authenticated responses must not be kept by shared caches
+        assert_eq!(
+            response.headers()["Cache-Control"],
+            "private, max-age=31536000, immutable"
+        );
+
+        // fetching with matching entity tag returns not modified
+        let response = client
+            .get(snapshot_url)
+            .header("Authorization", format!("Bearer {token}"))
+            .header("If-None-Match", &etag)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert!(response.bytes().await?.is_empty());
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_snapshot_upload_batch() {
+    with_service(|url| async move {
+        let volume = Privkey::generate();
+        let client = Client::new();
+        let token = Uuid::new_v4();
+        let machine = Uuid::new_v4();
+        volume_create(&url, &client, &token.to_string(), &volume).await?;
+        let parent = Manifest {
+            generation: 0,
+            creation: 0,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            machine,
+            size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            parent: None,
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        };
+        let parent = parent.sign(&volume);
+        let child = Manifest {
+            generation: 1,
+            creation: 0,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            machine,
+            size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            size_total: 2 * crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            parent: Some(Parent::new(parent.hash())),
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        };
+        let child = child.sign(&volume);
+
+        // upload out of order, server sorts them by generation
+        let results = snapshot_upload_batch(
+            &url,
+            &client,
+            &token.to_string(),
+            &volume.pubkey(),
+            &[child.clone(), parent.clone()],
+        )
+        .await?;
+        assert_eq!(results[0].hash, child.hash());
+        assert_eq!(results[0].status, SnapshotUploadStatus::Created);
+        assert_eq!(results[1].hash, parent.hash());
+        assert_eq!(results[1].status, SnapshotUploadStatus::Created);
+
+        // uploading again reports them as existing
+        let results = snapshot_upload_batch(
+            &url,
+            &client,
+            &token.to_string(),
+            &volume.pubkey(),
+            &[parent.clone(), child.clone()],
+        )
+        .await?;
+        assert_eq!(results[0].status, SnapshotUploadStatus::Existing);
+        assert_eq!(results[1].status, SnapshotUploadStatus::Existing);
+
+        let result = snapshot_list(
+            &url,
+            &client,
+            &token.to_string(),
+            &volume.pubkey(),
+            None,
+            false,
+        )
+        .await?;
+        assert_eq!(result, vec![parent.hash(), child.hash()]);
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_receive_upload_warnings() {
+    with_service(|url| async move {
+        let volume = Privkey::generate();
+        let client = Client::new();
+        let token = Uuid::new_v4().to_string();
+        let machine = Uuid::new_v4();
+        volume_create(&url, &client, &token, &volume).await?;
+        let parent = Manifest {
+            generation: 0,
+            creation: 0,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            machine,
+            size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            parent: None,
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        };
+        let parent = parent.sign(&volume);
+        let uploaded = snapshot_upload(&url, &client, &token, &volume.pubkey(), &parent).await?;
+        assert!(uploaded.warnings.is_empty());
+
+        // generations are skipped and the snapshot was created a day from now
+        let creation = crate::purge::now() + 24 * 60 * 60;
+        let child = Manifest {
+            generation: 3,
+            creation,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            machine,
+            size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            size_total: 2 * crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            parent: Some(Parent::new(parent.hash())),
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        };
+        let child = child.sign(&volume);
+        let results =
+            snapshot_upload_batch(&url, &client, &token, &volume.pubkey(), &[child]).await?;
+        assert_eq!(results[0].status, SnapshotUploadStatus::Created);
+        assert_eq!(results[0].warnings.len(), 2);
+        assert_eq!(
+            results[0].warnings[0],
+            Warning::GenerationGap {
+                parent: 0,
+                generation: 3
+            }
+        );
+        assert!(matches!(
+            results[0].warnings[1],
+            Warning::ClockSkew { creation: time, .. } if time == creation
+        ));
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_snapshot_upload_batch_rollback() {
+    with_service(|url| async move {
+        let volume = Privkey::generate();
+        let client = Client::new();
+        let token = Uuid::new_v4();
+        let machine = Uuid::new_v4();
+        volume_create(&url, &client, &token.to_string(), &volume).await?;
+        let root = Manifest {
+            generation: 0,
+            creation: 0,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            machine,
+            size: 10,
+            size_total: 10,
+            parent: None,
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        };
+        let root = root.sign(&volume);
+        let orphan = Manifest {
+            generation: 1,
+            creation: 0,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            machine,
+            size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE + 10,
+            parent: Some(Parent::new(Hash::generate(&[]))),
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        };
+        let orphan = orphan.sign(&volume);
+
+        // orphan fails because of missing parent, so root is not stored either
+        let results = snapshot_upload_batch(
+            &url,
+            &client,
+            &token.to_string(),
+            &volume.pubkey(),
+            &[root, orphan],
+        )
+        .await?;
+        assert_eq!(results[0].status, SnapshotUploadStatus::Skipped);
+        assert!(matches!(
+            results[1].status,
+            SnapshotUploadStatus::Failed { .. }
+        ));
+
+        let result = snapshot_list(
+            &url,
+            &client,
+            &token.to_string(),
+            &volume.pubkey(),
+            None,
+            false,
+        )
+        .await?;
+        assert_eq!(result, vec![]);
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_reject_oversized_payload() {
+    let system = Uuid::new_v4().to_string();
+    let static_system = format!("{system}:{}", Uuid::new_v4());
+    with_service_options(
+        |options| {
+            options.static_system = vec![static_system.parse().unwrap()];
+            options.route_budget = vec!["account_settings_set=30000:1024".parse().unwrap()];
+        },
+        |url| async move {
+            let volume = Privkey::generate();
+            let client = Client::new();
+            let token = Uuid::new_v4();
+            volume_create(&url, &client, &token.to_string(), &volume).await?;
+            let response = client
+                .post(url.join(&format!(
+                    "/api/v1/volume/{}/snapshot",
+                    volume.pubkey().to_hex()
+                ))?)
+                .header("Authorization", format!("Bearer {token}"))
+                .body(vec![0; 1024 * 1024 + 1])
+                .send()
+                .await?;
+            assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+            // bodies of unknown length are cut off at the limit
+            let chunked = |size: usize| {
+                let chunks = vec![Ok::<_, std::io::Error>(vec![b' '; size])];
+                reqwest::Body::wrap_stream(rocket::futures::stream::iter(chunks))
+            };
+            let response = client
+                .put(url.join("/api/v1/account/labels")?)
+                .bearer_auth(&token)
+                .body(chunked(1024 * 1024 + 1))
+                .send()
+                .await?;
+            assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+            // routes can have a limit of their own, below which bodies of unknown length
+            // cannot be cut off
+            let settings_url = url.join("/api/v1/account/settings")?;
+            let response = client
+                .put(settings_url.clone())
+                .bearer_auth(&token)
+                .body(chunked(16))
+                .send()
+                .await?;
+            assert_eq!(response.status(), StatusCode::LENGTH_REQUIRED);
+            let response = client
+                .put(settings_url)
+                .bearer_auth(&token)
+                .body(vec![b' '; 1025])
+                .send()
+                .await?;
+            assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+            assert!(response.text().await?.contains("1024 bytes"));
+
+            let metrics = client
+                .get(url.join("/metrics")?)
+                .bearer_auth(&system)
+                .send()
+                .await?
+                .text()
+                .await?;
+            assert!(metrics.contains("storage_requests_oversized_total 3"));
+
+            // metrics are only available to system tokens
+            let response = client.get(url.join("/metrics")?).send().await?;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            let response = client
+                .get(url.join("/metrics")?)
+                .bearer_auth(&token)
+                .send()
+                .await?;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+            Ok(())
+        },
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_answer_cors_preflight() {
+    let configure = |options: &mut Options| {
+        options.cors_origin = vec!["https://dashboard.example.com".into()];
+    };
+    with_service_options(configure, |url| async move {
+        let client = Client::new();
+        let volume = Privkey::generate();
+        let volume_url = url.join(&format!("/api/v1/volume/{}", volume.pubkey().to_hex()))?;
+
+        // allowed origin gets CORS headers
+        let response = client
+            .request(reqwest::Method::OPTIONS, volume_url.clone())
+            .header("Origin", "https://dashboard.example.com")
+            .header("Access-Control-Request-Method", "POST")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            response.headers()["Access-Control-Allow-Origin"],
+            "https://dashboard.example.com"
+        );
+        assert_eq!(response.headers()["Access-Control-Max-Age"], "3600");
+
+        // other origins do not
+        let response = client
+            .request(reqwest::Method::OPTIONS, volume_url)
+            .header("Origin", "https://evil.example.com")
+            .send()
+            .await?;
+        assert!(response
+            .headers()
+            .get("Access-Control-Allow-Origin")
+            .is_none());
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_snapshot_payload_without_ipfs() {
+    with_service(|url| async move {
+        let volume = Privkey::generate();
+        let client = Client::new();
+        let token = Uuid::new_v4();
+        volume_create(&url, &client, &token.to_string(), &volume).await?;
+        let response = client
+            .get(url.join(&format!(
+                "/api/v1/volume/{}/{}/payload",
+                volume.pubkey().to_hex(),
+                Hash::generate(&[]).to_hex()
+            ))?)
+            .header("Authorization", format!("Bearer {token}"))
+            .header("Range", "bytes=0-99")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::NOT_IMPLEMENTED);
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_receive_account_events() {
+    with_service(|url| async move {
+        let volume = Privkey::generate();
+        let client = Client::new();
+        let token = Uuid::new_v4();
+        let other = Uuid::new_v4();
+        let mut events = account_events(&url, &client, &token.to_string()).await?;
+
+        // events of other accounts are not received
+        volume_create(&url, &client, &other.to_string(), &Privkey::generate()).await?;
+        volume_create(&url, &client, &token.to_string(), &volume).await?;
+        let event = events.next().await.unwrap()?;
+        assert_eq!(
+            event,
+            AccountEvent::VolumeCreated {
+                volume: volume.pubkey()
+            }
+        );
+
+        volume_remove(&url, &client, &token.to_string(), &volume).await?;
+        let event = events.next().await.unwrap()?;
+        assert_eq!(
+            event,
+            AccountEvent::VolumeDeleted {
+                volume: volume.pubkey()
+            }
+        );
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_snapshot_chain_validate() {
+    with_service(|url| async move {
+        let client = Client::new();
+        let token = Uuid::new_v4().to_string();
+        let volume = Privkey::generate();
+        volume_create(&url, &client, &token, &volume).await?;
+
+        let mut manifest = Manifest {
+            generation: 0,
+            creation: 0,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            machine: Uuid::new_v4(),
+            size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            parent: None,
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        };
+        let mut hashes = vec![];
+        for generation in 0..3 {
+            manifest.generation = generation;
+            manifest.size_total = (generation + 1) * crate::snapshot::MINIMUM_SNAPSHOT_SIZE;
+            manifest.parent = hashes.last().copied().map(Parent::new);
+            let signed = manifest.sign(&volume);
+            snapshot_upload(&url, &client, &token, &volume.pubkey(), &signed).await?;
+            hashes.push(signed.hash());
+        }
+
+        let report =
+            snapshot_chain_validate(&url, &client, &token, &volume.pubkey(), &hashes[2]).await?;
+        assert!(report.valid);
+        let chain: Vec<Hash> = report.links.iter().map(|link| link.hash).collect();
+        assert_eq!(chain, hashes.iter().rev().copied().collect::<Vec<_>>());
+        assert!(report.links.iter().all(|link| link.errors.is_empty()));
+
+        let result = snapshot_chain_validate(
+            &url,
+            &client,
+            &token,
+            &volume.pubkey(),
+            &Manifest::hash(b"missing"),
+        )
+        .await;
+        assert!(matches!(result, Err(Error::SnapshotNotFound(_))));
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_snapshot_upload_cross_volume_parent() {
+    with_service(|url| async move {
+        let client = Client::new();
+        let token = Uuid::new_v4();
+        let machine = Uuid::new_v4();
+        let origin = Privkey::generate();
+        let volume = Privkey::generate();
+        volume_create(&url, &client, &token.to_string(), &origin).await?;
+        volume_create(&url, &client, &token.to_string(), &volume).await?;
+
+        // upload root snapshot into the origin volume
+        let manifest = Manifest {
+            generation: 0,
+            creation: 0,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            machine,
+            size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            parent: None,
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        };
+        let manifest = manifest.sign(&origin);
+        let parent = manifest.hash();
+        snapshot_upload(
+            &url,
+            &client,
+            &token.to_string(),
+            &origin.pubkey(),
+            &manifest,
+        )
+        .await?;
+
+        // child in other volume with wrong size_total is rejected
+        let mut manifest = Manifest {
+            generation: 1,
+            creation: 0,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            machine,
+            size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            parent: Some(Parent {
+                hash: parent,
+                volume: Some((origin.pubkey(), Secret::generate())),
+            }),
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        };
+        let result = snapshot_upload(
+            &url,
+            &client,
+            &token.to_string(),
+            &volume.pubkey(),
+            &manifest.sign(&volume),
+        )
+        .await;
+        assert!(result.is_err());
+
+        // child referencing a volume that does not exist is rejected
+        manifest.size_total = 2 * crate::snapshot::MINIMUM_SNAPSHOT_SIZE;
+        manifest.parent = Some(Parent {
+            hash: parent,
+            volume: Some((Privkey::generate().pubkey(), Secret::generate())),
+        });
+        let result = snapshot_upload(
+            &url,
+            &client,
+            &token.to_string(),
+            &volume.pubkey(),
+            &manifest.sign(&volume),
+        )
+        .await;
+        assert!(result.is_err());
+
+        // valid child is accepted
+        manifest.parent = Some(Parent {
+            hash: parent,
+            volume: Some((origin.pubkey(), Secret::generate())),
+        });
+        snapshot_upload(
+            &url,
+            &client,
+            &token.to_string(),
+            &volume.pubkey(),
+            &manifest.sign(&volume),
+        )
+        .await?;
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn cannot_link_parent_of_other_account() {
+    with_service(|url| async move {
+        let client = Client::new();
+        let owner = Uuid::new_v4();
+        let other = Uuid::new_v4();
+        let origin = Privkey::generate();
+        let volume = Privkey::generate();
+        volume_create(&url, &client, &owner.to_string(), &origin).await?;
+        volume_create(&url, &client, &other.to_string(), &volume).await?;
+
+        let mut manifest = Manifest {
+            generation: 0,
+            creation: 0,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            machine: Uuid::new_v4(),
+            size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            parent: None,
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        };
+        let root = manifest.sign(&origin);
+        snapshot_upload(&url, &client, &owner.to_string(), &origin.pubkey(), &root).await?;
+
+        // parents in volumes of other accounts cannot be told apart from missing ones
+        manifest.generation = 1;
+        manifest.size_total = 2 * crate::snapshot::MINIMUM_SNAPSHOT_SIZE;
+        for parent_volume in [origin.pubkey(), Privkey::generate().pubkey()] {
+            manifest.parent =
+                Some(Parent::new(root.hash()).with_volume(parent_volume, Secret::generate()));
+            let result = snapshot_upload(
+                &url,
+                &client,
+                &other.to_string(),
+                &volume.pubkey(),
+                &manifest.sign(&volume),
+            )
+            .await;
+            assert!(matches!(
+                result,
+                Err(Error::Unsuccessful(StatusCode::BAD_REQUEST))
+            ));
+        }
+
+        // unless the volume was shared with the account
+        let grant = VolumeGrant {
+            account: other,
+            access: VolumeAccess::Read,
+        };
+        volume_acl_grant(&url, &client, &owner.to_string(), &origin.pubkey(), &grant).await?;
+        manifest.parent =
+            Some(Parent::new(root.hash()).with_volume(origin.pubkey(), Secret::generate()));
+        snapshot_upload(
+            &url,
+            &client,
+            &other.to_string(),
+            &volume.pubkey(),
+            &manifest.sign(&volume),
+        )
+        .await?;
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_snapshot_ancestry() {
+    with_service(|url| async move {
+        let client = Client::new();
+        let token = Uuid::new_v4().to_string();
+        let other = Uuid::new_v4().to_string();
+        let origin = Privkey::generate();
+        let foreign = Privkey::generate();
+        let volume = Privkey::generate();
+        volume_create(&url, &client, &token, &origin).await?;
+        volume_create(&url, &client, &other, &foreign).await?;
+        volume_create(&url, &client, &token, &volume).await?;
+
+        let manifest = Manifest {
+            generation: 0,
+            creation: 0,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            machine: Uuid::new_v4(),
+            size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            parent: None,
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        };
+        let root = manifest.sign(&origin);
+        snapshot_upload(&url, &client, &token, &origin.pubkey(), &root).await?;
+        let foreign_root = manifest.sign(&foreign);
+        snapshot_upload(&url, &client, &other, &foreign.pubkey(), &foreign_root).await?;
+
+        // children of both roots, stored in the volume of the caller
+        let mut children = vec![];
+        for (parent, parent_volume) in [(&root, &origin), (&foreign_root, &foreign)] {
+            let mut child = manifest.clone();
+            child.generation = 1;
+            child.size_total = 2 * crate::snapshot::MINIMUM_SNAPSHOT_SIZE;
+            child.parent = Some(Parent {
+                hash: parent.hash(),
+                volume: Some((parent_volume.pubkey(), Secret::generate())),
+            });
+            let child = child.sign(&volume);
+            snapshot_upload(&url, &client, &token, &volume.pubkey(), &child).await?;
+            children.push(child);
+        }
+
+        // parent in another volume of the same account is resolved
+        let ancestry =
+            snapshot_ancestry(&url, &client, &token, &volume.pubkey(), &children[0].hash()).await?;
+        assert!(ancestry.complete);
+        assert_eq!(
+            ancestry.snapshots,
+            vec![
+                AncestryLink {
+                    volume: volume.pubkey(),
+                    manifest: children[0].clone(),
+                },
+                AncestryLink {
+                    volume: origin.pubkey(),
+                    manifest: root.clone(),
+                },
+            ]
+        );
+
+        // parent in a volume of another account is not
+        let ancestry =
+            snapshot_ancestry(&url, &client, &token, &volume.pubkey(), &children[1].hash()).await?;
+        assert!(!ancestry.complete);
+        assert_eq!(ancestry.snapshots.len(), 1);
+        assert_eq!(ancestry.snapshots[0].manifest, children[1]);
+
+        // volumes of other accounts cannot be queried
+        let result =
+            snapshot_ancestry(&url, &client, &other, &volume.pubkey(), &children[0].hash()).await;
+        assert!(matches!(result, Err(Error::VolumeNotFound(_))));
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_snapshot_exists() {
+    with_service(|url| async move {
+        let client = Client::new();
+        let token = Uuid::new_v4();
+        let volume = Privkey::generate();
+        volume_create(&url, &client, &token.to_string(), &volume).await?;
+
+        let manifest = Manifest {
+            generation: 0,
+            creation: 0,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            machine: Uuid::new_v4(),
+            size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            parent: None,
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        };
+        let manifest = manifest.sign(&volume);
+        snapshot_upload(
+            &url,
+            &client,
+            &token.to_string(),
+            &volume.pubkey(),
+            &manifest,
+        )
+        .await?;
+
+        let missing = Hash::generate(&[]);
+        let existing = snapshot_exists(
+            &url,
+            &client,
+            &token.to_string(),
+            &volume.pubkey(),
+            &[missing, manifest.hash()],
+        )
+        .await?;
+        assert_eq!(existing, vec![manifest.hash()]);
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_snapshot_data_upload() {
+    let blobs = std::env::temp_dir().join(Uuid::new_v4().to_string());
+    let blob_backend = Url::from_directory_path(&blobs).unwrap();
+    with_service_options(
+        |options| options.blob_backend = Some(blob_backend),
+        |url| async move {
+            let client = Client::new();
+            let token = Uuid::new_v4();
+            let volume = Privkey::generate();
+            volume_create(&url, &client, &token.to_string(), &volume).await?;
+
+            let manifest = Manifest {
+                generation: 0,
+                creation: 0,
+                path: PathBuf::from_str("/tmp/path").unwrap(),
+                machine: Uuid::new_v4(),
+                size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                parent: None,
+                data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                    .try_into()
+                    .unwrap(),
+            };
+            let manifest = manifest.sign(&volume);
+            let token = token.to_string();
+            let snapshot = manifest.hash();
+
+            // payload for unknown snapshot is rejected
+            let data: Vec<u8> = (0..4096).map(|i| i as u8).collect();
+            let result = snapshot_data_upload(
+                &url,
+                &client,
+                &token,
+                &volume.pubkey(),
+                &snapshot,
+                data.clone().into(),
+            )
+            .await;
+            assert!(result.is_err());
+
+            snapshot_upload(&url, &client, &token, &volume.pubkey(), &manifest).await?;
+            snapshot_data_upload(
+                &url,
+                &client,
+                &token,
+                &volume.pubkey(),
+                &snapshot,
+                data.clone().into(),
+            )
+            .await?;
+
+            // payloads are immutable
+            let result = snapshot_data_upload(
+                &url,
+                &client,
+                &token,
+                &volume.pubkey(),
+                &snapshot,
+                data.clone().into(),
+            )
+            .await;
+            assert!(matches!(
+                result,
+                Err(Error::Unsuccessful(StatusCode::CONFLICT))
+            ));
+
+            // uploading the manifest again reports the stored payload
+            let uploaded =
+                snapshot_upload(&url, &client, &token, &volume.pubkey(), &manifest).await?;
+            assert!(uploaded.deduplicated);
+            assert_eq!(uploaded.payload, Some(PayloadStatus::Stored));
+
+            let stream =
+                snapshot_data_fetch(&url, &client, &token, &volume.pubkey(), &snapshot).await?;
+            let fetched: Vec<_> = stream.collect().await;
+            let fetched: Vec<u8> = fetched.into_iter().collect::<Result<Vec<_>, _>>()?.concat();
+            assert_eq!(fetched, data);
+            Ok(())
+        },
+    )
+    .await
+    .unwrap();
+    std::fs::remove_dir_all(&blobs).unwrap();
+}
+
+#[tokio::test]
+async fn can_stream_large_snapshot_data() {
+    // sparse file, so that it takes up no disk space.
+    const SIZE: u64 = 1024 * 1024 * 1024;
+    let blobs = std::env::temp_dir().join(Uuid::new_v4().to_string());
+    let blob_backend = Url::from_directory_path(&blobs).unwrap();
+    with_service_options(
+        |options| options.blob_backend = Some(blob_backend),
+        |url| async move {
+            let client = Client::new();
+            let token = Uuid::new_v4();
+            let volume = Privkey::generate();
+            volume_create(&url, &client, &token.to_string(), &volume).await?;
+
+            let manifest = Manifest {
+                generation: 0,
+                creation: 0,
+                path: PathBuf::from_str("/tmp/path").unwrap(),
+                machine: Uuid::new_v4(),
+                size: SIZE,
+                size_total: SIZE,
+                parent: None,
+                data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                    .try_into()
+                    .unwrap(),
+            };
+            let manifest = manifest.sign(&volume);
+            let snapshot = manifest.hash();
+            snapshot_upload(
+                &url,
+                &client,
+                &token.to_string(),
+                &volume.pubkey(),
+                &manifest,
+            )
+            .await?;
+            let file = std::fs::File::create(blobs.join(snapshot.to_hex()))?;
+            file.set_len(SIZE)?;
+
+            let data = url.join(&format!(
+                "/api/v1/volume/{}/{}/data",
+                volume.pubkey().to_hex(),
+                snapshot.to_hex()
+            ))?;
+
+            // full payload is streamed with its length announced up front
+            let mut response = client
+                .get(data.clone())
+                .header("Authorization", format!("Bearer {token}"))
+                .send()
+                .await?;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(
+                response.headers().get("Content-Length").unwrap(),
+                &SIZE.to_string()
+            );
+            let mut received = 0;
+            while let Some(chunk) = response.chunk().await? {
+                received += chunk.len() as u64;
+            }
+            assert_eq!(received, SIZE);
+
+            // ranges announce the length of the range
+            let response = client
+                .get(data)
+                .header("Authorization", format!("Bearer {token}"))
+                .header("Range", format!("bytes={}-", SIZE - 100))
+                .send()
+                .await?;
+            assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+            assert_eq!(response.headers().get("Content-Length").unwrap(), "100");
+            assert_eq!(response.bytes().await?.len(), 100);
+            Ok(())
+        },
+    )
+    .await
+    .unwrap();
+    std::fs::remove_dir_all(&blobs).unwrap();
+}
+
+#[tokio::test]
+async fn can_limit_concurrent_uploads() {
+    let blobs = std::env::temp_dir().join(Uuid::new_v4().to_string());
+    let blob_backend = Url::from_directory_path(&blobs).unwrap();
+    with_service_options(
+        |options| {
+            options.blob_backend = Some(blob_backend);
+            options.upload_concurrency = 1;
+        },
+        |url| async move {
+            let client = Client::new();
+            let token = Uuid::new_v4().to_string();
+            let other = Uuid::new_v4().to_string();
+            let volume = Privkey::generate();
+            volume_create(&url, &client, &token, &volume).await?;
+
+            let mut manifest = Manifest {
+                generation: 0,
+                creation: 0,
+                path: PathBuf::from_str("/tmp/path").unwrap(),
+                machine: Uuid::new_v4(),
+                size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                parent: None,
+                data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                    .try_into()
+                    .unwrap(),
+            };
+            let first = manifest.sign(&volume);
+            snapshot_upload(&url, &client, &token, &volume.pubkey(), &first).await?;
+            manifest.generation = 1;
+            manifest.parent = Some(Parent::new(first.hash()));
+            manifest.size_total = 2 * crate::snapshot::MINIMUM_SNAPSHOT_SIZE;
+            let second = manifest.sign(&volume);
+
+            // payload upload that stays in flight until the sender is dropped
+            let (sender, receiver) = rocket::futures::channel::mpsc::unbounded();
+            sender
+                .unbounded_send(Ok::<_, std::io::Error>(vec![0u8; 1024]))
+                .unwrap();
+            let upload = tokio::spawn({
+                let url = url.clone();
+                let client = client.clone();
+                let token = token.clone();
+                let volume = volume.pubkey();
+                let snapshot = first.hash();
+                async move {
+                    let body = reqwest::Body::wrap_stream(receiver);
+                    snapshot_data_upload(&url, &client, &token, &volume, &snapshot, body).await
+                }
+            });
+
+            // further uploads of the account are rejected while it is in flight
+            let upload_url = url.join(&format!(
+                "/api/v1/volume/{}/snapshot",
+                volume.pubkey().to_hex()
+            ))?;
+            let response = tokio::time::timeout(Duration::from_secs(5), async {
+                loop {
+                    let response = client
+                        .post(upload_url.clone())
+                        .header("Authorization", format!("Bearer {token}"))
+                        .body(second.data())
+                        .send()
+                        .await?;
+                    if response.status() == StatusCode::TOO_MANY_REQUESTS {
+                        return Ok::<_, reqwest::Error>(response);
+                    }
+                    assert!(response.status().is_success() || response.status().is_redirection());
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await??;
+            assert_eq!(
+                response.headers().get("Retry-After").unwrap(),
+                &crate::limit::UPLOAD_RETRY_AFTER.to_string()
+            );
+
+            // other accounts are not affected
+            let foreign = Privkey::generate();
+            volume_create(&url, &client, &other, &foreign).await?;
+            let foreign_first = first.manifest.sign(&foreign);
+            snapshot_upload(&url, &client, &other, &foreign.pubkey(), &foreign_first).await?;
+
+            // once the upload finishes, the account can upload again
+            drop(sender);
+            upload.await??;
+            snapshot_upload(&url, &client, &token, &volume.pubkey(), &second).await?;
+            Ok(())
+        },
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_reject_oversized_uploads() {
+    let blobs = std::env::temp_dir().join(Uuid::new_v4().to_string());
+    let blob_backend = Url::from_directory_path(&blobs).unwrap();
+    with_service_options(
+        |options| {
+            options.blob_backend = Some(blob_backend);
+            options.manifest_limit = 512;
+            options.blob_limit = Some(1024);
+        },
+        |url| async move {
+            let client = Client::new();
+            let token = Uuid::new_v4().to_string();
+            let volume = Privkey::generate();
+            volume_create(&url, &client, &token, &volume).await?;
+
+            let mut manifest = Manifest {
+                generation: 0,
+                creation: 0,
+                path: PathBuf::from("/tmp").join("a".repeat(1024)),
+                machine: Uuid::new_v4(),
+                size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                parent: None,
+                data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                    .try_into()
+                    .unwrap(),
+            };
+
+            // oversized manifests are rejected with the limit in the error
+            let upload_url = url.join(&format!(
+                "/api/v1/volume/{}/snapshot",
+                volume.pubkey().to_hex()
+            ))?;
+            let response = client
+                .post(upload_url)
+                .header("Authorization", format!("Bearer {token}"))
+                .body(manifest.sign(&volume).data())
+                .send()
+                .await?;
+            assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+            assert!(response.text().await?.contains("512 bytes"));
+
+            manifest.path = PathBuf::from("/tmp/path");
+            let manifest = manifest.sign(&volume);
+            snapshot_upload(&url, &client, &token, &volume.pubkey(), &manifest).await?;
+
+            // so are oversized payloads, which are not stored
+            let result = snapshot_data_upload(
+                &url,
+                &client,
+                &token,
+                &volume.pubkey(),
+                &manifest.hash(),
+                vec![0u8; 2048].into(),
+            )
+            .await;
+            assert!(matches!(
+                result,
+                Err(Error::Unsuccessful(StatusCode::PAYLOAD_TOO_LARGE))
+            ));
+            snapshot_data_upload(
+                &url,
+                &client,
+                &token,
+                &volume.pubkey(),
+                &manifest.hash(),
+                vec![0u8; 1024].into(),
+            )
+            .await?;
+            Ok(())
+        },
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_prefetch_manifests() {
+    with_service(|url| async move {
+        let client = Client::new();
+        let token = Uuid::new_v4().to_string();
+        let volume = Privkey::generate();
+        volume_create(&url, &client, &token, &volume).await?;
+
+        // upload a chain of snapshots
+        let mut hashes = vec![];
+        for generation in 0..5 {
+            let manifest = Manifest {
+                generation,
+                creation: 0,
+                path: PathBuf::from_str("/tmp/path").unwrap(),
+                machine: Uuid::nil(),
+                size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                size_total: (generation + 1) * crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                parent: hashes.last().map(|hash: &Hash| Parent::new(*hash)),
+                data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                    .try_into()
+                    .unwrap(),
+            };
+            let manifest = manifest.sign(&volume);
+            snapshot_upload(&url, &client, &token, &volume.pubkey(), &manifest).await?;
+            hashes.push(manifest.hash());
+        }
+
+        // manifests are returned in request order
+        hashes.reverse();
+        let prefetcher =
+            ManifestPrefetcher::new(&url, &client, &token, &volume.pubkey()).with_concurrency(2);
+        let manifests: Vec<_> = prefetcher.fetch(hashes.clone()).collect().await;
+        let manifests = manifests.into_iter().collect::<Result<Vec<_>, _>>()?;
+        let fetched: Vec<Hash> = manifests.iter().map(|manifest| manifest.hash()).collect();
+        assert_eq!(fetched, hashes);
+
+        // manifests of other volumes fail validation
+        let other = Privkey::generate();
+        let prefetcher = ManifestPrefetcher::new(&url, &client, &token, &other.pubkey());
+        let manifests: Vec<_> = prefetcher.fetch(hashes).collect().await;
+        assert!(manifests.iter().all(|manifest| manifest.is_err()));
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_negotiate_manifest_version() {
+    with_service(|url| async move {
+        let client = Client::new();
+        let capabilities = capabilities(&url, &client).await?;
+        assert_eq!(capabilities.manifest_version(), Some(MANIFEST_VERSION));
+        assert!(capabilities
+            .signature_algorithms
+            .contains(&SignatureAlgorithm::Ed25519.id()));
+
+        // negotiated versioned manifests are accepted
+        let token = Uuid::new_v4().to_string();
+        let volume = Privkey::generate();
+        volume_create(&url, &client, &token, &volume).await?;
+        let manifest = Manifest {
+            generation: 0,
+            creation: 0,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            machine: Uuid::new_v4(),
+            size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            parent: None,
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        };
+        let manifest = manifest.sign_version(&volume, MANIFEST_VERSION);
+        assert_eq!(Manifest::version(&manifest.data()), Some(MANIFEST_VERSION));
+        snapshot_upload(&url, &client, &token, &volume.pubkey(), &manifest).await?;
+        let fetched =
+            snapshot_fetch(&url, &client, &token, &volume.pubkey(), &manifest.hash()).await?;
+        assert_eq!(fetched, manifest);
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_snapshot_upload_idempotent() {
+    with_service(|url| async move {
+        let client = Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+        let token = Uuid::new_v4();
+        let volume = Privkey::generate();
+        volume_create(&url, &client, &token.to_string(), &volume).await?;
+        let upload_url = url.join(&format!(
+            "/api/v1/volume/{}/snapshot",
+            volume.pubkey().to_hex()
+        ))?;
+
+        let mut manifest = Manifest {
+            generation: 0,
+            creation: 0,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            machine: Uuid::new_v4(),
+            size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            parent: None,
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        };
+        let signed = manifest.sign(&volume);
+
+        // retrying with the same key replays the response
+        for deduplicated in [false, true] {
+            let response = client
+                .post(upload_url.clone())
+                .header("Authorization", format!("Bearer {token}"))
+                .header("Idempotency-Key", "upload-1")
+                .body(signed.data())
+                .send()
+                .await?;
+            assert_eq!(response.status(), StatusCode::OK);
+            let uploaded: SnapshotUploaded = response.json().await?;
+            assert_eq!(uploaded.hash, signed.hash());
+            assert_eq!(uploaded.deduplicated, deduplicated);
+            assert_eq!(uploaded.payload, Some(PayloadStatus::Ipfs));
+        }
+
+        // reusing the key for a different manifest is rejected
+        manifest.creation = 1;
+        let response = client
+            .post(upload_url.clone())
+            .header("Authorization", format!("Bearer {token}"))
+            .header("Idempotency-Key", "upload-1")
+            .body(manifest.sign(&volume).data())
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        // invalid keys are rejected
+        let response = client
+            .post(upload_url.clone())
+            .header("Authorization", format!("Bearer {token}"))
+            .header("Idempotency-Key", "")
+            .body(signed.data())
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_snapshot_upload_with_key() {
+    with_service(|url| async move {
+        let client = Client::new();
+        let token = Uuid::new_v4().to_string();
+        let volume = Privkey::generate();
+        let pubkey = volume.pubkey();
+        volume_create(&url, &client, &token, &volume).await?;
+        let mut manifest = Manifest {
+            generation: 0,
+            creation: 0,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            machine: Uuid::new_v4(),
+            size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            parent: None,
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        };
+        let signed = manifest.sign(&volume);
+
+        // uploads send a key derived from the manifest
+        snapshot_upload(&url, &client, &token, &pubkey, &signed).await?;
+        manifest.creation = 1;
+        let other = manifest.sign(&volume);
+        let key = signed.idempotency_key();
+        let result =
+            snapshot_upload_with_key(&url, &client, &token, &pubkey, &other, Some(&key)).await;
+        assert!(matches!(
+            result,
+            Err(Error::Unsuccessful(StatusCode::UNPROCESSABLE_ENTITY))
+        ));
+        let uploaded =
+            snapshot_upload_with_key(&url, &client, &token, &pubkey, &signed, None).await?;
+        assert!(uploaded.deduplicated);
+
+        // snapshots that were deleted are stored again
+        snapshot_delete(&url, &client, &token, &pubkey, &signed.hash()).await?;
+        let uploaded = snapshot_upload(&url, &client, &token, &pubkey, &signed).await?;
+        assert!(!uploaded.deduplicated);
+        snapshot_fetch(&url, &client, &token, &pubkey, &signed.hash()).await?;
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_snapshot_list_v2() {
+    with_service(|url| async move {
+        let client = Client::new();
+        let token = Uuid::new_v4().to_string();
+        let volume = Privkey::generate();
+        volume_create(&url, &client, &token, &volume).await?;
+
+        let mut hashes = vec![];
+        for generation in 0..5 {
+            let manifest = Manifest {
+                generation,
+                creation: generation,
+                path: PathBuf::from_str("/tmp/path").unwrap(),
+                machine: Uuid::nil(),
+                size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                size_total: (generation + 1) * crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                parent: hashes.last().map(|hash: &Hash| Parent::new(*hash)),
+                data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                    .try_into()
+                    .unwrap(),
+            };
+            let manifest = manifest.sign(&volume);
+            snapshot_upload(&url, &client, &token, &volume.pubkey(), &manifest).await?;
+            hashes.push(manifest.hash());
+        }
+
+        // fetch in pages of two
+        let mut options = SnapshotListOptions {
+            limit: Some(2),
+            ..Default::default()
+        };
+        let mut records = vec![];
+        let mut pages = 0;
+        loop {
+            let page = snapshot_list_v2(&url, &client, &token, &volume.pubkey(), &options).await?;
+            records.extend(page.snapshots);
+            pages += 1;
+            match page.cursor {
+                Some(cursor) => options.cursor = Some(cursor),
+                None => break,
+            }
+        }
+        assert_eq!(pages, 3);
+        let listed: Vec<Hash> = records.iter().map(|record| record.hash).collect();
+        assert_eq!(listed, hashes);
+        assert_eq!(records[0].parent, None);
+        assert_eq!(records[1].parent, Some(hashes[0]));
+        assert_eq!(records[4].generation, 4);
+        assert_eq!(
+            records[4].size_total,
+            5 * crate::snapshot::MINIMUM_SNAPSHOT_SIZE
+        );
+
+        // the same, following the cursors
+        let options = SnapshotListOptions {
+            limit: Some(2),
+            ..Default::default()
+        };
+        let listed: Vec<Hash> =
+            snapshot_list_paged(&url, &client, &token, &volume.pubkey(), &options)
+                .try_collect()
+                .await?;
+        assert_eq!(listed, hashes);
+
+        // root only
+        let options = SnapshotListOptions {
+            root: true,
+            ..Default::default()
+        };
+        let page = snapshot_list_v2(&url, &client, &token, &volume.pubkey(), &options).await?;
+        assert_eq!(page.snapshots.len(), 1);
+        assert_eq!(page.cursor, None);
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[cfg(feature = "chaos")]
+#[tokio::test]
+async fn can_survive_database_faults() {
+    let blobs = std::env::temp_dir().join(Uuid::new_v4().to_string());
+    let blob_backend = Url::from_directory_path(&blobs).unwrap();
+    with_service_options(
+        |options| {
+            options.blob_backend = Some(blob_backend);
+            options.chaos = Some("database=0.5".parse().unwrap());
+        },
+        |url| async move {
+            let client = Client::new();
+            let token = Uuid::new_v4().to_string();
+            let volume = Privkey::generate();
+            volume_create(&url, &client, &token, &volume).await?;
+            let data: Vec<u8> = (0..4096).map(|i| i as u8).collect();
+
+            for generation in 0..8 {
+                let manifest = Manifest {
+                    generation,
+                    creation: 0,
+                    path: PathBuf::from_str("/tmp/path").unwrap(),
+                    machine: Uuid::new_v4(),
+                    size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                    size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                    parent: None,
+                    data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                        .try_into()
+                        .unwrap(),
+                }
+                .sign(&volume);
+                let hash = manifest.hash();
+
+                // failed uploads leave no snapshot behind
+                let mut uploaded = false;
+                for _ in 0..32 {
+                    let result =
+                        snapshot_upload(&url, &client, &token, &volume.pubkey(), &manifest).await;
+                    let exists =
+                        snapshot_exists(&url, &client, &token, &volume.pubkey(), &[hash]).await?;
+                    match result {
+                        Ok(_) => {
+                            assert_eq!(exists, vec![hash]);
+                            uploaded = true;
+                            break;
+                        }
+                        Err(error) => {
+                            assert!(matches!(
+                                error,
+                                Error::Unsuccessful(StatusCode::INTERNAL_SERVER_ERROR)
+                            ));
+                            assert!(exists.is_empty());
+                        }
+                    }
+                }
+                assert!(uploaded);
+
+                // failed payload uploads do not keep the payload, so they can be retried
+                let mut uploaded = false;
+                for _ in 0..32 {
+                    let result = snapshot_data_upload(
+                        &url,
+                        &client,
+                        &token,
+                        &volume.pubkey(),
+                        &hash,
+                        data.clone().into(),
+                    )
+                    .await;
+                    match result {
+                        Ok(()) => {
+                            uploaded = true;
+                            break;
+                        }
+                        Err(error) => assert!(matches!(
+                            error,
+                            Error::Unsuccessful(StatusCode::INTERNAL_SERVER_ERROR)
+                        )),
+                    }
+                }
+                assert!(uploaded);
+                let stream =
+                    snapshot_data_fetch(&url, &client, &token, &volume.pubkey(), &hash).await?;
+                let fetched: Vec<_> = stream.collect().await;
+                let fetched: Vec<u8> = fetched.into_iter().collect::<Result<Vec<_>, _>>()?.concat();
+                assert_eq!(fetched, data);
+            }
+            Ok(())
+        },
+    )
+    .await
+    .unwrap();
+    std::fs::remove_dir_all(&blobs).unwrap();
+}
+
+#[cfg(feature = "chaos")]
+#[tokio::test]
+async fn can_survive_ipfs_faults() {
+    with_service_options(
+        |options| {
+            options.ipfs = Some("http://127.0.0.1:5001".parse().unwrap());
+            options.chaos = Some("ipfs=1,delay=100".parse().unwrap());
+        },
+        |url| async move {
+            let client = Client::new();
+            let token = Uuid::new_v4().to_string();
+            let volume = Privkey::generate();
+            volume_create(&url, &client, &token, &volume).await?;
+            let manifest = Manifest {
+                generation: 0,
+                creation: 0,
+                path: PathBuf::from_str("/tmp/path").unwrap(),
+                machine: Uuid::new_v4(),
+                size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                parent: None,
+                data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                    .try_into()
+                    .unwrap(),
+            }
+            .sign(&volume);
+            snapshot_upload(&url, &client, &token, &volume.pubkey(), &manifest).await?;
+
+            // responses are delayed, IPFS timeouts are reported as gateway errors
+            let start = std::time::Instant::now();
+            let payload = url.join(&format!(
+                "/api/v1/volume/{}/{}/payload",
+                volume.pubkey(),
+                manifest.hash()
+            ))?;
+            let response = client
+                .get(payload)
+                .header("Authorization", format!("Bearer {token}"))
+                .send()
+                .await?;
+            assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+            assert!(start.elapsed() >= Duration::from_millis(100));
+            Ok(())
+        },
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_delete_account() {
+    let system = Uuid::new_v4().to_string();
+    let static_system = format!("{system}:{}", Uuid::new_v4());
+    with_service_options(
+        |options| options.static_system = vec![static_system.parse().unwrap()],
+        |url| async move {
+            let client = Client::new();
+            let account = Uuid::new_v4();
+            let token = account.to_string();
+            let volume = Privkey::generate();
+            volume_create(&url, &client, &token, &volume).await?;
+            let manifest = Manifest {
+                generation: 0,
+                creation: 0,
+                path: PathBuf::from_str("/tmp/path").unwrap(),
+                machine: Uuid::new_v4(),
+                size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                parent: None,
+                data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                    .try_into()
+                    .unwrap(),
+            }
+            .sign(&volume);
+            snapshot_upload(&url, &client, &token, &volume.pubkey(), &manifest).await?;
+            let other = Privkey::generate();
+            let other_token = Uuid::new_v4().to_string();
+            volume_create(&url, &client, &other_token, &other).await?;
+
+            // users cannot delete accounts, not even their own
+            assert!(matches!(
+                account_delete(&url, &client, &token, &account).await,
+                Err(Error::Unsuccessful(StatusCode::FORBIDDEN))
+            ));
+
+            let deleted = account_delete(&url, &client, &system, &account).await?;
+            assert_eq!(deleted.volumes, vec![volume.pubkey()]);
+            assert_eq!(deleted.snapshots, 1);
+            assert!(volume_get(&url, &client, &token, &volume.pubkey())
+                .await
+                .is_err());
+            volume_get(&url, &client, &other_token, &other.pubkey()).await?;
+
+            // the volume can be created again from scratch
+            volume_create(&url, &client, &token, &volume).await?;
+            let snapshots =
+                snapshot_list(&url, &client, &token, &volume.pubkey(), None, false).await?;
+            assert!(snapshots.is_empty());
+            Ok(())
+        },
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_search_snapshots_by_machine() {
+    with_service(|url| async move {
+        let client = Client::new();
+        let token = Uuid::new_v4().to_string();
+        let other_token = Uuid::new_v4().to_string();
+        let machine = Uuid::new_v4();
+        let manifest = |machine, creation| Manifest {
+            generation: 0,
+            creation,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            machine,
+            size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            parent: None,
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        };
+
+        // snapshots of the machine in two volumes, and of another machine and account
+        let mut uploaded = vec![];
+        for (token, machine, creation) in [
+            (&token, machine, 100),
+            (&token, machine, 200),
+            (&token, Uuid::new_v4(), 300),
+            (&other_token, machine, 400),
+        ] {
+            let volume = Privkey::generate();
+            volume_create(&url, &client, token, &volume).await?;
+            let manifest = manifest(machine, creation).sign(&volume);
+            snapshot_upload(&url, &client, token, &volume.pubkey(), &manifest).await?;
+            uploaded.push((volume.pubkey(), manifest.hash()));
+        }
+
+        let found = snapshot_search_machine(&url, &client, &token, &machine, None).await?;
+        let found: Vec<_> = found
+            .iter()
+            .map(|snapshot| (snapshot.volume, snapshot.hash, snapshot.creation))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (uploaded[1].0, uploaded[1].1, 200),
+                (uploaded[0].0, uploaded[0].1, 100)
+            ]
+        );
+        let found = snapshot_search_machine(&url, &client, &token, &machine, Some(1)).await?;
+        assert_eq!(found.len(), 1);
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_restrict_volumes_to_account() {
+    let system = Uuid::new_v4().to_string();
+    let static_system = format!("{system}:{}", Uuid::new_v4());
+    with_service_options(
+        |options| options.static_system = vec![static_system.parse().unwrap()],
+        |url| async move {
+            let client = Client::new();
+            let token = Uuid::new_v4().to_string();
+            let other = Uuid::new_v4().to_string();
+            let volume = Privkey::generate();
+            volume_create(&url, &client, &token, &volume).await?;
+            let manifest = Manifest {
+                generation: 0,
+                creation: 0,
+                path: PathBuf::from_str("/tmp/path").unwrap(),
+                machine: Uuid::new_v4(),
+                size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                parent: None,
+                data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                    .try_into()
+                    .unwrap(),
+            }
+            .sign(&volume);
+            snapshot_upload(&url, &client, &token, &volume.pubkey(), &manifest).await?;
+
+            // volumes of other accounts look like they do not exist
+            let not_found =
+                |result: Result<_, Error>| matches!(result, Err(Error::VolumeNotFound(_)));
+            assert!(not_found(
+                volume_get(&url, &client, &other, &volume.pubkey())
+                    .await
+                    .map(|_| ())
+            ));
+            let edit = VolumeEdit {
+                writer: Field::Missing,
+                account: None,
+                lock: None,
+                retain_count: Field::Present(Some(1)),
+                retain_age: Field::Missing,
+                worm_period: Field::Missing,
+                storage_class: None,
+                pq_pubkey: None,
+            };
+            assert!(not_found(
+                volume_edit(&url, &client, &other, &volume, &edit).await
+            ));
+            assert!(not_found(
+                snapshot_list(&url, &client, &other, &volume.pubkey(), None, false)
+                    .await
+                    .map(|_| ())
+            ));
+            assert!(not_found(
+                snapshot_exists(&url, &client, &other, &volume.pubkey(), &[manifest.hash()])
+                    .await
+                    .map(|_| ())
+            ));
+            assert!(not_found(
+                snapshot_fetch(&url, &client, &other, &volume.pubkey(), &manifest.hash())
+                    .await
+                    .map(|_| ())
+            ));
+            let other_manifest = Manifest {
+                generation: 1,
+                ..manifest.manifest.clone()
+            }
+            .sign(&volume);
+            assert!(not_found(
+                snapshot_upload(&url, &client, &other, &volume.pubkey(), &other_manifest)
+                    .await
+                    .map(|_| ())
+            ));
+            let info = volume_get(&url, &client, &token, &volume.pubkey()).await?;
+            assert_eq!(info.retain_count, None);
+            assert_eq!(info.snapshot_count, 1);
+
+            // system tokens can access volumes of any account
+            let info = volume_get(&url, &client, &system, &volume.pubkey()).await?;
+            assert_eq!(info.snapshot_count, 1);
+            let fetched =
+                snapshot_fetch(&url, &client, &system, &volume.pubkey(), &manifest.hash()).await?;
+            assert_eq!(fetched, manifest);
+            Ok(())
+        },
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_share_volumes() {
+    with_service(|url| async move {
+        let client = Client::new();
+        let token = Uuid::new_v4().to_string();
+        let reader = Uuid::new_v4();
+        let reader_token = reader.to_string();
+        let writer = Uuid::new_v4();
+        let writer_token = writer.to_string();
+        let volume = Privkey::generate();
+        volume_create(&url, &client, &token, &volume).await?;
+        let manifest = |generation, parent: Option<&ManifestSigned>| {
+            let size = crate::snapshot::MINIMUM_SNAPSHOT_SIZE;
+            Manifest {
+                generation,
+                creation: 0,
+                path: PathBuf::from_str("/tmp/path").unwrap(),
+                machine: Uuid::nil(),
+                size,
+                size_total: size * (generation + 1),
+                parent: parent.map(|parent| Parent {
+                    hash: parent.hash(),
+                    volume: None,
+                }),
+                data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                    .try_into()
+                    .unwrap(),
+            }
+            .sign(&volume)
+        };
+        let root = manifest(0, None);
+        snapshot_upload(&url, &client, &token, &volume.pubkey(), &root).await?;
+
+        // without a grant, the volume does not exist for other accounts
+        assert!(matches!(
+            snapshot_list(&url, &client, &reader_token, &volume.pubkey(), None, false).await,
+            Err(Error::VolumeNotFound(_))
+        ));
+
+        for (account, access) in [(reader, VolumeAccess::Read), (writer, VolumeAccess::Write)] {
+            let grant = VolumeGrant { account, access };
+            volume_acl_grant(&url, &client, &token, &volume.pubkey(), &grant).await?;
+        }
+        let grants = volume_acl_list(&url, &client, &token, &volume.pubkey()).await?;
+        assert_eq!(grants.len(), 2);
+
+        // readers can fetch snapshots but not upload them
+        let snapshots =
+            snapshot_list(&url, &client, &reader_token, &volume.pubkey(), None, false).await?;
+        assert_eq!(snapshots, vec![root.hash()]);
+        snapshot_fetch(&url, &client, &reader_token, &volume.pubkey(), &root.hash()).await?;
+        let child = manifest(1, Some(&root));
+        assert!(matches!(
+            snapshot_upload(&url, &client, &reader_token, &volume.pubkey(), &child).await,
+            Err(Error::Unsuccessful(StatusCode::FORBIDDEN))
+        ));
+
+        // writers can upload, but only the owner can manage the volume
+        snapshot_upload(&url, &client, &writer_token, &volume.pubkey(), &child).await?;
+        let upload =
+            upload_token_create(&url, &client, &writer_token, &volume.pubkey(), None).await?;
+        assert_eq!(whoami(&url, &client, &upload.token).await?.account, writer);
+        let grant = VolumeGrant {
+            account: Uuid::new_v4(),
+            access: VolumeAccess::Write,
+        };
+        assert!(matches!(
+            volume_acl_grant(&url, &client, &writer_token, &volume.pubkey(), &grant).await,
+            Err(Error::Unsuccessful(StatusCode::FORBIDDEN))
+        ));
+        assert!(matches!(
+            volume_acl_list(&url, &client, &writer_token, &volume.pubkey()).await,
+            Err(Error::Unsuccessful(StatusCode::FORBIDDEN))
+        ));
+
+        // revoked grants no longer give access
+        volume_acl_revoke(&url, &client, &token, &volume.pubkey(), &reader).await?;
+        assert!(matches!(
+            volume_acl_revoke(&url, &client, &token, &volume.pubkey(), &reader).await,
+            Err(Error::Unsuccessful(StatusCode::NOT_FOUND))
+        ));
+        assert!(matches!(
+            snapshot_list(&url, &client, &reader_token, &volume.pubkey(), None, false).await,
+            Err(Error::VolumeNotFound(_))
+        ));
+
+        // as do upload tokens minted with them
+        volume_acl_revoke(&url, &client, &token, &volume.pubkey(), &writer).await?;
+        let grandchild = manifest(2, Some(&child));
+        assert!(matches!(
+            snapshot_upload(&url, &client, &upload.token, &volume.pubkey(), &grandchild).await,
+            Err(Error::VolumeNotFound(_))
+        ));
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_record_drills() {
+    with_service(|url| async move {
+        let client = Client::new();
+        let token = Uuid::new_v4().to_string();
+        let volume = Privkey::generate();
+        volume_create(&url, &client, &token, &volume).await?;
+        let manifest = Manifest {
+            generation: 0,
+            creation: 0,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            machine: Uuid::new_v4(),
+            size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            parent: None,
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        }
+        .sign(&volume);
+        snapshot_upload(&url, &client, &token, &volume.pubkey(), &manifest).await?;
+
+        // volume was never verified
+        let report = account_drills(&url, &client, &token).await?;
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].volume, volume.pubkey());
+        assert_eq!(report[0].verified, None);
+
+        let result = DrillResult {
+            snapshot: manifest.hash(),
+            outcome: DrillOutcome::Success,
+            duration: 2500,
+            message: Some("restored 64 bytes".into()),
+        };
+        let drill = drill_record(&url, &client, &token, &volume.pubkey(), &result).await?;
+        assert_eq!(drill.snapshot, manifest.hash());
+        assert_eq!(drill.duration, 2500);
+        assert_eq!(
+            drill_list(&url, &client, &token, &volume.pubkey()).await?,
+            vec![drill.clone()]
+        );
+        let report = account_drills(&url, &client, &token).await?;
+        assert_eq!(report[0].verified, Some(drill.time));
+        assert_eq!(report[0].snapshot, Some(manifest.hash()));
+        assert_eq!(report[0].latest, Some(DrillOutcome::Success));
+
+        // drills are only recorded for existing snapshots, by the owner of the volume
+        let missing = DrillResult {
+            snapshot: Hash::generate(&[1, 2, 3]),
+            ..result.clone()
+        };
+        assert!(matches!(
+            drill_record(&url, &client, &token, &volume.pubkey(), &missing).await,
+            Err(Error::SnapshotNotFound(_))
+        ));
+        let other = Uuid::new_v4().to_string();
+        assert!(matches!(
+            drill_record(&url, &client, &other, &volume.pubkey(), &result).await,
+            Err(Error::VolumeNotFound(_))
+        ));
+        assert!(account_drills(&url, &client, &other).await?.is_empty());
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_get_admin_stats() {
+    let system = Uuid::new_v4().to_string();
+    let static_system = format!("{system}:{}", Uuid::new_v4());
+    with_service_options(
+        |options| options.static_system = vec![static_system.parse().unwrap()],
+        |url| async move {
+            let client = Client::new();
+            let token = Uuid::new_v4().to_string();
+            let volume = Privkey::generate();
+            volume_create(&url, &client, &token, &volume).await?;
+            volume_create(&url, &client, &token, &Privkey::generate()).await?;
+            let manifest = Manifest {
+                generation: 0,
+                creation: now(),
+                path: PathBuf::from_str("/tmp/path").unwrap(),
+                machine: Uuid::new_v4(),
+                size: 1000,
+                size_total: 1000,
+                parent: None,
+                data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                    .try_into()
+                    .unwrap(),
+            }
+            .sign(&volume);
+            snapshot_upload(&url, &client, &token, &volume.pubkey(), &manifest).await?;
+
+            // users cannot see global statistics
+            assert!(matches!(
+                admin_stats(&url, &client, &token, 7).await,
+                Err(Error::Unsuccessful(StatusCode::FORBIDDEN))
+            ));
+
+            let stats = admin_stats(&url, &client, &system, 7).await?;
+            assert_eq!(stats.volumes, 2);
+            assert_eq!(stats.accounts, 1);
+            assert_eq!(stats.snapshots, 1);
+            assert_eq!(stats.bytes, 1000);
+            assert_eq!(stats.daily.len(), 7);
+            let today = stats.daily.last().unwrap();
+            assert_eq!(today.day, now() / 86400 * 86400);
+            assert_eq!(today.snapshots, 1);
+            assert_eq!(today.bytes, 1000);
+            assert!(stats.daily[..6].iter().all(|day| day.snapshots == 0));
+            Ok(())
+        },
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_list_snapshots_ordered() {
+    with_service(|url| async move {
+        let client = Client::new();
+        let token = Uuid::new_v4().to_string();
+        let volume = Privkey::generate();
+        volume_create(&url, &client, &token, &volume).await?;
+        let size = crate::snapshot::MINIMUM_SNAPSHOT_SIZE;
+        let mut hashes = vec![];
+        let mut parent: Option<Hash> = None;
+        let mut size_total = 0;
+        for (generation, (creation, factor)) in [(20, 3), (30, 1), (10, 2)].iter().enumerate() {
+            size_total += factor * size;
+            let manifest = Manifest {
+                generation: generation as u64,
+                creation: *creation,
+                path: PathBuf::from_str("/tmp/path").unwrap(),
+                machine: Uuid::nil(),
+                size: factor * size,
+                size_total,
+                parent: parent.map(Parent::new),
+                data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                    .try_into()
+                    .unwrap(),
+            }
+            .sign(&volume);
+            snapshot_upload(&url, &client, &token, &volume.pubkey(), &manifest).await?;
+            parent = Some(manifest.hash());
+            hashes.push(manifest.hash());
+        }
+
+        assert_eq!(
+            snapshot_list(&url, &client, &token, &volume.pubkey(), None, false).await?,
+            hashes
+        );
+        let cases = [
+            (SnapshotOrder::Generation, SortDirection::Desc, [2, 1, 0]),
+            (SnapshotOrder::Creation, SortDirection::Asc, [2, 0, 1]),
+            (SnapshotOrder::Size, SortDirection::Desc, [0, 2, 1]),
+        ];
+        for (order, direction, expected) in cases {
+            let ordering = SnapshotOrdering { order, direction };
+            let listed = snapshot_list_ordered(
+                &url,
+                &client,
+                &token,
+                &volume.pubkey(),
+                None,
+                false,
+                &ordering,
+            )
+            .await?;
+            let expected: Vec<Hash> = expected.iter().map(|index| hashes[*index]).collect();
+            assert_eq!(listed, expected);
+        }
+
+        // unknown orders are rejected
+        let response = client
+            .get(url.join(&format!(
+                "/api/v1/volume/{}/snapshots?order=name",
+                volume.pubkey().to_hex()
+            ))?)
+            .bearer_auth(&token)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_upload_concurrent_snapshots() {
+    // several connections, so that the upload transactions overlap. the unique constraint
+    // that catches uploads racing past the generation check is covered by the tests of the
+    // snapshot module.
+    with_service_options(
+        |options| options.db_max_connections = 8,
+        |url| async move {
+            let client = Client::new();
+            let token = Uuid::new_v4().to_string();
+            let volume = Privkey::generate();
+            volume_create(&url, &client, &token, &volume).await?;
+
+            // conflicting manifests for the same generation, racing each other
+            let manifests: Vec<ManifestSigned> = (0..8)
+                .map(|index| {
+                    Manifest {
+                        generation: 0,
+                        creation: index,
+                        path: PathBuf::from_str("/tmp/path").unwrap(),
+                        machine: Uuid::nil(),
+                        size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                        size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                        parent: None,
+                        data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                            .try_into()
+                            .unwrap(),
+                    }
+                    .sign(&volume)
+                })
+                .collect();
+            let uploads = manifests
+                .iter()
+                .map(|manifest| snapshot_upload(&url, &client, &token, &volume.pubkey(), manifest));
+            let results = rocket::futures::future::join_all(uploads).await;
+            assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+            for result in results.iter().filter(|result| result.is_err()) {
+                assert!(matches!(result, Err(Error::ManifestExists(_))));
+            }
+            let snapshots =
+                snapshot_list(&url, &client, &token, &volume.pubkey(), None, false).await?;
+            assert_eq!(snapshots.len(), 1);
+
+            // identical manifests racing each other all succeed
+            let mut manifest = manifests[0].manifest.clone();
+            manifest.generation = 1;
+            manifest.parent = Some(Parent::new(snapshots[0]));
+            manifest.size_total = 2 * crate::snapshot::MINIMUM_SNAPSHOT_SIZE;
+            let manifest = manifest.sign(&volume);
+            let uploads =
+                (0..8).map(|_| snapshot_upload(&url, &client, &token, &volume.pubkey(), &manifest));
+            let results = rocket::futures::future::join_all(uploads).await;
+            assert!(results.iter().all(|result| result.is_ok()));
+            Ok(())
+        },
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_pin_pq_pubkey() {
+    with_service(|url| async move {
+        let client = Client::new();
+        let token = Uuid::new_v4().to_string();
+        let volume = Privkey::generate();
+        volume_create(&url, &client, &token, &volume).await?;
+        let manifest = Manifest {
+            generation: 0,
+            creation: 0,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            machine: Uuid::nil(),
+            size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            parent: None,
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        };
+        let pin = |key: &str| VolumeEdit {
+            writer: Field::Missing,
+            account: None,
+            lock: None,
+            retain_count: Field::Missing,
+            retain_age: Field::Missing,
+            worm_period: Field::Missing,
+            storage_class: None,
+            pq_pubkey: Some(key.to_string()),
+        };
+
+        #[cfg(feature = "pq")]
+        let (signer, key) = {
+            let signer = HybridSigner::generate(&volume);
+            let key = hex::encode(signer.public_key());
+            (signer, key)
+        };
+        #[cfg(not(feature = "pq"))]
+        let key = hex::encode([7u8; 32]);
+
+        assert!(matches!(
+            volume_edit(&url, &client, &token, &volume, &pin("not hex")).await,
+            Err(Error::Unsuccessful(StatusCode::BAD_REQUEST))
+        ));
+        volume_edit(&url, &client, &token, &volume, &pin(&key)).await?;
+        let info = volume_get(&url, &client, &token, &volume.pubkey()).await?;
+        assert_eq!(info.pq_pubkey, Some(key.clone()));
+
+        // pinning the same key again is fine, it cannot be replaced
+        volume_edit(&url, &client, &token, &volume, &pin(&key)).await?;
+        assert!(matches!(
+            volume_edit(
+                &url,
+                &client,
+                &token,
+                &volume,
+                &pin(&hex::encode([8u8; 32]))
+            )
+            .await,
+            Err(Error::Unsuccessful(StatusCode::CONFLICT))
+        ));
+
+        // plain signatures are not accepted once a key is pinned
+        assert!(matches!(
+            snapshot_upload(
+                &url,
+                &client,
+                &token,
+                &volume.pubkey(),
+                &manifest.sign(&volume)
+            )
+            .await,
+            Err(Error::Unsuccessful(StatusCode::BAD_REQUEST))
+        ));
+
+        // nor are hybrid signatures carrying another key
+        #[cfg(feature = "pq")]
+        {
+            let other = HybridSigner::generate(&volume);
+            assert!(matches!(
+                snapshot_upload(
+                    &url,
+                    &client,
+                    &token,
+                    &volume.pubkey(),
+                    &manifest.sign_with(&other)
+                )
+                .await,
+                Err(Error::Unsuccessful(StatusCode::BAD_REQUEST))
+            ));
+            let signed = manifest.sign_with(&signer);
+            snapshot_upload(&url, &client, &token, &volume.pubkey(), &signed).await?;
+            let fetched =
+                snapshot_fetch(&url, &client, &token, &volume.pubkey(), &signed.hash()).await?;
+            assert_eq!(fetched, signed);
+        }
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn cannot_upload_forged_manifest() {
+    with_service(|url| async move {
+        let client = Client::new();
+        let token = Uuid::new_v4().to_string();
+        let volume = Privkey::generate();
+        volume_create(&url, &client, &token, &volume).await?;
+
+        let mut manifest = Manifest {
+            generation: 0,
+            creation: 0,
+            path: PathBuf::from_str("/tmp/path").unwrap(),
+            machine: Uuid::nil(),
+            size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            size_total: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            parent: None,
+            data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                .try_into()
+                .unwrap(),
+        }
+        .sign(&volume);
+        manifest.signature[0] ^= 1;
+        assert!(matches!(
+            snapshot_upload(&url, &client, &token, &volume.pubkey(), &manifest).await,
+            Err(Error::Unsuccessful(StatusCode::BAD_REQUEST))
+        ));
+
+        // signed with the key of another volume
+        let manifest = manifest.manifest.sign(&Privkey::generate());
+        assert!(matches!(
+            snapshot_upload(&url, &client, &token, &volume.pubkey(), &manifest).await,
+            Err(Error::Unsuccessful(StatusCode::BAD_REQUEST))
+        ));
+        let snapshots = snapshot_list(&url, &client, &token, &volume.pubkey(), None, false).await?;
+        assert!(snapshots.is_empty());
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_configure_alerts() {
+    let system = Uuid::new_v4().to_string();
+    let static_system = format!("{system}:{}", Uuid::new_v4());
+    with_service_options(
+        |options| options.static_system = vec![static_system.parse().unwrap()],
+        |url| async move {
+            let client = Client::new();
+            let token = Uuid::new_v4().to_string();
+            assert_eq!(
+                account_settings(&url, &client, &token).await?,
+                AccountSettings::default()
+            );
+            let settings = AccountSettings {
+                alerts: AlertSettings {
+                    volume_size: Some(500 * 1000 * 1000 * 1000),
+                    snapshot_count: Some(10000),
+                    size_anomaly: Some(5),
+                    webhook: Some(Url::parse("https://example.com/alerts")?),
+                },
+            };
+            account_settings_set(&url, &client, &token, &settings).await?;
+            assert_eq!(account_settings(&url, &client, &token).await?, settings);
+            assert!(account_alerts(&url, &client, &token).await?.is_empty());
+
+            // webhooks must be http(s) URLs of public hosts
+            for webhook in [
+                "file:///etc/passwd",
+                "http://127.0.0.1:8000/",
+                "http://10.0.0.1/",
+            ] {
+                let mut invalid = settings.clone();
+                invalid.alerts.webhook = Some(Url::parse(webhook)?);
+                let result = account_settings_set(&url, &client, &token, &invalid).await;
+                assert!(matches!(
+                    result,
+                    Err(Error::Unsuccessful(StatusCode::BAD_REQUEST))
+                ));
+            }
+
+            let metrics = client
+                .get(url.join("/metrics")?)
+                .bearer_auth(&system)
+                .send()
+                .await?
+                .text()
+                .await?;
+            assert!(metrics.contains("storage_alerts_active 0"));
+            Ok(())
+        },
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_warn_size_anomaly() {
+    with_service(|url| async move {
+        let client = Client::new();
+        let token = Uuid::new_v4().to_string();
+        let volume = Privkey::generate();
+        volume_create(&url, &client, &token, &volume).await?;
+        let mut parent = None;
+        let mut size_total = 0;
+        for generation in 0..4 {
+            let size = match generation {
+                3 => 100 * crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                _ => crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+            };
+            size_total += size;
+            let manifest = Manifest {
+                generation,
+                creation: 0,
+                path: PathBuf::from_str("/tmp/path").unwrap(),
+                machine: Uuid::new_v4(),
+                size,
+                size_total,
+                parent: parent.map(Parent::new),
+                data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                    .try_into()
+                    .unwrap(),
+            }
+            .sign(&volume);
+            let uploaded =
+                snapshot_upload(&url, &client, &token, &volume.pubkey(), &manifest).await?;
+            parent = Some(manifest.hash());
+            let anomaly = uploaded
+                .warnings
+                .iter()
+                .any(|warning| matches!(warning, Warning::SizeAnomaly { .. }));
+            assert_eq!(anomaly, generation == 3);
+        }
+        Ok(())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_fetch_manifest_batch() {
+    with_service(|url| async move {
+        let client = Client::new();
+        let token = Uuid::new_v4().to_string();
+        let volume = Privkey::generate();
+        volume_create(&url, &client, &token, &volume).await?;
+
+        let mut manifests = vec![];
+        let mut parent = None;
+        for generation in 0..4 {
+            let manifest = Manifest {
+                generation,
+                creation: 0,
+                path: PathBuf::from_str("/tmp/path").unwrap(),
+                machine: Uuid::nil(),
+                size: crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                size_total: (generation + 1) * crate::snapshot::MINIMUM_SNAPSHOT_SIZE,
+                parent: parent.map(Parent::new),
+                data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+                    .try_into()
+                    .unwrap(),
+            }
+            .sign(&volume);
+            snapshot_upload(&url, &client, &token, &volume.pubkey(), &manifest).await?;
+            parent = Some(manifest.hash());
+            manifests.push(manifest);
+        }
+
+        // manifests come back in request order, missing ones are left out
+        let hashes = vec![
+            manifests[3].hash(),
+            Hash::generate(&[1, 2, 3]),
+            manifests[1].hash(),
+            manifests[0].hash(),
+        ];
+        let fetched =
+            snapshot_fetch_batch(&url, &client, &token, &volume.pubkey(), &hashes).await?;
+        assert_eq!(
+            fetched,
+            vec![
+                manifests[3].clone(),
+                manifests[1].clone(),
+                manifests[0].clone()
+            ]
+        );
 
+        // only the owner of the volume can fetch them
+        let other = Uuid::new_v4().to_string();
+        let result = snapshot_fetch_batch(&url, &client, &other, &volume.pubkey(), &hashes).await;
+        assert!(matches!(result, Err(Error::VolumeNotFound(_))));
         Ok(())
     })
     .await
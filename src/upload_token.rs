@@ -0,0 +1,262 @@
+use fractal_storage_client::{Hash, Pubkey};
+use hmac::{Hmac, Mac};
+use rand::{thread_rng, RngCore};
+use rocket::http::Method;
+use sha2::Sha256;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Prefix of upload tokens, tells them apart from API keys, JWTs and static tokens.
+pub const UPLOAD_TOKEN_PREFIX: &str = "fsu_";
+
+/// Lifetime of upload tokens if none is requested, in seconds.
+pub const UPLOAD_TOKEN_TTL_DEFAULT: u64 = 60 * 60;
+
+/// Longest lifetime of upload tokens, in seconds. Upload tokens cannot be revoked, so they
+/// should be short-lived.
+pub const UPLOAD_TOKEN_TTL_MAX: u64 = 7 * 24 * 60 * 60;
+
+#[derive(thiserror::Error, Debug)]
+pub enum UploadTokenError {
+    #[error("Malformed upload token")]
+    Malformed,
+    #[error("Invalid upload token signature")]
+    Signature,
+    #[error("Upload token has expired")]
+    Expired,
+    #[error("Invalid lifetime {0:?}, use a number of seconds or a duration like 30m, 1h or 1d")]
+    InvalidTtl(String),
+    #[error("Lifetime exceeds the maximum of {} seconds", UPLOAD_TOKEN_TTL_MAX)]
+    TtlTooLong,
+}
+
+/// Permission to upload snapshots to a single volume, granted by an upload token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UploadGrant {
+    /// Account that minted the token, which uploads are made as.
+    pub account: Uuid,
+    pub volume: Pubkey,
+    pub expires: u64,
+}
+
+impl UploadGrant {
+    /// Determines if this grant allows a request, only snapshot and payload uploads to the
+    /// volume are allowed, besides asking who the token belongs to.
+    pub fn allows(&self, method: Method, path: &str) -> bool {
+        if method == Method::Get && path == "/api/v1/whoami" {
+            return true;
+        }
+        let mut segments = path.split('/').skip_while(|segment| *segment != "volume");
+        segments.next();
+        match segments.next().map(Pubkey::from_str) {
+            Some(Ok(volume)) if volume == self.volume => {}
+            _ => return false,
+        }
+        match (method, segments.next(), segments.next(), segments.next()) {
+            (Method::Post, Some("snapshot" | "snapshots"), None, _) => true,
+            (Method::Put, Some(_), Some("data"), None) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Mints and verifies upload tokens. Tokens are verified statelessly using an HMAC, they
+/// stay valid until they expire or the key is changed. The same key signs pre-signed URLs
+/// for fetching manifests.
+#[derive(Clone)]
+pub struct UploadTokens {
+    key: Vec<u8>,
+}
+
+impl UploadTokens {
+    pub fn new(key: &[u8]) -> Self {
+        UploadTokens { key: key.to_vec() }
+    }
+
+    /// Use a random key, tokens are only valid until the service is restarted.
+    pub fn generate() -> Self {
+        let mut key = [0; 32];
+        thread_rng().fill_bytes(&mut key);
+        UploadTokens::new(&key)
+    }
+
+    fn mac(&self, payload: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).unwrap();
+        mac.update(payload.as_bytes());
+        mac
+    }
+
+    /// Mint a token allowing uploads to the volume until the expiry time.
+    pub fn mint(&self, grant: &UploadGrant) -> String {
+        let payload = format!(
+            "{UPLOAD_TOKEN_PREFIX}{}.{}.{}",
+            grant.volume.to_hex(),
+            grant.account,
+            grant.expires
+        );
+        let signature = hex::encode(self.mac(&payload).finalize().into_bytes());
+        format!("{payload}.{signature}")
+    }
+
+    /// Verify a token, returning what it grants if it is valid at the given time.
+    pub fn verify(&self, token: &str, now: u64) -> Result<UploadGrant, UploadTokenError> {
+        let (payload, signature) = token.rsplit_once('.').ok_or(UploadTokenError::Malformed)?;
+        let signature = hex::decode(signature).map_err(|_| UploadTokenError::Malformed)?;
+        self.mac(payload)
+            .verify_slice(&signature)
+            .map_err(|_| UploadTokenError::Signature)?;
+        let mut fields = payload
+            .strip_prefix(UPLOAD_TOKEN_PREFIX)
+            .ok_or(UploadTokenError::Malformed)?
+            .split('.');
+        let (volume, account, expires) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(volume), Some(account), Some(expires)) => (volume, account, expires),
+            _ => return Err(UploadTokenError::Malformed),
+        };
+        let grant = UploadGrant {
+            volume: Pubkey::from_str(volume).map_err(|_| UploadTokenError::Malformed)?,
+            account: Uuid::from_str(account).map_err(|_| UploadTokenError::Malformed)?,
+            expires: expires.parse().map_err(|_| UploadTokenError::Malformed)?,
+        };
+        if grant.expires <= now {
+            return Err(UploadTokenError::Expired);
+        }
+        Ok(grant)
+    }
+
+    fn presign_payload(volume: &Pubkey, snapshot: &Hash, expires: u64) -> String {
+        format!("fetch.{}.{}.{expires}", volume.to_hex(), snapshot.to_hex())
+    }
+
+    /// Sign a URL for fetching the manifest of a snapshot without a bearer token, returning
+    /// the signature to put into the URL.
+    pub fn presign(&self, volume: &Pubkey, snapshot: &Hash, expires: u64) -> String {
+        let payload = Self::presign_payload(volume, snapshot, expires);
+        hex::encode(self.mac(&payload).finalize().into_bytes())
+    }
+
+    /// Verify the signature of a pre-signed URL at the given time.
+    pub fn presigned_verify(
+        &self,
+        volume: &Pubkey,
+        snapshot: &Hash,
+        expires: u64,
+        signature: &str,
+        now: u64,
+    ) -> Result<(), UploadTokenError> {
+        let signature = hex::decode(signature).map_err(|_| UploadTokenError::Malformed)?;
+        self.mac(&Self::presign_payload(volume, snapshot, expires))
+            .verify_slice(&signature)
+            .map_err(|_| UploadTokenError::Signature)?;
+        if expires <= now {
+            return Err(UploadTokenError::Expired);
+        }
+        Ok(())
+    }
+}
+
+/// Parse the lifetime of an upload token, either in seconds or with a unit such as `30m`,
+/// `1h` or `1d`.
+pub fn parse_ttl(ttl: &str) -> Result<u64, UploadTokenError> {
+    let invalid = || UploadTokenError::InvalidTtl(ttl.to_string());
+    let (number, unit) = match ttl.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => ttl.split_at(index),
+        None => (ttl, "s"),
+    };
+    let unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    let ttl = number
+        .parse::<u64>()
+        .map_err(|_| invalid())?
+        .checked_mul(unit)
+        .ok_or_else(invalid)?;
+    if ttl > UPLOAD_TOKEN_TTL_MAX {
+        return Err(UploadTokenError::TtlTooLong);
+    }
+    Ok(ttl)
+}
+
+#[test]
+fn test_upload_token() {
+    let tokens = UploadTokens::generate();
+    let grant = UploadGrant {
+        account: Uuid::new_v4(),
+        volume: fractal_storage_client::Privkey::generate().pubkey(),
+        expires: 1000,
+    };
+    let token = tokens.mint(&grant);
+    assert!(token.starts_with(UPLOAD_TOKEN_PREFIX));
+    assert_eq!(tokens.verify(&token, 999).unwrap(), grant);
+    assert!(matches!(
+        tokens.verify(&token, 1000),
+        Err(UploadTokenError::Expired)
+    ));
+
+    // tokens from other keys or with altered fields are rejected
+    assert!(matches!(
+        UploadTokens::generate().verify(&token, 999),
+        Err(UploadTokenError::Signature)
+    ));
+    let altered = token.replacen(".1000.", ".2000.", 1);
+    assert!(matches!(
+        tokens.verify(&altered, 999),
+        Err(UploadTokenError::Signature)
+    ));
+    assert!(tokens.verify("fsu_invalid", 999).is_err());
+
+    // only uploads to the volume are allowed
+    let volume = grant.volume.to_hex();
+    let other = fractal_storage_client::Privkey::generate()
+        .pubkey()
+        .to_hex();
+    assert!(grant.allows(Method::Post, &format!("/api/v1/volume/{volume}/snapshot")));
+    assert!(grant.allows(Method::Post, &format!("/api/v1/volume/{volume}/snapshots")));
+    assert!(grant.allows(Method::Put, &format!("/api/v1/volume/{volume}/abcd/data")));
+    assert!(!grant.allows(Method::Post, &format!("/api/v1/volume/{other}/snapshot")));
+    assert!(!grant.allows(Method::Get, &format!("/api/v1/volume/{volume}/snapshots")));
+    assert!(!grant.allows(Method::Delete, &format!("/api/v1/volume/{volume}")));
+    assert!(grant.allows(Method::Get, "/api/v1/whoami"));
+    assert!(!grant.allows(
+        Method::Post,
+        &format!("/api/v1/volume/{volume}/upload-token")
+    ));
+}
+
+#[test]
+fn test_presign() {
+    let tokens = UploadTokens::generate();
+    let volume = fractal_storage_client::Privkey::generate().pubkey();
+    let snapshot = Hash::generate(&[1, 2, 3]);
+    let signature = tokens.presign(&volume, &snapshot, 1000);
+    assert!(tokens
+        .presigned_verify(&volume, &snapshot, 1000, &signature, 999)
+        .is_ok());
+    assert!(matches!(
+        tokens.presigned_verify(&volume, &snapshot, 1000, &signature, 1000),
+        Err(UploadTokenError::Expired)
+    ));
+    assert!(matches!(
+        tokens.presigned_verify(&volume, &snapshot, 2000, &signature, 999),
+        Err(UploadTokenError::Signature)
+    ));
+    let other = Hash::generate(&[4, 5, 6]);
+    assert!(tokens
+        .presigned_verify(&volume, &other, 1000, &signature, 999)
+        .is_err());
+}
+
+#[test]
+fn test_parse_ttl() {
+    assert_eq!(parse_ttl("90").unwrap(), 90);
+    assert_eq!(parse_ttl("30m").unwrap(), 30 * 60);
+    assert_eq!(parse_ttl("1h").unwrap(), 60 * 60);
+    assert_eq!(parse_ttl("2d").unwrap(), 2 * 24 * 60 * 60);
+    assert!(parse_ttl("1w").is_err());
+    assert!(parse_ttl("h").is_err());
+    assert!(parse_ttl("30d").is_err());
+}
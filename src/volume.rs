@@ -1,9 +1,13 @@
-use crate::snapshot::{SnapshotData, SnapshotError};
-use fractal_storage_client::{Pubkey, SnapshotInfo, VolumeEdit};
+use crate::snapshot::{SnapshotData, SnapshotError, ARCHIVE_DATA};
+use fractal_storage_client::{Pubkey, SnapshotInfo, StorageClass, VolumeEdit};
+use lru::LruCache;
 use optional_field::Field;
 use sqlx::any::AnyRow;
 use sqlx::{query, AnyConnection, Row};
 use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::instrument;
 use uuid::Uuid;
 
 /// Represents the primary key of a row in the storage_volume table
@@ -23,6 +27,18 @@ pub struct VolumeData {
     writer: Option<Uuid>,
     /// Prevent any changes to the volume in the database.
     locked: bool,
+    /// Time at which the volume was deleted, in seconds since the epoch.
+    deleted_at: Option<u64>,
+    /// Maximum number of snapshots to keep.
+    retain_count: Option<u64>,
+    /// Maximum age of snapshots to keep, in seconds.
+    retain_age: Option<u64>,
+    /// Period after their upload during which snapshots cannot be deleted, in seconds.
+    worm_period: Option<u64>,
+    /// Storage class of the volume.
+    storage_class: StorageClass,
+    /// Dilithium3 public key pinned for hybrid manifest signatures.
+    pq_pubkey: Option<Vec<u8>>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -35,6 +51,12 @@ pub enum VolumeError {
     ParseUuid(#[from] uuid::Error),
     #[error("Error parsing key: {0:}")]
     ParseKey(#[from] fractal_storage_client::keys::ParseError),
+    #[error("Write-once period can only be extended")]
+    WormShortened,
+    #[error("Invalid Dilithium3 public key")]
+    PqKeyInvalid,
+    #[error("A Dilithium3 public key is already pinned for the volume")]
+    PqKeyPinned,
 }
 
 impl VolumeData {
@@ -45,16 +67,57 @@ impl VolumeData {
         let account = Uuid::from_str(account)?;
         let writer: Option<&str> = row.try_get("volume_writer")?;
         let writer = writer.map(|w| Uuid::from_str(w)).transpose()?;
+        // storage classes this version does not know about are treated as hot
+        let storage_class: Option<String> = row.try_get("volume_storage_class")?;
+        let storage_class = storage_class
+            .and_then(|class| StorageClass::from_str(&class).ok())
+            .unwrap_or_default();
         Ok(VolumeData {
             id,
             pubkey: Pubkey::try_from(key)?,
             account,
             writer,
             locked: row.try_get("volume_locked")?,
+            deleted_at: row
+                .try_get::<Option<i64>, _>("volume_deleted_at")?
+                .map(|time| time as u64),
+            retain_count: row
+                .try_get::<Option<i64>, _>("volume_retain_count")?
+                .map(|count| count as u64),
+            retain_age: row
+                .try_get::<Option<i64>, _>("volume_retain_age")?
+                .map(|age| age as u64),
+            worm_period: row
+                .try_get::<Option<i64>, _>("volume_worm_period")?
+                .map(|period| period as u64),
+            storage_class,
+            pq_pubkey: row.try_get("volume_pq_pubkey")?,
         })
     }
 
-    pub async fn delete(&self, conn: &mut AnyConnection) -> Result<(), VolumeError> {
+    /// Mark the volume as deleted. It is hidden from lookups, but can be restored until it
+    /// is purged.
+    #[instrument(skip_all, fields(volume = %self.pubkey()))]
+    pub async fn delete(&self, conn: &mut AnyConnection, time: u64) -> Result<(), VolumeError> {
+        query("UPDATE storage_volume SET volume_deleted_at = ? WHERE volume_id = ?")
+            .bind(time as i64)
+            .bind(self.id)
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Restore a deleted volume.
+    pub async fn restore(&self, conn: &mut AnyConnection) -> Result<(), VolumeError> {
+        query("UPDATE storage_volume SET volume_deleted_at = NULL WHERE volume_id = ?")
+            .bind(self.id)
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Permanently delete the volume and all of its snapshots.
+    pub async fn purge(&self, conn: &mut AnyConnection) -> Result<(), VolumeError> {
         query("DELETE FROM storage_volume WHERE volume_id = ?")
             .bind(self.id)
             .execute(conn)
@@ -86,6 +149,55 @@ impl VolumeData {
         self.locked
     }
 
+    pub fn deleted_at(&self) -> Option<u64> {
+        self.deleted_at
+    }
+
+    pub fn retain_count(&self) -> Option<u64> {
+        self.retain_count
+    }
+
+    pub fn retain_age(&self) -> Option<u64> {
+        self.retain_age
+    }
+
+    pub fn worm_period(&self) -> Option<u64> {
+        self.worm_period
+    }
+
+    pub fn storage_class(&self) -> StorageClass {
+        self.storage_class
+    }
+
+    pub fn pq_pubkey(&self) -> Option<&[u8]> {
+        self.pq_pubkey.as_deref()
+    }
+
+    /// Time until which snapshots of this volume cannot be deleted, in seconds since the
+    /// epoch, if it has a write-once period and any snapshots.
+    pub async fn immutable_until(
+        &self,
+        conn: &mut AnyConnection,
+    ) -> Result<Option<u64>, VolumeError> {
+        let period = match self.worm_period {
+            Some(period) => period,
+            None => return Ok(None),
+        };
+        let row = query(
+            "SELECT MAX(snapshot_uploaded) AS uploaded FROM storage_snapshot WHERE volume_id = ?",
+        )
+        .bind(self.id)
+        .fetch_one(conn)
+        .await?;
+        let uploaded: Option<i64> = row.try_get("uploaded")?;
+        Ok(uploaded.map(|uploaded| (uploaded as u64).saturating_add(period)))
+    }
+
+    /// Whether any snapshot of this volume is still within its write-once period.
+    pub async fn immutable(&self, conn: &mut AnyConnection, now: u64) -> Result<bool, VolumeError> {
+        Ok(matches!(self.immutable_until(conn).await?, Some(until) if until > now))
+    }
+
     pub async fn register(
         &self,
         conn: &mut AnyConnection,
@@ -112,29 +224,47 @@ impl VolumeData {
         generation: u64,
         parent: Option<u64>,
     ) -> Result<Option<SnapshotData>, SnapshotError> {
-        let row = query(
-            "SELECT * FROM storage_snapshot
+        let statement = format!(
+            "SELECT *, {ARCHIVE_DATA} FROM storage_snapshot
                 WHERE volume_id = ?
                     AND snapshot_generation = ?
-                    AND snapshot_parent IS ?",
-        )
-        .bind(self.id as i64)
-        .bind(generation as i64)
-        .bind(parent.map(|parent| parent as i64))
-        .fetch_optional(conn)
-        .await
-        .unwrap();
+                    AND snapshot_parent IS ?"
+        );
+        let row = query(&statement)
+            .bind(self.id as i64)
+            .bind(generation as i64)
+            .bind(parent.map(|parent| parent as i64))
+            .fetch_optional(conn)
+            .await
+            .unwrap();
         match row {
             Some(row) => Ok(Some(SnapshotData::from_row(&row)?)),
             None => Ok(None),
         }
     }
 
+    #[instrument(skip_all, fields(volume = %self.pubkey()))]
     pub async fn edit(
         &self,
         conn: &mut AnyConnection,
         edit: &VolumeEdit,
     ) -> Result<(), VolumeError> {
+        if let Field::Present(value) = &edit.worm_period {
+            if &self.worm_period != value && !self.volume().worm_set(conn, *value).await? {
+                return Err(VolumeError::WormShortened);
+            }
+        }
+        if let Some(value) = &edit.pq_pubkey {
+            let key = hex::decode(value).map_err(|_| VolumeError::PqKeyInvalid)?;
+            if key.is_empty() {
+                return Err(VolumeError::PqKeyInvalid);
+            }
+            if self.pq_pubkey() != Some(&key[..])
+                && !self.volume().pq_pubkey_set(conn, &key).await?
+            {
+                return Err(VolumeError::PqKeyPinned);
+            }
+        }
         if let Field::Present(value) = &edit.writer {
             if &self.writer != value {
                 self.volume().writer_set(conn, value.as_ref()).await?;
@@ -150,11 +280,30 @@ impl VolumeData {
                 self.volume().locked_set(conn, *value).await?;
             }
         }
+        if let Some(value) = &edit.storage_class {
+            if &self.storage_class != value {
+                self.volume().storage_class_set(conn, *value).await?;
+            }
+        }
+        let retain_count = match &edit.retain_count {
+            Field::Present(value) => *value,
+            Field::Missing => self.retain_count,
+        };
+        let retain_age = match &edit.retain_age {
+            Field::Present(value) => *value,
+            Field::Missing => self.retain_age,
+        };
+        if (retain_count, retain_age) != (self.retain_count, self.retain_age) {
+            self.volume()
+                .retention_set(conn, retain_count, retain_age)
+                .await?;
+        }
         Ok(())
     }
 }
 
 impl Volume {
+    #[instrument(skip_all, fields(volume = %pubkey))]
     pub async fn create(
         conn: &mut AnyConnection,
         pubkey: &Pubkey,
@@ -173,13 +322,15 @@ impl Volume {
         ))
     }
 
+    #[instrument(skip_all, fields(volume = %pubkey))]
     pub async fn lookup(
         conn: &mut AnyConnection,
         pubkey: &Pubkey,
     ) -> Result<Option<VolumeData>, VolumeError> {
         let result = query(
             "SELECT * FROM storage_volume
-                WHERE volume_pubkey = ?",
+                WHERE volume_pubkey = ?
+                AND volume_deleted_at IS NULL",
         )
         .bind(pubkey.as_slice())
         .fetch_optional(conn)
@@ -191,11 +342,124 @@ impl Volume {
         }
     }
 
+    /// Look up a volume that was deleted but not purged yet.
+    pub async fn lookup_deleted(
+        conn: &mut AnyConnection,
+        pubkey: &Pubkey,
+    ) -> Result<Option<VolumeData>, VolumeError> {
+        let result = query(
+            "SELECT * FROM storage_volume
+                WHERE volume_pubkey = ?
+                AND volume_deleted_at IS NOT NULL",
+        )
+        .bind(pubkey.as_slice())
+        .fetch_optional(conn)
+        .await?;
+        if let Some(result) = result {
+            Ok(Some(VolumeData::from_row(&result)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// List volumes that have retention settings, colder storage classes first so that they
+    /// are pruned before volumes that are restored frequently.
+    pub async fn retained(conn: &mut AnyConnection) -> Result<Vec<VolumeData>, VolumeError> {
+        let rows = query(
+            "SELECT * FROM storage_volume
+                WHERE (volume_retain_count IS NOT NULL OR volume_retain_age IS NOT NULL)
+                AND volume_deleted_at IS NULL
+                ORDER BY CASE volume_storage_class
+                    WHEN 'archive' THEN 0
+                    WHEN 'cold' THEN 1
+                    ELSE 2
+                END, volume_id",
+        )
+        .fetch_all(conn)
+        .await?;
+        rows.iter().map(VolumeData::from_row).collect()
+    }
+
+    /// List all volumes of an account, including deleted ones.
+    pub async fn list_account(
+        conn: &mut AnyConnection,
+        account: &Uuid,
+    ) -> Result<Vec<VolumeData>, VolumeError> {
+        let rows = query("SELECT * FROM storage_volume WHERE account_id = ? ORDER BY volume_id")
+            .bind(account.to_string())
+            .fetch_all(conn)
+            .await?;
+        rows.iter().map(VolumeData::from_row).collect()
+    }
+
+    /// List volumes that were deleted before the given time.
+    pub async fn deleted_before(
+        conn: &mut AnyConnection,
+        time: u64,
+    ) -> Result<Vec<VolumeData>, VolumeError> {
+        let rows = query(
+            "SELECT * FROM storage_volume
+                WHERE volume_deleted_at IS NOT NULL
+                AND volume_deleted_at < ?",
+        )
+        .bind(time as i64)
+        .fetch_all(conn)
+        .await?;
+        rows.iter().map(VolumeData::from_row).collect()
+    }
+
+    /// Count the volumes that are not deleted, and the accounts owning them.
+    pub async fn totals(conn: &mut AnyConnection) -> Result<(u64, u64), VolumeError> {
+        let row = query(
+            "SELECT COUNT(*) AS volumes, COUNT(DISTINCT account_id) AS accounts
+                FROM storage_volume
+                WHERE volume_deleted_at IS NULL",
+        )
+        .fetch_one(conn)
+        .await?;
+        let volumes: i64 = row.try_get("volumes")?;
+        let accounts: i64 = row.try_get("accounts")?;
+        Ok((volumes as u64, accounts as u64))
+    }
+
     pub fn from_row(row: &AnyRow) -> Result<Self, VolumeError> {
         let id: i64 = row.try_get("volume_id")?;
         Ok(Volume(id))
     }
 
+    /// Count the snapshots of this volume, along with the latest generation and the total
+    /// size of their payloads.
+    pub async fn stats(
+        &self,
+        conn: &mut AnyConnection,
+    ) -> Result<(u64, Option<u64>, u64), VolumeError> {
+        let row = query(
+            "SELECT COUNT(*) AS snapshots, MAX(snapshot_generation) AS generation,
+                COALESCE(SUM(snapshot_size), 0) AS bytes
+                FROM storage_snapshot
+                WHERE volume_id = ?",
+        )
+        .bind(self.0)
+        .fetch_one(conn)
+        .await?;
+        let snapshots: i64 = row.try_get("snapshots")?;
+        let generation: Option<i64> = row.try_get("generation")?;
+        let bytes: i64 = row.try_get("bytes")?;
+        Ok((
+            snapshots as u64,
+            generation.map(|generation| generation as u64),
+            bytes as u64,
+        ))
+    }
+
+    pub async fn fetch(&self, conn: &mut AnyConnection) -> Result<VolumeData, VolumeError> {
+        let row = query("SELECT * FROM storage_volume WHERE volume_id = ?")
+            .bind(self.0)
+            .fetch_one(conn)
+            .await?;
+        VolumeData::from_row(&row)
+    }
+
     pub fn id(&self) -> i64 {
         self.0
     }
@@ -238,6 +502,147 @@ impl Volume {
             .await?;
         Ok(())
     }
+
+    pub async fn retention_set(
+        &self,
+        conn: &mut AnyConnection,
+        count: Option<u64>,
+        age: Option<u64>,
+    ) -> Result<(), VolumeError> {
+        query(
+            "UPDATE storage_volume SET volume_retain_count = ?, volume_retain_age = ?
+                WHERE volume_id = ?",
+        )
+        .bind(count.map(|count| count as i64))
+        .bind(age.map(|age| age as i64))
+        .bind(self.0)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn storage_class_set(
+        &self,
+        conn: &mut AnyConnection,
+        class: StorageClass,
+    ) -> Result<(), VolumeError> {
+        query("UPDATE storage_volume SET volume_storage_class = ? WHERE volume_id = ?")
+            .bind(class.as_str())
+            .bind(self.0)
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Lock the volume until the end of the transaction, so that concurrent uploads into it
+    /// are serialized. This is a write, so on SQLite (which has no row locks) it has to be
+    /// the first statement of the transaction: concurrent transactions then wait for the
+    /// lock instead of failing to upgrade their read locks.
+    pub async fn lock_for_update(&self, conn: &mut AnyConnection) -> Result<(), VolumeError> {
+        query("UPDATE storage_volume SET volume_locked = volume_locked WHERE volume_id = ?")
+            .bind(self.0)
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Pin the Dilithium3 public key of the volume, returning `false` if one is pinned
+    /// already. It cannot be replaced, so that a broken volume key cannot be used to swap it.
+    pub async fn pq_pubkey_set(
+        &self,
+        conn: &mut AnyConnection,
+        key: &[u8],
+    ) -> Result<bool, VolumeError> {
+        let result = query(
+            "UPDATE storage_volume SET volume_pq_pubkey = ?
+                WHERE volume_id = ? AND volume_pq_pubkey IS NULL",
+        )
+        .bind(key)
+        .bind(self.0)
+        .execute(conn)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Set the write-once period of the volume, returning `false` if that would shorten or
+    /// remove it. The period can only be extended, regardless of who asks, so that stolen
+    /// credentials cannot be used to lift it.
+    pub async fn worm_set(
+        &self,
+        conn: &mut AnyConnection,
+        period: Option<u64>,
+    ) -> Result<bool, VolumeError> {
+        let period = period.map(|period| period as i64);
+        let result = query(
+            "UPDATE storage_volume SET volume_worm_period = ?
+                WHERE volume_id = ?
+                    AND (volume_worm_period IS NULL OR volume_worm_period <= ?)",
+        )
+        .bind(period)
+        .bind(self.0)
+        .bind(period)
+        .execute(conn)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+/// LRU cache in front of [`Volume::lookup`], so that frequent requests for the same volume
+/// do not each query the database. Entries are invalidated whenever this instance modifies
+/// a volume, and expire after a while to pick up changes made by other instances.
+pub struct VolumeCache {
+    entries: Option<Mutex<LruCache<Pubkey, (Instant, VolumeData)>>>,
+    ttl: Duration,
+}
+
+impl VolumeCache {
+    /// Create cache holding up to `capacity` volumes, a capacity of zero disables it.
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        VolumeCache {
+            entries: (capacity > 0).then(|| Mutex::new(LruCache::new(capacity))),
+            ttl,
+        }
+    }
+
+    /// Look up a volume, using the cached copy if it has not expired.
+    #[instrument(name = "cached_lookup", skip_all, fields(volume = %pubkey))]
+    pub async fn lookup(
+        &self,
+        conn: &mut AnyConnection,
+        pubkey: &Pubkey,
+    ) -> Result<Option<VolumeData>, VolumeError> {
+        let entries = match &self.entries {
+            Some(entries) => entries,
+            None => return Volume::lookup(conn, pubkey).await,
+        };
+        if let Some((time, volume)) = entries.lock().unwrap().get(pubkey) {
+            if time.elapsed() < self.ttl {
+                return Ok(Some(volume.clone()));
+            }
+        }
+        // volumes that do not exist are not cached, they may be created at any time.
+        let volume = Volume::lookup(conn, pubkey).await?;
+        if let Some(volume) = &volume {
+            entries
+                .lock()
+                .unwrap()
+                .put(*pubkey, (Instant::now(), volume.clone()));
+        }
+        Ok(volume)
+    }
+
+    /// Remove a volume from the cache, must be called whenever it is modified.
+    pub fn invalidate(&self, pubkey: &Pubkey) {
+        if let Some(entries) = &self.entries {
+            entries.lock().unwrap().pop(pubkey);
+        }
+    }
+}
+
+impl From<i64> for Volume {
+    fn from(id: i64) -> Self {
+        Volume(id)
+    }
 }
 
 #[tokio::test]
@@ -258,4 +663,54 @@ async fn test_volume() {
 
     assert_eq!(volume.pubkey(), &pubkey);
     assert_eq!(volume.account(), &account);
+    assert_eq!(
+        volume.volume().stats(&mut conn).await.unwrap(),
+        (0, None, 0)
+    );
+
+    volume.delete(&mut conn, 1000).await.unwrap();
+    assert!(Volume::lookup(&mut conn, &pubkey).await.unwrap().is_none());
+    let deleted = Volume::lookup_deleted(&mut conn, &pubkey)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(deleted.deleted_at(), Some(1000));
+    assert!(Volume::deleted_before(&mut conn, 1000)
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        Volume::deleted_before(&mut conn, 1001).await.unwrap().len(),
+        1
+    );
+
+    deleted.restore(&mut conn).await.unwrap();
+    assert!(Volume::lookup(&mut conn, &pubkey).await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn test_volume_cache() {
+    use fractal_storage_client::Privkey;
+    use sqlx::AnyPool;
+
+    let pool = AnyPool::connect("sqlite://:memory:").await.unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+    let mut conn = pool.acquire().await.unwrap();
+    let cache = VolumeCache::new(16, Duration::from_secs(60));
+    let pubkey = Privkey::generate().pubkey();
+    assert!(cache.lookup(&mut conn, &pubkey).await.unwrap().is_none());
+
+    Volume::create(&mut conn, &pubkey, &Uuid::new_v4())
+        .await
+        .unwrap();
+    let volume = cache.lookup(&mut conn, &pubkey).await.unwrap().unwrap();
+    assert!(!volume.locked());
+
+    // cached copy is used until invalidated
+    volume.volume().locked_set(&mut conn, true).await.unwrap();
+    let volume = cache.lookup(&mut conn, &pubkey).await.unwrap().unwrap();
+    assert!(!volume.locked());
+    cache.invalidate(&pubkey);
+    let volume = cache.lookup(&mut conn, &pubkey).await.unwrap().unwrap();
+    assert!(volume.locked());
 }
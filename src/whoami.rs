@@ -0,0 +1,87 @@
+use crate::auth::{BearerToken, Principal};
+use fractal_storage_client::{TokenType, Whoami};
+use rocket::serde::json::{serde_json, Json};
+use rocket::{get, routes, Route};
+use serde::Deserialize;
+
+/// Static system tokens configured at startup, used to tell what kind of token a request
+/// was authenticated with.
+#[derive(Clone, Debug, Default)]
+pub struct SystemTokens(pub Vec<String>);
+
+/// Claims of a JWT that are reported back to the user.
+#[derive(Deserialize, Debug, Default, PartialEq, Eq)]
+struct Claims {
+    exp: Option<u64>,
+    #[serde(default)]
+    scope: Option<String>,
+}
+
+/// Read the claims of a JWT without verifying it, the token has already been verified by
+/// the time this is called. Returns `None` if the token is not a JWT.
+fn jwt_claims(token: &str) -> Option<Claims> {
+    let mut parts = token.split('.');
+    let (_header, payload, _signature) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some() {
+        return None;
+    }
+    let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok()?;
+    serde_json::from_slice(&payload).ok()
+}
+
+/// Returns the principal that the token of the request maps to.
+#[get("/whoami")]
+async fn whoami(context: Principal, token: BearerToken<'_>) -> Json<Whoami> {
+    if let Some(key) = context.api_key() {
+        return Json(Whoami {
+            account: *context.account(),
+            token_type: TokenType::ApiKey,
+            scopes: key
+                .scope()
+                .map(|scope| vec![scope.as_str().to_string()])
+                .unwrap_or_default(),
+            expiry: key.expires(),
+        });
+    }
+    if let Some(grant) = context.upload() {
+        return Json(Whoami {
+            account: *context.account(),
+            token_type: TokenType::Upload,
+            scopes: vec![],
+            expiry: Some(grant.expires),
+        });
+    }
+    let claims = token.0.and_then(jwt_claims).unwrap_or_default();
+    Json(Whoami {
+        account: *context.account(),
+        token_type: context.token_type(),
+        scopes: claims
+            .scope
+            .map(|scope| scope.split_whitespace().map(String::from).collect())
+            .unwrap_or_default(),
+        expiry: claims.exp,
+    })
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![whoami]
+}
+
+#[test]
+fn test_jwt_claims() {
+    let encode = |json: &str| base64::encode_config(json, base64::URL_SAFE_NO_PAD);
+    let token = format!(
+        "{}.{}.signature",
+        encode(r#"{"alg":"RS256"}"#),
+        encode(r#"{"sub":"user","exp":1700000000,"scope":"storage:read storage:write"}"#)
+    );
+    assert_eq!(
+        jwt_claims(&token),
+        Some(Claims {
+            exp: Some(1700000000),
+            scope: Some("storage:read storage:write".into()),
+        })
+    );
+    assert_eq!(jwt_claims("static-token"), None);
+    assert_eq!(jwt_claims("a.b.c.d"), None);
+}
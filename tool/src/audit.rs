@@ -0,0 +1,317 @@
+use anyhow::Result;
+use fractal_storage_client::{
+    snapshot_fetch_unverified, volume_get, Hash, Manifest, ManifestSigned, Pubkey,
+};
+use futures::stream::{self, StreamExt};
+use ipfs_api::{IpfsApi, IpfsClient};
+use reqwest::Client;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use url::Url;
+
+/// How long to wait for IPFS to locate snapshot data.
+const IPFS_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Result of auditing a single snapshot.
+#[derive(Debug, Clone)]
+pub struct AuditResult {
+    pub hash: Hash,
+    pub generation: Option<u64>,
+    pub failures: Vec<String>,
+}
+
+impl AuditResult {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Check that a manifest links up with its parent, using the same rules that the server
+/// validates uploads with. Returns a description of every problem found.
+fn check_chain(manifest: &Manifest, parent: Option<&Manifest>) -> Vec<String> {
+    let mut failures = vec![];
+    match parent {
+        None if manifest.size != manifest.size_total => failures.push(format!(
+            "root snapshot has size {} but size_total {}",
+            manifest.size, manifest.size_total
+        )),
+        None => {}
+        Some(parent) => {
+            if manifest.generation <= parent.generation {
+                failures.push(format!(
+                    "generation {} is not after parent's {}",
+                    manifest.generation, parent.generation
+                ));
+            }
+            if manifest.size_total != parent.size_total + manifest.size {
+                failures.push(format!(
+                    "size_total {} does not match parent's {} plus size {}",
+                    manifest.size_total, parent.size_total, manifest.size
+                ));
+            }
+        }
+    }
+    failures
+}
+
+/// Fetch a manifest and check its hash and signature, against the Dilithium3 key pinned for
+/// the volume if it has one.
+async fn audit_fetch(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    pubkey: &Pubkey,
+    pq_pubkey: Option<&[u8]>,
+    hash: Hash,
+) -> (Hash, Result<ManifestSigned, String>) {
+    let manifest = match snapshot_fetch_unverified(api, client, token, pubkey, &hash).await {
+        Ok(manifest) => manifest,
+        Err(e) => return (hash, Err(format!("cannot fetch manifest: {e}"))),
+    };
+    if manifest.hash() != hash {
+        return (hash, Err(format!("manifest has hash {}", manifest.hash())));
+    }
+    if let Err(e) = manifest.validate_pinned(pubkey, pq_pubkey) {
+        return (hash, Err(format!("invalid signature: {e}")));
+    }
+    (hash, Ok(manifest))
+}
+
+/// Check that the data of a manifest is available in IPFS.
+async fn audit_ipfs(ipfs: &IpfsClient, manifest: &ManifestSigned) -> Option<String> {
+    let data = &manifest.manifest.data;
+    let cid = match (data.scheme(), data.host_str()) {
+        ("ipfs", Some(cid)) => cid,
+        _ => return Some(format!("data is not stored in IPFS: {data}")),
+    };
+    match tokio::time::timeout(IPFS_TIMEOUT, ipfs.block_stat(cid)).await {
+        Ok(Ok(_)) => None,
+        Ok(Err(e)) => Some(format!("data {cid} not available in IPFS: {e}")),
+        Err(_) => Some(format!(
+            "data {cid} not found in IPFS within {IPFS_TIMEOUT:?}"
+        )),
+    }
+}
+
+/// Verify all snapshots of a volume: manifest signatures, chain links and optionally the
+/// availability of the data in IPFS. Manifests are fetched and checked in parallel.
+pub async fn audit(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    pubkey: &Pubkey,
+    ipfs: Option<&IpfsClient>,
+    concurrency: usize,
+) -> Result<Vec<AuditResult>> {
+    let hashes =
+        fractal_storage_client::snapshot_list(api, client, token, pubkey, None, false).await?;
+    let pq_pubkey = volume_get(api, client, token, pubkey)
+        .await?
+        .pq_pubkey_bytes();
+    let fetched: BTreeMap<Hash, Result<ManifestSigned, String>> = stream::iter(hashes.clone())
+        .map(|hash| audit_fetch(api, client, token, pubkey, pq_pubkey.as_deref(), hash))
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+
+    // check chain links
+    let mut results: Vec<AuditResult> = hashes
+        .iter()
+        .map(|hash| {
+            let mut result = AuditResult {
+                hash: *hash,
+                generation: None,
+                failures: vec![],
+            };
+            let manifest = match &fetched[hash] {
+                Ok(manifest) => &manifest.manifest,
+                Err(e) => {
+                    result.failures.push(e.clone());
+                    return result;
+                }
+            };
+            result.generation = Some(manifest.generation);
+            match &manifest.parent {
+                None => result.failures.extend(check_chain(manifest, None)),
+                // parents in other volumes are not audited
+                Some(parent) if parent.volume.is_some() => {}
+                Some(parent) => match fetched.get(&parent.hash) {
+                    None => result
+                        .failures
+                        .push(format!("parent {} is missing", parent.hash)),
+                    Some(Err(_)) => result
+                        .failures
+                        .push(format!("parent {} is invalid", parent.hash)),
+                    Some(Ok(parent)) => result
+                        .failures
+                        .extend(check_chain(manifest, Some(&parent.manifest))),
+                },
+            }
+            result
+        })
+        .collect();
+
+    // check data availability
+    if let Some(ipfs) = ipfs {
+        let fetched = &fetched;
+        let failures: Vec<(usize, Option<String>)> = stream::iter(results.iter().enumerate())
+            .filter_map(|(index, result)| async move {
+                fetched[&result.hash]
+                    .as_ref()
+                    .ok()
+                    .map(|manifest| (index, manifest))
+            })
+            .map(|(index, manifest)| async move { (index, audit_ipfs(ipfs, manifest).await) })
+            .buffer_unordered(concurrency.max(1))
+            .collect()
+            .await;
+        for (index, failure) in failures {
+            results[index].failures.extend(failure);
+        }
+    }
+
+    results.sort_by_key(|result| result.generation);
+    Ok(results)
+}
+
+#[test]
+fn test_check_chain() {
+    let root = Manifest {
+        creation: 0,
+        machine: uuid::Uuid::nil(),
+        path: "/".into(),
+        size: 100,
+        size_total: 100,
+        parent: None,
+        data: "ipfs://root".parse().unwrap(),
+        generation: 0,
+    };
+    assert!(check_chain(&root, None).is_empty());
+    let child = Manifest {
+        size: 50,
+        size_total: 150,
+        generation: 1,
+        ..root.clone()
+    };
+    assert!(check_chain(&child, Some(&root)).is_empty());
+    assert_eq!(check_chain(&child, None).len(), 1);
+    assert_eq!(check_chain(&root, Some(&child)).len(), 2);
+}
+
+#[tokio::test]
+async fn test_audit() {
+    use fractal_storage_client::testing::spawn_mock_server;
+    use fractal_storage_client::{volume_create, Parent, Privkey};
+
+    let server = spawn_mock_server().await.unwrap();
+    let client = Client::new();
+    let token = uuid::Uuid::new_v4().to_string();
+    let privkey = Privkey::generate();
+    let pubkey = privkey.pubkey();
+    volume_create(server.url(), &client, &token, &privkey)
+        .await
+        .unwrap();
+
+    let root = Manifest {
+        creation: 0,
+        machine: uuid::Uuid::nil(),
+        path: "/".into(),
+        size: 100,
+        size_total: 100,
+        parent: None,
+        data: "ipfs://root".parse().unwrap(),
+        generation: 0,
+    }
+    .sign(&privkey);
+    let child = Manifest {
+        size: 50,
+        size_total: 150,
+        generation: 1,
+        parent: Some(Parent::new(root.hash())),
+        ..root.manifest.clone()
+    }
+    .sign(&privkey);
+    for manifest in [&root, &child] {
+        fractal_storage_client::snapshot_upload(server.url(), &client, &token, &pubkey, manifest)
+            .await
+            .unwrap();
+    }
+
+    let results = audit(server.url(), &client, &token, &pubkey, None, 2)
+        .await
+        .unwrap();
+    let hashes: Vec<Hash> = results.iter().map(|result| result.hash).collect();
+    assert_eq!(hashes, vec![root.hash(), child.hash()]);
+    assert!(results.iter().all(AuditResult::passed));
+
+    // volumes that do not exist cannot be audited
+    let other = Privkey::generate().pubkey();
+    assert!(audit(server.url(), &client, &token, &other, None, 2)
+        .await
+        .is_err());
+}
+
+#[cfg(feature = "pq")]
+#[tokio::test]
+async fn test_audit_hybrid() {
+    use fractal_storage_client::testing::spawn_mock_server;
+    use fractal_storage_client::{volume_create, volume_edit, HybridSigner, Privkey, VolumeEdit};
+    use optional_field::Field;
+
+    let server = spawn_mock_server().await.unwrap();
+    let client = Client::new();
+    let token = uuid::Uuid::new_v4().to_string();
+    let privkey = Privkey::generate();
+    let pubkey = privkey.pubkey();
+    volume_create(server.url(), &client, &token, &privkey)
+        .await
+        .unwrap();
+    let signer = HybridSigner::generate(&privkey);
+    let edit = VolumeEdit {
+        writer: Field::Missing,
+        account: None,
+        lock: None,
+        retain_count: Field::Missing,
+        retain_age: Field::Missing,
+        worm_period: Field::Missing,
+        storage_class: None,
+        pq_pubkey: Some(hex::encode(signer.public_key())),
+    };
+    volume_edit(server.url(), &client, &token, &privkey, &edit)
+        .await
+        .unwrap();
+
+    // manifests with hybrid signatures are verified against the pinned key
+    let root = Manifest {
+        creation: 0,
+        machine: uuid::Uuid::nil(),
+        path: "/".into(),
+        size: 100,
+        size_total: 100,
+        parent: None,
+        data: "ipfs://root".parse().unwrap(),
+        generation: 0,
+    }
+    .sign_with(&signer);
+    fractal_storage_client::snapshot_upload(server.url(), &client, &token, &pubkey, &root)
+        .await
+        .unwrap();
+    let results = audit(server.url(), &client, &token, &pubkey, None, 2)
+        .await
+        .unwrap();
+    assert_eq!(results.len(), 1);
+    assert!(results[0].passed());
+
+    // and fail against any other key
+    let (hash, result) = audit_fetch(
+        server.url(),
+        &client,
+        &token,
+        &pubkey,
+        Some(HybridSigner::generate(&privkey).public_key()),
+        root.hash(),
+    )
+    .await;
+    assert_eq!(hash, root.hash());
+    assert!(result.is_err());
+}
@@ -0,0 +1,315 @@
+use fractal_storage_client::{keys::*, *};
+use ipfs_api::IpfsClient;
+use reqwest::{header::DATE, Client, StatusCode};
+use std::time::{Duration, SystemTime};
+use url::Url;
+
+/// Clock skew beyond which token validation and snapshot timestamps become unreliable.
+const CLOCK_SKEW_MAX: Duration = Duration::from_secs(60);
+
+/// Outcome of a single diagnostic check.
+#[derive(Debug, Clone)]
+pub struct Check {
+    /// What was checked.
+    pub name: &'static str,
+    /// Details about the outcome.
+    pub message: String,
+    /// How to fix the problem, if the check failed.
+    pub hint: Option<&'static str>,
+}
+
+impl Check {
+    fn pass(name: &'static str, message: impl Into<String>) -> Self {
+        Check {
+            name,
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    fn fail(name: &'static str, message: impl Into<String>, hint: &'static str) -> Self {
+        Check {
+            name,
+            message: message.into(),
+            hint: Some(hint),
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.hint.is_none()
+    }
+}
+
+/// Settings to diagnose.
+pub struct Doctor<'a> {
+    pub server: &'a Url,
+    pub client: &'a Client,
+    pub token: &'a str,
+    pub ipfs: &'a IpfsClient,
+    pub ipfs_url: &'a str,
+    pub privkey: Option<Privkey>,
+    pub secret: Option<Secret>,
+}
+
+impl<'a> Doctor<'a> {
+    /// Run all checks. Checks that depend on a failed check are skipped.
+    pub async fn run(&self) -> Vec<Check> {
+        let mut checks = vec![self.config()];
+        if checks[0].passed() {
+            checks.push(self.server().await);
+        }
+        if checks.iter().all(Check::passed) {
+            checks.push(self.capabilities().await);
+            checks.push(self.clock().await);
+            checks.push(self.auth().await);
+        }
+        checks.push(self.ipfs().await);
+        if let Some(privkey) = &self.privkey {
+            checks.push(self.keys(privkey));
+            if checks.iter().all(Check::passed) {
+                checks.push(self.volume(privkey).await);
+            }
+        }
+        checks
+    }
+
+    fn config(&self) -> Check {
+        if !matches!(self.server.scheme(), "http" | "https") {
+            return Check::fail(
+                "config",
+                format!("server URL {} is not an HTTP URL", self.server),
+                "set --server (STORAGE_API) to the URL of the storage service",
+            );
+        }
+        let local = matches!(
+            self.server.host_str(),
+            Some("localhost" | "127.0.0.1" | "[::1]")
+        );
+        if self.server.scheme() == "http" && !local {
+            return Check::fail(
+                "config",
+                format!("server URL {} is not encrypted", self.server),
+                "use an https:// URL for --server (STORAGE_API), tokens are sent with every \
+                 request",
+            );
+        }
+        match Url::parse(self.ipfs_url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => {
+                Check::pass("config", "settings are valid")
+            }
+            _ => Check::fail(
+                "config",
+                format!("IPFS URL {} is not an HTTP URL", self.ipfs_url),
+                "set --ipfs (IPFS_API) to the URL of the IPFS API, such as \
+                 http://localhost:5001",
+            ),
+        }
+    }
+
+    async fn clock(&self) -> Check {
+        let response = match self
+            .client
+            .get(self.server.join("/health").unwrap())
+            .send()
+            .await
+        {
+            Ok(response) => response,
+            Err(e) => {
+                return Check::fail(
+                    "clock",
+                    format!("cannot fetch server time: {e}"),
+                    "check your network connection",
+                )
+            }
+        };
+        let date = response
+            .headers()
+            .get(DATE)
+            .and_then(|date| date.to_str().ok())
+            .and_then(|date| httpdate::parse_http_date(date).ok());
+        let date = match date {
+            Some(date) => date,
+            None => return Check::pass("clock", "server does not report its time, skipped"),
+        };
+        let now = SystemTime::now();
+        let skew = match now.duration_since(date) {
+            Ok(skew) => skew,
+            Err(e) => e.duration(),
+        };
+        if skew > CLOCK_SKEW_MAX {
+            Check::fail(
+                "clock",
+                format!("local clock differs from the server by {}s", skew.as_secs()),
+                "enable time synchronization (NTP) on this machine",
+            )
+        } else {
+            Check::pass("clock", format!("skew is {}s", skew.as_secs()))
+        }
+    }
+
+    async fn server(&self) -> Check {
+        match health_check(self.server, self.client).await {
+            Ok(()) => Check::pass("server", format!("{} is reachable", self.server)),
+            Err(e) => Check::fail(
+                "server",
+                format!("{} is unreachable: {e}", self.server),
+                "check --server (STORAGE_API) and your network connection",
+            ),
+        }
+    }
+
+    async fn capabilities(&self) -> Check {
+        match capabilities(self.server, self.client).await {
+            Ok(capabilities) => match capabilities.manifest_version() {
+                Some(version) => {
+                    Check::pass("capabilities", format!("using manifest version {version}"))
+                }
+                None => Check::fail(
+                    "capabilities",
+                    format!(
+                        "no common manifest version, server supports {:?}",
+                        capabilities.manifest_versions
+                    ),
+                    "upgrade this tool or the storage service",
+                ),
+            },
+            Err(Error::Unsuccessful(StatusCode::NOT_FOUND)) => Check::fail(
+                "capabilities",
+                "server does not report its capabilities",
+                "the storage service is outdated, only legacy manifests can be used",
+            ),
+            Err(e) => Check::fail(
+                "capabilities",
+                format!("cannot fetch capabilities: {e}"),
+                "check that --server (STORAGE_API) points to the storage service",
+            ),
+        }
+    }
+
+    async fn auth(&self) -> Check {
+        if self.token.is_empty() {
+            return Check::fail(
+                "auth",
+                "no token configured",
+                "set --token (STORAGE_TOKEN) to a JWT or API key",
+            );
+        }
+        match whoami(self.server, self.client, self.token).await {
+            Ok(whoami) => {
+                let expired = whoami.expiry.map(|expiry| {
+                    SystemTime::UNIX_EPOCH + Duration::from_secs(expiry) < SystemTime::now()
+                });
+                match expired {
+                    Some(true) => Check::fail(
+                        "auth",
+                        format!("token for account {} has expired", whoami.account),
+                        "obtain a new token",
+                    ),
+                    _ => Check::pass(
+                        "auth",
+                        format!(
+                            "{:?} token for account {}",
+                            whoami.token_type, whoami.account
+                        ),
+                    ),
+                }
+            }
+            Err(Error::Unsuccessful(status))
+                if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN =>
+            {
+                Check::fail(
+                    "auth",
+                    format!("token is rejected ({status})"),
+                    "the token is invalid or expired, obtain a new one",
+                )
+            }
+            Err(Error::Unsuccessful(StatusCode::NOT_FOUND)) => Check::fail(
+                "auth",
+                "server cannot report which account the token maps to",
+                "the storage service is outdated, upgrade it",
+            ),
+            Err(e) => Check::fail(
+                "auth",
+                format!("cannot validate token: {e}"),
+                "check the server logs",
+            ),
+        }
+    }
+
+    async fn ipfs(&self) -> Check {
+        match ipfs_preflight(self.ipfs, self.ipfs_url).await {
+            Ok(version) => Check::pass("ipfs", format!("node version {version}")),
+            Err(e @ IpfsPreflightError::Unreachable(..)) => Check::fail(
+                "ipfs",
+                e.to_string(),
+                "start the IPFS daemon or set --ipfs (IPFS_API)",
+            ),
+            Err(e @ IpfsPreflightError::TooOld { .. }) => {
+                Check::fail("ipfs", e.to_string(), "upgrade the IPFS node")
+            }
+            Err(e) => Check::fail(
+                "ipfs",
+                e.to_string(),
+                "use a full IPFS node rather than a gateway or pinning service",
+            ),
+        }
+    }
+
+    fn keys(&self, privkey: &Privkey) -> Check {
+        match self.secret {
+            Some(secret) if secret != privkey.derive_secret() => Check::fail(
+                "keys",
+                "secret was not derived from the private key",
+                "snapshots encrypted with this secret cannot be read with this key, check \
+                 which key the volume was created with",
+            ),
+            _ => Check::pass("keys", format!("volume {}", privkey.pubkey())),
+        }
+    }
+
+    async fn volume(&self, privkey: &Privkey) -> Check {
+        match volume_get(self.server, self.client, self.token, &privkey.pubkey()).await {
+            Ok(info) => Check::pass("volume", format!("owned by account {}", info.account)),
+            Err(Error::VolumeNotFound(_)) => Check::fail(
+                "volume",
+                "volume does not exist",
+                "create it with volume-create, or check that the private key is correct",
+            ),
+            Err(e) => Check::fail(
+                "volume",
+                format!("cannot fetch volume: {e}"),
+                "check the server logs",
+            ),
+        }
+    }
+}
+
+#[test]
+fn test_doctor_config() {
+    let client = Client::new();
+    let ipfs = IpfsClient::default();
+    let check = |server: &str, ipfs_url: &str| {
+        Doctor {
+            server: &Url::parse(server).unwrap(),
+            client: &client,
+            token: "",
+            ipfs: &ipfs,
+            ipfs_url,
+            privkey: None,
+            secret: None,
+        }
+        .config()
+        .passed()
+    };
+    assert!(check(
+        "https://storage.example.com",
+        "http://localhost:5001"
+    ));
+    assert!(check("http://localhost:8000", "http://localhost:5001"));
+    assert!(!check(
+        "http://storage.example.com",
+        "http://localhost:5001"
+    ));
+    assert!(!check("ftp://storage.example.com", "http://localhost:5001"));
+    assert!(!check("https://storage.example.com", "localhost:5001"));
+}
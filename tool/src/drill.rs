@@ -0,0 +1,112 @@
+use anyhow::{anyhow, Result};
+use cid::Cid;
+use fractal_storage_client::{
+    drill_record, fetch_decrypt, snapshot_data_fetch, snapshot_fetch, snapshot_list_ordered,
+    ChaCha20DecryptionStream, Drill, DrillOutcome, DrillResult, Hash, Privkey, SnapshotOrder,
+    SnapshotOrdering, SortDirection,
+};
+use futures::StreamExt;
+use ipfs_api::IpfsClient;
+use reqwest::Client;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Instant;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use url::Url;
+
+/// Restore a snapshot of the volume into a temporary file in `dir`, then record the result
+/// with the server so that the volume shows up as verified (or not). Defaults to the latest
+/// snapshot. The restored data is removed again afterwards.
+pub async fn drill(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    ipfs: &IpfsClient,
+    privkey: &Privkey,
+    hash: Option<Hash>,
+    dir: &Path,
+) -> Result<Drill> {
+    let pubkey = privkey.pubkey();
+    let hash = match hash {
+        Some(hash) => hash,
+        None => {
+            let ordering = SnapshotOrdering {
+                order: SnapshotOrder::Generation,
+                direction: SortDirection::Desc,
+            };
+            snapshot_list_ordered(api, client, token, &pubkey, None, false, &ordering)
+                .await?
+                .first()
+                .copied()
+                .ok_or_else(|| anyhow!("Volume has no snapshots to restore"))?
+        }
+    };
+
+    let path = dir.join(format!("storage-drill-{}", hash.to_hex()));
+    let start = Instant::now();
+    let restored = restore(api, client, token, ipfs, privkey, &hash, &path).await;
+    let duration = start.elapsed().as_millis() as u64;
+    if let Err(e) = tokio::fs::remove_file(&path).await {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            eprintln!("Cannot remove restored data {}: {e}", path.display());
+        }
+    }
+    let (outcome, message) = match &restored {
+        Ok(bytes) => (DrillOutcome::Success, format!("restored {bytes} bytes")),
+        Err(e) => (DrillOutcome::Failure, e.to_string()),
+    };
+    let result = DrillResult {
+        snapshot: hash,
+        outcome,
+        duration,
+        message: Some(message),
+    };
+    Ok(drill_record(api, client, token, &pubkey, &result).await?)
+}
+
+/// Fetch and verify the manifest of a snapshot, then fetch and decrypt its payload into the
+/// file at `path`. Returns the number of bytes restored.
+async fn restore(
+    api: &Url,
+    client: &Client,
+    token: &str,
+    ipfs: &IpfsClient,
+    privkey: &Privkey,
+    hash: &Hash,
+    path: &Path,
+) -> Result<u64> {
+    // the hash and signature (against the pinned Dilithium3 key of hybrid volumes) are
+    // verified when fetching.
+    let pubkey = privkey.pubkey();
+    let manifest = snapshot_fetch(api, client, token, &pubkey, hash).await?;
+
+    // payloads are either in IPFS or stored on the service itself.
+    let secret = privkey.derive_secret();
+    let data = &manifest.manifest.data;
+    let mut stream = match (data.scheme(), data.host_str()) {
+        ("ipfs", Some(cid)) => {
+            let cid = Cid::from_str(cid)?;
+            fetch_decrypt(ipfs, &secret, &cid)
+                .await?
+                .map(|chunk| chunk.map_err(|e| anyhow!("Error fetching data from IPFS: {e}")))
+                .boxed()
+        }
+        _ => {
+            let stream = snapshot_data_fetch(api, client, token, &pubkey, hash).await?;
+            ChaCha20DecryptionStream::new(stream, &secret.to_chacha20_key())
+                .map(|chunk| chunk.map_err(|e| anyhow!("Error fetching data: {e}")))
+                .boxed()
+        }
+    };
+
+    let mut file = File::create(path).await?;
+    let mut bytes = 0;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        bytes += chunk.len() as u64;
+        file.write_all(&chunk).await?;
+    }
+    file.sync_all().await?;
+    Ok(bytes)
+}
@@ -2,20 +2,33 @@ use anyhow::anyhow;
 use anyhow::Result;
 use cid::Cid;
 use fractal_storage_client::{keys::*, *};
-use futures::StreamExt;
+use futures::{StreamExt, TryStreamExt};
 use ipfs_api::{IpfsClient, TryFromUri};
-use reqwest::ClientBuilder;
+use reqwest::{Client, ClientBuilder};
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
+use summary::Summary;
 use tokio::fs::File;
 use tokio::io::stdin;
 use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio_util::io::ReaderStream;
+use undo::UndoToken;
 use url::Url;
+use uuid::Uuid;
+
+mod audit;
+mod doctor;
+mod drill;
+mod summary;
+mod undo;
 
 const STORAGE_API: &str = "https://storage.fractalnetworks.co";
+const IPFS_API: &str = "http://localhost:5001";
 
 #[derive(StructOpt, Debug, Clone)]
 pub struct Options {
@@ -25,14 +38,61 @@ pub struct Options {
     /// Url of IPFS server.
     #[structopt(long, global = true, env = "IPFS_API")]
     ipfs: Option<Url>,
+    /// Fetch data from this HTTP gateway, such as `https://ipfs.io`, instead of an IPFS
+    /// node. Uploads still need an IPFS node.
+    #[structopt(long, global = true, env = "IPFS_GATEWAY")]
+    ipfs_gateway: Option<Url>,
     /// JWT or ApiKey of user
     #[structopt(long, global = true, env = "STORAGE_TOKEN")]
     token: Option<String>,
-    /// Allow invalid TLS certificates.
+    /// Allow invalid TLS certificates. This disables verifying the server certificate
+    /// altogether, whichever root certificates the tool was built with.
     #[structopt(long, global = true)]
     insecure: bool,
+    /// Only accept a server certificate with this public key, given as the hex SHA-256
+    /// hash of its SubjectPublicKeyInfo. Use this for self-signed certificates instead of
+    /// `--insecure`.
+    #[structopt(
+        long,
+        global = true,
+        env = "STORAGE_PIN_CERT",
+        conflicts_with = "insecure"
+    )]
+    pin_cert: Option<CertificatePin>,
+    /// Send requests to the server through this proxy, such as `http://proxy:3128` or
+    /// `socks5h://127.0.0.1:9050`. Defaults to `ALL_PROXY`, `HTTP_PROXY` and `HTTPS_PROXY`.
+    /// The IPFS API cannot be proxied, so it must be local when a proxy is used.
+    #[structopt(long, global = true, env = "STORAGE_PROXY")]
+    proxy: Option<ProxyConfig>,
+    /// Send requests to the server through a local Tor daemon, needed for `.onion` servers.
+    #[structopt(long, global = true, conflicts_with = "proxy")]
+    tor: bool,
+    /// Address family to connect to the server over: `ipv4`, `ipv6`, or `any`, which tries
+    /// both and uses whichever connects first.
+    #[structopt(
+        long,
+        global = true,
+        env = "STORAGE_ADDRESS_FAMILY",
+        default_value = "any"
+    )]
+    address_family: AddressFamily,
+    /// Give up on requests to the server that take longer than this many seconds.
+    #[structopt(long, global = true, env = "STORAGE_TIMEOUT")]
+    timeout: Option<u64>,
+    /// Give up on connecting to the server after this many seconds.
+    #[structopt(long, global = true, env = "STORAGE_CONNECT_TIMEOUT")]
+    connect_timeout: Option<u64>,
+    /// Fail any operation involving a manifest whose signature cannot be verified against
+    /// the volume's public key, instead of warning about it.
+    #[structopt(long, global = true, env = "STORAGE_STRICT")]
+    strict: bool,
+    /// Write a JSON document summarizing the result of the command to this path.
+    #[structopt(long, global = true)]
+    summary_json: Option<PathBuf>,
     #[structopt(subcommand)]
     command: Command,
+    #[structopt(skip)]
+    summary: Arc<Mutex<Summary>>,
 }
 
 #[derive(StructOpt, Debug, Clone)]
@@ -45,10 +105,20 @@ pub enum Command {
     Secret(SecretCommand),
     /// Create a new volume (and private key).
     VolumeCreate(VolumeCreateCommand),
+    /// List the volumes of the account.
+    VolumeList,
+    /// Remove a volume. Prints a token to undo this with until the server purges it.
+    VolumeRemove(VolumeRemoveCommand),
+    /// Undo a destructive command, using the token it printed.
+    Undo(UndoCommand),
     /// List all snapshots that exist.
     SnapshotList(SnapshotListCommand),
     /// Fetch a snapshot.
     SnapshotFetch(SnapshotFetchCommand),
+    /// Upload a signed manifest as a new snapshot.
+    SnapshotUpload(SnapshotUploadCommand),
+    /// Upload the data of a new snapshot to IPFS, then sign and upload its manifest.
+    SnapshotPublish(SnapshotPublishCommand),
     /// Upload a new snapshot using IPFS
     IpfsUpload(IpfsUploadCommand),
     /// Fetch data from IPFS.
@@ -56,6 +126,59 @@ pub enum Command {
     /// Generate a manifest from JSON
     ManifestGenerate(ManifestGenerateCommand),
     ManifestParse(ManifestParseCommand),
+    /// Edit fields of a signed manifest and re-sign it.
+    ManifestEdit(ManifestEditCommand),
+    /// Show differences between two manifests.
+    ManifestDiff(ManifestDiffCommand),
+    /// Verify all snapshots of a volume.
+    Audit(AuditCommand),
+    /// Restore a snapshot to a temporary location and record the result with the server.
+    Drill(DrillCommand),
+    /// Diagnose problems with the configuration, server and IPFS node.
+    Doctor(DoctorCommand),
+    /// Show which account the token maps to.
+    Whoami,
+    /// Mint a token that only allows uploading snapshots to a volume.
+    UploadToken(UploadTokenCommand),
+    /// Issue a long-lived API key for the account, for backup agents.
+    ApiKeyCreate(ApiKeyCreateCommand),
+    /// List the API keys of the account.
+    ApiKeyList,
+    /// Revoke an API key of the account.
+    ApiKeyRevoke(ApiKeyRevokeCommand),
+}
+
+impl Command {
+    /// Name of the command, as used on the command line.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Privkey => "privkey",
+            Command::Pubkey(_) => "pubkey",
+            Command::Secret(_) => "secret",
+            Command::VolumeCreate(_) => "volume-create",
+            Command::VolumeList => "volume-list",
+            Command::VolumeRemove(_) => "volume-remove",
+            Command::Undo(_) => "undo",
+            Command::SnapshotList(_) => "snapshot-list",
+            Command::SnapshotFetch(_) => "snapshot-fetch",
+            Command::SnapshotUpload(_) => "snapshot-upload",
+            Command::SnapshotPublish(_) => "snapshot-publish",
+            Command::IpfsUpload(_) => "ipfs-upload",
+            Command::IpfsFetch(_) => "ipfs-fetch",
+            Command::ManifestGenerate(_) => "manifest-generate",
+            Command::ManifestParse(_) => "manifest-parse",
+            Command::ManifestEdit(_) => "manifest-edit",
+            Command::ManifestDiff(_) => "manifest-diff",
+            Command::Audit(_) => "audit",
+            Command::Drill(_) => "drill",
+            Command::Doctor(_) => "doctor",
+            Command::Whoami => "whoami",
+            Command::UploadToken(_) => "upload-token",
+            Command::ApiKeyCreate(_) => "api-key-create",
+            Command::ApiKeyList => "api-key-list",
+            Command::ApiKeyRevoke(_) => "api-key-revoke",
+        }
+    }
 }
 
 #[derive(StructOpt, Debug, Clone)]
@@ -85,13 +208,217 @@ pub struct ManifestParseCommand {
     /// Ignore signature.
     #[structopt(long)]
     split_signature: bool,
-    /// Ignore invalid signature.
+    /// Ignore invalid signature (not allowed in strict mode).
     #[structopt(long)]
     ignore_invalid: bool,
     /// File to read manifest from (or read from standard input).
     file: Option<PathBuf>,
 }
 
+#[derive(StructOpt, Debug, Clone)]
+pub struct ManifestEditCommand {
+    /// Key to re-sign manifest with, must be the volume's private key.
+    #[structopt(long, short)]
+    privkey: Privkey,
+    /// Set time that the snapshot was created.
+    #[structopt(long)]
+    creation: Option<u64>,
+    /// Set machine that the snapshot was created on.
+    #[structopt(long)]
+    machine: Option<Uuid>,
+    /// Set path of the snapshot.
+    #[structopt(long)]
+    path: Option<PathBuf>,
+    /// Set size of the snapshot.
+    #[structopt(long)]
+    size: Option<u64>,
+    /// Set size of the snapshot and the previous ones.
+    #[structopt(long)]
+    size_total: Option<u64>,
+    /// Set generation of the snapshot.
+    #[structopt(long)]
+    generation: Option<u64>,
+    /// Set parent snapshot (in the same volume).
+    #[structopt(long, conflicts_with = "no-parent")]
+    parent: Option<Hash>,
+    /// Remove parent snapshot.
+    #[structopt(long)]
+    no_parent: bool,
+    /// Set data URL of the snapshot.
+    #[structopt(long)]
+    data: Option<Url>,
+    /// JSON merge patch (RFC 7396) to apply to the manifest, applied before other edits.
+    #[structopt(long)]
+    patch: Option<String>,
+    /// File to read signed manifest from (or read from standard input).
+    file: Option<PathBuf>,
+}
+
+impl ManifestEditCommand {
+    /// Apply the edits to the manifest.
+    pub fn apply(&self, manifest: Manifest) -> Result<Manifest> {
+        let mut manifest = match &self.patch {
+            Some(patch) => {
+                let mut value = serde_json::to_value(&manifest)?;
+                json_merge_patch(&mut value, serde_json::from_str(patch)?);
+                serde_json::from_value(value)?
+            }
+            None => manifest,
+        };
+        if let Some(creation) = self.creation {
+            manifest.creation = creation;
+        }
+        if let Some(machine) = self.machine {
+            manifest.machine = machine;
+        }
+        if let Some(path) = &self.path {
+            manifest.path = path.clone();
+        }
+        if let Some(size) = self.size {
+            manifest.size = size;
+        }
+        if let Some(size_total) = self.size_total {
+            manifest.size_total = size_total;
+        }
+        if let Some(generation) = self.generation {
+            manifest.generation = generation;
+        }
+        if let Some(parent) = self.parent {
+            manifest.parent = Some(Parent::new(parent));
+        }
+        if self.no_parent {
+            manifest.parent = None;
+        }
+        if let Some(data) = &self.data {
+            manifest.data = data.clone();
+        }
+        Ok(manifest)
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub struct AuditCommand {
+    /// Private key of the volume to audit.
+    #[structopt(long, short = "k")]
+    privkey: Privkey,
+    /// How many snapshots to verify in parallel.
+    #[structopt(long, short, default_value = "8")]
+    concurrency: usize,
+    /// Also check that the data of every snapshot is available in IPFS.
+    #[structopt(long)]
+    check_ipfs: bool,
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub struct DrillCommand {
+    /// Private key of the volume to restore.
+    #[structopt(long, short = "k")]
+    privkey: Privkey,
+    /// Snapshot to restore, defaults to the latest one.
+    #[structopt(long, short)]
+    hash: Option<Hash>,
+    /// Directory to restore into, defaults to the temporary directory. The restored data
+    /// is removed afterwards.
+    #[structopt(long)]
+    dir: Option<PathBuf>,
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub struct DoctorCommand {
+    /// Private key of a volume to check.
+    #[structopt(long, short = "k")]
+    privkey: Option<Privkey>,
+    /// Encryption secret to check against the private key.
+    #[structopt(long)]
+    secret: Option<Secret>,
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub struct SnapshotUploadCommand {
+    /// Volume to upload the snapshot to.
+    #[structopt(long, short)]
+    pubkey: Pubkey,
+    /// File to read the signed manifest from (or read from standard input).
+    file: Option<PathBuf>,
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub struct VolumeRemoveCommand {
+    /// Private key of the volume to remove, needed to sign the request.
+    #[structopt(long, short = "k")]
+    privkey: Privkey,
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub struct UndoCommand {
+    /// Token printed by the command to undo.
+    token: UndoToken,
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub struct UploadTokenCommand {
+    /// Volume to allow uploads to.
+    #[structopt(long, short)]
+    pubkey: Pubkey,
+    /// How long the token is valid for, such as `30m`, `1h` or `1d`.
+    #[structopt(long, default_value = "1h")]
+    ttl: String,
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub struct ApiKeyCreateCommand {
+    /// Human-readable name of the key.
+    #[structopt(long, short)]
+    name: Option<String>,
+    /// Restrict the key to `read` or `write` requests, by default it has full access.
+    #[structopt(long)]
+    scope: Option<ApiKeyScope>,
+    /// Restrict the key to a single volume.
+    #[structopt(long, short)]
+    pubkey: Option<Pubkey>,
+    /// How long the key is valid for, in seconds. By default it does not expire.
+    #[structopt(long)]
+    ttl: Option<u64>,
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub struct ApiKeyRevokeCommand {
+    /// Identifier of the key, as listed by `api-key-list`.
+    id: i64,
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub struct ManifestDiffCommand {
+    /// Volume to fetch manifests from, when they are given as snapshot hashes.
+    #[structopt(long, short)]
+    pubkey: Option<Pubkey>,
+    /// First manifest, file name or snapshot hash.
+    first: String,
+    /// Second manifest, file name or snapshot hash.
+    second: String,
+}
+
+/// Apply a JSON merge patch (RFC 7396) to a value.
+fn json_merge_patch(target: &mut serde_json::Value, patch: serde_json::Value) {
+    use serde_json::Value;
+    match patch {
+        Value::Object(patch) => {
+            if !target.is_object() {
+                *target = Value::Object(Default::default());
+            }
+            let target = target.as_object_mut().unwrap();
+            for (key, value) in patch {
+                if value.is_null() {
+                    target.remove(&key);
+                } else {
+                    json_merge_patch(target.entry(key).or_insert(Value::Null), value);
+                }
+            }
+        }
+        patch => *target = patch,
+    }
+}
+
 #[derive(StructOpt, Debug, Clone)]
 pub struct VolumeCreateCommand {
     #[structopt(long, short)]
@@ -108,6 +435,12 @@ pub struct SnapshotListCommand {
     root: bool,
     #[structopt(long, short)]
     fetch: bool,
+    /// Order snapshots by `generation`, `creation` or `size`.
+    #[structopt(long, default_value = "generation")]
+    order: SnapshotOrder,
+    /// Sort direction, `asc` or `desc`.
+    #[structopt(long, default_value = "asc")]
+    dir: SortDirection,
 }
 
 #[derive(StructOpt, Debug, Clone)]
@@ -118,6 +451,24 @@ pub struct SnapshotFetchCommand {
     hash: Hash,
 }
 
+#[derive(StructOpt, Debug, Clone)]
+pub struct SnapshotPublishCommand {
+    /// Private key of the volume to publish the snapshot to.
+    #[structopt(long, short = "k")]
+    privkey: Privkey,
+    /// Parent snapshot, if this is an incremental snapshot.
+    #[structopt(long, short)]
+    parent: Option<Hash>,
+    /// Machine the snapshot was created on.
+    #[structopt(long, default_value = "00000000-0000-0000-0000-000000000000")]
+    machine: Uuid,
+    /// Path of the snapshot.
+    #[structopt(long)]
+    path: PathBuf,
+    /// File to upload, if none specified, read from standard input.
+    file: Option<PathBuf>,
+}
+
 #[derive(StructOpt, Debug, Clone)]
 pub struct IpfsUploadCommand {
     /// Decryption key (can also be derived from private key).
@@ -126,6 +477,9 @@ pub struct IpfsUploadCommand {
     /// Private key (used to derive decryption key).
     #[structopt(long, required_unless("secret"))]
     privkey: Option<Privkey>,
+    /// Show the number of bytes uploaded so far on standard error.
+    #[structopt(long)]
+    progress: bool,
     /// File to upload, if none specified, read from standard input.
     file: Option<PathBuf>,
 }
@@ -138,6 +492,12 @@ pub struct IpfsFetchCommand {
     /// Private key (used to derive decryption key).
     #[structopt(long, required_unless("secret"))]
     privkey: Option<Privkey>,
+    /// Show the number of bytes fetched so far on standard error.
+    #[structopt(long)]
+    progress: bool,
+    /// Resume a fetch that was interrupted, skipping this many bytes of decrypted data.
+    #[structopt(long, default_value = "0")]
+    offset: u64,
     cid: Cid,
 }
 
@@ -148,6 +508,62 @@ async fn read_privkey() -> Result<Privkey> {
     Ok(Privkey::from_str(&line)?)
 }
 
+/// Differences between two manifests, one line per field, followed by how they relate.
+fn manifest_diff(first: (&Hash, &Manifest), second: (&Hash, &Manifest)) -> Result<Vec<String>> {
+    let (first_hash, first) = first;
+    let (second_hash, second) = second;
+    let mut lines = vec![];
+    let first_value = serde_json::to_value(first)?;
+    let second_value = serde_json::to_value(second)?;
+    let fields = first_value.as_object().unwrap();
+    for (field, value) in fields {
+        let other = &second_value[field];
+        if value == other {
+            lines.push(format!("  {field}: {value}"));
+        } else {
+            lines.push(format!("- {field}: {value}"));
+            lines.push(format!("+ {field}: {other}"));
+        }
+    }
+
+    // explain relationship, using the same rules the server validates uploads with
+    let (parent, child, child_hash) = match (&first.parent, &second.parent) {
+        (_, Some(parent)) if parent.hash == *first_hash => (first, second, second_hash),
+        (Some(parent), _) if parent.hash == *second_hash => (second, first, first_hash),
+        _ => {
+            lines.push("Manifests are not parent and child".into());
+            return Ok(lines);
+        }
+    };
+    lines.push(format!("Manifest {child_hash} is a child of the other"));
+    if child.generation <= parent.generation {
+        lines.push(format!(
+            "Invalid generation: child has {} but parent has {}",
+            child.generation, parent.generation
+        ));
+    }
+    let expected_size_total = parent.size_total + child.size;
+    if child.size_total != expected_size_total {
+        lines.push(format!(
+            "Wrong size_total: child has {} but expected {expected_size_total}",
+            child.size_total
+        ));
+    }
+    Ok(lines)
+}
+
+/// Counter that shows the number of bytes transferred on standard error, updated whenever
+/// another MiB was transferred.
+fn progress_count(action: &'static str) -> BytesCount {
+    let shown = AtomicUsize::new(0);
+    BytesCount::observed(move |bytes| {
+        let mib = bytes >> 20;
+        if shown.swap(mib, Ordering::Relaxed) != mib {
+            eprint!("\r{action} {mib} MiB");
+        }
+    })
+}
+
 async fn read_data(file: Option<&Path>) -> Result<Vec<u8>> {
     let mut reader: Box<dyn AsyncRead + Unpin> = match file {
         Some(path) => Box::new(File::open(path).await?),
@@ -160,26 +576,188 @@ async fn read_data(file: Option<&Path>) -> Result<Vec<u8>> {
 
 impl Options {
     pub fn ipfs(&self) -> Result<IpfsClient> {
+        let proxy = self.proxy()?;
+        let url = Url::parse(&self.ipfs_url())?;
+        ProxyConfig::check(None, &url)?;
+        ProxyConfig::check_local(proxy.as_ref(), &url)?;
         match &self.ipfs {
             Some(url) => Ok(IpfsClient::from_str(&url.to_string())?),
             None => Ok(IpfsClient::default()),
         }
     }
 
+    /// IPFS client, after checking that the node is reachable and compatible.
+    pub async fn ipfs_checked(&self) -> Result<IpfsClient> {
+        let ipfs = self.ipfs()?;
+        ipfs_preflight(&ipfs, &self.ipfs_url()).await?;
+        Ok(ipfs)
+    }
+
+    pub fn ipfs_url(&self) -> String {
+        match &self.ipfs {
+            Some(url) => url.to_string(),
+            None => IPFS_API.to_string(),
+        }
+    }
+
     pub fn server(&self) -> Url {
         self.server
             .clone()
             .unwrap_or_else(|| Url::from_str(STORAGE_API).unwrap())
     }
 
+    /// Proxy to send requests to the server through, if any.
+    pub fn proxy(&self) -> Result<Option<ProxyConfig>> {
+        if self.tor {
+            return Ok(Some(ProxyConfig::tor()));
+        }
+        match &self.proxy {
+            Some(proxy) => Ok(Some(proxy.clone())),
+            None => Ok(ProxyConfig::from_env()?),
+        }
+    }
+
+    /// HTTP client for fetching from the IPFS gateway. It only shares the proxy settings with
+    /// the client for the server, certificates are pinned for the server only.
+    pub fn gateway_client(&self) -> Result<Client> {
+        let mut client = ClientBuilder::new();
+        if let Some(proxy) = &self.proxy()? {
+            client = with_proxy(client, proxy)?;
+        }
+        Ok(client.build()?)
+    }
+
     pub fn token(&self) -> String {
         self.token.clone().unwrap_or_else(|| String::new())
     }
 
+    /// Load a manifest from a file (signed or unsigned), or fetch it from the server if
+    /// given a snapshot hash.
+    pub async fn load_manifest(
+        &self,
+        client: &Client,
+        source: &str,
+        pubkey: Option<&Pubkey>,
+    ) -> Result<(Hash, Manifest)> {
+        if !Path::new(source).exists() {
+            if let Ok(hash) = Hash::from_str(source) {
+                let pubkey = pubkey.ok_or(anyhow!("Need --pubkey to fetch manifest {source}"))?;
+                let manifest = fractal_storage_client::snapshot_fetch_unverified(
+                    &self.server(),
+                    client,
+                    &self.token(),
+                    pubkey,
+                    &hash,
+                )
+                .await?;
+                self.verify_manifest(client, &manifest, pubkey, &hash)
+                    .await?;
+                return Ok((hash, manifest.manifest));
+            }
+        }
+        let data = read_data(Some(Path::new(source))).await?;
+        if let Ok(manifest) = ManifestSigned::parse(&data) {
+            if let Some(pubkey) = pubkey {
+                self.verify_manifest(client, &manifest, pubkey, &manifest.hash())
+                    .await?;
+            }
+            return Ok((manifest.hash(), manifest.manifest));
+        }
+        Ok((Manifest::hash(&data), Manifest::decode(&data)?))
+    }
+
+    /// Print a warning, and record it in the summary.
+    pub fn warn(&self, message: String) {
+        eprintln!("Warning: {message}");
+        self.summary.lock().unwrap().warnings.push(message);
+    }
+
+    /// Update the summary of the command.
+    pub fn summary(&self, update: impl FnOnce(&mut Summary)) {
+        update(&mut self.summary.lock().unwrap());
+    }
+
+    /// Run the command and write the summary, if requested.
+    pub async fn run_summary(&self) -> Result<()> {
+        let start = Instant::now();
+        let result = self.run().await;
+        if let Some(path) = &self.summary_json {
+            let mut summary = self.summary.lock().unwrap().clone();
+            summary.command = self.command.name().to_string();
+            summary.finish(&result, start.elapsed());
+            summary.write(path).await?;
+        }
+        result
+    }
+
+    /// Verify that a fetched manifest has the expected hash and a valid signature. Hybrid
+    /// signatures are checked against the Dilithium3 key pinned for the volume, which is
+    /// looked up on the server. In strict mode, failures are errors, otherwise they are only
+    /// reported as warnings.
+    pub async fn verify_manifest(
+        &self,
+        client: &Client,
+        manifest: &ManifestSigned,
+        pubkey: &Pubkey,
+        hash: &Hash,
+    ) -> Result<()> {
+        let result = if manifest.hash() != *hash {
+            Err(anyhow!(
+                "Manifest has hash {}, expected {hash}",
+                manifest.hash()
+            ))
+        } else {
+            match self.pq_pubkey(client, manifest, pubkey).await {
+                Ok(pq_pubkey) => manifest.validate_pinned(pubkey, pq_pubkey.as_deref()),
+                Err(e) => Err(e),
+            }
+        };
+        match result {
+            Err(e) if self.strict => Err(anyhow!("Invalid manifest {hash}: {e}")),
+            Err(e) => {
+                self.warn(format!("Invalid manifest {hash}: {e}"));
+                Ok(())
+            }
+            Ok(()) => Ok(()),
+        }
+    }
+
+    /// Dilithium3 key pinned for the volume, if the manifest has a hybrid signature that has
+    /// to be verified against it.
+    async fn pq_pubkey(
+        &self,
+        client: &Client,
+        manifest: &ManifestSigned,
+        pubkey: &Pubkey,
+    ) -> Result<Option<Vec<u8>>> {
+        if SignatureAlgorithm::of(&manifest.signature)
+            != Some(SignatureAlgorithm::Ed25519Dilithium3)
+        {
+            return Ok(None);
+        }
+        let info =
+            fractal_storage_client::volume_get(&self.server(), client, &self.token(), pubkey)
+                .await?;
+        Ok(info.pq_pubkey_bytes())
+    }
+
     pub async fn run(&self) -> Result<()> {
-        let client = ClientBuilder::new()
-            .danger_accept_invalid_certs(self.insecure)
-            .build()?;
+        let mut client = ClientBuilder::new().danger_accept_invalid_certs(self.insecure);
+        if let Some(pin) = self.pin_cert {
+            client = with_pinned_cert(client, pin);
+        }
+        client = with_address_family(client, self.address_family);
+        let timeouts = Timeouts {
+            request: self.timeout.map(Duration::from_secs),
+            connect: self.connect_timeout.map(Duration::from_secs),
+        };
+        client = with_timeouts(client, &timeouts);
+        let proxy = self.proxy()?;
+        ProxyConfig::check(proxy.as_ref(), &self.server())?;
+        if let Some(proxy) = &proxy {
+            client = with_proxy(client, proxy)?;
+        }
+        let client = client.build()?;
         match &self.command {
             Command::VolumeCreate(create) => {
                 let privkey = create.privkey.unwrap_or_else(|| {
@@ -194,18 +772,23 @@ impl Options {
                 Ok(())
             }
             Command::SnapshotList(opts) => {
-                let result = fractal_storage_client::snapshot_list(
+                let ordering = SnapshotOrdering {
+                    order: opts.order,
+                    direction: opts.dir,
+                };
+                let result = fractal_storage_client::snapshot_list_ordered(
                     &self.server(),
                     &client,
                     &self.token(),
                     &opts.privkey.pubkey(),
                     opts.parent.as_ref(),
                     opts.root,
+                    &ordering,
                 )
                 .await?;
                 for hash in &result {
                     if opts.fetch {
-                        let result = fractal_storage_client::snapshot_fetch(
+                        let result = fractal_storage_client::snapshot_fetch_unverified(
                             &self.server(),
                             &client,
                             &self.token(),
@@ -213,6 +796,8 @@ impl Options {
                             &hash,
                         )
                         .await?;
+                        self.verify_manifest(&client, &result, &opts.privkey.pubkey(), hash)
+                            .await?;
                         println!("{}", serde_json::to_string(&result)?);
                     } else {
                         println!("{hash}");
@@ -221,7 +806,7 @@ impl Options {
                 Ok(())
             }
             Command::SnapshotFetch(opts) => {
-                let result = fractal_storage_client::snapshot_fetch(
+                let result = fractal_storage_client::snapshot_fetch_unverified(
                     &self.server(),
                     &client,
                     &self.token(),
@@ -229,50 +814,190 @@ impl Options {
                     &opts.hash,
                 )
                 .await?;
+                self.verify_manifest(&client, &result, &opts.privkey.pubkey(), &opts.hash)
+                    .await?;
+                self.summary(|summary| summary.hash = Some(opts.hash.to_string()));
                 println!("{}", serde_json::to_string(&result)?);
                 Ok(())
             }
+            Command::SnapshotUpload(opts) => {
+                let data = read_data(opts.file.as_deref()).await?;
+                let manifest = ManifestSigned::parse(&data)?;
+                self.verify_manifest(&client, &manifest, &opts.pubkey, &manifest.hash())
+                    .await?;
+                let uploaded = fractal_storage_client::snapshot_upload(
+                    &self.server(),
+                    &client,
+                    &self.token(),
+                    &opts.pubkey,
+                    &manifest,
+                )
+                .await?;
+                for warning in &uploaded.warnings {
+                    self.warn(warning.to_string());
+                }
+                let hash = manifest.hash();
+                self.summary(|summary| summary.hash = Some(hash.to_string()));
+                println!("{hash}");
+                Ok(())
+            }
+            Command::SnapshotPublish(opts) => {
+                let input: Pin<Box<dyn AsyncRead + Send + Sync>> = match &opts.file {
+                    Some(file) => Box::pin(File::open(file).await?),
+                    None => Box::pin(stdin()),
+                };
+                let input = Box::pin(ReaderStream::new(input));
+                let pubkey = opts.privkey.pubkey();
+                let parent = match &opts.parent {
+                    Some(hash) => {
+                        let parent = fractal_storage_client::snapshot_fetch_unverified(
+                            &self.server(),
+                            &client,
+                            &self.token(),
+                            &pubkey,
+                            hash,
+                        )
+                        .await?;
+                        self.verify_manifest(&client, &parent, &pubkey, hash)
+                            .await?;
+                        Some(parent)
+                    }
+                    None => None,
+                };
+                let snapshot = SnapshotPublish {
+                    machine: opts.machine,
+                    path: opts.path.clone(),
+                    parent,
+                };
+                let ipfs = self.ipfs_checked().await?;
+                let published = fractal_storage_client::snapshot_publish(
+                    &self.server(),
+                    &client,
+                    &self.token(),
+                    &ipfs,
+                    &opts.privkey,
+                    &snapshot,
+                    input,
+                )
+                .await?;
+                for warning in &published.warnings {
+                    self.warn(warning.to_string());
+                }
+                let hash = published.manifest.hash();
+                self.summary(|summary| {
+                    summary.bytes = Some(published.size.plaintext);
+                    summary.cid = Some(published.cid.to_string());
+                    summary.hash = Some(hash.to_string());
+                });
+                println!("{hash}");
+                Ok(())
+            }
             Command::IpfsUpload(opts) => {
                 let input: Pin<Box<dyn AsyncRead + Send + Sync>> = match &opts.file {
                     Some(file) => Box::pin(File::open(file).await?),
                     None => Box::pin(stdin()),
                 };
 
-                let input = ReaderStream::new(input);
-                let input = Box::pin(input);
+                let input = Box::pin(ReaderStream::new(input));
 
-                let ipfs = self.ipfs()?;
+                let ipfs = self.ipfs_checked().await?;
 
                 let secret = opts
                     .secret
                     .or_else(|| opts.privkey.map(|k| k.derive_secret()))
                     .unwrap();
-                let cid = fractal_storage_client::upload_encrypt(&ipfs, &secret, input).await?;
+                let progress = opts.progress.then(|| progress_count("Uploaded"));
+                let (cid, size) = fractal_storage_client::upload_encrypt_progress(
+                    &ipfs,
+                    &secret,
+                    input,
+                    progress.as_ref(),
+                )
+                .await?;
+                if progress.is_some() {
+                    eprintln!();
+                }
+                self.summary(|summary| {
+                    summary.bytes = Some(size.plaintext);
+                    summary.cid = Some(cid.to_string());
+                });
                 println!("{cid}");
                 Ok(())
             }
             Command::IpfsFetch(opts) => {
-                let ipfs = self.ipfs()?;
                 let secret = opts
                     .secret
                     .or_else(|| opts.privkey.map(|k| k.derive_secret()))
                     .unwrap();
-                let mut data =
-                    fractal_storage_client::fetch_decrypt(&ipfs, &secret, &opts.cid).await?;
+                let progress = opts.progress.then(|| progress_count("Fetched"));
+                // only fetches from the IPFS node from the start are observed by the library.
+                let observed = self.ipfs_gateway.is_none() && opts.offset == 0;
+                let mut data: IpfsDataStream = match (&self.ipfs_gateway, opts.offset) {
+                    (Some(gateway), 0) => {
+                        fractal_storage_client::fetch_decrypt_gateway(
+                            &self.gateway_client()?,
+                            gateway,
+                            &secret,
+                            &opts.cid,
+                        )
+                        .await?
+                    }
+                    (Some(_), _) => {
+                        return Err(anyhow!("Fetches from a gateway cannot be resumed"));
+                    }
+                    (None, 0) => Box::pin(
+                        fractal_storage_client::fetch_decrypt_progress(
+                            &self.ipfs_checked().await?,
+                            &secret,
+                            &opts.cid,
+                            progress.as_ref(),
+                        )
+                        .await?
+                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e)),
+                    ),
+                    (None, offset) => Box::pin(
+                        fractal_storage_client::fetch_decrypt_from(
+                            &self.ipfs_checked().await?,
+                            &secret,
+                            &opts.cid,
+                            offset,
+                        )
+                        .await?
+                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e)),
+                    ),
+                };
                 let mut stdout = tokio::io::stdout();
+                self.summary(|summary| summary.cid = Some(opts.cid.to_string()));
 
+                let mut bytes = 0;
                 loop {
                     match data.next().await {
-                        Some(data) => stdout.write_all(&data?).await?,
+                        Some(data) => {
+                            let data = data?;
+                            bytes += data.len() as u64;
+                            if let (Some(progress), false) = (&progress, observed) {
+                                progress.add(data.len());
+                            }
+                            stdout.write_all(&data).await?;
+                        }
                         None => break,
                     }
                 }
+                if progress.is_some() {
+                    eprintln!();
+                }
+                self.summary(|summary| summary.bytes = Some(bytes));
 
                 Ok(())
             }
             Command::ManifestGenerate(opts) => {
                 let data = read_data(opts.file.as_deref()).await?;
                 let manifest: Manifest = serde_json::from_slice(&data)?;
+                if self.strict && opts.privkey.is_none() {
+                    return Err(anyhow!(
+                        "Strict mode: refusing to generate unsigned manifest"
+                    ));
+                }
                 match &opts.privkey {
                     Some(key) => {
                         tokio::io::stdout()
@@ -285,14 +1010,19 @@ impl Options {
             }
             Command::ManifestParse(opts) => {
                 let data = read_data(opts.file.as_deref()).await?;
+                if self.strict && opts.pubkey.is_none() {
+                    return Err(anyhow!(
+                        "Strict mode: signature cannot be verified without --pubkey"
+                    ));
+                }
                 let manifest = match &opts.pubkey {
                     Some(key) => {
                         let (manifest, signature) =
                             Manifest::split(&data).ok_or(anyhow!("Manifest too short"))?;
                         match Manifest::validate(manifest, signature, key) {
                             Ok(()) => {}
-                            Err(e) if opts.ignore_invalid => {
-                                eprintln!("Warning: Invalid signature: {e}")
+                            Err(e) if opts.ignore_invalid && !self.strict => {
+                                self.warn(format!("Invalid signature: {e}"))
                             }
                             Err(e) => return Err(e),
                         }
@@ -312,6 +1042,204 @@ impl Options {
                 println!("{manifest}");
                 Ok(())
             }
+            Command::ManifestEdit(opts) => {
+                let data = read_data(opts.file.as_deref()).await?;
+                let (raw, signature) =
+                    Manifest::split(&data).ok_or(anyhow!("Manifest too short"))?;
+                match Manifest::validate(raw, signature, &opts.privkey.pubkey()) {
+                    Ok(()) => {}
+                    Err(e) if self.strict => {
+                        return Err(anyhow!("Invalid signature of original manifest: {e}"))
+                    }
+                    Err(e) => self.warn(format!("Invalid signature of original manifest: {e}")),
+                }
+                let version = Manifest::version(raw).unwrap_or(MANIFEST_VERSION_LEGACY);
+                let manifest = opts.apply(Manifest::decode(raw)?)?;
+                let signed = manifest.sign_version(&opts.privkey, version);
+                eprintln!(
+                    "Edited manifest {} -> {}",
+                    Manifest::hash(raw),
+                    signed.hash()
+                );
+                tokio::io::stdout().write_all(&signed.data()).await?;
+                Ok(())
+            }
+            Command::ManifestDiff(opts) => {
+                let pubkey = opts.pubkey.as_ref();
+                let (first_hash, first) = self.load_manifest(&client, &opts.first, pubkey).await?;
+                let (second_hash, second) =
+                    self.load_manifest(&client, &opts.second, pubkey).await?;
+                println!("--- {first_hash}");
+                println!("+++ {second_hash}");
+                for line in manifest_diff((&first_hash, &first), (&second_hash, &second))? {
+                    println!("{line}");
+                }
+                Ok(())
+            }
+            Command::Audit(opts) => {
+                let ipfs = match opts.check_ipfs {
+                    true => Some(self.ipfs_checked().await?),
+                    false => None,
+                };
+                let results = audit::audit(
+                    &self.server(),
+                    &client,
+                    &self.token(),
+                    &opts.privkey.pubkey(),
+                    ipfs.as_ref(),
+                    opts.concurrency,
+                )
+                .await?;
+                let failed = results.iter().filter(|result| !result.passed()).count();
+                for result in &results {
+                    match result.passed() {
+                        true => println!("PASS {}", result.hash),
+                        false => println!("FAIL {}: {}", result.hash, result.failures.join(", ")),
+                    }
+                }
+                println!("{} snapshots, {} failed", results.len(), failed);
+                if failed > 0 {
+                    return Err(anyhow!("Audit failed for {failed} snapshots"));
+                }
+                Ok(())
+            }
+            Command::Drill(opts) => {
+                let dir = opts.dir.clone().unwrap_or_else(std::env::temp_dir);
+                let drill = drill::drill(
+                    &self.server(),
+                    &client,
+                    &self.token(),
+                    &self.ipfs()?,
+                    &opts.privkey,
+                    opts.hash,
+                    &dir,
+                )
+                .await?;
+                self.summary(|summary| summary.hash = Some(drill.snapshot.to_string()));
+                let message = drill.message.as_deref().unwrap_or_default();
+                match drill.outcome {
+                    DrillOutcome::Success => {
+                        println!("PASS {} in {}ms: {message}", drill.snapshot, drill.duration);
+                        Ok(())
+                    }
+                    DrillOutcome::Failure => {
+                        println!("FAIL {}: {message}", drill.snapshot);
+                        Err(anyhow!("Restore drill of {} failed", drill.snapshot))
+                    }
+                }
+            }
+            Command::Doctor(opts) => {
+                let ipfs = self.ipfs()?;
+                let doctor = doctor::Doctor {
+                    server: &self.server(),
+                    client: &client,
+                    token: &self.token(),
+                    ipfs: &ipfs,
+                    ipfs_url: &self.ipfs_url(),
+                    privkey: opts.privkey,
+                    secret: opts.secret,
+                };
+                let checks = doctor.run().await;
+                for check in &checks {
+                    match check.hint {
+                        None => println!("[ok]   {}: {}", check.name, check.message),
+                        Some(hint) => {
+                            println!("[FAIL] {}: {}", check.name, check.message);
+                            println!("       hint: {hint}");
+                        }
+                    }
+                }
+                let failed = checks.iter().filter(|check| !check.passed()).count();
+                if failed > 0 {
+                    return Err(anyhow!("{failed} checks failed"));
+                }
+                Ok(())
+            }
+            Command::Whoami => {
+                let whoami = whoami(&self.server(), &client, &self.token()).await?;
+                println!("{}", serde_json::to_string_pretty(&whoami)?);
+                Ok(())
+            }
+            Command::VolumeList => {
+                let volumes =
+                    fractal_storage_client::volume_list(&self.server(), &client, &self.token())
+                        .await?;
+                for (pubkey, info) in &volumes {
+                    println!("{} {}", pubkey, serde_json::to_string(info)?);
+                }
+                Ok(())
+            }
+            Command::VolumeRemove(opts) => {
+                fractal_storage_client::volume_remove(
+                    &self.server(),
+                    &client,
+                    &self.token(),
+                    &opts.privkey,
+                )
+                .await?;
+                let undo = UndoToken::VolumeRemove(opts.privkey.pubkey());
+                println!("Removed volume {}", opts.privkey.pubkey());
+                println!(
+                    "To undo this until the server purges the volume, run: storage undo {undo}"
+                );
+                Ok(())
+            }
+            Command::Undo(opts) => {
+                opts.token
+                    .undo(&self.server(), &client, &self.token())
+                    .await?;
+                println!("Undone");
+                Ok(())
+            }
+            Command::UploadToken(opts) => {
+                let token = fractal_storage_client::upload_token_create(
+                    &self.server(),
+                    &client,
+                    &self.token(),
+                    &opts.pubkey,
+                    Some(&opts.ttl),
+                )
+                .await?;
+                println!("{}", token.token);
+                Ok(())
+            }
+            Command::ApiKeyCreate(opts) => {
+                let request = ApiKeyCreate {
+                    name: opts.name.clone(),
+                    scope: opts.scope,
+                    volume: opts.pubkey,
+                    ttl: opts.ttl,
+                };
+                let created = fractal_storage_client::api_key_create(
+                    &self.server(),
+                    &client,
+                    &self.token(),
+                    &request,
+                )
+                .await?;
+                eprintln!("Created API key {}", created.info.id);
+                println!("{}", created.key);
+                Ok(())
+            }
+            Command::ApiKeyList => {
+                let keys =
+                    fractal_storage_client::api_key_list(&self.server(), &client, &self.token())
+                        .await?;
+                for key in &keys {
+                    println!("{}", serde_json::to_string(key)?);
+                }
+                Ok(())
+            }
+            Command::ApiKeyRevoke(opts) => {
+                fractal_storage_client::api_key_revoke(
+                    &self.server(),
+                    &client,
+                    &self.token(),
+                    opts.id,
+                )
+                .await?;
+                Ok(())
+            }
             Command::Privkey => {
                 let privkey = Privkey::generate();
                 println!("{privkey}");
@@ -343,8 +1271,49 @@ impl Options {
 async fn main() {
     env_logger::init();
     let options = Options::from_args();
-    match options.run().await {
+    match options.run_summary().await {
         Ok(_) => {}
-        Err(e) => eprintln!("{}", e.to_string()),
+        Err(e) => {
+            eprintln!("{}", e.to_string());
+            std::process::exit(1);
+        }
     }
 }
+
+#[test]
+fn test_json_merge_patch() {
+    use serde_json::json;
+    let mut value = json!({"a": 1, "b": {"c": 2, "d": 3}});
+    json_merge_patch(&mut value, json!({"a": 5, "b": {"c": null, "e": 4}}));
+    assert_eq!(value, json!({"a": 5, "b": {"d": 3, "e": 4}}));
+}
+
+#[test]
+fn test_manifest_diff() {
+    let parent = Manifest {
+        creation: 0,
+        machine: Uuid::nil(),
+        path: PathBuf::from("/tmp/path"),
+        size: 100,
+        size_total: 100,
+        generation: 1,
+        parent: None,
+        data: "ipfs://QmTvXmLGiTV6CoCRvSEMHEKU3oMWsrVSMdhyKGzw9UcAth"
+            .try_into()
+            .unwrap(),
+    };
+    let parent_hash = Manifest::hash(&parent.encode());
+    let mut child = parent.clone();
+    child.size_total = 150;
+    child.size = 100;
+    child.parent = Some(Parent::new(parent_hash));
+    let child_hash = Manifest::hash(&child.encode());
+
+    let lines = manifest_diff((&parent_hash, &parent), (&child_hash, &child)).unwrap();
+    assert!(lines.contains(&"- size_total: 100".to_string()));
+    assert!(lines.contains(&"+ size_total: 150".to_string()));
+    assert!(lines.contains(&"  machine: \"00000000-0000-0000-0000-000000000000\"".to_string()));
+    assert!(lines.contains(&format!("Manifest {child_hash} is a child of the other")));
+    assert!(lines.contains(&"Invalid generation: child has 1 but parent has 1".to_string()));
+    assert!(lines.contains(&"Wrong size_total: child has 150 but expected 200".to_string()));
+}
@@ -0,0 +1,42 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::path::Path;
+use std::time::Duration;
+
+/// Machine-readable result of a command, written with `--summary-json` so that CI pipelines
+/// do not have to parse standard output.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Summary {
+    /// Command that was run.
+    pub command: String,
+    /// Whether the command succeeded.
+    pub success: bool,
+    /// Error the command failed with.
+    pub error: Option<String>,
+    /// How long the command took, in seconds.
+    pub duration: f64,
+    /// Number of (unencrypted) bytes transferred.
+    pub bytes: Option<u64>,
+    /// IPFS CID of the data that was transferred.
+    pub cid: Option<String>,
+    /// Hash of the snapshot that was operated on.
+    pub hash: Option<String>,
+    /// Warnings emitted while running the command.
+    pub warnings: Vec<String>,
+}
+
+impl Summary {
+    /// Record the outcome of the command.
+    pub fn finish(&mut self, result: &Result<()>, duration: Duration) {
+        self.success = result.is_ok();
+        self.error = result.as_ref().err().map(|e| e.to_string());
+        self.duration = duration.as_secs_f64();
+    }
+
+    /// Write summary as JSON to the given path.
+    pub async fn write(&self, path: &Path) -> Result<()> {
+        let data = serde_json::to_vec_pretty(self)?;
+        tokio::fs::write(path, data).await?;
+        Ok(())
+    }
+}
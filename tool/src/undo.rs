@@ -0,0 +1,58 @@
+use anyhow::{anyhow, Result};
+use fractal_storage_client::{volume_restore, Pubkey};
+use reqwest::Client;
+use std::fmt;
+use std::str::FromStr;
+use url::Url;
+
+/// Token printed by destructive commands, which reverts them when passed to `undo`. Removed
+/// volumes are only soft-deleted by the server, so this works until they are purged.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UndoToken {
+    /// Restore a removed volume.
+    VolumeRemove(Pubkey),
+}
+
+impl UndoToken {
+    /// Revert the operation this token was issued for.
+    pub async fn undo(&self, api: &Url, client: &Client, token: &str) -> Result<()> {
+        match self {
+            UndoToken::VolumeRemove(volume) => volume_restore(api, client, token, volume)
+                .await
+                .map_err(|e| {
+                    anyhow!("Cannot restore volume {volume}, it may have been purged already: {e}")
+                }),
+        }
+    }
+}
+
+impl fmt::Display for UndoToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UndoToken::VolumeRemove(volume) => write!(f, "volume-remove.{}", volume.to_hex()),
+        }
+    }
+}
+
+impl FromStr for UndoToken {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.split_once('.') {
+            Some(("volume-remove", volume)) => Pubkey::from_hex(volume)
+                .map(UndoToken::VolumeRemove)
+                .map_err(|e| format!("Invalid volume in undo token: {e}")),
+            _ => Err(format!("Unknown undo token {input:?}")),
+        }
+    }
+}
+
+#[test]
+fn test_undo_token() {
+    use fractal_storage_client::Privkey;
+
+    let token = UndoToken::VolumeRemove(Privkey::generate().pubkey());
+    assert_eq!(token.to_string().parse(), Ok(token));
+    assert!("volume-remove.abc".parse::<UndoToken>().is_err());
+    assert!("prune.abc".parse::<UndoToken>().is_err());
+}